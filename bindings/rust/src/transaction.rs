@@ -103,6 +103,23 @@ impl Transaction<'_> {
         })
     }
 
+    /// Begin a savepoint nested in the transaction, which can be committed or
+    /// rolled back without ending the transaction.
+    ///
+    /// Even though we don't mutate the transaction, we take a `&mut
+    /// Transaction` to prevent using the transaction while the savepoint is
+    /// open.
+    #[inline]
+    pub async fn savepoint(&mut self) -> Result<Savepoint<'_>> {
+        Savepoint::with_depth(self.conn, 1).await
+    }
+
+    /// Begin a named savepoint nested in the transaction.
+    #[inline]
+    pub async fn savepoint_with_name<T: Into<String>>(&mut self, name: T) -> Result<Savepoint<'_>> {
+        Savepoint::with_depth_and_name(self.conn, 1, name).await
+    }
+
     /// Get the current setting for what happens to the transaction when it is
     /// dropped.
     #[inline]
@@ -192,6 +209,190 @@ impl Drop for Transaction<'_> {
     }
 }
 
+/// Represents a savepoint on a database connection.
+///
+/// ## Note
+///
+/// Savepoints will roll back by default. Use `commit` method to explicitly
+/// commit the savepoint, or use `set_drop_behavior` to change what happens
+/// when the savepoint is dropped.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// # use turso::{Connection, Result};
+/// # fn do_queries_part_1(_conn: &Connection) -> Result<()> { Ok(()) }
+/// # fn do_queries_part_2(_conn: &Connection) -> Result<()> { Ok(()) }
+/// async fn perform_queries(conn: &mut Connection) -> Result<()> {
+///     let sp = conn.savepoint().await?;
+///
+///     do_queries_part_1(&sp)?; // sp causes rollback if this fails
+///     do_queries_part_2(&sp)?; // sp causes rollback if this fails
+///
+///     sp.commit().await
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Savepoint<'conn> {
+    conn: &'conn Connection,
+    name: String,
+    depth: u32,
+    drop_behavior: DropBehavior,
+    must_finish: bool,
+}
+
+impl Savepoint<'_> {
+    #[inline]
+    async fn with_depth_and_name<T: Into<String>>(
+        conn: &Connection,
+        depth: u32,
+        name: T,
+    ) -> Result<Savepoint<'_>> {
+        let name = name.into();
+        conn.execute(&format!("SAVEPOINT {name}"), ())
+            .await
+            .map(|_| Savepoint {
+                conn,
+                name,
+                depth,
+                drop_behavior: DropBehavior::Rollback,
+                must_finish: true,
+            })
+    }
+
+    #[inline]
+    async fn with_depth(conn: &Connection, depth: u32) -> Result<Savepoint<'_>> {
+        let name = format!("_turso_sp_{depth}");
+        Savepoint::with_depth_and_name(conn, depth, name).await
+    }
+
+    /// Begin a new savepoint. Can be nested.
+    #[inline]
+    pub async fn new(conn: &mut Connection) -> Result<Savepoint<'_>> {
+        Savepoint::with_depth(conn, 0).await
+    }
+
+    /// Begin a new savepoint with a user-provided savepoint name.
+    #[inline]
+    pub async fn with_name<T: Into<String>>(
+        conn: &mut Connection,
+        name: T,
+    ) -> Result<Savepoint<'_>> {
+        Savepoint::with_depth_and_name(conn, 0, name).await
+    }
+
+    /// Begin a nested savepoint.
+    #[inline]
+    pub async fn savepoint(&mut self) -> Result<Savepoint<'_>> {
+        Savepoint::with_depth(self.conn, self.depth + 1).await
+    }
+
+    /// Begin a nested savepoint with a user-provided savepoint name.
+    #[inline]
+    pub async fn savepoint_with_name<T: Into<String>>(&mut self, name: T) -> Result<Savepoint<'_>> {
+        Savepoint::with_depth_and_name(self.conn, self.depth + 1, name).await
+    }
+
+    /// Get the current setting for what happens to the savepoint when it is
+    /// dropped.
+    #[inline]
+    #[must_use]
+    pub fn drop_behavior(&self) -> DropBehavior {
+        self.drop_behavior
+    }
+
+    /// Configure the savepoint to perform the specified action when it is
+    /// dropped.
+    #[inline]
+    pub fn set_drop_behavior(&mut self, drop_behavior: DropBehavior) {
+        self.drop_behavior = drop_behavior;
+    }
+
+    /// A convenience method which consumes and commits a savepoint.
+    #[inline]
+    pub async fn commit(mut self) -> Result<()> {
+        self._commit().await
+    }
+
+    #[inline]
+    async fn _commit(&mut self) -> Result<()> {
+        self.must_finish = false;
+        self.conn
+            .execute(&format!("RELEASE {}", self.name), ())
+            .await?;
+        Ok(())
+    }
+
+    /// A convenience method which rolls back a savepoint.
+    ///
+    /// ## Note
+    ///
+    /// Unlike `Transaction`s, savepoints remain active after they have been
+    /// rolled back, and can be rolled back again or committed.
+    #[inline]
+    pub async fn rollback(&mut self) -> Result<()> {
+        self.conn
+            .execute(&format!("ROLLBACK TO {}", self.name), ())
+            .await?;
+        Ok(())
+    }
+
+    /// Consumes the savepoint, committing or rolling back according to the
+    /// current setting (see `drop_behavior`).
+    ///
+    /// Functionally equivalent to the `Drop` implementation, but allows
+    /// callers to see any errors that occur.
+    #[inline]
+    pub async fn finish(mut self) -> Result<()> {
+        self._finish().await
+    }
+
+    #[inline]
+    async fn _finish(&mut self) -> Result<()> {
+        if self.conn.is_autocommit()? {
+            self.must_finish = false;
+            return Ok(());
+        }
+        match self.drop_behavior() {
+            DropBehavior::Commit => {
+                if (self._commit().await).is_err() {
+                    self.rollback().await?;
+                    self._commit().await
+                } else {
+                    Ok(())
+                }
+            }
+            DropBehavior::Rollback => {
+                self.rollback().await?;
+                self._commit().await
+            }
+            DropBehavior::Ignore => {
+                self.must_finish = false;
+                Ok(())
+            }
+            DropBehavior::Panic => panic!("Savepoint dropped unexpectedly."),
+        }
+    }
+}
+
+impl Deref for Savepoint<'_> {
+    type Target = Connection;
+
+    #[inline]
+    fn deref(&self) -> &Connection {
+        self.conn
+    }
+}
+
+impl Drop for Savepoint<'_> {
+    #[inline]
+    fn drop(&mut self) {
+        if self.must_finish {
+            panic!("Savepoint dropped without finish()")
+        }
+    }
+}
+
 impl Connection {
     /// Begin a new transaction with the default behavior (DEFERRED).
     ///
@@ -274,6 +475,48 @@ impl Connection {
         Transaction::new_unchecked(self, self.transaction_behavior).await
     }
 
+    /// Begin a new savepoint.
+    ///
+    /// The savepoint defaults to rolling back when it is dropped. If you want
+    /// the savepoint to commit, you must call [`commit`](Savepoint::commit) or
+    /// [`set_drop_behavior(DropBehavior::Commit)`](Savepoint::set_drop_behavior).
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// # use turso::{Connection, Result};
+    /// # fn do_queries_part_1(_conn: &Connection) -> Result<()> { Ok(()) }
+    /// # fn do_queries_part_2(_conn: &Connection) -> Result<()> { Ok(()) }
+    /// async fn perform_queries(conn: &mut Connection) -> Result<()> {
+    ///     let sp = conn.savepoint().await?;
+    ///
+    ///     do_queries_part_1(&sp)?; // sp causes rollback if this fails
+    ///     do_queries_part_2(&sp)?; // sp causes rollback if this fails
+    ///
+    ///     sp.commit().await
+    /// }
+    /// ```
+    ///
+    /// # Failure
+    ///
+    /// Will return `Err` if the call fails.
+    #[inline]
+    pub async fn savepoint(&mut self) -> Result<Savepoint<'_>> {
+        Savepoint::new(self).await
+    }
+
+    /// Begin a new savepoint with a specified name.
+    ///
+    /// See [`savepoint`](Connection::savepoint).
+    ///
+    /// # Failure
+    ///
+    /// Will return `Err` if the call fails.
+    #[inline]
+    pub async fn savepoint_with_name<T: Into<String>>(&mut self, name: T) -> Result<Savepoint<'_>> {
+        Savepoint::with_name(self, name).await
+    }
+
     /// Set the default transaction behavior for the connection.
     ///
     /// ## Note
@@ -408,15 +651,14 @@ mod test {
     async fn test_explicit_rollback_commit() -> Result<()> {
         let mut conn = checked_memory_handle().await?;
         {
-            let tx = conn.transaction().await?;
-            tx.execute("INSERT INTO foo VALUES(?)", &[1]).await?;
-            tx.rollback().await?;
-
-            // This is a current Turso's limitation.
-            // Since we don't have support for savepoints yet,
-            // a rollback ends with a transaction so we need to immediately open a new one.
-            let tx = conn.transaction().await?;
-            tx.execute("INSERT INTO foo VALUES(?)", &[2]).await?;
+            let mut tx = conn.transaction().await?;
+            {
+                let mut sp = tx.savepoint().await?;
+                sp.execute("INSERT INTO foo VALUES(?)", &[1]).await?;
+                sp.rollback().await?;
+                sp.execute("INSERT INTO foo VALUES(?)", &[2]).await?;
+                sp.commit().await?;
+            }
             tx.commit().await?;
         }
         {
@@ -424,19 +666,95 @@ mod test {
             tx.execute("INSERT INTO foo VALUES(?)", &[4]).await?;
             tx.commit().await?;
         }
+        assert_current_sum(6, &conn).await
+    }
+
+    async fn assert_current_sum(x: i64, conn: &Connection) -> Result<()> {
+        let mut result = conn.query("SELECT SUM(x) FROM foo", ()).await?;
+        assert_eq!(
+            x,
+            *result
+                .next()
+                .await?
+                .unwrap()
+                .get_value(0)?
+                .as_integer()
+                .unwrap()
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_savepoint() -> Result<()> {
+        let mut conn = checked_memory_handle().await?;
         {
-            let mut result = conn.query("SELECT SUM(x) FROM foo", ()).await?;
-            assert_eq!(
-                6,
-                *result
-                    .next()
-                    .await?
-                    .unwrap()
-                    .get_value(0)?
-                    .as_integer()
-                    .unwrap()
-            );
+            let mut tx = conn.transaction().await?;
+            tx.execute("INSERT INTO foo VALUES(?)", &[1]).await?;
+            assert_current_sum(1, &tx).await?;
+            tx.set_drop_behavior(DropBehavior::Commit);
+            {
+                let mut sp1 = tx.savepoint().await?;
+                sp1.execute("INSERT INTO foo VALUES(?)", &[2]).await?;
+                assert_current_sum(3, &sp1).await?;
+                {
+                    let mut sp2 = sp1.savepoint().await?;
+                    sp2.execute("INSERT INTO foo VALUES(?)", &[4]).await?;
+                    assert_current_sum(7, &sp2).await?;
+                    {
+                        let sp3 = sp2.savepoint().await?;
+                        sp3.execute("INSERT INTO foo VALUES(?)", &[8]).await?;
+                        assert_current_sum(15, &sp3).await?;
+                        // committed sp3, but will be erased by sp2 rollback
+                        sp3.commit().await?;
+                    }
+                    assert_current_sum(15, &sp2).await?;
+                    sp2.finish().await?;
+                }
+                assert_current_sum(3, &sp1).await?;
+                sp1.finish().await?;
+            }
+            assert_current_sum(1, &tx).await?;
+            tx.finish().await?;
         }
+        assert_current_sum(1, &conn).await
+    }
+
+    #[tokio::test]
+    async fn test_savepoint_names() -> Result<()> {
+        let mut conn = checked_memory_handle().await?;
+        {
+            let mut sp1 = conn.savepoint_with_name("my_sp").await?;
+            sp1.execute("INSERT INTO foo VALUES(?)", &[1]).await?;
+            assert_current_sum(1, &sp1).await?;
+            {
+                let mut sp2 = sp1.savepoint_with_name("my_sp").await?;
+                sp2.set_drop_behavior(DropBehavior::Commit);
+                sp2.execute("INSERT INTO foo VALUES(?)", &[2]).await?;
+                assert_current_sum(3, &sp2).await?;
+                sp2.finish().await?;
+            }
+            assert_current_sum(3, &sp1).await?;
+            sp1.rollback().await?;
+            assert_current_sum_is_null(&sp1).await?;
+            sp1.commit().await?;
+        }
+        assert!(conn.is_autocommit()?);
+        Ok(())
+    }
+
+    async fn assert_current_sum_is_null(conn: &Connection) -> Result<()> {
+        let mut result = conn.query("SELECT SUM(x) FROM foo", ()).await?;
+        assert!(result.next().await?.unwrap().get_value(0)?.is_null());
         Ok(())
     }
+
+    #[tokio::test]
+    #[should_panic(expected = "Savepoint dropped without finish()")]
+    async fn test_savepoint_drop_panic() {
+        let mut conn = checked_memory_handle().await.unwrap();
+        {
+            let sp = conn.savepoint().await.unwrap();
+            sp.execute("INSERT INTO foo VALUES(?)", &[1]).await.unwrap();
+        }
+    }
 }
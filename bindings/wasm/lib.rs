@@ -260,6 +260,17 @@ impl turso_core::File for File {
     fn size(&self) -> Result<u64> {
         Ok(self.vfs.size(self.fd))
     }
+
+    fn truncate(
+        &self,
+        _len: usize,
+        c: Arc<turso_core::Completion>,
+    ) -> Result<Arc<turso_core::Completion>> {
+        // The JavaScript VFS has no truncate, so the file keeps its current size.
+        c.complete(0);
+        #[allow(clippy::arc_with_non_send_sync)]
+        Ok(c)
+    }
 }
 
 pub struct PlatformIO {
@@ -379,6 +390,11 @@ impl turso_core::DatabaseStorage for DatabaseFile {
     fn size(&self) -> Result<u64> {
         self.file.size()
     }
+
+    fn truncate(&self, len: usize, c: turso_core::Completion) -> Result<()> {
+        let _ = self.file.truncate(len, c.into())?;
+        Ok(())
+    }
 }
 
 #[cfg(all(feature = "web", not(feature = "nodejs")))]
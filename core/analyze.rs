@@ -0,0 +1,594 @@
+//! ANALYZE gathers statistics about the tables and indexes of a database into the `sqlite_stat1`
+//! and `sqlite_stat4` tables, in the same format as SQLite. `sqlite_stat1` has the number of rows
+//! of each index and how many rows share the same values in its first columns on average, and
+//! `sqlite_stat4` has samples of index keys with the number of rows equal to and less than them.
+//! The statistics are loaded with the schema, and the optimizer uses them to estimate how many
+//! rows scans and seeks return.
+
+use crate::schema::{BTreeTable, Index, Schema};
+use crate::storage::btree::BTreeCursor;
+use crate::types::ImmutableRecord;
+use crate::util::{normalize_ident, quote_ident, IOExt};
+use crate::vdbe::StepResult;
+use crate::{Connection, Pager, Result, Value};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+use turso_sqlite3_parser::ast::SortOrder;
+
+pub const STAT1_TABLE_NAME: &str = "sqlite_stat1";
+pub const STAT4_TABLE_NAME: &str = "sqlite_stat4";
+pub const STAT1_TABLE_SQL: &str = "CREATE TABLE sqlite_stat1(tbl,idx,stat)";
+pub const STAT4_TABLE_SQL: &str = "CREATE TABLE sqlite_stat4(tbl,idx,neq,nlt,ndlt,sample)";
+
+/// The number of index keys sampled into `sqlite_stat4`, SQLITE_STAT4_SAMPLES in SQLite.
+const STAT4_SAMPLES: usize = 24;
+
+/// A candidate row for `sqlite_stat4`.
+#[derive(Debug, Clone)]
+struct StatSample {
+    /// `eq[i]` is the number of rows equal to the sample in the first `i + 1` columns.
+    eq: Vec<u64>,
+    /// `lt[i]` is the number of rows less than the sample in the first `i + 1` columns.
+    lt: Vec<u64>,
+    /// `dlt[i]` is the number of distinct values less than the sample in the first `i + 1` columns.
+    dlt: Vec<u64>,
+    /// The index key of the sample.
+    record: Option<ImmutableRecord>,
+    /// Whether the sample was taken at a regular interval, rather than for being a key that
+    /// many rows share.
+    is_periodic: bool,
+    /// The column of the key the sample was chosen for.
+    col: usize,
+    /// Pseudo-random value breaking ties between otherwise equally good samples.
+    hash: u32,
+}
+
+impl StatSample {
+    fn new(num_columns: usize, col: usize) -> Self {
+        Self {
+            eq: vec![0; num_columns],
+            lt: vec![0; num_columns],
+            dlt: vec![0; num_columns],
+            record: None,
+            is_periodic: false,
+            col,
+            hash: 0,
+        }
+    }
+}
+
+/// Accumulates the statistics of an index while ANALYZE scans it, a port of SQLite's StatAccum.
+/// The scan pushes every key with the position of the first column that differs from the
+/// previous key, and the statistics are then read as the `sqlite_stat1` and `sqlite_stat4` rows
+/// of the index.
+#[derive(Debug, Clone)]
+pub struct StatAccum {
+    /// Number of columns compared, including the rowid or the primary key of the table.
+    num_columns: usize,
+    /// Number of columns of the index key.
+    num_key_columns: usize,
+    /// Number of entries of the index.
+    num_entries: u64,
+    /// Stop after this many rows if not 0.
+    limit: u64,
+    /// Number of rows pushed so far.
+    num_rows: u64,
+    current: StatSample,
+    /// Maximum number of samples, 0 if no samples are collected.
+    max_samples: usize,
+    /// Number of rows between periodic samples.
+    periodic_interval: u64,
+    samples: Vec<StatSample>,
+    /// The best candidate sample for each column, until the value of the column changes.
+    best: Vec<StatSample>,
+    /// The position of the least desirable sample once `samples` is full.
+    min_sample: usize,
+    /// No sample has a 0 in its `eq` counts at or after this position.
+    max_eq_zero: usize,
+    prng: u32,
+    /// The next sample to read, once the scan is over.
+    next_sample: Option<usize>,
+}
+
+impl StatAccum {
+    /// Like in SQLite, no samples are collected when the scan is limited.
+    pub fn new(num_columns: usize, num_key_columns: usize, num_entries: u64, limit: u64) -> Self {
+        let max_samples = if limit == 0 { STAT4_SAMPLES } else { 0 };
+        Self {
+            num_columns,
+            num_key_columns,
+            num_entries,
+            limit,
+            num_rows: 0,
+            current: StatSample::new(num_columns, 0),
+            max_samples,
+            periodic_interval: num_entries / (max_samples as u64 / 3 + 1) + 1,
+            samples: Vec::with_capacity(max_samples),
+            best: (0..num_columns)
+                .map(|col| StatSample::new(num_columns, col))
+                .collect(),
+            min_sample: 0,
+            max_eq_zero: 0,
+            prng: 0x689e962d_u32.wrapping_mul(num_columns as u32)
+                ^ 0xd0944565_u32.wrapping_mul(num_entries as u32),
+            next_sample: None,
+        }
+    }
+
+    /// Adds the next key of the index, whose columns from `change` onwards differ from the
+    /// previous key. `record` is the key, for sampling.
+    pub fn push(&mut self, change: usize, record: Option<ImmutableRecord>) {
+        if self.num_rows == 0 {
+            self.current.eq.fill(1);
+        } else {
+            if self.max_samples > 0 {
+                self.push_previous(change);
+            }
+            for i in 0..change {
+                self.current.eq[i] += 1;
+            }
+            for i in change..self.num_columns {
+                self.current.dlt[i] += 1;
+                if self.max_samples > 0 {
+                    self.current.lt[i] += self.current.eq[i];
+                }
+                self.current.eq[i] = 1;
+            }
+        }
+        self.num_rows += 1;
+        if self.max_samples == 0 {
+            return;
+        }
+        self.current.record = record;
+        self.prng = self.prng.wrapping_mul(1103515245).wrapping_add(12345);
+        self.current.hash = self.prng;
+        let lt = self.current.lt[self.num_columns - 1];
+        if lt / self.periodic_interval != (lt + 1) / self.periodic_interval {
+            let mut sample = self.current.clone();
+            sample.is_periodic = true;
+            sample.col = 0;
+            self.insert_sample(sample, self.num_columns - 1);
+        }
+        for i in 0..self.num_columns - 1 {
+            self.current.col = i;
+            if i >= change || self.is_better_post(&self.current, &self.best[i]) {
+                self.best[i] = self.current.clone();
+            }
+        }
+    }
+
+    /// The `stat` column of the `sqlite_stat1` row of the index.
+    pub fn stat1(&self) -> String {
+        // A limited scan only knows the number of entries from the size of the index
+        let num_rows = if self.limit > 0 && self.num_rows < self.num_entries {
+            self.num_entries
+        } else {
+            self.num_rows
+        };
+        let mut stat = num_rows.to_string();
+        for i in 0..self.num_key_columns {
+            let num_distinct = self.current.dlt[i] + 1;
+            let mut avg = self.num_rows.div_ceil(num_distinct);
+            if avg == 2 && self.num_rows * 10 <= num_distinct * 11 {
+                avg = 1;
+            }
+            stat.push_str(&format!(" {avg}"));
+        }
+        stat
+    }
+
+    /// The `neq`, `nlt` and `ndlt` columns and the key of the next `sqlite_stat4` row of the
+    /// index, or None once all samples were read.
+    pub fn next_sample(&mut self) -> Option<(String, String, String, ImmutableRecord)> {
+        if self.max_samples == 0 {
+            return None;
+        }
+        let next = match self.next_sample {
+            Some(next) => next,
+            None => {
+                self.push_previous(0);
+                0
+            }
+        };
+        let sample = self.samples.get(next)?;
+        self.next_sample = Some(next + 1);
+        let counts = |counts: &[u64]| {
+            counts
+                .iter()
+                .map(|count| count.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        Some((
+            counts(&sample.eq),
+            counts(&sample.lt),
+            counts(&sample.dlt),
+            sample.record.clone()?,
+        ))
+    }
+
+    /// Column `change` of the current key differs from the previous key, so the best
+    /// candidates for the columns from `change` onwards are final.
+    fn push_previous(&mut self, change: usize) {
+        for i in (change..self.num_columns - 1).rev() {
+            self.best[i].eq[i] = self.current.eq[i];
+            if self.samples.len() < self.max_samples
+                || self.is_better(&self.best[i], &self.samples[self.min_sample])
+            {
+                self.insert_sample(self.best[i].clone(), i);
+            }
+        }
+        if change < self.max_eq_zero {
+            for sample in self.samples.iter_mut() {
+                for j in change..self.num_columns {
+                    if sample.eq[j] == 0 {
+                        sample.eq[j] = self.current.eq[j];
+                    }
+                }
+            }
+            self.max_eq_zero = change;
+        }
+    }
+
+    fn insert_sample(&mut self, mut sample: StatSample, eq_zero: usize) {
+        self.max_eq_zero = self.max_eq_zero.max(eq_zero);
+        if !sample.is_periodic {
+            // A sample sharing the prefix of the new sample makes it redundant, so the best
+            // such sample is upgraded instead.
+            let mut upgrade: Option<usize> = None;
+            for i in (0..self.samples.len()).rev() {
+                let old = &self.samples[i];
+                if old.eq[sample.col] == 0 {
+                    if old.is_periodic {
+                        return;
+                    }
+                    if upgrade.is_none_or(|upgrade| self.is_better(old, &self.samples[upgrade])) {
+                        upgrade = Some(i);
+                    }
+                }
+            }
+            if let Some(upgrade) = upgrade {
+                let upgraded = &mut self.samples[upgrade];
+                upgraded.col = sample.col;
+                upgraded.eq[sample.col] = sample.eq[sample.col];
+                self.find_min_sample();
+                return;
+            }
+        }
+        if self.samples.len() >= self.max_samples {
+            self.samples.remove(self.min_sample);
+        }
+        sample.eq[..eq_zero].fill(0);
+        self.samples.push(sample);
+        self.find_min_sample();
+    }
+
+    fn find_min_sample(&mut self) {
+        if self.samples.len() < self.max_samples {
+            return;
+        }
+        let mut min_sample: Option<usize> = None;
+        for (i, sample) in self.samples.iter().enumerate() {
+            if sample.is_periodic {
+                continue;
+            }
+            if min_sample.is_none_or(|min| self.is_better(&self.samples[min], sample)) {
+                min_sample = Some(i);
+            }
+        }
+        self.min_sample = min_sample.expect("a full set of samples has non-periodic samples");
+    }
+
+    /// Whether `new` is a better sample than `old`.
+    fn is_better(&self, new: &StatSample, old: &StatSample) -> bool {
+        let eq_new = new.eq[new.col];
+        let eq_old = old.eq[old.col];
+        if eq_new > eq_old {
+            return true;
+        }
+        eq_new == eq_old
+            && (new.col < old.col || (new.col == old.col && self.is_better_post(new, old)))
+    }
+
+    /// Whether `new` is a better sample than `old` for the same column, by the columns after it.
+    fn is_better_post(&self, new: &StatSample, old: &StatSample) -> bool {
+        for i in new.col + 1..self.num_columns {
+            if new.eq[i] != old.eq[i] {
+                return new.eq[i] > old.eq[i];
+            }
+        }
+        new.hash > old.hash
+    }
+}
+
+/// The statistics of a table, from `sqlite_stat1` and `sqlite_stat4`.
+#[derive(Debug, Clone, Default)]
+pub struct TableStats {
+    /// The number of rows of the table.
+    pub row_count: u64,
+    /// The statistics of the indexes of the table, by index name.
+    pub indexes: HashMap<String, IndexStats>,
+}
+
+/// The statistics of an index, from `sqlite_stat1` and `sqlite_stat4`.
+#[derive(Debug, Clone, Default)]
+pub struct IndexStats {
+    /// The number of entries of the index.
+    pub row_count: u64,
+    /// `avg_eq[i]` is the average number of rows that have the same values in the first `i + 1`
+    /// columns of the index.
+    pub avg_eq: Vec<u64>,
+    /// Sampled keys of the index, in index order.
+    pub samples: Vec<IndexSample>,
+    /// `sample_avg_eq[i]` is the average number of rows that have the same values in the first
+    /// `i + 1` columns of the index, for the values that are not sampled.
+    pub sample_avg_eq: Vec<u64>,
+}
+
+/// A row of `sqlite_stat4`.
+#[derive(Debug, Clone)]
+pub struct IndexSample {
+    pub key: Vec<Value>,
+    pub eq: Vec<u64>,
+    pub lt: Vec<u64>,
+    pub dlt: Vec<u64>,
+}
+
+impl IndexStats {
+    /// Estimates how many rows have a first column less than `value`, and how many are equal to
+    /// it, from the samples of the index.
+    pub fn estimate_rows_lt_eq(&self, value: &Value, order: SortOrder) -> Option<(f64, f64)> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let row_count = self.row_count as f64;
+        let in_index_order = |key: &Value| match order {
+            SortOrder::Asc => key.cmp(value),
+            SortOrder::Desc => value.cmp(key),
+        };
+        let mut before = None;
+        let mut eq = None;
+        let mut lower = 0.0;
+        let mut upper = row_count;
+        for sample in self.samples.iter() {
+            let (Some(key), Some(&sample_eq), Some(&sample_lt)) =
+                (sample.key.first(), sample.eq.first(), sample.lt.first())
+            else {
+                continue;
+            };
+            match in_index_order(key) {
+                std::cmp::Ordering::Less => lower = (sample_lt + sample_eq) as f64,
+                std::cmp::Ordering::Equal => {
+                    before = Some(sample_lt as f64);
+                    eq = Some(sample_eq as f64);
+                    break;
+                }
+                std::cmp::Ordering::Greater => {
+                    upper = sample_lt as f64;
+                    break;
+                }
+            }
+        }
+        let eq = eq.unwrap_or_else(|| self.sample_avg_eq.first().copied().unwrap_or(1) as f64);
+        let before = before.unwrap_or_else(|| lower + (upper - lower).max(0.0) / 2.0);
+        let lt = match order {
+            SortOrder::Asc => before,
+            SortOrder::Desc => (row_count - before - eq).max(0.0),
+        };
+        Some((lt, eq))
+    }
+
+    /// Computes the average number of rows for the values that are not sampled, like SQLite's
+    /// initAvgEq().
+    fn init_sample_avg_eq(&mut self) {
+        let Some(last) = self.samples.last() else {
+            return;
+        };
+        let num_columns = last.eq.len();
+        self.sample_avg_eq = (0..num_columns)
+            .map(|col| {
+                let mut num_samples = self.samples.len();
+                let (row_count, num_distinct_100) = match self.avg_eq.get(col) {
+                    Some(&avg_eq) if avg_eq > 0 => (self.row_count, 100 * self.row_count / avg_eq),
+                    _ => {
+                        num_samples -= 1;
+                        (last.lt[col], 100 * last.dlt[col])
+                    }
+                };
+                let mut sum_eq = 0;
+                let mut sum_100 = 0;
+                for i in 0..num_samples {
+                    if i == self.samples.len() - 1
+                        || self.samples[i].dlt[col] != self.samples[i + 1].dlt[col]
+                    {
+                        sum_eq += self.samples[i].eq[col];
+                        sum_100 += 100;
+                    }
+                }
+                if num_distinct_100 > sum_100 && sum_eq < row_count {
+                    (100 * (row_count - sum_eq) / (num_distinct_100 - sum_100)).max(1)
+                } else {
+                    1
+                }
+            })
+            .collect();
+    }
+}
+
+/// Parses a list of space separated counts, ignoring the options SQLite may append to the
+/// `stat` column, such as `unordered` or `sz=N`.
+fn parse_counts(value: &Value) -> Vec<u64> {
+    let Value::Text(text) = value else {
+        return match value {
+            Value::Integer(count) => vec![*count as u64],
+            _ => vec![],
+        };
+    };
+    text.as_str()
+        .split_whitespace()
+        .map_while(|count| count.parse().ok())
+        .collect()
+}
+
+fn text_value(value: &Value) -> Option<String> {
+    match value {
+        Value::Text(text) => Some(normalize_ident(text.as_str())),
+        _ => None,
+    }
+}
+
+impl Schema {
+    /// Replaces the statistics of the schema with those of the rows of `sqlite_stat1` and
+    /// `sqlite_stat4`. Rows of tables and indexes that do not exist are ignored.
+    pub fn load_analysis(&mut self, stat1_rows: Vec<Vec<Value>>, stat4_rows: Vec<Vec<Value>>) {
+        let mut table_stats: HashMap<String, TableStats> = HashMap::new();
+        for row in stat1_rows {
+            let [tbl, idx, stat] = row.as_slice() else {
+                continue;
+            };
+            let Some(table_name) = text_value(tbl) else {
+                continue;
+            };
+            let Some(table) = self.get_btree_table(&table_name) else {
+                continue;
+            };
+            let counts = parse_counts(stat);
+            let Some(&row_count) = counts.first() else {
+                continue;
+            };
+            let stats = table_stats.entry(table_name.clone()).or_default();
+            let index = text_value(idx).and_then(|idx| self.stat_index(&table, &idx));
+            // A partial index lacks some of the rows of the table
+            if index
+                .as_ref()
+                .is_none_or(|index| index.where_clause.is_none())
+            {
+                stats.row_count = row_count;
+            }
+            let Some(index) = index else {
+                continue;
+            };
+            stats.indexes.insert(
+                index.name.clone(),
+                IndexStats {
+                    row_count,
+                    avg_eq: counts[1..].to_vec(),
+                    ..Default::default()
+                },
+            );
+        }
+        for row in stat4_rows {
+            let [tbl, idx, neq, nlt, ndlt, Value::Blob(sample)] = row.as_slice() else {
+                continue;
+            };
+            let (Some(table_name), Some(index_name)) = (text_value(tbl), text_value(idx)) else {
+                continue;
+            };
+            let Some(table) = self.get_btree_table(&table_name) else {
+                continue;
+            };
+            let Some(index) = self.stat_index(&table, &index_name) else {
+                continue;
+            };
+            let Some(index_stats) = table_stats
+                .get_mut(&table_name)
+                .and_then(|stats| stats.indexes.get_mut(&index.name))
+            else {
+                continue;
+            };
+            let mut record = ImmutableRecord::new(sample.len());
+            record.start_serialization(sample);
+            index_stats.samples.push(IndexSample {
+                key: record.get_values().iter().map(|v| v.to_owned()).collect(),
+                eq: parse_counts(neq),
+                lt: parse_counts(nlt),
+                dlt: parse_counts(ndlt),
+            });
+        }
+        for index_stats in table_stats
+            .values_mut()
+            .flat_map(|stats| stats.indexes.values_mut())
+        {
+            let num_columns = index_stats.samples.first().map_or(0, |s| s.eq.len());
+            let consistent = index_stats.samples.iter().all(|sample| {
+                sample.eq.len() == num_columns
+                    && sample.lt.len() == num_columns
+                    && sample.dlt.len() == num_columns
+            });
+            if consistent {
+                index_stats.init_sample_avg_eq();
+            } else {
+                index_stats.samples.clear();
+            }
+        }
+        self.table_stats = table_stats
+            .into_iter()
+            .map(|(k, v)| (k, Arc::new(v)))
+            .collect();
+    }
+
+    /// The index a row of `sqlite_stat1` or `sqlite_stat4` is for. Like in SQLite, the rows of
+    /// the primary key of a WITHOUT ROWID table are named after the table.
+    fn stat_index(&self, table: &BTreeTable, index_name: &str) -> Option<&Arc<Index>> {
+        if index_name == normalize_ident(&table.name) {
+            return self.get_clustered_index(table);
+        }
+        self.get_indices(&table.name)
+            .iter()
+            .find(|index| index.name == index_name)
+    }
+
+    /// The statistics of a table of the schema, if it was analyzed.
+    pub fn get_table_stats(&self, table_name: &str) -> Option<&Arc<TableStats>> {
+        self.table_stats.get(&normalize_ident(table_name))
+    }
+}
+
+/// Reads all rows of a table B-tree, used to load the statistics with the schema.
+pub(crate) fn read_btree_rows(pager: &Rc<Pager>, root_page: usize) -> Result<Vec<Vec<Value>>> {
+    let mut cursor = BTreeCursor::new_table(None, pager.clone(), root_page, 6);
+    let mut rows = Vec::new();
+    pager.io.block(|| cursor.rewind())?;
+    loop {
+        let Some(row) = pager.io.block(|| cursor.record())? else {
+            break;
+        };
+        rows.push(row.get_values().iter().map(|v| v.to_owned()).collect());
+        drop(row);
+        pager.io.block(|| cursor.next())?;
+    }
+    Ok(rows)
+}
+
+impl Connection {
+    /// Loads the statistics of `sqlite_stat1` and `sqlite_stat4` into the schema of a database.
+    pub(crate) fn load_analysis(self: &Arc<Connection>, database_id: usize) -> Result<()> {
+        let schema = self.database_schema(database_id);
+        let database_name = quote_ident(&self.get_database_name(database_id));
+        let read_rows = |table_name: &str| -> Result<Vec<Vec<Value>>> {
+            if schema.get_btree_table(table_name).is_none() {
+                return Ok(vec![]);
+            }
+            let mut stmt = self.prepare(format!("SELECT * FROM {database_name}.{table_name}"))?;
+            let mut rows = Vec::new();
+            loop {
+                match stmt.step()? {
+                    StepResult::Row => {
+                        let row = stmt.row().unwrap();
+                        rows.push(row.get_values().cloned().collect());
+                    }
+                    StepResult::IO => stmt.run_once()?,
+                    StepResult::Interrupt | StepResult::Done | StepResult::Busy => break,
+                }
+            }
+            Ok(rows)
+        };
+        let stat1_rows = read_rows(STAT1_TABLE_NAME)?;
+        let stat4_rows = read_rows(STAT4_TABLE_NAME)?;
+        self.with_database_schema_mut(database_id, |schema| {
+            schema.load_analysis(stat1_rows, stat4_rows)
+        });
+        Ok(())
+    }
+}
@@ -90,6 +90,12 @@ impl From<turso_ext::ResultCode> for LimboError {
     }
 }
 
+/// Generic error, used to halt a statement with a runtime error message.
+pub const SQLITE_ERROR: usize = 1;
 pub const SQLITE_CONSTRAINT: usize = 19;
+pub const SQLITE_CONSTRAINT_CHECK: usize = SQLITE_CONSTRAINT | (1 << 8);
 pub const SQLITE_CONSTRAINT_PRIMARYKEY: usize = SQLITE_CONSTRAINT | (6 << 8);
 pub const SQLITE_CONSTRAINT_NOTNULL: usize = SQLITE_CONSTRAINT | (5 << 8);
+pub const SQLITE_CONSTRAINT_TRIGGER: usize = SQLITE_CONSTRAINT | (7 << 8);
+/// Halt code of RAISE(IGNORE): ends the trigger program and skips the current row.
+pub const SQLITE_IGNORE: usize = 2;
@@ -569,6 +569,53 @@ impl Display for MathFunc {
     }
 }
 
+/// Built-in window functions. Aggregates used with an `OVER` clause are [AggFunc]s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WindowFunc {
+    RowNumber,
+    Rank,
+    DenseRank,
+    PercentRank,
+    CumeDist,
+    Ntile,
+    Lag,
+    Lead,
+    FirstValue,
+    LastValue,
+    NthValue,
+}
+
+impl WindowFunc {
+    pub fn is_deterministic(&self) -> bool {
+        false // the result depends on the other rows of the partition
+    }
+
+    /// Whether the result depends on the window frame. The other functions only look
+    /// at the partition and the peer groups of its rows.
+    pub fn uses_frame(&self) -> bool {
+        matches!(self, Self::FirstValue | Self::LastValue | Self::NthValue)
+    }
+}
+
+impl Display for WindowFunc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let str = match self {
+            Self::RowNumber => "row_number",
+            Self::Rank => "rank",
+            Self::DenseRank => "dense_rank",
+            Self::PercentRank => "percent_rank",
+            Self::CumeDist => "cume_dist",
+            Self::Ntile => "ntile",
+            Self::Lag => "lag",
+            Self::Lead => "lead",
+            Self::FirstValue => "first_value",
+            Self::LastValue => "last_value",
+            Self::NthValue => "nth_value",
+        };
+        write!(f, "{str}")
+    }
+}
+
 #[derive(Debug)]
 pub enum AlterTableFunc {
     RenameTable,
@@ -592,6 +639,7 @@ pub enum Func {
     Vector(VectorFunc),
     #[cfg(feature = "json")]
     Json(JsonFunc),
+    Window(WindowFunc),
     AlterTable(AlterTableFunc),
     External(Rc<ExternalFunc>),
 }
@@ -605,6 +653,7 @@ impl Display for Func {
             Self::Vector(vector_func) => write!(f, "{vector_func}"),
             #[cfg(feature = "json")]
             Self::Json(json_func) => write!(f, "{json_func}"),
+            Self::Window(window_func) => write!(f, "{window_func}"),
             Self::External(generic_func) => write!(f, "{generic_func}"),
             Self::AlterTable(alter_func) => write!(f, "{alter_func}"),
         }
@@ -626,6 +675,7 @@ impl Func {
             Self::Vector(vector_func) => vector_func.is_deterministic(),
             #[cfg(feature = "json")]
             Self::Json(json_func) => json_func.is_deterministic(),
+            Self::Window(window_func) => window_func.is_deterministic(),
             Self::External(external_func) => external_func.is_deterministic(),
             Self::AlterTable(_) => true,
         }
@@ -703,6 +753,44 @@ impl Func {
             "jsonb_group_object" => Ok(Self::Agg(AggFunc::JsonbGroupObject)),
             #[cfg(feature = "json")]
             "json_group_object" => Ok(Self::Agg(AggFunc::JsonGroupObject)),
+            "row_number" | "rank" | "dense_rank" | "percent_rank" | "cume_dist" => {
+                if arg_count != 0 {
+                    crate::bail_parse_error!("wrong number of arguments to function {}()", name)
+                }
+                Ok(Self::Window(match normalized_name.as_str() {
+                    "row_number" => WindowFunc::RowNumber,
+                    "rank" => WindowFunc::Rank,
+                    "dense_rank" => WindowFunc::DenseRank,
+                    "percent_rank" => WindowFunc::PercentRank,
+                    _ => WindowFunc::CumeDist,
+                }))
+            }
+            "ntile" | "first_value" | "last_value" => {
+                if arg_count != 1 {
+                    crate::bail_parse_error!("wrong number of arguments to function {}()", name)
+                }
+                Ok(Self::Window(match normalized_name.as_str() {
+                    "ntile" => WindowFunc::Ntile,
+                    "first_value" => WindowFunc::FirstValue,
+                    _ => WindowFunc::LastValue,
+                }))
+            }
+            "nth_value" => {
+                if arg_count != 2 {
+                    crate::bail_parse_error!("wrong number of arguments to function {}()", name)
+                }
+                Ok(Self::Window(WindowFunc::NthValue))
+            }
+            "lag" | "lead" => {
+                if !(1..=3).contains(&arg_count) {
+                    crate::bail_parse_error!("wrong number of arguments to function {}()", name)
+                }
+                Ok(Self::Window(if normalized_name == "lag" {
+                    WindowFunc::Lag
+                } else {
+                    WindowFunc::Lead
+                }))
+            }
             "char" => Ok(Self::Scalar(ScalarFunc::Char)),
             "coalesce" => Ok(Self::Scalar(ScalarFunc::Coalesce)),
             "concat" => Ok(Self::Scalar(ScalarFunc::Concat)),
@@ -128,6 +128,13 @@ impl File for GenericFile {
         let file = self.file.borrow();
         Ok(file.metadata().unwrap().len())
     }
+
+    fn truncate(&self, len: usize, c: Arc<Completion>) -> Result<Arc<Completion>> {
+        let file = self.file.borrow();
+        file.set_len(len as u64).map_err(LimboError::IOError)?;
+        c.complete(0);
+        Ok(c)
+    }
 }
 
 impl Drop for GenericFile {
@@ -352,6 +352,14 @@ impl File for UringFile {
     fn size(&self) -> Result<u64> {
         Ok(self.file.metadata()?.len())
     }
+
+    fn truncate(&self, len: usize, c: Arc<Completion>) -> Result<Arc<Completion>> {
+        // The ring's Ftruncate opcode needs a recent kernel, so truncate synchronously.
+        trace!("truncate(len = {})", len);
+        self.file.set_len(len as u64)?;
+        c.complete(0);
+        Ok(c)
+    }
 }
 
 impl Drop for UringFile {
@@ -174,6 +174,23 @@ impl File for MemoryFile {
     fn size(&self) -> Result<u64> {
         Ok(self.size.get() as u64)
     }
+
+    fn truncate(&self, len: usize, c: Arc<Completion>) -> Result<Arc<Completion>> {
+        if len < self.size.get() {
+            let pages = unsafe { &mut *self.pages.get() };
+            let first_dropped_page = len.div_ceil(PAGE_SIZE);
+            pages.split_off(&first_dropped_page);
+            // Zero the tail of a partially kept page so that growing the file again reads zeroes.
+            if len % PAGE_SIZE != 0 {
+                if let Some(page) = pages.get_mut(&(len / PAGE_SIZE)) {
+                    page[len % PAGE_SIZE..].fill(0);
+                }
+            }
+            self.size.set(len);
+        }
+        c.complete(0);
+        Ok(c)
+    }
 }
 
 impl Drop for MemoryFile {
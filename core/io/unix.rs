@@ -449,6 +449,20 @@ impl File for UnixFile<'_> {
         let file = self.file.borrow();
         Ok(file.metadata()?.len())
     }
+
+    #[instrument(err, skip_all, level = Level::TRACE)]
+    fn truncate(&self, len: usize, c: Arc<Completion>) -> Result<Arc<Completion>> {
+        let file = self.file.borrow();
+        let result = fs::ftruncate(file.as_fd(), len as u64);
+        match result {
+            Ok(()) => {
+                trace!("ftruncate(len={})", len);
+                c.complete(0);
+                Ok(c)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
 }
 
 impl Drop for UnixFile<'_> {
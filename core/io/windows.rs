@@ -127,4 +127,12 @@ impl File for WindowsFile {
         let file = self.file.read();
         Ok(file.metadata().unwrap().len())
     }
+
+    #[instrument(err, skip_all, level = Level::TRACE)]
+    fn truncate(&self, len: usize, c: Arc<Completion>) -> Result<Arc<Completion>> {
+        let file = self.file.write();
+        file.set_len(len as u64).map_err(LimboError::IOError)?;
+        c.complete(0);
+        Ok(c)
+    }
 }
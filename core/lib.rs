@@ -1,5 +1,6 @@
 #![allow(clippy::arc_with_non_send_sync)]
 
+mod analyze;
 mod assert;
 mod error;
 mod ext;
@@ -27,6 +28,7 @@ pub mod types;
 mod util;
 #[cfg(feature = "uuid")]
 mod uuid;
+mod vacuum;
 mod vdbe;
 mod vector;
 mod vtab;
@@ -61,12 +63,13 @@ pub use io::{
     Buffer, Completion, CompletionType, File, MemoryIO, OpenFlags, PlatformIO, SyscallIO,
     WriteCompletion, IO,
 };
+pub use mvcc::database::IsolationLevel;
 use parking_lot::RwLock;
 use schema::Schema;
 use std::{
     borrow::Cow,
     cell::{Cell, RefCell, UnsafeCell},
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     fmt::{self, Display},
     io::Write,
     num::NonZero,
@@ -104,10 +107,25 @@ enum TransactionState {
     None,
 }
 
+/// A savepoint opened by the SAVEPOINT statement.
+struct Savepoint {
+    name: String,
+    /// Whether the savepoint was opened outside of a transaction, in which case releasing it
+    /// commits the transaction.
+    starts_transaction: bool,
+    /// The schema when the savepoint was opened, which ROLLBACK TO restores.
+    schema: Arc<Schema>,
+    /// The number of deferred foreign key violations when the savepoint was opened, which
+    /// ROLLBACK TO restores.
+    fk_deferred_violations: i64,
+}
+
 pub(crate) type MvStore = mvcc::MvStore<mvcc::LocalClock>;
 
 pub(crate) type MvCursor = mvcc::cursor::ScanCursor<mvcc::LocalClock>;
 
+pub(crate) type MvIndexCursor = mvcc::cursor::IndexCursor<mvcc::LocalClock>;
+
 pub struct Database {
     mv_store: Option<Rc<MvStore>>,
     schema: Mutex<Arc<Schema>>,
@@ -229,10 +247,15 @@ impl Database {
         let maybe_shared_wal = WalFileShared::open_shared_if_exists(&io, wal_path.as_str())?;
 
         let mv_store = if enable_mvcc {
-            Some(Rc::new(MvStore::new(
-                mvcc::LocalClock::new(),
-                mvcc::persistent_storage::Storage::new_noop(),
-            )))
+            let storage = if path == util::MEMORY_PATH {
+                mvcc::persistent_storage::Storage::new_noop()
+            } else {
+                mvcc::persistent_storage::Storage::new_logical_log(
+                    io.clone(),
+                    &format!("{path}-log"),
+                )?
+            };
+            Some(Rc::new(MvStore::new(mvcc::LocalClock::new(), storage)))
         } else {
             None
         };
@@ -282,9 +305,49 @@ impl Database {
                 Ok(())
             })?;
         }
+        if let Some(mv_store) = db
+            .mv_store
+            .as_ref()
+            .filter(|mv_store| mv_store.is_durable())
+        {
+            db.recover_mv_store(mv_store)?;
+        }
         Ok(db)
     }
 
+    /// Loads the tables and indexes of the b-tree file into the MVCC store and replays the logical
+    /// log over them. The schema is then read from the store, which has the tables and indexes
+    /// created since the last checkpoint.
+    fn recover_mv_store(self: &Arc<Database>, mv_store: &Rc<MvStore>) -> Result<()> {
+        let conn = self.connect()?;
+        let pager = conn.pager.borrow().clone();
+        if self.db_state.is_initialized() {
+            mvcc::checkpoint::load_tables(mv_store, &pager, &*self.clone_schema()?)?;
+        }
+        mv_store.recover()?;
+        if mv_store.scan_row_ids_for_table(1)?.is_empty() {
+            return Ok(());
+        }
+        let tx_id = mv_store.begin_tx();
+        let mv_cursor = Rc::new(RefCell::new(MvCursor::new(mv_store.clone(), tx_id, 1)?));
+        let syms = conn.syms.borrow();
+        let result = self.with_schema_mut(|schema| {
+            let mut recovered = Schema::new(schema.indexes_enabled);
+            recovered.schema_version = schema.schema_version;
+            if let Err(LimboError::ExtensionError(e)) =
+                recovered.make_from_btree(Some(mv_cursor), pager, &syms)
+            {
+                eprintln!("Warning: {e}");
+            }
+            // The indexes are only known once the schema is, so their entries are replayed last.
+            mv_store.recover_indexes(&mvcc::checkpoint::index_infos(&recovered))?;
+            *schema = recovered;
+            Ok(())
+        });
+        mv_store.commit_tx(tx_id)?;
+        result
+    }
+
     #[instrument(skip_all, level = Level::INFO)]
     pub fn connect(self: &Arc<Database>) -> Result<Arc<Connection>> {
         let pager = self.init_pager(None)?;
@@ -305,6 +368,7 @@ impl Database {
             ),
             database_schemas: RefCell::new(std::collections::HashMap::new()),
             auto_commit: Cell::new(true),
+            savepoints: RefCell::new(Vec::new()),
             mv_transactions: RefCell::new(Vec::new()),
             transaction_state: Cell::new(TransactionState::None),
             last_insert_rowid: Cell::new(0),
@@ -318,6 +382,14 @@ impl Database {
             capture_data_changes: RefCell::new(CaptureDataChangesMode::Off),
             closed: Cell::new(false),
             attached_databases: RefCell::new(DatabaseCatalog::new()),
+            database_transactions: RefCell::new(BTreeMap::new()),
+            temp_store: Cell::new(TempStore::Default),
+            foreign_keys: Cell::new(false),
+            defer_foreign_keys: Cell::new(false),
+            fk_deferred_violations: Cell::new(0),
+            ignore_check_constraints: Cell::new(false),
+            analysis_limit: Cell::new(0),
+            isolation_level: Cell::new(IsolationLevel::Snapshot),
         });
         let builtin_syms = self.builtin_syms.borrow();
         // add built-in extensions symbols to the connection to prevent having to load each time
@@ -581,6 +653,26 @@ impl CaptureDataChangesMode {
     }
 }
 
+/// Where the temp database of a connection is stored (`PRAGMA temp_store`).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TempStore {
+    /// In a file, like SQLite does by default.
+    Default,
+    File,
+    Memory,
+}
+
+impl TempStore {
+    /// Like SQLite, unknown values select the default.
+    pub fn parse(value: &str) -> TempStore {
+        match value.to_ascii_lowercase().as_str() {
+            "1" | "file" => TempStore::File,
+            "2" | "memory" => TempStore::Memory,
+            _ => TempStore::Default,
+        }
+    }
+}
+
 // Optimized for fast get() operations and supports unlimited attached databases.
 struct DatabaseCatalog {
     name_to_index: HashMap<String, usize>,
@@ -636,6 +728,20 @@ impl DatabaseCatalog {
         idx
     }
 
+    /// Backs the reserved temp index. The temp database has no name in the catalog, as it is
+    /// not an attached database.
+    fn set_temp(&mut self, data: (Arc<Database>, Rc<Pager>)) {
+        self.index_to_data.insert(1, data);
+    }
+
+    fn remove_temp(&mut self) -> Option<(Arc<Database>, Rc<Pager>)> {
+        self.index_to_data.remove(&1)
+    }
+
+    fn has_temp(&self) -> bool {
+        self.index_to_data.contains_key(&1)
+    }
+
     fn remove(&mut self, s: &str) -> Option<usize> {
         if let Some(index) = self.name_to_index.remove(s) {
             // Should be impossible to remove main or temp.
@@ -697,6 +803,8 @@ pub struct Connection {
     database_schemas: RefCell<std::collections::HashMap<usize, Arc<Schema>>>,
     /// Whether to automatically commit transaction
     auto_commit: Cell<bool>,
+    /// The open savepoints, outermost first
+    savepoints: RefCell<Vec<Savepoint>>,
     mv_transactions: RefCell<Vec<crate::mvcc::database::TxID>>,
     transaction_state: Cell<TransactionState>,
     last_insert_rowid: Cell<i64>,
@@ -711,8 +819,27 @@ pub struct Connection {
     wal_checkpoint_disabled: Cell<bool>,
     capture_data_changes: RefCell<CaptureDataChangesMode>,
     closed: Cell<bool>,
-    /// Attached databases
+    /// Attached databases, along with the temp database once it is opened
     attached_databases: RefCell<DatabaseCatalog>,
+    /// The transactions open on the databases other than main, by database index. They end
+    /// with the transaction of the main database.
+    database_transactions: RefCell<BTreeMap<usize, TransactionState>>,
+    /// Where the temp database is stored (`PRAGMA temp_store`).
+    temp_store: Cell<TempStore>,
+    /// Whether FOREIGN KEY constraints are enforced (`PRAGMA foreign_keys`).
+    foreign_keys: Cell<bool>,
+    /// Whether the checks of all FOREIGN KEY constraints are deferred until the transaction
+    /// commits (`PRAGMA defer_foreign_keys`). Turned off when the transaction ends.
+    defer_foreign_keys: Cell<bool>,
+    /// The number of foreign key violations of the transaction that are checked when it commits.
+    fk_deferred_violations: Cell<i64>,
+    /// Whether CHECK constraints are left unchecked (`PRAGMA ignore_check_constraints`).
+    ignore_check_constraints: Cell<bool>,
+    /// The number of rows of each index ANALYZE scans, 0 for all rows (`PRAGMA analysis_limit`).
+    analysis_limit: Cell<u64>,
+    /// The isolation level of the MVCC transactions the connection begins
+    /// (`PRAGMA isolation_level`).
+    isolation_level: Cell<IsolationLevel>,
 }
 
 impl Connection {
@@ -804,6 +931,7 @@ impl Connection {
 
                 Result::Ok(())
             })?;
+            self.load_analysis(0)?;
             Result::Ok(())
         };
 
@@ -888,6 +1016,7 @@ impl Connection {
                             *select,
                             &syms,
                             &[],
+                            &[],
                             &mut table_ref_counter,
                             translate::plan::QueryDestination::ResultRows,
                             &self.clone(),
@@ -970,7 +1099,14 @@ impl Connection {
         }
         let res = self._db.io.run_once();
         if let Err(ref e) = res {
-            vdbe::handle_program_error(&self.pager.borrow(), self, e)?;
+            vdbe::handle_program_error(
+                &self.pager.borrow(),
+                self,
+                e,
+                ast::ResolveType::Rollback,
+                None,
+                self.fk_deferred_violations.get(),
+            )?;
         }
         res
     }
@@ -1002,10 +1138,13 @@ impl Connection {
 
     #[cfg(feature = "fs")]
     fn from_uri_attached(uri: &str, use_indexes: bool, use_mvcc: bool) -> Result<Arc<Database>> {
-        let mut opts = OpenOptions::parse(uri)?;
-        // FIXME: for now, only support read only attach
-        opts.mode = OpenMode::ReadOnly;
+        use crate::util::MEMORY_PATH;
+        let opts = OpenOptions::parse(uri)?;
         let flags = opts.get_flags()?;
+        if opts.path == MEMORY_PATH || matches!(opts.mode, OpenMode::Memory) {
+            let io = Arc::new(MemoryIO::new());
+            return Database::open_file_with_flags(io, MEMORY_PATH, flags, use_mvcc, use_indexes);
+        }
         let (_io, db) =
             Database::open_new(&opts.path, opts.vfs.as_ref(), flags, use_indexes, use_mvcc)?;
         if let Some(modeof) = opts.modeof {
@@ -1103,17 +1242,27 @@ impl Connection {
         if self.closed.get() {
             return Err(LimboError::InternalError("Connection closed".to_string()));
         }
+        if let Some(mv_store) = &self._db.mv_store {
+            self.checkpoint_mv_store(mv_store)?;
+        }
         self.pager
             .borrow()
             .wal_checkpoint(self.wal_checkpoint_disabled.get())
     }
 
+    /// Writes the rows changed by the transactions of the MVCC logical log to the b-tree file.
+    pub(crate) fn checkpoint_mv_store(&self, mv_store: &MvStore) -> Result<()> {
+        let pager = self.pager.borrow().clone();
+        mvcc::checkpoint::checkpoint(mv_store, &pager, self, self.wal_checkpoint_disabled.get())
+    }
+
     /// Close a connection and checkpoint.
     pub fn close(&self) -> Result<()> {
         if self.closed.get() {
             return Ok(());
         }
         self.closed.set(true);
+        self.close_temp_database();
         self.pager
             .borrow()
             .checkpoint_shutdown(self.wal_checkpoint_disabled.get())
@@ -1158,6 +1307,43 @@ impl Connection {
     pub fn set_capture_data_changes(&self, opts: CaptureDataChangesMode) {
         self.capture_data_changes.replace(opts);
     }
+    pub fn foreign_keys_enabled(&self) -> bool {
+        self.foreign_keys.get()
+    }
+    pub fn set_foreign_keys(&self, enabled: bool) {
+        self.foreign_keys.set(enabled);
+    }
+    pub fn get_defer_foreign_keys(&self) -> bool {
+        self.defer_foreign_keys.get()
+    }
+    pub fn set_defer_foreign_keys(&self, enabled: bool) {
+        self.defer_foreign_keys.set(enabled);
+    }
+    pub fn get_ignore_check_constraints(&self) -> bool {
+        self.ignore_check_constraints.get()
+    }
+    pub fn set_ignore_check_constraints(&self, ignore: bool) {
+        self.ignore_check_constraints.set(ignore);
+    }
+    pub fn get_analysis_limit(&self) -> u64 {
+        self.analysis_limit.get()
+    }
+    pub fn set_analysis_limit(&self, limit: u64) {
+        self.analysis_limit.set(limit);
+    }
+    pub fn get_isolation_level(&self) -> IsolationLevel {
+        self.isolation_level.get()
+    }
+    /// Sets the isolation level of the MVCC transactions that begin from now on. Statements
+    /// that already began their transaction keep theirs.
+    pub fn set_isolation_level(&self, isolation_level: IsolationLevel) {
+        self.isolation_level.set(isolation_level);
+    }
+    /// Forgets the deferred foreign key violations of the transaction that ended.
+    pub(crate) fn end_fk_transaction(&self) {
+        self.fk_deferred_violations.set(0);
+        self.defer_foreign_keys.set(false);
+    }
     pub fn get_page_size(&self) -> u32 {
         self.page_size.get()
     }
@@ -1179,6 +1365,8 @@ impl Connection {
     pub fn is_readonly(&self, index: usize) -> bool {
         if index == 0 {
             self._db.is_readonly()
+        } else if index == 1 {
+            false
         } else {
             let db = self
                 .attached_databases
@@ -1360,13 +1548,262 @@ impl Connection {
     }
 
     fn get_pager_from_database_index(&self, index: &usize) -> Rc<Pager> {
-        if *index < 2 {
+        if *index == 0 {
             self.pager.borrow().clone()
         } else {
             self.attached_databases.borrow().get_pager_by_index(index)
         }
     }
 
+    /// Opens the temp database if it is not open yet. Like in SQLite, it is private to the
+    /// connection and created on first use, in a file that is deleted once it is open, unless
+    /// `PRAGMA temp_store` keeps it in memory.
+    #[cfg(feature = "fs")]
+    fn open_temp_database(&self) -> Result<()> {
+        use crate::util::MEMORY_PATH;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static NEXT_TEMP_DATABASE: AtomicUsize = AtomicUsize::new(0);
+
+        if self.attached_databases.borrow().has_temp() {
+            return Ok(());
+        }
+        if self._db.mv_store.is_some() {
+            return Err(LimboError::ParseError(
+                "temporary databases are not supported in MVCC mode".to_string(),
+            ));
+        }
+        let (io, path): (Arc<dyn IO>, String) = match self.temp_store.get() {
+            TempStore::Memory => (Arc::new(MemoryIO::new()), MEMORY_PATH.to_string()),
+            TempStore::Default | TempStore::File => {
+                let name = format!(
+                    "turso_temp_{}_{}",
+                    std::process::id(),
+                    NEXT_TEMP_DATABASE.fetch_add(1, Ordering::Relaxed)
+                );
+                let path = std::env::temp_dir().join(name);
+                (self._db.io.clone(), path.to_string_lossy().to_string())
+            }
+        };
+        let db = Database::open_file_with_flags(
+            io,
+            &path,
+            OpenFlags::Create,
+            false,
+            self.schema.borrow().indexes_enabled(),
+        )?;
+        let pager = Rc::new(db.init_pager(None)?);
+        if path != MEMORY_PATH {
+            // The open files stay usable, like those SQLite opens with DELETEONCLOSE
+            let _ = std::fs::remove_file(&path);
+            let _ = std::fs::remove_file(format!("{path}-wal"));
+        }
+        self.database_schemas
+            .borrow_mut()
+            .insert(1, db.clone_schema()?);
+        self.attached_databases.borrow_mut().set_temp((db, pager));
+        Ok(())
+    }
+
+    #[cfg(not(feature = "fs"))]
+    fn open_temp_database(&self) -> Result<()> {
+        Err(LimboError::InvalidArgument(
+            "temporary databases not available in this build (no-fs)".to_string(),
+        ))
+    }
+
+    /// Closes the temp database, dropping the temp tables, as changing where it is stored does
+    /// in SQLite.
+    fn close_temp_database(&self) {
+        self.attached_databases.borrow_mut().remove_temp();
+        self.database_schemas.borrow_mut().remove(&1);
+    }
+
+    pub fn get_temp_store(&self) -> TempStore {
+        self.temp_store.get()
+    }
+
+    pub fn set_temp_store(&self, temp_store: TempStore) -> Result<()> {
+        if temp_store == self.temp_store.get() {
+            return Ok(());
+        }
+        if self.attached_databases.borrow().has_temp() {
+            if !self.auto_commit.get() {
+                return Err(LimboError::TxError(
+                    "temporary storage cannot be changed from within a transaction".to_string(),
+                ));
+            }
+            self.close_temp_database();
+        }
+        self.temp_store.set(temp_store);
+        Ok(())
+    }
+
+    /// Starts a transaction on the database `database_id` other than main, which ends with
+    /// the transaction of the main database, see [Self::end_database_transactions].
+    fn begin_database_transaction(
+        &self,
+        database_id: usize,
+        write: bool,
+    ) -> Result<IOResult<result::LimboResult>> {
+        if database_id == 1 {
+            self.open_temp_database()?;
+        } else if write {
+            if self.is_readonly(database_id) {
+                return Err(LimboError::ReadOnly);
+            }
+            if self._db.mv_store.is_some() {
+                return Err(LimboError::ParseError(
+                    "writing to attached databases is not supported in MVCC mode".to_string(),
+                ));
+            }
+        }
+        let pager = self.get_pager_from_database_index(&database_id);
+        if database_id == 1 {
+            // The temp database is private to the connection, so its first page
+            // can be allocated as soon as it is used, even for reads.
+            if let IOResult::IO = pager.maybe_allocate_page1()? {
+                return Ok(IOResult::IO);
+            }
+        }
+        let current_state = self
+            .database_transactions
+            .borrow()
+            .get(&database_id)
+            .copied()
+            .unwrap_or(TransactionState::None);
+        if current_state == TransactionState::None {
+            if let result::LimboResult::Busy = pager.begin_read_tx()? {
+                return Ok(IOResult::Done(result::LimboResult::Busy));
+            }
+        }
+        let new_state = match current_state {
+            TransactionState::Write { .. } => return Ok(IOResult::Done(result::LimboResult::Ok)),
+            _ if !write => TransactionState::Read,
+            _ => {
+                let result = pager.begin_write_tx()?;
+                if !matches!(result, IOResult::Done(result::LimboResult::Ok)) {
+                    if current_state == TransactionState::None {
+                        pager.end_read_tx()?;
+                    }
+                    return Ok(result);
+                }
+                // Line the savepoints of the database up with the ones open on main, see
+                // [Self::savepoint_pagers].
+                pager.release_savepoint(0);
+                for _ in 0..self.savepoints.borrow().len() {
+                    pager.open_savepoint();
+                }
+                TransactionState::Write {
+                    schema_did_change: false,
+                }
+            }
+        };
+        self.database_transactions
+            .borrow_mut()
+            .insert(database_id, new_state);
+        Ok(IOResult::Done(result::LimboResult::Ok))
+    }
+
+    /// Returns the pager of main and the pagers of the other databases written in the current
+    /// transaction. Savepoints are opened, released and rolled back on all of them at the same
+    /// depth, so that they undo the changes to every database.
+    pub(crate) fn savepoint_pagers(&self) -> Vec<Rc<Pager>> {
+        let attached_databases = self.attached_databases.borrow();
+        let written = self
+            .database_transactions
+            .borrow()
+            .iter()
+            .filter(|(_, state)| matches!(state, TransactionState::Write { .. }))
+            .map(|(database_id, _)| attached_databases.get_pager_by_index(database_id))
+            .collect::<Vec<_>>();
+        std::iter::once(self.pager.borrow().clone())
+            .chain(written)
+            .collect()
+    }
+
+    /// Ends the transactions open on the databases other than main, committing them unless
+    /// `rollback` is set. The schema of a database is kept or restored along with its pages.
+    ///
+    /// The databases are committed one file at a time, in the order of their index, after the
+    /// main database. Like in SQLite in WAL mode, the commit is atomic within each file but
+    /// not across files: if committing one of them fails, the ones not committed yet are
+    /// rolled back and the error is returned.
+    fn end_database_transactions(&self, rollback: bool) -> Result<()> {
+        let transactions = std::mem::take(&mut *self.database_transactions.borrow_mut());
+        let mut rollback = rollback;
+        let mut result = Ok(());
+        for (database_id, state) in transactions {
+            let (db, pager) = self
+                .attached_databases
+                .borrow()
+                .index_to_data
+                .get(&database_id)
+                .cloned()
+                .expect("database of an open transaction must exist");
+            match state {
+                TransactionState::Write { schema_did_change } => {
+                    pager.release_savepoint(0);
+                    if !rollback {
+                        if let Err(e) = pager.io.block(|| {
+                            pager.end_tx(false, false, self, self.wal_checkpoint_disabled.get())
+                        }) {
+                            rollback = true;
+                            result = Err(e);
+                        }
+                    }
+                    if rollback {
+                        pager.end_tx(true, false, self, false)?;
+                        pager.rollback(false, self)?;
+                    }
+                    if schema_did_change {
+                        let mut schemas = self.database_schemas.borrow_mut();
+                        let mut committed_schema =
+                            db.schema.lock().map_err(|_| LimboError::SchemaLocked)?;
+                        match (rollback, schemas.get(&database_id)) {
+                            (false, Some(schema)) => *committed_schema = schema.clone(),
+                            _ => {
+                                schemas.insert(database_id, committed_schema.clone());
+                            }
+                        }
+                    }
+                }
+                TransactionState::Read => pager.end_read_tx()?,
+                TransactionState::None => {}
+            }
+        }
+        result
+    }
+
+    /// Records that the schema of the database `database_id` other than main changed in the
+    /// current transaction.
+    fn set_database_schema_did_change(&self, database_id: usize) {
+        if let Some(state) = self
+            .database_transactions
+            .borrow_mut()
+            .get_mut(&database_id)
+        {
+            *state = TransactionState::Write {
+                schema_did_change: true,
+            };
+        }
+    }
+
+    /// Mutates the schema of the database `database_id`.
+    pub(crate) fn with_database_schema_mut<T>(
+        &self,
+        database_id: usize,
+        f: impl FnOnce(&mut Schema) -> T,
+    ) -> T {
+        if database_id == 0 {
+            return self.with_schema_mut(f);
+        }
+        let mut schemas = self.database_schemas.borrow_mut();
+        let schema = schemas
+            .get_mut(&database_id)
+            .expect("schema of the database must be loaded");
+        f(Arc::make_mut(schema))
+    }
+
     #[cfg(feature = "fs")]
     fn is_attached(&self, alias: &str) -> bool {
         self.attached_databases
@@ -1433,13 +1870,23 @@ impl Connection {
             )));
         }
 
+        if let Some((index, _)) = self.get_attached_database(alias) {
+            if self.database_transactions.borrow().contains_key(&index) {
+                return Err(LimboError::InvalidArgument(format!(
+                    "database {alias} is locked"
+                )));
+            }
+        }
+
         // Remove from attached databases
         let mut attached_dbs = self.attached_databases.borrow_mut();
-        if attached_dbs.remove(alias).is_none() {
+        let Some(index) = attached_dbs.remove(alias) else {
             return Err(LimboError::InvalidArgument(format!(
                 "no such database: {alias}"
             )));
-        }
+        };
+        // The index may be reused by the next attached database
+        self.database_schemas.borrow_mut().remove(&index);
 
         Ok(())
     }
@@ -1449,6 +1896,22 @@ impl Connection {
         self.attached_databases.borrow().get_database_by_name(alias)
     }
 
+    /// Returns the name a database is referred to by in SQL.
+    pub(crate) fn get_database_name(&self, database_id: usize) -> String {
+        match database_id {
+            0 => "main".to_string(),
+            1 => "temp".to_string(),
+            _ => self
+                .attached_databases
+                .borrow()
+                .name_to_index
+                .iter()
+                .find(|(_, index)| **index == database_id)
+                .map(|(name, _)| name.clone())
+                .expect("attached database must exist"),
+        }
+    }
+
     /// List all attached database aliases
     pub fn list_attached_databases(&self) -> Vec<String> {
         self.attached_databases
@@ -1459,7 +1922,8 @@ impl Connection {
             .collect()
     }
 
-    /// Resolve database ID from a qualified name
+    /// Resolve database ID from a qualified name. Like in SQLite, an unqualified name refers
+    /// to the temp database if it has an object of that name, and to main otherwise.
     pub(crate) fn resolve_database_id(&self, qualified_name: &ast::QualifiedName) -> Result<usize> {
         use crate::util::normalize_ident;
 
@@ -1481,22 +1945,80 @@ impl Connection {
                     )))
                 }
             }
+        } else if self.temp_schema_has_object(qualified_name.name.as_str()) {
+            Ok(1)
         } else {
-            // Unqualified table name - use main database
             Ok(0)
         }
     }
 
+    /// Resolve the database an object is created in: temp for `CREATE TEMP`, the database
+    /// the name is qualified with, or main.
+    pub(crate) fn resolve_create_database_id(
+        &self,
+        qualified_name: &ast::QualifiedName,
+        temporary: bool,
+    ) -> Result<usize> {
+        match &qualified_name.db_name {
+            None if temporary => Ok(1),
+            None => Ok(0),
+            Some(_) => {
+                let database_id = self.resolve_database_id(qualified_name)?;
+                if temporary && database_id != 1 {
+                    return Err(LimboError::ParseError(
+                        "temporary table name must be unqualified".to_string(),
+                    ));
+                }
+                Ok(database_id)
+            }
+        }
+    }
+
+    /// Whether the temp database has a table, view or index named `name`.
+    fn temp_schema_has_object(&self, name: &str) -> bool {
+        let name = crate::util::normalize_ident(name);
+        if name == "sqlite_temp_master" || name == "sqlite_temp_schema" {
+            return true;
+        }
+        let schemas = self.database_schemas.borrow();
+        let Some(schema) = schemas.get(&1) else {
+            return false;
+        };
+        schema
+            .get_table(&name)
+            .is_some_and(|table| table.btree().is_some() && name != "sqlite_schema")
+            || schema.get_view(&name).is_some()
+            || schema
+                .indexes
+                .values()
+                .flatten()
+                .any(|index| index.name == name)
+    }
+
+    /// Returns the schema of a database, see [Self::with_schema].
+    pub(crate) fn database_schema(&self, database_id: usize) -> Arc<Schema> {
+        self.with_schema(database_id, |schema| schema.clone())
+    }
+
     /// Access schema for a database using a closure pattern to avoid cloning
-    pub(crate) fn with_schema<T>(&self, database_id: usize, f: impl FnOnce(&Schema) -> T) -> T {
+    pub(crate) fn with_schema<T>(
+        &self,
+        database_id: usize,
+        f: impl FnOnce(&Arc<Schema>) -> T,
+    ) -> T {
         if database_id == 0 {
             // Main database - use connection's schema which should be kept in sync
             let schema = self.schema.borrow();
             f(&schema)
         } else if database_id == 1 {
-            // Temp database - uses same schema as main for now, but this will change later.
-            let schema = self.schema.borrow();
-            f(&schema)
+            // Temp database - empty until it is opened
+            let schemas = self.database_schemas.borrow();
+            match schemas.get(&1) {
+                Some(schema) => f(schema),
+                None => f(&Arc::new(Schema::new(
+                    self.schema.borrow().indexes_enabled(),
+                ))),
+            }
         } else {
             // Attached database - check cache first, then load from database
             let mut schemas = self.database_schemas.borrow_mut();
@@ -1548,6 +2070,11 @@ impl Connection {
         let main_path = Self::get_canonical_path_for_database(&self._db);
         databases.push((0, "main".to_string(), main_path));
 
+        // Like in SQLite, the temp database is listed once it is opened, without a path
+        if self.attached_databases.borrow().has_temp() {
+            databases.push((1, "temp".to_string(), String::new()));
+        }
+
         // Add attached databases
         let attached_dbs = self.attached_databases.borrow();
         for (alias, &seq_number) in attached_dbs.name_to_index.iter() {
@@ -0,0 +1,242 @@
+//! Moving rows between the b-tree file and the [MvStore].
+//!
+//! In MVCC mode, transactions read and write the rows of the [MvStore], and the logical log makes
+//! their commits durable. When the database is opened, the rows of its tables and the entries of
+//! its indexes are loaded from the b-tree file and the transactions of the log are replayed over
+//! them. A checkpoint writes the rows and index entries changed by the transactions of the log to
+//! the b-tree file, and then truncates the log.
+
+use crate::mvcc::database::{IndexKey, Row, RowID};
+use crate::result::LimboResult;
+use crate::schema::{Index, Schema, Table};
+use crate::storage::btree::{BTreeCursor, BTreeKey};
+use crate::storage::pager::{CreateBTreeFlags, Pager};
+use crate::types::{ImmutableRecord, IndexInfo, SeekKey, SeekOp, SeekResult};
+use crate::util::IOExt;
+use crate::{Connection, LimboError, MvStore, Result, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Returns the key information of the indexes of `schema`, by index ID.
+pub(crate) fn index_infos(schema: &Schema) -> HashMap<u64, Arc<IndexInfo>> {
+    schema
+        .indexes
+        .values()
+        .flatten()
+        .filter(|index| index.root_page != 0)
+        .map(|index| {
+            (
+                index.root_page as u64,
+                Arc::new(IndexInfo::new_from_index(index)),
+            )
+        })
+        .collect()
+}
+
+/// Loads the rows of the rowid tables of `schema`, including `sqlite_schema`, and the entries of
+/// its indexes into `mv_store`.
+pub(crate) fn load_tables(mv_store: &MvStore, pager: &Rc<Pager>, schema: &Schema) -> Result<()> {
+    if matches!(pager.begin_read_tx()?, LimboResult::Busy) {
+        return Err(LimboError::Busy);
+    }
+    for table in schema.tables.values() {
+        let Table::BTree(table) = table.as_ref() else {
+            continue;
+        };
+        if !table.has_rowid || table.root_page == 0 {
+            continue;
+        }
+        let table_id = table.root_page as u64;
+        let mut cursor =
+            BTreeCursor::new_table(None, pager.clone(), table.root_page, table.columns.len());
+        pager.io.block(|| cursor.rewind())?;
+        loop {
+            let Some(row_id) = pager.io.block(|| cursor.rowid())? else {
+                break;
+            };
+            let data = match pager.io.block(|| cursor.record())? {
+                Some(record) => record.get_payload().to_vec(),
+                None => break,
+            };
+            mv_store.load_row(Row::new(RowID::new(table_id, row_id), data));
+            pager.io.block(|| cursor.next())?;
+        }
+    }
+    for index in schema.indexes.values().flatten() {
+        if index.root_page == 0 {
+            continue;
+        }
+        let info = Arc::new(IndexInfo::new_from_index(index));
+        let mut cursor = BTreeCursor::new_index(
+            None,
+            pager.clone(),
+            index.root_page,
+            index,
+            index.columns.len(),
+        );
+        pager.io.block(|| cursor.rewind())?;
+        loop {
+            let record = match pager.io.block(|| cursor.record())? {
+                Some(record) => record.clone(),
+                None => break,
+            };
+            mv_store.load_index_entry(IndexKey::new(index.root_page as u64, record, info.clone()));
+            pager.io.block(|| cursor.next())?;
+        }
+    }
+    pager.end_read_tx()?;
+    Ok(())
+}
+
+/// Writes the rows and index entries changed by the transactions of the logical log to the b-tree
+/// file, and truncates the log.
+pub(crate) fn checkpoint(
+    mv_store: &MvStore,
+    pager: &Rc<Pager>,
+    connection: &Connection,
+    wal_checkpoint_disabled: bool,
+) -> Result<()> {
+    let rows = mv_store.logged_rows()?;
+    let index_entries = mv_store.logged_index_entries()?;
+    if rows.is_empty() && index_entries.is_empty() {
+        return Ok(());
+    }
+    if matches!(pager.begin_read_tx()?, LimboResult::Busy) {
+        return Err(LimboError::Busy);
+    }
+    if matches!(
+        pager.io.block(|| pager.begin_write_tx())?,
+        LimboResult::Busy
+    ) {
+        pager.end_read_tx()?;
+        return Err(LimboError::Busy);
+    }
+    let schema = connection.schema.borrow().clone();
+    let written = write_rows(pager, rows.into_iter())
+        .and_then(|_| write_index_entries(pager, &schema, index_entries));
+    if let Err(err) = written {
+        pager.rollback(false, connection)?;
+        pager
+            .io
+            .block(|| pager.end_tx(true, false, connection, true))?;
+        return Err(err);
+    }
+    pager
+        .io
+        .block(|| pager.end_tx(false, false, connection, wal_checkpoint_disabled))?;
+    mv_store.truncate_log()?;
+    Ok(())
+}
+
+/// Creates a b-tree in a transaction of its own, so that its root page is in the b-tree file
+/// before the logical log refers to it.
+pub(crate) fn create_btree(
+    pager: &Rc<Pager>,
+    connection: &Connection,
+    flags: &CreateBTreeFlags,
+) -> Result<u32> {
+    if matches!(pager.begin_read_tx()?, LimboResult::Busy) {
+        return Err(LimboError::Busy);
+    }
+    if matches!(
+        pager.io.block(|| pager.begin_write_tx())?,
+        LimboResult::Busy
+    ) {
+        pager.end_read_tx()?;
+        return Err(LimboError::Busy);
+    }
+    let root_page = match pager.io.block(|| pager.btree_create(flags)) {
+        Ok(root_page) => root_page,
+        Err(err) => {
+            pager.rollback(false, connection)?;
+            pager
+                .io
+                .block(|| pager.end_tx(true, false, connection, true))?;
+            return Err(err);
+        }
+    };
+    pager
+        .io
+        .block(|| pager.end_tx(false, false, connection, true))?;
+    Ok(root_page)
+}
+
+fn write_rows(
+    pager: &Rc<Pager>,
+    rows: impl Iterator<Item = (RowID, Option<Vec<u8>>)>,
+) -> Result<()> {
+    let mut cursor: Option<BTreeCursor> = None;
+    for (id, data) in rows {
+        let cursor = match &mut cursor {
+            Some(cursor) if cursor.root_page() == id.table_id as usize => cursor,
+            cursor => cursor.insert(BTreeCursor::new_table(
+                None,
+                pager.clone(),
+                id.table_id as usize,
+                0,
+            )),
+        };
+        let exists = pager
+            .io
+            .block(|| cursor.exists(&Value::Integer(id.row_id)))?;
+        match data {
+            Some(data) => {
+                let mut record = ImmutableRecord::new(data.len());
+                record.start_serialization(&data);
+                let key = BTreeKey::new_table_rowid(id.row_id, Some(&record));
+                pager.io.block(|| cursor.insert(&key, true))?;
+            }
+            None if exists => pager.io.block(|| cursor.delete())?,
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+fn write_index_entries(
+    pager: &Rc<Pager>,
+    schema: &Schema,
+    entries: BTreeMap<(u64, Vec<u8>), bool>,
+) -> Result<()> {
+    let indexes: HashMap<u64, &Arc<Index>> = schema
+        .indexes
+        .values()
+        .flatten()
+        .map(|index| (index.root_page as u64, index))
+        .collect();
+    let mut cursor: Option<BTreeCursor> = None;
+    for ((index_id, data), present) in entries {
+        // The entries of a dropped index have nowhere to go.
+        let Some(index) = indexes.get(&index_id) else {
+            continue;
+        };
+        let cursor = match &mut cursor {
+            Some(cursor) if cursor.root_page() == index.root_page => cursor,
+            cursor => cursor.insert(BTreeCursor::new_index(
+                None,
+                pager.clone(),
+                index.root_page,
+                index,
+                index.columns.len(),
+            )),
+        };
+        let mut record = ImmutableRecord::new(data.len());
+        record.start_serialization(&data);
+        let exists = matches!(
+            pager
+                .io
+                .block(|| cursor.seek(SeekKey::IndexKey(&record), SeekOp::GE { eq_only: true }))?,
+            SeekResult::Found
+        );
+        match (present, exists) {
+            (true, false) => {
+                let key = BTreeKey::new_index_key(&record);
+                pager.io.block(|| cursor.insert(&key, true))?;
+            }
+            (false, true) => pager.io.block(|| cursor.delete())?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
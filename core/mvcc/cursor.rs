@@ -1,25 +1,33 @@
 use crate::mvcc::clock::LogicalClock;
-use crate::mvcc::database::{MvStore, Result, Row, RowID};
+use crate::mvcc::database::{IndexKey, MvStore, Result, Row, RowID};
+use crate::translate::plan::IterationDirection;
 use crate::turso_assert;
+use crate::types::{ImmutableRecord, IndexInfo, SeekOp};
+use std::cmp::Ordering;
 use std::fmt::Debug;
+use std::ops::Bound;
 use std::rc::Rc;
+use std::sync::Arc;
 
 #[derive(Debug)]
 pub struct ScanCursor<Clock: LogicalClock> {
     pub db: Rc<MvStore<Clock>>,
     pub row_ids: Vec<RowID>,
     pub index: usize,
+    table_id: u64,
     tx_id: u64,
 }
 
 impl<Clock: LogicalClock> ScanCursor<Clock> {
     pub fn new(db: Rc<MvStore<Clock>>, tx_id: u64, table_id: u64) -> Result<ScanCursor<Clock>> {
+        db.track_table_scan(tx_id, table_id)?;
         let row_ids = db.scan_row_ids_for_table(table_id)?;
         Ok(Self {
             db,
             tx_id,
             row_ids,
             index: 0,
+            table_id,
         })
     }
 
@@ -55,14 +63,181 @@ impl<Clock: LogicalClock> ScanCursor<Clock> {
     }
 
     pub fn forward(&mut self) -> bool {
-        self.index += 1;
-        let idx = self.index - 1;
-        idx < self.row_ids.len()
+        loop {
+            self.index += 1;
+            let idx = self.index - 1;
+            if idx >= self.row_ids.len() {
+                return false;
+            }
+            // Rows without a version visible to the transaction, e.g. rows inserted by a
+            // transaction that has not committed, are skipped.
+            if matches!(self.db.read(self.tx_id, self.row_ids[idx]), Ok(Some(_))) {
+                return true;
+            }
+        }
     }
 
     pub fn is_empty(&self) -> bool {
         self.index >= self.row_ids.len()
     }
+
+    /// Positions the cursor on the row that `op` seeks for `row_id` among the rows visible to the
+    /// transaction, and returns whether there is such a row.
+    pub fn seek(&mut self, row_id: i64, op: SeekOp) -> Result<bool> {
+        let id = RowID::new(self.table_id, row_id);
+        if op.eq_only() {
+            if self.db.read(self.tx_id, id)?.is_none() {
+                return Ok(false);
+            }
+            // The row may have been inserted after the cursor listed the rows of the table.
+            let position = self.row_ids.binary_search(&id).unwrap_or_else(|position| {
+                self.row_ids.insert(position, id);
+                position
+            });
+            self.index = position + 1;
+            return Ok(true);
+        }
+        // The rows before the boundary are the ones that compare less than the seek key (GE, LT),
+        // or less than or equal to it (GT, LE).
+        let boundary = match op {
+            SeekOp::GE { .. } | SeekOp::LT => self.row_ids.partition_point(|r| *r < id),
+            SeekOp::GT | SeekOp::LE { .. } => self.row_ids.partition_point(|r| *r <= id),
+        };
+        let is_visible = |id: &RowID| matches!(self.db.read(self.tx_id, *id), Ok(Some(_)));
+        let position = match op.iteration_direction() {
+            IterationDirection::Forwards => self.row_ids[boundary..]
+                .iter()
+                .position(is_visible)
+                .map(|position| boundary + position),
+            IterationDirection::Backwards => self.row_ids[..boundary].iter().rposition(is_visible),
+        };
+        match position {
+            Some(position) => {
+                self.index = position + 1;
+                Ok(true)
+            }
+            None => {
+                self.index = self.row_ids.len() + 1;
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// A cursor over the entries of an index that are visible to a transaction, in index order.
+#[derive(Debug)]
+pub struct IndexCursor<Clock: LogicalClock> {
+    pub db: Rc<MvStore<Clock>>,
+    index_id: u64,
+    info: Arc<IndexInfo>,
+    /// The number of leading columns that are unique in the index, if any.
+    unique_columns: Option<usize>,
+    /// The entry the cursor is on.
+    current: Option<IndexKey>,
+    tx_id: u64,
+}
+
+impl<Clock: LogicalClock> IndexCursor<Clock> {
+    pub fn new(
+        db: Rc<MvStore<Clock>>,
+        tx_id: u64,
+        index_id: u64,
+        info: Arc<IndexInfo>,
+        unique_columns: Option<usize>,
+    ) -> Result<IndexCursor<Clock>> {
+        db.track_table_scan(tx_id, index_id)?;
+        Ok(Self {
+            db,
+            index_id,
+            info,
+            unique_columns,
+            current: None,
+            tx_id,
+        })
+    }
+
+    /// Moves the cursor to the first entry of the index, and returns whether there is one.
+    pub fn rewind(&mut self) -> Result<bool> {
+        self.current = self
+            .db
+            .next_index_entry(self.tx_id, self.index_id, Bound::Unbounded)?;
+        Ok(self.current.is_some())
+    }
+
+    /// Moves the cursor to the last entry of the index, and returns whether there is one.
+    pub fn last(&mut self) -> Result<bool> {
+        self.current = self
+            .db
+            .prev_index_entry(self.tx_id, self.index_id, Bound::Unbounded)?;
+        Ok(self.current.is_some())
+    }
+
+    pub fn forward(&mut self) -> Result<bool> {
+        if let Some(current) = self.current.take() {
+            self.current =
+                self.db
+                    .next_index_entry(self.tx_id, self.index_id, Bound::Excluded(&current))?;
+        }
+        Ok(self.current.is_some())
+    }
+
+    pub fn backward(&mut self) -> Result<bool> {
+        if let Some(current) = self.current.take() {
+            self.current =
+                self.db
+                    .prev_index_entry(self.tx_id, self.index_id, Bound::Excluded(&current))?;
+        }
+        Ok(self.current.is_some())
+    }
+
+    /// Positions the cursor on the entry that `op` seeks for `record`, whose columns are compared
+    /// with the leading columns of the entries. Returns whether the cursor is on an entry that
+    /// satisfies `op`; if `op` is an equality seek that finds no equal entry, the cursor is still
+    /// on the entry next to where one would be, if any.
+    pub fn seek(&mut self, record: &ImmutableRecord, op: SeekOp) -> Result<bool> {
+        let bias = match op {
+            SeekOp::GE { .. } | SeekOp::LT => Ordering::Less,
+            SeekOp::GT | SeekOp::LE { .. } => Ordering::Greater,
+        };
+        let key = IndexKey::seek(self.index_id, record.clone(), self.info.clone(), bias);
+        self.current = match op.iteration_direction() {
+            IterationDirection::Forwards => {
+                self.db
+                    .next_index_entry(self.tx_id, self.index_id, Bound::Included(&key))?
+            }
+            IterationDirection::Backwards => {
+                self.db
+                    .prev_index_entry(self.tx_id, self.index_id, Bound::Included(&key))?
+            }
+        };
+        Ok(match &self.current {
+            Some(current) if op.eq_only() => current.cmp_prefix(&key) == Ordering::Equal,
+            Some(_) => true,
+            None => false,
+        })
+    }
+
+    pub fn current_record(&self) -> Option<&ImmutableRecord> {
+        self.current.as_ref().map(|key| &key.record)
+    }
+
+    pub fn insert(&self, record: ImmutableRecord) -> Result<()> {
+        let key = IndexKey::new(self.index_id, record, self.info.clone());
+        self.db
+            .insert_index_entry(self.tx_id, key, self.unique_columns)
+    }
+
+    /// Deletes the entry the cursor is on.
+    pub fn delete(&self) -> Result<bool> {
+        match &self.current {
+            Some(key) => self.db.delete_index_entry(self.tx_id, key),
+            None => Ok(false),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.current.is_none()
+    }
 }
 
 #[derive(Debug)]
@@ -76,6 +251,7 @@ pub struct LazyScanCursor<Clock: LogicalClock> {
 
 impl<Clock: LogicalClock> LazyScanCursor<Clock> {
     pub fn new(db: Rc<MvStore<Clock>>, tx_id: u64, table_id: u64) -> Result<LazyScanCursor<Clock>> {
+        db.track_table_scan(tx_id, table_id)?;
         let current_pos = db.get_next_row_id_for_table(table_id, 0);
         Ok(Self {
             db,
@@ -140,6 +316,7 @@ impl<Clock: LogicalClock> BucketScanCursor<Clock> {
         table_id: u64,
         size: u64,
     ) -> Result<BucketScanCursor<Clock>> {
+        db.track_table_scan(tx_id, table_id)?;
         let mut bucket = Vec::with_capacity(size as usize);
         db.get_row_id_range(table_id, 0, &mut bucket, size)?;
         Ok(Self {
@@ -1,10 +1,13 @@
 use crate::mvcc::clock::LogicalClock;
 use crate::mvcc::errors::DatabaseError;
-use crate::mvcc::persistent_storage::Storage;
-use crossbeam_skiplist::{SkipMap, SkipSet};
+use crate::mvcc::persistent_storage::{Storage, DEFAULT_CHECKPOINT_THRESHOLD};
+use crate::types::{compare_immutable, ImmutableRecord, IndexInfo, RefValue, Value};
+use crossbeam_skiplist::{map::Entry, SkipMap, SkipSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::Debug;
+use std::ops::Bound;
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::RwLock;
+use std::sync::{Arc, RwLock};
 
 pub type Result<T> = std::result::Result<T, DatabaseError>;
 
@@ -36,12 +39,120 @@ impl Row {
     }
 }
 
+/// The key of an index entry: the ID of the index, which is the root page of its b-tree, and the
+/// index record, which ends with the rowid of the indexed row if the index has one.
+///
+/// Keys are ordered like the entries of the index b-tree, by the sort order and collation of the
+/// index columns.
+#[derive(Clone, Debug)]
+pub struct IndexKey {
+    pub index_id: u64,
+    pub record: ImmutableRecord,
+    info: Arc<IndexInfo>,
+    /// Seek keys are not entries of the index, and order before (`Less`) or after (`Greater`) all
+    /// the entries whose leading columns they are equal to.
+    bias: Option<std::cmp::Ordering>,
+}
+
+impl IndexKey {
+    pub fn new(index_id: u64, record: ImmutableRecord, info: Arc<IndexInfo>) -> Self {
+        Self {
+            index_id,
+            record,
+            info,
+            bias: None,
+        }
+    }
+
+    /// A seek key that orders before or after, according to `bias`, all the entries of the index
+    /// whose leading columns are equal to the columns of `record`.
+    pub fn seek(
+        index_id: u64,
+        record: ImmutableRecord,
+        info: Arc<IndexInfo>,
+        bias: std::cmp::Ordering,
+    ) -> Self {
+        Self {
+            index_id,
+            record,
+            info,
+            bias: Some(bias),
+        }
+    }
+
+    /// A seek key that orders before (`Less`) or after (`Greater`) all the entries of the index.
+    fn index_bound(index_id: u64, bias: std::cmp::Ordering) -> Self {
+        let record = ImmutableRecord::from_values(std::iter::empty(), 0);
+        Self::seek(index_id, record, Arc::default(), bias)
+    }
+
+    pub fn info(&self) -> &Arc<IndexInfo> {
+        &self.info
+    }
+
+    /// The rowid of the indexed row, or 0 if the index has no rowid.
+    pub fn row_id(&self) -> i64 {
+        if !self.info.has_rowid {
+            return 0;
+        }
+        match self.record.get_values().last() {
+            Some(RefValue::Integer(row_id)) => *row_id,
+            _ => 0,
+        }
+    }
+
+    /// Compares the columns of the shorter of the two records with the leading columns of the
+    /// other one.
+    pub fn cmp_prefix(&self, other: &IndexKey) -> std::cmp::Ordering {
+        let values = self.record.get_values();
+        let other_values = other.record.get_values();
+        let len = values.len().min(other_values.len());
+        compare_immutable(&values[..len], &other_values[..len], &self.info.key_info)
+    }
+}
+
+impl Ord for IndexKey {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.index_id.cmp(&other.index_id).then_with(|| {
+            let values = self.record.get_values();
+            let other_values = other.record.get_values();
+            let len = values.len().min(other_values.len());
+            let key_info = if self.bias.is_none() {
+                &self.info.key_info
+            } else {
+                &other.info.key_info
+            };
+            compare_immutable(&values[..len], &other_values[..len], key_info).then_with(|| {
+                match (self.bias, other.bias) {
+                    (Some(bias), _) => bias,
+                    (None, Some(bias)) => bias.reverse(),
+                    (None, None) => values.len().cmp(&other_values.len()),
+                }
+            })
+        })
+    }
+}
+
+impl PartialOrd for IndexKey {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for IndexKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == std::cmp::Ordering::Equal
+    }
+}
+
+impl Eq for IndexKey {}
+
 /// A row version.
 #[derive(Clone, Debug, PartialEq)]
 pub struct RowVersion {
-    begin: TxTimestampOrID,
-    end: Option<TxTimestampOrID>,
-    row: Row,
+    pub(crate) begin: TxTimestampOrID,
+    pub(crate) end: Option<TxTimestampOrID>,
+    pub(crate) row: Row,
 }
 
 pub type TxID = u64;
@@ -50,14 +161,18 @@ pub type TxID = u64;
 #[derive(Clone, Debug)]
 pub struct LogRecord {
     pub(crate) tx_timestamp: TxID,
-    row_versions: Vec<RowVersion>,
+    pub(crate) row_versions: Vec<RowVersion>,
+    /// The versions of index entries. The row of such a version has the index ID and the rowid
+    /// of the indexed row as its ID, and the index record as its data.
+    pub(crate) index_versions: Vec<RowVersion>,
 }
 
 impl LogRecord {
-    fn new(tx_timestamp: TxID) -> Self {
+    pub(crate) fn new(tx_timestamp: TxID) -> Self {
         Self {
             tx_timestamp,
             row_versions: Vec::new(),
+            index_versions: Vec::new(),
         }
     }
 }
@@ -69,13 +184,45 @@ impl LogRecord {
 /// transaction ID in the `begin` and `end` fields. After a transaction commits,
 /// versions switch to tracking timestamps.
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
-enum TxTimestampOrID {
+pub(crate) enum TxTimestampOrID {
     /// A committed transaction's timestamp.
     Timestamp(u64),
     /// The ID of a non-committed transaction.
     TxID(TxID),
 }
 
+/// The isolation level of a transaction.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IsolationLevel {
+    /// The transaction reads from the snapshot of the database taken when it began, and only
+    /// conflicts with the transactions that write the same rows. Write skew is possible.
+    #[default]
+    Snapshot,
+    /// Like [IsolationLevel::Snapshot], but the transaction also fails to commit if another
+    /// transaction committed, after it began, a row that it read or that its scans would see.
+    /// Transactions that commit appear to have run one after the other.
+    Serializable,
+}
+
+impl IsolationLevel {
+    pub fn parse(value: &str) -> Option<IsolationLevel> {
+        match value.to_ascii_lowercase().as_str() {
+            "snapshot" => Some(IsolationLevel::Snapshot),
+            "serializable" => Some(IsolationLevel::Serializable),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for IsolationLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IsolationLevel::Snapshot => write!(f, "snapshot"),
+            IsolationLevel::Serializable => write!(f, "serializable"),
+        }
+    }
+}
+
 /// Transaction
 #[derive(Debug)]
 pub struct Transaction {
@@ -85,20 +232,30 @@ pub struct Transaction {
     tx_id: u64,
     /// The transaction begin timestamp.
     begin_ts: u64,
+    /// The transaction isolation level.
+    isolation: IsolationLevel,
     /// The transaction write set.
     write_set: SkipSet<RowID>,
+    /// The index entries the transaction wrote.
+    index_write_set: SkipSet<IndexKey>,
     /// The transaction read set.
     read_set: SkipSet<RowID>,
+    /// The ranges of row IDs the transaction scanned, each from its first row ID, inclusive, to its
+    /// last one, exclusive.
+    scan_set: SkipSet<(RowID, RowID)>,
 }
 
 impl Transaction {
-    fn new(tx_id: u64, begin_ts: u64) -> Transaction {
+    fn new(tx_id: u64, begin_ts: u64, isolation: IsolationLevel) -> Transaction {
         Transaction {
             state: TransactionState::Active.into(),
             tx_id,
             begin_ts,
+            isolation,
             write_set: SkipSet::new(),
+            index_write_set: SkipSet::new(),
             read_set: SkipSet::new(),
+            scan_set: SkipSet::new(),
         }
     }
 
@@ -106,9 +263,17 @@ impl Transaction {
         self.read_set.insert(id);
     }
 
+    fn insert_to_scan_set(&self, start: RowID, end: RowID) {
+        self.scan_set.insert((start, end));
+    }
+
     fn insert_to_write_set(&mut self, id: RowID) {
         self.write_set.insert(id);
     }
+
+    fn insert_to_index_write_set(&mut self, key: IndexKey) {
+        self.index_write_set.insert(key);
+    }
 }
 
 impl std::fmt::Display for Transaction {
@@ -225,6 +390,7 @@ impl AtomicTransactionState {
 #[derive(Debug)]
 pub struct MvStore<Clock: LogicalClock> {
     rows: SkipMap<RowID, RwLock<Vec<RowVersion>>>,
+    index_rows: SkipMap<IndexKey, RwLock<Vec<RowVersion>>>,
     txs: SkipMap<TxID, RwLock<Transaction>>,
     tx_ids: AtomicU64,
     next_rowid: AtomicU64,
@@ -237,9 +403,10 @@ impl<Clock: LogicalClock> MvStore<Clock> {
     pub fn new(clock: Clock, storage: Storage) -> Self {
         Self {
             rows: SkipMap::new(),
+            index_rows: SkipMap::new(),
             txs: SkipMap::new(),
             tx_ids: AtomicU64::new(1), // let's reserve transaction 0 for special purposes
-            next_rowid: AtomicU64::new(0), // TODO: determine this from B-Tree
+            next_rowid: AtomicU64::new(0), // advanced past the loaded and recovered rows
             clock,
             storage,
         }
@@ -388,6 +555,9 @@ impl<Clock: LogicalClock> MvStore<Clock> {
         let tx = self.txs.get(&tx_id).unwrap();
         let tx = tx.value().read().unwrap();
         assert_eq!(tx.state, TransactionState::Active);
+        // Reads of missing rows are tracked as well, so that a serializable transaction
+        // notices when the row is inserted by another transaction.
+        tx.insert_to_read_set(id);
         if let Some(row_versions) = self.rows.get(&id) {
             let row_versions = row_versions.value().read().unwrap();
             if let Some(rv) = row_versions
@@ -395,7 +565,6 @@ impl<Clock: LogicalClock> MvStore<Clock> {
                 .rev()
                 .find(|rv| rv.is_visible_to(&tx, &self.txs))
             {
-                tx.insert_to_read_set(id);
                 return Ok(Some(rv.row.clone()));
             }
         }
@@ -427,6 +596,20 @@ impl<Clock: LogicalClock> MvStore<Clock> {
             .collect())
     }
 
+    /// Records that the transaction `tx_id` scans the rows of the table, or the entries of the
+    /// index, `table_id`, so that a serializable transaction can detect the rows that other
+    /// transactions insert or delete meanwhile (phantoms).
+    pub fn track_table_scan(&self, tx_id: TxID, table_id: u64) -> Result<()> {
+        tracing::trace!("track_table_scan(tx_id={}, table_id={})", tx_id, table_id);
+        let tx = self
+            .txs
+            .get(&tx_id)
+            .ok_or(DatabaseError::NoSuchTransactionID(tx_id))?;
+        let tx = tx.value().read().unwrap();
+        tx.insert_to_scan_set(RowID::new(table_id, 0), RowID::new(table_id, i64::MAX));
+        Ok(())
+    }
+
     pub fn get_row_id_range(
         &self,
         table_id: u64,
@@ -479,16 +662,193 @@ impl<Clock: LogicalClock> MvStore<Clock> {
             .map(|entry| *entry.key())
     }
 
+    /// Inserts a new entry into an index.
+    ///
+    /// If `unique_columns` is `Some(n)`, the first `n` columns of the entry are unique in the
+    /// index. The transaction cannot see the entries of concurrent transactions, so this function
+    /// checks that none of them has the same unique columns, and otherwise rolls the transaction
+    /// back and fails with a write-write conflict. The entries the transaction can see are for
+    /// the caller to check, as it does for other uniqueness violations.
+    ///
+    /// # Arguments
+    ///
+    /// * `tx_id` - the ID of the transaction in which to insert the entry.
+    /// * `key` - the key of the entry.
+    /// * `unique_columns` - the number of leading columns that are unique in the index, if any.
+    pub fn insert_index_entry(
+        &self,
+        tx_id: TxID,
+        key: IndexKey,
+        unique_columns: Option<usize>,
+    ) -> Result<()> {
+        tracing::trace!("insert_index_entry(tx_id={}, key={:?})", tx_id, key);
+        if let Some(unique_columns) = unique_columns {
+            if self.has_hidden_duplicate(tx_id, &key, unique_columns)? {
+                self.rollback_tx(tx_id);
+                return Err(DatabaseError::WriteWriteConflict);
+            }
+        }
+        let tx = self
+            .txs
+            .get(&tx_id)
+            .ok_or(DatabaseError::NoSuchTransactionID(tx_id))?;
+        let mut tx = tx.value().write().unwrap();
+        assert_eq!(tx.state, TransactionState::Active);
+        let row_version = RowVersion {
+            begin: TxTimestampOrID::TxID(tx.tx_id),
+            end: None,
+            row: Row::new(
+                RowID::new(key.index_id, key.row_id()),
+                key.record.get_payload().to_vec(),
+            ),
+        };
+        tx.insert_to_index_write_set(key.clone());
+        drop(tx);
+        let versions = self
+            .index_rows
+            .get_or_insert_with(key, || RwLock::new(Vec::new()));
+        let mut versions = versions.value().write().unwrap();
+        self.insert_version_raw(&mut versions, row_version);
+        Ok(())
+    }
+
+    /// Deletes an entry from an index.
+    ///
+    /// Like [MvStore::delete], but for the index entry with the given `key`.
+    pub fn delete_index_entry(&self, tx_id: TxID, key: &IndexKey) -> Result<bool> {
+        tracing::trace!("delete_index_entry(tx_id={}, key={:?})", tx_id, key);
+        let Some(versions) = self.index_rows.get(key) else {
+            return Ok(false);
+        };
+        let tx_unlocked = self
+            .txs
+            .get(&tx_id)
+            .ok_or(DatabaseError::NoSuchTransactionID(tx_id))?;
+        let mut row_versions = versions.value().write().unwrap();
+        let tx = tx_unlocked.value().read().unwrap();
+        assert_eq!(tx.state, TransactionState::Active);
+        // A transaction cannot delete a version that it cannot see, nor can it conflict with it.
+        let Some(rv) = row_versions
+            .iter_mut()
+            .rev()
+            .find(|rv| rv.is_visible_to(&tx, &self.txs))
+        else {
+            return Ok(false);
+        };
+        if is_write_write_conflict(&self.txs, &tx, rv) {
+            drop(tx);
+            drop(row_versions);
+            self.rollback_tx(tx_id);
+            return Err(DatabaseError::WriteWriteConflict);
+        }
+        rv.end = Some(TxTimestampOrID::TxID(tx_id));
+        drop(tx);
+        drop(row_versions);
+        let mut tx = tx_unlocked.value().write().unwrap();
+        tx.insert_to_index_write_set(key.clone());
+        Ok(true)
+    }
+
+    /// Returns the first entry of the index `index_id`, from `start` on in index order, that is
+    /// visible to the transaction `tx_id`.
+    pub fn next_index_entry(
+        &self,
+        tx_id: TxID,
+        index_id: u64,
+        start: Bound<&IndexKey>,
+    ) -> Result<Option<IndexKey>> {
+        let end = IndexKey::index_bound(index_id, std::cmp::Ordering::Greater);
+        let entries = self.index_rows.range((start, Bound::Excluded(&end)));
+        self.first_visible_index_entry(tx_id, entries)
+    }
+
+    /// Returns the last entry of the index `index_id`, up to `end` in index order, that is visible
+    /// to the transaction `tx_id`.
+    pub fn prev_index_entry(
+        &self,
+        tx_id: TxID,
+        index_id: u64,
+        end: Bound<&IndexKey>,
+    ) -> Result<Option<IndexKey>> {
+        let start = IndexKey::index_bound(index_id, std::cmp::Ordering::Less);
+        let entries = self.index_rows.range((Bound::Excluded(&start), end));
+        self.first_visible_index_entry(tx_id, entries.rev())
+    }
+
+    fn first_visible_index_entry<'a>(
+        &'a self,
+        tx_id: TxID,
+        mut entries: impl Iterator<Item = Entry<'a, IndexKey, RwLock<Vec<RowVersion>>>>,
+    ) -> Result<Option<IndexKey>> {
+        let tx = self
+            .txs
+            .get(&tx_id)
+            .ok_or(DatabaseError::NoSuchTransactionID(tx_id))?;
+        let tx = tx.value().read().unwrap();
+        assert_eq!(tx.state, TransactionState::Active);
+        Ok(entries
+            .find(|entry| {
+                let row_versions = entry.value().read().unwrap();
+                row_versions
+                    .iter()
+                    .any(|rv| rv.is_visible_to(&tx, &self.txs))
+            })
+            .map(|entry| entry.key().clone()))
+    }
+
+    /// Whether another transaction wrote an entry of the index with the same first
+    /// `unique_columns` columns as `key`, that the transaction `tx_id` cannot see but that is, or
+    /// may still become, part of the index.
+    fn has_hidden_duplicate(
+        &self,
+        tx_id: TxID,
+        key: &IndexKey,
+        unique_columns: usize,
+    ) -> Result<bool> {
+        let values = key.record.get_values();
+        let unique_values = &values[..unique_columns.min(values.len())];
+        // NULLs are distinct from each other.
+        if unique_values.iter().any(|v| matches!(v, RefValue::Null)) {
+            return Ok(false);
+        }
+        let unique_values: Vec<Value> = unique_values.iter().map(|v| v.to_owned()).collect();
+        let prefix = ImmutableRecord::from_values(&unique_values, unique_values.len());
+        let seek = |bias| IndexKey::seek(key.index_id, prefix.clone(), key.info.clone(), bias);
+        let (start, end) = (
+            seek(std::cmp::Ordering::Less),
+            seek(std::cmp::Ordering::Greater),
+        );
+        let tx = self
+            .txs
+            .get(&tx_id)
+            .ok_or(DatabaseError::NoSuchTransactionID(tx_id))?;
+        let tx = tx.value().read().unwrap();
+        Ok(self.index_rows.range(start..end).any(|entry| {
+            let row_versions = entry.value().read().unwrap();
+            row_versions
+                .iter()
+                .any(|rv| is_hidden_duplicate(&self.txs, &tx, rv))
+        }))
+    }
+
     /// Begins a new transaction in the database.
     ///
     /// This function starts a new transaction in the database and returns a `TxID` value
     /// that you can use to perform operations within the transaction. All changes made within the
     /// transaction are isolated from other transactions until you commit the transaction.
     pub fn begin_tx(&self) -> TxID {
+        self.begin_tx_with_isolation(IsolationLevel::Snapshot)
+    }
+
+    /// Begins a new transaction with the given isolation level.
+    ///
+    /// See [MvStore::begin_tx]. A [IsolationLevel::Serializable] transaction may fail to commit
+    /// with [DatabaseError::SerializationFailure], in which case it can be retried.
+    pub fn begin_tx_with_isolation(&self, isolation: IsolationLevel) -> TxID {
         let tx_id = self.get_tx_id();
         let begin_ts = self.get_timestamp();
-        let tx = Transaction::new(tx_id, begin_ts);
-        tracing::trace!("begin_tx(tx_id={})", tx_id);
+        let tx = Transaction::new(tx_id, begin_ts, isolation);
+        tracing::trace!("begin_tx(tx_id={}, isolation={})", tx_id, isolation);
         self.txs.insert(tx_id, RwLock::new(tx));
         tx_id
     }
@@ -506,8 +866,8 @@ impl<Clock: LogicalClock> MvStore<Clock> {
         let end_ts = self.get_timestamp();
         // NOTICE: the first shadowed tx keeps the entry alive in the map
         // for the duration of this whole function, which is important for correctness!
-        let tx = self.txs.get(&tx_id).ok_or(DatabaseError::TxTerminated)?;
-        let tx = tx.value().write().unwrap();
+        let tx_unlocked = self.txs.get(&tx_id).ok_or(DatabaseError::TxTerminated)?;
+        let tx = tx_unlocked.value().write().unwrap();
         match tx.state.load() {
             TransactionState::Terminated => return Err(DatabaseError::TxTerminated),
             _ => {
@@ -516,83 +876,46 @@ impl<Clock: LogicalClock> MvStore<Clock> {
         }
         tx.state.store(TransactionState::Preparing);
         tracing::trace!("prepare_tx(tx_id={})", tx_id);
-
-        /* TODO: The code we have here is sufficient for snapshot isolation.
-        ** In order to implement serializability, we need the following steps:
-        **
-        ** 1. Validate if all read versions are still visible by inspecting the read_set
-        ** 2. Validate if there are no phantoms by walking the scans from scan_set (which we don't even have yet)
-        **    - a phantom is a version that became visible in the middle of our transaction,
-        **      but wasn't taken into account during one of the scans from the scan_set
-        ** 3. Wait for commit dependencies, which we don't even track yet...
-        **    Excerpt from what's a commit dependency and how it's tracked in the original paper:
-        **    """
-                A transaction T1 has a commit dependency on another transaction
-                T2, if T1 is allowed to commit only if T2 commits. If T2 aborts,
-                T1 must also abort, so cascading aborts are possible. T1 acquires a
-                commit dependency either by speculatively reading or speculatively ignoring a version,
-                instead of waiting for T2 to commit.
-                We implement commit dependencies by a register-and-report
-                approach: T1 registers its dependency with T2 and T2 informs T1
-                when it has committed or aborted. Each transaction T contains a
-                counter, CommitDepCounter, that counts how many unresolved
-                commit dependencies it still has. A transaction cannot commit
-                until this counter is zero. In addition, T has a Boolean variable
-                AbortNow that other transactions can set to tell T to abort. Each
-                transaction T also has a set, CommitDepSet, that stores transaction IDs
-                of the transactions that depend on T.
-                To take a commit dependency on a transaction T2, T1 increments
-                its CommitDepCounter and adds its transaction ID to T2’s CommitDepSet.
-                When T2 has committed, it locates each transaction in
-                its CommitDepSet and decrements their CommitDepCounter. If
-                T2 aborted, it tells the dependent transactions to also abort by
-                setting their AbortNow flags. If a dependent transaction is not
-                found, this means that it has already aborted.
-                Note that a transaction with commit dependencies may not have to
-                wait at all - the dependencies may have been resolved before it is
-                ready to commit. Commit dependencies consolidate all waits into
-                a single wait and postpone the wait to just before commit.
-                Some transactions may have to wait before commit.
-                Waiting raises a concern of deadlocks.
-                However, deadlocks cannot occur because an older transaction never
-                waits on a younger transaction. In
-                a wait-for graph the direction of edges would always be from a
-                younger transaction (higher end timestamp) to an older transaction
-                (lower end timestamp) so cycles are impossible.
-            """
-        **  If you're wondering when a speculative read happens, here you go:
-        **  Case 1: speculative read of TB:
-            """
-                If transaction TB is in the Preparing state, it has acquired an end
-                timestamp TS which will be V’s begin timestamp if TB commits.
-                A safe approach in this situation would be to have transaction T
-                wait until transaction TB commits. However, we want to avoid all
-                blocking during normal processing so instead we continue with
-                the visibility test and, if the test returns true, allow T to
-                speculatively read V. Transaction T acquires a commit dependency on
-                TB, restricting the serialization order of the two transactions. That
-                is, T is allowed to commit only if TB commits.
-            """
-        **  Case 2: speculative ignore of TE:
-            """
-                If TE’s state is Preparing, it has an end timestamp TS that will become
-                the end timestamp of V if TE does commit. If TS is greater than the read
-                time RT, it is obvious that V will be visible if TE commits. If TE
-                aborts, V will still be visible, because any transaction that updates
-                V after TE has aborted will obtain an end timestamp greater than
-                TS. If TS is less than RT, we have a more complicated situation:
-                if TE commits, V will not be visible to T but if TE aborts, it will
-                be visible. We could handle this by forcing T to wait until TE
-                commits or aborts but we want to avoid all blocking during normal processing.
-                Instead we allow T to speculatively ignore V and
-                proceed with its processing. Transaction T acquires a commit
-                dependency (see Section 2.7) on TE, that is, T is allowed to commit
-                only if TE commits.
-            """
-        */
+        // Snapshot isolation needs no validation: write-write conflicts were already detected
+        // when the rows were written.
+        //
+        // A serializable transaction T must also make sure that what it read is still current
+        // at its end timestamp, i.e. that no other transaction committed a change after T began
+        // to a row in T's read set, or to a row in one of the ranges of T's scan set. The
+        // latter covers phantoms: rows that became visible (or invisible) in the middle of T,
+        // and that one of T's scans would otherwise have missed.
+        //
+        // The validation is pessimistic about concurrent commits: a change by a transaction
+        // that is still Preparing counts as a conflict, so that of two transactions validating
+        // at once, at least one of them aborts. This spares us the commit dependencies of the
+        // Hekaton paper (https://www.cs.cmu.edu/~15721-f24/papers/Hekaton.pdf , 2.7), which
+        // are only needed for speculative reads and ignores, and we do neither: versions of
+        // Preparing transactions are never visible (see is_begin_visible()).
+        //
+        // The validation runs without the lock on T, because other transactions take the
+        // locks on row versions before the locks on transactions.
+        if tx.isolation == IsolationLevel::Serializable {
+            let begin_ts = tx.begin_ts;
+            let read_set: Vec<RowID> = tx.read_set.iter().map(|v| *v.value()).collect();
+            let scan_set: Vec<(RowID, RowID)> = tx.scan_set.iter().map(|v| *v.value()).collect();
+            drop(tx);
+            if !self.is_serializable(tx_id, begin_ts, &read_set, &scan_set) {
+                tracing::trace!("serialization_failure(tx_id={})", tx_id);
+                self.rollback_tx(tx_id);
+                return Err(DatabaseError::SerializationFailure);
+            }
+        } else {
+            drop(tx);
+        }
+        let tx = tx_unlocked.value().write().unwrap();
         tx.state.store(TransactionState::Committed(end_ts));
         tracing::trace!("commit_tx(tx_id={})", tx_id);
         let write_set: Vec<RowID> = tx.write_set.iter().map(|v| *v.value()).collect();
+        let index_write_set: Vec<IndexKey> = tx
+            .index_write_set
+            .iter()
+            .map(|v| v.value().clone())
+            .collect();
         drop(tx);
         // Postprocessing: inserting row versions and logging the transaction to persistent storage.
         // TODO: we should probably save to persistent storage first, and only then update the in-memory structures.
@@ -600,30 +923,23 @@ impl<Clock: LogicalClock> MvStore<Clock> {
         for ref id in write_set {
             if let Some(row_versions) = self.rows.get(id) {
                 let mut row_versions = row_versions.value().write().unwrap();
-                for row_version in row_versions.iter_mut() {
-                    if let TxTimestampOrID::TxID(id) = row_version.begin {
-                        if id == tx_id {
-                            // New version is valid STARTING FROM committing transaction's end timestamp
-                            // See diagram on page 299: https://www.cs.cmu.edu/~15721-f24/papers/Hekaton.pdf
-                            row_version.begin = TxTimestampOrID::Timestamp(end_ts);
-                            self.insert_version_raw(
-                                &mut log_record.row_versions,
-                                row_version.clone(),
-                            ); // FIXME: optimize cloning out
-                        }
-                    }
-                    if let Some(TxTimestampOrID::TxID(id)) = row_version.end {
-                        if id == tx_id {
-                            // Old version is valid UNTIL committing transaction's end timestamp
-                            // See diagram on page 299: https://www.cs.cmu.edu/~15721-f24/papers/Hekaton.pdf
-                            row_version.end = Some(TxTimestampOrID::Timestamp(end_ts));
-                            self.insert_version_raw(
-                                &mut log_record.row_versions,
-                                row_version.clone(),
-                            ); // FIXME: optimize cloning out
-                        }
-                    }
-                }
+                self.commit_versions(
+                    tx_id,
+                    end_ts,
+                    &mut row_versions,
+                    &mut log_record.row_versions,
+                );
+            }
+        }
+        for ref key in index_write_set {
+            if let Some(row_versions) = self.index_rows.get(key) {
+                let mut row_versions = row_versions.value().write().unwrap();
+                self.commit_versions(
+                    tx_id,
+                    end_ts,
+                    &mut row_versions,
+                    &mut log_record.index_versions,
+                );
             }
         }
         tracing::trace!("updated(tx_id={})", tx_id);
@@ -636,7 +952,7 @@ impl<Clock: LogicalClock> MvStore<Clock> {
         // FIXME: it actually just become a problem for today!!!
         // TODO: test that reproduces this failure, and then a fix
         self.txs.remove(&tx_id);
-        if !log_record.row_versions.is_empty() {
+        if !log_record.row_versions.is_empty() || !log_record.index_versions.is_empty() {
             self.storage.log_tx(log_record)?;
         }
         tracing::trace!("logged(tx_id={})", tx_id);
@@ -652,12 +968,26 @@ impl<Clock: LogicalClock> MvStore<Clock> {
     ///
     /// * `tx_id` - The ID of the transaction to abort.
     pub fn rollback_tx(&self, tx_id: TxID) {
-        let tx_unlocked = self.txs.get(&tx_id).unwrap();
+        // A transaction that failed with a write-write conflict or a serialization failure is
+        // rolled back already.
+        let Some(tx_unlocked) = self.txs.get(&tx_id) else {
+            tracing::trace!("abort(tx_id={}) -> already terminated", tx_id);
+            return;
+        };
         let tx = tx_unlocked.value().write().unwrap();
-        assert_eq!(tx.state, TransactionState::Active);
+        // A transaction that fails validation is rolled back while it is preparing to commit.
+        assert!(matches!(
+            tx.state.load(),
+            TransactionState::Active | TransactionState::Preparing
+        ));
         tx.state.store(TransactionState::Aborted);
         tracing::trace!("abort(tx_id={})", tx_id);
         let write_set: Vec<RowID> = tx.write_set.iter().map(|v| *v.value()).collect();
+        let index_write_set: Vec<IndexKey> = tx
+            .index_write_set
+            .iter()
+            .map(|v| v.value().clone())
+            .collect();
         drop(tx);
 
         for ref id in write_set {
@@ -669,6 +999,15 @@ impl<Clock: LogicalClock> MvStore<Clock> {
                 }
             }
         }
+        for ref key in index_write_set {
+            if let Some(row_versions) = self.index_rows.get(key) {
+                let mut row_versions = row_versions.value().write().unwrap();
+                row_versions.retain(|rv| rv.begin != TxTimestampOrID::TxID(tx_id));
+                if row_versions.is_empty() {
+                    self.index_rows.remove(key);
+                }
+            }
+        }
 
         let tx = tx_unlocked.value().read().unwrap();
         tx.state.store(TransactionState::Terminated);
@@ -746,18 +1085,189 @@ impl<Clock: LogicalClock> MvStore<Clock> {
         dropped
     }
 
+    /// Replays the transactions of the logical log, so that the store has the row versions they
+    /// committed.
+    ///
+    /// Rows loaded with [MvStore::load_row] are in their state at the last checkpoint, and are
+    /// superseded by the versions that later transactions committed.
     pub fn recover(&self) -> Result<()> {
         let tx_log = self.storage.read_tx_log()?;
         for record in tx_log {
             tracing::debug!("recover() -> tx_timestamp={}", record.tx_timestamp);
             for version in record.row_versions {
-                self.insert_version(version.row.id, version);
+                self.recover_version(version);
             }
             self.clock.reset(record.tx_timestamp);
         }
         Ok(())
     }
 
+    /// Loads a row of the b-tree file into the store, as a version committed before any
+    /// transaction of the store began.
+    pub fn load_row(&self, row: Row) {
+        let id = row.id;
+        self.advance_next_rowid(id.row_id);
+        let row_version = RowVersion {
+            begin: TxTimestampOrID::Timestamp(0),
+            end: None,
+            row,
+        };
+        self.insert_version(id, row_version);
+    }
+
+    /// Replays the index entries of the transactions of the logical log, once the indexes they
+    /// belong to are known from the schema that [MvStore::recover] recovered. `indexes` maps the
+    /// ID of each index to its key structure. The entries of indexes that no longer exist are
+    /// skipped.
+    pub fn recover_indexes(&self, indexes: &HashMap<u64, Arc<IndexInfo>>) -> Result<()> {
+        for record in self.storage.read_tx_log()? {
+            for version in record.index_versions {
+                let Some(info) = indexes.get(&version.row.id.table_id) else {
+                    continue;
+                };
+                let mut index_record = ImmutableRecord::new(version.row.data.len());
+                index_record.start_serialization(&version.row.data);
+                let key = IndexKey::new(version.row.id.table_id, index_record, info.clone());
+                let versions = self
+                    .index_rows
+                    .get_or_insert_with(key, || RwLock::new(Vec::new()));
+                let mut versions = versions.value().write().unwrap();
+                self.recover_version_raw(&mut versions, version);
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads an entry of an index b-tree into the store, like [MvStore::load_row].
+    pub fn load_index_entry(&self, key: IndexKey) {
+        let row_version = RowVersion {
+            begin: TxTimestampOrID::Timestamp(0),
+            end: None,
+            row: Row::new(
+                RowID::new(key.index_id, key.row_id()),
+                key.record.get_payload().to_vec(),
+            ),
+        };
+        let versions = self
+            .index_rows
+            .get_or_insert_with(key, || RwLock::new(Vec::new()));
+        let mut versions = versions.value().write().unwrap();
+        self.insert_version_raw(&mut versions, row_version);
+    }
+
+    /// Returns the rows written by the transactions of the logical log, each in the state the last
+    /// of them left it: `Some` with the data of the row, or `None` if the row was deleted.
+    pub fn logged_rows(&self) -> Result<BTreeMap<RowID, Option<Vec<u8>>>> {
+        let mut rows = BTreeMap::new();
+        for record in self.storage.read_tx_log()? {
+            let mut deleted = Vec::new();
+            let mut written = HashSet::new();
+            for version in record.row_versions {
+                let id = version.row.id;
+                if version.end.is_none() {
+                    written.insert(id);
+                    rows.insert(id, Some(version.row.data));
+                } else {
+                    deleted.push(id);
+                }
+            }
+            for id in deleted {
+                if !written.contains(&id) {
+                    rows.insert(id, None);
+                }
+            }
+        }
+        Ok(rows)
+    }
+
+    /// Returns the index entries written by the transactions of the logical log, keyed by index ID
+    /// and record, each with whether the last of them left it in the index.
+    pub fn logged_index_entries(&self) -> Result<BTreeMap<(u64, Vec<u8>), bool>> {
+        let mut entries = BTreeMap::new();
+        for record in self.storage.read_tx_log()? {
+            let mut deleted = Vec::new();
+            let mut written = HashSet::new();
+            for version in record.index_versions {
+                let key = (version.row.id.table_id, version.row.data);
+                if version.end.is_none() {
+                    written.insert(key.clone());
+                    entries.insert(key, true);
+                } else {
+                    deleted.push(key);
+                }
+            }
+            for key in deleted {
+                if !written.contains(&key) {
+                    entries.insert(key, false);
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Removes the transactions from the logical log, once their rows are checkpointed.
+    pub fn truncate_log(&self) -> Result<()> {
+        self.storage.truncate_tx_log()
+    }
+
+    /// Whether the logical log has grown enough to be checkpointed.
+    pub fn needs_checkpoint(&self) -> bool {
+        self.storage.tx_log_size() >= DEFAULT_CHECKPOINT_THRESHOLD
+    }
+
+    /// Whether committed transactions are logged, and survive the store.
+    pub fn is_durable(&self) -> bool {
+        self.storage.is_durable()
+    }
+
+    /// Validates the reads and scans of the serializable transaction `tx_id`, which began at
+    /// `begin_ts`: no other transaction may have committed, or be committing, a change to the
+    /// rows it read or to the rows in the ranges it scanned.
+    fn is_serializable(
+        &self,
+        tx_id: TxID,
+        begin_ts: u64,
+        read_set: &[RowID],
+        scan_set: &[(RowID, RowID)],
+    ) -> bool {
+        let is_changed = |row_versions: &RwLock<Vec<RowVersion>>| {
+            let row_versions = row_versions.read().unwrap();
+            row_versions.iter().any(|rv| {
+                is_concurrent_change(&self.txs, tx_id, begin_ts, &rv.begin)
+                    || rv
+                        .end
+                        .as_ref()
+                        .is_some_and(|end| is_concurrent_change(&self.txs, tx_id, begin_ts, end))
+            })
+        };
+        for id in read_set {
+            if let Some(row_versions) = self.rows.get(id) {
+                if is_changed(row_versions.value()) {
+                    tracing::trace!("read of {:?} by tx_id={} is stale", id, tx_id);
+                    return false;
+                }
+            }
+        }
+        for (start, end) in scan_set {
+            for entry in self.rows.range(*start..*end) {
+                if is_changed(entry.value()) {
+                    tracing::trace!("scan by tx_id={} has phantom {:?}", tx_id, entry.key());
+                    return false;
+                }
+            }
+            // Scans of an index cover all of its entries.
+            let index_start = IndexKey::index_bound(start.table_id, std::cmp::Ordering::Less);
+            let index_end = IndexKey::index_bound(start.table_id, std::cmp::Ordering::Greater);
+            for entry in self.index_rows.range(index_start..index_end) {
+                if is_changed(entry.value()) {
+                    tracing::trace!("scan by tx_id={} has phantom {:?}", tx_id, entry.key());
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
     // Extracts the begin timestamp from a transaction
     fn get_begin_timestamp(&self, ts_or_id: &TxTimestampOrID) -> u64 {
         match ts_or_id {
@@ -774,6 +1284,69 @@ impl<Clock: LogicalClock> MvStore<Clock> {
         }
     }
 
+    /// Inserts a row version read from the logical log. The log has the version already if a
+    /// version begins at the same timestamp, which the version updates the end of. Otherwise the
+    /// version supersedes the versions of the row that began earlier and have not ended.
+    fn recover_version(&self, row_version: RowVersion) {
+        self.advance_next_rowid(row_version.row.id.row_id);
+        let versions = self
+            .rows
+            .get_or_insert_with(row_version.row.id, || RwLock::new(Vec::new()));
+        let mut versions = versions.value().write().unwrap();
+        self.recover_version_raw(&mut versions, row_version);
+    }
+
+    /// Inserts a version read from the logical log into `versions`, the versions of the same row
+    /// or index entry.
+    fn recover_version_raw(&self, versions: &mut Vec<RowVersion>, row_version: RowVersion) {
+        for version in versions.iter_mut() {
+            if version.end.is_none() && version.begin < row_version.begin {
+                version.end = Some(row_version.begin.clone());
+            }
+        }
+        match versions.iter_mut().find(|v| v.begin == row_version.begin) {
+            Some(version) => version.end = row_version.end,
+            None => self.insert_version_raw(versions, row_version),
+        }
+    }
+
+    /// Switches the versions that the transaction `tx_id` inserted or deleted from its ID to its
+    /// end timestamp `end_ts`, and adds them to the `logged` versions.
+    fn commit_versions(
+        &self,
+        tx_id: TxID,
+        end_ts: u64,
+        row_versions: &mut [RowVersion],
+        logged: &mut Vec<RowVersion>,
+    ) {
+        for row_version in row_versions.iter_mut() {
+            if let TxTimestampOrID::TxID(id) = row_version.begin {
+                if id == tx_id {
+                    // New version is valid STARTING FROM committing transaction's end timestamp
+                    // See diagram on page 299: https://www.cs.cmu.edu/~15721-f24/papers/Hekaton.pdf
+                    row_version.begin = TxTimestampOrID::Timestamp(end_ts);
+                    self.insert_version_raw(logged, row_version.clone()); // FIXME: optimize cloning out
+                }
+            }
+            if let Some(TxTimestampOrID::TxID(id)) = row_version.end {
+                if id == tx_id {
+                    // Old version is valid UNTIL committing transaction's end timestamp
+                    // See diagram on page 299: https://www.cs.cmu.edu/~15721-f24/papers/Hekaton.pdf
+                    row_version.end = Some(TxTimestampOrID::Timestamp(end_ts));
+                    self.insert_version_raw(logged, row_version.clone()); // FIXME: optimize cloning out
+                }
+            }
+        }
+    }
+
+    /// Makes sure that [Self::get_next_rowid] returns row ids greater than `row_id`.
+    fn advance_next_rowid(&self, row_id: i64) {
+        if row_id >= 0 {
+            self.next_rowid
+                .fetch_max(row_id as u64 + 1, Ordering::SeqCst);
+        }
+    }
+
     /// Inserts a new row version into the database, while making sure that
     /// the row version is inserted in the correct order.
     fn insert_version(&self, id: RowID, row_version: RowVersion) {
@@ -843,6 +1416,74 @@ pub(crate) fn is_write_write_conflict(
     }
 }
 
+/// Whether the begin or end `ts_or_id` of a row version was set by a transaction other than
+/// `tx_id` that committed after `begin_ts`, or is preparing to commit.
+fn is_concurrent_change(
+    txs: &SkipMap<TxID, RwLock<Transaction>>,
+    tx_id: TxID,
+    begin_ts: u64,
+    ts_or_id: &TxTimestampOrID,
+) -> bool {
+    match ts_or_id {
+        TxTimestampOrID::Timestamp(ts) => *ts > begin_ts,
+        TxTimestampOrID::TxID(id) if *id == tx_id => false,
+        TxTimestampOrID::TxID(id) => {
+            // A transaction that is gone has either rolled back, or committed and replaced its
+            // ID in the row version with its timestamp, which a reread would see.
+            let Some(other) = txs.get(id) else {
+                return false;
+            };
+            let other = other.value().read().unwrap();
+            match other.state.load() {
+                TransactionState::Preparing => true,
+                TransactionState::Committed(committed_ts) => committed_ts > begin_ts,
+                TransactionState::Active
+                | TransactionState::Aborted
+                | TransactionState::Terminated => false,
+            }
+        }
+    }
+}
+
+/// Whether the index entry version `rv` is invisible to `tx`, but was inserted by another
+/// transaction that committed or may still commit, and was not deleted by a transaction that
+/// committed or by `tx`. A unique entry that `tx` inserts with the same key conflicts with it.
+fn is_hidden_duplicate(
+    txs: &SkipMap<TxID, RwLock<Transaction>>,
+    tx: &Transaction,
+    rv: &RowVersion,
+) -> bool {
+    if rv.is_visible_to(tx, txs) {
+        return false;
+    }
+    let may_commit = |tx_id: &TxID| {
+        txs.get(tx_id).is_some_and(|other| {
+            !matches!(
+                other.value().read().unwrap().state.load(),
+                TransactionState::Aborted | TransactionState::Terminated
+            )
+        })
+    };
+    let inserted = match &rv.begin {
+        TxTimestampOrID::Timestamp(_) => true,
+        TxTimestampOrID::TxID(id) => *id != tx.tx_id && may_commit(id),
+    };
+    let deleted = match &rv.end {
+        None => false,
+        Some(TxTimestampOrID::Timestamp(_)) => true,
+        Some(TxTimestampOrID::TxID(id)) => {
+            *id == tx.tx_id
+                || txs.get(id).is_some_and(|other| {
+                    matches!(
+                        other.value().read().unwrap().state.load(),
+                        TransactionState::Committed(_)
+                    )
+                })
+        }
+    };
+    inserted && !deleted
+}
+
 impl RowVersion {
     pub fn is_visible_to(
         &self,
@@ -519,6 +519,124 @@ fn test_lost_update() {
     assert_eq!(tx2_row, row);
 }
 
+/// Two on-call doctors, each of whom may go off call as long as the other stays on call.
+fn setup_write_skew(db: &MvStore<LocalClock>) {
+    let tx = db.begin_tx();
+    db.insert(tx, row(1, "on call")).unwrap();
+    db.insert(tx, row(2, "on call")).unwrap();
+    db.commit_tx(tx).unwrap();
+}
+
+/// Reads the row of the other doctor and, if they are on call, takes `row_id` off call.
+fn go_off_call(db: &MvStore<LocalClock>, tx: TxID, row_id: i64) {
+    let other = db.read(tx, RowID::new(1, 3 - row_id)).unwrap().unwrap();
+    assert_eq!(other.data, b"on call");
+    assert!(db.update(tx, row(row_id, "off call")).unwrap());
+}
+
+#[test]
+fn test_snapshot_isolation_allows_write_skew() {
+    let db = test_db();
+    setup_write_skew(&db);
+
+    let tx1 = db.begin_tx();
+    let tx2 = db.begin_tx();
+    go_off_call(&db, tx1, 1);
+    go_off_call(&db, tx2, 2);
+    db.commit_tx(tx1).unwrap();
+    db.commit_tx(tx2).unwrap();
+
+    let tx3 = db.begin_tx();
+    assert_eq!(
+        db.read(tx3, RowID::new(1, 1)).unwrap(),
+        Some(row(1, "off call"))
+    );
+    assert_eq!(
+        db.read(tx3, RowID::new(1, 2)).unwrap(),
+        Some(row(2, "off call"))
+    );
+}
+
+#[test]
+fn test_serializable_prevents_write_skew() {
+    let db = test_db();
+    setup_write_skew(&db);
+
+    let tx1 = db.begin_tx_with_isolation(IsolationLevel::Serializable);
+    let tx2 = db.begin_tx_with_isolation(IsolationLevel::Serializable);
+    go_off_call(&db, tx1, 1);
+    go_off_call(&db, tx2, 2);
+    db.commit_tx(tx1).unwrap();
+    assert_eq!(db.commit_tx(tx2), Err(DatabaseError::SerializationFailure));
+
+    // The failed transaction is rolled back, and can be retried.
+    let tx3 = db.begin_tx_with_isolation(IsolationLevel::Serializable);
+    assert_eq!(
+        db.read(tx3, RowID::new(1, 2)).unwrap(),
+        Some(row(2, "on call"))
+    );
+    assert_eq!(
+        db.read(tx3, RowID::new(1, 1)).unwrap(),
+        Some(row(1, "off call"))
+    );
+    db.commit_tx(tx3).unwrap();
+}
+
+#[test]
+fn test_serializable_commits_after_concurrent_unrelated_write() {
+    let db = test_db();
+    setup_write_skew(&db);
+
+    let tx1 = db.begin_tx_with_isolation(IsolationLevel::Serializable);
+    let tx2 = db.begin_tx();
+    go_off_call(&db, tx1, 1);
+    db.insert(tx2, row(3, "on call")).unwrap();
+    db.commit_tx(tx2).unwrap();
+    db.commit_tx(tx1).unwrap();
+}
+
+#[test]
+fn test_serializable_detects_read_of_missing_row() {
+    let db = test_db();
+
+    let tx1 = db.begin_tx_with_isolation(IsolationLevel::Serializable);
+    assert_eq!(db.read(tx1, RowID::new(1, 1)).unwrap(), None);
+    db.insert(tx1, row(2, "Hello")).unwrap();
+
+    let tx2 = db.begin_tx();
+    db.insert(tx2, row(1, "World")).unwrap();
+    db.commit_tx(tx2).unwrap();
+
+    assert_eq!(db.commit_tx(tx1), Err(DatabaseError::SerializationFailure));
+    let tx3 = db.begin_tx();
+    assert_eq!(db.read(tx3, RowID::new(1, 2)).unwrap(), None);
+}
+
+#[test]
+fn test_serializable_detects_phantom() {
+    let db = Rc::new(test_db());
+    setup_write_skew(&db);
+
+    // T1 counts the doctors on call by scanning the table, and goes off call if there are two.
+    let tx1 = db.begin_tx_with_isolation(IsolationLevel::Serializable);
+    let mut cursor = ScanCursor::new(db.clone(), tx1, 1).unwrap();
+    let mut on_call = 0;
+    while cursor.forward() {
+        if let Some(row) = cursor.current_row().unwrap() {
+            on_call += (row.data == b"on call") as usize;
+        }
+    }
+    assert_eq!(on_call, 2);
+
+    // T2 adds a doctor that T1's scan missed.
+    let tx2 = db.begin_tx();
+    db.insert(tx2, row(3, "off call")).unwrap();
+    db.commit_tx(tx2).unwrap();
+
+    assert!(db.update(tx1, row(1, "off call")).unwrap());
+    assert_eq!(db.commit_tx(tx1), Err(DatabaseError::SerializationFailure));
+}
+
 // Test for the visibility to check if a new transaction can see old committed values.
 // This test checks for the typo present in the paper, explained in https://github.com/penberg/mvcc-rs/issues/15
 #[test]
@@ -618,9 +736,10 @@ fn test_future_row() {
 }
 
 use crate::mvcc::clock::LogicalClock;
-use crate::mvcc::cursor::{BucketScanCursor, LazyScanCursor, ScanCursor};
+use crate::mvcc::cursor::{BucketScanCursor, IndexCursor, LazyScanCursor, ScanCursor};
 use crate::mvcc::database::{MvStore, Row, RowID};
 use crate::mvcc::persistent_storage::Storage;
+use crate::types::{KeyInfo, SeekOp};
 use std::rc::Rc;
 use std::sync::atomic::{AtomicU64, Ordering};
 
@@ -846,6 +965,27 @@ fn test_scan_cursor_basic() {
     assert!(cursor.is_empty());
 }
 
+#[test]
+fn test_scan_cursor_skips_invisible_rows() {
+    let (db, tx_id) = setup_sequential_db();
+    let table_id = 1;
+
+    // Rows of a transaction that has not committed are not visible to the scan.
+    let other_tx = db.begin_tx();
+    db.insert(
+        other_tx,
+        Row::new(RowID::new(table_id, 6), b"row6".to_vec()),
+    )
+    .unwrap();
+
+    let mut cursor = ScanCursor::new(db.clone(), tx_id, table_id).unwrap();
+    let mut row_ids = Vec::new();
+    while cursor.forward() {
+        row_ids.push(cursor.current_row().unwrap().unwrap().id.row_id);
+    }
+    assert_eq!(row_ids, vec![1, 2, 3, 4, 5]);
+}
+
 #[test]
 fn test_cursor_with_empty_table() {
     let clock = TestClock::new(1);
@@ -988,8 +1128,11 @@ fn new_tx(tx_id: TxID, begin_ts: u64, state: TransactionState) -> RwLock<Transac
         state,
         tx_id,
         begin_ts,
+        isolation: IsolationLevel::Snapshot,
         write_set: SkipSet::new(),
+        index_write_set: SkipSet::new(),
         read_set: SkipSet::new(),
+        scan_set: SkipSet::new(),
     })
 }
 
@@ -1091,3 +1234,427 @@ fn test_snapshot_isolation_tx_visible1() {
         Some(TxTimestampOrID::TxID(7))
     ));
 }
+
+fn durable_db(path: &std::path::Path) -> MvStore<LocalClock> {
+    let io: std::sync::Arc<dyn crate::IO> = std::sync::Arc::new(crate::PlatformIO::new().unwrap());
+    let storage = Storage::new_logical_log(io, path.to_str().unwrap()).unwrap();
+    MvStore::new(LocalClock::new(), storage)
+}
+
+fn row(row_id: i64, data: &str) -> Row {
+    Row::new(RowID::new(1, row_id), data.as_bytes().to_vec())
+}
+
+#[test]
+fn test_recover_committed_transactions() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let path = dir.path().join("test.db-log");
+    {
+        let db = durable_db(&path);
+        let tx1 = db.begin_tx();
+        db.insert(tx1, row(1, "Hello")).unwrap();
+        db.insert(tx1, row(2, "World")).unwrap();
+        db.commit_tx(tx1).unwrap();
+        let tx2 = db.begin_tx();
+        db.update(tx2, row(1, "Hi")).unwrap();
+        db.delete(tx2, RowID::new(1, 2)).unwrap();
+        db.commit_tx(tx2).unwrap();
+        let tx3 = db.begin_tx();
+        db.insert(tx3, row(3, "Uncommitted")).unwrap();
+        db.rollback_tx(tx3);
+        let tx4 = db.begin_tx();
+        db.insert(tx4, row(4, "Active")).unwrap();
+    }
+
+    let db = durable_db(&path);
+    db.recover().unwrap();
+    let tx = db.begin_tx();
+    assert_eq!(db.read(tx, RowID::new(1, 1)).unwrap(), Some(row(1, "Hi")));
+    assert_eq!(db.read(tx, RowID::new(1, 2)).unwrap(), None);
+    assert_eq!(db.read(tx, RowID::new(1, 3)).unwrap(), None);
+    assert_eq!(db.read(tx, RowID::new(1, 4)).unwrap(), None);
+    assert_eq!(db.get_next_rowid(), 3);
+}
+
+/// A file that fails to write while `fail` is set.
+struct FailingFile {
+    inner: std::sync::Arc<dyn crate::io::File>,
+    fail: std::sync::Arc<std::sync::atomic::AtomicBool>,
+}
+
+impl crate::io::File for FailingFile {
+    fn lock_file(&self, exclusive: bool) -> crate::Result<()> {
+        self.inner.lock_file(exclusive)
+    }
+
+    fn unlock_file(&self) -> crate::Result<()> {
+        self.inner.unlock_file()
+    }
+
+    fn pread(
+        &self,
+        pos: usize,
+        c: std::sync::Arc<crate::io::Completion>,
+    ) -> crate::Result<std::sync::Arc<crate::io::Completion>> {
+        self.inner.pread(pos, c)
+    }
+
+    fn pwrite(
+        &self,
+        pos: usize,
+        buffer: std::sync::Arc<std::cell::RefCell<crate::io::Buffer>>,
+        c: std::sync::Arc<crate::io::Completion>,
+    ) -> crate::Result<std::sync::Arc<crate::io::Completion>> {
+        if self.fail.load(Ordering::SeqCst) {
+            return Err(crate::LimboError::InternalError(
+                "injected write failure".into(),
+            ));
+        }
+        self.inner.pwrite(pos, buffer, c)
+    }
+
+    fn sync(
+        &self,
+        c: std::sync::Arc<crate::io::Completion>,
+    ) -> crate::Result<std::sync::Arc<crate::io::Completion>> {
+        self.inner.sync(c)
+    }
+
+    fn size(&self) -> crate::Result<u64> {
+        self.inner.size()
+    }
+
+    fn truncate(
+        &self,
+        len: usize,
+        c: std::sync::Arc<crate::io::Completion>,
+    ) -> crate::Result<std::sync::Arc<crate::io::Completion>> {
+        self.inner.truncate(len, c)
+    }
+}
+
+#[test]
+fn test_log_rejects_commits_after_failed_write() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let path = dir.path().join("test.db-log");
+    {
+        let io: std::sync::Arc<dyn crate::IO> =
+            std::sync::Arc::new(crate::PlatformIO::new().unwrap());
+        let inner = io
+            .open_file(path.to_str().unwrap(), crate::io::OpenFlags::Create, false)
+            .unwrap();
+        let fail = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let file = std::sync::Arc::new(FailingFile {
+            inner,
+            fail: fail.clone(),
+        });
+        let log = crate::mvcc::persistent_storage::LogicalLog::from_file(io, file).unwrap();
+        let db = MvStore::new(LocalClock::new(), Storage::LogicalLog(log));
+        let tx1 = db.begin_tx();
+        db.insert(tx1, row(1, "Hello")).unwrap();
+        db.commit_tx(tx1).unwrap();
+
+        fail.store(true, Ordering::SeqCst);
+        let tx2 = db.begin_tx();
+        db.insert(tx2, row(2, "Lost")).unwrap();
+        assert!(matches!(db.commit_tx(tx2), Err(DatabaseError::Io(_))));
+
+        // The log doesn't know how much of the failed frame is in the file, so it accepts no
+        // more records, even once writes succeed again.
+        fail.store(false, Ordering::SeqCst);
+        let tx3 = db.begin_tx();
+        db.insert(tx3, row(3, "Rejected")).unwrap();
+        assert!(matches!(db.commit_tx(tx3), Err(DatabaseError::Io(_))));
+    }
+
+    let db = durable_db(&path);
+    db.recover().unwrap();
+    let tx = db.begin_tx();
+    assert_eq!(
+        db.read(tx, RowID::new(1, 1)).unwrap(),
+        Some(row(1, "Hello"))
+    );
+    assert_eq!(db.read(tx, RowID::new(1, 2)).unwrap(), None);
+    assert_eq!(db.read(tx, RowID::new(1, 3)).unwrap(), None);
+}
+
+#[test]
+fn test_recover_ignores_torn_log_tail() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let path = dir.path().join("test.db-log");
+    {
+        let db = durable_db(&path);
+        let tx = db.begin_tx();
+        db.insert(tx, row(1, "Hello")).unwrap();
+        db.commit_tx(tx).unwrap();
+    }
+    let size = std::fs::metadata(&path).unwrap().len();
+    // A frame header claiming a payload that was never written.
+    let mut contents = std::fs::read(&path).unwrap();
+    contents.extend_from_slice(&[64, 0, 0, 0, 1, 2, 3]);
+    std::fs::write(&path, contents).unwrap();
+
+    {
+        let db = durable_db(&path);
+        db.recover().unwrap();
+        let tx = db.begin_tx();
+        assert_eq!(
+            db.read(tx, RowID::new(1, 1)).unwrap(),
+            Some(row(1, "Hello"))
+        );
+        db.commit_tx(tx).unwrap();
+    }
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), size);
+}
+
+#[test]
+fn test_logged_rows_and_truncate_log() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let path = dir.path().join("test.db-log");
+    {
+        let db = durable_db(&path);
+        db.load_row(row(1, "Loaded"));
+        db.load_row(row(2, "Loaded"));
+        let tx = db.begin_tx();
+        db.update(tx, row(1, "Updated")).unwrap();
+        db.delete(tx, RowID::new(1, 2)).unwrap();
+        db.insert(tx, row(3, "Inserted")).unwrap();
+        db.commit_tx(tx).unwrap();
+
+        let rows = db.logged_rows().unwrap();
+        assert_eq!(
+            rows.into_iter().collect::<Vec<_>>(),
+            vec![
+                (RowID::new(1, 1), Some(b"Updated".to_vec())),
+                (RowID::new(1, 2), None),
+                (RowID::new(1, 3), Some(b"Inserted".to_vec())),
+            ]
+        );
+        db.truncate_log().unwrap();
+        assert!(db.logged_rows().unwrap().is_empty());
+    }
+
+    let db = durable_db(&path);
+    db.recover().unwrap();
+    assert!(db.scan_row_ids_for_table(1).unwrap().is_empty());
+}
+
+#[test]
+fn test_recover_supersedes_loaded_rows() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let path = dir.path().join("test.db-log");
+    {
+        let db = durable_db(&path);
+        db.load_row(row(1, "Old"));
+        db.load_row(row(2, "Old"));
+        let tx = db.begin_tx();
+        db.update(tx, row(1, "New")).unwrap();
+        db.delete(tx, RowID::new(1, 2)).unwrap();
+        db.commit_tx(tx).unwrap();
+    }
+
+    // The b-tree file still has the rows as they were before the logged transaction.
+    let db = durable_db(&path);
+    db.load_row(row(1, "Old"));
+    db.load_row(row(2, "Old"));
+    db.recover().unwrap();
+    let tx = db.begin_tx();
+    assert_eq!(db.read(tx, RowID::new(1, 1)).unwrap(), Some(row(1, "New")));
+    assert_eq!(db.read(tx, RowID::new(1, 2)).unwrap(), None);
+}
+
+/// An index on one integer column of table 1, with index ID 2.
+fn index_info() -> Arc<IndexInfo> {
+    let key_info = KeyInfo {
+        sort_order: turso_sqlite3_parser::ast::SortOrder::Asc,
+        collation: crate::translate::collate::CollationSeq::Binary,
+    };
+    Arc::new(IndexInfo {
+        key_info: vec![key_info, key_info],
+        has_rowid: true,
+        num_cols: 2,
+    })
+}
+
+fn index_record(values: &[Value]) -> ImmutableRecord {
+    ImmutableRecord::from_values(values, values.len())
+}
+
+fn index_entry(value: i64, row_id: i64) -> ImmutableRecord {
+    index_record(&[Value::Integer(value), Value::Integer(row_id)])
+}
+
+fn index_cursor(db: &Rc<MvStore<LocalClock>>, tx_id: TxID) -> IndexCursor<LocalClock> {
+    IndexCursor::new(db.clone(), tx_id, 2, index_info(), Some(1)).unwrap()
+}
+
+fn current_entry(cursor: &IndexCursor<LocalClock>) -> Option<Vec<u8>> {
+    cursor
+        .current_record()
+        .map(|record| record.get_payload().to_vec())
+}
+
+#[test]
+fn test_index_cursor_seek() {
+    let db = Rc::new(test_db());
+    let tx1 = db.begin_tx();
+    let cursor = IndexCursor::new(db.clone(), tx1, 2, index_info(), None).unwrap();
+    for (value, row_id) in [(30, 4), (20, 3), (10, 1), (20, 2)] {
+        cursor.insert(index_entry(value, row_id)).unwrap();
+    }
+    db.commit_tx(tx1).unwrap();
+
+    let tx2 = db.begin_tx();
+    let mut cursor = index_cursor(&db, tx2);
+    let key = index_record(&[Value::Integer(20)]);
+    assert!(cursor.seek(&key, SeekOp::GE { eq_only: true }).unwrap());
+    assert_eq!(
+        current_entry(&cursor),
+        Some(index_entry(20, 2).get_payload().to_vec())
+    );
+    assert!(cursor.forward().unwrap());
+    assert_eq!(
+        current_entry(&cursor),
+        Some(index_entry(20, 3).get_payload().to_vec())
+    );
+    assert!(cursor.forward().unwrap());
+    assert_eq!(
+        current_entry(&cursor),
+        Some(index_entry(30, 4).get_payload().to_vec())
+    );
+    assert!(!cursor.forward().unwrap());
+
+    assert!(cursor.seek(&key, SeekOp::GT).unwrap());
+    assert_eq!(
+        current_entry(&cursor),
+        Some(index_entry(30, 4).get_payload().to_vec())
+    );
+    assert!(cursor.seek(&key, SeekOp::LE { eq_only: false }).unwrap());
+    assert_eq!(
+        current_entry(&cursor),
+        Some(index_entry(20, 3).get_payload().to_vec())
+    );
+    assert!(cursor.seek(&key, SeekOp::LT).unwrap());
+    assert_eq!(
+        current_entry(&cursor),
+        Some(index_entry(10, 1).get_payload().to_vec())
+    );
+    assert!(!cursor.backward().unwrap());
+
+    let missing = index_record(&[Value::Integer(25)]);
+    assert!(!cursor.seek(&missing, SeekOp::GE { eq_only: true }).unwrap());
+    assert!(!cursor.seek(&missing, SeekOp::LE { eq_only: true }).unwrap());
+}
+
+#[test]
+fn test_index_entries_visibility() {
+    let db = Rc::new(test_db());
+
+    // T1 inserts an entry, which T2 doesn't see until T1 commits.
+    let tx1 = db.begin_tx();
+    index_cursor(&db, tx1).insert(index_entry(10, 1)).unwrap();
+    let tx2 = db.begin_tx();
+    assert!(!index_cursor(&db, tx2).rewind().unwrap());
+    db.commit_tx(tx1).unwrap();
+    let tx3 = db.begin_tx();
+    let mut cursor = index_cursor(&db, tx3);
+    assert!(cursor.rewind().unwrap());
+
+    // T3 deletes the entry, which T4 still sees until T3 commits.
+    assert!(cursor.delete().unwrap());
+    assert!(!cursor.rewind().unwrap());
+    let tx4 = db.begin_tx();
+    assert!(index_cursor(&db, tx4).rewind().unwrap());
+    db.commit_tx(tx3).unwrap();
+    let tx5 = db.begin_tx();
+    assert!(!index_cursor(&db, tx5).rewind().unwrap());
+}
+
+#[test]
+fn test_index_rollback_removes_entries() {
+    let db = Rc::new(test_db());
+    let tx1 = db.begin_tx();
+    index_cursor(&db, tx1).insert(index_entry(10, 1)).unwrap();
+    db.rollback_tx(tx1);
+    let tx2 = db.begin_tx();
+    assert!(!index_cursor(&db, tx2).rewind().unwrap());
+    assert!(db.index_rows.is_empty());
+}
+
+#[test]
+fn test_unique_index_conflicts_with_concurrent_insert() {
+    let db = Rc::new(test_db());
+
+    // T2 cannot see the entry of T1, but inserting the same key conflicts with it.
+    let tx1 = db.begin_tx();
+    index_cursor(&db, tx1).insert(index_entry(10, 1)).unwrap();
+    let tx2 = db.begin_tx();
+    assert_eq!(
+        index_cursor(&db, tx2).insert(index_entry(10, 2)),
+        Err(DatabaseError::WriteWriteConflict)
+    );
+    // A different key, or NULL, which is never equal to another NULL, doesn't conflict.
+    let tx3 = db.begin_tx();
+    let cursor = index_cursor(&db, tx3);
+    cursor.insert(index_entry(20, 3)).unwrap();
+    cursor
+        .insert(index_record(&[Value::Null, Value::Integer(4)]))
+        .unwrap();
+    let tx4 = db.begin_tx();
+    index_cursor(&db, tx4)
+        .insert(index_record(&[Value::Null, Value::Integer(5)]))
+        .unwrap();
+    db.commit_tx(tx3).unwrap();
+    db.commit_tx(tx4).unwrap();
+
+    // Once T1 rolls back, its key is free again.
+    db.rollback_tx(tx1);
+    let tx5 = db.begin_tx();
+    index_cursor(&db, tx5).insert(index_entry(10, 6)).unwrap();
+    db.commit_tx(tx5).unwrap();
+}
+
+#[test]
+fn test_recover_index_entries() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let path = dir.path().join("test.db-log");
+    {
+        let db = Rc::new(durable_db(&path));
+        db.load_index_entry(IndexKey::new(2, index_entry(10, 1), index_info()));
+        let tx1 = db.begin_tx();
+        let mut cursor = IndexCursor::new(db.clone(), tx1, 2, index_info(), None).unwrap();
+        cursor.insert(index_entry(20, 2)).unwrap();
+        assert!(cursor
+            .seek(
+                &index_record(&[Value::Integer(10)]),
+                SeekOp::GE { eq_only: true }
+            )
+            .unwrap());
+        assert!(cursor.delete().unwrap());
+        db.commit_tx(tx1).unwrap();
+
+        let entries = db.logged_index_entries().unwrap();
+        assert_eq!(
+            entries.into_iter().collect::<Vec<_>>(),
+            vec![
+                ((2, index_entry(10, 1).get_payload().to_vec()), false),
+                ((2, index_entry(20, 2).get_payload().to_vec()), true),
+            ]
+        );
+    }
+
+    // The b-tree file still has the entry the logged transaction deleted.
+    let db = Rc::new(durable_db(&path));
+    db.load_index_entry(IndexKey::new(2, index_entry(10, 1), index_info()));
+    db.recover().unwrap();
+    db.recover_indexes(&HashMap::from([(2, index_info())]))
+        .unwrap();
+    let tx = db.begin_tx();
+    let mut cursor = index_cursor(&db, tx);
+    assert!(cursor.rewind().unwrap());
+    assert_eq!(
+        current_entry(&cursor),
+        Some(index_entry(20, 2).get_payload().to_vec())
+    );
+    assert!(!cursor.forward().unwrap());
+}
@@ -6,8 +6,20 @@ pub enum DatabaseError {
     NoSuchTransactionID(u64),
     #[error("transaction aborted because of a write-write conflict")]
     WriteWriteConflict,
+    #[error("transaction aborted because of a serialization failure")]
+    SerializationFailure,
     #[error("transaction is terminated")]
     TxTerminated,
     #[error("I/O error: {0}")]
     Io(String),
 }
+
+impl From<DatabaseError> for crate::LimboError {
+    fn from(err: DatabaseError) -> Self {
+        match err {
+            // Like SQLite's SQLITE_BUSY_SNAPSHOT, the transaction can be retried.
+            DatabaseError::SerializationFailure => crate::LimboError::Busy,
+            err => crate::LimboError::TxError(err.to_string()),
+        }
+    }
+}
@@ -24,13 +24,42 @@
 //!   prevents lost updates by detecting the write-write conflict and letting the
 //!   first-writer win by aborting the later transaction.
 //!
-//! TODO: phantom reads, cursor lost updates, read skew, write skew.
+//! * A *write skew* occurs when transactions T_m and T_n read an overlapping set of
+//!   values, and each updates values the other read, so that the result matches no
+//!   serial order of T_m and T_n. Snapshot isolation, the default, allows it. With
+//!   [IsolationLevel::Serializable](database::IsolationLevel::Serializable), a transaction
+//!   validates when it commits that no other transaction committed a change to a row it
+//!   read, or to the rows of a table it scanned (which also prevents *phantom reads*), and
+//!   otherwise fails to commit with a retryable error. The isolation level is chosen per
+//!   transaction with [MvStore::begin_tx_with_isolation], or per connection with
+//!   `PRAGMA isolation_level`.
+//!
+//! TODO: cursor lost updates, read skew.
+//!
+//! ## Indexes
+//!
+//! The entries of secondary indexes are versioned like rows, keyed by index record, and follow
+//! the same visibility rules. Since a transaction cannot see the entries inserted by concurrent
+//! transactions, inserting into a UNIQUE index also checks the entries it cannot see: if another
+//! transaction inserted the same key and has not rolled back, the insert fails with a
+//! write-write conflict.
+//!
+//! ## Durability
+//!
+//! Committed transactions are appended to a logical log next to the database file
+//! (`<path>-log`), and the log is synced before the commit returns. Transactions that commit
+//! concurrently share a sync. When the database is opened, its tables and indexes are loaded
+//! from the b-tree file and the log is replayed over them. A checkpoint, run by
+//! `PRAGMA wal_checkpoint` or once the log grows past
+//! [DEFAULT_CHECKPOINT_THRESHOLD](persistent_storage::DEFAULT_CHECKPOINT_THRESHOLD), writes
+//! the logged rows and index entries to the b-tree file and truncates the log.
 //!
 //! ## TODO
 //!
 //! * Optimistic reads and writes
 //! * Garbage collection
 
+pub(crate) mod checkpoint;
 pub mod clock;
 pub mod cursor;
 pub mod database;
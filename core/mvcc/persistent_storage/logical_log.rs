@@ -0,0 +1,390 @@
+//! A file-backed logical log of the transactions committed to an [MvStore](crate::mvcc::MvStore).
+//!
+//! The log starts with an 8-byte header, [LOG_MAGIC] followed by [LOG_VERSION], and continues with
+//! one frame per committed transaction:
+//!
+//! | size | content                                    |
+//! |------|--------------------------------------------|
+//! | 4    | length `n` of the payload in bytes         |
+//! | 8    | checksum of the payload                    |
+//! | n    | payload: the [LogRecord] of the transaction |
+//!
+//! The payload is the commit timestamp of the transaction and the number of its row versions,
+//! followed by the table ID, row ID, begin timestamp, end timestamp, data length and data of each
+//! row version. The end timestamp is preceded by a byte that is 1 if the version has ended and 0
+//! otherwise. The number of versions of index entries and the versions follow in the same format,
+//! with the index ID as the table ID and the index record as the data. All integers are
+//! little-endian.
+//!
+//! Committing transactions append their frames to a buffer, and whichever of them gets to write
+//! first writes and syncs the frames of all of them at once (group commit). A transaction is durable
+//! once the frame of its log record is synced.
+//!
+//! If writing or syncing frames fails, the log stops accepting records: the frames after the
+//! failed ones would be written at offsets that depend on how much of them reached the file.
+//!
+//! A frame that is incomplete or does not match its checksum was being written when the process
+//! stopped. Its transaction never committed, so reading the log stops there and truncates the log.
+
+use crate::io::{Buffer, Completion, File, OpenFlags, IO};
+use crate::mvcc::database::{LogRecord, Result, Row, RowID, RowVersion, TxTimestampOrID};
+use crate::mvcc::errors::DatabaseError;
+use std::cell::{Cell, RefCell};
+use std::fmt::Debug;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// The magic number at the start of a logical log.
+pub const LOG_MAGIC: u32 = 0x4c56_4d54; // "TMVL"
+/// The version of the logical log format.
+pub const LOG_VERSION: u32 = 2;
+
+const LOG_HEADER_SIZE: u64 = 8;
+const FRAME_HEADER_SIZE: usize = 12;
+
+pub struct LogicalLog {
+    io: Arc<dyn IO>,
+    file: Arc<dyn File>,
+    /// Frames appended to the log that are not written to the file yet.
+    pending: Mutex<PendingFrames>,
+    /// Held while pending frames are written and synced.
+    flush_lock: Mutex<()>,
+    /// The offset in the file up to which the log is synced.
+    durable_end: AtomicU64,
+    /// Whether writing or syncing frames failed, after which no record can be appended.
+    failed: AtomicBool,
+}
+
+struct PendingFrames {
+    frames: Vec<u8>,
+    /// The offset in the file at which the pending frames end.
+    end: u64,
+}
+
+impl Debug for LogicalLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LogicalLog")
+            .field("durable_end", &self.durable_end.load(Ordering::Acquire))
+            .finish()
+    }
+}
+
+impl LogicalLog {
+    /// Opens the logical log at `path`, creating it if it does not exist.
+    pub fn open(io: Arc<dyn IO>, path: &str) -> Result<Self> {
+        let file = io
+            .open_file(path, OpenFlags::Create, false)
+            .map_err(io_error)?;
+        Self::from_file(io, file)
+    }
+
+    /// Uses `file` as the logical log, writing the log header to it if it is empty.
+    pub fn from_file(io: Arc<dyn IO>, file: Arc<dyn File>) -> Result<Self> {
+        let log = Self {
+            io,
+            file,
+            pending: Mutex::new(PendingFrames {
+                frames: Vec::new(),
+                end: LOG_HEADER_SIZE,
+            }),
+            flush_lock: Mutex::new(()),
+            durable_end: AtomicU64::new(LOG_HEADER_SIZE),
+            failed: AtomicBool::new(false),
+        };
+        let size = log.file.size().map_err(io_error)?;
+        if size < LOG_HEADER_SIZE {
+            let mut header = Vec::with_capacity(LOG_HEADER_SIZE as usize);
+            header.extend_from_slice(&LOG_MAGIC.to_le_bytes());
+            header.extend_from_slice(&LOG_VERSION.to_le_bytes());
+            log.truncate_file(0)?;
+            log.write_at(0, header)?;
+            log.sync()?;
+        } else {
+            let header = log.read_at(0, LOG_HEADER_SIZE as usize)?;
+            let magic = u32::from_le_bytes(header[0..4].try_into().unwrap());
+            let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+            if magic != LOG_MAGIC || version != LOG_VERSION {
+                return Err(DatabaseError::Io(format!(
+                    "invalid logical log header: magic {magic:#x}, version {version}"
+                )));
+            }
+            log.pending.lock().unwrap().end = size;
+            log.durable_end.store(size, Ordering::Release);
+        }
+        Ok(log)
+    }
+
+    /// Appends `record` to the log and returns once it is synced, possibly together with the
+    /// records of other transactions that committed meanwhile. Fails if the frames of the record,
+    /// or of any record before it, could not be written.
+    pub fn append(&self, record: &LogRecord) -> Result<()> {
+        if self.failed.load(Ordering::Acquire) {
+            return Err(log_failed());
+        }
+        let frame = encode_frame(record);
+        let end = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.frames.extend_from_slice(&frame);
+            pending.end += frame.len() as u64;
+            pending.end
+        };
+        let _flush_lock = self.flush_lock.lock().unwrap();
+        // Our frame may have been in a batch that failed.
+        if self.failed.load(Ordering::Acquire) {
+            return Err(log_failed());
+        }
+        if self.durable_end.load(Ordering::Acquire) >= end {
+            // Another transaction wrote our frame along with its own.
+            return Ok(());
+        }
+        let (frames, start, end) = {
+            let mut pending = self.pending.lock().unwrap();
+            let start = pending.end - pending.frames.len() as u64;
+            (std::mem::take(&mut pending.frames), start, pending.end)
+        };
+        if let Err(err) = self.write_at(start, frames).and_then(|_| self.sync()) {
+            self.failed.store(true, Ordering::Release);
+            return Err(err);
+        }
+        self.durable_end.store(end, Ordering::Release);
+        Ok(())
+    }
+
+    /// Reads the records of the log in commit order. An incomplete or corrupt frame at the end of
+    /// the log, and anything after it, is discarded.
+    pub fn read_all(&self) -> Result<Vec<LogRecord>> {
+        let _flush_lock = self.flush_lock.lock().unwrap();
+        let size = self.file.size().map_err(io_error)?;
+        let log = self.read_at(LOG_HEADER_SIZE, (size - LOG_HEADER_SIZE) as usize)?;
+        let mut records = Vec::new();
+        let mut offset = 0;
+        while let Some((record, frame_len)) = decode_frame(&log[offset..])? {
+            records.push(record);
+            offset += frame_len;
+        }
+        let valid_end = LOG_HEADER_SIZE + offset as u64;
+        if valid_end < size {
+            tracing::debug!(
+                "discarding {} bytes of incomplete frames at the end of the logical log",
+                size - valid_end
+            );
+            self.truncate_file(valid_end)?;
+            self.sync()?;
+        }
+        let mut pending = self.pending.lock().unwrap();
+        assert!(pending.frames.is_empty());
+        pending.end = valid_end;
+        self.durable_end.store(valid_end, Ordering::Release);
+        Ok(records)
+    }
+
+    /// Removes all records from the log.
+    pub fn truncate(&self) -> Result<()> {
+        let _flush_lock = self.flush_lock.lock().unwrap();
+        let mut pending = self.pending.lock().unwrap();
+        self.truncate_file(LOG_HEADER_SIZE)?;
+        self.sync()?;
+        pending.frames.clear();
+        pending.end = LOG_HEADER_SIZE;
+        self.durable_end.store(LOG_HEADER_SIZE, Ordering::Release);
+        Ok(())
+    }
+
+    /// The number of bytes of records in the log.
+    pub fn size(&self) -> u64 {
+        self.durable_end.load(Ordering::Acquire) - LOG_HEADER_SIZE
+    }
+
+    fn write_at(&self, pos: u64, data: Vec<u8>) -> Result<()> {
+        let len = data.len();
+        let drop_fn = Rc::new(|_buf| {});
+        let buffer = Arc::new(RefCell::new(Buffer::new(Pin::new(data), drop_fn)));
+        let written = Rc::new(Cell::new(-1));
+        let c = Completion::new_write({
+            let written = written.clone();
+            move |bytes_written| written.set(bytes_written)
+        });
+        let c = self
+            .file
+            .pwrite(pos as usize, buffer, Arc::new(c))
+            .map_err(io_error)?;
+        self.wait(&c)?;
+        if written.get() != len as i32 {
+            return Err(DatabaseError::Io(format!(
+                "short write to the logical log: {} of {len} bytes",
+                written.get()
+            )));
+        }
+        Ok(())
+    }
+
+    fn read_at(&self, pos: u64, len: usize) -> Result<Vec<u8>> {
+        let drop_fn = Rc::new(|_buf| {});
+        let buffer = Arc::new(RefCell::new(Buffer::allocate(len, drop_fn)));
+        let read = Rc::new(Cell::new(-1));
+        let c = Completion::new_read(buffer.clone(), {
+            let read = read.clone();
+            move |_buf, bytes_read| read.set(bytes_read)
+        });
+        let c = self
+            .file
+            .pread(pos as usize, Arc::new(c))
+            .map_err(io_error)?;
+        self.wait(&c)?;
+        if read.get() != len as i32 {
+            return Err(DatabaseError::Io(format!(
+                "short read from the logical log: {} of {len} bytes",
+                read.get()
+            )));
+        }
+        let data = buffer.borrow().as_slice().to_vec();
+        Ok(data)
+    }
+
+    fn sync(&self) -> Result<()> {
+        let c = Completion::new_sync(|_| {});
+        let c = self.file.sync(Arc::new(c)).map_err(io_error)?;
+        self.wait(&c)
+    }
+
+    fn truncate_file(&self, len: u64) -> Result<()> {
+        let c = Completion::new_trunc(|_| {});
+        let c = self
+            .file
+            .truncate(len as usize, Arc::new(c))
+            .map_err(io_error)?;
+        self.wait(&c)
+    }
+
+    fn wait(&self, c: &Completion) -> Result<()> {
+        while !c.is_completed() {
+            self.io.run_once().map_err(io_error)?;
+        }
+        Ok(())
+    }
+}
+
+fn io_error(err: crate::LimboError) -> DatabaseError {
+    DatabaseError::Io(err.to_string())
+}
+
+fn log_failed() -> DatabaseError {
+    DatabaseError::Io("the logical log failed to write an earlier commit".to_string())
+}
+
+/// FNV-1a hash of the payload of a frame.
+fn checksum(payload: &[u8]) -> u64 {
+    payload.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn encode_frame(record: &LogRecord) -> Vec<u8> {
+    let mut payload = Vec::new();
+    payload.extend_from_slice(&record.tx_timestamp.to_le_bytes());
+    encode_versions(&mut payload, &record.row_versions);
+    encode_versions(&mut payload, &record.index_versions);
+    let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&checksum(&payload).to_le_bytes());
+    frame.extend_from_slice(&payload);
+    frame
+}
+
+fn encode_versions(payload: &mut Vec<u8>, versions: &[RowVersion]) {
+    payload.extend_from_slice(&(versions.len() as u32).to_le_bytes());
+    for version in versions {
+        payload.extend_from_slice(&version.row.id.table_id.to_le_bytes());
+        payload.extend_from_slice(&version.row.id.row_id.to_le_bytes());
+        payload.extend_from_slice(&committed_timestamp(&version.begin).to_le_bytes());
+        match &version.end {
+            Some(end) => {
+                payload.push(1);
+                payload.extend_from_slice(&committed_timestamp(end).to_le_bytes());
+            }
+            None => {
+                payload.push(0);
+                payload.extend_from_slice(&0u64.to_le_bytes());
+            }
+        }
+        payload.extend_from_slice(&(version.row.data.len() as u32).to_le_bytes());
+        payload.extend_from_slice(&version.row.data);
+    }
+}
+
+/// The row versions of a log record are committed, so they are delimited by timestamps.
+fn committed_timestamp(ts_or_id: &TxTimestampOrID) -> u64 {
+    match ts_or_id {
+        TxTimestampOrID::Timestamp(ts) => *ts,
+        TxTimestampOrID::TxID(tx_id) => {
+            unreachable!("row version of transaction {tx_id} is logged before it commits")
+        }
+    }
+}
+
+/// Decodes the frame at the start of `buf`, returning its record and length, or `None` if `buf`
+/// does not start with a complete frame that matches its checksum.
+fn decode_frame(buf: &[u8]) -> Result<Option<(LogRecord, usize)>> {
+    if buf.len() < FRAME_HEADER_SIZE {
+        return Ok(None);
+    }
+    let payload_len = u32::from_le_bytes(buf[0..4].try_into().unwrap()) as usize;
+    let expected_checksum = u64::from_le_bytes(buf[4..12].try_into().unwrap());
+    let Some(payload) = buf.get(FRAME_HEADER_SIZE..FRAME_HEADER_SIZE + payload_len) else {
+        return Ok(None);
+    };
+    if checksum(payload) != expected_checksum {
+        return Ok(None);
+    }
+    let mut reader = PayloadReader { payload };
+    let tx_timestamp = reader.u64()?;
+    let mut record = LogRecord::new(tx_timestamp);
+    record.row_versions = decode_versions(&mut reader)?;
+    record.index_versions = decode_versions(&mut reader)?;
+    Ok(Some((record, FRAME_HEADER_SIZE + payload_len)))
+}
+
+fn decode_versions(reader: &mut PayloadReader) -> Result<Vec<RowVersion>> {
+    let num_versions = reader.u32()?;
+    let mut versions = Vec::with_capacity(num_versions as usize);
+    for _ in 0..num_versions {
+        let table_id = reader.u64()?;
+        let row_id = reader.u64()? as i64;
+        let begin = reader.u64()?;
+        let has_end = reader.bytes(1)?[0] == 1;
+        let end = reader.u64()?;
+        let data_len = reader.u32()? as usize;
+        let data = reader.bytes(data_len)?.to_vec();
+        versions.push(RowVersion {
+            begin: TxTimestampOrID::Timestamp(begin),
+            end: has_end.then_some(TxTimestampOrID::Timestamp(end)),
+            row: Row::new(RowID::new(table_id, row_id), data),
+        });
+    }
+    Ok(versions)
+}
+
+struct PayloadReader<'a> {
+    payload: &'a [u8],
+}
+
+impl<'a> PayloadReader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.payload.len() < len {
+            return Err(DatabaseError::Io("corrupt logical log record".to_string()));
+        }
+        let (bytes, rest) = self.payload.split_at(len);
+        self.payload = rest;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+}
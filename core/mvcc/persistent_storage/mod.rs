@@ -1,23 +1,40 @@
 use std::fmt::Debug;
+use std::sync::Arc;
 
 use crate::mvcc::database::{LogRecord, Result};
 use crate::mvcc::errors::DatabaseError;
+use crate::IO;
+
+mod logical_log;
+
+pub use logical_log::LogicalLog;
+
+/// The size in bytes the logical log may grow to before its records are checkpointed into the
+/// b-tree file.
+pub const DEFAULT_CHECKPOINT_THRESHOLD: u64 = 4 * 1024 * 1024;
 
 #[derive(Debug)]
 pub enum Storage {
     Noop,
+    LogicalLog(LogicalLog),
 }
 
 impl Storage {
     pub fn new_noop() -> Self {
         Self::Noop
     }
+
+    /// Creates a storage that logs transactions to the logical log at `path`.
+    pub fn new_logical_log(io: Arc<dyn IO>, path: &str) -> Result<Self> {
+        Ok(Self::LogicalLog(LogicalLog::open(io, path)?))
+    }
 }
 
 impl Storage {
-    pub fn log_tx(&self, _m: LogRecord) -> Result<()> {
+    pub fn log_tx(&self, m: LogRecord) -> Result<()> {
         match self {
             Self::Noop => (),
+            Self::LogicalLog(log) => log.append(&m)?,
         }
         Ok(())
     }
@@ -27,6 +44,29 @@ impl Storage {
             Self::Noop => Err(DatabaseError::Io(
                 "cannot read from Noop storage".to_string(),
             )),
+            Self::LogicalLog(log) => log.read_all(),
         }
     }
+
+    /// Removes all transactions from the log, once they are checkpointed.
+    pub fn truncate_tx_log(&self) -> Result<()> {
+        match self {
+            Self::Noop => Ok(()),
+            Self::LogicalLog(log) => log.truncate(),
+        }
+    }
+
+    /// The size in bytes of the transactions in the log.
+    pub fn tx_log_size(&self) -> u64 {
+        match self {
+            Self::Noop => 0,
+            Self::LogicalLog(log) => log.size(),
+        }
+    }
+
+    /// Whether the storage keeps a log that [MvStore::recover](crate::mvcc::MvStore::recover) can
+    /// read.
+    pub fn is_durable(&self) -> bool {
+        matches!(self, Self::LogicalLog(_))
+    }
 }
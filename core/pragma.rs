@@ -35,6 +35,10 @@ pub fn pragma_for(pragma: &PragmaName) -> Pragma {
     use PragmaName::*;
 
     match pragma {
+        AnalysisLimit => Pragma::new(
+            PragmaFlags::Result0 | PragmaFlags::NoColumns1,
+            &["analysis_limit"],
+        ),
         ApplicationId => Pragma::new(
             PragmaFlags::NoColumns1 | PragmaFlags::Result0,
             &["application_id"],
@@ -47,10 +51,54 @@ pub fn pragma_for(pragma: &PragmaName) -> Pragma {
             &["cache_size"],
         ),
         DatabaseList => Pragma::new(PragmaFlags::Result0, &["seq", "name", "file"]),
+        DeferForeignKeys => Pragma::new(
+            PragmaFlags::Result0 | PragmaFlags::NoColumns1,
+            &["defer_foreign_keys"],
+        ),
         Encoding => Pragma::new(
             PragmaFlags::Result0 | PragmaFlags::NoColumns1,
             &["encoding"],
         ),
+        ForeignKeyCheck => Pragma::new(
+            PragmaFlags::NeedSchema
+                | PragmaFlags::Result0
+                | PragmaFlags::Result1
+                | PragmaFlags::SchemaOpt,
+            &["table", "rowid", "parent", "fkid"],
+        ),
+        ForeignKeyList => Pragma::new(
+            PragmaFlags::NeedSchema | PragmaFlags::Result1 | PragmaFlags::SchemaOpt,
+            &[
+                "id",
+                "seq",
+                "table",
+                "from",
+                "to",
+                "on_update",
+                "on_delete",
+                "match",
+            ],
+        ),
+        ForeignKeys => Pragma::new(
+            PragmaFlags::Result0 | PragmaFlags::NoColumns1,
+            &["foreign_keys"],
+        ),
+        IgnoreCheckConstraints => Pragma::new(
+            PragmaFlags::Result0 | PragmaFlags::NoColumns1,
+            &["ignore_check_constraints"],
+        ),
+        IndexInfo => Pragma::new(
+            PragmaFlags::NeedSchema | PragmaFlags::Result1 | PragmaFlags::SchemaOpt,
+            &["seqno", "cid", "name"],
+        ),
+        IndexList => Pragma::new(
+            PragmaFlags::NeedSchema | PragmaFlags::Result1 | PragmaFlags::SchemaOpt,
+            &["seq", "name", "unique", "origin", "partial"],
+        ),
+        IndexXinfo => Pragma::new(
+            PragmaFlags::NeedSchema | PragmaFlags::Result1 | PragmaFlags::SchemaOpt,
+            &["seqno", "cid", "name", "desc", "coll", "key"],
+        ),
         JournalMode => Pragma::new(
             PragmaFlags::NeedSchema | PragmaFlags::Result0 | PragmaFlags::SchemaReq,
             &["journal_mode"],
@@ -58,6 +106,10 @@ pub fn pragma_for(pragma: &PragmaName) -> Pragma {
         LegacyFileFormat => {
             unreachable!("pragma_for() called with LegacyFileFormat, which is unsupported")
         }
+        Optimize => Pragma::new(
+            PragmaFlags::Result1 | PragmaFlags::NeedSchema,
+            &["optimize"],
+        ),
         PageCount => Pragma::new(
             PragmaFlags::NeedSchema | PragmaFlags::Result0 | PragmaFlags::SchemaReq,
             &["page_count"],
@@ -74,6 +126,22 @@ pub fn pragma_for(pragma: &PragmaName) -> Pragma {
             PragmaFlags::NeedSchema | PragmaFlags::Result1 | PragmaFlags::SchemaOpt,
             &["cid", "name", "type", "notnull", "dflt_value", "pk"],
         ),
+        TableXinfo => Pragma::new(
+            PragmaFlags::NeedSchema | PragmaFlags::Result1 | PragmaFlags::SchemaOpt,
+            &[
+                "cid",
+                "name",
+                "type",
+                "notnull",
+                "dflt_value",
+                "pk",
+                "hidden",
+            ],
+        ),
+        TempStore => Pragma::new(
+            PragmaFlags::Result0 | PragmaFlags::NoColumns1,
+            &["temp_store"],
+        ),
         UserVersion => Pragma::new(
             PragmaFlags::NoColumns1 | PragmaFlags::Result0,
             &["user_version"],
@@ -87,6 +155,10 @@ pub fn pragma_for(pragma: &PragmaName) -> Pragma {
             PragmaFlags::NeedSchema | PragmaFlags::ReadOnly | PragmaFlags::Result0,
             &["message"],
         ),
+        IsolationLevel => Pragma::new(
+            PragmaFlags::Result0 | PragmaFlags::NoColumns1,
+            &["isolation_level"],
+        ),
         UnstableCaptureDataChangesConn => Pragma::new(
             PragmaFlags::NeedSchema | PragmaFlags::Result0 | PragmaFlags::SchemaReq,
             &["mode", "table"],
@@ -106,6 +178,8 @@ impl PragmaVirtualTable {
     pub(crate) fn functions() -> Vec<(PragmaVirtualTable, String)> {
         PragmaName::iter()
             .filter(|name| *name != PragmaName::LegacyFileFormat)
+            // Its "rowid" column cannot be declared in the schema of a table
+            .filter(|name| *name != PragmaName::ForeignKeyCheck)
             .filter_map(|name| {
                 let pragma = pragma_for(&name);
                 if pragma
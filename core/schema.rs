@@ -1,19 +1,28 @@
+use crate::analyze::{read_btree_rows, TableStats, STAT1_TABLE_NAME, STAT4_TABLE_NAME};
 use crate::result::LimboResult;
 use crate::storage::btree::BTreeCursor;
 use crate::translate::collate::CollationSeq;
-use crate::translate::plan::SelectPlan;
-use crate::util::{module_args_from_sql, module_name_from_sql, IOExt, UnparsedFromSqlIndex};
+use crate::translate::expr::{walk_expr, WalkControl};
+use crate::translate::plan::{CtePlan, SelectPlan};
+use crate::util::{
+    module_args_from_sql, module_name_from_sql, IOExt, UnparsedFromSqlIndex,
+    PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX,
+};
+use crate::vdbe::BranchOffset;
 use crate::{util::normalize_ident, Result};
 use crate::{LimboError, MvCursor, Pager, RefValue, SymbolTable, VirtualTable};
 use core::fmt;
 use fallible_iterator::FallibleIterator;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::collections::hash_map::Entry;
 use std::collections::{BTreeSet, HashMap};
 use std::rc::Rc;
 use std::sync::Arc;
 use tracing::trace;
-use turso_sqlite3_parser::ast::{self, ColumnDefinition, Expr, Literal, SortOrder, TableOptions};
+use turso_sqlite3_parser::ast::{
+    self, ColumnDefinition, Expr, Literal, ResolveType, SortOrder, TableOptions, TriggerCmd,
+    TriggerEvent, TriggerTime,
+};
 use turso_sqlite3_parser::{
     ast::{Cmd, CreateTableBody, QualifiedName, ResultColumn, Stmt},
     lexer::sql::Parser,
@@ -21,6 +30,9 @@ use turso_sqlite3_parser::{
 
 const SCHEMA_TABLE_NAME: &str = "sqlite_schema";
 const SCHEMA_TABLE_NAME_ALT: &str = "sqlite_master";
+/// The names the schema table of the temp database is also referred to by.
+const TEMP_SCHEMA_TABLE_NAME: &str = "sqlite_temp_schema";
+const TEMP_SCHEMA_TABLE_NAME_ALT: &str = "sqlite_temp_master";
 
 #[derive(Debug, Clone)]
 pub struct Schema {
@@ -30,6 +42,12 @@ pub struct Schema {
     pub has_indexes: std::collections::HashSet<String>,
     pub indexes_enabled: bool,
     pub schema_version: u32,
+    /// table_name to list of triggers for the table, most recently created first
+    pub triggers: HashMap<String, Vec<Arc<Trigger>>>,
+    /// view_name to view definition
+    pub views: HashMap<String, Arc<View>>,
+    /// table_name to the statistics ANALYZE gathered for the table
+    pub table_stats: HashMap<String, Arc<TableStats>>,
 }
 
 impl Schema {
@@ -51,6 +69,9 @@ impl Schema {
             has_indexes,
             indexes_enabled,
             schema_version: 0,
+            triggers: HashMap::new(),
+            views: HashMap::new(),
+            table_stats: HashMap::new(),
         }
     }
 
@@ -73,7 +94,10 @@ impl Schema {
 
     pub fn get_table(&self, name: &str) -> Option<Arc<Table>> {
         let name = normalize_ident(name);
-        let name = if name.eq_ignore_ascii_case(SCHEMA_TABLE_NAME_ALT) {
+        let name = if name.eq_ignore_ascii_case(SCHEMA_TABLE_NAME_ALT)
+            || name.eq_ignore_ascii_case(TEMP_SCHEMA_TABLE_NAME)
+            || name.eq_ignore_ascii_case(TEMP_SCHEMA_TABLE_NAME_ALT)
+        {
             SCHEMA_TABLE_NAME
         } else {
             &name
@@ -81,6 +105,13 @@ impl Schema {
         self.tables.get(name).cloned()
     }
 
+    /// Whether `table` is a table of this schema, rather than a table of another database
+    /// with the same name.
+    pub fn has_btree_table(&self, table: &Rc<BTreeTable>) -> bool {
+        self.get_btree_table(&table.name)
+            .is_some_and(|own_table| Rc::ptr_eq(&own_table, table))
+    }
+
     pub fn remove_table(&mut self, table_name: &str) {
         let name = normalize_ident(table_name);
         self.tables.remove(&name);
@@ -95,12 +126,15 @@ impl Schema {
         }
     }
 
+    /// Adds an index to the schema, replacing the index of the same name if the schema is
+    /// being parsed again.
     pub fn add_index(&mut self, index: Arc<Index>) {
         let table_name = normalize_ident(&index.table_name);
-        self.indexes
-            .entry(table_name)
-            .or_default()
-            .push(index.clone())
+        let indexes = self.indexes.entry(table_name).or_default();
+        match indexes.iter_mut().find(|other| other.name == index.name) {
+            Some(other) => *other = index,
+            None => indexes.push(index),
+        }
     }
 
     pub fn get_indices(&self, table_name: &str) -> &[Arc<Index>] {
@@ -118,6 +152,25 @@ impl Schema {
             .find(|index| index.name == index_name)
     }
 
+    /// Returns the index named `index_name`, whatever table it is on.
+    pub fn get_index_by_name(&self, index_name: &str) -> Option<&Arc<Index>> {
+        let name = normalize_ident(index_name);
+        self.indexes
+            .values()
+            .flatten()
+            .find(|index| index.name == name)
+    }
+
+    /// Returns the clustered index a WITHOUT ROWID table is stored in.
+    pub fn get_clustered_index(&self, table: &BTreeTable) -> Option<&Arc<Index>> {
+        if table.has_rowid {
+            return None;
+        }
+        self.get_indices(&table.name)
+            .iter()
+            .find(|index| index.root_page == table.root_page)
+    }
+
     pub fn remove_indices_for_table(&mut self, table_name: &str) {
         let name = normalize_ident(table_name);
         self.indexes.remove(&name);
@@ -143,6 +196,126 @@ impl Schema {
         self.indexes_enabled
     }
 
+    /// Triggers are kept most recently created first, which is the order
+    /// SQLite fires them in.
+    pub fn add_trigger(&mut self, trigger: Arc<Trigger>) {
+        let table_name = normalize_ident(&trigger.table_name);
+        self.triggers
+            .entry(table_name)
+            .or_default()
+            .insert(0, trigger);
+    }
+
+    pub fn get_trigger(&self, name: &str) -> Option<&Arc<Trigger>> {
+        let name = normalize_ident(name);
+        self.triggers
+            .values()
+            .flat_map(|triggers| triggers.iter())
+            .find(|trigger| trigger.name == name)
+    }
+
+    /// Returns the FOREIGN KEY constraints that reference `parent_table`, with the tables that
+    /// declare them, sorted by table name.
+    pub fn get_referencing_foreign_keys(
+        &self,
+        parent_table: &str,
+    ) -> Vec<(Rc<BTreeTable>, ForeignKey)> {
+        let parent_table = normalize_ident(parent_table);
+        let mut foreign_keys = self
+            .tables
+            .values()
+            .filter_map(|table| table.btree())
+            .flat_map(|table| {
+                table
+                    .foreign_keys
+                    .iter()
+                    .filter(|foreign_key| foreign_key.parent_table == parent_table)
+                    .map(|foreign_key| (table.clone(), foreign_key.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        foreign_keys.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name));
+        foreign_keys
+    }
+
+    pub fn get_triggers(&self, table_name: &str) -> &[Arc<Trigger>] {
+        let name = normalize_ident(table_name);
+        self.triggers
+            .get(&name)
+            .map_or_else(|| &[] as &[Arc<Trigger>], |v| v.as_slice())
+    }
+
+    pub fn remove_trigger(&mut self, name: &str) {
+        let name = normalize_ident(name);
+        for triggers in self.triggers.values_mut() {
+            triggers.retain(|trigger| trigger.name != name);
+        }
+        self.triggers.retain(|_, triggers| !triggers.is_empty());
+    }
+
+    pub fn remove_triggers_for_table(&mut self, table_name: &str) {
+        let name = normalize_ident(table_name);
+        self.triggers.remove(&name);
+    }
+
+    pub fn add_view(&mut self, view: Arc<View>) {
+        let name = normalize_ident(&view.name);
+        self.views.insert(name, view);
+    }
+
+    pub fn get_view(&self, name: &str) -> Option<Arc<View>> {
+        let name = normalize_ident(name);
+        self.views.get(&name).cloned()
+    }
+
+    pub fn remove_view(&mut self, name: &str) {
+        let name = normalize_ident(name);
+        self.views.remove(&name);
+    }
+
+    /// Adds the automatic indexes found in the schema table, keyed by table name, along with
+    /// the clustered indexes of the WITHOUT ROWID tables, which have no schema entry. Tables
+    /// without rowid are read through their clustered index, so it is added even when
+    /// indexes are disabled.
+    pub fn add_automatic_indices(
+        &mut self,
+        automatic_indices: HashMap<String, Vec<(String, usize)>>,
+    ) -> Result<()> {
+        for (table_name, auto_indices) in automatic_indices {
+            let table = self.get_btree_table(&table_name).unwrap();
+            if !self.indexes_enabled() {
+                self.table_set_has_index(&table_name);
+                if !table.has_rowid {
+                    let index = Index::clustered(table.as_ref(), auto_indices.len() + 1);
+                    self.add_index(Arc::new(index));
+                }
+            } else {
+                let ret_index =
+                    Index::automatic_from_primary_key_and_unique(table.as_ref(), auto_indices)?;
+                for index in ret_index {
+                    self.add_index(Arc::new(index));
+                }
+            }
+        }
+        // Tables without automatic indexes other than the clustered one have no rows for them
+        // in sqlite_schema, and the clustered index must store every column of the table.
+        let tables_without_clustered_index = self
+            .tables
+            .values()
+            .filter_map(|table| table.btree())
+            .filter(|table| {
+                !table.has_rowid
+                    && self
+                        .get_clustered_index(table)
+                        .is_none_or(|index| index.columns.len() != table.columns.len())
+            })
+            .collect::<Vec<_>>();
+        for table in tables_without_clustered_index {
+            self.add_index(Arc::new(Index::clustered(table.as_ref(), 1)));
+        }
+        Ok(())
+    }
+
     /// Update [Schema] by scanning the first root page (sqlite_schema)
     pub fn make_from_btree(
         &mut self,
@@ -155,6 +328,8 @@ impl Schema {
         let mut from_sql_indexes = Vec::with_capacity(10);
         let mut automatic_indices: HashMap<String, Vec<(String, usize)>> =
             HashMap::with_capacity(10);
+        let mut trigger_sqls = Vec::new();
+        let mut view_sqls = Vec::new();
 
         if matches!(pager.begin_read_tx()?, LimboResult::Busy) {
             return Err(LimboError::Busy);
@@ -266,6 +441,20 @@ impl Schema {
                         }
                     }
                 }
+                "trigger" => {
+                    let sql_value = record_cursor.get_value(&row, 4)?;
+                    let RefValue::Text(sql_text) = sql_value else {
+                        return Err(LimboError::ConversionError("Expected text value".into()));
+                    };
+                    trigger_sqls.push(sql_text.as_str().to_string());
+                }
+                "view" => {
+                    let sql_value = record_cursor.get_value(&row, 4)?;
+                    let RefValue::Text(sql_text) = sql_value else {
+                        return Err(LimboError::ConversionError("Expected text value".into()));
+                    };
+                    view_sqls.push(sql_text.as_str().to_string());
+                }
                 _ => {}
             };
             drop(record_cursor);
@@ -274,6 +463,13 @@ impl Schema {
             pager.io.block(|| cursor.next())?;
         }
 
+        let read_stat_rows = |table_name: &str| match self.get_btree_table(table_name) {
+            Some(table) => read_btree_rows(&pager, table.root_page),
+            None => Ok(vec![]),
+        };
+        let stat1_rows = read_stat_rows(STAT1_TABLE_NAME)?;
+        let stat4_rows = read_stat_rows(STAT4_TABLE_NAME)?;
+
         pager.end_read_tx()?;
 
         for unparsed_sql_from_index in from_sql_indexes {
@@ -292,21 +488,18 @@ impl Schema {
             }
         }
 
-        for automatic_index in automatic_indices {
-            if !self.indexes_enabled() {
-                self.table_set_has_index(&automatic_index.0);
-            } else {
-                let table = self.get_btree_table(&automatic_index.0).unwrap();
-                let ret_index = Index::automatic_from_primary_key_and_unique(
-                    table.as_ref(),
-                    automatic_index.1,
-                )?;
-                for index in ret_index {
-                    self.add_index(Arc::new(index));
-                }
-            }
+        self.add_automatic_indices(automatic_indices)?;
+
+        for sql in trigger_sqls {
+            self.add_trigger(Arc::new(Trigger::from_sql(&sql)?));
+        }
+
+        for sql in view_sqls {
+            self.add_view(Arc::new(View::from_sql(&sql)?));
         }
 
+        self.load_analysis(stat1_rows, stat4_rows);
+
         Ok(())
     }
 }
@@ -316,6 +509,7 @@ pub enum Table {
     BTree(Rc<BTreeTable>),
     Virtual(Rc<VirtualTable>),
     FromClauseSubquery(FromClauseSubquery),
+    Cte(Rc<CteTable>),
 }
 
 impl Table {
@@ -324,6 +518,7 @@ impl Table {
             Table::BTree(table) => table.root_page,
             Table::Virtual(_) => unimplemented!(),
             Table::FromClauseSubquery(_) => unimplemented!(),
+            Table::Cte(_) => unimplemented!(),
         }
     }
 
@@ -332,6 +527,7 @@ impl Table {
             Self::BTree(table) => &table.name,
             Self::Virtual(table) => &table.name,
             Self::FromClauseSubquery(from_clause_subquery) => &from_clause_subquery.name,
+            Self::Cte(cte) => &cte.table.name,
         }
     }
 
@@ -342,6 +538,7 @@ impl Table {
             Self::FromClauseSubquery(from_clause_subquery) => {
                 from_clause_subquery.columns.get(index)
             }
+            Self::Cte(cte) => cte.table.columns.get(index),
         }
     }
 
@@ -350,6 +547,7 @@ impl Table {
             Self::BTree(table) => &table.columns,
             Self::Virtual(table) => &table.columns,
             Self::FromClauseSubquery(from_clause_subquery) => &from_clause_subquery.columns,
+            Self::Cte(cte) => &cte.table.columns,
         }
     }
 
@@ -358,6 +556,7 @@ impl Table {
             Self::BTree(table) => Some(table.clone()),
             Self::Virtual(_) => None,
             Self::FromClauseSubquery(_) => None,
+            Self::Cte(_) => None,
         }
     }
 
@@ -374,6 +573,7 @@ impl PartialEq for Table {
         match (self, other) {
             (Self::BTree(a), Self::BTree(b)) => Rc::ptr_eq(a, b),
             (Self::Virtual(a), Self::Virtual(b)) => Rc::ptr_eq(a, b),
+            (Self::Cte(a), Self::Cte(b)) => Rc::ptr_eq(a, b),
             _ => false,
         }
     }
@@ -388,6 +588,145 @@ pub struct BTreeTable {
     pub has_rowid: bool,
     pub is_strict: bool,
     pub unique_sets: Option<Vec<Vec<(String, SortOrder)>>>,
+    /// The ON CONFLICT clauses of the PRIMARY KEY and UNIQUE constraints, with the names of the
+    /// constrained columns.
+    pub conflict_clauses: Vec<(Vec<String>, ResolveType)>,
+    /// The FOREIGN KEY constraints of the table, in declaration order.
+    pub foreign_keys: Vec<ForeignKey>,
+    /// The CHECK constraints of the table, in declaration order.
+    pub checks: Vec<CheckConstraint>,
+    /// The ON CONFLICT clauses of the NOT NULL constraints, with the names of the constrained
+    /// columns.
+    pub notnull_conflict_clauses: Vec<(String, ResolveType)>,
+}
+
+/// A CHECK constraint: the rows of the table must not make `expr` false.
+#[derive(Debug, Clone)]
+pub struct CheckConstraint {
+    pub name: Option<String>,
+    pub expr: Expr,
+}
+
+impl CheckConstraint {
+    pub fn new(name: Option<ast::Name>, expr: Expr) -> Self {
+        Self {
+            name: name.map(|name| normalize_ident(name.as_str())),
+            expr,
+        }
+    }
+
+    /// How a failure of the constraint is reported: by its name, or by its expression when it
+    /// has none.
+    pub fn description(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => self.expr.to_string(),
+        }
+    }
+
+    /// Whether the expression of the constraint refers to the column named `name`.
+    pub fn references_column(&self, name: &str) -> bool {
+        let mut found = false;
+        let _ = walk_expr(&self.expr, &mut |expr| {
+            match expr {
+                Expr::Id(id) | Expr::Qualified(_, id) => {
+                    found |= normalize_ident(id.as_str()) == name;
+                }
+                Expr::DoublyQualified(_, _, id) => {
+                    found |= normalize_ident(id.as_str()) == name;
+                }
+                _ => {}
+            }
+            Ok(WalkControl::Continue)
+        });
+        found
+    }
+
+    /// The CHECK table constraint that declares this constraint.
+    fn to_sql(&self) -> String {
+        match &self.name {
+            Some(name) => format!("CONSTRAINT {name} CHECK ({})", self.expr),
+            None => format!("CHECK ({})", self.expr),
+        }
+    }
+}
+
+/// A FOREIGN KEY constraint: `child_columns` of the table it is declared on reference
+/// `parent_columns` of `parent_table`.
+#[derive(Debug, Clone)]
+pub struct ForeignKey {
+    pub parent_table: String,
+    pub child_columns: Vec<String>,
+    /// Empty when the constraint references the primary key of the parent table.
+    pub parent_columns: Vec<String>,
+    pub on_delete: ast::RefAct,
+    pub on_update: ast::RefAct,
+    /// Whether the constraint is checked when the transaction commits rather than at the end
+    /// of each statement (`DEFERRABLE INITIALLY DEFERRED`).
+    pub deferred: bool,
+}
+
+impl ForeignKey {
+    fn new(
+        child_columns: Vec<String>,
+        clause: ast::ForeignKeyClause,
+        defer_clause: Option<ast::DeferSubclause>,
+    ) -> Self {
+        let mut on_delete = ast::RefAct::NoAction;
+        let mut on_update = ast::RefAct::NoAction;
+        for arg in clause.args {
+            match arg {
+                ast::RefArg::OnDelete(action) => on_delete = action,
+                ast::RefArg::OnUpdate(action) => on_update = action,
+                ast::RefArg::OnInsert(_) | ast::RefArg::Match(_) => {}
+            }
+        }
+        let deferred = defer_clause.is_some_and(|defer_clause| Self::is_deferred(&defer_clause));
+        Self {
+            parent_table: normalize_ident(clause.tbl_name.as_str()),
+            child_columns,
+            parent_columns: clause
+                .columns
+                .unwrap_or_default()
+                .iter()
+                .map(|column| normalize_ident(column.col_name.as_str()))
+                .collect(),
+            on_delete,
+            on_update,
+            deferred,
+        }
+    }
+
+    fn is_deferred(defer_clause: &ast::DeferSubclause) -> bool {
+        defer_clause.deferrable
+            && defer_clause.init_deferred == Some(ast::InitDeferredPred::InitiallyDeferred)
+    }
+
+    /// The FOREIGN KEY table constraint that declares this constraint.
+    fn to_sql(&self) -> String {
+        let mut sql = format!(
+            "FOREIGN KEY ({}) REFERENCES {}",
+            self.child_columns.join(", "),
+            self.parent_table
+        );
+        if !self.parent_columns.is_empty() {
+            sql.push_str(&format!("({})", self.parent_columns.join(", ")));
+        }
+        for (event, action) in [("DELETE", self.on_delete), ("UPDATE", self.on_update)] {
+            let action = match action {
+                ast::RefAct::SetNull => "SET NULL",
+                ast::RefAct::SetDefault => "SET DEFAULT",
+                ast::RefAct::Cascade => "CASCADE",
+                ast::RefAct::Restrict => "RESTRICT",
+                ast::RefAct::NoAction => continue,
+            };
+            sql.push_str(&format!(" ON {event} {action}"));
+        }
+        if self.deferred {
+            sql.push_str(" DEFERRABLE INITIALLY DEFERRED");
+        }
+        sql
+    }
 }
 
 impl BTreeTable {
@@ -418,6 +757,64 @@ impl BTreeTable {
             .find(|(_, column)| column.name.as_ref() == Some(&name))
     }
 
+    /// Returns the position in the record of a row of the column at `pos_in_table`, or None
+    /// if it is a VIRTUAL generated column, which is not stored.
+    pub fn column_storage_position(&self, pos_in_table: usize) -> Option<usize> {
+        if self.columns[pos_in_table].is_virtual() {
+            return None;
+        }
+        Some(
+            self.columns[..pos_in_table]
+                .iter()
+                .filter(|column| !column.is_virtual())
+                .count(),
+        )
+    }
+
+    pub fn has_virtual_columns(&self) -> bool {
+        self.columns.iter().any(|column| column.is_virtual())
+    }
+
+    /// Returns the ON CONFLICT clause of the PRIMARY KEY or UNIQUE constraint on exactly the
+    /// given columns, in any order.
+    pub fn conflict_clause<'a>(
+        &self,
+        columns: impl IntoIterator<Item = &'a str>,
+    ) -> Option<ResolveType> {
+        let columns = columns.into_iter().map(normalize_ident).collect::<Vec<_>>();
+        self.conflict_clauses
+            .iter()
+            .find(|(names, _)| {
+                names.len() == columns.len() && names.iter().all(|name| columns.contains(name))
+            })
+            .map(|(_, conflict_clause)| *conflict_clause)
+    }
+
+    /// Returns the ON CONFLICT clause of the NOT NULL constraint of a column.
+    pub fn notnull_conflict_clause(&self, column: &Column) -> Option<ResolveType> {
+        self.notnull_conflict_clauses
+            .iter()
+            .find(|(name, _)| column.name.as_ref() == Some(name))
+            .map(|(_, conflict_clause)| *conflict_clause)
+    }
+
+    /// Returns the ON CONFLICT clause of the constraint enforced by an index. Only the automatic
+    /// indexes of PRIMARY KEY and UNIQUE constraints have one.
+    pub fn index_conflict_clause(&self, index: &Index) -> Option<ResolveType> {
+        if !index
+            .name
+            .starts_with(PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX)
+        {
+            return None;
+        }
+        self.conflict_clause(
+            index
+                .key_columns()
+                .iter()
+                .map(|column| column.name.as_str()),
+        )
+    }
+
     pub fn from_sql(sql: &str, root_page: usize) -> Result<BTreeTable> {
         let mut parser = Parser::new(sql.as_bytes());
         let cmd = parser.next()?;
@@ -460,16 +857,65 @@ impl BTreeTable {
                 sql.push_str(" UNIQUE");
             }
 
-            if column.primary_key {
+            if column.primary_key && self.primary_key_columns.len() == 1 {
                 sql.push_str(" PRIMARY KEY");
+                if self.primary_key_columns[0].1 == SortOrder::Desc {
+                    sql.push_str(" DESC");
+                }
+            }
+
+            if column.notnull {
+                sql.push_str(" NOT NULL");
+                if let Some(conflict_clause) = self.notnull_conflict_clause(column) {
+                    sql.push_str(match conflict_clause {
+                        ResolveType::Rollback => " ON CONFLICT ROLLBACK",
+                        ResolveType::Abort => " ON CONFLICT ABORT",
+                        ResolveType::Fail => " ON CONFLICT FAIL",
+                        ResolveType::Ignore => " ON CONFLICT IGNORE",
+                        ResolveType::Replace => " ON CONFLICT REPLACE",
+                    });
+                }
             }
 
             if let Some(default) = &column.default {
                 sql.push_str(" DEFAULT ");
                 sql.push_str(&default.to_string());
             }
+
+            if let Some(generated) = &column.generated {
+                sql.push_str(" GENERATED ALWAYS AS (");
+                sql.push_str(&generated.expr.to_string());
+                sql.push(')');
+                if generated.stored {
+                    sql.push_str(" STORED");
+                }
+            }
+        }
+        if self.primary_key_columns.len() > 1 {
+            sql.push_str(", PRIMARY KEY (");
+            for (i, (name, order)) in self.primary_key_columns.iter().enumerate() {
+                if i > 0 {
+                    sql.push_str(", ");
+                }
+                sql.push_str(name);
+                if *order == SortOrder::Desc {
+                    sql.push_str(" DESC");
+                }
+            }
+            sql.push(')');
+        }
+        for foreign_key in &self.foreign_keys {
+            sql.push_str(", ");
+            sql.push_str(&foreign_key.to_sql());
+        }
+        for check in &self.checks {
+            sql.push_str(", ");
+            sql.push_str(&check.to_sql());
         }
         sql.push(')');
+        if !self.has_rowid {
+            sql.push_str(" WITHOUT ROWID");
+        }
         sql
     }
 
@@ -513,6 +959,36 @@ pub struct FromClauseSubquery {
     pub result_columns_start_reg: Option<usize>,
 }
 
+/// A common table expression that is not planned as a FROM clause subquery: a CTE with a compound
+/// SELECT, a recursive CTE, or a materialized CTE.
+#[derive(Debug)]
+pub struct CteTable {
+    /// The name and the columns of the CTE.
+    pub table: Rc<BTreeTable>,
+    /// The plan that computes the rows. Taken when the CTE is emitted. Always `None` for the
+    /// table holding the current row of a recursive CTE, which the recursive CTE fills itself.
+    pub plan: RefCell<Option<CtePlan>>,
+    /// Whether the rows are computed once, into an ephemeral table, by the query that declares
+    /// the CTE, rather than by each query that reads the CTE.
+    pub materialized: bool,
+    /// Where the rows are read from; set when the CTE is emitted.
+    pub rows: Cell<Option<CteRows>>,
+}
+
+/// Where the rows of a [CteTable] are read from.
+#[derive(Debug, Clone, Copy)]
+pub enum CteRows {
+    /// An ephemeral table holding all the rows, read through a cursor of its own by every
+    /// reference to the CTE.
+    Ephemeral { cursor_id: usize },
+    /// A coroutine yielding the rows one at a time.
+    Coroutine {
+        yield_reg: usize,
+        coroutine_implementation_start: BranchOffset,
+        result_columns_start_reg: usize,
+    },
+}
+
 #[derive(Debug, Eq)]
 struct UniqueColumnProps {
     column_name: String,
@@ -550,6 +1026,13 @@ fn create_table(
     let is_strict: bool;
     // BtreeSet here to preserve order of inserted keys
     let mut unique_sets: Vec<BTreeSet<UniqueColumnProps>> = vec![];
+    let mut conflict_clauses = vec![];
+    // Column constraints come first in declaration order, like in SQLite
+    let mut foreign_keys = vec![];
+    let mut table_foreign_keys = vec![];
+    let mut checks = vec![];
+    let mut table_checks = vec![];
+    let mut notnull_conflict_clauses = vec![];
     match body {
         CreateTableBody::ColumnsAndConstraints {
             columns,
@@ -560,9 +1043,12 @@ fn create_table(
             if let Some(constraints) = constraints {
                 for c in constraints {
                     if let turso_sqlite3_parser::ast::TableConstraint::PrimaryKey {
-                        columns, ..
+                        columns,
+                        conflict_clause,
+                        ..
                     } = c.constraint
                     {
+                        let first_column = primary_key_columns.len();
                         for column in columns {
                             let col_name = match column.expr {
                                 Expr::Id(id) => normalize_ident(id.as_str()),
@@ -576,30 +1062,63 @@ fn create_table(
                             primary_key_columns
                                 .push((col_name, column.order.unwrap_or(SortOrder::Asc)));
                         }
+                        if let Some(conflict_clause) = conflict_clause {
+                            let names = primary_key_columns[first_column..]
+                                .iter()
+                                .map(|(name, _)| name.clone())
+                                .collect();
+                            conflict_clauses.push((names, conflict_clause));
+                        }
                     } else if let turso_sqlite3_parser::ast::TableConstraint::Unique {
                         columns,
                         conflict_clause,
                     } = c.constraint
                     {
-                        if conflict_clause.is_some() {
-                            unimplemented!("ON CONFLICT not implemented");
-                        }
-                        let unique_set = columns
+                        let unique_set: BTreeSet<UniqueColumnProps> = columns
                             .into_iter()
                             .map(|column| {
                                 let column_name = match column.expr {
                                     Expr::Id(id) => normalize_ident(id.as_str()),
                                     _ => {
-                                        todo!("Unsupported unique expression");
+                                        return Err(LimboError::ParseError(
+                                            "expressions prohibited in PRIMARY KEY and UNIQUE constraints"
+                                                .to_string(),
+                                        ));
                                     }
                                 };
-                                UniqueColumnProps {
+                                Ok(UniqueColumnProps {
                                     column_name,
                                     order: column.order.unwrap_or(SortOrder::Asc),
-                                }
+                                })
                             })
-                            .collect();
+                            .collect::<Result<_>>()?;
+                        if let Some(conflict_clause) = conflict_clause {
+                            let names = unique_set
+                                .iter()
+                                .map(|column| column.column_name.clone())
+                                .collect();
+                            conflict_clauses.push((names, conflict_clause));
+                        }
                         unique_sets.push(unique_set);
+                    } else if let turso_sqlite3_parser::ast::TableConstraint::ForeignKey {
+                        columns,
+                        clause,
+                        deref_clause,
+                    } = c.constraint
+                    {
+                        let child_columns = columns
+                            .iter()
+                            .map(|column| normalize_ident(column.col_name.as_str()))
+                            .collect();
+                        table_foreign_keys.push(ForeignKey::new(
+                            child_columns,
+                            clause,
+                            deref_clause,
+                        ));
+                    } else if let turso_sqlite3_parser::ast::TableConstraint::Check(expr) =
+                        c.constraint
+                    {
+                        table_checks.push(CheckConstraint::new(c.name, expr));
                     }
                 }
             }
@@ -662,34 +1181,68 @@ fn create_table(
                 let mut order = SortOrder::Asc;
                 let mut unique = false;
                 let mut collation = None;
+                let mut generated = None;
                 for c_def in col_def.constraints {
                     match c_def.constraint {
                         turso_sqlite3_parser::ast::ColumnConstraint::PrimaryKey {
                             order: o,
+                            conflict_clause,
                             ..
                         } => {
                             primary_key = true;
                             if let Some(o) = o {
                                 order = o;
                             }
+                            if let Some(conflict_clause) = conflict_clause {
+                                conflict_clauses
+                                    .push((vec![normalize_ident(&name)], conflict_clause));
+                            }
                         }
-                        turso_sqlite3_parser::ast::ColumnConstraint::NotNull { .. } => {
+                        turso_sqlite3_parser::ast::ColumnConstraint::NotNull {
+                            conflict_clause,
+                            ..
+                        } => {
                             notnull = true;
+                            if let Some(conflict_clause) = conflict_clause {
+                                notnull_conflict_clauses
+                                    .push((normalize_ident(&name), conflict_clause));
+                            }
                         }
                         turso_sqlite3_parser::ast::ColumnConstraint::Default(expr) => {
                             default = Some(expr)
                         }
-                        // TODO: for now we don't check Resolve type of unique
-                        turso_sqlite3_parser::ast::ColumnConstraint::Unique(on_conflict) => {
-                            if on_conflict.is_some() {
-                                unimplemented!("ON CONFLICT not implemented");
+                        turso_sqlite3_parser::ast::ColumnConstraint::Check(expr) => {
+                            checks.push(CheckConstraint::new(c_def.name, expr));
+                        }
+                        turso_sqlite3_parser::ast::ColumnConstraint::Unique(conflict_clause) => {
+                            if let Some(conflict_clause) = conflict_clause {
+                                conflict_clauses
+                                    .push((vec![normalize_ident(&name)], conflict_clause));
                             }
                             unique = true;
                         }
                         turso_sqlite3_parser::ast::ColumnConstraint::Collate { collation_name } => {
                             collation = Some(CollationSeq::new(collation_name.as_str())?);
                         }
-                        _ => {}
+                        turso_sqlite3_parser::ast::ColumnConstraint::ForeignKey {
+                            clause,
+                            deref_clause,
+                        } => {
+                            foreign_keys.push(ForeignKey::new(
+                                vec![normalize_ident(&name)],
+                                clause,
+                                deref_clause,
+                            ));
+                        }
+                        // Applies to the constraint declared before it
+                        turso_sqlite3_parser::ast::ColumnConstraint::Defer(defer_clause) => {
+                            if let Some(foreign_key) = foreign_keys.last_mut() {
+                                foreign_key.deferred = ForeignKey::is_deferred(&defer_clause);
+                            }
+                        }
+                        turso_sqlite3_parser::ast::ColumnConstraint::Generated { expr, typ } => {
+                            generated = Some(GeneratedColumn::new(&name, expr, typ.as_ref())?);
+                        }
                     }
                 }
 
@@ -713,13 +1266,18 @@ fn create_table(
                     unique,
                     collation,
                     hidden: false,
+                    generated,
                 });
             }
             if options.contains(TableOptions::WITHOUT_ROWID) {
                 has_rowid = false;
             }
         }
-        CreateTableBody::AsSelect(_) => todo!(),
+        CreateTableBody::AsSelect(_) => {
+            return Err(LimboError::ParseError(
+                "CREATE TABLE ... AS SELECT must be stored as a column list".to_string(),
+            ))
+        }
     };
     // flip is_rowid_alias back to false if the table has multiple primary keys
     // or if the table has no rowid
@@ -728,6 +1286,12 @@ fn create_table(
             col.is_rowid_alias = false;
         }
     }
+    // the primary key of a table without rowid is its key, so it cannot be NULL
+    if !has_rowid {
+        for col in cols.iter_mut().filter(|col| col.primary_key) {
+            col.notnull = true;
+        }
+    }
     Ok(BTreeTable {
         root_page,
         name: table_name,
@@ -751,6 +1315,10 @@ fn create_table(
                     .collect(),
             )
         },
+        conflict_clauses,
+        foreign_keys: foreign_keys.into_iter().chain(table_foreign_keys).collect(),
+        checks: checks.into_iter().chain(table_checks).collect(),
+        notnull_conflict_clauses,
     })
 }
 
@@ -785,11 +1353,61 @@ pub struct Column {
     pub unique: bool,
     pub collation: Option<CollationSeq>,
     pub hidden: bool,
+    pub generated: Option<GeneratedColumn>,
 }
 
 impl Column {
     pub fn affinity(&self) -> Affinity {
-        affinity(&self.ty_str)
+        affinity(&self.ty_str.to_uppercase())
+    }
+
+    pub fn is_generated(&self) -> bool {
+        self.generated.is_some()
+    }
+
+    /// Whether the column is a VIRTUAL generated column, whose value is not stored in the
+    /// record of the row but computed when it is read.
+    pub fn is_virtual(&self) -> bool {
+        self.generated
+            .as_ref()
+            .is_some_and(|generated| !generated.stored)
+    }
+}
+
+/// The `GENERATED ALWAYS AS (expr)` clause of a column.
+#[derive(Debug, Clone)]
+pub struct GeneratedColumn {
+    pub expr: Expr,
+    /// STORED columns are computed when the row is written and stored in its record, VIRTUAL
+    /// ones are computed when they are read.
+    pub stored: bool,
+}
+
+impl GeneratedColumn {
+    pub fn new(column_name: &str, expr: Expr, typ: Option<&ast::Name>) -> Result<Self> {
+        let stored = match typ.map(|typ| typ.as_str()) {
+            None => false,
+            Some(typ) if typ.eq_ignore_ascii_case("VIRTUAL") => false,
+            Some(typ) if typ.eq_ignore_ascii_case("STORED") => true,
+            Some(_) => {
+                return Err(LimboError::ParseError(format!(
+                    "error in generated column \"{column_name}\""
+                )))
+            }
+        };
+        Ok(Self { expr, stored })
+    }
+
+    /// The names of the columns the expression refers to.
+    pub fn referenced_columns(&self) -> Vec<String> {
+        let mut names = vec![];
+        let _ = walk_expr(&self.expr, &mut |expr| {
+            if let Expr::Id(id) = expr {
+                names.push(normalize_ident(id.as_str()));
+            }
+            Ok(WalkControl::Continue)
+        });
+        names
     }
 }
 
@@ -803,6 +1421,7 @@ impl From<ColumnDefinition> for Column {
         let mut primary_key = false;
         let mut unique = false;
         let mut collation = None;
+        let mut generated = None;
 
         for ast::NamedColumnConstraint { constraint, .. } in value.constraints {
             match constraint {
@@ -818,6 +1437,9 @@ impl From<ColumnDefinition> for Column {
                             .expect("collation should have been set correctly in create table"),
                     );
                 }
+                ast::ColumnConstraint::Generated { expr, typ } => {
+                    generated = GeneratedColumn::new(name, expr, typ.as_ref()).ok();
+                }
                 _ => {}
             };
         }
@@ -864,6 +1486,7 @@ impl From<ColumnDefinition> for Column {
             unique,
             collation,
             hidden: false,
+            generated,
         }
     }
 }
@@ -1080,6 +1703,7 @@ pub fn sqlite_schema_table() -> BTreeTable {
                 unique: false,
                 collation: None,
                 hidden: false,
+                generated: None,
             },
             Column {
                 name: Some("name".to_string()),
@@ -1092,6 +1716,7 @@ pub fn sqlite_schema_table() -> BTreeTable {
                 unique: false,
                 collation: None,
                 hidden: false,
+                generated: None,
             },
             Column {
                 name: Some("tbl_name".to_string()),
@@ -1104,6 +1729,7 @@ pub fn sqlite_schema_table() -> BTreeTable {
                 unique: false,
                 collation: None,
                 hidden: false,
+                generated: None,
             },
             Column {
                 name: Some("rootpage".to_string()),
@@ -1116,6 +1742,7 @@ pub fn sqlite_schema_table() -> BTreeTable {
                 unique: false,
                 collation: None,
                 hidden: false,
+                generated: None,
             },
             Column {
                 name: Some("sql".to_string()),
@@ -1128,9 +1755,127 @@ pub fn sqlite_schema_table() -> BTreeTable {
                 unique: false,
                 collation: None,
                 hidden: false,
+                generated: None,
             },
         ],
         unique_sets: None,
+        conflict_clauses: vec![],
+        foreign_keys: vec![],
+        checks: vec![],
+        notnull_conflict_clauses: vec![],
+    }
+}
+
+/// A trigger as stored in `sqlite_schema`.
+///
+/// CREATE TRIGGER name BEFORE INSERT ON table_name BEGIN ... END
+#[derive(Debug, Clone)]
+pub struct Trigger {
+    pub name: String,
+    pub table_name: String,
+    pub time: TriggerTime,
+    pub event: TriggerEvent,
+    pub for_each_row: bool,
+    pub when_clause: Option<Expr>,
+    pub commands: Vec<TriggerCmd>,
+    pub sql: String,
+}
+
+impl Trigger {
+    pub fn from_sql(sql: &str) -> Result<Trigger> {
+        let mut parser = Parser::new(sql.as_bytes());
+        let cmd = parser.next()?;
+        match cmd {
+            Some(Cmd::Stmt(Stmt::CreateTrigger(create_trigger))) => {
+                let ast::CreateTrigger {
+                    trigger_name,
+                    time,
+                    event,
+                    tbl_name,
+                    for_each_row,
+                    when_clause,
+                    commands,
+                    ..
+                } = *create_trigger;
+                Ok(Trigger {
+                    name: normalize_ident(trigger_name.name.as_str()),
+                    table_name: normalize_ident(tbl_name.name.as_str()),
+                    time: time.unwrap_or(TriggerTime::Before),
+                    event,
+                    for_each_row,
+                    when_clause,
+                    commands,
+                    sql: sql.to_string(),
+                })
+            }
+            _ => Err(LimboError::ParseError(format!(
+                "Expected CREATE TRIGGER statement: {sql}"
+            ))),
+        }
+    }
+
+    /// Returns true if the trigger fires for `event` at `time`. For UPDATE triggers,
+    /// `updated_columns` are the names of the columns assigned by the statement, which are
+    /// matched against the column list of an `UPDATE OF` trigger.
+    pub fn fires_on(
+        &self,
+        time: TriggerTime,
+        event: &TriggerEvent,
+        updated_columns: &[&str],
+    ) -> bool {
+        if self.time != time {
+            return false;
+        }
+        match (&self.event, event) {
+            (TriggerEvent::Insert, TriggerEvent::Insert)
+            | (TriggerEvent::Delete, TriggerEvent::Delete)
+            | (TriggerEvent::Update, TriggerEvent::Update) => true,
+            (TriggerEvent::UpdateOf(columns), TriggerEvent::Update) => columns.iter().any(|c| {
+                let c = normalize_ident(c.as_str());
+                updated_columns.iter().any(|u| normalize_ident(u) == c)
+            }),
+            _ => false,
+        }
+    }
+}
+
+/// A view as stored in `sqlite_schema`.
+///
+/// CREATE VIEW name (column_list) AS SELECT ...
+#[derive(Debug, Clone)]
+pub struct View {
+    pub name: String,
+    /// The optional column list, which renames the columns of the SELECT.
+    pub columns: Option<Vec<String>>,
+    pub select: Box<ast::Select>,
+    pub sql: String,
+}
+
+impl View {
+    pub fn from_sql(sql: &str) -> Result<View> {
+        let mut parser = Parser::new(sql.as_bytes());
+        let cmd = parser.next()?;
+        match cmd {
+            Some(Cmd::Stmt(Stmt::CreateView {
+                view_name,
+                columns,
+                select,
+                ..
+            })) => Ok(View {
+                name: normalize_ident(view_name.name.as_str()),
+                columns: columns.map(|columns| {
+                    columns
+                        .iter()
+                        .map(|c| normalize_ident(c.col_name.as_str()))
+                        .collect()
+                }),
+                select,
+                sql: sql.to_string(),
+            }),
+            _ => Err(LimboError::ParseError(format!(
+                "Expected CREATE VIEW statement: {sql}"
+            ))),
+        }
     }
 }
 
@@ -1146,11 +1891,20 @@ pub struct Index {
     /// Does the index have a rowid as the last column?
     /// This is the case for btree indexes (persistent or ephemeral) that
     /// have been created based on a table with a rowid.
-    /// For example, WITHOUT ROWID tables (not supported in Limbo yet),
+    /// For example, indexes of WITHOUT ROWID tables,
     /// and  SELECT DISTINCT ephemeral indexes will not have a rowid.
     pub has_rowid: bool,
+    /// Number of trailing columns that are stored in the index but are not part of its key.
+    /// Indexes of WITHOUT ROWID tables store the primary key columns they lack in place of
+    /// a rowid, and the primary key index stores all the remaining columns of the table.
+    pub num_extra_columns: usize,
+    /// The WHERE clause of a partial index: only the rows it is true for have an entry.
+    pub where_clause: Option<Box<Expr>>,
 }
 
+/// The `pos_in_table` of the columns of an index on expressions that are expressions.
+pub const EXPR_COLUMN_POS: usize = usize::MAX;
+
 #[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub struct IndexColumn {
@@ -1164,6 +1918,51 @@ pub struct IndexColumn {
     pub pos_in_table: usize,
     pub collation: Option<CollationSeq>,
     pub default: Option<Expr>,
+    /// The expression indexed in place of a column of the table, whose `pos_in_table` is then
+    /// [EXPR_COLUMN_POS]. It refers to the columns of the table by name.
+    pub expr: Option<Box<Expr>>,
+}
+
+impl IndexColumn {
+    /// Resolves a column of a CREATE INDEX statement: a column of `table` or an expression,
+    /// optionally followed by a collation that overrides the one of the column.
+    pub fn from_sorted_column(table: &BTreeTable, column: &ast::SortedColumn) -> Result<Self> {
+        let (expr, collation) = match &column.expr {
+            Expr::Collate(expr, collation) => {
+                (expr.as_ref(), Some(CollationSeq::new(collation.as_str())?))
+            }
+            expr => (expr, None),
+        };
+        let order = column.order.unwrap_or(SortOrder::Asc);
+        if let Expr::Id(name) | Expr::Name(name) = expr {
+            let Some((pos_in_table, col)) = table.get_column(name.as_str()) else {
+                return Err(LimboError::ParseError(format!(
+                    "no such column: {}",
+                    normalize_ident(name.as_str())
+                )));
+            };
+            return Ok(Self {
+                name: col.name.clone().unwrap_or_default(),
+                order,
+                pos_in_table,
+                collation: collation.or(col.collation),
+                default: col.default.clone(),
+                expr: None,
+            });
+        }
+        Ok(Self {
+            name: expr.to_string(),
+            order,
+            pos_in_table: EXPR_COLUMN_POS,
+            collation,
+            default: None,
+            expr: Some(Box::new(expr.clone())),
+        })
+    }
+
+    pub fn is_expression(&self) -> bool {
+        self.expr.is_some()
+    }
 }
 
 impl Index {
@@ -1176,28 +1975,21 @@ impl Index {
                 tbl_name,
                 columns,
                 unique,
+                where_clause,
                 ..
             })) => {
                 let index_name = normalize_ident(idx_name.name.as_str());
-                let mut index_columns = Vec::with_capacity(columns.len());
-                for col in columns.into_iter() {
-                    let name = normalize_ident(&col.expr.to_string());
-                    let Some((pos_in_table, _)) = table.get_column(&name) else {
-                        return Err(crate::LimboError::InternalError(format!(
-                            "Column {} is in index {} but not found in table {}",
-                            name, index_name, table.name
-                        )));
-                    };
-                    let (_, column) = table.get_column(&name).unwrap();
-                    index_columns.push(IndexColumn {
-                        name,
-                        order: col.order.unwrap_or(SortOrder::Asc),
-                        pos_in_table,
-                        collation: column.collation,
-                        default: column.default.clone(),
-                    });
-                }
-                Ok(Index {
+                let index_columns = columns
+                    .iter()
+                    .map(|column| IndexColumn::from_sorted_column(table, column))
+                    .collect::<Result<Vec<_>>>()
+                    .map_err(|e| {
+                        LimboError::InternalError(format!(
+                            "Index {} on table {} is invalid: {}",
+                            index_name, table.name, e
+                        ))
+                    })?;
+                let mut index = Index {
                     name: index_name,
                     table_name: normalize_ident(tbl_name.as_str()),
                     root_page,
@@ -1205,7 +1997,11 @@ impl Index {
                     unique,
                     ephemeral: false,
                     has_rowid: table.has_rowid,
-                })
+                    num_extra_columns: 0,
+                    where_clause,
+                };
+                index.append_primary_key_columns(table);
+                Ok(index)
             }
             _ => todo!("Expected create index statement"),
         }
@@ -1220,9 +2016,14 @@ impl Index {
         table: &BTreeTable,
         auto_indices: Vec<(String, usize)>,
     ) -> Result<Vec<Index>> {
-        assert!(!auto_indices.is_empty());
+        // The primary key index of a table without rowid is the table itself, so it has no
+        // entry of its own in the schema table
+        assert!(!auto_indices.is_empty() || !table.has_rowid);
 
-        let mut indices = Vec::with_capacity(auto_indices.len());
+        let mut indices = Vec::with_capacity(auto_indices.len() + 1);
+        if !table.has_rowid {
+            indices.push(Index::clustered(table, auto_indices.len() + 1));
+        }
 
         // The number of auto_indices in create table should match in the number of indices we calculate in this function
         let mut auto_indices = auto_indices.into_iter();
@@ -1233,7 +2034,7 @@ impl Index {
         // To circumvent this, I'm having to allocate a second Vec, and extend the other from it.
         let has_primary_key_index =
             table.get_rowid_alias_column().is_none() && !table.primary_key_columns.is_empty();
-        if has_primary_key_index {
+        if has_primary_key_index && table.has_rowid {
             let (index_name, root_page) = auto_indices.next().expect(
                 "number of auto_indices in schema should be same number of indices calculated",
             );
@@ -1259,6 +2060,7 @@ impl Index {
                         pos_in_table,
                         collation: column.collation,
                         default: column.default.clone(),
+                        expr: None,
                     }
                 })
                 .collect::<Vec<_>>();
@@ -1271,6 +2073,8 @@ impl Index {
                 unique: true,
                 ephemeral: false,
                 has_rowid: table.has_rowid,
+                num_extra_columns: 0,
+                where_clause: None,
             });
         }
 
@@ -1291,7 +2095,7 @@ impl Index {
                     }
                     let (index_name, root_page) = auto_indices.next().expect("number of auto_indices in schema should be same number of indices calculated");
                     let (_, column) = table.get_column(col_name).unwrap();
-                    Some(Index {
+                    let mut index = Index {
                         name: normalize_ident(index_name.as_str()),
                         table_name: table.name.clone(),
                         root_page,
@@ -1301,11 +2105,16 @@ impl Index {
                             pos_in_table,
                             collation: column.collation,
                             default: column.default.clone(),
+                            expr: None,
                         }],
                         unique: true,
                         ephemeral: false,
                         has_rowid: table.has_rowid,
-                    })
+                        num_extra_columns: 0,
+                        where_clause: None,
+                    };
+                    index.append_primary_key_columns(table);
+                    Some(index)
                 } else {
                     None
                 }
@@ -1369,9 +2178,10 @@ impl Index {
                             pos_in_table,
                             collation: column.collation,
                             default: column.default.clone(),
+                            expr: None,
                         }
                     });
-                    Index {
+                    let mut index = Index {
                         name: normalize_ident(index_name.as_str()),
                         table_name: table.name.clone(),
                         root_page,
@@ -1379,7 +2189,11 @@ impl Index {
                         unique: true,
                         ephemeral: false,
                         has_rowid: table.has_rowid,
-                    }
+                        num_extra_columns: 0,
+                        where_clause: None,
+                    };
+                    index.append_primary_key_columns(table);
+                    index
                 });
             indices.extend(unique_set_indices);
         }
@@ -1391,6 +2205,125 @@ impl Index {
         Ok(indices)
     }
 
+    /// Builds the clustered index a WITHOUT ROWID table is stored in: the primary key columns
+    /// followed by the remaining columns of the table, rooted at the table's root page. SQLite
+    /// does not store it in the schema table, so it is named after the table's automatic indexes.
+    pub fn clustered(table: &BTreeTable, number: usize) -> Index {
+        let index_column = |(pos_in_table, column): (usize, &Column), order| IndexColumn {
+            name: column.name.clone().unwrap_or_default(),
+            order,
+            pos_in_table,
+            collation: column.collation,
+            default: column.default.clone(),
+            expr: None,
+        };
+        let mut columns = table
+            .primary_key_columns
+            .iter()
+            .map(|(name, order)| {
+                let column = table
+                    .get_column(name)
+                    .expect("primary key column must exist in table");
+                index_column(column, *order)
+            })
+            .collect::<Vec<_>>();
+        let num_key_columns = columns.len();
+        for (pos_in_table, column) in table.columns.iter().enumerate() {
+            if columns.iter().all(|c| c.pos_in_table != pos_in_table) {
+                columns.push(index_column((pos_in_table, column), SortOrder::Asc));
+            }
+        }
+        Index {
+            name: format!(
+                "{}{}_{}",
+                PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX, table.name, number
+            ),
+            table_name: table.name.clone(),
+            root_page: table.root_page,
+            num_extra_columns: columns.len() - num_key_columns,
+            where_clause: None,
+            columns,
+            unique: true,
+            ephemeral: false,
+            has_rowid: false,
+        }
+    }
+
+    /// Indexes of WITHOUT ROWID tables identify their rows by the primary key, so the primary
+    /// key columns that are not already indexed are stored after the indexed columns.
+    pub fn append_primary_key_columns(&mut self, table: &BTreeTable) {
+        if table.has_rowid {
+            return;
+        }
+        for (name, _) in table.primary_key_columns.iter() {
+            let (pos_in_table, column) = table
+                .get_column(name)
+                .expect("primary key column must exist in table");
+            if self.columns.iter().any(|c| c.pos_in_table == pos_in_table) {
+                continue;
+            }
+            self.columns.push(IndexColumn {
+                name: normalize_ident(name),
+                order: SortOrder::Asc,
+                pos_in_table,
+                collation: column.collation,
+                default: column.default.clone(),
+                expr: None,
+            });
+            self.num_extra_columns += 1;
+        }
+    }
+
+    /// The columns that make up the key of the index, i.e. the ones a UNIQUE index constrains.
+    pub fn key_columns(&self) -> &[IndexColumn] {
+        &self.columns[..self.columns.len() - self.num_extra_columns]
+    }
+
+    pub fn has_expressions(&self) -> bool {
+        self.columns.iter().any(IndexColumn::is_expression)
+    }
+
+    /// The positions of the columns of `table` the entries of the index depend on, including
+    /// the ones its expressions and its WHERE clause refer to.
+    pub fn referenced_columns(&self, table: &BTreeTable) -> Vec<usize> {
+        let mut positions = vec![];
+        let exprs = self
+            .columns
+            .iter()
+            .filter_map(|column| column.expr.as_deref())
+            .chain(self.where_clause.as_deref());
+        for expr in exprs {
+            let _ = walk_expr(expr, &mut |expr| {
+                if let Expr::Id(name) = expr {
+                    if let Some((pos, _)) = table.get_column(name.as_str()) {
+                        positions.push(pos);
+                    }
+                }
+                Ok(WalkControl::Continue)
+            });
+        }
+        positions.extend(
+            self.columns
+                .iter()
+                .filter(|column| !column.is_expression())
+                .map(|column| column.pos_in_table),
+        );
+        positions
+    }
+
+    /// The description of a violation of the UNIQUE constraint of the index: its key columns,
+    /// or the index itself if it has expressions, like in SQLite.
+    pub fn unique_constraint_description(&self, table_name: &str) -> String {
+        if self.has_expressions() {
+            return format!("index '{}'", self.name);
+        }
+        self.key_columns()
+            .iter()
+            .map(|column| format!("{}.{}", table_name, column.name))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
     /// Given a column position in the table, return the position in the index.
     /// Returns None if the column is not found in the index.
     /// For example, given:
@@ -1687,6 +2620,38 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    pub fn test_check_constraints() -> Result<()> {
+        let sql = r#"CREATE TABLE t (a CHECK (a > 0), b, CONSTRAINT b_small CHECK (b < a));"#;
+        let table = BTreeTable::from_sql(sql, 0)?;
+        let descriptions = table
+            .checks
+            .iter()
+            .map(|check| check.description())
+            .collect::<Vec<_>>();
+        assert_eq!(descriptions, vec!["a > 0", "b_small"]);
+        assert_eq!(
+            table.to_sql(),
+            "CREATE TABLE t (a, b, CHECK (a > 0), CONSTRAINT b_small CHECK (b < a))"
+        );
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_notnull_conflict_clause() -> Result<()> {
+        let sql = r#"CREATE TABLE t (a NOT NULL ON CONFLICT IGNORE, b NOT NULL);"#;
+        let table = BTreeTable::from_sql(sql, 0)?;
+        let (_, a) = table.get_column("a").unwrap();
+        let (_, b) = table.get_column("b").unwrap();
+        assert_eq!(table.notnull_conflict_clause(a), Some(ResolveType::Ignore));
+        assert_eq!(table.notnull_conflict_clause(b), None);
+        assert_eq!(
+            table.to_sql(),
+            "CREATE TABLE t (a NOT NULL ON CONFLICT IGNORE, b NOT NULL)"
+        );
+        Ok(())
+    }
+
     #[test]
     #[should_panic]
     fn test_automatic_index_single_column() {
@@ -1761,8 +2726,13 @@ mod tests {
                 unique: false,
                 collation: None,
                 hidden: false,
+                generated: None,
             }],
             unique_sets: None,
+            conflict_clauses: vec![],
+            foreign_keys: vec![],
+            checks: vec![],
+            notnull_conflict_clauses: vec![],
         };
 
         let _result = Index::automatic_from_primary_key_and_unique(
@@ -1967,4 +2937,85 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_automatic_index_without_rowid() -> Result<()> {
+        let sql = r#"CREATE TABLE t1 (a, b UNIQUE, c, PRIMARY KEY (c DESC, a)) WITHOUT ROWID"#;
+        let table = BTreeTable::from_sql(sql, 2)?;
+        let mut indexes = Index::automatic_from_primary_key_and_unique(
+            &table,
+            vec![("sqlite_autoindex_t1_1".to_string(), 3)],
+        )?;
+
+        assert!(indexes.len() == 2);
+        let index = indexes.pop().unwrap();
+        assert_eq!(index.name, "sqlite_autoindex_t1_1");
+        assert_eq!(index.root_page, 3);
+        assert!(!index.has_rowid);
+        assert_eq!(index.key_columns().len(), 1);
+        assert_eq!(index.key_columns()[0].name, "b");
+        let names = index
+            .columns
+            .iter()
+            .map(|c| c.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["b", "c", "a"]);
+
+        // The clustered index is the table itself.
+        let index = indexes.pop().unwrap();
+        assert_eq!(index.name, "sqlite_autoindex_t1_2");
+        assert_eq!(index.root_page, 2);
+        assert!(index.unique);
+        assert!(!index.has_rowid);
+        assert_eq!(index.key_columns().len(), 2);
+        let names = index
+            .columns
+            .iter()
+            .map(|c| c.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["c", "a", "b"]);
+        assert!(matches!(index.columns[0].order, SortOrder::Desc));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_to_sql_without_rowid() -> Result<()> {
+        let sql = r#"CREATE TABLE t1 (a, b, PRIMARY KEY (b DESC, a)) WITHOUT ROWID"#;
+        let table = BTreeTable::from_sql(sql, 2)?;
+        assert_eq!(
+            table.to_sql(),
+            "CREATE TABLE t1 (a NOT NULL, b NOT NULL, PRIMARY KEY (b DESC, a)) WITHOUT ROWID"
+        );
+        let table = BTreeTable::from_sql(&table.to_sql(), 2)?;
+        assert!(!table.has_rowid);
+        assert_eq!(table.primary_key_columns.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_generated_columns() -> Result<()> {
+        let sql =
+            r#"CREATE TABLE t1 (a, b INT AS (a * 2), c GENERATED ALWAYS AS (a + 1) STORED, d)"#;
+        let table = BTreeTable::from_sql(sql, 2)?;
+        assert!(!table.columns[0].is_generated());
+        assert!(table.columns[1].is_virtual());
+        assert_eq!(table.columns[1].ty_str, "INT");
+        assert!(table.columns[2].is_generated());
+        assert!(!table.columns[2].is_virtual());
+        assert_eq!(table.column_storage_position(0), Some(0));
+        assert_eq!(table.column_storage_position(1), None);
+        assert_eq!(table.column_storage_position(2), Some(1));
+        assert_eq!(table.column_storage_position(3), Some(2));
+        assert_eq!(
+            table.to_sql(),
+            "CREATE TABLE t1 (a, b INT GENERATED ALWAYS AS (a * 2), c GENERATED ALWAYS AS (a + 1) STORED, d)"
+        );
+        let table = BTreeTable::from_sql(&table.to_sql(), 2)?;
+        assert!(table.columns[1].is_virtual());
+        assert_eq!(table.columns[1].ty_str, "INT");
+
+        Ok(())
+    }
 }
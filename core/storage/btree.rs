@@ -17,7 +17,7 @@ use crate::{
         find_compare, get_tie_breaker_from_seek_op, IndexInfo, ParseRecordState, RecordCompare,
         RecordCursor, SeekResult,
     },
-    MvCursor,
+    MvCursor, MvIndexCursor,
 };
 
 use crate::{
@@ -166,6 +166,8 @@ enum DestroyState {
 
 struct DestroyInfo {
     state: DestroyState,
+    /// Whether the root page is emptied instead of freed, see [BTreeCursor::clear_btree].
+    keep_root: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -509,6 +511,8 @@ pub enum CursorSeekState {
 pub struct BTreeCursor {
     /// The multi-version cursor that is used to read and write to the database file.
     mv_cursor: Option<Rc<RefCell<MvCursor>>>,
+    /// The multi-version cursor that is used to read and write the entries of an index.
+    mv_index_cursor: Option<Rc<RefCell<MvIndexCursor>>>,
     /// The pager that is used to read and write to the database file.
     pager: Rc<Pager>,
     /// Page id of the root page used to go back up fast.
@@ -592,6 +596,7 @@ impl BTreeCursor {
     ) -> Self {
         Self {
             mv_cursor,
+            mv_index_cursor: None,
             pager,
             root_page,
             has_record: Cell::new(false),
@@ -626,13 +631,14 @@ impl BTreeCursor {
     }
 
     pub fn new_index(
-        mv_cursor: Option<Rc<RefCell<MvCursor>>>,
+        mv_cursor: Option<Rc<RefCell<MvIndexCursor>>>,
         pager: Rc<Pager>,
         root_page: usize,
         index: &Index,
         num_columns: usize,
     ) -> Self {
-        let mut cursor = Self::new(mv_cursor, pager, root_page, num_columns);
+        let mut cursor = Self::new(None, pager, root_page, num_columns);
+        cursor.mv_index_cursor = mv_cursor;
         cursor.index_info = Some(IndexInfo::new_from_index(index));
         cursor
     }
@@ -640,7 +646,7 @@ impl BTreeCursor {
     pub fn has_rowid(&self) -> bool {
         match &self.index_info {
             Some(index_key_info) => index_key_info.has_rowid,
-            None => true, // currently we don't support WITHOUT ROWID tables
+            None => true, // tables without rowid are read through index cursors
         }
     }
 
@@ -672,6 +678,10 @@ impl BTreeCursor {
             let mv_cursor = mv_cursor.borrow();
             return Ok(IOResult::Done(mv_cursor.is_empty()));
         }
+        if let Some(mv_cursor) = &self.mv_index_cursor {
+            let mv_cursor = mv_cursor.borrow();
+            return Ok(IOResult::Done(mv_cursor.is_empty()));
+        }
         let page = self.pager.read_page(self.root_page)?;
         return_if_locked!(page);
 
@@ -683,6 +693,9 @@ impl BTreeCursor {
     /// Used in backwards iteration.
     #[instrument(skip(self), level = Level::DEBUG, name = "prev")]
     fn get_prev_record(&mut self) -> Result<IOResult<bool>> {
+        if let Some(mv_cursor) = &self.mv_index_cursor {
+            return Ok(IOResult::Done(mv_cursor.borrow_mut().backward()?));
+        }
         loop {
             let page = self.stack.top();
 
@@ -1211,6 +1224,9 @@ impl BTreeCursor {
                 None => return Ok(IOResult::Done(false)),
             }
         }
+        if let Some(mv_cursor) = &self.mv_index_cursor {
+            return Ok(IOResult::Done(mv_cursor.borrow_mut().forward()?));
+        }
         loop {
             let mem_page_rc = self.stack.top();
             return_if_locked_maybe_load!(self.pager, mem_page_rc);
@@ -4165,6 +4181,10 @@ impl BTreeCursor {
         self.root_page
     }
 
+    pub fn pager(&self) -> Rc<Pager> {
+        self.pager.clone()
+    }
+
     #[instrument(skip_all, level = Level::DEBUG)]
     pub fn rewind(&mut self) -> Result<IOResult<()>> {
         if let Some(mv_cursor) = &self.mv_cursor {
@@ -4175,6 +4195,10 @@ impl BTreeCursor {
             let cursor_has_record = return_if_io!(self.get_next_record());
             self.invalidate_record();
             self.has_record.replace(cursor_has_record);
+        } else if let Some(mv_cursor) = &self.mv_index_cursor {
+            let cursor_has_record = mv_cursor.borrow_mut().rewind()?;
+            self.invalidate_record();
+            self.has_record.replace(cursor_has_record);
         } else {
             self.move_to_root()?;
 
@@ -4188,6 +4212,12 @@ impl BTreeCursor {
     #[instrument(skip_all, level = Level::DEBUG)]
     pub fn last(&mut self) -> Result<IOResult<()>> {
         assert!(self.mv_cursor.is_none());
+        if let Some(mv_cursor) = &self.mv_index_cursor {
+            let cursor_has_record = mv_cursor.borrow_mut().last()?;
+            self.has_record.replace(cursor_has_record);
+            self.invalidate_record();
+            return Ok(IOResult::Done(()));
+        }
         let cursor_has_record = return_if_io!(self.move_to_rightmost());
         self.has_record.replace(cursor_has_record);
         self.invalidate_record();
@@ -4233,6 +4263,14 @@ impl BTreeCursor {
                 return Ok(IOResult::Done(None));
             }
         }
+        if self.mv_index_cursor.is_some() {
+            if self.has_record.get() {
+                let _ = return_if_io!(self.record());
+                return Ok(IOResult::Done(self.get_index_rowid_from_record()));
+            } else {
+                return Ok(IOResult::Done(None));
+            }
+        }
         if self.has_record.get() {
             let page = self.stack.top();
             return_if_locked_maybe_load!(self.pager, page);
@@ -4261,13 +4299,15 @@ impl BTreeCursor {
 
     #[instrument(skip(self), level = Level::DEBUG)]
     pub fn seek(&mut self, key: SeekKey<'_>, op: SeekOp) -> Result<IOResult<SeekResult>> {
-        assert!(self.mv_cursor.is_none());
         // Empty trace to capture the span information
         tracing::trace!("");
         // We need to clear the null flag for the table cursor before seeking,
         // because it might have been set to false by an unmatched left-join row during the previous iteration
         // on the outer loop.
         self.set_null_flag(false);
+        if self.mv_cursor.is_some() || self.mv_index_cursor.is_some() {
+            return Ok(IOResult::Done(self.mv_seek(key, op)?));
+        }
         let seek_result = return_if_io!(self.do_seek(key, op));
         self.invalidate_record();
         // Reset seek state
@@ -4276,6 +4316,34 @@ impl BTreeCursor {
         Ok(IOResult::Done(seek_result))
     }
 
+    /// Seeks with the multi-version cursor. The cursor has a record if it is on a row or entry,
+    /// which for table cursors is only the case if the row satisfies `op`.
+    fn mv_seek(&mut self, key: SeekKey<'_>, op: SeekOp) -> Result<SeekResult> {
+        let (found, has_record) = match (key, &self.mv_cursor, &self.mv_index_cursor) {
+            (SeekKey::TableRowId(rowid), Some(mv_cursor), _) => {
+                let found = mv_cursor.borrow_mut().seek(rowid, op)?;
+                (found, found)
+            }
+            (SeekKey::IndexKey(record), _, Some(mv_cursor)) => {
+                let mut mv_cursor = mv_cursor.borrow_mut();
+                let found = mv_cursor.seek(record, op)?;
+                (found, !mv_cursor.is_empty())
+            }
+            (key, _, _) => {
+                return Err(LimboError::InternalError(format!(
+                    "cannot seek {key:?} with this multi-version cursor"
+                )))
+            }
+        };
+        self.invalidate_record();
+        self.has_record.replace(has_record);
+        Ok(if found {
+            SeekResult::Found
+        } else {
+            SeekResult::NotFound
+        })
+    }
+
     /// Return a reference to the record the cursor is currently pointing to.
     /// If record was not parsed yet, then we have to parse it and in case of I/O we yield control
     /// back.
@@ -4313,6 +4381,25 @@ impl BTreeCursor {
                     .unwrap();
             return Ok(IOResult::Done(Some(record_ref)));
         }
+        if let Some(mv_cursor) = &self.mv_index_cursor {
+            let mv_cursor = mv_cursor.borrow();
+            let Some(record) = mv_cursor.current_record() else {
+                return Ok(IOResult::Done(None));
+            };
+            self.get_immutable_record_or_create()
+                .as_mut()
+                .unwrap()
+                .invalidate();
+            self.get_immutable_record_or_create()
+                .as_mut()
+                .unwrap()
+                .start_serialization(record.get_payload());
+            self.record_cursor.borrow_mut().invalidate();
+            let record_ref =
+                Ref::filter_map(self.reusable_immutable_record.borrow(), |opt| opt.as_ref())
+                    .unwrap();
+            return Ok(IOResult::Done(Some(record_ref)));
+        }
 
         if *self.parse_record_state.borrow() == ParseRecordState::Init {
             *self.parse_record_state.borrow_mut() = ParseRecordState::Parsing {
@@ -4375,6 +4462,11 @@ impl BTreeCursor {
         mut moved_before: bool,
     ) -> Result<IOResult<()>> {
         tracing::debug!(valid_state = ?self.valid_state, cursor_state = ?self.state, is_write_in_progress = self.is_write_in_progress());
+        if let Some(mv_cursor) = &self.mv_index_cursor {
+            let record = key.get_record().unwrap().clone();
+            mv_cursor.borrow().insert(record)?;
+            return Ok(IOResult::Done(()));
+        }
         match &self.mv_cursor {
             Some(mv_cursor) => match key.maybe_rowid() {
                 Some(rowid) => {
@@ -4383,7 +4475,9 @@ impl BTreeCursor {
                     let row = crate::mvcc::database::Row::new(row_id, record_buf);
                     mv_cursor.borrow_mut().insert(row).unwrap();
                 }
-                None => todo!("Support mvcc inserts with index btrees"),
+                None => {
+                    unreachable!("index entries are inserted by the multi-version index cursor")
+                }
             },
             None => {
                 match (&self.valid_state, self.is_write_in_progress()) {
@@ -4461,6 +4555,10 @@ impl BTreeCursor {
     #[instrument(skip(self), level = Level::DEBUG)]
     pub fn delete(&mut self) -> Result<IOResult<()>> {
         assert!(self.mv_cursor.is_none());
+        if let Some(mv_cursor) = &self.mv_index_cursor {
+            mv_cursor.borrow().delete()?;
+            return Ok(IOResult::Done(()));
+        }
 
         if let CursorState::None = &self.state {
             self.state = CursorState::Delete(DeleteInfo {
@@ -4879,7 +4977,6 @@ impl BTreeCursor {
 
     #[instrument(skip_all, level = Level::DEBUG)]
     pub fn exists(&mut self, key: &Value) -> Result<IOResult<bool>> {
-        assert!(self.mv_cursor.is_none());
         let int_key = match key {
             Value::Integer(i) => i,
             _ => unreachable!("btree tables are indexed by integers!"),
@@ -4965,10 +5062,25 @@ impl BTreeCursor {
     /// The destruction order would be: [4',4,5,2,6,7,3,1]
     #[instrument(skip(self), level = Level::DEBUG)]
     pub fn btree_destroy(&mut self) -> Result<IOResult<Option<usize>>> {
+        self.free_btree_pages(false)
+    }
+
+    /// Deletes all the entries of a B-tree, freeing all its pages but the root page, which is
+    /// left as an empty leaf page. Like [Self::btree_destroy], it can be resumed after IO.
+    #[instrument(skip(self), level = Level::DEBUG)]
+    pub fn clear_btree(&mut self) -> Result<IOResult<()>> {
+        return_if_io!(self.free_btree_pages(true));
+        self.invalidate_record();
+        self.has_record.set(false);
+        Ok(IOResult::Done(()))
+    }
+
+    fn free_btree_pages(&mut self, keep_root: bool) -> Result<IOResult<Option<usize>>> {
         if let CursorState::None = &self.state {
             self.move_to_root()?;
             self.state = CursorState::Destroy(DestroyInfo {
                 state: DestroyState::Start,
+                keep_root,
             });
         }
 
@@ -5119,6 +5231,33 @@ impl BTreeCursor {
                     let page = self.stack.top();
                     let page_id = page.get().get().id;
 
+                    let keep_root = self
+                        .state
+                        .destroy_info()
+                        .expect("unable to get a mut reference to destroy state in cursor")
+                        .keep_root;
+                    if keep_root && !self.stack.has_parent() {
+                        let (page_type, offset) = {
+                            let page = page.get();
+                            let contents = page.get().contents.as_ref().unwrap();
+                            let page_type = match contents.page_type() {
+                                PageType::IndexInterior | PageType::IndexLeaf => {
+                                    PageType::IndexLeaf
+                                }
+                                PageType::TableInterior | PageType::TableLeaf => {
+                                    PageType::TableLeaf
+                                }
+                            };
+                            (page_type, contents.offset)
+                        };
+                        // Mark the page dirty first, so that savepoints capture it before it is
+                        // cleared.
+                        self.pager.add_dirty(&page.get());
+                        btree_init_page(&page, page_type, offset, self.usable_space() as u16);
+                        self.state = CursorState::None;
+                        return Ok(IOResult::Done(None));
+                    }
+
                     return_if_io!(self.pager.free_page(Some(page.get()), page_id));
 
                     if self.stack.has_parent() {
@@ -7586,6 +7725,7 @@ mod tests {
                         collation: None,
                         pos_in_table: i,
                         default: None,
+                        expr: None,
                     })
                     .collect(),
                 table_name: "test".to_string(),
@@ -7593,6 +7733,8 @@ mod tests {
                 unique: false,
                 ephemeral: false,
                 has_rowid: false,
+                num_extra_columns: 0,
+                where_clause: None,
             };
             let num_columns = index_def.columns.len();
             let mut cursor = BTreeCursor::new_index(
@@ -7759,12 +7901,15 @@ mod tests {
                     collation: None,
                     pos_in_table: 0,
                     default: None,
+                    expr: None,
                 }],
                 table_name: "test".to_string(),
                 root_page: index_root_page,
                 unique: false,
                 ephemeral: false,
                 has_rowid: false,
+                num_extra_columns: 0,
+                where_clause: None,
             };
             let mut cursor =
                 BTreeCursor::new_index(None, pager.clone(), index_root_page, &index_def, 1);
@@ -8311,13 +8456,12 @@ mod tests {
         Ok(())
     }
 
-    #[test]
-    fn test_btree_destroy() -> Result<()> {
-        let initial_size = 1;
-        let pager = setup_test_env(initial_size);
+    /// Builds a table b-tree rooted at page 2 whose interior root page points to two leaf
+    /// pages with a record each.
+    fn build_two_leaf_table_btree(pager: &Rc<Pager>) -> Result<BTreeCursor> {
         let num_columns = 5;
 
-        let mut cursor = BTreeCursor::new_table(None, pager.clone(), 2, num_columns);
+        let cursor = BTreeCursor::new_table(None, pager.clone(), 2, num_columns);
 
         // Initialize page 2 as a root page (interior)
         let root_page = cursor.allocate_page(PageType::TableInterior, 0)?;
@@ -8368,6 +8512,15 @@ mod tests {
             insert_into_cell(contents, &record_bytes, 0, 512)?;
         }
 
+        Ok(cursor)
+    }
+
+    #[test]
+    fn test_btree_destroy() -> Result<()> {
+        let initial_size = 1;
+        let pager = setup_test_env(initial_size);
+        let mut cursor = build_two_leaf_table_btree(&pager)?;
+
         // Verify structure before destruction
         assert_eq!(
             header_accessor::get_database_size(&pager)?,
@@ -8387,6 +8540,30 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_btree_clear() -> Result<()> {
+        let initial_size = 1;
+        let pager = setup_test_env(initial_size);
+        let mut cursor = build_two_leaf_table_btree(&pager)?;
+
+        run_until_done(|| cursor.clear_btree(), pager.deref())?;
+
+        let pages_freed = header_accessor::get_freelist_pages(&pager)?;
+        assert_eq!(pages_freed, 2, "should free the 2 leaves but not the root");
+
+        // The root page is left as an empty leaf page
+        let root_page = cursor.read_page(2)?;
+        let root_page = root_page.get();
+        let contents = root_page.get().contents.as_ref().unwrap();
+        assert_eq!(contents.page_type(), PageType::TableLeaf);
+        assert_eq!(contents.cell_count(), 0);
+
+        run_until_done(|| cursor.rewind(), pager.deref())?;
+        assert!(!cursor.has_record.get());
+
+        Ok(())
+    }
+
     #[test]
     pub fn test_defragment() {
         let db = get_database();
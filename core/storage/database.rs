@@ -19,6 +19,7 @@ pub trait DatabaseStorage: Send + Sync {
     ) -> Result<()>;
     fn sync(&self, c: Completion) -> Result<()>;
     fn size(&self) -> Result<u64>;
+    fn truncate(&self, len: usize, c: Completion) -> Result<()>;
 }
 
 #[cfg(feature = "fs")]
@@ -73,6 +74,12 @@ impl DatabaseStorage for DatabaseFile {
     fn size(&self) -> Result<u64> {
         self.file.size()
     }
+
+    #[instrument(skip_all, level = Level::DEBUG)]
+    fn truncate(&self, len: usize, c: Completion) -> Result<()> {
+        let _ = self.file.truncate(len, c.into())?;
+        Ok(())
+    }
 }
 
 #[cfg(feature = "fs")]
@@ -132,6 +139,12 @@ impl DatabaseStorage for FileMemoryStorage {
     fn size(&self) -> Result<u64> {
         self.file.size()
     }
+
+    #[instrument(skip_all, level = Level::DEBUG)]
+    fn truncate(&self, len: usize, c: Completion) -> Result<()> {
+        let _ = self.file.truncate(len, c.into())?;
+        Ok(())
+    }
 }
 
 impl FileMemoryStorage {
@@ -13,7 +13,7 @@ use crate::{return_if_io, Completion};
 use crate::{turso_assert, Buffer, Connection, LimboError, Result};
 use parking_lot::RwLock;
 use std::cell::{Cell, OnceCell, RefCell, UnsafeCell};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::hash;
 use std::rc::Rc;
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -22,7 +22,9 @@ use tracing::{instrument, trace, Level};
 
 use super::btree::{btree_init_page, BTreePage};
 use super::page_cache::{CacheError, CacheResizeResult, DumbLruPageCache, PageCacheKey};
-use super::sqlite3_ondisk::{begin_write_btree_page, DATABASE_HEADER_SIZE};
+use super::sqlite3_ondisk::{
+    begin_write_btree_page, DATABASE_HEADER_PAGE_ID, DATABASE_HEADER_SIZE,
+};
 use super::wal::CheckpointMode;
 
 #[cfg(not(feature = "omit_autovacuum"))]
@@ -248,6 +250,16 @@ struct FlushInfo {
     dirty_pages: Vec<usize>,
 }
 
+/// The state of the database when a savepoint was opened, which ROLLBACK TO restores.
+#[derive(Default)]
+struct Savepoint {
+    /// The pages that existed when the savepoint was opened and were modified since, with their
+    /// contents before the first modification.
+    pages: HashMap<usize, Arc<[u8]>>,
+    /// The pages allocated since the savepoint was opened.
+    allocated_pages: HashSet<usize>,
+}
+
 /// Track the state of the auto-vacuum mode.
 #[derive(Clone, Copy, Debug)]
 pub enum AutoVacuumMode {
@@ -342,6 +354,8 @@ pub struct Pager {
     page_size: Cell<Option<u32>>,
     reserved_space: OnceCell<u8>,
     free_page_state: RefCell<FreePageState>,
+    /// The open savepoints of the current transaction, outermost first.
+    savepoints: RefCell<Vec<Savepoint>>,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -421,6 +435,7 @@ impl Pager {
                 dirty_pages: Vec::new(),
             }),
             free_page_state: RefCell::new(FreePageState::Start),
+            savepoints: RefCell::new(Vec::new()),
         })
     }
 
@@ -571,6 +586,12 @@ impl Pager {
             }
         };
 
+        turso_assert!(
+            ptrmap_page.get().id == ptrmap_pg_no as usize,
+            "ptrmap page has unexpected number"
+        );
+        self.add_dirty(&ptrmap_page);
+
         let mut page_buffer_guard = page_content.buffer.borrow_mut();
         let full_buffer_slice = page_buffer_guard.as_mut_slice();
 
@@ -593,11 +614,6 @@ impl Pager {
                 [offset_in_ptrmap_page..offset_in_ptrmap_page + PTRMAP_ENTRY_SIZE],
         )?;
 
-        turso_assert!(
-            ptrmap_page.get().id == ptrmap_pg_no as usize,
-            "ptrmap page has unexpected number"
-        );
-        self.add_dirty(&ptrmap_page);
         Ok(IOResult::Done(()))
     }
 
@@ -880,6 +896,19 @@ impl Pager {
         page_cache.get(&page_key)
     }
 
+    /// Removes a page from the cache, e.g. when the database was truncated before it.
+    pub fn cache_delete(&self, page_idx: usize) -> Result<()> {
+        let mut page_cache = self.page_cache.write();
+        let page_key = PageCacheKey::new(page_idx);
+        if let Some(page) = page_cache.peek(&page_key, false) {
+            page.clear_dirty();
+        }
+        self.dirty_pages.borrow_mut().remove(&page_idx);
+        page_cache.delete(page_key).map_err(|e| {
+            LimboError::InternalError(format!("Failed to remove page from cache: {e:?}"))
+        })
+    }
+
     /// Changes the size of the page cache.
     pub fn change_page_cache_size(&self, capacity: usize) -> Result<CacheResizeResult> {
         let mut page_cache = self.page_cache.write();
@@ -887,12 +916,97 @@ impl Pager {
     }
 
     pub fn add_dirty(&self, page: &Page) {
+        self.save_page_for_savepoints(page);
         // TODO: check duplicates?
         let mut dirty_pages = RefCell::borrow_mut(&self.dirty_pages);
         dirty_pages.insert(page.get().id);
         page.set_dirty();
     }
 
+    /// Saves the contents of a page that is about to be modified in the open savepoints that
+    /// have not saved it yet.
+    fn save_page_for_savepoints(&self, page: &Page) {
+        let mut savepoints = self.savepoints.borrow_mut();
+        let page_id = page.get().id;
+        let is_saved = |savepoint: &Savepoint| {
+            savepoint.pages.contains_key(&page_id) || savepoint.allocated_pages.contains(&page_id)
+        };
+        // A page saved by a savepoint is also saved by the savepoints opened before it.
+        match savepoints.last() {
+            Some(savepoint) if !is_saved(savepoint) => {}
+            _ => return,
+        }
+        let Some(contents) = page.get().contents.as_ref() else {
+            return;
+        };
+        let contents: Arc<[u8]> = Arc::from(&*contents.as_ptr());
+        for savepoint in savepoints
+            .iter_mut()
+            .filter(|savepoint| !is_saved(savepoint))
+        {
+            savepoint.pages.insert(page_id, contents.clone());
+        }
+    }
+
+    /// Opens a savepoint, nested in the savepoints that are already open, and returns its depth.
+    pub fn open_savepoint(&self) -> usize {
+        let mut savepoints = self.savepoints.borrow_mut();
+        savepoints.push(Savepoint::default());
+        savepoints.len() - 1
+    }
+
+    /// Closes the savepoint at `depth` and the savepoints nested in it, keeping their changes.
+    pub fn release_savepoint(&self, depth: usize) {
+        self.savepoints.borrow_mut().truncate(depth);
+    }
+
+    /// Restores the pages to their state when the savepoint at `depth` was opened. The savepoint
+    /// stays open, and the savepoints nested in it are closed.
+    pub fn rollback_to_savepoint(&self, depth: usize) -> Result<()> {
+        let mut savepoints = self.savepoints.borrow_mut();
+        savepoints.truncate(depth + 1);
+        let savepoint = savepoints.last_mut().expect("savepoint must be open");
+        let mut cache = self.page_cache.write();
+        let mut dirty_pages = self.dirty_pages.borrow_mut();
+        for page_id in savepoint.allocated_pages.drain() {
+            let page_key = PageCacheKey::new(page_id);
+            if let Some(page) = cache.peek(&page_key, false) {
+                page.clear_dirty();
+            }
+            dirty_pages.remove(&page_id);
+            cache.delete(page_key).map_err(|e| {
+                LimboError::InternalError(format!("Failed to remove page from cache: {e:?}"))
+            })?;
+        }
+        for (page_id, contents) in savepoint.pages.iter() {
+            let page_key = PageCacheKey::new(*page_id);
+            let page = match cache.peek(&page_key, false) {
+                Some(page) => page,
+                // The page was flushed to the WAL and evicted since it was modified.
+                None => {
+                    let offset = if *page_id == DATABASE_HEADER_PAGE_ID {
+                        DATABASE_HEADER_SIZE
+                    } else {
+                        0
+                    };
+                    let page = allocate_page(*page_id, &self.buffer_pool, offset);
+                    cache.insert(page_key, page.clone()).map_err(|e| {
+                        LimboError::InternalError(format!(
+                            "Failed to insert page into cache: {e:?}"
+                        ))
+                    })?;
+                    page
+                }
+            };
+            let page_contents = page.get_contents();
+            page_contents.as_ptr().copy_from_slice(contents);
+            page_contents.overflow_cells.clear();
+            dirty_pages.insert(*page_id);
+            page.set_dirty();
+        }
+        Ok(())
+    }
+
     pub fn wal_frame_count(&self) -> Result<u64> {
         Ok(self.wal.borrow().get_max_frame_in_wal())
     }
@@ -1493,7 +1607,7 @@ impl Pager {
                 && is_ptrmap_page(new_db_size, header_accessor::get_page_size(self)? as usize)
             {
                 let page = allocate_page(new_db_size as usize, &self.buffer_pool, 0);
-                self.add_dirty(&page);
+                self.add_allocated_page(&page);
 
                 let page_key = PageCacheKey::new(page.get().id);
                 let mut cache = self.page_cache.write();
@@ -1517,7 +1631,7 @@ impl Pager {
         let page = allocate_page(new_db_size as usize, &self.buffer_pool, 0);
         {
             // setup page and add to cache
-            self.add_dirty(&page);
+            self.add_allocated_page(&page);
 
             let page_key = PageCacheKey::new(page.get().id);
             let mut cache = self.page_cache.write();
@@ -1531,6 +1645,15 @@ impl Pager {
         }
     }
 
+    /// Marks a newly allocated page dirty. The open savepoints discard the page on ROLLBACK TO
+    /// instead of saving its contents.
+    fn add_allocated_page(&self, page: &Page) {
+        for savepoint in self.savepoints.borrow_mut().iter_mut() {
+            savepoint.allocated_pages.insert(page.get().id);
+        }
+        self.add_dirty(page);
+    }
+
     pub fn update_dirty_loaded_page_in_cache(
         &self,
         id: usize,
@@ -1565,6 +1688,8 @@ impl Pager {
         connection: &Connection,
     ) -> Result<(), LimboError> {
         tracing::debug!(schema_did_change);
+        self.savepoints.borrow_mut().clear();
+        connection.savepoints.borrow_mut().clear();
         self.dirty_pages.borrow_mut().clear();
         let mut cache = self.page_cache.write();
 
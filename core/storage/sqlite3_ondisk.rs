@@ -1375,6 +1375,7 @@ pub fn read_entire_wal_dumb(file: &Arc<dyn File>) -> Result<Arc<UnsafeCell<WalFi
         frame_cache: Arc::new(SpinLock::new(HashMap::new())),
         pages_in_frames: Arc::new(SpinLock::new(Vec::new())),
         last_checksum: (0, 0),
+        db_size: AtomicU64::new(0),
         file: file.clone(),
         read_locks: [
             LimboRwLock::new(),
@@ -1553,6 +1554,9 @@ pub fn read_entire_wal_dumb(file: &Arc<dyn File>) -> Result<Arc<UnsafeCell<WalFi
                 let is_commit_record = frame_h_db_size > 0;
                 if is_commit_record {
                     wfs_data.max_frame.store(frame_idx, Ordering::SeqCst);
+                    wfs_data
+                        .db_size
+                        .store(frame_h_db_size as u64, Ordering::SeqCst);
                     wfs_data.last_checksum = cumulative_checksum;
                 }
 
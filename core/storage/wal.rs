@@ -363,6 +363,7 @@ pub enum CheckpointState {
     WritePage,
     WaitWritePage,
     Done,
+    WaitTruncateDbFile,
 }
 
 // Checkpointing is a state machine that has multiple steps. Since there are multiple steps we save
@@ -413,6 +414,9 @@ pub struct WalFile {
     min_frame: u64,
     /// Check of last frame in WAL, this is a cumulative checksum over all frames in the WAL
     last_checksum: (u32, u32),
+    /// Database size in pages of the last commit frame appended by this connection, published
+    /// to the shared state when the commit finishes.
+    commit_db_size: u64,
 
     /// Hack for now in case of rollback, will not be needed once we remove this bullshit frame cache.
     start_pages_in_frames: usize,
@@ -460,6 +464,9 @@ pub struct WalFileShared {
     // Another memory inefficient array made to just keep track of pages that are in frame_cache.
     pub pages_in_frames: Arc<SpinLock<Vec<u64>>>,
     pub last_checksum: (u32, u32), // Check of last frame in WAL, this is a cumulative checksum over all frames in the WAL
+    /// Size of the database in pages as of the last commit in the WAL. Once every frame has been
+    /// backfilled the database file is truncated to this size.
+    pub db_size: AtomicU64,
     pub file: Arc<dyn File>,
     /// read_locks is a list of read locks that can coexist with the max_frame number stored in
     /// value. There is a limited amount because and unbounded amount of connections could be
@@ -734,7 +741,7 @@ impl Wal for WalFile {
         let c = Arc::new(Completion::new_write(|_| {}));
         let c = shared.file.pwrite(offset, frame_bytes, c)?;
         self.io.wait_for_completion(c)?;
-        self.complete_append_frame(page_id, frame_id, checksums);
+        self.complete_append_frame(page_id, frame_id, db_size, checksums);
         if db_size > 0 {
             self.finish_append_frames_commit()?;
         }
@@ -791,7 +798,7 @@ impl Wal for WalFile {
             }
             frame_checksums
         };
-        self.complete_append_frame(page_id as u64, frame_id, checksums);
+        self.complete_append_frame(page_id as u64, frame_id, db_size as u64, checksums);
         Ok(())
     }
 
@@ -963,9 +970,27 @@ impl Wal for WalFile {
                     shared
                         .nbackfills
                         .store(self.ongoing_checkpoint.max_frame, Ordering::SeqCst);
+                    let mut truncating = false;
                     if everything_backfilled {
-                        // TODO: Even in Passive mode, if everything was backfilled we should
-                        // truncate and fsync the *db file*
+                        // The database file now holds every committed page, so anything past the
+                        // size of the last commit (e.g. pages dropped by VACUUM) can be cut off.
+                        // The db file is fsynced by the pager once the checkpoint is done.
+                        let db_size = shared.db_size.load(Ordering::SeqCst) as usize;
+                        let db_file_len = db_size * self.page_size() as usize;
+                        if db_size > 0 && pager.db_file.size()? > db_file_len as u64 {
+                            *write_counter.borrow_mut() += 1;
+                            let c = Completion::new_trunc({
+                                let write_counter = write_counter.clone();
+                                move |_| {
+                                    *write_counter.borrow_mut() -= 1;
+                                }
+                            });
+                            if let Err(err) = pager.db_file.truncate(db_file_len, c) {
+                                *write_counter.borrow_mut() -= 1;
+                                return Err(err);
+                            }
+                            truncating = true;
+                        }
 
                         // To properly reset the *wal file* we will need restart and/or truncate mode.
                         // Currently, it will grow the WAL file indefinetly, but don't resetting is better than breaking.
@@ -982,9 +1007,20 @@ impl Wal for WalFile {
                         }
                     }
                     self.prev_checkpoint = checkpoint_result;
+                    if truncating {
+                        self.ongoing_checkpoint.state = CheckpointState::WaitTruncateDbFile;
+                        continue 'checkpoint_loop;
+                    }
                     self.ongoing_checkpoint.state = CheckpointState::Start;
                     return Ok(IOResult::Done(checkpoint_result));
                 }
+                CheckpointState::WaitTruncateDbFile => {
+                    if *write_counter.borrow() > 0 {
+                        return Ok(IOResult::IO);
+                    }
+                    self.ongoing_checkpoint.state = CheckpointState::Start;
+                    return Ok(IOResult::Done(self.prev_checkpoint));
+                }
             }
         }
     }
@@ -1065,6 +1101,7 @@ impl Wal for WalFile {
         shared.max_frame.store(self.max_frame, Ordering::SeqCst);
         tracing::trace!(self.max_frame, ?self.last_checksum);
         shared.last_checksum = self.last_checksum;
+        shared.db_size.store(self.commit_db_size, Ordering::SeqCst);
         Ok(())
     }
 }
@@ -1109,6 +1146,7 @@ impl WalFile {
             min_frame: 0,
             max_frame_read_lock_index: 0,
             last_checksum,
+            commit_db_size: 0,
             prev_checkpoint: CheckpointResult::default(),
             start_pages_in_frames: 0,
             header: *header,
@@ -1131,9 +1169,18 @@ impl WalFile {
         unsafe { self.shared.get().as_mut().unwrap() }
     }
 
-    fn complete_append_frame(&mut self, page_id: u64, frame_id: u64, checksums: (u32, u32)) {
+    fn complete_append_frame(
+        &mut self,
+        page_id: u64,
+        frame_id: u64,
+        db_size: u64,
+        checksums: (u32, u32),
+    ) {
         self.last_checksum = checksums;
         self.max_frame = frame_id;
+        if db_size > 0 {
+            self.commit_db_size = db_size;
+        }
         let shared = self.get_shared();
         {
             let mut frame_cache = shared.frame_cache.lock();
@@ -1231,6 +1278,7 @@ impl WalFileShared {
             nbackfills: AtomicU64::new(0),
             frame_cache: Arc::new(SpinLock::new(HashMap::new())),
             last_checksum: checksum,
+            db_size: AtomicU64::new(0),
             file,
             pages_in_frames: Arc::new(SpinLock::new(Vec::new())),
             read_locks: array::from_fn(|_| LimboRwLock {
@@ -105,6 +105,27 @@ pub fn translate_aggregation_step(
     target_register: usize,
     resolver: &Resolver,
 ) -> Result<usize> {
+    // Rows for which the FILTER condition is not true skip the step, including the distinct check.
+    let label_filtered = match &agg.filter {
+        Some(filter) => {
+            let filter_reg = program.alloc_register();
+            translate_expr(
+                program,
+                Some(referenced_tables),
+                filter,
+                filter_reg,
+                resolver,
+            )?;
+            let label = program.allocate_label();
+            program.emit_insn(Insn::IfNot {
+                reg: filter_reg,
+                target_pc: label,
+                jump_if_null: true,
+            });
+            Some(label)
+        }
+        None => None,
+    };
     let dest = match agg.func {
         AggFunc::Avg => {
             if agg.args.len() != 1 {
@@ -369,5 +390,8 @@ pub fn translate_aggregation_step(
             target_register
         }
     };
+    if let Some(label) = label_filtered {
+        program.preassign_label_to_next_insn(label);
+    }
     Ok(dest)
 }
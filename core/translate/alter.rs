@@ -3,28 +3,38 @@ use std::sync::Arc;
 use turso_sqlite3_parser::{ast, lexer::sql::Parser};
 
 use crate::{
+    error::SQLITE_ERROR,
     function::{AlterTableFunc, Func},
-    schema::{Column, Schema},
-    util::normalize_ident,
+    schema::{CheckConstraint, Column, GeneratedColumn, Index},
+    util::{normalize_ident, quote_ident},
     vdbe::{
-        builder::ProgramBuilder,
-        insn::{Cookie, Insn, RegisterOrLiteral},
+        builder::{CursorType, ProgramBuilder},
+        insn::{Cookie, IdxInsertFlags, Insn, RegisterOrLiteral},
     },
     LimboError, Result, SymbolTable,
 };
 
 use super::{
-    emitter::TransactionMode, schema::SQLITE_TABLEID, update::translate_update_with_after,
+    emitter::TransactionMode, expr::walk_expr_mut, generated::validate_generated_columns,
+    schema::SQLITE_TABLEID, update::translate_update_with_after,
 };
 
 pub fn translate_alter_table(
     alter: (ast::QualifiedName, ast::AlterTableBody),
     syms: &SymbolTable,
-    schema: &Schema,
     mut program: ProgramBuilder,
     connection: &Arc<crate::Connection>,
 ) -> Result<ProgramBuilder> {
     let (table_name, alter_table) = alter;
+    let database_id = connection.resolve_database_id(&table_name)?;
+    let schema = &connection.database_schema(database_id);
+    let schema_table = match database_id {
+        0 => SQLITE_TABLEID.to_string(),
+        _ => format!(
+            "{}.{SQLITE_TABLEID}",
+            quote_ident(&connection.get_database_name(database_id))
+        ),
+    };
     let table_name = table_name.name.as_str();
     if schema.table_has_indexes(table_name) && !schema.indexes_enabled() {
         // Let's disable altering a table with indices altogether instead of checking column by
@@ -78,13 +88,52 @@ pub fn translate_alter_table(
                 )));
             }
 
+            let column_name = normalize_ident(column_name);
+            if btree
+                .checks
+                .iter()
+                .any(|check| check.references_column(&column_name))
+            {
+                return Err(LimboError::ParseError(format!(
+                    "error in table {table_name} after drop column: no such column: {column_name}"
+                )));
+            }
+
+            if btree.columns.iter().any(|column| {
+                column
+                    .generated
+                    .as_ref()
+                    .is_some_and(|generated| generated.referenced_columns().contains(&column_name))
+            }) {
+                return Err(LimboError::ParseError(format!(
+                    "error in table {table_name} after drop column: no such column: {column_name}"
+                )));
+            }
+
+            if let Some(index) = schema
+                .get_indices(table_name)
+                .iter()
+                .find(|index| index.referenced_columns(&btree).contains(&dropped_index))
+            {
+                return Err(LimboError::ParseError(format!(
+                    "error in index {} after drop column: no such column: {column_name}",
+                    index.name
+                )));
+            }
+
+            // The records only hold the columns that are not VIRTUAL.
+            let stored_count = btree.columns.iter().filter(|col| !col.is_virtual()).count();
+            let dropped_storage_position = btree.column_storage_position(dropped_index);
+            // The rows of a table without rowid are the entries of its clustered index
+            let clustered_index = schema.get_clustered_index(&original_btree).cloned();
+
             btree.columns.remove(dropped_index);
 
-            let sql = btree.to_sql();
+            let sql = btree.to_sql().replace('\'', "''");
 
             let stmt = format!(
                 r#"
-                    UPDATE {SQLITE_TABLEID}
+                    UPDATE {schema_table}
                     SET sql = '{sql}'
                     WHERE name = '{table_name}' COLLATE NOCASE AND type = 'table'
                 "#,
@@ -95,25 +144,27 @@ pub fn translate_alter_table(
                 unreachable!();
             };
 
-            translate_update_with_after(
-                schema,
-                &mut update,
-                syms,
-                program,
-                connection,
-                |program| {
-                    let column_count = btree.columns.len();
+            translate_update_with_after(&mut update, syms, program, connection, |program| {
+                if let Some(clustered_index) = clustered_index {
+                    emit_drop_clustered_index_column(
+                        program,
+                        &clustered_index,
+                        dropped_index,
+                        database_id,
+                        schema.schema_version,
+                    );
+                } else {
                     let root_page = btree.root_page;
+                    let column_count =
+                        stored_count - usize::from(dropped_storage_position.is_some());
                     let table_name = btree.name.clone();
 
-                    let cursor_id = program.alloc_cursor_id(
-                        crate::vdbe::builder::CursorType::BTreeTable(original_btree),
-                    );
+                    let cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(original_btree));
 
                     program.emit_insn(Insn::OpenWrite {
                         cursor_id,
                         root_page: RegisterOrLiteral::Literal(root_page),
-                        db: 0,
+                        db: database_id,
                     });
 
                     program.cursor_loop(cursor_id, |program, rowid| {
@@ -121,8 +172,8 @@ pub fn translate_alter_table(
 
                         let mut iter = first_column;
 
-                        for i in 0..(column_count + 1) {
-                            if i == dropped_index {
+                        for i in 0..stored_count {
+                            if Some(i) == dropped_storage_position {
                                 continue;
                             }
 
@@ -140,7 +191,7 @@ pub fn translate_alter_table(
                             index_name: None,
                         });
                         program.emit_insn(Insn::SetCookie {
-                            db: 0,
+                            db: database_id,
                             cookie: Cookie::SchemaVersion,
                             value: schema.schema_version as i32 + 1,
                             p5: 0,
@@ -154,15 +205,35 @@ pub fn translate_alter_table(
                             table_name: table_name.clone(),
                         });
                     });
+                }
 
-                    program.emit_insn(Insn::ParseSchema {
-                        db: usize::MAX, // TODO: This value is unused, change when we do something with it
-                        where_clause: None,
-                    })
-                },
-            )?
+                program.emit_insn(Insn::ParseSchema {
+                    db: database_id,
+                    where_clause: None,
+                })
+            })?
         }
         ast::AlterTableBody::AddColumn(col_def) => {
+            let column_name = normalize_ident(col_def.col_name.as_str());
+            let mut stored = false;
+            for constraint in &col_def.constraints {
+                match &constraint.constraint {
+                    ast::ColumnConstraint::Check(expr) => btree
+                        .checks
+                        .push(CheckConstraint::new(constraint.name.clone(), expr.clone())),
+                    ast::ColumnConstraint::NotNull {
+                        conflict_clause: Some(conflict_clause),
+                        ..
+                    } => btree
+                        .notnull_conflict_clauses
+                        .push((column_name.clone(), *conflict_clause)),
+                    ast::ColumnConstraint::Generated { expr, typ } => {
+                        stored =
+                            GeneratedColumn::new(&column_name, expr.clone(), typ.as_ref())?.stored;
+                    }
+                    _ => {}
+                }
+            }
             let column = Column::from(col_def);
 
             if let Some(default) = &column.default {
@@ -184,6 +255,7 @@ pub fn translate_alter_table(
             }
 
             btree.columns.push(column);
+            validate_generated_columns(&btree)?;
 
             let sql = btree.to_sql();
             let mut escaped = String::with_capacity(sql.len());
@@ -197,7 +269,7 @@ pub fn translate_alter_table(
 
             let stmt = format!(
                 r#"
-                    UPDATE {SQLITE_TABLEID}
+                    UPDATE {schema_table}
                     SET sql = '{escaped}'
                     WHERE name = '{table_name}' COLLATE NOCASE AND type = 'table'
                 "#,
@@ -208,25 +280,41 @@ pub fn translate_alter_table(
                 unreachable!();
             };
 
-            translate_update_with_after(
-                schema,
-                &mut update,
-                syms,
-                program,
-                connection,
-                |program| {
-                    program.emit_insn(Insn::SetCookie {
-                        db: 0,
-                        cookie: Cookie::SchemaVersion,
-                        value: schema.schema_version as i32 + 1,
-                        p5: 0,
+            translate_update_with_after(&mut update, syms, program, connection, |program| {
+                // Like SQLite, a STORED column can only be added to an empty table, as the
+                // existing rows would lack its value.
+                if stored {
+                    let cursor_id = program.alloc_cursor_id(
+                        crate::vdbe::builder::CursorType::BTreeTable(original_btree.clone()),
+                    );
+                    program.emit_insn(Insn::OpenRead {
+                        cursor_id,
+                        root_page: original_btree.root_page,
+                        db: database_id,
+                    });
+                    let empty_label = program.allocate_label();
+                    program.emit_insn(Insn::Rewind {
+                        cursor_id,
+                        pc_if_empty: empty_label,
                     });
-                    program.emit_insn(Insn::ParseSchema {
-                        db: usize::MAX, // TODO: This value is unused, change when we do something with it
-                        where_clause: None,
+                    program.emit_insn(Insn::Halt {
+                        err_code: SQLITE_ERROR,
+                        on_error: ast::ResolveType::Abort,
+                        description: "cannot add a STORED column".to_string(),
                     });
-                },
-            )?
+                    program.preassign_label_to_next_insn(empty_label);
+                }
+                program.emit_insn(Insn::SetCookie {
+                    db: database_id,
+                    cookie: Cookie::SchemaVersion,
+                    value: schema.schema_version as i32 + 1,
+                    p5: 0,
+                });
+                program.emit_insn(Insn::ParseSchema {
+                    db: database_id,
+                    where_clause: None,
+                });
+            })?
         }
         ast::AlterTableBody::RenameColumn { old, new } => {
             let rename_from = old.as_str();
@@ -255,7 +343,7 @@ pub fn translate_alter_table(
             program.emit_insn(Insn::OpenWrite {
                 cursor_id,
                 root_page: RegisterOrLiteral::Literal(sqlite_schema.root_page),
-                db: 0,
+                db: database_id,
             });
 
             program.cursor_loop(cursor_id, |program, rowid| {
@@ -308,13 +396,13 @@ pub fn translate_alter_table(
             });
 
             program.emit_insn(Insn::SetCookie {
-                db: 0,
+                db: database_id,
                 cookie: Cookie::SchemaVersion,
                 value: schema.schema_version as i32 + 1,
                 p5: 0,
             });
             program.emit_insn(Insn::ParseSchema {
-                db: usize::MAX, // TODO: This value is unused, change when we do something with it
+                db: database_id,
                 where_clause: None,
             });
 
@@ -342,7 +430,7 @@ pub fn translate_alter_table(
             program.emit_insn(Insn::OpenWrite {
                 cursor_id,
                 root_page: RegisterOrLiteral::Literal(sqlite_schema.root_page),
-                db: 0,
+                db: database_id,
             });
 
             program.cursor_loop(cursor_id, |program, rowid| {
@@ -392,14 +480,14 @@ pub fn translate_alter_table(
             });
 
             program.emit_insn(Insn::SetCookie {
-                db: 0,
+                db: database_id,
                 cookie: Cookie::SchemaVersion,
                 value: schema.schema_version as i32 + 1,
                 p5: 0,
             });
 
             program.emit_insn(Insn::ParseSchema {
-                db: usize::MAX, // TODO: This value is unused, change when we do something with it
+                db: database_id,
                 where_clause: None,
             });
 
@@ -409,3 +497,485 @@ pub fn translate_alter_table(
         }
     })
 }
+
+/// Rewrites the entries of the clustered index a WITHOUT ROWID table is stored in without the
+/// column at `dropped_pos_in_table`. The primary key columns the entries start with cannot be
+/// dropped, so each entry keeps its place in the index.
+fn emit_drop_clustered_index_column(
+    program: &mut ProgramBuilder,
+    clustered_index: &Arc<Index>,
+    dropped_pos_in_table: usize,
+    database_id: usize,
+    schema_version: u32,
+) {
+    let dropped_position = clustered_index
+        .column_table_pos_to_index_pos(dropped_pos_in_table)
+        .expect("clustered index must store all the columns of the table");
+    let column_count = clustered_index.columns.len() - 1;
+    let cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(clustered_index.clone()));
+    program.emit_insn(Insn::OpenWrite {
+        cursor_id,
+        root_page: RegisterOrLiteral::Literal(clustered_index.root_page),
+        db: database_id,
+    });
+    program.emit_insn(Insn::SetCookie {
+        db: database_id,
+        cookie: Cookie::SchemaVersion,
+        value: schema_version as i32 + 1,
+        p5: 0,
+    });
+
+    let loop_start_label = program.allocate_label();
+    let loop_end_label = program.allocate_label();
+    program.emit_insn(Insn::Rewind {
+        cursor_id,
+        pc_if_empty: loop_end_label,
+    });
+    program.preassign_label_to_next_insn(loop_start_label);
+    let first_column = program.alloc_registers(column_count);
+    for (i, position) in (0..clustered_index.columns.len())
+        .filter(|position| *position != dropped_position)
+        .enumerate()
+    {
+        program.emit_column(cursor_id, position, first_column + i);
+    }
+    let record = program.alloc_register();
+    program.emit_insn(Insn::MakeRecord {
+        start_reg: first_column,
+        count: column_count,
+        dest_reg: record,
+        index_name: Some(clustered_index.name.clone()),
+    });
+    program.emit_insn(Insn::Delete { cursor_id });
+    program.emit_insn(Insn::IdxInsert {
+        cursor_id,
+        record_reg: record,
+        unpacked_start: Some(first_column),
+        unpacked_count: Some(column_count as u16),
+        flags: IdxInsertFlags::new(),
+    });
+    program.emit_insn(Insn::Next {
+        cursor_id,
+        pc_if_next: loop_start_label,
+    });
+    program.preassign_label_to_next_insn(loop_end_label);
+}
+
+/// Renames the references to table `from` in `select` to `to`, leaving the references to the
+/// CTEs in scope, listed in `ctes`, untouched.
+pub(crate) fn rename_select_table(
+    select: &mut ast::Select,
+    from: &str,
+    to: &str,
+    ctes: &mut Vec<String>,
+) -> Result<()> {
+    let scope = ctes.len();
+    if let Some(with) = &mut select.with {
+        for cte in &mut with.ctes {
+            ctes.push(normalize_ident(cte.tbl_name.as_str()));
+            rename_select_table(&mut cte.select, from, to, ctes)?;
+        }
+    }
+    let compounds = select.body.compounds.iter_mut().flatten();
+    for one in std::iter::once(&mut select.body.select).chain(compounds.map(|c| &mut c.select)) {
+        match one.as_mut() {
+            ast::OneSelect::Select(one) => {
+                if let Some(from_clause) = &mut one.from {
+                    rename_from_table(from_clause, from, to, ctes)?;
+                }
+                let columns = one.columns.iter_mut().filter_map(|column| match column {
+                    ast::ResultColumn::Expr(expr, _) => Some(expr),
+                    _ => None,
+                });
+                let group_by =
+                    one.group_by
+                        .iter_mut()
+                        .flat_map(|ast::GroupBy { exprs, having }| {
+                            exprs.iter_mut().chain(having.as_deref_mut())
+                        });
+                for expr in columns.chain(one.where_clause.as_mut()).chain(group_by) {
+                    rename_expr_table(expr, from, to, ctes)?;
+                }
+            }
+            ast::OneSelect::Values(rows) => {
+                for expr in rows.iter_mut().flatten() {
+                    rename_expr_table(expr, from, to, ctes)?;
+                }
+            }
+        }
+    }
+    let order_by = select.order_by.iter_mut().flatten().map(|c| &mut c.expr);
+    let limit = select
+        .limit
+        .iter_mut()
+        .flat_map(|limit| std::iter::once(&mut limit.expr).chain(limit.offset.as_mut()));
+    for expr in order_by.chain(limit) {
+        rename_expr_table(expr, from, to, ctes)?;
+    }
+    ctes.truncate(scope);
+    Ok(())
+}
+
+fn rename_from_table(
+    from_clause: &mut ast::FromClause,
+    from: &str,
+    to: &str,
+    ctes: &mut Vec<String>,
+) -> Result<()> {
+    let joins = from_clause.joins.iter_mut().flatten();
+    for table in from_clause
+        .select
+        .as_deref_mut()
+        .into_iter()
+        .chain(joins.map(|j| &mut j.table))
+    {
+        match table {
+            ast::SelectTable::Table(name, ..) | ast::SelectTable::TableCall(name, ..) => {
+                let table_name = normalize_ident(name.name.as_str());
+                if name.db_name.is_none() && table_name == from && !ctes.contains(&table_name) {
+                    name.name = ast::Name::from_str(to);
+                }
+            }
+            ast::SelectTable::Select(select, _) => rename_select_table(select, from, to, ctes)?,
+            ast::SelectTable::Sub(from_clause, _) => {
+                rename_from_table(from_clause, from, to, ctes)?
+            }
+        }
+    }
+    for join in from_clause.joins.iter_mut().flatten() {
+        if let Some(ast::JoinConstraint::On(expr)) = &mut join.constraint {
+            rename_expr_table(expr, from, to, ctes)?;
+        }
+    }
+    Ok(())
+}
+
+fn rename_expr_table(
+    expr: &mut ast::Expr,
+    from: &str,
+    to: &str,
+    ctes: &mut Vec<String>,
+) -> Result<()> {
+    walk_expr_mut(expr, &mut |expr: &mut ast::Expr| -> Result<()> {
+        match expr {
+            ast::Expr::Qualified(table, _) | ast::Expr::DoublyQualified(_, table, _) => {
+                let table_name = normalize_ident(table.as_str());
+                if table_name == from && !ctes.contains(&table_name) {
+                    *table = ast::Name::from_str(to);
+                }
+            }
+            ast::Expr::InTable { rhs, .. } => {
+                let table_name = normalize_ident(rhs.name.as_str());
+                if rhs.db_name.is_none() && table_name == from && !ctes.contains(&table_name) {
+                    rhs.name = ast::Name::from_str(to);
+                }
+            }
+            ast::Expr::Exists(select) | ast::Expr::Subquery(select) => {
+                rename_select_table(select, from, to, ctes)?
+            }
+            ast::Expr::InSelect { rhs, .. } => rename_select_table(rhs, from, to, ctes)?,
+            _ => {}
+        }
+        Ok(())
+    })
+}
+
+/// Renames the table `trigger` fires on and the references to it in the trigger body.
+pub(crate) fn rename_trigger_table(
+    trigger: &mut ast::CreateTrigger,
+    from: &str,
+    to: &str,
+) -> Result<()> {
+    let rename = |name: &mut ast::Name| {
+        if normalize_ident(name.as_str()) == from {
+            *name = ast::Name::from_str(to);
+        }
+    };
+    if trigger.tbl_name.db_name.is_none() {
+        rename(&mut trigger.tbl_name.name);
+    }
+    let ctes = &mut vec![];
+    if let Some(expr) = &mut trigger.when_clause {
+        rename_expr_table(expr, from, to, ctes)?;
+    }
+    for command in &mut trigger.commands {
+        match command {
+            ast::TriggerCmd::Update(update) => {
+                rename(&mut update.tbl_name);
+                if let Some(from_clause) = &mut update.from {
+                    rename_from_table(from_clause, from, to, ctes)?;
+                }
+                let sets = update.sets.iter_mut().map(|set| &mut set.expr);
+                for expr in sets.chain(update.where_clause.as_mut()) {
+                    rename_expr_table(expr, from, to, ctes)?;
+                }
+            }
+            ast::TriggerCmd::Insert(insert) => {
+                rename(&mut insert.tbl_name);
+                rename_select_table(&mut insert.select, from, to, ctes)?;
+                let mut upsert = insert.upsert.as_mut();
+                while let Some(ast::Upsert {
+                    index,
+                    do_clause,
+                    next,
+                }) = upsert
+                {
+                    let targets = index.iter_mut().flat_map(|index| {
+                        let targets = index.targets.iter_mut().map(|target| &mut target.expr);
+                        targets.chain(index.where_clause.as_mut())
+                    });
+                    let sets = match do_clause.as_mut() {
+                        ast::UpsertDo::Set { sets, where_clause } => {
+                            let exprs = sets.iter_mut().map(|set| &mut set.expr);
+                            exprs.chain(where_clause.as_mut()).collect()
+                        }
+                        ast::UpsertDo::Nothing => vec![],
+                    };
+                    for expr in targets.chain(sets) {
+                        rename_expr_table(expr, from, to, ctes)?;
+                    }
+                    upsert = next.as_deref_mut();
+                }
+            }
+            ast::TriggerCmd::Delete(delete) => {
+                rename(&mut delete.tbl_name);
+                if let Some(expr) = &mut delete.where_clause {
+                    rename_expr_table(expr, from, to, ctes)?;
+                }
+            }
+            ast::TriggerCmd::Select(select) => rename_select_table(select, from, to, ctes)?,
+        }
+    }
+    Ok(())
+}
+
+/// Calls `func` on every expression in `select`, including the ones in its subqueries.
+fn walk_select_exprs_mut<F>(select: &mut ast::Select, func: &mut F) -> Result<()>
+where
+    F: FnMut(&mut ast::Expr) -> Result<()>,
+{
+    if let Some(with) = &mut select.with {
+        for cte in &mut with.ctes {
+            walk_select_exprs_mut(&mut cte.select, func)?;
+        }
+    }
+    let compounds = select.body.compounds.iter_mut().flatten();
+    for one in std::iter::once(&mut select.body.select).chain(compounds.map(|c| &mut c.select)) {
+        match one.as_mut() {
+            ast::OneSelect::Select(one) => {
+                if let Some(from) = &mut one.from {
+                    walk_from_exprs_mut(from, func)?;
+                }
+                let columns = one.columns.iter_mut().filter_map(|column| match column {
+                    ast::ResultColumn::Expr(expr, _) => Some(expr),
+                    _ => None,
+                });
+                let group_by =
+                    one.group_by
+                        .iter_mut()
+                        .flat_map(|ast::GroupBy { exprs, having }| {
+                            exprs.iter_mut().chain(having.as_deref_mut())
+                        });
+                for expr in columns.chain(one.where_clause.as_mut()).chain(group_by) {
+                    walk_subquery_exprs_mut(expr, func)?;
+                }
+            }
+            ast::OneSelect::Values(rows) => {
+                for expr in rows.iter_mut().flatten() {
+                    walk_subquery_exprs_mut(expr, func)?;
+                }
+            }
+        }
+    }
+    let order_by = select.order_by.iter_mut().flatten().map(|c| &mut c.expr);
+    let limit = select
+        .limit
+        .iter_mut()
+        .flat_map(|limit| std::iter::once(&mut limit.expr).chain(limit.offset.as_mut()));
+    for expr in order_by.chain(limit) {
+        walk_subquery_exprs_mut(expr, func)?;
+    }
+    Ok(())
+}
+
+fn walk_from_exprs_mut<F>(from: &mut ast::FromClause, func: &mut F) -> Result<()>
+where
+    F: FnMut(&mut ast::Expr) -> Result<()>,
+{
+    let joins = from.joins.iter_mut().flatten();
+    for table in from
+        .select
+        .as_deref_mut()
+        .into_iter()
+        .chain(joins.map(|j| &mut j.table))
+    {
+        match table {
+            ast::SelectTable::Table(..) => {}
+            ast::SelectTable::TableCall(_, args, _) => {
+                for expr in args.iter_mut().flatten() {
+                    walk_subquery_exprs_mut(expr, func)?;
+                }
+            }
+            ast::SelectTable::Select(select, _) => walk_select_exprs_mut(select, func)?,
+            ast::SelectTable::Sub(from, _) => walk_from_exprs_mut(from, func)?,
+        }
+    }
+    for join in from.joins.iter_mut().flatten() {
+        if let Some(ast::JoinConstraint::On(expr)) = &mut join.constraint {
+            walk_subquery_exprs_mut(expr, func)?;
+        }
+    }
+    Ok(())
+}
+
+/// Like [walk_expr_mut], but also walks the expressions of the subqueries in `expr`.
+fn walk_subquery_exprs_mut<F>(expr: &mut ast::Expr, func: &mut F) -> Result<()>
+where
+    F: FnMut(&mut ast::Expr) -> Result<()>,
+{
+    walk_expr_mut(expr, &mut |expr: &mut ast::Expr| -> Result<()> {
+        func(expr)?;
+        match expr {
+            ast::Expr::Exists(select) | ast::Expr::Subquery(select) => {
+                walk_select_exprs_mut(select, func)
+            }
+            ast::Expr::InSelect { rhs, .. } => walk_select_exprs_mut(rhs, func),
+            _ => Ok(()),
+        }
+    })
+}
+
+fn rename_distinct_names(names: &mut ast::DistinctNames, from: &str, to: &str) {
+    if !names
+        .iter()
+        .any(|name| normalize_ident(name.as_str()) == from)
+    {
+        return;
+    }
+    let mut renamed = names.iter().map(|name| {
+        if normalize_ident(name.as_str()) == from {
+            ast::Name::from_str(to)
+        } else {
+            name.clone()
+        }
+    });
+    let mut new_names = ast::DistinctNames::single(renamed.next().unwrap());
+    for name in renamed {
+        // `to` is not a column of the table yet, so it can't collide with the other names.
+        new_names
+            .insert(name)
+            .expect("renamed column names should be distinct");
+    }
+    *names = new_names;
+}
+
+fn rename_qualified_column(expr: &mut ast::Expr, table: &str, from: &str, to: &str) -> Result<()> {
+    if let ast::Expr::Qualified(qualifier, column) = expr {
+        let qualifier = normalize_ident(qualifier.as_str());
+        if (matches!(qualifier.as_str(), "new" | "old") || qualifier == table)
+            && normalize_ident(column.as_str()) == from
+        {
+            *column = ast::Name::from_str(to);
+        }
+    }
+    Ok(())
+}
+
+/// Renames column `from` of `table` to `to` in a trigger on `table`: in its UPDATE OF list, in
+/// the NEW and OLD references and in the body commands writing `table`.
+pub(crate) fn rename_trigger_column(
+    trigger: &mut ast::CreateTrigger,
+    table: &str,
+    from: &str,
+    to: &str,
+) -> Result<()> {
+    if let ast::TriggerEvent::UpdateOf(columns) = &mut trigger.event {
+        rename_distinct_names(columns, from, to);
+    }
+    // Unqualified names only refer to `table` in the commands writing it, outside of their
+    // subqueries.
+    let rename = |expr: &mut ast::Expr, unqualified: bool| -> Result<()> {
+        if unqualified {
+            walk_expr_mut(expr, &mut |expr: &mut ast::Expr| -> Result<()> {
+                if let ast::Expr::Id(column) = expr {
+                    if normalize_ident(column.as_str()) == from {
+                        *column = ast::Name::from_str(to);
+                    }
+                }
+                Ok(())
+            })?;
+        }
+        walk_subquery_exprs_mut(expr, &mut |expr| {
+            rename_qualified_column(expr, table, from, to)
+        })
+    };
+    if let Some(expr) = &mut trigger.when_clause {
+        rename(expr, false)?;
+    }
+    for command in &mut trigger.commands {
+        match command {
+            ast::TriggerCmd::Update(update) => {
+                let writes_table = normalize_ident(update.tbl_name.as_str()) == table;
+                for set in &mut update.sets {
+                    if writes_table {
+                        rename_distinct_names(&mut set.col_names, from, to);
+                    }
+                    rename(&mut set.expr, writes_table)?;
+                }
+                if let Some(from_clause) = &mut update.from {
+                    walk_from_exprs_mut(from_clause, &mut |expr| {
+                        rename_qualified_column(expr, table, from, to)
+                    })?;
+                }
+                if let Some(expr) = &mut update.where_clause {
+                    rename(expr, writes_table)?;
+                }
+            }
+            ast::TriggerCmd::Insert(insert) => {
+                let writes_table = normalize_ident(insert.tbl_name.as_str()) == table;
+                if let Some(columns) = insert.col_names.as_mut().filter(|_| writes_table) {
+                    rename_distinct_names(columns, from, to);
+                }
+                walk_select_exprs_mut(&mut insert.select, &mut |expr| {
+                    rename_qualified_column(expr, table, from, to)
+                })?;
+                let mut upsert = insert.upsert.as_mut();
+                while let Some(ast::Upsert {
+                    index,
+                    do_clause,
+                    next,
+                }) = upsert
+                {
+                    let targets = index.iter_mut().flat_map(|index| {
+                        let targets = index.targets.iter_mut().map(|target| &mut target.expr);
+                        targets.chain(index.where_clause.as_mut())
+                    });
+                    let sets = match do_clause.as_mut() {
+                        ast::UpsertDo::Set { sets, where_clause } => {
+                            for set in sets.iter_mut().filter(|_| writes_table) {
+                                rename_distinct_names(&mut set.col_names, from, to);
+                            }
+                            let exprs = sets.iter_mut().map(|set| &mut set.expr);
+                            exprs.chain(where_clause.as_mut()).collect()
+                        }
+                        ast::UpsertDo::Nothing => vec![],
+                    };
+                    for expr in targets.chain(sets) {
+                        rename(expr, writes_table)?;
+                    }
+                    upsert = next.as_deref_mut();
+                }
+            }
+            ast::TriggerCmd::Delete(delete) => {
+                let writes_table = normalize_ident(delete.tbl_name.as_str()) == table;
+                if let Some(expr) = &mut delete.where_clause {
+                    rename(expr, writes_table)?;
+                }
+            }
+            ast::TriggerCmd::Select(select) => walk_select_exprs_mut(select, &mut |expr| {
+                rename_qualified_column(expr, table, from, to)
+            })?,
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,760 @@
+use std::rc::Rc;
+use std::sync::Arc;
+
+use turso_sqlite3_parser::ast;
+
+use crate::analyze::{STAT1_TABLE_NAME, STAT1_TABLE_SQL, STAT4_TABLE_NAME, STAT4_TABLE_SQL};
+use crate::schema::{Affinity, BTreeTable, Index, Schema};
+use crate::storage::pager::CreateBTreeFlags;
+use crate::translate::emitter::TransactionMode;
+use crate::translate::schema::{emit_schema_entry, SchemaEntryType, SQLITE_TABLEID};
+use crate::util::normalize_ident;
+use crate::vdbe::builder::{CursorType, ProgramBuilder, ProgramBuilderOpts};
+use crate::vdbe::insn::{CmpInsFlags, Cookie, InsertFlags, Insn, RegisterOrLiteral};
+use crate::vdbe::BranchOffset;
+use crate::{bail_parse_error, Connection, Result};
+
+/// The old statistics ANALYZE replaces in the stat tables.
+#[derive(Debug, Clone)]
+pub enum StatScope {
+    /// All the statistics of the database.
+    Database,
+    /// The statistics of a table and its indexes.
+    Table(String),
+    /// The statistics of an index.
+    Index(String),
+}
+
+/// Translate ANALYZE [schema-name | table-or-index-name | schema-name.table-or-index-name]
+///
+/// Like in SQLite, the statistics of the analyzed indexes are written to sqlite_stat1 and
+/// sqlite_stat4, which are created if they do not exist yet, and reloaded into the schema.
+pub fn translate_analyze(
+    target: Option<ast::QualifiedName>,
+    connection: &Arc<Connection>,
+    mut program: ProgramBuilder,
+) -> Result<ProgramBuilder> {
+    if connection._db.mv_store.is_some() {
+        bail_parse_error!("ANALYZE is not supported in MVCC mode");
+    }
+    program.extend(&ProgramBuilderOpts {
+        num_cursors: 4,
+        approx_num_insns: 60,
+        approx_num_labels: 10,
+    });
+
+    let databases: Vec<(usize, StatScope)> = match target {
+        // Like in SQLite, all databases but temp are analyzed
+        None => connection
+            .list_all_databases()
+            .into_iter()
+            .filter(|(database_id, ..)| *database_id != 1)
+            .map(|(database_id, ..)| (database_id, StatScope::Database))
+            .collect(),
+        Some(name) => {
+            let object_name = normalize_ident(name.name.as_str());
+            let database_id = match &name.db_name {
+                None => {
+                    if let Some((database_id, ..)) = connection
+                        .list_all_databases()
+                        .into_iter()
+                        .find(|(_, database_name, _)| *database_name == object_name)
+                    {
+                        analyze_databases(
+                            &mut program,
+                            connection,
+                            &[(database_id, StatScope::Database)],
+                        )?;
+                        program.epilogue(TransactionMode::Write);
+                        return Ok(program);
+                    }
+                    connection.resolve_database_id(&name)?
+                }
+                Some(_) => connection.resolve_database_id(&name)?,
+            };
+            let schema = connection.database_schema(database_id);
+            let scope = if schema.get_index_by_name(&object_name).is_some() {
+                StatScope::Index(object_name)
+            } else if schema.get_btree_table(&object_name).is_some() {
+                StatScope::Table(object_name)
+            } else {
+                bail_parse_error!("no such table: {}", name.name.as_str());
+            };
+            vec![(database_id, scope)]
+        }
+    };
+    analyze_databases(&mut program, connection, &databases)?;
+    program.epilogue(TransactionMode::Write);
+    Ok(program)
+}
+
+/// Emits the analysis of the tables and indexes in `scope` for each database.
+pub fn analyze_databases(
+    program: &mut ProgramBuilder,
+    connection: &Arc<Connection>,
+    databases: &[(usize, StatScope)],
+) -> Result<()> {
+    for (database_id, scope) in databases {
+        let schema = connection.database_schema(*database_id);
+        let mut targets: Vec<(Rc<BTreeTable>, Option<Arc<Index>>)> = match scope {
+            StatScope::Database => schema
+                .tables
+                .values()
+                .filter_map(|table| table.btree())
+                .filter(|table| !table.name.starts_with("sqlite_"))
+                .map(|table| (table, None))
+                .collect(),
+            StatScope::Table(table_name) => {
+                let table = schema.get_btree_table(table_name).unwrap();
+                if table.name.starts_with("sqlite_") {
+                    vec![]
+                } else {
+                    vec![(table, None)]
+                }
+            }
+            StatScope::Index(index_name) => {
+                let index = schema.get_index_by_name(index_name).unwrap().clone();
+                let table = schema.get_btree_table(&index.table_name).unwrap();
+                vec![(table, Some(index))]
+            }
+        };
+        targets.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name));
+        emit_analyze_database(
+            program,
+            &schema,
+            *database_id,
+            scope,
+            &targets,
+            connection.get_analysis_limit(),
+        );
+    }
+    Ok(())
+}
+
+/// The analysis limit `PRAGMA optimize` uses with the 0x10 mask bit, unless the connection's
+/// analysis limit is lower, SQLITE_DEFAULT_OPTIMIZE_LIMIT in SQLite.
+const OPTIMIZE_ANALYSIS_LIMIT: u64 = 2000;
+
+/// Translate PRAGMA optimize[(mask)]
+///
+/// Like in SQLite, the tables with an index that was never analyzed are analyzed if they have
+/// rows, and the analyzed tables are reanalyzed if their size changed tenfold since. With the
+/// 0x01 mask bit, the ANALYZE statements are returned instead of run.
+pub fn translate_optimize(
+    mask: u64,
+    connection: &Arc<Connection>,
+    program: &mut ProgramBuilder,
+) -> Result<TransactionMode> {
+    if mask & 0x02 == 0 {
+        return Ok(TransactionMode::None);
+    }
+    let debug = mask & 0x01 != 0;
+    let limit = match connection.get_analysis_limit() {
+        limit if mask & 0x10 == 0 || (limit > 0 && limit < OPTIMIZE_ANALYSIS_LIMIT) => limit,
+        _ => OPTIMIZE_ANALYSIS_LIMIT,
+    };
+
+    let mut mode = TransactionMode::None;
+    for (database_id, database_name, _) in connection.list_all_databases() {
+        if database_id == 1 {
+            continue;
+        }
+        let schema = connection.database_schema(database_id);
+        let mut tables: Vec<(Rc<BTreeTable>, Option<u64>)> = schema
+            .tables
+            .values()
+            .filter_map(|table| table.btree())
+            .filter(|table| !table.name.starts_with("sqlite_"))
+            .filter(|table| !schema.get_indices(&table.name).is_empty())
+            .map(|table| {
+                // The number of rows of the table when it was analyzed, unless an index lacks
+                // statistics
+                let stats = schema.get_table_stats(&table.name);
+                let analyzed_rows = stats
+                    .filter(|stats| {
+                        schema
+                            .get_indices(&table.name)
+                            .iter()
+                            .all(|index| stats.indexes.contains_key(&index.name))
+                    })
+                    .map(|stats| stats.row_count);
+                (table, analyzed_rows)
+            })
+            .collect();
+        if tables.is_empty() {
+            continue;
+        }
+        tables.sort_by(|(a, _), (b, _)| a.name.cmp(&b.name));
+        if debug {
+            mode = TransactionMode::Read;
+        } else {
+            if connection._db.mv_store.is_some() {
+                bail_parse_error!("ANALYZE is not supported in MVCC mode");
+            }
+            mode = TransactionMode::Write;
+        }
+
+        let stat_tables = (!debug).then(|| StatTables::open(program, &schema, database_id));
+        for (table, analyzed_rows) in tables {
+            let skip_label = program.allocate_label();
+            let cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(table.clone()));
+            program.emit_insn(Insn::OpenRead {
+                cursor_id,
+                root_page: table.root_page,
+                db: database_id,
+            });
+            match analyzed_rows {
+                // Skip the table unless its size changed tenfold
+                Some(analyzed_rows) => {
+                    let count_reg = program.alloc_register();
+                    let bound_reg = program.alloc_register();
+                    let analyze_label = program.allocate_label();
+                    program.emit_insn(Insn::Count {
+                        cursor_id,
+                        target_reg: count_reg,
+                        exact: true,
+                    });
+                    program.emit_int((analyzed_rows * 10) as i64, bound_reg);
+                    program.emit_insn(Insn::Ge {
+                        lhs: count_reg,
+                        rhs: bound_reg,
+                        target_pc: analyze_label,
+                        flags: CmpInsFlags::default(),
+                        collation: None,
+                    });
+                    // Like in SQLite, a small table is not reanalyzed when it shrinks
+                    if analyzed_rows >= 10 {
+                        program.emit_int((analyzed_rows / 10) as i64, bound_reg);
+                        program.emit_insn(Insn::Gt {
+                            lhs: count_reg,
+                            rhs: bound_reg,
+                            target_pc: skip_label,
+                            flags: CmpInsFlags::default(),
+                            collation: None,
+                        });
+                    } else {
+                        program.emit_insn(Insn::Goto {
+                            target_pc: skip_label,
+                        });
+                    }
+                    program.preassign_label_to_next_insn(analyze_label);
+                }
+                // Skip the table if it is empty
+                None => program.emit_insn(Insn::Rewind {
+                    cursor_id,
+                    pc_if_empty: skip_label,
+                }),
+            }
+            match &stat_tables {
+                None => {
+                    let reg = program.emit_string8_new_reg(format!(
+                        "ANALYZE \"{}\".\"{}\"",
+                        database_name.replace('"', "\"\""),
+                        table.name.replace('"', "\"\"")
+                    ));
+                    program.emit_result_row(reg, 1);
+                }
+                Some(stat_tables) => {
+                    stat_tables.emit_delete_rows(program, &StatScope::Table(table.name.clone()));
+                    emit_analyze_table(
+                        program,
+                        &schema,
+                        database_id,
+                        &table,
+                        None,
+                        stat_tables,
+                        limit,
+                    );
+                }
+            }
+            program.preassign_label_to_next_insn(skip_label);
+            program.close_cursors(&[cursor_id]);
+        }
+        if let Some(stat_tables) = stat_tables {
+            stat_tables.close(program, &schema, database_id);
+        }
+    }
+    Ok(mode)
+}
+
+/// Emits the analysis of `targets`, the tables (or a single index of a table) of a database.
+fn emit_analyze_database(
+    program: &mut ProgramBuilder,
+    schema: &Schema,
+    database_id: usize,
+    scope: &StatScope,
+    targets: &[(Rc<BTreeTable>, Option<Arc<Index>>)],
+    limit: u64,
+) {
+    let stat_tables = StatTables::open(program, schema, database_id);
+    stat_tables.emit_delete_rows(program, scope);
+    for (table, only_index) in targets {
+        emit_analyze_table(
+            program,
+            schema,
+            database_id,
+            table,
+            only_index.as_ref(),
+            &stat_tables,
+            limit,
+        );
+    }
+    stat_tables.close(program, schema, database_id);
+}
+
+/// The cursors of sqlite_stat1 and sqlite_stat4 opened for writing, and whether the tables were
+/// created by the program.
+struct StatTables {
+    stat1_cursor_id: usize,
+    stat4_cursor_id: usize,
+    created: bool,
+}
+
+impl StatTables {
+    /// Opens the stat tables of a database for writing, creating those that do not exist.
+    fn open(program: &mut ProgramBuilder, schema: &Schema, database_id: usize) -> Self {
+        let (stat1_cursor_id, stat1_created) =
+            emit_open_stat_table(program, schema, database_id, STAT1_TABLE_NAME);
+        let (stat4_cursor_id, stat4_created) =
+            emit_open_stat_table(program, schema, database_id, STAT4_TABLE_NAME);
+        Self {
+            stat1_cursor_id,
+            stat4_cursor_id,
+            created: stat1_created || stat4_created,
+        }
+    }
+
+    /// Deletes the statistics in `scope` from both tables.
+    fn emit_delete_rows(&self, program: &mut ProgramBuilder, scope: &StatScope) {
+        emit_delete_stat_rows(program, self.stat1_cursor_id, scope);
+        emit_delete_stat_rows(program, self.stat4_cursor_id, scope);
+    }
+
+    /// Closes the tables, and reloads the schema if they were created and the statistics.
+    fn close(&self, program: &mut ProgramBuilder, schema: &Schema, database_id: usize) {
+        program.close_cursors(&[self.stat1_cursor_id, self.stat4_cursor_id]);
+        if self.created {
+            program.emit_insn(Insn::SetCookie {
+                db: database_id,
+                cookie: Cookie::SchemaVersion,
+                value: schema.schema_version as i32 + 1,
+                p5: 0,
+            });
+            program.emit_insn(Insn::ParseSchema {
+                db: database_id,
+                where_clause: Some(format!(
+                    "tbl_name IN ('{STAT1_TABLE_NAME}', '{STAT4_TABLE_NAME}') AND type != 'trigger'"
+                )),
+            });
+        }
+        program.emit_insn(Insn::LoadAnalysis { db: database_id });
+    }
+}
+
+/// Opens a stat table for writing, creating it if it does not exist. Returns the cursor and
+/// whether the table was created.
+fn emit_open_stat_table(
+    program: &mut ProgramBuilder,
+    schema: &Schema,
+    database_id: usize,
+    table_name: &str,
+) -> (usize, bool) {
+    let sql = if table_name == STAT1_TABLE_NAME {
+        STAT1_TABLE_SQL
+    } else {
+        STAT4_TABLE_SQL
+    };
+    if let Some(table) = schema.get_btree_table(table_name) {
+        let cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(table.clone()));
+        program.emit_insn(Insn::OpenWrite {
+            cursor_id,
+            root_page: RegisterOrLiteral::Literal(table.root_page),
+            db: database_id,
+        });
+        return (cursor_id, false);
+    }
+
+    let table = Rc::new(BTreeTable::from_sql(sql, 0).expect("stat table SQL must parse"));
+    let root_reg = program.alloc_register();
+    program.emit_insn(Insn::CreateBtree {
+        db: database_id,
+        root: root_reg,
+        flags: CreateBTreeFlags::new_table(),
+    });
+    let sqlite_schema = schema.get_btree_table(SQLITE_TABLEID).unwrap();
+    let sqlite_schema_cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(sqlite_schema));
+    program.emit_insn(Insn::OpenWrite {
+        cursor_id: sqlite_schema_cursor_id,
+        root_page: 1usize.into(),
+        db: database_id,
+    });
+    emit_schema_entry(
+        program,
+        sqlite_schema_cursor_id,
+        SchemaEntryType::Table,
+        table_name,
+        table_name,
+        root_reg,
+        Some(sql.to_string()),
+    );
+    program.close_cursors(&[sqlite_schema_cursor_id]);
+
+    let cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(table));
+    program.emit_insn(Insn::OpenWrite {
+        cursor_id,
+        root_page: RegisterOrLiteral::Register(root_reg),
+        db: database_id,
+    });
+    (cursor_id, true)
+}
+
+/// Deletes the rows of the stat table opened by `cursor_id` that are in `scope`: all rows, or
+/// those whose `tbl` or `idx` column is the name of the table or index.
+fn emit_delete_stat_rows(program: &mut ProgramBuilder, cursor_id: usize, scope: &StatScope) {
+    let filter = match scope {
+        StatScope::Database => None,
+        StatScope::Table(name) => Some((0, name)),
+        StatScope::Index(name) => Some((1, name)),
+    };
+    let name_reg = filter.map(|(_, name)| program.emit_string8_new_reg(name.clone()));
+    let column_reg = program.alloc_register();
+
+    let loop_start_label = program.allocate_label();
+    let loop_end_label = program.allocate_label();
+    let next_label = program.allocate_label();
+    program.emit_insn(Insn::Rewind {
+        cursor_id,
+        pc_if_empty: loop_end_label,
+    });
+    program.preassign_label_to_next_insn(loop_start_label);
+    if let (Some((column, _)), Some(name_reg)) = (filter, name_reg) {
+        program.emit_column(cursor_id, column, column_reg);
+        program.emit_insn(Insn::Ne {
+            lhs: column_reg,
+            rhs: name_reg,
+            target_pc: next_label,
+            flags: CmpInsFlags::default().jump_if_null(),
+            collation: None,
+        });
+    }
+    program.emit_insn(Insn::Delete { cursor_id });
+    program.preassign_label_to_next_insn(next_label);
+    program.emit_insn(Insn::Next {
+        cursor_id,
+        pc_if_next: loop_start_label,
+    });
+    program.preassign_label_to_next_insn(loop_end_label);
+}
+
+/// Emits the analysis of the indexes of a table, or of `only_index`, a port of
+/// `analyzeOneTable()` in SQLite. A non-zero `limit` is the number of entries scanned per index.
+fn emit_analyze_table(
+    program: &mut ProgramBuilder,
+    schema: &Schema,
+    database_id: usize,
+    table: &Rc<BTreeTable>,
+    only_index: Option<&Arc<Index>>,
+    stat_tables: &StatTables,
+    limit: u64,
+) {
+    let indexes: Vec<Arc<Index>> = match only_index {
+        Some(index) => vec![index.clone()],
+        None => schema.get_indices(&table.name).to_vec(),
+    };
+    // tbl, idx, stat, followed by the neq, nlt, ndlt and sample columns of sqlite_stat4
+    let stat_reg = program.alloc_registers(6);
+    let table_name_reg = stat_reg;
+    program.emit_string8(table.name.clone(), table_name_reg);
+
+    for index in indexes.iter() {
+        emit_analyze_index(
+            program,
+            database_id,
+            table,
+            index,
+            stat_reg,
+            stat_tables,
+            limit,
+        );
+    }
+
+    // The number of rows of the table is the number of entries of its indexes, unless it has
+    // none that is not partial, like in SQLite.
+    if indexes.iter().all(|index| index.where_clause.is_some()) {
+        let cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(table.clone()));
+        program.emit_insn(Insn::OpenRead {
+            cursor_id,
+            root_page: table.root_page,
+            db: database_id,
+        });
+        program.emit_insn(Insn::Count {
+            cursor_id,
+            target_reg: stat_reg + 2,
+            exact: true,
+        });
+        program.close_cursors(&[cursor_id]);
+        let skip_label = program.allocate_label();
+        program.emit_insn(Insn::IfNot {
+            reg: stat_reg + 2,
+            target_pc: skip_label,
+            jump_if_null: true,
+        });
+        program.emit_null(stat_reg + 1, None);
+        program.emit_insn(Insn::Affinity {
+            start_reg: stat_reg + 2,
+            count: std::num::NonZeroUsize::new(1).unwrap(),
+            affinities: Affinity::Text.aff_mask().to_string(),
+        });
+        emit_insert_stat_row(
+            program,
+            stat_tables.stat1_cursor_id,
+            stat_reg,
+            3,
+            STAT1_TABLE_NAME,
+        );
+        program.preassign_label_to_next_insn(skip_label);
+    }
+}
+
+/// Emits the scan of an index that accumulates its statistics, and the insertion of its
+/// sqlite_stat1 and sqlite_stat4 rows. `stat_reg` holds the name of the table and is followed
+/// by 5 free registers.
+fn emit_analyze_index(
+    program: &mut ProgramBuilder,
+    database_id: usize,
+    table: &Rc<BTreeTable>,
+    index: &Arc<Index>,
+    stat_reg: usize,
+    stat_tables: &StatTables,
+    limit: u64,
+) {
+    let clustered = !table.has_rowid && index.root_page == table.root_page;
+    let num_key_columns = index.key_columns().len();
+    // The columns that tell index entries apart: the key and the rowid, or the key and the
+    // primary key columns stored in indexes of tables without rowid.
+    let num_columns = if index.has_rowid {
+        num_key_columns + 1
+    } else if clustered {
+        num_key_columns
+    } else {
+        index.columns.len()
+    };
+    let unique_not_null = clustered
+        || (index.unique
+            && index.key_columns().iter().all(|column| {
+                !column.is_expression() && table.columns[column.pos_in_table].notnull
+            }));
+    // Columns past this one only differ between entries with distinct keys
+    let num_columns_tested = if unique_not_null {
+        num_key_columns - 1
+    } else {
+        num_columns - 1
+    };
+
+    // The stat1 row of the index is named after the table for the primary key of a table
+    // without rowid, like in SQLite.
+    let index_name = if clustered {
+        table.name.clone()
+    } else {
+        index.name.clone()
+    };
+    program.emit_string8(index_name, stat_reg + 1);
+
+    let cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(index.clone()));
+    program.emit_insn(Insn::OpenRead {
+        cursor_id,
+        root_page: index.root_page,
+        db: database_id,
+    });
+
+    let accum_reg = program.alloc_register();
+    let entries_reg = program.alloc_register();
+    let change_reg = program.alloc_register();
+    let prev_reg = program.alloc_registers(num_columns_tested.max(1));
+    let column_reg = program.alloc_register();
+    let limit_reg = program.alloc_register();
+    program.emit_insn(Insn::Count {
+        cursor_id,
+        target_reg: entries_reg,
+        exact: true,
+    });
+    program.emit_insn(Insn::StatInit {
+        accum_reg,
+        num_columns,
+        num_key_columns,
+        entries_reg,
+        limit,
+    });
+    if limit > 0 {
+        program.emit_int(limit as i64, limit_reg);
+    }
+
+    let done_label = program.allocate_label();
+    program.emit_insn(Insn::Rewind {
+        cursor_id,
+        pc_if_empty: done_label,
+    });
+    program.emit_int(0, change_reg);
+    let next_row_label = program.allocate_label();
+    let end_distinct_label = program.allocate_label();
+    if num_columns_tested == 0 {
+        program.preassign_label_to_next_insn(next_row_label);
+    } else {
+        let change_labels: Vec<BranchOffset> = (0..num_columns_tested)
+            .map(|_| program.allocate_label())
+            .collect();
+        program.emit_insn(Insn::Goto {
+            target_pc: change_labels[0],
+        });
+
+        // Find the first column that differs from the previous entry
+        program.preassign_label_to_next_insn(next_row_label);
+        if num_columns_tested == 1 && num_key_columns == 1 && index.unique {
+            // Once a key of a single column unique index is not NULL, all keys are distinct
+            program.emit_insn(Insn::NotNull {
+                reg: prev_reg,
+                target_pc: end_distinct_label,
+            });
+        }
+        for (i, change_label) in change_labels.iter().enumerate() {
+            program.emit_int(i as i64, change_reg);
+            program.emit_insn(Insn::Column {
+                cursor_id,
+                column: i,
+                dest: column_reg,
+                default: None,
+            });
+            program.emit_insn(Insn::Ne {
+                lhs: column_reg,
+                rhs: prev_reg + i,
+                target_pc: *change_label,
+                flags: CmpInsFlags::default().null_eq(),
+                collation: index.columns.get(i).and_then(|column| column.collation),
+            });
+        }
+        program.emit_int(num_columns_tested as i64, change_reg);
+        program.emit_insn(Insn::Goto {
+            target_pc: end_distinct_label,
+        });
+
+        // Remember the columns of the entry from the first one that changed
+        for (i, change_label) in change_labels.iter().enumerate() {
+            program.preassign_label_to_next_insn(*change_label);
+            program.emit_insn(Insn::Column {
+                cursor_id,
+                column: i,
+                dest: prev_reg + i,
+                default: None,
+            });
+        }
+    }
+
+    program.preassign_label_to_next_insn(end_distinct_label);
+    let sample_reg = if limit == 0 {
+        let key_reg = program.alloc_registers(num_columns);
+        for i in 0..num_columns {
+            program.emit_insn(Insn::Column {
+                cursor_id,
+                column: i,
+                dest: key_reg + i,
+                default: None,
+            });
+        }
+        let sample_reg = program.alloc_register();
+        program.emit_insn(Insn::MakeRecord {
+            start_reg: key_reg,
+            count: num_columns,
+            dest_reg: sample_reg,
+            index_name: None,
+        });
+        Some(sample_reg)
+    } else {
+        None
+    };
+    program.emit_insn(Insn::StatPush {
+        accum_reg,
+        change_reg,
+        sample_reg,
+    });
+    if limit > 0 {
+        program.emit_insn(Insn::DecrJumpZero {
+            reg: limit_reg,
+            target_pc: done_label,
+        });
+    }
+    program.emit_insn(Insn::Next {
+        cursor_id,
+        pc_if_next: next_row_label,
+    });
+
+    program.preassign_label_to_next_insn(done_label);
+    program.close_cursors(&[cursor_id]);
+    let skip_label = program.allocate_label();
+    program.emit_insn(Insn::IfNot {
+        reg: entries_reg,
+        target_pc: skip_label,
+        jump_if_null: true,
+    });
+    program.emit_insn(Insn::StatGet {
+        accum_reg,
+        dest: stat_reg + 2,
+    });
+    emit_insert_stat_row(
+        program,
+        stat_tables.stat1_cursor_id,
+        stat_reg,
+        3,
+        STAT1_TABLE_NAME,
+    );
+
+    if limit == 0 {
+        let samples_loop_label = program.allocate_label();
+        program.preassign_label_to_next_insn(samples_loop_label);
+        program.emit_insn(Insn::StatNextSample {
+            accum_reg,
+            dest: stat_reg + 2,
+            pc_if_done: skip_label,
+        });
+        emit_insert_stat_row(
+            program,
+            stat_tables.stat4_cursor_id,
+            stat_reg,
+            6,
+            STAT4_TABLE_NAME,
+        );
+        program.emit_insn(Insn::Goto {
+            target_pc: samples_loop_label,
+        });
+    }
+    program.preassign_label_to_next_insn(skip_label);
+}
+
+/// Appends the row in the `count` registers from `start_reg` to a stat table.
+fn emit_insert_stat_row(
+    program: &mut ProgramBuilder,
+    cursor_id: usize,
+    start_reg: usize,
+    count: usize,
+    table_name: &str,
+) {
+    let record_reg = program.alloc_register();
+    program.emit_insn(Insn::MakeRecord {
+        start_reg,
+        count,
+        dest_reg: record_reg,
+        index_name: None,
+    });
+    let rowid_reg = program.alloc_register();
+    program.emit_insn(Insn::NewRowid {
+        cursor: cursor_id,
+        rowid_reg,
+        prev_largest_reg: 0,
+    });
+    program.emit_insn(Insn::Insert {
+        cursor: cursor_id,
+        key_reg: rowid_reg,
+        record_reg,
+        flag: InsertFlags::new(),
+        table_name: table_name.to_string(),
+    });
+}
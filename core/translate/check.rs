@@ -0,0 +1,195 @@
+//! Enforcement of CHECK constraints, unless `PRAGMA ignore_check_constraints` is on.
+//!
+//! The expressions of the constraints are bound to the table when a statement writing to it
+//! is translated, and evaluated on each row it writes. Their columns are read from the
+//! registers holding the new row rather than from the table cursor, through the expression
+//! cache of a [Resolver].
+
+use std::rc::Rc;
+use std::sync::Arc;
+
+use turso_sqlite3_parser::ast::{Expr, ResolveType};
+
+use crate::error::SQLITE_CONSTRAINT_CHECK;
+use crate::schema::{BTreeTable, Table};
+use crate::translate::emitter::Resolver;
+use crate::translate::expr::{translate_expr, walk_expr, WalkControl};
+use crate::translate::plan::{ColumnUsedMask, JoinedTable, Operation, TableReferences};
+use crate::translate::planner::bind_column_references;
+use crate::vdbe::builder::ProgramBuilder;
+use crate::vdbe::insn::Insn;
+use crate::vdbe::BranchOffset;
+use crate::{Connection, Result};
+
+/// The CHECK constraints a statement checks the rows it writes to a table against.
+#[derive(Debug, Clone)]
+pub struct CheckConstraints {
+    table_references: TableReferences,
+    /// The bound expression of each constraint, with the description of its failure.
+    checks: Vec<(Expr, String)>,
+}
+
+impl CheckConstraints {
+    /// Binds the CHECK constraints of `table`. An UPDATE only checks the constraints that
+    /// refer to one of `updated_columns`, like in SQLite.
+    pub fn compile(
+        program: &mut ProgramBuilder,
+        connection: &Arc<Connection>,
+        table: &Rc<BTreeTable>,
+        updated_columns: Option<&[usize]>,
+    ) -> Result<Self> {
+        if connection.get_ignore_check_constraints() {
+            return Ok(Self::default());
+        }
+        let internal_id = program.table_reference_counter.next();
+        let mut table_references = TableReferences::new(
+            vec![JoinedTable {
+                op: Operation::Scan {
+                    iter_dir: crate::translate::plan::IterationDirection::Forwards,
+                    index: None,
+                },
+                table: Table::BTree(table.clone()),
+                identifier: table.name.clone(),
+                internal_id,
+                join_info: None,
+                col_used_mask: ColumnUsedMask::default(),
+                database_id: 0,
+                index_hint: None,
+            }],
+            vec![],
+        );
+        let mut checks = vec![];
+        let rowid_alias = table.get_rowid_alias_column().map(|(idx, _)| idx);
+        for check in &table.checks {
+            let mut expr = check.expr.clone();
+            bind_column_references(&mut expr, &mut table_references, None, connection)?;
+            if let Some(updated_columns) = updated_columns {
+                let mut is_updated = false;
+                walk_expr(&expr, &mut |expr| {
+                    match expr {
+                        Expr::Column { column, .. } => {
+                            is_updated |= updated_columns.contains(column);
+                        }
+                        Expr::RowId { .. } => {
+                            is_updated |=
+                                rowid_alias.is_some_and(|idx| updated_columns.contains(&idx));
+                        }
+                        _ => {}
+                    }
+                    Ok(WalkControl::Continue)
+                })?;
+                if !is_updated {
+                    continue;
+                }
+            }
+            checks.push((expr, check.description()));
+        }
+        Ok(Self {
+            table_references,
+            checks,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.checks.is_empty()
+    }
+
+    /// Emits the checks of the new row, whose rowid is held in `rowid_reg` and whose columns
+    /// are held in the registers from `columns_start_reg`. A constraint fails when its
+    /// expression is false, but not when it is NULL. A failure is resolved with `on_conflict`:
+    /// IGNORE skips the row by jumping to `skip_label`, and REPLACE fails like ABORT.
+    pub fn emit(
+        &self,
+        program: &mut ProgramBuilder,
+        resolver: &Resolver,
+        rowid_reg: usize,
+        columns_start_reg: usize,
+        on_conflict: ResolveType,
+        skip_label: BranchOffset,
+    ) -> Result<()> {
+        if self.checks.is_empty() {
+            return Ok(());
+        }
+        let table = &self.table_references.joined_tables()[0];
+        let column_exprs = table
+            .columns()
+            .iter()
+            .enumerate()
+            .map(|(column, col)| Expr::Column {
+                database: None,
+                table: table.internal_id,
+                column,
+                is_rowid_alias: col.is_rowid_alias,
+            })
+            .chain(std::iter::once(Expr::RowId {
+                database: None,
+                table: table.internal_id,
+            }))
+            .collect::<Vec<_>>();
+        let mut row_resolver = Resolver::new(resolver.schema, resolver.symbol_table);
+        for expr in &column_exprs {
+            let reg = match expr {
+                Expr::Column {
+                    column,
+                    is_rowid_alias: false,
+                    ..
+                } => columns_start_reg + column,
+                _ => rowid_reg,
+            };
+            row_resolver.expr_to_reg_cache.push((expr, reg));
+        }
+        row_resolver.enable_expr_to_reg_cache();
+
+        for (expr, description) in &self.checks {
+            let reg = program.alloc_register();
+            translate_expr(
+                program,
+                Some(&self.table_references),
+                expr,
+                reg,
+                &row_resolver,
+            )?;
+            let passed_label = program.allocate_label();
+            program.emit_insn(Insn::If {
+                reg,
+                target_pc: passed_label,
+                jump_if_null: true,
+            });
+            match on_conflict {
+                ResolveType::Ignore => program.emit_insn(Insn::Goto {
+                    target_pc: skip_label,
+                }),
+                on_conflict => program.emit_insn(Insn::Halt {
+                    err_code: SQLITE_CONSTRAINT_CHECK,
+                    on_error: match on_conflict {
+                        ResolveType::Replace => ResolveType::Abort,
+                        on_conflict => on_conflict,
+                    },
+                    description: description.clone(),
+                }),
+            }
+            program.preassign_label_to_next_insn(passed_label);
+        }
+        Ok(())
+    }
+}
+
+impl Default for CheckConstraints {
+    fn default() -> Self {
+        Self {
+            table_references: TableReferences::new(vec![], vec![]),
+            checks: vec![],
+        }
+    }
+}
+
+/// Fails if `expr` may not be used in a CHECK constraint.
+pub fn validate_check_expr(expr: &Expr) -> Result<()> {
+    walk_expr(expr, &mut |expr| {
+        if let Expr::Exists(_) | Expr::Subquery(_) | Expr::InSelect { .. } = expr {
+            crate::bail_parse_error!("subqueries prohibited in CHECK constraints");
+        }
+        Ok(WalkControl::Continue)
+    })?;
+    Ok(())
+}
@@ -39,16 +39,7 @@ pub fn emit_program_for_compound_select(
         }
     }
 
-    // Each subselect shares the same limit_ctx, because the LIMIT applies to the entire compound select,
-    // not just a single subselect.
-    let limit_ctx = limit.map(|limit| {
-        let reg = program.alloc_register();
-        program.emit_insn(Insn::Integer {
-            value: limit as i64,
-            dest: reg,
-        });
-        LimitCtx::new_shared(reg)
-    });
+    let limit_ctx = init_compound_limit(program, *limit);
 
     // When a compound SELECT is part of a query that yields results to a coroutine (e.g. within an INSERT clause),
     // we must allocate registers for the result columns to be yielded. Each subselect will then yield to
@@ -78,6 +69,81 @@ pub fn emit_program_for_compound_select(
     Ok(())
 }
 
+/// Emits a compound SELECT as a coroutine, like [crate::translate::subquery::emit_subquery] does
+/// for a single SELECT. Returns the coroutine register, the start of the coroutine implementation
+/// and the start register of the result columns, which every subselect yields.
+pub fn emit_compound_subquery(
+    program: &mut ProgramBuilder,
+    mut plan: Plan,
+    schema: &Schema,
+    syms: &SymbolTable,
+) -> crate::Result<(usize, BranchOffset, usize)> {
+    let Plan::CompoundSelect {
+        left,
+        right_most,
+        limit,
+        ..
+    } = &mut plan
+    else {
+        crate::bail_parse_error!("expected compound select plan");
+    };
+    let limit = *limit;
+    let yield_reg = program.alloc_register();
+    let reg_result_cols_start = program.alloc_registers(right_most.result_columns.len());
+    let coroutine_implementation_start = program.allocate_label();
+    for select in left
+        .iter_mut()
+        .map(|(select, _)| select)
+        .chain(std::iter::once(right_most))
+    {
+        select.query_destination = QueryDestination::CoroutineYield {
+            yield_reg,
+            coroutine_implementation_start,
+        };
+    }
+
+    let label_body_end = program.allocate_label();
+    program.emit_insn(Insn::InitCoroutine {
+        yield_reg,
+        jump_on_definition: label_body_end,
+        start_offset: coroutine_implementation_start,
+    });
+    program.preassign_label_to_next_insn(coroutine_implementation_start);
+    // Trivial exit on LIMIT 0
+    if limit != Some(0) {
+        let limit_ctx = init_compound_limit(program, limit);
+        emit_compound_select(
+            program,
+            plan,
+            schema,
+            syms,
+            limit_ctx,
+            Some(yield_reg),
+            Some(reg_result_cols_start),
+        )?;
+    }
+    program.emit_insn(Insn::EndCoroutine { yield_reg });
+    program.preassign_label_to_next_insn(label_body_end);
+    Ok((
+        yield_reg,
+        coroutine_implementation_start,
+        reg_result_cols_start,
+    ))
+}
+
+// Each subselect shares the same limit_ctx, because the LIMIT applies to the entire compound select,
+// not just a single subselect.
+fn init_compound_limit(program: &mut ProgramBuilder, limit: Option<isize>) -> Option<LimitCtx> {
+    limit.map(|limit| {
+        let reg = program.alloc_register();
+        program.emit_insn(Insn::Integer {
+            value: limit as i64,
+            dest: reg,
+        });
+        LimitCtx::new_shared(reg)
+    })
+}
+
 // Emits bytecode for a compound SELECT statement. This function processes the rightmost part of
 // the compound SELECT and handles the left parts recursively based on the compound operator type.
 fn emit_compound_select(
@@ -155,7 +221,7 @@ fn emit_compound_select(
                     } => (cursor_id, index.clone()),
                     _ => {
                         new_dedupe_index = true;
-                        create_dedupe_index(program, &right_most, schema)?
+                        create_dedupe_index(program, &right_most)?
                     }
                 };
                 plan.query_destination = QueryDestination::EphemeralIndex {
@@ -205,8 +271,7 @@ fn emit_compound_select(
                     target_cursor_id = Some(cursor_id);
                 }
 
-                let (left_cursor_id, left_index) =
-                    create_dedupe_index(program, &right_most, schema)?;
+                let (left_cursor_id, left_index) = create_dedupe_index(program, &right_most)?;
                 plan.query_destination = QueryDestination::EphemeralIndex {
                     cursor_id: left_cursor_id,
                     index: left_index.clone(),
@@ -229,8 +294,7 @@ fn emit_compound_select(
                     reg_result_cols_start,
                 )?;
 
-                let (right_cursor_id, right_index) =
-                    create_dedupe_index(program, &right_most, schema)?;
+                let (right_cursor_id, right_index) = create_dedupe_index(program, &right_most)?;
                 right_most.query_destination = QueryDestination::EphemeralIndex {
                     cursor_id: right_cursor_id,
                     index: right_index,
@@ -255,7 +319,7 @@ fn emit_compound_select(
                     } => (cursor_id, index),
                     _ => {
                         new_index = true;
-                        create_dedupe_index(program, &right_most, schema)?
+                        create_dedupe_index(program, &right_most)?
                     }
                 };
                 plan.query_destination = QueryDestination::EphemeralIndex {
@@ -308,12 +372,7 @@ fn emit_compound_select(
 fn create_dedupe_index(
     program: &mut ProgramBuilder,
     select: &SelectPlan,
-    schema: &Schema,
 ) -> crate::Result<(usize, Arc<Index>)> {
-    if !schema.indexes_enabled {
-        crate::bail_parse_error!("UNION OR INTERSECT is not supported without indexes");
-    }
-
     let dedupe_index = Arc::new(Index {
         columns: select
             .result_columns
@@ -326,6 +385,7 @@ fn create_dedupe_index(
                 order: SortOrder::Asc,
                 pos_in_table: 0,
                 default: None,
+                expr: None,
                 collation: None, // FIXME: this should be inferred
             })
             .collect(),
@@ -335,6 +395,8 @@ fn create_dedupe_index(
         table_name: String::new(),
         unique: false,
         has_rowid: false,
+        num_extra_columns: 0,
+        where_clause: None,
     });
     let cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(dedupe_index.clone()));
     program.emit_insn(Insn::OpenEphemeral {
@@ -0,0 +1,795 @@
+//! Common table expressions, i.e. the tables of a WITH clause.
+//!
+//! Unless it is declared `AS MATERIALIZED`, a CTE is planned anew wherever it is referenced. A CTE
+//! whose body is a single SELECT is planned as a FROM clause subquery, like a view. A CTE whose
+//! body is a compound SELECT, or that is recursive, i.e. its body references the CTE itself, is
+//! planned as a coroutine that yields its rows. A materialized CTE is computed once, into an
+//! ephemeral table, before the query that declares it runs, and every reference reads that table.
+//!
+//! A recursive CTE has the form `setup UNION [ALL] step`, where only `step` references the CTE.
+//! The rows of `setup` are added to a queue. Then, until the queue is empty, the first row of the
+//! queue is removed from it and becomes a row of the CTE, and `step` is run with that row as the
+//! contents of the CTE. The rows of `step` are added to the queue. With UNION, a row is only added
+//! to the queue if it was not added before. Since the rows are produced one at a time, a LIMIT on
+//! the query reading a CTE that is not materialized stops the recursion.
+
+use std::cell::{Cell, OnceCell, RefCell};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use turso_sqlite3_parser::ast::{self, FromClause, Materialized, SortOrder};
+
+use crate::schema::{BTreeTable, CteRows, CteTable, Index, IndexColumn, Schema, Table, View};
+use crate::translate::compound_select::emit_compound_subquery;
+use crate::translate::emitter::TranslateCtx;
+use crate::translate::plan::{
+    ColumnUsedMask, Cte, CteBody, CteDefinition, CtePlan, DistinctCtx, IterationDirection,
+    JoinedTable, Operation, OuterQueryReference, Plan, QueryDestination, RecursiveCtePlan,
+    SelectPlan, TableReferences,
+};
+use crate::translate::planner::parse_limit;
+use crate::translate::select::prepare_select_plan;
+use crate::translate::subquery::emit_subquery;
+use crate::util::normalize_ident;
+use crate::vdbe::builder::{CursorType, ProgramBuilder, TableRefIdCounter};
+use crate::vdbe::insn::{InsertFlags, Insn};
+use crate::vdbe::BranchOffset;
+use crate::{bail_parse_error, Result, SymbolTable};
+
+/// Adds the CTEs declared by `with` to the CTEs in scope, `ctes`. The CTEs are not planned until
+/// they are referenced.
+pub fn parse_with(
+    with: Option<ast::With>,
+    ctes: &[Rc<Cte>],
+    outer_query_refs: &[OuterQueryReference],
+) -> Result<Vec<Rc<Cte>>> {
+    let mut scope = ctes.to_vec();
+    let Some(with) = with else {
+        return Ok(scope);
+    };
+    let mut names: Vec<String> = Vec::with_capacity(with.ctes.len());
+    for cte in with.ctes {
+        let name = normalize_ident(cte.tbl_name.as_str());
+        if names.contains(&name) {
+            bail_parse_error!("duplicate WITH table name: {}", name);
+        }
+        names.push(name.clone());
+        let body = CteBody {
+            select: *cte.select,
+            columns: cte.columns.map(|columns| {
+                columns
+                    .iter()
+                    .map(|c| normalize_ident(c.col_name.as_str()))
+                    .collect()
+            }),
+            outer_query_refs: outer_query_refs.to_vec(),
+        };
+        let definition = if cte.materialized == Materialized::Yes {
+            CteDefinition::Materialized {
+                body,
+                cte: OnceCell::new(),
+            }
+        } else {
+            CteDefinition::Inline(body)
+        };
+        scope.push(Rc::new(Cte { name, definition }));
+    }
+    Ok(scope)
+}
+
+/// Parses the WITH clause of an UPDATE or DELETE. Their plans have no place to compute
+/// materialized CTEs in, so every CTE is planned wherever it is referenced instead.
+pub fn parse_dml_with(with: Option<ast::With>) -> Result<Vec<Rc<Cte>>> {
+    let with = with.map(|mut with| {
+        for cte in &mut with.ctes {
+            cte.materialized = Materialized::Any;
+        }
+        with
+    });
+    parse_with(with, &[], &[])
+}
+
+/// The materialized CTEs among `ctes` that were referenced, and so must be computed.
+pub fn referenced_materialized_ctes(ctes: &[Rc<Cte>]) -> Vec<Rc<CteTable>> {
+    ctes.iter()
+        .filter_map(|cte| match &cte.definition {
+            CteDefinition::Materialized { cte, .. } => cte.get().cloned(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Adds the CTE `ctes[index]` to the FROM clause tables, as `identifier`.
+#[allow(clippy::too_many_arguments)]
+pub fn parse_cte_reference(
+    schema: &Schema,
+    syms: &SymbolTable,
+    ctes: &[Rc<Cte>],
+    index: usize,
+    identifier: String,
+    table_references: &mut TableReferences,
+    table_ref_counter: &mut TableRefIdCounter,
+    connection: &Arc<crate::Connection>,
+) -> Result<()> {
+    let cte = &ctes[index];
+    // The body of a CTE can reference the CTEs declared before it.
+    let scope = &ctes[..index];
+    let cte_table = match &cte.definition {
+        CteDefinition::Inline(body)
+            if body.select.body.compounds.is_some()
+                || references_table(&body.select, &cte.name) =>
+        {
+            plan_cte_table(
+                schema,
+                syms,
+                &cte.name,
+                body,
+                false,
+                scope,
+                table_ref_counter,
+                connection,
+            )?
+        }
+        CteDefinition::Inline(body) => {
+            let Plan::Select(mut plan) = plan_cte_select(
+                schema,
+                syms,
+                body.select.clone(),
+                body,
+                scope,
+                table_ref_counter,
+                connection,
+            )?
+            else {
+                unreachable!("a SELECT without compounds is planned as a single SELECT");
+            };
+            rename_columns(&cte.name, body.columns.as_deref(), &mut plan)?;
+            table_references.add_joined_table(JoinedTable::new_subquery(
+                identifier,
+                plan,
+                None,
+                table_ref_counter.next(),
+            ));
+            return Ok(());
+        }
+        CteDefinition::Materialized { body, cte: planned } => match planned.get() {
+            Some(cte_table) => cte_table.clone(),
+            None => {
+                let cte_table = plan_cte_table(
+                    schema,
+                    syms,
+                    &cte.name,
+                    body,
+                    true,
+                    scope,
+                    table_ref_counter,
+                    connection,
+                )?;
+                let _ = planned.set(cte_table.clone());
+                cte_table
+            }
+        },
+        CteDefinition::RecursiveRow {
+            cte: cte_table,
+            referenced,
+        } => {
+            if referenced.replace(true) {
+                bail_parse_error!("multiple references to recursive table: {}", cte.name);
+            }
+            cte_table.clone()
+        }
+    };
+    table_references.add_joined_table(JoinedTable {
+        op: Operation::Scan {
+            iter_dir: IterationDirection::Forwards,
+            index: None,
+        },
+        table: Table::Cte(cte_table),
+        identifier,
+        internal_id: table_ref_counter.next(),
+        join_info: None,
+        col_used_mask: ColumnUsedMask::default(),
+        database_id: 0,
+        index_hint: None,
+    });
+    Ok(())
+}
+
+fn plan_cte_select(
+    schema: &Schema,
+    syms: &SymbolTable,
+    select: ast::Select,
+    body: &CteBody,
+    scope: &[Rc<Cte>],
+    table_ref_counter: &mut TableRefIdCounter,
+    connection: &Arc<crate::Connection>,
+) -> Result<Plan> {
+    prepare_select_plan(
+        schema,
+        select,
+        syms,
+        &body.outer_query_refs,
+        scope,
+        table_ref_counter,
+        QueryDestination::CoroutineYield {
+            yield_reg: usize::MAX, // will be set later in bytecode emission
+            coroutine_implementation_start: BranchOffset::Placeholder, // will be set later in bytecode emission
+        },
+        connection,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn plan_cte_table(
+    schema: &Schema,
+    syms: &SymbolTable,
+    name: &str,
+    body: &CteBody,
+    materialized: bool,
+    scope: &[Rc<Cte>],
+    table_ref_counter: &mut TableRefIdCounter,
+    connection: &Arc<crate::Connection>,
+) -> Result<Rc<CteTable>> {
+    if !references_table(&body.select, name) {
+        let mut plan = plan_cte_select(
+            schema,
+            syms,
+            body.select.clone(),
+            body,
+            scope,
+            table_ref_counter,
+            connection,
+        )?;
+        let table = cte_table(name, body, &mut plan)?;
+        return Ok(Rc::new(CteTable {
+            table,
+            plan: RefCell::new(Some(CtePlan::Select(plan))),
+            materialized,
+            rows: Cell::new(None),
+        }));
+    }
+
+    let ast::Select {
+        with,
+        body: ast::SelectBody { select, compounds },
+        order_by,
+        limit,
+    } = body.select.clone();
+    if with.is_some() {
+        bail_parse_error!("WITH is not supported for compound SELECTs yet");
+    }
+    if order_by.is_some() {
+        bail_parse_error!("ORDER BY is not supported in recursive CTEs yet");
+    }
+    let mut compounds = compounds.unwrap_or_default();
+    let step = match compounds.pop() {
+        Some(ast::CompoundSelect {
+            select: step,
+            operator: operator @ (ast::CompoundOperator::Union | ast::CompoundOperator::UnionAll),
+        }) => Some((step, operator)),
+        _ => None,
+    };
+    let setup = ast::Select {
+        with: None,
+        body: ast::SelectBody {
+            select,
+            compounds: (!compounds.is_empty()).then_some(compounds),
+        },
+        order_by: None,
+        limit: None,
+    };
+    let Some((step, operator)) = step.filter(|_| !references_table(&setup, name)) else {
+        bail_parse_error!("circular reference: {}", name);
+    };
+    let distinct = operator == ast::CompoundOperator::Union;
+
+    let mut setup = plan_cte_select(
+        schema,
+        syms,
+        setup,
+        body,
+        scope,
+        table_ref_counter,
+        connection,
+    )?;
+    let table = cte_table(name, body, &mut setup)?;
+
+    // The references to the CTE in the recursive part read the row it is run for.
+    let current = Rc::new(CteTable {
+        table: table.clone(),
+        plan: RefCell::new(None),
+        materialized: true,
+        rows: Cell::new(None),
+    });
+    let mut step_scope = scope.to_vec();
+    step_scope.push(Rc::new(Cte {
+        name: name.to_string(),
+        definition: CteDefinition::RecursiveRow {
+            cte: current.clone(),
+            referenced: Cell::new(false),
+        },
+    }));
+    let step = ast::Select {
+        with: None,
+        body: ast::SelectBody {
+            select: step,
+            compounds: None,
+        },
+        order_by: None,
+        limit: None,
+    };
+    let Plan::Select(step) = plan_cte_select(
+        schema,
+        syms,
+        step,
+        body,
+        &step_scope,
+        table_ref_counter,
+        connection,
+    )?
+    else {
+        unreachable!("a SELECT without compounds is planned as a single SELECT");
+    };
+    if step.result_columns.len() != table.columns.len() {
+        bail_parse_error!(
+            "SELECTs to the left and right of {} do not have the same number of result columns",
+            operator
+        );
+    }
+    if !step.aggregates.is_empty() || step.group_by.is_some() {
+        bail_parse_error!("recursive aggregate queries not supported");
+    }
+
+    let (limit, offset) = limit.map_or(Ok((None, None)), |l| parse_limit(&l))?;
+    Ok(Rc::new(CteTable {
+        table,
+        plan: RefCell::new(Some(CtePlan::Recursive(RecursiveCtePlan {
+            setup,
+            step: Box::new(step),
+            current,
+            distinct,
+            limit,
+            offset,
+        }))),
+        materialized,
+        rows: Cell::new(None),
+    }))
+}
+
+/// Plans the view `view`, whose body `select` is a compound SELECT, like a CTE that is not
+/// materialized, i.e. read from a coroutine.
+pub fn plan_compound_view(
+    schema: &Schema,
+    syms: &SymbolTable,
+    view: &View,
+    select: ast::Select,
+    table_ref_counter: &mut TableRefIdCounter,
+    connection: &Arc<crate::Connection>,
+) -> Result<Rc<CteTable>> {
+    let body = CteBody {
+        select,
+        columns: view.columns.clone(),
+        outer_query_refs: vec![],
+    };
+    // A view cannot reference the tables of the query it is used in, nor the CTEs.
+    let mut plan = plan_cte_select(
+        schema,
+        syms,
+        body.select.clone(),
+        &body,
+        &[],
+        table_ref_counter,
+        connection,
+    )?;
+    let num_columns = leftmost_select(&mut plan).result_columns.len();
+    if let Some(columns) = &view.columns {
+        if columns.len() != num_columns {
+            bail_parse_error!(
+                "expected {} columns for '{}' but got {}",
+                columns.len(),
+                view.name,
+                num_columns
+            );
+        }
+    }
+    let table = cte_table(&view.name, &body, &mut plan)?;
+    Ok(Rc::new(CteTable {
+        table,
+        plan: RefCell::new(Some(CtePlan::Select(plan))),
+        materialized: false,
+        rows: Cell::new(None),
+    }))
+}
+
+/// The leftmost SELECT of `plan`, which names the columns of a compound SELECT.
+fn leftmost_select(plan: &mut Plan) -> &mut SelectPlan {
+    match plan {
+        Plan::Select(plan) => plan,
+        Plan::CompoundSelect {
+            left, right_most, ..
+        } => left.first_mut().map_or(right_most, |(plan, _)| plan),
+        _ => unreachable!("a CTE is a SELECT"),
+    }
+}
+
+/// Creates the table of a CTE computed by `plan`, renaming the result columns of `plan` to the
+/// column list of the CTE, if it has one.
+fn cte_table(name: &str, body: &CteBody, plan: &mut Plan) -> Result<Rc<BTreeTable>> {
+    // The columns of a compound SELECT are named after the leftmost SELECT.
+    let leftmost = leftmost_select(plan);
+    rename_columns(name, body.columns.as_deref(), leftmost)?;
+    Ok(Rc::new(BTreeTable {
+        root_page: 0,
+        name: name.to_string(),
+        primary_key_columns: vec![],
+        columns: leftmost.result_table_columns(),
+        has_rowid: true,
+        is_strict: false,
+        unique_sets: None,
+        conflict_clauses: vec![],
+        foreign_keys: vec![],
+        checks: vec![],
+        notnull_conflict_clauses: vec![],
+    }))
+}
+
+fn rename_columns(name: &str, columns: Option<&[String]>, plan: &mut SelectPlan) -> Result<()> {
+    let Some(columns) = columns else {
+        return Ok(());
+    };
+    if columns.len() != plan.result_columns.len() {
+        bail_parse_error!(
+            "table {} has {} values for {} columns",
+            name,
+            plan.result_columns.len(),
+            columns.len()
+        );
+    }
+    for (result_column, name) in plan.result_columns.iter_mut().zip(columns) {
+        result_column.alias = Some(name.clone());
+    }
+    Ok(())
+}
+
+/// Returns true if a FROM clause of `select`, or of a query nested in its FROM clauses,
+/// references the table `name`.
+fn references_table(select: &ast::Select, name: &str) -> bool {
+    fn from_references_table(from: &FromClause, name: &str) -> bool {
+        let joins = from.joins.iter().flatten().map(|j| &j.table);
+        from.select
+            .as_deref()
+            .into_iter()
+            .chain(joins)
+            .any(|table| match table {
+                ast::SelectTable::Table(qualified_name, ..)
+                | ast::SelectTable::TableCall(qualified_name, ..) => {
+                    qualified_name.db_name.is_none()
+                        && normalize_ident(qualified_name.name.as_str()) == name
+                }
+                ast::SelectTable::Select(select, _) => references_table(select, name),
+                ast::SelectTable::Sub(from, _) => from_references_table(from, name),
+            })
+    }
+
+    let in_with = select
+        .with
+        .iter()
+        .flat_map(|with| &with.ctes)
+        .any(|cte| references_table(&cte.select, name));
+    let compounds = select.body.compounds.iter().flatten();
+    in_with
+        || std::iter::once(&select.body.select)
+            .chain(compounds.map(|c| &c.select))
+            .any(|one| match one.as_ref() {
+                ast::OneSelect::Select(one) => one
+                    .from
+                    .as_ref()
+                    .is_some_and(|from| from_references_table(from, name)),
+                ast::OneSelect::Values(_) => false,
+            })
+}
+
+/// Computes the rows of the materialized CTEs `ctes` into their ephemeral tables.
+pub fn emit_materialized_ctes(
+    program: &mut ProgramBuilder,
+    t_ctx: &mut TranslateCtx,
+    ctes: &[Rc<CteTable>],
+) -> Result<()> {
+    for cte in ctes {
+        let Some(plan) = cte.plan.borrow_mut().take() else {
+            continue;
+        };
+        let cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(cte.table.clone()));
+        program.emit_insn(Insn::OpenEphemeral {
+            cursor_id,
+            is_table: true,
+        });
+        cte.rows.set(Some(CteRows::Ephemeral { cursor_id }));
+        let emit_row = |program: &mut ProgramBuilder, start_reg: usize| {
+            emit_insert_row(program, &cte.table, cursor_id, start_reg)
+        };
+        match plan {
+            CtePlan::Select(plan) => emit_rows(program, t_ctx, plan, emit_row)?,
+            CtePlan::Recursive(recursive) => {
+                emit_recursive_cte(program, t_ctx, &cte.table, recursive, emit_row)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Emits a CTE that is not materialized as a coroutine that yields its rows.
+pub fn emit_cte_coroutine(
+    program: &mut ProgramBuilder,
+    t_ctx: &mut TranslateCtx,
+    cte: &CteTable,
+) -> Result<()> {
+    let Some(plan) = cte.plan.borrow_mut().take() else {
+        return Ok(());
+    };
+    let (yield_reg, coroutine_implementation_start, result_columns_start_reg) = match plan {
+        CtePlan::Select(plan) => emit_coroutine(program, t_ctx, plan)?,
+        CtePlan::Recursive(recursive) => {
+            let yield_reg = program.alloc_register();
+            let coroutine_implementation_start = program.allocate_label();
+            let label_body_end = program.allocate_label();
+            program.emit_insn(Insn::InitCoroutine {
+                yield_reg,
+                jump_on_definition: label_body_end,
+                start_offset: coroutine_implementation_start,
+            });
+            program.preassign_label_to_next_insn(coroutine_implementation_start);
+            let row_reg =
+                emit_recursive_cte(program, t_ctx, &cte.table, recursive, |program, _| {
+                    program.emit_insn(Insn::Yield {
+                        yield_reg,
+                        end_offset: BranchOffset::Offset(0),
+                    });
+                })?;
+            program.emit_insn(Insn::EndCoroutine { yield_reg });
+            program.preassign_label_to_next_insn(label_body_end);
+            (yield_reg, coroutine_implementation_start, row_reg)
+        }
+    };
+    cte.rows.set(Some(CteRows::Coroutine {
+        yield_reg,
+        coroutine_implementation_start,
+        result_columns_start_reg,
+    }));
+    Ok(())
+}
+
+/// Emits the loop of a recursive CTE, which runs `emit_row` for each row of the CTE. Returns the
+/// start register of the rows.
+fn emit_recursive_cte(
+    program: &mut ProgramBuilder,
+    t_ctx: &mut TranslateCtx,
+    table: &Rc<BTreeTable>,
+    recursive: RecursiveCtePlan,
+    emit_row: impl FnOnce(&mut ProgramBuilder, usize),
+) -> Result<usize> {
+    let RecursiveCtePlan {
+        setup,
+        step,
+        current,
+        distinct,
+        limit,
+        offset,
+    } = recursive;
+    let num_columns = table.columns.len();
+    let row_reg = program.alloc_registers(num_columns);
+    if limit == Some(0) {
+        return Ok(row_reg);
+    }
+
+    let queue_cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(table.clone()));
+    program.emit_insn(Insn::OpenEphemeral {
+        cursor_id: queue_cursor_id,
+        is_table: true,
+    });
+    // With UNION, every row that was ever added to the queue is kept in an index, and the rows
+    // that are already in the index are not added again.
+    let distinct_ctx = distinct.then(|| {
+        let index_name = format!("distinct_{}", program.offset().as_offset_int());
+        let index = Arc::new(Index {
+            name: index_name.clone(),
+            table_name: String::new(),
+            ephemeral: true,
+            root_page: 0,
+            columns: table
+                .columns
+                .iter()
+                .enumerate()
+                .map(|(i, column)| IndexColumn {
+                    name: column.name.clone().unwrap_or_default(),
+                    order: SortOrder::Asc,
+                    pos_in_table: i,
+                    collation: column.collation,
+                    default: None,
+                    expr: None,
+                })
+                .collect(),
+            unique: false,
+            has_rowid: false,
+            num_extra_columns: 0,
+            where_clause: None,
+        });
+        let cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(index));
+        program.emit_insn(Insn::OpenEphemeral {
+            cursor_id,
+            is_table: false,
+        });
+        DistinctCtx {
+            cursor_id,
+            ephemeral_index_name: index_name,
+            label_on_conflict: BranchOffset::Placeholder,
+        }
+    });
+    let enqueue = |program: &mut ProgramBuilder, start_reg: usize| {
+        let label_skip = program.allocate_label();
+        if let Some(distinct_ctx) = &distinct_ctx {
+            DistinctCtx {
+                label_on_conflict: label_skip,
+                ..distinct_ctx.clone()
+            }
+            .emit_deduplication_insns(program, num_columns, start_reg);
+        }
+        emit_insert_row(program, table, queue_cursor_id, start_reg);
+        program.preassign_label_to_next_insn(label_skip);
+    };
+
+    // The current row always has rowid 1, so inserting the next one replaces it.
+    let current_cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(table.clone()));
+    program.emit_insn(Insn::OpenEphemeral {
+        cursor_id: current_cursor_id,
+        is_table: true,
+    });
+    current.rows.set(Some(CteRows::Ephemeral {
+        cursor_id: current_cursor_id,
+    }));
+    let current_rowid_reg = program.alloc_register();
+    program.emit_int(1, current_rowid_reg);
+    let reg_limit = limit.map(|limit| {
+        let reg = program.alloc_register();
+        program.emit_int(limit as i64, reg);
+        reg
+    });
+    let reg_offset = offset.filter(|offset| *offset > 0).map(|offset| {
+        let reg = program.alloc_register();
+        program.emit_int(offset as i64, reg);
+        reg
+    });
+
+    emit_rows(program, t_ctx, setup, enqueue)?;
+
+    let label_loop_start = program.allocate_label();
+    let label_loop_end = program.allocate_label();
+    program.preassign_label_to_next_insn(label_loop_start);
+    program.emit_insn(Insn::Rewind {
+        cursor_id: queue_cursor_id,
+        pc_if_empty: label_loop_end,
+    });
+    for i in 0..num_columns {
+        program.emit_column(queue_cursor_id, i, row_reg + i);
+    }
+    program.emit_insn(Insn::Delete {
+        cursor_id: queue_cursor_id,
+    });
+    // Rows skipped by OFFSET are not rows of the CTE, but the recursive part is still run for them.
+    let label_skip_row = program.allocate_label();
+    if let Some(reg_offset) = reg_offset {
+        program.emit_insn(Insn::IfPos {
+            reg: reg_offset,
+            target_pc: label_skip_row,
+            decrement_by: 1,
+        });
+    }
+    emit_row(program, row_reg);
+    if let Some(reg_limit) = reg_limit {
+        program.emit_insn(Insn::DecrJumpZero {
+            reg: reg_limit,
+            target_pc: label_loop_end,
+        });
+    }
+    program.preassign_label_to_next_insn(label_skip_row);
+    let record_reg = program.alloc_register();
+    program.emit_insn(Insn::MakeRecord {
+        start_reg: row_reg,
+        count: num_columns,
+        dest_reg: record_reg,
+        index_name: None,
+    });
+    program.emit_insn(Insn::Insert {
+        cursor: current_cursor_id,
+        key_reg: current_rowid_reg,
+        record_reg,
+        flag: InsertFlags::new(),
+        table_name: table.name.clone(),
+    });
+    emit_rows(program, t_ctx, Plan::Select(*step), enqueue)?;
+    program.emit_insn(Insn::Goto {
+        target_pc: label_loop_start,
+    });
+    program.preassign_label_to_next_insn(label_loop_end);
+    Ok(row_reg)
+}
+
+/// Emits `plan` as a coroutine. Returns the coroutine register, the start of the coroutine
+/// implementation and the start register of the result columns.
+fn emit_coroutine(
+    program: &mut ProgramBuilder,
+    t_ctx: &mut TranslateCtx,
+    plan: Plan,
+) -> Result<(usize, BranchOffset, usize)> {
+    match plan {
+        Plan::Select(mut plan) => {
+            let start_reg = emit_subquery(program, &mut plan, t_ctx)?;
+            let QueryDestination::CoroutineYield {
+                yield_reg,
+                coroutine_implementation_start,
+            } = plan.query_destination
+            else {
+                unreachable!("a CTE is planned as a coroutine");
+            };
+            Ok((yield_reg, coroutine_implementation_start, start_reg))
+        }
+        plan => emit_compound_subquery(
+            program,
+            plan,
+            t_ctx.resolver.schema,
+            t_ctx.resolver.symbol_table,
+        ),
+    }
+}
+
+/// Emits `plan` as a coroutine, followed by a loop that runs `emit_row` with the start register
+/// of each row of `plan`.
+fn emit_rows(
+    program: &mut ProgramBuilder,
+    t_ctx: &mut TranslateCtx,
+    plan: Plan,
+    emit_row: impl FnOnce(&mut ProgramBuilder, usize),
+) -> Result<()> {
+    let (yield_reg, _, start_reg) = emit_coroutine(program, t_ctx, plan)?;
+    let label_loop_start = program.allocate_label();
+    let label_loop_end = program.allocate_label();
+    program.preassign_label_to_next_insn(label_loop_start);
+    program.emit_insn(Insn::Yield {
+        yield_reg,
+        end_offset: label_loop_end,
+    });
+    emit_row(program, start_reg);
+    program.emit_insn(Insn::Goto {
+        target_pc: label_loop_start,
+    });
+    program.preassign_label_to_next_insn(label_loop_end);
+    Ok(())
+}
+
+fn emit_insert_row(
+    program: &mut ProgramBuilder,
+    table: &BTreeTable,
+    cursor_id: usize,
+    start_reg: usize,
+) {
+    let record_reg = program.alloc_register();
+    let rowid_reg = program.alloc_register();
+    program.emit_insn(Insn::MakeRecord {
+        start_reg,
+        count: table.columns.len(),
+        dest_reg: record_reg,
+        index_name: None,
+    });
+    program.emit_insn(Insn::NewRowid {
+        cursor: cursor_id,
+        rowid_reg,
+        prev_largest_reg: 0,
+    });
+    program.emit_insn(Insn::Insert {
+        cursor: cursor_id,
+        key_reg: rowid_reg,
+        record_reg,
+        flag: InsertFlags::new(),
+        table_name: table.name.clone(),
+    });
+}
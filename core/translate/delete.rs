@@ -2,18 +2,25 @@ use crate::schema::Table;
 use crate::translate::emitter::emit_program;
 use crate::translate::optimizer::optimize_plan;
 use crate::translate::plan::{DeletePlan, Operation, Plan};
-use crate::translate::planner::{parse_limit, parse_where};
+use crate::translate::planner::{parse_limit, parse_where, resolve_index_hint};
 use crate::vdbe::builder::{ProgramBuilder, ProgramBuilderOpts, TableRefIdCounter};
 use crate::{schema::Schema, Result, SymbolTable};
 use std::sync::Arc;
-use turso_sqlite3_parser::ast::{Expr, Limit, QualifiedName, ResultColumn};
+use turso_sqlite3_parser::ast::{
+    Expr, Indexed, Limit, QualifiedName, ResultColumn, TriggerEvent, With,
+};
 
+use super::cte::parse_dml_with;
+use super::instead_of::translate_view_delete;
 use super::plan::{ColumnUsedMask, IterationDirection, JoinedTable, TableReferences};
+use super::subquery::plan_subqueries_from_expr;
+use super::trigger::TableTriggers;
 
 #[allow(clippy::too_many_arguments)]
 pub fn translate_delete(
-    schema: &Schema,
+    with: Option<With>,
     tbl_name: &QualifiedName,
+    indexed: Option<Indexed>,
     where_clause: Option<Box<Expr>>,
     limit: Option<Box<Limit>>,
     returning: Option<Vec<ResultColumn>>,
@@ -21,6 +28,24 @@ pub fn translate_delete(
     mut program: ProgramBuilder,
     connection: &Arc<crate::Connection>,
 ) -> Result<ProgramBuilder> {
+    let database_id = connection.resolve_database_id(tbl_name)?;
+    let schema = &connection.database_schema(database_id);
+    if let Some(view) = schema.get_view(tbl_name.name.as_str()) {
+        return translate_view_delete(
+            schema,
+            database_id,
+            &view,
+            with,
+            tbl_name,
+            indexed,
+            where_clause,
+            limit,
+            returning,
+            syms,
+            program,
+            connection,
+        );
+    }
     if schema.table_has_indexes(&tbl_name.name.to_string()) && !schema.indexes_enabled() {
         // Let's disable altering a table with indices altogether instead of checking column by
         // column to be extra safe.
@@ -40,17 +65,34 @@ pub fn translate_delete(
 
     let mut delete_plan = prepare_delete_plan(
         schema,
+        database_id,
+        with,
         tbl_name,
+        indexed,
         where_clause,
         limit,
         result_columns,
+        syms,
         &mut program.table_reference_counter,
         connection,
     )?;
     optimize_plan(&mut delete_plan, schema)?;
-    let Plan::Delete(ref delete) = delete_plan else {
+    let Plan::Delete(ref mut delete) = delete_plan else {
         panic!("delete_plan is not a DeletePlan");
     };
+    if let Some(btree_table) = delete.table_references.joined_tables()[0].btree() {
+        let triggers = TableTriggers::compile(
+            &program,
+            schema,
+            database_id,
+            syms,
+            connection,
+            &btree_table,
+            TriggerEvent::Delete,
+            &[],
+        )?;
+        delete.triggers = (!triggers.is_empty()).then_some(triggers);
+    }
     let opts = ProgramBuilderOpts {
         num_cursors: 1,
         approx_num_insns: estimate_num_instructions(delete),
@@ -61,15 +103,23 @@ pub fn translate_delete(
     Ok(program)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn prepare_delete_plan(
     schema: &Schema,
+    database_id: usize,
+    with: Option<With>,
     tbl_name: &QualifiedName,
+    indexed: Option<Indexed>,
     where_clause: Option<Box<Expr>>,
     limit: Option<Box<Limit>>,
     result_columns: Vec<super::plan::ResultSetColumn>,
+    syms: &SymbolTable,
     table_ref_counter: &mut TableRefIdCounter,
     connection: &Arc<crate::Connection>,
 ) -> Result<Plan> {
+    if schema.get_view(tbl_name.name.as_str()).is_some() {
+        crate::bail_parse_error!("cannot modify {} because it is a view", tbl_name.name);
+    }
     let table = match schema.get_table(tbl_name.name.as_str()) {
         Some(table) => table,
         None => crate::bail_parse_error!("no such table: {}", tbl_name),
@@ -83,6 +133,7 @@ pub fn prepare_delete_plan(
     };
     let name = tbl_name.name.as_str().to_string();
     let indexes = schema.get_indices(table.get_name()).to_vec();
+    let index_hint = resolve_index_hint(indexed, &indexes)?;
     let joined_tables = vec![JoinedTable {
         table,
         identifier: name,
@@ -93,9 +144,11 @@ pub fn prepare_delete_plan(
         },
         join_info: None,
         col_used_mask: ColumnUsedMask::default(),
-        database_id: 0,
+        database_id,
+        index_hint,
     }];
     let mut table_references = TableReferences::new(joined_tables, vec![]);
+    let ctes = parse_dml_with(with)?;
 
     let mut where_predicates = vec![];
 
@@ -107,6 +160,19 @@ pub fn prepare_delete_plan(
         &mut where_predicates,
         connection,
     )?;
+    let mut non_from_clause_subqueries = vec![];
+    for term in where_predicates.iter_mut() {
+        plan_subqueries_from_expr(
+            schema,
+            syms,
+            &mut term.expr,
+            &mut table_references,
+            &mut non_from_clause_subqueries,
+            &ctes,
+            table_ref_counter,
+            connection,
+        )?;
+    }
 
     // Parse the LIMIT/OFFSET clause
     let (resolved_limit, resolved_offset) = limit.map_or(Ok((None, None)), |l| parse_limit(&l))?;
@@ -120,6 +186,8 @@ pub fn prepare_delete_plan(
         offset: resolved_offset,
         contains_constant_false_condition: false,
         indexes,
+        triggers: None,
+        non_from_clause_subqueries,
     };
 
     Ok(Plan::Delete(plan))
@@ -13,10 +13,20 @@ use turso_sqlite3_parser::{
 use crate::{schema::Table, translate::plan::TableReferences};
 
 use super::plan::{
-    Aggregate, DeletePlan, JoinedTable, Operation, Plan, ResultSetColumn, Search, SelectPlan,
-    UpdatePlan,
+    Aggregate, DeletePlan, IndexHint, JoinedTable, Operation, Plan, ResultSetColumn, Search,
+    SelectPlan, UpdatePlan,
 };
 
+/// The INDEXED BY or NOT INDEXED clause of a table reference, shown after its access method
+/// since it is not the choice of the optimizer.
+fn index_hint_suffix(reference: &JoinedTable) -> String {
+    match &reference.index_hint {
+        Some(IndexHint::IndexedBy(index)) => format!(" (INDEXED BY {})", index.name),
+        Some(IndexHint::NotIndexed) => " (NOT INDEXED)".to_string(),
+        None => String::new(),
+    }
+}
+
 impl Display for Aggregate {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         let args_str = self
@@ -100,14 +110,20 @@ impl Display for SelectPlan {
                         format!("{} AS {}", reference.table.get_name(), reference.identifier)
                     };
 
-                    writeln!(f, "{indent}SCAN {table_name}")?;
+                    writeln!(
+                        f,
+                        "{indent}SCAN {table_name}{}",
+                        index_hint_suffix(reference)
+                    )?;
                 }
                 Operation::Search(search) => match search {
                     Search::RowidEq { .. } | Search::Seek { index: None, .. } => {
                         writeln!(
                             f,
-                            "{}SEARCH {} USING INTEGER PRIMARY KEY (rowid=?)",
-                            indent, reference.identifier
+                            "{}SEARCH {} USING INTEGER PRIMARY KEY (rowid=?){}",
+                            indent,
+                            reference.identifier,
+                            index_hint_suffix(reference)
                         )?;
                     }
                     Search::Seek {
@@ -115,8 +131,11 @@ impl Display for SelectPlan {
                     } => {
                         writeln!(
                             f,
-                            "{}SEARCH {} USING INDEX {}",
-                            indent, reference.identifier, index.name
+                            "{}SEARCH {} USING INDEX {}{}",
+                            indent,
+                            reference.identifier,
+                            index.name,
+                            index_hint_suffix(reference)
                         )?;
                     }
                 },
@@ -142,7 +161,11 @@ impl Display for DeletePlan {
                         format!("{} AS {}", reference.table.get_name(), reference.identifier)
                     };
 
-                    writeln!(f, "{indent}DELETE FROM {table_name}")?;
+                    writeln!(
+                        f,
+                        "{indent}DELETE FROM {table_name}{}",
+                        index_hint_suffix(reference)
+                    )?;
                 }
                 Operation::Search { .. } => {
                     panic!("DELETE plans should not contain search operations");
@@ -177,18 +200,21 @@ impl fmt::Display for UpdatePlan {
                         format!("{} AS {}", reference.table.get_name(), reference.identifier)
                     };
 
+                    let hint = index_hint_suffix(reference);
                     if i == 0 {
-                        writeln!(f, "{indent}UPDATE {table_name}")?;
+                        writeln!(f, "{indent}UPDATE {table_name}{hint}")?;
                     } else {
-                        writeln!(f, "{indent}SCAN {table_name}")?;
+                        writeln!(f, "{indent}SCAN {table_name}{hint}")?;
                     }
                 }
                 Operation::Search(search) => match search {
                     Search::RowidEq { .. } | Search::Seek { index: None, .. } => {
                         writeln!(
                             f,
-                            "{}SEARCH {} USING INTEGER PRIMARY KEY (rowid=?)",
-                            indent, reference.identifier
+                            "{}SEARCH {} USING INTEGER PRIMARY KEY (rowid=?){}",
+                            indent,
+                            reference.identifier,
+                            index_hint_suffix(reference)
                         )?;
                     }
                     Search::Seek {
@@ -196,8 +222,11 @@ impl fmt::Display for UpdatePlan {
                     } => {
                         writeln!(
                             f,
-                            "{}SEARCH {} USING INDEX {}",
-                            indent, reference.identifier, index.name
+                            "{}SEARCH {} USING INDEX {}{}",
+                            indent,
+                            reference.identifier,
+                            index.name,
+                            index_hint_suffix(reference)
                         )?;
                     }
                 },
@@ -335,7 +364,7 @@ impl ToTokens for JoinedTable {
         _context: &C,
     ) -> Result<(), S::Error> {
         match &self.table {
-            Table::BTree(..) | Table::Virtual(..) => {
+            Table::BTree(..) | Table::Virtual(..) | Table::Cte(..) => {
                 let name = self.table.get_name();
                 s.append(TokenType::TK_ID, Some(name))?;
                 if self.identifier != name {
@@ -2,30 +2,39 @@
 // It handles translating high-level SQL operations into low-level bytecode that can be executed by the virtual machine.
 
 use std::rc::Rc;
+use std::sync::Arc;
 
 use tracing::{instrument, Level};
 use turso_sqlite3_parser::ast::{self, Expr};
 
 use super::aggregation::emit_ungrouped_aggregation;
-use super::expr::translate_expr;
+use super::cte::emit_materialized_ctes;
+use super::expr::{translate_expr, translate_expr_no_constant_opt, NoConstantOptReason};
 use super::group_by::{
     group_by_agg_phase, group_by_emit_row_phase, init_group_by, GroupByMetadata, GroupByRowSource,
 };
 use super::main_loop::{
-    close_loop, emit_loop, init_distinct, init_loop, open_loop, LeftJoinMetadata, LoopLabels,
+    close_loop, emit_loop, emit_loop_with_right_joins, init_distinct, init_loop, open_loop,
+    LeftJoinMetadata, LoopLabels, RightJoinMetadata,
 };
 use super::order_by::{emit_order_by, init_order_by, SortMetadata};
 use super::plan::{
-    Distinctness, JoinOrderMember, Operation, SelectPlan, TableReferences, UpdatePlan,
+    Distinctness, JoinOrderMember, NonFromClauseSubquery, Operation, SelectPlan, TableReferences,
+    UpdatePlan,
 };
 use super::select::emit_simple_count;
 use super::subquery::emit_subqueries;
-use crate::error::SQLITE_CONSTRAINT_PRIMARYKEY;
+use super::window::{emit_window, init_window, WindowMetadata};
+use crate::error::{SQLITE_CONSTRAINT_NOTNULL, SQLITE_CONSTRAINT_PRIMARYKEY};
 use crate::function::Func;
-use crate::schema::{Schema, Table};
+use crate::schema::{BTreeTable, Column, Index, Schema, Table};
 use crate::translate::compound_select::emit_program_for_compound_select;
 use crate::translate::expr::{emit_returning_results, ReturningValueRegisters};
+use crate::translate::fkey::ForeignKeyChecks;
+use crate::translate::generated::{emit_row_from_cursor, emit_table_record, GeneratedColumns};
+use crate::translate::index::emit_index_entry_from_row;
 use crate::translate::plan::{DeletePlan, Plan, QueryDestination, Search};
+use crate::translate::trigger::{TableTriggers, TriggerParams};
 use crate::translate::values::emit_values;
 use crate::util::exprs_are_equivalent;
 use crate::vdbe::builder::{CursorKey, CursorType, ProgramBuilder};
@@ -39,6 +48,8 @@ pub struct Resolver<'a> {
     pub symbol_table: &'a SymbolTable,
     pub expr_to_reg_cache_enabled: bool,
     pub expr_to_reg_cache: Vec<(&'a ast::Expr, usize)>,
+    /// The subqueries used as expressions by the query being translated.
+    pub subqueries: Vec<NonFromClauseSubquery>,
 }
 
 impl<'a> Resolver<'a> {
@@ -48,6 +59,7 @@ impl<'a> Resolver<'a> {
             symbol_table,
             expr_to_reg_cache_enabled: false,
             expr_to_reg_cache: Vec::new(),
+            subqueries: Vec::new(),
         }
     }
 
@@ -129,9 +141,14 @@ pub struct TranslateCtx<'a> {
     pub meta_group_by: Option<GroupByMetadata>,
     // metadata for the order by operator
     pub meta_sort: Option<SortMetadata>,
+    // metadata for the window function stages
+    pub meta_window: Option<WindowMetadata>,
     /// mapping between table loop index and associated metadata (for left joins only)
     /// this metadata exists for the right table in a given left join
     pub meta_left_joins: Vec<Option<LeftJoinMetadata>>,
+    /// mapping between table loop index and associated metadata (for right joins only)
+    /// this metadata exists for the right table in a given right or full outer join
+    pub meta_right_joins: Vec<Option<RightJoinMetadata>>,
     // We need to emit result columns in the order they are present in the SELECT, but they may not be in the same order in the ORDER BY sorter.
     // This vector holds the indexes of the result columns in the ORDER BY sorter.
     pub result_column_indexes_in_orderby_sorter: Vec<usize>,
@@ -173,7 +190,9 @@ impl<'a> TranslateCtx<'a> {
             reg_result_cols_start: None,
             meta_group_by: None,
             meta_left_joins: (0..table_count).map(|_| None).collect(),
+            meta_right_joins: (0..table_count).map(|_| None).collect(),
             meta_sort: None,
+            meta_window: None,
             result_column_indexes_in_orderby_sorter: (0..result_column_count).collect(),
             result_columns_to_skip_in_orderby_sorter: None,
             resolver: Resolver::new(schema, syms),
@@ -213,7 +232,7 @@ pub fn emit_program(
     match plan {
         Plan::Select(plan) => emit_program_for_select(program, plan, schema, syms),
         Plan::Delete(plan) => emit_program_for_delete(program, plan, schema, syms),
-        Plan::Update(plan) => emit_program_for_update(program, plan, schema, syms, after),
+        Plan::Update(plan) => emit_program_for_update(program, *plan, schema, syms, after),
         Plan::CompoundSelect { .. } => {
             emit_program_for_compound_select(program, plan, schema, syms)
         }
@@ -244,11 +263,15 @@ fn emit_program_for_select(
             return Ok(());
         }
     }
+    // Subqueries in expressions may read tables even if the query itself does not.
+    let reads_tables = !plan.table_references.joined_tables().is_empty()
+        || !plan.non_from_clause_subqueries.is_empty();
+
     // Emit main parts of query
     emit_query(program, &mut plan, &mut t_ctx)?;
 
     // Finalize program
-    if plan.table_references.joined_tables().is_empty() {
+    if !reads_tables {
         program.epilogue(TransactionMode::None);
     } else {
         program.epilogue(TransactionMode::Read);
@@ -269,8 +292,18 @@ pub fn emit_query<'a>(
         let reg_result_cols_start = emit_values(program, plan, &t_ctx.resolver)?;
         return Ok(reg_result_cols_start);
     }
+    t_ctx.resolver.subqueries = std::mem::take(&mut plan.non_from_clause_subqueries);
 
-    // Emit subqueries first so the results can be read in the main query loop.
+    // Allocate registers for result columns. This happens before the subqueries allocate any
+    // registers, because INSERT INTO ... SELECT expects the result columns right after its
+    // coroutine register.
+    if t_ctx.reg_result_cols_start.is_none() {
+        t_ctx.reg_result_cols_start = Some(program.alloc_registers(plan.result_columns.len()));
+    }
+
+    // Compute the materialized CTEs, and then the subqueries, so the results can be read in the
+    // main query loop.
+    emit_materialized_ctes(program, t_ctx, &plan.materialized_ctes)?;
     emit_subqueries(program, t_ctx, &mut plan.table_references)?;
 
     init_limit(program, t_ctx, plan.limit, plan.offset);
@@ -298,11 +331,6 @@ pub fn emit_query<'a>(
         t_ctx.reg_nonagg_emit_once_flag = Some(flag);
     }
 
-    // Allocate registers for result columns
-    if t_ctx.reg_result_cols_start.is_none() {
-        t_ctx.reg_result_cols_start = Some(program.alloc_registers(plan.result_columns.len()));
-    }
-
     // Initialize cursors and other resources needed for query execution
     if let Some(ref mut order_by) = plan.order_by {
         init_order_by(program, t_ctx, order_by, &plan.table_references)?;
@@ -323,6 +351,10 @@ pub fn emit_query<'a>(
         t_ctx.reg_agg_start = Some(program.alloc_registers_and_init_w_null(plan.aggregates.len()));
     }
 
+    if let Some(ref window) = plan.window {
+        init_window(program, t_ctx, window, &plan.table_references)?;
+    }
+
     let distinct_ctx = if let Distinctness::Distinct { .. } = &plan.distinctness {
         Some(init_distinct(program, plan))
     } else {
@@ -356,25 +388,32 @@ pub fn emit_query<'a>(
         None,
     )?;
 
-    // Process result columns and expressions in the inner loop
-    emit_loop(program, t_ctx, plan)?;
+    if plan.join_order.iter().any(|member| member.is_right) {
+        // The unmatched rows of a RIGHT JOIN are emitted after the main loop, which is closed here.
+        emit_loop_with_right_joins(program, t_ctx, plan)?;
+    } else {
+        // Process result columns and expressions in the inner loop
+        emit_loop(program, t_ctx, plan)?;
 
-    // Clean up and close the main execution loop
-    close_loop(
-        program,
-        t_ctx,
-        &plan.table_references,
-        &plan.join_order,
-        None,
-    )?;
+        // Clean up and close the main execution loop
+        close_loop(
+            program,
+            t_ctx,
+            &plan.table_references,
+            &plan.join_order,
+            None,
+        )?;
+    }
 
     program.preassign_label_to_next_insn(after_main_loop_label);
 
     let mut order_by_necessary = plan.order_by.is_some() && !plan.contains_constant_false_condition;
     let order_by = plan.order_by.as_ref();
 
-    // Handle GROUP BY and aggregation processing
-    if plan.group_by.is_some() {
+    // Handle GROUP BY, aggregation and window function processing
+    if plan.window.is_some() {
+        emit_window(program, t_ctx, plan)?;
+    } else if plan.group_by.is_some() {
         let row_source = &t_ctx
             .meta_group_by
             .as_ref()
@@ -402,7 +441,7 @@ pub fn emit_query<'a>(
 #[instrument(skip_all, level = Level::DEBUG)]
 fn emit_program_for_delete(
     program: &mut ProgramBuilder,
-    plan: DeletePlan,
+    mut plan: DeletePlan,
     schema: &Schema,
     syms: &SymbolTable,
 ) -> Result<()> {
@@ -422,6 +461,11 @@ fn emit_program_for_delete(
         return Ok(());
     }
 
+    t_ctx.resolver.subqueries = std::mem::take(&mut plan.non_from_clause_subqueries);
+    let join_order = [JoinOrderMember {
+        table_id: plan.table_references.joined_tables()[0].internal_id,
+        ..Default::default()
+    }];
     init_limit(program, &mut t_ctx, plan.limit, None);
 
     // No rows will be read from source table loops if there is a constant false condition eg. WHERE 0
@@ -449,7 +493,7 @@ fn emit_program_for_delete(
         program,
         &mut t_ctx,
         &plan.table_references,
-        &[JoinOrderMember::default()],
+        &join_order,
         &plan.where_clause,
         None,
     )?;
@@ -459,6 +503,7 @@ fn emit_program_for_delete(
         &mut t_ctx,
         &plan.table_references,
         &plan.result_columns,
+        plan.triggers.as_ref(),
     )?;
 
     // Clean up and close the main execution loop
@@ -466,7 +511,7 @@ fn emit_program_for_delete(
         program,
         &mut t_ctx,
         &plan.table_references,
-        &[JoinOrderMember::default()],
+        &join_order,
         None,
     )?;
     program.preassign_label_to_next_insn(after_main_loop_label);
@@ -483,6 +528,7 @@ fn emit_delete_insns(
     t_ctx: &mut TranslateCtx,
     table_references: &TableReferences,
     result_columns: &[super::plan::ResultSetColumn],
+    triggers: Option<&TableTriggers>,
 ) -> Result<()> {
     let table_reference = table_references.joined_tables().first().unwrap();
     if table_reference
@@ -492,33 +538,27 @@ fn emit_delete_insns(
         return Err(crate::LimboError::ReadOnly);
     }
 
-    let cursor_id = match &table_reference.op {
-        Operation::Scan { .. } => {
-            program.resolve_cursor_id(&CursorKey::table(table_reference.internal_id))
-        }
-        Operation::Search(search) => match search {
-            Search::RowidEq { .. } | Search::Seek { index: None, .. } => {
-                program.resolve_cursor_id(&CursorKey::table(table_reference.internal_id))
-            }
-            Search::Seek {
-                index: Some(index), ..
-            } => program.resolve_cursor_id(&CursorKey::index(
+    // The rows of a table without rowid are deleted from its clustered index
+    let main_table_cursor_id = program
+        .resolve_cursor_id_safe(&CursorKey::table(table_reference.internal_id))
+        .unwrap_or_else(|| {
+            let index = table_reference
+                .op
+                .index()
+                .expect("table without rowid is scanned through its clustered index");
+            program.resolve_cursor_id(&CursorKey::index(
                 table_reference.internal_id,
                 index.clone(),
-            )),
-        },
-    };
-    let main_table_cursor_id =
-        program.resolve_cursor_id(&CursorKey::table(table_reference.internal_id));
-
-    // Emit the instructions to delete the row
-    let key_reg = program.alloc_register();
-    program.emit_insn(Insn::RowId {
-        cursor_id: main_table_cursor_id,
-        dest: key_reg,
-    });
+            ))
+        });
 
     if table_reference.virtual_table().is_some() {
+        // Emit the instructions to delete the row
+        let key_reg = program.alloc_register();
+        program.emit_insn(Insn::RowId {
+            cursor_id: main_table_cursor_id,
+            dest: key_reg,
+        });
         let conflict_action = 0u16;
         let start_reg = key_reg;
 
@@ -528,12 +568,41 @@ fn emit_delete_insns(
             dest_end: None,
         });
         program.emit_insn(Insn::VUpdate {
-            cursor_id,
+            cursor_id: main_table_cursor_id,
             arg_count: 2,
             start_reg,
             conflict_action,
         });
     } else {
+        let loop_labels = *t_ctx.labels_main_loop.first().unwrap();
+        let trigger_params = if let Some(triggers) = triggers {
+            let btree_table = table_reference.btree().unwrap();
+            let params = triggers.alloc_params(program);
+            params.emit_old_from_cursor(
+                program,
+                &t_ctx.resolver,
+                &btree_table,
+                main_table_cursor_id,
+            )?;
+            params.emit_null_new(program);
+            if !triggers.before.is_empty() {
+                triggers.emit_before(program, &params, loop_labels.next);
+                // The BEFORE triggers may have moved the cursor or deleted the row
+                emit_seek_row(
+                    program,
+                    &btree_table,
+                    main_table_cursor_id,
+                    params.old_rowid_reg(),
+                    params.old_columns_start_reg(),
+                    loop_labels.next,
+                );
+            }
+            triggers.emit_old_foreign_key_checks(program, &params);
+            Some(params)
+        } else {
+            None
+        };
+
         // Delete from all indexes before deleting from the main table.
         let indexes = t_ctx
             .resolver
@@ -555,32 +624,28 @@ fn emit_delete_insns(
                 .collect::<Vec<_>>()
         });
 
-        if let Some(index_refs) = index_refs_opt {
-            for (index, index_cursor_id) in index_refs {
-                let num_regs = index.columns.len() + 1;
-                let start_reg = program.alloc_registers(num_regs);
-                // Emit columns that are part of the index
-                index
-                    .columns
-                    .iter()
-                    .enumerate()
-                    .for_each(|(reg_offset, column_index)| {
-                        program.emit_column(
-                            main_table_cursor_id,
-                            column_index.pos_in_table,
-                            start_reg + reg_offset,
-                        );
-                    });
-                program.emit_insn(Insn::RowId {
-                    cursor_id: main_table_cursor_id,
-                    dest: start_reg + num_regs - 1,
-                });
+        if let (Some(index_refs), Some(btree_table)) = (index_refs_opt, table_reference.btree()) {
+            // The clustered index of a table without rowid is the table itself
+            for (index, index_cursor_id) in index_refs
+                .into_iter()
+                .filter(|(_, id)| *id != main_table_cursor_id)
+            {
+                let not_indexed_label = program.allocate_label();
+                let (start_reg, num_regs) = emit_index_entry(
+                    program,
+                    &t_ctx.resolver,
+                    &btree_table,
+                    main_table_cursor_id,
+                    &index,
+                    not_indexed_label,
+                )?;
                 program.emit_insn(Insn::IdxDelete {
                     start_reg,
                     num_regs,
                     cursor_id: index_cursor_id,
                     raise_error_if_no_matching_entry: true,
                 });
+                program.preassign_label_to_next_insn(not_indexed_label);
             }
         }
 
@@ -618,17 +683,27 @@ fn emit_delete_insns(
         if !result_columns.is_empty() {
             // Get rowid for RETURNING
             let rowid_reg = program.alloc_register();
-            program.emit_insn(Insn::RowId {
-                cursor_id: main_table_cursor_id,
-                dest: rowid_reg,
-            });
+            if table_reference.btree().is_some_and(|table| table.has_rowid) {
+                program.emit_insn(Insn::RowId {
+                    cursor_id: main_table_cursor_id,
+                    dest: rowid_reg,
+                });
+            }
 
             // Allocate registers for column values
             let columns_start_reg = program.alloc_registers(table_reference.columns().len());
 
             // Read all column values from the row to be deleted
             for (i, _column) in table_reference.columns().iter().enumerate() {
-                program.emit_column(main_table_cursor_id, i, columns_start_reg + i);
+                program.emit_table_column(main_table_cursor_id, i, columns_start_reg + i);
+            }
+            if let Some(btree_table) = table_reference.btree() {
+                GeneratedColumns::compile(program, &btree_table)?.emit_virtual(
+                    program,
+                    &t_ctx.resolver,
+                    rowid_reg,
+                    columns_start_reg,
+                )?;
             }
 
             // Emit RETURNING results using the values we just read
@@ -644,6 +719,12 @@ fn emit_delete_insns(
         program.emit_insn(Insn::Delete {
             cursor_id: main_table_cursor_id,
         });
+
+        if let (Some(triggers), Some(params)) = (triggers, &trigger_params) {
+            let row_done_label = program.allocate_label();
+            triggers.emit_after(program, params, row_done_label);
+            program.preassign_label_to_next_insn(row_done_label);
+        }
     }
     if let Some(limit_ctx) = t_ctx.limit_ctx {
         program.emit_insn(Insn::DecrJumpZero {
@@ -655,6 +736,259 @@ fn emit_delete_insns(
     Ok(())
 }
 
+/// Emits the NOT NULL check of the new value of a column, held in `reg`. A NULL is resolved
+/// with `on_conflict`: IGNORE skips the row by jumping to `skip_label`, REPLACE stores the
+/// default value of the column instead, and fails like ABORT when that is NULL too.
+pub fn emit_notnull_check(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    table_name: &str,
+    column: &Column,
+    reg: usize,
+    on_conflict: ast::ResolveType,
+    skip_label: BranchOffset,
+) -> Result<()> {
+    let description = format!(
+        "{}.{}",
+        table_name,
+        column.name.as_ref().expect("Column name must be present")
+    );
+    match (on_conflict, &column.default) {
+        (ast::ResolveType::Ignore, _) => {
+            program.emit_insn(Insn::IsNull {
+                reg,
+                target_pc: skip_label,
+            });
+            return Ok(());
+        }
+        (ast::ResolveType::Replace, Some(default)) => {
+            let not_null_label = program.allocate_label();
+            program.emit_insn(Insn::NotNull {
+                reg,
+                target_pc: not_null_label,
+            });
+            translate_expr_no_constant_opt(
+                program,
+                None,
+                default,
+                reg,
+                resolver,
+                NoConstantOptReason::RegisterReuse,
+            )?;
+            program.preassign_label_to_next_insn(not_null_label);
+        }
+        _ => {}
+    }
+    let on_error = match on_conflict {
+        ast::ResolveType::Ignore | ast::ResolveType::Replace => ast::ResolveType::Abort,
+        on_conflict => on_conflict,
+    };
+    program.emit_insn(Insn::HaltIfNull {
+        target_reg: reg,
+        err_code: SQLITE_CONSTRAINT_NOTNULL,
+        on_error,
+        description,
+    });
+    Ok(())
+}
+
+/// Deletes the row `cursor_id` points to and its entries in the indexes of the table, to make
+/// room for a row that conflicts with it under the REPLACE conflict resolution. Like SQLite with
+/// recursive_triggers off, the DELETE triggers of the table do not fire. `foreign_keys` counts
+/// the rows of other tables the deleted row was the parent of.
+pub fn emit_replace_delete(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    table: &Rc<BTreeTable>,
+    cursor_id: CursorID,
+    index_cursors: &[(Arc<Index>, CursorID)],
+    cdc_cursor_id: Option<CursorID>,
+    foreign_keys: &ForeignKeyChecks,
+) -> Result<()> {
+    if !foreign_keys.is_empty() {
+        let params = TriggerParams::alloc(program, table.columns.len());
+        params.emit_old_from_cursor(program, resolver, table, cursor_id)?;
+        foreign_keys.emit_old(program, &params);
+    }
+    // The clustered index of a table without rowid is the table itself
+    for (index, index_cursor_id) in index_cursors.iter().filter(|(_, id)| *id != cursor_id) {
+        let not_indexed_label = program.allocate_label();
+        let (start_reg, num_regs) = emit_index_entry(
+            program,
+            resolver,
+            table,
+            cursor_id,
+            index,
+            not_indexed_label,
+        )?;
+        program.emit_insn(Insn::IdxDelete {
+            start_reg,
+            num_regs,
+            cursor_id: *index_cursor_id,
+            raise_error_if_no_matching_entry: true,
+        });
+        program.preassign_label_to_next_insn(not_indexed_label);
+    }
+    if let Some(cdc_cursor_id) = cdc_cursor_id {
+        let rowid_reg = program.alloc_register();
+        program.emit_insn(Insn::RowId {
+            cursor_id,
+            dest: rowid_reg,
+        });
+        let table = Table::BTree(table.clone());
+        let before_record_reg = if program.capture_data_changes_mode().has_before() {
+            Some(emit_cdc_full_record(program, &table, cursor_id, rowid_reg))
+        } else {
+            None
+        };
+        emit_cdc_insns(
+            program,
+            resolver,
+            OperationMode::DELETE,
+            cdc_cursor_id,
+            rowid_reg,
+            before_record_reg,
+            None,
+            table.get_name(),
+        )?;
+    }
+    program.emit_insn(Insn::Delete { cursor_id });
+    Ok(())
+}
+
+/// Emits the entry in `index` of the row the table cursor `cursor_id` is positioned on: the
+/// indexed columns, followed by the rowid if the table has one. Returns the first register of
+/// the entry and the number of registers. Jumps to `not_indexed_label` if the index is partial
+/// and the row has no entry in it.
+pub fn emit_index_entry(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    table: &Rc<BTreeTable>,
+    cursor_id: CursorID,
+    index: &Index,
+    not_indexed_label: BranchOffset,
+) -> Result<(usize, usize)> {
+    let num_regs = index.columns.len() + usize::from(index.has_rowid);
+    let start_reg = program.alloc_registers(num_regs);
+    if index.has_expressions()
+        || index.where_clause.is_some()
+        || index
+            .columns
+            .iter()
+            .any(|column| table.columns[column.pos_in_table].is_virtual())
+    {
+        // The expressions and VIRTUAL columns of the entry are computed from the row
+        let rowid_reg = program.alloc_registers(table.columns.len() + 1);
+        emit_row_from_cursor(
+            program,
+            resolver,
+            table,
+            cursor_id,
+            rowid_reg,
+            rowid_reg + 1,
+        )?;
+        emit_index_entry_from_row(
+            program,
+            resolver,
+            table,
+            index,
+            rowid_reg,
+            rowid_reg + 1,
+            start_reg,
+            not_indexed_label,
+        )?;
+        return Ok((start_reg, num_regs));
+    }
+    for (i, column) in index.columns.iter().enumerate() {
+        program.emit_table_column(cursor_id, column.pos_in_table, start_reg + i);
+    }
+    if index.has_rowid {
+        program.emit_insn(Insn::RowId {
+            cursor_id,
+            dest: start_reg + num_regs - 1,
+        });
+    }
+    Ok((start_reg, num_regs))
+}
+
+/// Positions the table cursor `cursor_id` on the row of the entry `index_cursor_id` points to,
+/// or jumps to `not_found_label` if there is no such row. The row of a table without rowid is
+/// found by the primary key columns its index entries end with.
+pub fn emit_seek_row_of_index_entry(
+    program: &mut ProgramBuilder,
+    table: &BTreeTable,
+    cursor_id: CursorID,
+    index: &Index,
+    index_cursor_id: CursorID,
+    not_found_label: BranchOffset,
+) {
+    if table.has_rowid {
+        let rowid_reg = program.alloc_register();
+        program.emit_insn(Insn::IdxRowId {
+            cursor_id: index_cursor_id,
+            dest: rowid_reg,
+        });
+        program.emit_insn(Insn::NotExists {
+            cursor: cursor_id,
+            rowid_reg,
+            target_pc: not_found_label,
+        });
+        return;
+    }
+    let num_regs = table.primary_key_columns.len();
+    let start_reg = program.alloc_registers(num_regs);
+    for (i, (name, _)) in table.primary_key_columns.iter().enumerate() {
+        let (pos_in_table, _) = table.get_column(name).unwrap();
+        let column = index
+            .column_table_pos_to_index_pos(pos_in_table)
+            .expect("indexes of a table without rowid must store the primary key");
+        program.emit_column(index_cursor_id, column, start_reg + i);
+    }
+    program.emit_insn(Insn::NotFound {
+        cursor_id,
+        target_pc: not_found_label,
+        record_reg: start_reg,
+        num_regs,
+    });
+}
+
+/// Positions the table cursor `cursor_id` on the row whose rowid is in `rowid_reg` and whose
+/// columns start at `columns_start_reg`, or jumps to `not_found_label` if there is no such row.
+/// The row of a table without rowid is found by its primary key columns.
+pub fn emit_seek_row(
+    program: &mut ProgramBuilder,
+    table: &BTreeTable,
+    cursor_id: CursorID,
+    rowid_reg: usize,
+    columns_start_reg: usize,
+    not_found_label: BranchOffset,
+) {
+    if table.has_rowid {
+        program.emit_insn(Insn::NotExists {
+            cursor: cursor_id,
+            rowid_reg,
+            target_pc: not_found_label,
+        });
+        return;
+    }
+    let num_regs = table.primary_key_columns.len();
+    let start_reg = program.alloc_registers(num_regs);
+    for (i, (name, _)) in table.primary_key_columns.iter().enumerate() {
+        let (pos_in_table, _) = table.get_column(name).unwrap();
+        program.emit_insn(Insn::Copy {
+            src_reg: columns_start_reg + pos_in_table,
+            dst_reg: start_reg + i,
+            extra_amount: 0,
+        });
+    }
+    program.emit_insn(Insn::NotFound {
+        cursor_id,
+        target_pc: not_found_label,
+        record_reg: start_reg,
+        num_regs,
+    });
+}
+
 #[instrument(skip_all, level = Level::DEBUG)]
 fn emit_program_for_update(
     program: &mut ProgramBuilder,
@@ -679,6 +1013,11 @@ fn emit_program_for_update(
         return Ok(());
     }
 
+    t_ctx.resolver.subqueries = std::mem::take(&mut plan.non_from_clause_subqueries);
+    let join_order = [JoinOrderMember {
+        table_id: plan.table_references.joined_tables()[0].internal_id,
+        ..Default::default()
+    }];
     init_limit(program, &mut t_ctx, plan.limit, plan.offset);
     let after_main_loop_label = program.allocate_label();
     t_ctx.label_main_loop_end = Some(after_main_loop_label);
@@ -689,16 +1028,18 @@ fn emit_program_for_update(
     }
 
     let ephemeral_plan = plan.ephemeral_plan.take();
-    let temp_cursor_id = ephemeral_plan.as_ref().map(|plan| {
-        let QueryDestination::EphemeralTable { cursor_id, .. } = &plan.query_destination else {
+    let temp_cursor = ephemeral_plan.as_ref().map(|plan| {
+        let QueryDestination::EphemeralTable { cursor_id, table } = &plan.query_destination else {
             unreachable!()
         };
-        *cursor_id
+        (*cursor_id, table.has_rowid)
     });
+    let temp_cursor_id = temp_cursor.map(|(cursor_id, _)| cursor_id);
     if let Some(ephemeral_plan) = ephemeral_plan {
+        let (cursor_id, is_table) = temp_cursor.unwrap();
         program.emit_insn(Insn::OpenEphemeral {
-            cursor_id: temp_cursor_id.unwrap(),
-            is_table: true,
+            cursor_id,
+            is_table,
         });
         program.incr_nesting();
         emit_program_for_select(program, ephemeral_plan, schema, syms)?;
@@ -733,7 +1074,7 @@ fn emit_program_for_update(
             program.emit_insn(Insn::OpenWrite {
                 cursor_id: cursor,
                 root_page: RegisterOrLiteral::Literal(index.root_page),
-                db: 0,
+                db: plan.table_references.joined_tables()[0].database_id,
             });
             cursor
         };
@@ -746,7 +1087,7 @@ fn emit_program_for_update(
         program,
         &mut t_ctx,
         &plan.table_references,
-        &[JoinOrderMember::default()],
+        &join_order,
         &plan.where_clause,
         temp_cursor_id,
     )?;
@@ -759,7 +1100,7 @@ fn emit_program_for_update(
         program,
         &mut t_ctx,
         &plan.table_references,
-        &[JoinOrderMember::default()],
+        &join_order,
         temp_cursor_id,
     )?;
 
@@ -783,6 +1124,19 @@ fn emit_update_insns(
     temp_cursor_id: Option<CursorID>,
 ) -> crate::Result<()> {
     let table_ref = plan.table_references.joined_tables().first().unwrap();
+    if let Some(btree_table) = table_ref
+        .btree()
+        .filter(|btree_table| !btree_table.has_rowid)
+    {
+        return emit_update_insns_without_rowid(
+            plan,
+            t_ctx,
+            program,
+            &btree_table,
+            index_cursors,
+            temp_cursor_id,
+        );
+    }
     let loop_labels = t_ctx.labels_main_loop.first().unwrap();
     let cursor_id = program.resolve_cursor_id(&CursorKey::table(table_ref.internal_id));
     let (index, is_virtual) = match &table_ref.op {
@@ -853,6 +1207,13 @@ fn emit_update_insns(
             rowid_reg: beg,
             target_pc: check_rowid_not_exists_label.unwrap(),
         });
+    } else if temp_cursor_id.is_some() {
+        // the rowid comes from the ephemeral table, position the table cursor on the row
+        program.emit_insn(Insn::NotExists {
+            cursor: cursor_id,
+            rowid_reg: beg,
+            target_pc: loop_labels.next,
+        });
     } else {
         // if no rowid, we're done
         program.emit_insn(Insn::IsNull {
@@ -910,19 +1271,17 @@ fn emit_update_insns(
                     &t_ctx.resolver,
                 )?;
                 if table_column.notnull {
-                    use crate::error::SQLITE_CONSTRAINT_NOTNULL;
-                    program.emit_insn(Insn::HaltIfNull {
+                    emit_notnull_check(
+                        program,
+                        &t_ctx.resolver,
+                        table_ref.table.get_name(),
+                        table_column,
                         target_reg,
-                        err_code: SQLITE_CONSTRAINT_NOTNULL,
-                        description: format!(
-                            "{}.{}",
-                            table_ref.table.get_name(),
-                            table_column
-                                .name
-                                .as_ref()
-                                .expect("Column name must be present")
-                        ),
-                    });
+                        plan.or_conflict
+                            .or_else(|| table_ref.btree()?.notnull_conflict_clause(table_column))
+                            .unwrap_or(ast::ResolveType::Abort),
+                        loop_labels.next,
+                    )?;
                 }
             }
         } else {
@@ -953,11 +1312,60 @@ fn emit_update_insns(
                         }
                     })
                     .unwrap_or(&cursor_id);
-                program.emit_column(cursor_id, column_idx_in_index.unwrap_or(idx), target_reg);
+                match column_idx_in_index {
+                    Some(column_idx_in_index) => {
+                        program.emit_column(cursor_id, column_idx_in_index, target_reg)
+                    }
+                    None => program.emit_table_column(cursor_id, idx, target_reg),
+                }
             }
         }
     }
+    if let Some(btree_table) = table_ref.btree() {
+        GeneratedColumns::compile(program, &btree_table)?.emit(
+            program,
+            &t_ctx.resolver,
+            rowid_set_clause_reg.unwrap_or(beg),
+            start,
+        )?;
+    }
+
+    let trigger_params = if let Some(triggers) = &plan.triggers {
+        let btree_table = table_ref.btree().unwrap();
+        let params = triggers.alloc_params(program);
+        params.emit_old_from_cursor(program, &t_ctx.resolver, &btree_table, cursor_id)?;
+        params.emit_copy_row(
+            program,
+            &btree_table,
+            rowid_set_clause_reg.unwrap_or(beg),
+            start,
+            false,
+        );
+        if !triggers.before.is_empty() {
+            triggers.emit_before(program, &params, loop_labels.next);
+            // The BEFORE triggers may have moved the cursor or deleted the row
+            program.emit_insn(Insn::NotExists {
+                cursor: cursor_id,
+                rowid_reg: beg,
+                target_pc: loop_labels.next,
+            });
+        }
+        triggers.emit_old_foreign_key_checks(program, &params);
+        Some(params)
+    } else {
+        None
+    };
+
+    plan.checks.emit(
+        program,
+        &t_ctx.resolver,
+        rowid_set_clause_reg.unwrap_or(beg),
+        start,
+        plan.or_conflict.unwrap_or(ast::ResolveType::Abort),
+        loop_labels.next,
+    )?;
 
+    let btree_table = table_ref.btree();
     for (index, (idx_cursor_id, record_reg)) in plan.indexes_to_update.iter().zip(&index_cursors) {
         let num_cols = index.columns.len();
         // allocate scratch registers for the index columns plus rowid
@@ -973,24 +1381,27 @@ fn emit_update_insns(
         } else {
             beg
         };
-        let idx_cols_start_reg = beg + 1;
 
-        // copy each index column from the table's column registers into these scratch regs
-        for (i, col) in index.columns.iter().enumerate() {
-            // copy from the table's column register over to the index's scratch register
-
-            program.emit_insn(Insn::Copy {
-                src_reg: idx_cols_start_reg + col.pos_in_table,
-                dst_reg: idx_start_reg + i,
-                extra_amount: 0,
+        // The record stays NULL if the index is partial and the updated row has no entry in it.
+        let not_indexed_label = program.allocate_label();
+        if index.where_clause.is_some() {
+            program.emit_insn(Insn::Null {
+                dest: *record_reg,
+                dest_end: None,
             });
         }
-        // last register is the rowid
-        program.emit_insn(Insn::Copy {
-            src_reg: rowid_reg,
-            dst_reg: idx_start_reg + num_cols,
-            extra_amount: 0,
-        });
+        emit_index_entry_from_row(
+            program,
+            &t_ctx.resolver,
+            btree_table
+                .as_ref()
+                .expect("only b-tree tables have indexes"),
+            index,
+            rowid_reg,
+            beg + 1,
+            idx_start_reg,
+            not_indexed_label,
+        )?;
 
         // this record will be inserted into the index later
         program.emit_insn(Insn::MakeRecord {
@@ -1001,6 +1412,7 @@ fn emit_update_insns(
         });
 
         if !index.unique {
+            program.preassign_label_to_next_insn(not_indexed_label);
             continue;
         }
 
@@ -1013,19 +1425,7 @@ fn emit_update_insns(
             num_regs: num_cols,
         });
 
-        let column_names = index.columns.iter().enumerate().fold(
-            String::with_capacity(50),
-            |mut accum, (idx, col)| {
-                if idx > 0 {
-                    accum.push_str(", ");
-                }
-                accum.push_str(table_ref.table.get_name());
-                accum.push('.');
-                accum.push_str(&col.name);
-
-                accum
-            },
-        );
+        let column_names = index.unique_constraint_description(table_ref.table.get_name());
 
         let idx_rowid_reg = program.alloc_register();
         program.emit_insn(Insn::IdxRowId {
@@ -1033,20 +1433,33 @@ fn emit_update_insns(
             dest: idx_rowid_reg,
         });
 
+        // The entry of the row being updated has the rowid the row had before the update.
         program.emit_insn(Insn::Eq {
-            lhs: rowid_reg,
+            lhs: beg,
             rhs: idx_rowid_reg,
             target_pc: constraint_check,
             flags: CmpInsFlags::default(), // TODO: not sure what type of comparison flag is needed
             collation: program.curr_collation(),
         });
 
-        program.emit_insn(Insn::Halt {
-            err_code: SQLITE_CONSTRAINT_PRIMARYKEY, // TODO: distinct between primary key and unique index for error code
-            description: column_names,
-        });
+        let on_conflict = plan
+            .or_conflict
+            .or_else(|| btree_table.as_ref()?.index_conflict_clause(index))
+            .unwrap_or(ast::ResolveType::Abort);
+        emit_update_conflict(
+            program,
+            t_ctx,
+            plan,
+            &index_cursors,
+            cursor_id,
+            beg,
+            idx_rowid_reg,
+            on_conflict,
+            column_names,
+        )?;
 
         program.preassign_label_to_next_insn(constraint_check);
+        program.preassign_label_to_next_insn(not_indexed_label);
     }
 
     if let Some(btree_table) = table_ref.btree() {
@@ -1077,31 +1490,34 @@ fn emit_update_insns(
                 target_pc: record_label,
             });
 
-            program.emit_insn(Insn::Halt {
-                err_code: SQLITE_CONSTRAINT_PRIMARYKEY,
-                description: format!(
-                    "{}.{}",
-                    table_ref.table.get_name(),
-                    &table_ref
-                        .columns()
-                        .get(idx)
-                        .unwrap()
-                        .name
-                        .as_ref()
-                        .map_or("", |v| v)
-                ),
-            });
+            let rowid_alias_name = table_ref
+                .columns()
+                .get(idx)
+                .unwrap()
+                .name
+                .as_deref()
+                .unwrap_or("");
+            let on_conflict = plan
+                .or_conflict
+                .or_else(|| btree_table.conflict_clause([rowid_alias_name]))
+                .unwrap_or(ast::ResolveType::Abort);
+            emit_update_conflict(
+                program,
+                t_ctx,
+                plan,
+                &index_cursors,
+                cursor_id,
+                beg,
+                target_reg,
+                on_conflict,
+                format!("{}.{}", table_ref.table.get_name(), rowid_alias_name),
+            )?;
 
             program.preassign_label_to_next_insn(record_label);
         }
 
         let record_reg = program.alloc_register();
-        program.emit_insn(Insn::MakeRecord {
-            start_reg: start,
-            count: table_ref.columns().len(),
-            dest_reg: record_reg,
-            index_name: None,
-        });
+        emit_table_record(program, &btree_table, start, record_reg);
 
         if has_user_provided_rowid {
             program.emit_insn(Insn::NotExists {
@@ -1114,26 +1530,16 @@ fn emit_update_insns(
         // For each index -> insert
         for (index, (idx_cursor_id, record_reg)) in plan.indexes_to_update.iter().zip(index_cursors)
         {
-            let num_regs = index.columns.len() + 1;
-            let start_reg = program.alloc_registers(num_regs);
-
             // Delete existing index key
-            index
-                .columns
-                .iter()
-                .enumerate()
-                .for_each(|(reg_offset, column_index)| {
-                    program.emit_column(
-                        cursor_id,
-                        column_index.pos_in_table,
-                        start_reg + reg_offset,
-                    );
-                });
-
-            program.emit_insn(Insn::RowId {
+            let old_not_indexed_label = program.allocate_label();
+            let (start_reg, num_regs) = emit_index_entry(
+                program,
+                &t_ctx.resolver,
+                &btree_table,
                 cursor_id,
-                dest: start_reg + num_regs - 1,
-            });
+                index,
+                old_not_indexed_label,
+            )?;
 
             program.emit_insn(Insn::IdxDelete {
                 start_reg,
@@ -1141,8 +1547,16 @@ fn emit_update_insns(
                 cursor_id: idx_cursor_id,
                 raise_error_if_no_matching_entry: true,
             });
+            program.preassign_label_to_next_insn(old_not_indexed_label);
 
             // Insert new index key (filled further above with values from set_clauses)
+            let new_not_indexed_label = program.allocate_label();
+            if index.where_clause.is_some() {
+                program.emit_insn(Insn::IsNull {
+                    reg: record_reg,
+                    target_pc: new_not_indexed_label,
+                });
+            }
             program.emit_insn(Insn::IdxInsert {
                 cursor_id: idx_cursor_id,
                 record_reg,
@@ -1150,6 +1564,7 @@ fn emit_update_insns(
                 unpacked_count: Some((index.columns.len() + 1) as u16),
                 flags: IdxInsertFlags::new(),
             });
+            program.preassign_label_to_next_insn(new_not_indexed_label);
         }
 
         // create alias for CDC rowid after the change (will differ from cdc_rowid_before_reg only in case of UPDATE with change in rowid alias)
@@ -1268,6 +1683,13 @@ fn emit_update_insns(
                 )?;
             }
         }
+
+        if let (Some(triggers), Some(params)) = (&plan.triggers, &trigger_params) {
+            triggers.emit_new_foreign_key_checks(program, params);
+            let row_done_label = program.allocate_label();
+            triggers.emit_after(program, params, row_done_label);
+            program.preassign_label_to_next_insn(row_done_label);
+        }
     } else if table_ref.virtual_table().is_some() {
         let arg_count = table_ref.columns().len() + 2;
         program.emit_insn(Insn::VUpdate {
@@ -1293,6 +1715,492 @@ fn emit_update_insns(
     Ok(())
 }
 
+/// Updates a row of a table without rowid. The table is its clustered index, so the row is
+/// replaced by deleting its entry and inserting the updated one. `temp_cursor_id` holds the
+/// primary keys of the rows to update when the UPDATE changes them.
+fn emit_update_insns_without_rowid(
+    plan: &UpdatePlan,
+    t_ctx: &TranslateCtx,
+    program: &mut ProgramBuilder,
+    btree_table: &Rc<BTreeTable>,
+    index_cursors: Vec<(usize, usize)>,
+    temp_cursor_id: Option<CursorID>,
+) -> crate::Result<()> {
+    let table_ref = plan.table_references.joined_tables().first().unwrap();
+    let loop_labels = t_ctx.labels_main_loop.first().unwrap();
+    let clustered_index = match &table_ref.op {
+        Operation::Scan {
+            index: Some(index), ..
+        }
+        | Operation::Search(Search::Seek {
+            index: Some(index), ..
+        }) => index.clone(),
+        _ => unreachable!("tables without rowid are accessed through their clustered index"),
+    };
+    let cursor_id = program.resolve_cursor_id(&CursorKey::index(
+        table_ref.internal_id,
+        clustered_index.clone(),
+    ));
+    let primary_key_columns = clustered_index.key_columns();
+    let num_pk_cols = primary_key_columns.len();
+
+    // Load the primary key of the row to update, and position the cursor on it if the
+    // primary keys were collected upfront.
+    let old_pk_reg = program.alloc_registers(num_pk_cols);
+    if let Some(temp_cursor_id) = temp_cursor_id {
+        for i in 0..num_pk_cols {
+            program.emit_column(temp_cursor_id, i, old_pk_reg + i);
+        }
+        program.emit_insn(Insn::NotFound {
+            cursor_id,
+            target_pc: loop_labels.next,
+            record_reg: old_pk_reg,
+            num_regs: num_pk_cols,
+        });
+    } else {
+        for i in 0..num_pk_cols {
+            program.emit_column(cursor_id, i, old_pk_reg + i);
+        }
+    }
+
+    if let Some(offset) = t_ctx.reg_offset {
+        program.emit_insn(Insn::IfPos {
+            reg: offset,
+            target_pc: loop_labels.next,
+            decrement_by: 1,
+        });
+    }
+
+    let start = program.alloc_registers(table_ref.columns().len());
+    for (idx, table_column) in table_ref.columns().iter().enumerate() {
+        let target_reg = start + idx;
+        if let Some((_, expr)) = plan.set_clauses.iter().find(|(i, _)| *i == idx) {
+            translate_expr(
+                program,
+                Some(&plan.table_references),
+                expr,
+                target_reg,
+                &t_ctx.resolver,
+            )?;
+            if table_column.notnull {
+                emit_notnull_check(
+                    program,
+                    &t_ctx.resolver,
+                    table_ref.table.get_name(),
+                    table_column,
+                    target_reg,
+                    plan.or_conflict
+                        .or_else(|| btree_table.notnull_conflict_clause(table_column))
+                        .unwrap_or(ast::ResolveType::Abort),
+                    loop_labels.next,
+                )?;
+            }
+        } else {
+            program.emit_table_column(cursor_id, idx, target_reg);
+        }
+    }
+
+    // Tables without rowid have no rowid for the constraints to refer to.
+    let rowid_reg = program.alloc_register();
+    program.emit_null(rowid_reg, None);
+    GeneratedColumns::compile(program, btree_table)?.emit(
+        program,
+        &t_ctx.resolver,
+        rowid_reg,
+        start,
+    )?;
+
+    let trigger_params = if let Some(triggers) = &plan.triggers {
+        let params = triggers.alloc_params(program);
+        params.emit_old_from_cursor(program, &t_ctx.resolver, btree_table, cursor_id)?;
+        params.emit_copy_row(program, btree_table, rowid_reg, start, false);
+        if !triggers.before.is_empty() {
+            triggers.emit_before(program, &params, loop_labels.next);
+            // The BEFORE triggers may have moved the cursor or deleted the row
+            program.emit_insn(Insn::NotFound {
+                cursor_id,
+                target_pc: loop_labels.next,
+                record_reg: old_pk_reg,
+                num_regs: num_pk_cols,
+            });
+        }
+        triggers.emit_old_foreign_key_checks(program, &params);
+        Some(params)
+    } else {
+        None
+    };
+
+    plan.checks.emit(
+        program,
+        &t_ctx.resolver,
+        rowid_reg,
+        start,
+        plan.or_conflict.unwrap_or(ast::ResolveType::Abort),
+        loop_labels.next,
+    )?;
+
+    if btree_table.is_strict {
+        program.emit_insn(Insn::TypeCheck {
+            start_reg: start,
+            count: table_ref.columns().len(),
+            check_generated: true,
+            table_reference: btree_table.clone(),
+        });
+    }
+
+    // An UPDATE that may replace rows maintains all the indexes of the table.
+    let replace_index_cursors = plan
+        .indexes_to_update
+        .iter()
+        .cloned()
+        .zip(index_cursors.iter().map(|(cursor_id, _)| *cursor_id))
+        .collect::<Vec<_>>();
+    // Resolves a conflict with the row the table cursor points to, or with the row of the index
+    // entry `conflicting_entry` points to. REPLACE deletes that row and moves back to the row
+    // being updated.
+    let emit_conflict = |program: &mut ProgramBuilder,
+                         on_conflict,
+                         description,
+                         conflicting_entry: Option<(&Arc<Index>, CursorID)>|
+     -> Result<()> {
+        match on_conflict {
+            ast::ResolveType::Ignore => program.emit_insn(Insn::Goto {
+                target_pc: loop_labels.next,
+            }),
+            ast::ResolveType::Replace => {
+                let deleted_label = program.allocate_label();
+                if let Some((index, idx_cursor_id)) = conflicting_entry {
+                    emit_seek_row_of_index_entry(
+                        program,
+                        btree_table,
+                        cursor_id,
+                        index,
+                        idx_cursor_id,
+                        deleted_label,
+                    );
+                }
+                emit_replace_delete(
+                    program,
+                    &t_ctx.resolver,
+                    btree_table,
+                    cursor_id,
+                    &replace_index_cursors,
+                    t_ctx.cdc_cursor_id,
+                    &plan.replace_foreign_keys,
+                )?;
+                program.preassign_label_to_next_insn(deleted_label);
+                program.emit_insn(Insn::NotFound {
+                    cursor_id,
+                    target_pc: loop_labels.next,
+                    record_reg: old_pk_reg,
+                    num_regs: num_pk_cols,
+                });
+            }
+            on_error => program.emit_insn(Insn::Halt {
+                err_code: SQLITE_CONSTRAINT_PRIMARYKEY,
+                on_error,
+                description,
+            }),
+        }
+        Ok(())
+    };
+
+    // If the primary key changes, it must not collide with the primary key of another row.
+    if temp_cursor_id.is_some() {
+        let new_pk_reg = program.alloc_registers(num_pk_cols);
+        for (i, column) in primary_key_columns.iter().enumerate() {
+            program.emit_insn(Insn::Copy {
+                src_reg: start + column.pos_in_table,
+                dst_reg: new_pk_reg + i,
+                extra_amount: 0,
+            });
+        }
+        let pk_changed_label = program.allocate_label();
+        let pk_checked_label = program.allocate_label();
+        for (i, column) in primary_key_columns.iter().enumerate() {
+            program.emit_insn(Insn::Ne {
+                lhs: new_pk_reg + i,
+                rhs: old_pk_reg + i,
+                target_pc: pk_changed_label,
+                flags: CmpInsFlags::default(),
+                collation: column.collation,
+            });
+        }
+        program.emit_insn(Insn::Goto {
+            target_pc: pk_checked_label,
+        });
+        program.preassign_label_to_next_insn(pk_changed_label);
+        let no_conflict_label = program.allocate_label();
+        program.emit_insn(Insn::NoConflict {
+            cursor_id,
+            target_pc: no_conflict_label,
+            record_reg: new_pk_reg,
+            num_regs: num_pk_cols,
+        });
+        let on_conflict = plan
+            .or_conflict
+            .or_else(|| btree_table.index_conflict_clause(&clustered_index))
+            .unwrap_or(ast::ResolveType::Abort);
+        emit_conflict(
+            program,
+            on_conflict,
+            primary_key_columns
+                .iter()
+                .map(|column| format!("{}.{}", table_ref.table.get_name(), column.name))
+                .collect::<Vec<_>>()
+                .join(", "),
+            None,
+        )?;
+        program.preassign_label_to_next_insn(no_conflict_label);
+        // Move back to the row being updated.
+        program.emit_insn(Insn::NotFound {
+            cursor_id,
+            target_pc: loop_labels.next,
+            record_reg: old_pk_reg,
+            num_regs: num_pk_cols,
+        });
+        program.preassign_label_to_next_insn(pk_checked_label);
+    }
+
+    for (index, (idx_cursor_id, record_reg)) in plan.indexes_to_update.iter().zip(&index_cursors) {
+        let num_cols = index.columns.len();
+        let idx_start_reg = program.alloc_registers(num_cols);
+        // The record stays NULL if the index is partial and the updated row has no entry in it.
+        let not_indexed_label = program.allocate_label();
+        if index.where_clause.is_some() {
+            program.emit_insn(Insn::Null {
+                dest: *record_reg,
+                dest_end: None,
+            });
+        }
+        emit_index_entry_from_row(
+            program,
+            &t_ctx.resolver,
+            btree_table,
+            index,
+            rowid_reg,
+            start,
+            idx_start_reg,
+            not_indexed_label,
+        )?;
+        // this record will be inserted into the index later
+        program.emit_insn(Insn::MakeRecord {
+            start_reg: idx_start_reg,
+            count: num_cols,
+            dest_reg: *record_reg,
+            index_name: Some(index.name.clone()),
+        });
+
+        if !index.unique {
+            program.preassign_label_to_next_insn(not_indexed_label);
+            continue;
+        }
+
+        let constraint_check = program.allocate_label();
+        program.emit_insn(Insn::NoConflict {
+            cursor_id: *idx_cursor_id,
+            target_pc: constraint_check,
+            record_reg: idx_start_reg,
+            num_regs: index.key_columns().len(),
+        });
+
+        // The entry of the row being updated has the primary key the row had before the update.
+        let conflict_label = program.allocate_label();
+        let conflict_pk_reg = program.alloc_register();
+        for (i, column) in primary_key_columns.iter().enumerate() {
+            let pos_in_index = index
+                .column_table_pos_to_index_pos(column.pos_in_table)
+                .expect("indexes of tables without rowid store the primary key");
+            program.emit_column(*idx_cursor_id, pos_in_index, conflict_pk_reg);
+            program.emit_insn(Insn::Ne {
+                lhs: conflict_pk_reg,
+                rhs: old_pk_reg + i,
+                target_pc: conflict_label,
+                flags: CmpInsFlags::default(),
+                collation: column.collation,
+            });
+        }
+        program.emit_insn(Insn::Goto {
+            target_pc: constraint_check,
+        });
+        program.preassign_label_to_next_insn(conflict_label);
+
+        let on_conflict = plan
+            .or_conflict
+            .or_else(|| btree_table.index_conflict_clause(index))
+            .unwrap_or(ast::ResolveType::Abort);
+        emit_conflict(
+            program,
+            on_conflict,
+            index.unique_constraint_description(table_ref.table.get_name()),
+            Some((index, *idx_cursor_id)),
+        )?;
+
+        program.preassign_label_to_next_insn(constraint_check);
+        program.preassign_label_to_next_insn(not_indexed_label);
+    }
+
+    // Replace the index entries of the row
+    for (index, (idx_cursor_id, record_reg)) in plan.indexes_to_update.iter().zip(index_cursors) {
+        let old_not_indexed_label = program.allocate_label();
+        let (start_reg, num_regs) = emit_index_entry(
+            program,
+            &t_ctx.resolver,
+            btree_table,
+            cursor_id,
+            index,
+            old_not_indexed_label,
+        )?;
+        program.emit_insn(Insn::IdxDelete {
+            start_reg,
+            num_regs,
+            cursor_id: idx_cursor_id,
+            raise_error_if_no_matching_entry: true,
+        });
+        program.preassign_label_to_next_insn(old_not_indexed_label);
+        let new_not_indexed_label = program.allocate_label();
+        if index.where_clause.is_some() {
+            program.emit_insn(Insn::IsNull {
+                reg: record_reg,
+                target_pc: new_not_indexed_label,
+            });
+        }
+        program.emit_insn(Insn::IdxInsert {
+            cursor_id: idx_cursor_id,
+            record_reg,
+            unpacked_start: None,
+            unpacked_count: None,
+            flags: IdxInsertFlags::new(),
+        });
+        program.preassign_label_to_next_insn(new_not_indexed_label);
+    }
+
+    // Replace the row itself
+    let num_cols = clustered_index.columns.len();
+    let row_start_reg = program.alloc_registers(num_cols);
+    for (i, col) in clustered_index.columns.iter().enumerate() {
+        program.emit_insn(Insn::Copy {
+            src_reg: start + col.pos_in_table,
+            dst_reg: row_start_reg + i,
+            extra_amount: 0,
+        });
+    }
+    let record_reg = program.alloc_register();
+    program.emit_insn(Insn::MakeRecord {
+        start_reg: row_start_reg,
+        count: num_cols,
+        dest_reg: record_reg,
+        index_name: Some(clustered_index.name.clone()),
+    });
+    program.emit_insn(Insn::Delete { cursor_id });
+    program.emit_insn(Insn::IdxInsert {
+        cursor_id,
+        record_reg,
+        unpacked_start: Some(row_start_reg),
+        unpacked_count: Some(num_cols as u16),
+        flags: IdxInsertFlags::new(),
+    });
+
+    if let Some(returning_columns) = &plan.returning {
+        if !returning_columns.is_empty() {
+            let value_registers = ReturningValueRegisters {
+                rowid_register: rowid_reg,
+                columns_start_register: start,
+                num_columns: table_ref.columns().len(),
+            };
+
+            emit_returning_results(program, returning_columns, &value_registers)?;
+        }
+    }
+
+    if let (Some(triggers), Some(params)) = (&plan.triggers, &trigger_params) {
+        triggers.emit_new_foreign_key_checks(program, params);
+        let row_done_label = program.allocate_label();
+        triggers.emit_after(program, params, row_done_label);
+        program.preassign_label_to_next_insn(row_done_label);
+    }
+
+    if let Some(limit_ctx) = t_ctx.limit_ctx {
+        program.emit_insn(Insn::DecrJumpZero {
+            reg: limit_ctx.reg_limit,
+            target_pc: t_ctx.label_main_loop_end.unwrap(),
+        })
+    }
+
+    Ok(())
+}
+
+/// Resolves a conflict of the row being updated, whose rowid is in `rowid_reg`, with the row
+/// whose rowid is in `conflict_rowid_reg`: IGNORE skips the row being updated, REPLACE deletes
+/// the conflicting row, and the other resolutions fail the statement.
+#[allow(clippy::too_many_arguments)]
+fn emit_update_conflict(
+    program: &mut ProgramBuilder,
+    t_ctx: &TranslateCtx,
+    plan: &UpdatePlan,
+    index_cursors: &[(usize, usize)],
+    cursor_id: CursorID,
+    rowid_reg: usize,
+    conflict_rowid_reg: usize,
+    on_conflict: ast::ResolveType,
+    description: String,
+) -> Result<()> {
+    let loop_labels = t_ctx.labels_main_loop.first().unwrap();
+    match on_conflict {
+        ast::ResolveType::Ignore => {
+            program.emit_insn(Insn::Goto {
+                target_pc: loop_labels.next,
+            });
+        }
+        ast::ResolveType::Replace => {
+            let table = plan
+                .table_references
+                .joined_tables()
+                .first()
+                .unwrap()
+                .btree()
+                .unwrap();
+            // An UPDATE that may replace rows maintains all the indexes of the table.
+            let index_cursors = plan
+                .indexes_to_update
+                .iter()
+                .cloned()
+                .zip(index_cursors.iter().map(|(cursor_id, _)| *cursor_id))
+                .collect::<Vec<_>>();
+            let deleted_label = program.allocate_label();
+            program.emit_insn(Insn::NotExists {
+                cursor: cursor_id,
+                rowid_reg: conflict_rowid_reg,
+                target_pc: deleted_label,
+            });
+            emit_replace_delete(
+                program,
+                &t_ctx.resolver,
+                &table,
+                cursor_id,
+                &index_cursors,
+                t_ctx.cdc_cursor_id,
+                &plan.replace_foreign_keys,
+            )?;
+            program.preassign_label_to_next_insn(deleted_label);
+            // Move back to the row being updated.
+            program.emit_insn(Insn::NotExists {
+                cursor: cursor_id,
+                rowid_reg,
+                target_pc: loop_labels.next,
+            });
+        }
+        on_error => {
+            program.emit_insn(Insn::Halt {
+                err_code: SQLITE_CONSTRAINT_PRIMARYKEY,
+                on_error,
+                description,
+            });
+        }
+    }
+    Ok(())
+}
+
 pub fn emit_cdc_patch_record(
     program: &mut ProgramBuilder,
     table: &Table,
@@ -1337,7 +2245,7 @@ pub fn emit_cdc_full_record(
                 extra_amount: 0,
             });
         } else {
-            program.emit_column(table_cursor_id, i, columns_reg + 1 + i);
+            program.emit_table_column(table_cursor_id, i, columns_reg + 1 + i);
         }
     }
     program.emit_insn(Insn::MakeRecord {
@@ -2,13 +2,15 @@ use tracing::{instrument, Level};
 use turso_sqlite3_parser::ast::{self, As, Expr, UnaryOperator};
 
 use super::emitter::Resolver;
+use super::generated::{bind_generated_column, emit_column_affinity};
 use super::optimizer::Optimizable;
 use super::plan::TableReferences;
+use super::subquery::translate_subquery_result;
 #[cfg(feature = "json")]
 use crate::function::JsonFunc;
 use crate::function::{Func, FuncCtx, MathFuncArity, ScalarFunc, VectorFunc};
 use crate::functions::datetime;
-use crate::schema::{Affinity, Table, Type};
+use crate::schema::{Affinity, CteRows, Table, Type};
 use crate::util::{exprs_are_equivalent, parse_numeric_literal};
 use crate::vdbe::builder::CursorKey;
 use crate::vdbe::{
@@ -213,7 +215,8 @@ pub fn translate_condition_expr(
         | ast::Expr::FunctionCall { .. }
         | ast::Expr::Column { .. }
         | ast::Expr::RowId { .. }
-        | ast::Expr::Case { .. } => {
+        | ast::Expr::Case { .. }
+        | ast::Expr::SubqueryResult { .. } => {
             let reg = program.alloc_register();
             translate_expr(program, Some(referenced_tables), expr, reg, resolver)?;
             emit_cond_jump(program, condition_metadata, reg);
@@ -681,12 +684,14 @@ pub fn translate_expr(
             Ok(target_register)
         }
         ast::Expr::DoublyQualified(_, _, _) => todo!(),
-        ast::Expr::Exists(_) => todo!(),
+        ast::Expr::Exists(_) => {
+            crate::bail_parse_error!("EXISTS is not supported in this context")
+        }
         ast::Expr::FunctionCall {
             name,
             distinctness: _,
             args,
-            filter_over: _,
+            filter_over,
             order_by: _,
         } => {
             let args_count = if let Some(args) = args { args.len() } else { 0 };
@@ -701,10 +706,24 @@ pub fn translate_expr(
                 arg_count: args_count,
             };
 
+            if filter_over
+                .as_ref()
+                .is_some_and(|f| f.filter_clause.is_some())
+                && !matches!(func_ctx.func, Func::Agg(_) | Func::Window(_))
+            {
+                crate::bail_parse_error!(
+                    "FILTER may not be used with non-aggregate {}()",
+                    name.as_str()
+                );
+            }
+
             match &func_ctx.func {
                 Func::Agg(_) => {
                     crate::bail_parse_error!("misuse of aggregate function {}()", name.as_str())
                 }
+                Func::Window(_) => {
+                    crate::bail_parse_error!("misuse of window function {}()", name.as_str())
+                }
                 Func::External(_) => {
                     let regs = program.alloc_registers(args_count);
                     if let Some(args) = args {
@@ -1928,6 +1947,29 @@ pub fn translate_expr(
             // the table and read the column from the cursor.
             // If we have a covering index, we don't have an open table cursor so we read from the index cursor.
             match &table {
+                Table::BTree(btree)
+                    if table_column.is_virtual()
+                        && !(use_covering_index
+                            && index.is_some_and(|index| {
+                                index.column_table_pos_to_index_pos(*column).is_some()
+                            })) =>
+                {
+                    // VIRTUAL generated columns are not stored, they are computed from the
+                    // columns of the row they refer to.
+                    let expr = bind_generated_column(btree, *column, *table_ref_id);
+                    translate_expr_no_constant_opt(
+                        program,
+                        referenced_tables,
+                        &expr,
+                        target_register,
+                        resolver,
+                        NoConstantOptReason::RegisterReuse,
+                    )?;
+                    emit_column_affinity(program, table_column.affinity(), target_register);
+                    program
+                        .set_collation(Some((table_column.collation.unwrap_or_default(), false)));
+                    Ok(target_register)
+                }
                 Table::BTree(_) => {
                     let table_cursor_id = if use_covering_index {
                         None
@@ -1961,18 +2003,17 @@ pub fn translate_expr(
                                 "table cursor should be opened when use_covering_index=false",
                             )
                         };
-                        let column = if use_covering_index {
+                        if use_covering_index {
                             let index = index.expect(
                                 "index cursor should be opened when use_covering_index=true",
                             );
-                            index.column_table_pos_to_index_pos(*column).unwrap_or_else(|| {
+                            let column = index.column_table_pos_to_index_pos(*column).unwrap_or_else(|| {
                                         panic!("covering index {} does not contain column number {} of table {}", index.name, column, table_ref_id)
-                                    })
+                                    });
+                            program.emit_column(read_cursor, column, target_register);
                         } else {
-                            *column
-                        };
-
-                        program.emit_column(read_cursor, column, target_register);
+                            program.emit_table_column(read_cursor, *column, target_register);
+                        }
                     }
                     let Some(column) = table.get_column_at(*column) else {
                         crate::bail_parse_error!("column index out of bounds");
@@ -1993,6 +2034,30 @@ pub fn translate_expr(
                     });
                     Ok(target_register)
                 }
+                Table::Cte(cte) => {
+                    match cte
+                        .rows
+                        .get()
+                        .expect("CTE must be emitted before it is read")
+                    {
+                        CteRows::Ephemeral { .. } => {
+                            let cursor_id =
+                                program.resolve_cursor_id(&CursorKey::table(*table_ref_id));
+                            program.emit_column(cursor_id, *column, target_register);
+                        }
+                        CteRows::Coroutine {
+                            result_columns_start_reg,
+                            ..
+                        } => {
+                            program.emit_insn(Insn::Copy {
+                                src_reg: result_columns_start_reg + *column,
+                                dst_reg: target_register,
+                                extra_amount: 0,
+                            });
+                        }
+                    }
+                    Ok(target_register)
+                }
                 Table::Virtual(_) => {
                     let cursor_id = program.resolve_cursor_id(&CursorKey::table(*table_ref_id));
                     program.emit_insn(Insn::VColumn {
@@ -2040,9 +2105,74 @@ pub fn translate_expr(
             }
             Ok(target_register)
         }
-        ast::Expr::InList { .. } => todo!(),
-        ast::Expr::InSelect { .. } => todo!(),
-        ast::Expr::InTable { .. } => todo!(),
+        ast::Expr::InList { lhs, not, rhs } => {
+            // The result is 1 if the left hand side equals one of the values, NULL if it does
+            // not but the comparison with a NULL value was unknown, and 0 otherwise.
+            let rhs = rhs.as_deref().unwrap_or_default();
+            program.emit_insn(Insn::Integer {
+                value: 0,
+                dest: target_register,
+            });
+            if !rhs.is_empty() {
+                let lhs_reg = program.alloc_register();
+                translate_expr(program, referenced_tables, lhs, lhs_reg, resolver)?;
+                let null_label = program.allocate_label();
+                let match_label = program.allocate_label();
+                let done_label = program.allocate_label();
+                program.emit_insn(Insn::IsNull {
+                    reg: lhs_reg,
+                    target_pc: null_label,
+                });
+                for expr in rhs {
+                    let rhs_reg = program.alloc_register();
+                    translate_expr(program, referenced_tables, expr, rhs_reg, resolver)?;
+                    program.emit_insn(Insn::Eq {
+                        lhs: lhs_reg,
+                        rhs: rhs_reg,
+                        target_pc: match_label,
+                        flags: CmpInsFlags::default(),
+                        collation: program.curr_collation(),
+                    });
+                    let not_null_label = program.allocate_label();
+                    program.emit_insn(Insn::NotNull {
+                        reg: rhs_reg,
+                        target_pc: not_null_label,
+                    });
+                    program.emit_insn(Insn::Null {
+                        dest: target_register,
+                        dest_end: None,
+                    });
+                    program.preassign_label_to_next_insn(not_null_label);
+                }
+                program.emit_insn(Insn::Goto {
+                    target_pc: done_label,
+                });
+                program.preassign_label_to_next_insn(match_label);
+                program.emit_insn(Insn::Integer {
+                    value: 1,
+                    dest: target_register,
+                });
+                program.emit_insn(Insn::Goto {
+                    target_pc: done_label,
+                });
+                program.preassign_label_to_next_insn(null_label);
+                program.emit_insn(Insn::Null {
+                    dest: target_register,
+                    dest_end: None,
+                });
+                program.preassign_label_to_next_insn(done_label);
+            }
+            if *not {
+                program.emit_insn(Insn::Not {
+                    reg: target_register,
+                    dest: target_register,
+                });
+            }
+            Ok(target_register)
+        }
+        ast::Expr::InSelect { .. } | ast::Expr::InTable { .. } => {
+            crate::bail_parse_error!("IN (SELECT ...) is not supported in this context")
+        }
         ast::Expr::IsNull(expr) => {
             let reg = program.alloc_register();
             translate_expr(program, referenced_tables, expr, reg, resolver)?;
@@ -2120,8 +2250,48 @@ pub fn translate_expr(
         ast::Expr::Qualified(_, _) => {
             unreachable!("Qualified should be resolved to a Column before translation")
         }
-        ast::Expr::Raise(_, _) => todo!(),
-        ast::Expr::Subquery(_) => todo!(),
+        ast::Expr::Raise(resolve_type, message) => {
+            if !program.is_trigger_program() {
+                crate::bail_parse_error!("RAISE() may only be used within a trigger-program");
+            }
+            match resolve_type {
+                ast::ResolveType::Ignore => {
+                    program.emit_insn(Insn::Halt {
+                        err_code: crate::error::SQLITE_IGNORE,
+                        on_error: ast::ResolveType::Ignore,
+                        description: String::new(),
+                    });
+                }
+                ast::ResolveType::Abort | ast::ResolveType::Fail | ast::ResolveType::Rollback => {
+                    let description = match message.as_deref() {
+                        Some(ast::Expr::Literal(ast::Literal::String(s))) => sanitize_string(s),
+                        Some(ast::Expr::Id(name)) | Some(ast::Expr::Name(name)) => {
+                            name.as_str().to_string()
+                        }
+                        _ => crate::bail_parse_error!("RAISE() requires an error message"),
+                    };
+                    program.emit_insn(Insn::Halt {
+                        err_code: crate::error::SQLITE_CONSTRAINT_TRIGGER,
+                        on_error: *resolve_type,
+                        description,
+                    });
+                }
+                ast::ResolveType::Replace => {
+                    crate::bail_parse_error!("RAISE(REPLACE) is not supported")
+                }
+            }
+            program.emit_insn(Insn::Null {
+                dest: target_register,
+                dest_end: None,
+            });
+            Ok(target_register)
+        }
+        ast::Expr::Subquery(_) => {
+            crate::bail_parse_error!("subqueries are not supported in this context")
+        }
+        ast::Expr::SubqueryResult { .. } => {
+            translate_subquery_result(program, referenced_tables, expr, target_register, resolver)
+        }
         ast::Expr::Unary(op, expr) => match (op, expr.as_ref()) {
             (UnaryOperator::Positive, expr) => {
                 translate_expr(program, referenced_tables, expr, target_register, resolver)
@@ -2735,6 +2905,16 @@ where
                 ast::Expr::Exists(_select) | ast::Expr::Subquery(_select) => {
                     // TODO: Walk through select statements if needed
                 }
+                ast::Expr::SubqueryResult {
+                    lhs, outer_columns, ..
+                } => {
+                    if let Some(lhs) = lhs {
+                        walk_expr(lhs, func)?;
+                    }
+                    for expr in outer_columns {
+                        walk_expr(expr, func)?;
+                    }
+                }
                 ast::Expr::FunctionCall {
                     args,
                     order_by,
@@ -2931,6 +3111,16 @@ where
         ast::Expr::Exists(_) | ast::Expr::Subquery(_) => {
             // TODO: Walk through select statements if needed
         }
+        ast::Expr::SubqueryResult {
+            lhs, outer_columns, ..
+        } => {
+            if let Some(lhs) = lhs {
+                walk_expr_mut(lhs, func)?;
+            }
+            for expr in outer_columns {
+                walk_expr_mut(expr, func)?;
+            }
+        }
         ast::Expr::FunctionCall {
             args,
             order_by,
@@ -3397,6 +3587,7 @@ pub fn process_returning_clause(
             join_info: None,
             col_used_mask: ColumnUsedMask::default(),
             database_id: 0,
+            index_hint: None,
         }],
         vec![],
     );
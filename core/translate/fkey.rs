@@ -0,0 +1,672 @@
+//! Enforcement of FOREIGN KEY constraints, while `PRAGMA foreign_keys` is on.
+//!
+//! Like SQLite, violations are counted rather than reported as soon as a row is written:
+//! a statement may break a constraint and fix it further on, e.g. by inserting a child row
+//! before its parent. Each row written adds one to a counter for every constraint it
+//! breaks and subtracts one for every violation it resolves. Immediate constraints are
+//! counted per statement, which fails if its counter is not zero when it ends. Deferred
+//! constraints, and all of them while `PRAGMA defer_foreign_keys` is on, are counted per
+//! transaction, and COMMIT fails until their counter is back to zero. Decrements are
+//! skipped while the counter is zero: they would resolve a violation that was not counted,
+//! such as one already in the database when the constraint started being enforced.
+//!
+//! The ON DELETE and ON UPDATE actions are compiled into sub-programs fired like AFTER
+//! triggers, see [super::trigger].
+
+use std::rc::Rc;
+use std::sync::Arc;
+
+use fallible_iterator::FallibleIterator as _;
+use turso_sqlite3_parser::ast::{self, fmt::ToTokens, RefAct, TriggerEvent};
+use turso_sqlite3_parser::lexer::sql::Parser;
+
+use crate::schema::{Affinity, BTreeTable, ForeignKey, Index, Schema};
+use crate::translate::trigger::{compile_sub_program, TriggerParams};
+use crate::util::quote_ident;
+use crate::vdbe::builder::{CursorType, ProgramBuilder};
+use crate::vdbe::insn::{CmpInsFlags, Insn, SubProgram};
+use crate::vdbe::BranchOffset;
+use crate::{bail_parse_error, Connection, LimboError, Result, SymbolTable};
+
+/// A FOREIGN KEY constraint of `child`, resolved against the schema.
+#[derive(Debug)]
+pub struct ForeignKeyRef {
+    pub child: Rc<BTreeTable>,
+    /// The clustered index the child table is stored in, if it has no rowid.
+    pub child_clustered_index: Option<Arc<Index>>,
+    /// The database of the child and parent tables.
+    pub database_id: usize,
+    /// Positions of the child key columns in the child table.
+    pub child_columns: Vec<usize>,
+    /// `None` if the parent table does not exist, in which case every child row with a
+    /// non-NULL key violates the constraint.
+    pub parent: Option<ParentKey>,
+    pub deferred: bool,
+}
+
+/// The parent key of a FOREIGN KEY constraint.
+#[derive(Debug)]
+pub struct ParentKey {
+    pub table: Rc<BTreeTable>,
+    /// Positions of the parent key columns in the parent table, in child key order.
+    pub columns: Vec<usize>,
+    /// The UNIQUE index on the parent key, `None` if the key is the rowid.
+    pub index: Option<Arc<Index>>,
+}
+
+impl ParentKey {
+    /// Returns `None` if the referenced columns are not the PRIMARY KEY or a UNIQUE key of
+    /// `table`.
+    fn resolve(
+        schema: &Schema,
+        table: Rc<BTreeTable>,
+        foreign_key: &ForeignKey,
+        num_columns: usize,
+    ) -> Option<Self> {
+        let names = if foreign_key.parent_columns.is_empty() {
+            table
+                .primary_key_columns
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>()
+        } else {
+            foreign_key
+                .parent_columns
+                .iter()
+                .map(String::as_str)
+                .collect()
+        };
+        if names.len() != num_columns {
+            return None;
+        }
+        let columns = names
+            .iter()
+            .map(|name| table.get_column(name).map(|(idx, _)| idx))
+            .collect::<Option<Vec<_>>>()?;
+        if let [column] = columns[..] {
+            if table.columns[column].is_rowid_alias {
+                return Some(Self {
+                    table,
+                    columns,
+                    index: None,
+                });
+            }
+        }
+        let index = schema
+            .get_indices(&table.name)
+            .iter()
+            .find(|index| {
+                index.unique
+                    && index.where_clause.is_none()
+                    && index.key_columns().len() == columns.len()
+                    && index
+                        .key_columns()
+                        .iter()
+                        .all(|column| columns.contains(&column.pos_in_table))
+            })?
+            .clone();
+        Some(Self {
+            table,
+            columns,
+            index: Some(index),
+        })
+    }
+}
+
+impl ForeignKeyRef {
+    pub fn resolve(
+        schema: &Schema,
+        database_id: usize,
+        child: Rc<BTreeTable>,
+        foreign_key: &ForeignKey,
+    ) -> Result<Self> {
+        let mismatch = || {
+            LimboError::ParseError(format!(
+                "foreign key mismatch - \"{}\" referencing \"{}\"",
+                child.name, foreign_key.parent_table
+            ))
+        };
+        let child_columns = foreign_key
+            .child_columns
+            .iter()
+            .map(|name| child.get_column(name).map(|(idx, _)| idx))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(mismatch)?;
+        let parent = match schema.get_btree_table(&foreign_key.parent_table) {
+            Some(table) => Some(
+                ParentKey::resolve(schema, table, foreign_key, child_columns.len())
+                    .ok_or_else(mismatch)?,
+            ),
+            None => None,
+        };
+        Ok(Self {
+            child_clustered_index: schema.get_clustered_index(&child).cloned(),
+            child,
+            database_id,
+            child_columns,
+            parent,
+            deferred: foreign_key.deferred,
+        })
+    }
+
+    /// Look up the parent of the child row whose columns are in the registers starting at
+    /// `columns_start_reg`. Jumps to `found_label` if the parent exists or if the child key
+    /// contains a NULL, and falls through otherwise.
+    pub fn emit_parent_lookup(
+        &self,
+        program: &mut ProgramBuilder,
+        columns_start_reg: usize,
+        found_label: BranchOffset,
+    ) {
+        for column in &self.child_columns {
+            program.emit_insn(Insn::IsNull {
+                reg: columns_start_reg + column,
+                target_pc: found_label,
+            });
+        }
+        let Some(parent) = &self.parent else {
+            return;
+        };
+        match &parent.index {
+            None => {
+                let cursor_id =
+                    program.alloc_cursor_id(CursorType::BTreeTable(parent.table.clone()));
+                program.emit_insn(Insn::OpenRead {
+                    cursor_id,
+                    root_page: parent.table.root_page,
+                    db: self.database_id,
+                });
+                let missing_label = program.allocate_label();
+                program.emit_insn(Insn::SeekRowid {
+                    cursor_id,
+                    src_reg: columns_start_reg + self.child_columns[0],
+                    target_pc: missing_label,
+                });
+                program.emit_insn(Insn::Goto {
+                    target_pc: found_label,
+                });
+                program.preassign_label_to_next_insn(missing_label);
+            }
+            Some(index) => {
+                // The child key, in index order and with the affinities of the parent key
+                let num_key_columns = index.key_columns().len();
+                let key_reg = program.alloc_registers(num_key_columns);
+                let mut affinities = String::with_capacity(num_key_columns);
+                for (i, index_column) in index.key_columns().iter().enumerate() {
+                    let key_idx = parent
+                        .columns
+                        .iter()
+                        .position(|column| *column == index_column.pos_in_table)
+                        .expect("parent key column not in index");
+                    program.emit_insn(Insn::Copy {
+                        src_reg: columns_start_reg + self.child_columns[key_idx],
+                        dst_reg: key_reg + i,
+                        extra_amount: 0,
+                    });
+                    affinities.push(
+                        parent.table.columns[index_column.pos_in_table]
+                            .affinity()
+                            .aff_mask(),
+                    );
+                }
+                program.emit_insn(Insn::Affinity {
+                    start_reg: key_reg,
+                    count: std::num::NonZeroUsize::new(num_key_columns).unwrap(),
+                    affinities,
+                });
+                let cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(index.clone()));
+                program.emit_insn(Insn::OpenRead {
+                    cursor_id,
+                    root_page: index.root_page,
+                    db: self.database_id,
+                });
+                program.emit_insn(Insn::Found {
+                    cursor_id,
+                    target_pc: found_label,
+                    record_reg: key_reg,
+                    num_regs: num_key_columns,
+                });
+            }
+        }
+    }
+
+    /// Count the child row in `columns_start_reg` as a violation if its parent does not
+    /// exist. `increment` is 1 for a row being added and -1 for a row being removed.
+    fn emit_child_check(
+        &self,
+        program: &mut ProgramBuilder,
+        columns_start_reg: usize,
+        increment: i64,
+    ) {
+        let ok_label = program.allocate_label();
+        if increment < 0 {
+            program.emit_insn(Insn::FkIfZero {
+                deferred: self.deferred,
+                target_pc: ok_label,
+            });
+        }
+        self.emit_parent_lookup(program, columns_start_reg, ok_label);
+        program.emit_insn(Insn::FkCounter {
+            increment_value: increment,
+            deferred: self.deferred,
+        });
+        program.preassign_label_to_next_insn(ok_label);
+    }
+
+    /// Count a violation for each child row referencing the parent row in `rowid_reg` and
+    /// `columns_start_reg`. `increment` is 1 for a row being removed and -1 for a row
+    /// being added. When the constraint references its own table, the parent row itself
+    /// is not counted.
+    fn emit_parent_check(
+        &self,
+        program: &mut ProgramBuilder,
+        rowid_reg: usize,
+        columns_start_reg: usize,
+        increment: i64,
+    ) {
+        let parent = self.parent.as_ref().expect("parent table exists");
+        let done_label = program.allocate_label();
+        if increment < 0 {
+            program.emit_insn(Insn::FkIfZero {
+                deferred: self.deferred,
+                target_pc: done_label,
+            });
+        }
+        for column in &parent.columns {
+            program.emit_insn(Insn::IsNull {
+                reg: columns_start_reg + column,
+                target_pc: done_label,
+            });
+        }
+        // The child table is scanned through its clustered index if it has no rowid
+        let (cursor_type, root_page) = match &self.child_clustered_index {
+            Some(index) => (CursorType::BTreeIndex(index.clone()), index.root_page),
+            None => (
+                CursorType::BTreeTable(self.child.clone()),
+                self.child.root_page,
+            ),
+        };
+        let cursor_id = program.alloc_cursor_id(cursor_type);
+        program.emit_insn(Insn::OpenRead {
+            cursor_id,
+            root_page,
+            db: self.database_id,
+        });
+        let same_table = self.child.name == parent.table.name;
+        let child_key_reg = program.alloc_registers(self.child_columns.len());
+        let primary_key_reg = program.alloc_register();
+        let emit_row = |program: &mut ProgramBuilder, rowid: Option<usize>| {
+            let next_label = program.allocate_label();
+            if same_table {
+                match rowid {
+                    Some(rowid) => program.emit_insn(Insn::Eq {
+                        lhs: rowid,
+                        rhs: rowid_reg,
+                        target_pc: next_label,
+                        flags: CmpInsFlags::default(),
+                        collation: None,
+                    }),
+                    None => {
+                        // A row without rowid is the parent row if it has its primary key
+                        let other_row_label = program.allocate_label();
+                        for (name, _) in &self.child.primary_key_columns {
+                            let (pos_in_table, column) = self.child.get_column(name).unwrap();
+                            program.emit_table_column(cursor_id, pos_in_table, primary_key_reg);
+                            program.emit_insn(Insn::Ne {
+                                lhs: primary_key_reg,
+                                rhs: columns_start_reg + pos_in_table,
+                                target_pc: other_row_label,
+                                flags: CmpInsFlags::default(),
+                                collation: column.collation,
+                            });
+                        }
+                        program.emit_insn(Insn::Goto {
+                            target_pc: next_label,
+                        });
+                        program.preassign_label_to_next_insn(other_row_label);
+                    }
+                }
+            }
+            for (i, (child_column, parent_column)) in
+                self.child_columns.iter().zip(&parent.columns).enumerate()
+            {
+                let child_column_def = &self.child.columns[*child_column];
+                match rowid {
+                    Some(rowid) if child_column_def.is_rowid_alias => {
+                        program.emit_insn(Insn::Copy {
+                            src_reg: rowid,
+                            dst_reg: child_key_reg + i,
+                            extra_amount: 0,
+                        });
+                    }
+                    _ => program.emit_table_column(cursor_id, *child_column, child_key_reg + i),
+                }
+                let parent_column_def = &parent.table.columns[*parent_column];
+                program.emit_insn(Insn::Ne {
+                    lhs: columns_start_reg + parent_column,
+                    rhs: child_key_reg + i,
+                    target_pc: next_label,
+                    flags: CmpInsFlags::default().jump_if_null().with_affinity(
+                        comparison_affinity(
+                            parent_column_def.affinity(),
+                            child_column_def.affinity(),
+                        ),
+                    ),
+                    collation: parent_column_def.collation,
+                });
+            }
+            program.emit_insn(Insn::FkCounter {
+                increment_value: increment,
+                deferred: self.deferred,
+            });
+            program.preassign_label_to_next_insn(next_label);
+        };
+        if self.child_clustered_index.is_some() {
+            let loop_start_label = program.allocate_label();
+            let loop_end_label = program.allocate_label();
+            program.emit_insn(Insn::Rewind {
+                cursor_id,
+                pc_if_empty: loop_end_label,
+            });
+            program.preassign_label_to_next_insn(loop_start_label);
+            emit_row(program, None);
+            program.emit_insn(Insn::Next {
+                cursor_id,
+                pc_if_next: loop_start_label,
+            });
+            program.preassign_label_to_next_insn(loop_end_label);
+        } else {
+            program.cursor_loop(cursor_id, |program, rowid| emit_row(program, Some(rowid)));
+        }
+        program.preassign_label_to_next_insn(done_label);
+    }
+
+    /// The statement run by the ON DELETE or ON UPDATE `action` of the constraint, with the
+    /// OLD and NEW parent row as trigger parameters. The child table is qualified with
+    /// `database_name` unless it is in the main database.
+    fn action_sql(&self, action: RefAct, on_update: bool, database_name: &str) -> Result<String> {
+        let parent = self.parent.as_ref().expect("parent table exists");
+        let num_columns = parent.table.columns.len();
+        let old_param = |column: usize| format!("?{}", column + 2);
+        let new_param = |column: usize| format!("?{}", num_columns + column + 3);
+        let child_column_names = self
+            .child_columns
+            .iter()
+            .map(|column| quote_ident(self.child.columns[*column].name.as_deref().unwrap()))
+            .collect::<Vec<_>>();
+
+        let mut where_clause = child_column_names
+            .iter()
+            .zip(&parent.columns)
+            .map(|(child_column, parent_column)| {
+                format!("{child_column} = {}", old_param(*parent_column))
+            })
+            .collect::<Vec<_>>()
+            .join(" AND ");
+        if on_update {
+            let unchanged = parent
+                .columns
+                .iter()
+                .map(|column| format!("{} IS {}", old_param(*column), new_param(*column)))
+                .collect::<Vec<_>>()
+                .join(" AND ");
+            where_clause.push_str(&format!(" AND NOT ({unchanged})"));
+        }
+
+        let table = match self.database_id {
+            0 => quote_ident(&self.child.name),
+            _ => format!(
+                "{}.{}",
+                quote_ident(database_name),
+                quote_ident(&self.child.name)
+            ),
+        };
+        let set = |value: &dyn Fn(usize, usize) -> Result<String>| -> Result<String> {
+            let mut sets = Vec::with_capacity(self.child_columns.len());
+            for (i, (child_column, parent_column)) in
+                self.child_columns.iter().zip(&parent.columns).enumerate()
+            {
+                sets.push(format!(
+                    "{} = {}",
+                    child_column_names[i],
+                    value(*child_column, *parent_column)?
+                ));
+            }
+            Ok(format!(
+                "UPDATE {table} SET {} WHERE {where_clause}",
+                sets.join(", ")
+            ))
+        };
+        match action {
+            RefAct::Cascade if on_update => set(&|_, parent_column| Ok(new_param(parent_column))),
+            RefAct::Cascade => Ok(format!("DELETE FROM {table} WHERE {where_clause}")),
+            RefAct::SetNull => set(&|_, _| Ok("NULL".to_string())),
+            RefAct::SetDefault => set(&|child_column, _| {
+                match &self.child.columns[child_column].default {
+                    Some(default) => default
+                        .format()
+                        .map_err(|e| LimboError::ParseError(e.to_string())),
+                    None => Ok("NULL".to_string()),
+                }
+            }),
+            RefAct::Restrict => Ok(format!(
+                "SELECT RAISE(ABORT, 'FOREIGN KEY constraint failed') FROM {table} WHERE {where_clause}"
+            )),
+            RefAct::NoAction => unreachable!("NO ACTION has no statement"),
+        }
+    }
+}
+
+/// The affinity a comparison between columns with affinities `lhs` and `rhs` applies.
+fn comparison_affinity(lhs: Affinity, rhs: Affinity) -> Affinity {
+    match (lhs.has_affinity(), rhs.has_affinity()) {
+        (true, true) if lhs.is_numeric() || rhs.is_numeric() => Affinity::Numeric,
+        (true, true) => Affinity::Blob,
+        (true, false) => lhs,
+        _ => rhs,
+    }
+}
+
+#[derive(Debug, Clone)]
+enum ForeignKeyCheck {
+    /// The row is in the child table of the constraint.
+    Child(Rc<ForeignKeyRef>),
+    /// The row is in the parent table of the constraint.
+    Parent(Rc<ForeignKeyRef>),
+}
+
+/// The foreign key checks of the rows a statement changes in a table.
+#[derive(Debug, Clone, Default)]
+pub struct ForeignKeyChecks {
+    /// Checks of the OLD row, run before it is deleted or overwritten.
+    old: Vec<ForeignKeyCheck>,
+    /// Checks of the NEW row, run once it is written.
+    new: Vec<ForeignKeyCheck>,
+}
+
+impl ForeignKeyChecks {
+    /// Resolve the constraints of `table` and the constraints referencing it that rows
+    /// changed by `event` may break. An UPDATE only checks the constraints whose key
+    /// contains one of `updated_columns`, and those of `table` that reference itself.
+    pub fn compile(
+        schema: &Schema,
+        database_id: usize,
+        connection: &Arc<Connection>,
+        table: &BTreeTable,
+        event: &TriggerEvent,
+        updated_columns: &[&str],
+    ) -> Result<Self> {
+        let mut checks = Self::default();
+        if !connection.foreign_keys_enabled() {
+            return Ok(checks);
+        }
+        let Some(table_ref) = schema.get_btree_table(&table.name) else {
+            return Ok(checks);
+        };
+        let (check_old, check_new) = match event {
+            TriggerEvent::Insert => (false, true),
+            TriggerEvent::Delete => (true, false),
+            TriggerEvent::Update | TriggerEvent::UpdateOf(_) => (true, true),
+        };
+        let is_updated = |columns: &[usize]| {
+            !matches!(event, TriggerEvent::Update | TriggerEvent::UpdateOf(_))
+                || columns_updated(table, columns, updated_columns)
+        };
+
+        for foreign_key in &table.foreign_keys {
+            let parent_table = &foreign_key.parent_table;
+            let foreign_key =
+                ForeignKeyRef::resolve(schema, database_id, table_ref.clone(), foreign_key)?;
+            if foreign_key.parent.is_none() {
+                bail_parse_error!("no such table: main.{}", parent_table);
+            }
+            // Updating any column of a row may break a constraint referencing its own table
+            if !is_updated(&foreign_key.child_columns) && *parent_table != table.name {
+                continue;
+            }
+            let foreign_key = Rc::new(foreign_key);
+            if check_old {
+                checks.old.push(ForeignKeyCheck::Child(foreign_key.clone()));
+            }
+            if check_new {
+                checks.new.push(ForeignKeyCheck::Child(foreign_key));
+            }
+        }
+        for (child, foreign_key) in schema.get_referencing_foreign_keys(&table.name) {
+            let foreign_key = ForeignKeyRef::resolve(schema, database_id, child, &foreign_key)?;
+            let parent = foreign_key.parent.as_ref().expect("parent table exists");
+            if !is_updated(&parent.columns) {
+                continue;
+            }
+            let foreign_key = Rc::new(foreign_key);
+            if check_old {
+                checks
+                    .old
+                    .push(ForeignKeyCheck::Parent(foreign_key.clone()));
+            }
+            if check_new {
+                checks.new.push(ForeignKeyCheck::Parent(foreign_key));
+            }
+        }
+        Ok(checks)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.old.is_empty() && self.new.is_empty()
+    }
+
+    pub fn emit_old(&self, program: &mut ProgramBuilder, params: &TriggerParams) {
+        for check in &self.old {
+            match check {
+                ForeignKeyCheck::Child(foreign_key) => {
+                    foreign_key.emit_child_check(program, params.old_columns_start_reg(), -1)
+                }
+                ForeignKeyCheck::Parent(foreign_key) => foreign_key.emit_parent_check(
+                    program,
+                    params.old_rowid_reg(),
+                    params.old_columns_start_reg(),
+                    1,
+                ),
+            }
+        }
+    }
+
+    pub fn emit_new(&self, program: &mut ProgramBuilder, params: &TriggerParams) {
+        for check in &self.new {
+            match check {
+                ForeignKeyCheck::Child(foreign_key) => {
+                    foreign_key.emit_child_check(program, params.new_columns_start_reg(), 1)
+                }
+                ForeignKeyCheck::Parent(foreign_key) => foreign_key.emit_parent_check(
+                    program,
+                    params.new_rowid_reg(),
+                    params.new_columns_start_reg(),
+                    -1,
+                ),
+            }
+        }
+    }
+}
+
+/// Compile the ON DELETE or ON UPDATE actions of the constraints referencing `table` that
+/// rows changed by `event` fire. An ON UPDATE action only fires if the statement updates
+/// the parent key.
+#[allow(clippy::too_many_arguments)]
+pub fn compile_actions(
+    program: &ProgramBuilder,
+    schema: &Schema,
+    database_id: usize,
+    syms: &SymbolTable,
+    connection: &Arc<Connection>,
+    table: &BTreeTable,
+    event: &TriggerEvent,
+    updated_columns: &[&str],
+) -> Result<Vec<SubProgram>> {
+    let mut actions = Vec::new();
+    if !connection.foreign_keys_enabled() {
+        return Ok(actions);
+    }
+    let on_update = match event {
+        TriggerEvent::Delete => false,
+        TriggerEvent::Update | TriggerEvent::UpdateOf(_) => true,
+        TriggerEvent::Insert => return Ok(actions),
+    };
+    let referencing_foreign_keys = schema.get_referencing_foreign_keys(&table.name);
+    for (i, (child, foreign_key)) in referencing_foreign_keys.into_iter().enumerate() {
+        let action = if on_update {
+            foreign_key.on_update
+        } else {
+            foreign_key.on_delete
+        };
+        // RESTRICT fails right away, unless all constraints are deferred.
+        if action == RefAct::NoAction
+            || (action == RefAct::Restrict && connection.get_defer_foreign_keys())
+        {
+            continue;
+        }
+        let foreign_key = ForeignKeyRef::resolve(schema, database_id, child, &foreign_key)?;
+        let parent = foreign_key.parent.as_ref().expect("parent table exists");
+        if on_update && !columns_updated(table, &parent.columns, updated_columns) {
+            continue;
+        }
+        let name = format!(
+            "sqlite_fk_action:{}:{i}:{}",
+            table.name,
+            if on_update { "update" } else { "delete" }
+        );
+        if let Some(action) = program.active_sub_program(&name) {
+            actions.push(action);
+            continue;
+        }
+        let sql = foreign_key.action_sql(
+            action,
+            on_update,
+            &connection.get_database_name(database_id),
+        )?;
+        let mut parser = Parser::new(sql.as_bytes());
+        let Some(ast::Cmd::Stmt(stmt)) = parser.next()? else {
+            unreachable!("foreign key action is a statement");
+        };
+        actions.push(SubProgram::Program(compile_sub_program(
+            program,
+            &name,
+            None,
+            vec![stmt],
+            schema,
+            syms,
+            connection,
+        )?));
+    }
+    Ok(actions)
+}
+
+/// Whether one of `columns` of `table` is among the columns an UPDATE assigns.
+fn columns_updated(table: &BTreeTable, columns: &[usize], updated_columns: &[&str]) -> bool {
+    columns.iter().any(|column| {
+        table.columns[*column].name.as_deref().is_some_and(|name| {
+            updated_columns
+                .iter()
+                .any(|updated| updated.eq_ignore_ascii_case(name))
+        })
+    })
+}
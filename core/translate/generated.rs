@@ -0,0 +1,393 @@
+//! Computation of generated columns.
+//!
+//! STORED generated columns are computed when a row is written and stored in its record like
+//! the other columns. VIRTUAL ones are not stored: they are computed when they are read, and
+//! when a row is written or deleted, for the constraints and indexes that refer to them. Their
+//! expressions are bound to the table either to read the columns they refer to from a table
+//! cursor, or, like CHECK constraints, from the registers holding a row.
+
+use std::num::NonZeroUsize;
+use std::rc::Rc;
+
+use turso_sqlite3_parser::ast::{Expr, TableInternalId};
+
+use crate::function::Func;
+use crate::schema::{Affinity, BTreeTable, Table};
+use crate::translate::emitter::Resolver;
+use crate::translate::expr::{
+    is_double_quoted_identifier, translate_expr_no_constant_opt, walk_expr, walk_expr_mut,
+    NoConstantOptReason, WalkControl,
+};
+use crate::translate::plan::{ColumnUsedMask, JoinedTable, Operation, TableReferences};
+use crate::util::normalize_ident;
+use crate::vdbe::builder::ProgramBuilder;
+use crate::vdbe::insn::Insn;
+use crate::vdbe::CursorID;
+use crate::Result;
+
+/// The generated columns of a table, computed from the registers holding a row.
+#[derive(Debug, Clone)]
+pub struct GeneratedColumns {
+    table_references: TableReferences,
+    /// The position and bound expression of each generated column, ordered so that a column
+    /// comes after the generated columns it refers to.
+    columns: Vec<(usize, Expr)>,
+}
+
+impl GeneratedColumns {
+    pub fn compile(program: &mut ProgramBuilder, table: &Rc<BTreeTable>) -> Result<Self> {
+        let internal_id = program.table_reference_counter.next();
+        let table_references = row_table_references(table, internal_id);
+        let columns = generated_column_order(table)?
+            .into_iter()
+            .map(|pos| (pos, bind_generated_column(table, pos, internal_id)))
+            .collect();
+        Ok(Self {
+            table_references,
+            columns,
+        })
+    }
+
+    /// Computes the generated columns of the row whose rowid is held in `rowid_reg` and whose
+    /// columns are held in the registers from `columns_start_reg`, into those registers.
+    pub fn emit(
+        &self,
+        program: &mut ProgramBuilder,
+        resolver: &Resolver,
+        rowid_reg: usize,
+        columns_start_reg: usize,
+    ) -> Result<()> {
+        self.emit_columns(program, resolver, rowid_reg, columns_start_reg, false)
+    }
+
+    /// Like [Self::emit], but only computes the VIRTUAL columns of a row read from the table,
+    /// whose STORED columns were read from its record.
+    pub fn emit_virtual(
+        &self,
+        program: &mut ProgramBuilder,
+        resolver: &Resolver,
+        rowid_reg: usize,
+        columns_start_reg: usize,
+    ) -> Result<()> {
+        self.emit_columns(program, resolver, rowid_reg, columns_start_reg, true)
+    }
+
+    fn emit_columns(
+        &self,
+        program: &mut ProgramBuilder,
+        resolver: &Resolver,
+        rowid_reg: usize,
+        columns_start_reg: usize,
+        virtual_only: bool,
+    ) -> Result<()> {
+        if self.columns.is_empty() {
+            return Ok(());
+        }
+        let table = &self.table_references.joined_tables()[0];
+        let column_exprs = table
+            .columns()
+            .iter()
+            .enumerate()
+            .map(|(column, col)| Expr::Column {
+                database: None,
+                table: table.internal_id,
+                column,
+                is_rowid_alias: col.is_rowid_alias,
+            })
+            .collect::<Vec<_>>();
+        let mut row_resolver = Resolver::new(resolver.schema, resolver.symbol_table);
+        for expr in &column_exprs {
+            let reg = match expr {
+                Expr::Column {
+                    column,
+                    is_rowid_alias: false,
+                    ..
+                } => columns_start_reg + column,
+                _ => rowid_reg,
+            };
+            row_resolver.expr_to_reg_cache.push((expr, reg));
+        }
+        row_resolver.enable_expr_to_reg_cache();
+
+        for (pos, expr) in &self.columns {
+            let column = &table.columns()[*pos];
+            if virtual_only && !column.is_virtual() {
+                continue;
+            }
+            let reg = columns_start_reg + pos;
+            translate_expr_no_constant_opt(
+                program,
+                Some(&self.table_references),
+                expr,
+                reg,
+                &row_resolver,
+                NoConstantOptReason::RegisterReuse,
+            )?;
+            emit_column_affinity(program, column.affinity(), reg);
+        }
+        Ok(())
+    }
+}
+
+/// Binds the expression of the generated column at `pos_in_table` to the table reference
+/// `internal_id`: the columns it refers to become columns of that table reference.
+pub fn bind_generated_column(
+    table: &BTreeTable,
+    pos_in_table: usize,
+    internal_id: TableInternalId,
+) -> Expr {
+    let generated = table.columns[pos_in_table]
+        .generated
+        .as_ref()
+        .expect("column must be generated");
+    bind_column_names(&generated.expr, table, internal_id)
+}
+
+/// Binds an expression of the schema of `table`, which refers to its columns by name, to the
+/// table reference `internal_id`.
+pub fn bind_column_names(expr: &Expr, table: &BTreeTable, internal_id: TableInternalId) -> Expr {
+    let mut expr = expr.clone();
+    let _ = walk_expr_mut(&mut expr, &mut |expr| {
+        if let Expr::Id(id) = expr {
+            if let Some((column, col)) = table.get_column(id.as_str()) {
+                *expr = Expr::Column {
+                    database: None,
+                    table: internal_id,
+                    column,
+                    is_rowid_alias: col.is_rowid_alias,
+                };
+            }
+        }
+        Ok(())
+    });
+    expr
+}
+
+/// The table references of the expressions of the schema of `table` bound to the table
+/// reference `internal_id`.
+pub fn row_table_references(
+    table: &Rc<BTreeTable>,
+    internal_id: TableInternalId,
+) -> TableReferences {
+    TableReferences::new(
+        vec![JoinedTable {
+            op: Operation::Scan {
+                iter_dir: crate::translate::plan::IterationDirection::Forwards,
+                index: None,
+            },
+            table: Table::BTree(table.clone()),
+            identifier: table.name.clone(),
+            internal_id,
+            join_info: None,
+            col_used_mask: ColumnUsedMask::default(),
+            database_id: 0,
+            index_hint: None,
+        }],
+        vec![],
+    )
+}
+
+/// Applies the affinity of a generated column to its computed value, like SQLite does for the
+/// affinities other than BLOB.
+pub fn emit_column_affinity(program: &mut ProgramBuilder, affinity: Affinity, reg: usize) {
+    if affinity == Affinity::Blob {
+        return;
+    }
+    program.emit_insn(Insn::Affinity {
+        start_reg: reg,
+        count: NonZeroUsize::new(1).unwrap(),
+        affinities: affinity.aff_mask().to_string(),
+    });
+}
+
+/// Reads the row the table cursor `cursor_id` is positioned on into the registers from
+/// `columns_start_reg`, computing its VIRTUAL columns. The rowid is read into `rowid_reg`,
+/// which the rowid alias column, if any, is copied from.
+pub fn emit_row_from_cursor(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    table: &Rc<BTreeTable>,
+    cursor_id: CursorID,
+    rowid_reg: usize,
+    columns_start_reg: usize,
+) -> Result<()> {
+    if table.has_rowid {
+        program.emit_insn(Insn::RowId {
+            cursor_id,
+            dest: rowid_reg,
+        });
+    }
+    for (i, column) in table.columns.iter().enumerate() {
+        if column.is_rowid_alias {
+            program.emit_insn(Insn::Copy {
+                src_reg: rowid_reg,
+                dst_reg: columns_start_reg + i,
+                extra_amount: 0,
+            });
+        } else {
+            program.emit_table_column(cursor_id, i, columns_start_reg + i);
+        }
+    }
+    if table.has_virtual_columns() {
+        GeneratedColumns::compile(program, table)?.emit_virtual(
+            program,
+            resolver,
+            rowid_reg,
+            columns_start_reg,
+        )?;
+    }
+    Ok(())
+}
+
+/// Emits the record of the row whose columns are held in the registers from
+/// `columns_start_reg`, which leaves out the VIRTUAL columns.
+pub fn emit_table_record(
+    program: &mut ProgramBuilder,
+    table: &BTreeTable,
+    columns_start_reg: usize,
+    dest_reg: usize,
+) {
+    if !table.has_virtual_columns() {
+        program.emit_insn(Insn::MakeRecord {
+            start_reg: columns_start_reg,
+            count: table.columns.len(),
+            dest_reg,
+            index_name: None,
+        });
+        return;
+    }
+    let stored_columns = table
+        .columns
+        .iter()
+        .enumerate()
+        .filter(|(_, column)| !column.is_virtual())
+        .map(|(i, _)| i)
+        .collect::<Vec<_>>();
+    let start_reg = program.alloc_registers(stored_columns.len());
+    for (i, pos) in stored_columns.iter().enumerate() {
+        program.emit_insn(Insn::Copy {
+            src_reg: columns_start_reg + pos,
+            dst_reg: start_reg + i,
+            extra_amount: 0,
+        });
+    }
+    program.emit_insn(Insn::MakeRecord {
+        start_reg,
+        count: stored_columns.len(),
+        dest_reg,
+        index_name: None,
+    });
+}
+
+/// Returns the positions of the generated columns that depend on one of `columns`, directly
+/// or through other generated columns.
+pub fn dependent_generated_columns(table: &BTreeTable, columns: &[usize]) -> Vec<usize> {
+    let mut dependent: Vec<usize> = vec![];
+    loop {
+        let found = table.columns.iter().enumerate().find(|(pos, column)| {
+            !dependent.contains(pos)
+                && column.generated.as_ref().is_some_and(|generated| {
+                    generated.referenced_columns().iter().any(|name| {
+                        table.get_column(name).is_some_and(|(dep, _)| {
+                            columns.contains(&dep) || dependent.contains(&dep)
+                        })
+                    })
+                })
+        });
+        match found {
+            Some((pos, _)) => dependent.push(pos),
+            None => return dependent,
+        }
+    }
+}
+
+/// Returns the positions of the generated columns of `table`, ordered so that a column comes
+/// after the generated columns it refers to.
+fn generated_column_order(table: &BTreeTable) -> Result<Vec<usize>> {
+    let mut pending = table
+        .columns
+        .iter()
+        .enumerate()
+        .filter(|(_, column)| column.is_generated())
+        .map(|(pos, _)| pos)
+        .collect::<Vec<_>>();
+    let mut order = Vec::with_capacity(pending.len());
+    while let Some(&last) = pending.last() {
+        let ready = pending.iter().position(|pos| {
+            let generated = table.columns[*pos].generated.as_ref().unwrap();
+            generated.referenced_columns().iter().all(|name| {
+                table
+                    .get_column(name)
+                    .is_none_or(|(dep, column)| !column.is_generated() || order.contains(&dep))
+            })
+        });
+        let Some(ready) = ready else {
+            crate::bail_parse_error!(
+                "generated column loop on \"{}\"",
+                table.columns[last].name.as_deref().unwrap_or_default()
+            );
+        };
+        order.push(pending.remove(ready));
+    }
+    Ok(order)
+}
+
+/// Fails if the generated columns of a table being created are invalid.
+pub fn validate_generated_columns(table: &BTreeTable) -> Result<()> {
+    for column in &table.columns {
+        let Some(generated) = &column.generated else {
+            continue;
+        };
+        let name = column.name.as_deref().unwrap_or_default();
+        if column.primary_key {
+            crate::bail_parse_error!("generated columns cannot be part of the PRIMARY KEY");
+        }
+        if column.default.is_some() {
+            crate::bail_parse_error!("error in generated column \"{}\"", name);
+        }
+        if !generated.stored && !table.has_rowid {
+            crate::bail_parse_error!(
+                "VIRTUAL generated columns are not supported on WITHOUT ROWID tables"
+            );
+        }
+        walk_expr(&generated.expr, &mut |expr| {
+            match expr {
+                Expr::Exists(_) | Expr::Subquery(_) | Expr::InSelect { .. } => {
+                    crate::bail_parse_error!("subqueries prohibited in generated columns");
+                }
+                Expr::Qualified(..) | Expr::DoublyQualified(..) => {
+                    crate::bail_parse_error!("the \".\" operator prohibited in generated columns");
+                }
+                Expr::Variable(_) => {
+                    crate::bail_parse_error!("parameters prohibited in generated columns");
+                }
+                Expr::Id(id) => {
+                    if table.get_column(id.as_str()).is_none()
+                        && !is_double_quoted_identifier(id.as_str())
+                        && !id.as_str().eq_ignore_ascii_case("true")
+                        && !id.as_str().eq_ignore_ascii_case("false")
+                    {
+                        crate::bail_parse_error!(
+                            "no such column: {}",
+                            normalize_ident(id.as_str())
+                        );
+                    }
+                }
+                Expr::FunctionCall { name, args, .. } => {
+                    let arg_count = args.as_ref().map_or(0, |args| args.len());
+                    if Func::resolve_function(name.as_str(), arg_count)
+                        .is_ok_and(|func| !func.is_deterministic())
+                    {
+                        crate::bail_parse_error!(
+                            "non-deterministic functions prohibited in generated columns"
+                        );
+                    }
+                }
+                _ => {}
+            }
+            Ok(WalkControl::Continue)
+        })?;
+    }
+    generated_column_order(table)?;
+    Ok(())
+}
@@ -12,7 +12,7 @@ use crate::{
         insn::Insn,
         BranchOffset,
     },
-    Result,
+    LimboError, Result,
 };
 
 use super::{
@@ -428,7 +428,8 @@ impl<'a> GroupByAggArgumentSource<'a> {
         col_start: usize,
         aggregate: &'a Aggregate,
     ) -> Self {
-        let dest_reg_start = program.alloc_registers(aggregate.args.len());
+        let dest_reg_start =
+            program.alloc_registers(aggregate.args.len() + usize::from(aggregate.filter.is_some()));
         Self::PseudoCursor {
             cursor_id,
             col_start,
@@ -479,7 +480,7 @@ impl<'a> GroupByAggArgumentSource<'a> {
                 dest_reg_start,
                 ..
             } => {
-                program.emit_column(*cursor_id, *col_start, dest_reg_start + arg_idx);
+                program.emit_column(*cursor_id, *col_start + arg_idx, dest_reg_start + arg_idx);
                 Ok(dest_reg_start + arg_idx)
             }
             GroupByAggArgumentSource::Register {
@@ -620,7 +621,7 @@ pub fn group_by_process_single_group(
                 .expect("distinct aggregate context not populated");
             program.preassign_label_to_next_insn(ctx.label_on_conflict);
         }
-        offset += agg.args.len();
+        offset += agg.args.len() + usize::from(agg.filter.is_some());
     }
 
     // We only need to store non-aggregate columns once per group
@@ -863,7 +864,7 @@ pub fn group_by_emit_row_phase<'a>(
     program.emit_insn(Insn::Null {
         dest: start_reg,
         dest_end: Some(
-            start_reg + t_ctx.non_aggregate_expressions.len() + plan.agg_args_count() - 1,
+            start_reg + t_ctx.non_aggregate_expressions.len() + plan.aggregates.len() - 1,
         ),
     });
 
@@ -913,6 +914,20 @@ pub fn translate_aggregation_step_groupby(
     resolver: &Resolver,
 ) -> Result<usize> {
     let num_args = agg_arg_source.num_args();
+    // The FILTER condition, if any, was stored after the arguments.
+    let label_filtered = match agg_arg_source.aggregate().filter {
+        Some(_) => {
+            let filter_reg = agg_arg_source.translate(program, num_args)?;
+            let label = program.allocate_label();
+            program.emit_insn(Insn::IfNot {
+                reg: filter_reg,
+                target_pc: label,
+                jump_if_null: true,
+            });
+            Some(label)
+        }
+        None => None,
+    };
     let dest = match agg_arg_source.agg_func() {
         AggFunc::Avg => {
             if num_args != 1 {
@@ -1109,9 +1124,40 @@ pub fn translate_aggregation_step_groupby(
             });
             target_register
         }
-        AggFunc::External(_) => {
-            todo!("External aggregate functions are not yet supported in GROUP BY");
+        AggFunc::External(func) => {
+            let argc = func.agg_args().map_err(|_| {
+                LimboError::ExtensionError(
+                    "External aggregate function called with wrong number of arguments".to_string(),
+                )
+            })?;
+            if argc != num_args {
+                crate::bail_parse_error!(
+                    "External aggregate function called with wrong number of arguments"
+                );
+            }
+            // Both argument sources read the arguments into consecutive registers.
+            let expr_reg = match argc {
+                0 => program.alloc_register(),
+                _ => agg_arg_source.translate(program, 0)?,
+            };
+            for i in 1..argc {
+                agg_arg_source.translate(program, i)?;
+            }
+            // invariant: distinct aggregates are only supported for single-argument functions
+            if argc == 1 {
+                handle_distinct(program, agg_arg_source.aggregate(), expr_reg);
+            }
+            program.emit_insn(Insn::AggStep {
+                acc_reg: target_register,
+                col: expr_reg,
+                delimiter: 0,
+                func: AggFunc::External(func.clone()),
+            });
+            target_register
         }
     };
+    if let Some(label) = label_filtered {
+        program.preassign_label_to_next_insn(label);
+    }
     Ok(dest)
 }
@@ -1,33 +1,56 @@
+use std::rc::Rc;
+use std::str::FromStr as _;
 use std::sync::Arc;
 
+use crate::function::Func;
+use crate::translate::expr::{
+    is_double_quoted_identifier, translate_expr_no_constant_opt, walk_expr, NoConstantOptReason,
+    WalkControl,
+};
+use crate::translate::generated::{bind_column_names, row_table_references};
 use crate::vdbe::insn::{CmpInsFlags, Cookie};
 use crate::{
-    schema::{BTreeTable, Column, Index, IndexColumn, PseudoCursorType, Schema},
+    schema::{BTreeTable, Index, IndexColumn, PseudoCursorType},
     storage::pager::CreateBTreeFlags,
+    translate::collate::CollationSeq,
     util::normalize_ident,
     vdbe::{
         builder::{CursorType, ProgramBuilder},
         insn::{IdxInsertFlags, Insn, RegisterOrLiteral},
+        BranchOffset,
     },
+    Connection, SymbolTable,
 };
 use turso_sqlite3_parser::ast::{self, Expr, SortOrder, SortedColumn};
 
+use super::emitter::{emit_index_entry, Resolver};
 use super::schema::{emit_schema_entry, SchemaEntryType, SQLITE_TABLEID};
 
+#[allow(clippy::too_many_arguments)]
 pub fn translate_create_index(
     unique_if_not_exists: (bool, bool),
-    idx_name: &str,
+    idx_name: &ast::QualifiedName,
     tbl_name: &str,
     columns: &[SortedColumn],
-    schema: &Schema,
+    where_clause: Option<&Expr>,
+    syms: &SymbolTable,
     mut program: ProgramBuilder,
+    connection: &Arc<Connection>,
 ) -> crate::Result<ProgramBuilder> {
+    // Like in SQLite, an index is created in the database its name is qualified with, or else
+    // in the database of its table.
+    let database_id = match idx_name.db_name {
+        Some(_) => connection.resolve_database_id(idx_name)?,
+        None => connection
+            .resolve_database_id(&ast::QualifiedName::single(ast::Name::from_str(tbl_name)))?,
+    };
+    let schema = &connection.database_schema(database_id);
     if !schema.indexes_enabled() {
         crate::bail_parse_error!(
             "CREATE INDEX is disabled by default. Run with `--experimental-indexes` to enable this feature."
         );
     }
-    let idx_name = normalize_ident(idx_name);
+    let idx_name = normalize_ident(idx_name.name.as_str());
     let tbl_name = normalize_ident(tbl_name);
     let opts = crate::vdbe::builder::ProgramBuilderOpts {
         num_cursors: 5,
@@ -41,54 +64,51 @@ pub fn translate_create_index(
     if !schema.is_unique_idx_name(&idx_name) {
         crate::bail_parse_error!("Error: index with name '{idx_name}' already exists.");
     }
+    if schema.get_view(&tbl_name).is_some() {
+        crate::bail_parse_error!("views may not be indexed");
+    }
     let Some(tbl) = schema.tables.get(&tbl_name) else {
         crate::bail_parse_error!("Error: table '{tbl_name}' does not exist.");
     };
     let Some(tbl) = tbl.btree() else {
         crate::bail_parse_error!("Error: table '{tbl_name}' is not a b-tree table.");
     };
-    let columns = resolve_sorted_columns(&tbl, columns)?;
+    let index_columns = columns
+        .iter()
+        .map(|column| {
+            let column = IndexColumn::from_sorted_column(&tbl, column)?;
+            if let Some(expr) = &column.expr {
+                validate_index_expr(&tbl, expr, "index expressions")?;
+            }
+            Ok(column)
+        })
+        .collect::<crate::Result<Vec<_>>>()?;
+    if let Some(where_clause) = where_clause {
+        validate_index_expr(&tbl, where_clause, "partial index WHERE clauses")?;
+    }
 
-    let idx = Arc::new(Index {
+    let mut idx = Index {
         name: idx_name.clone(),
         table_name: tbl.name.clone(),
         root_page: 0, //  we dont have access till its created, after we parse the schema table
-        columns: columns
-            .iter()
-            .map(|((pos_in_table, col), order)| IndexColumn {
-                name: col.name.as_ref().unwrap().clone(),
-                order: *order,
-                pos_in_table: *pos_in_table,
-                collation: col.collation,
-                default: col.default.clone(),
-            })
-            .collect(),
+        columns: index_columns,
         unique: unique_if_not_exists.0,
         ephemeral: false,
         has_rowid: tbl.has_rowid,
-    });
+        num_extra_columns: 0,
+        where_clause: where_clause.map(|expr| Box::new(expr.clone())),
+    };
+    idx.append_primary_key_columns(&tbl);
+    let idx = Arc::new(idx);
 
-    // Allocate the necessary cursors:
-    //
-    // 1. sqlite_schema_cursor_id - sqlite_schema table
-    // 2. btree_cursor_id         - new index btree
-    // 3. table_cursor_id         - table we are creating the index on
-    // 4. sorter_cursor_id        - sorter
-    // 5. pseudo_cursor_id        - pseudo table to store the sorted index values
     let sqlite_table = schema.get_btree_table(SQLITE_TABLEID).unwrap();
     let sqlite_schema_cursor_id =
         program.alloc_cursor_id(CursorType::BTreeTable(sqlite_table.clone()));
-    let btree_cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(idx.clone()));
-    let table_cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(tbl.clone()));
-    let sorter_cursor_id = program.alloc_cursor_id(CursorType::Sorter);
-    let pseudo_cursor_id = program.alloc_cursor_id(CursorType::Pseudo(PseudoCursorType {
-        column_count: tbl.columns.len(),
-    }));
 
     // Create a new B-Tree and store the root page index in a register
     let root_page_reg = program.alloc_register();
     program.emit_insn(Insn::CreateBtree {
-        db: 0,
+        db: database_id,
         root: root_page_reg,
         flags: CreateBTreeFlags::new_index(),
     });
@@ -97,9 +117,16 @@ pub fn translate_create_index(
     program.emit_insn(Insn::OpenWrite {
         cursor_id: sqlite_schema_cursor_id,
         root_page: RegisterOrLiteral::Literal(sqlite_table.root_page),
-        db: 0,
+        db: database_id,
     });
-    let sql = create_idx_stmt_to_sql(&tbl_name, &idx_name, unique_if_not_exists, &columns);
+    let sql = create_idx_stmt_to_sql(
+        &tbl_name,
+        &idx_name,
+        unique_if_not_exists,
+        columns,
+        &idx.columns,
+        where_clause,
+    );
     emit_schema_entry(
         &mut program,
         sqlite_schema_cursor_id,
@@ -110,12 +137,80 @@ pub fn translate_create_index(
         Some(sql),
     );
 
+    let resolver = Resolver::new(schema, syms);
+    emit_populate_index(
+        &mut program,
+        &resolver,
+        database_id,
+        &tbl,
+        &idx,
+        RegisterOrLiteral::Register(root_page_reg),
+        false,
+    )?;
+
+    // The schema table is kept open to emit ParseSchema
+    program.emit_insn(Insn::SetCookie {
+        db: database_id,
+        cookie: Cookie::SchemaVersion,
+        value: schema.schema_version as i32 + 1,
+        p5: 0,
+    });
+    // Parse the schema table to get the index root page and add new index to Schema
+    let parse_schema_where_clause = format!("name = '{idx_name}' AND type = 'index'");
+    program.emit_insn(Insn::ParseSchema {
+        db: database_id,
+        where_clause: Some(parse_schema_where_clause),
+    });
+    // Close the final sqlite_schema cursor
+    program.emit_insn(Insn::Close {
+        cursor_id: sqlite_schema_cursor_id,
+    });
+
+    // Epilogue:
+    program.epilogue(super::emitter::TransactionMode::Write);
+
+    Ok(program)
+}
+
+/// Emits the loop that adds an entry to the b-tree of `idx` rooted at `root_page` for each row
+/// of `tbl`. The entries are sorted first so that they can be appended to the b-tree in order.
+/// With `clear`, the entries the b-tree has are deleted first, which is how REINDEX rebuilds
+/// an index.
+pub(crate) fn emit_populate_index(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    database_id: usize,
+    tbl: &Rc<BTreeTable>,
+    idx: &Arc<Index>,
+    root_page: RegisterOrLiteral<usize>,
+    clear: bool,
+) -> crate::Result<()> {
+    // Tables without rowid are read through their clustered index.
+    let table_cursor_type = match resolver.schema.get_clustered_index(tbl) {
+        Some(clustered_index) => CursorType::BTreeIndex(clustered_index.clone()),
+        None => CursorType::BTreeTable(tbl.clone()),
+    };
+    let num_record_columns = idx.columns.len() + usize::from(idx.has_rowid);
+
+    // Allocate the necessary cursors:
+    //
+    // 1. btree_cursor_id         - index btree
+    // 2. table_cursor_id         - table the index is on
+    // 3. sorter_cursor_id        - sorter
+    // 4. pseudo_cursor_id        - pseudo table to store the sorted index values
+    let btree_cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(idx.clone()));
+    let table_cursor_id = program.alloc_cursor_id(table_cursor_type);
+    let sorter_cursor_id = program.alloc_cursor_id(CursorType::Sorter);
+    let pseudo_cursor_id = program.alloc_cursor_id(CursorType::Pseudo(PseudoCursorType {
+        column_count: tbl.columns.len(),
+    }));
+
     // determine the order of the columns in the index for the sorter
     let order = idx.columns.iter().map(|c| c.order).collect();
     // open the sorter and the pseudo table
     program.emit_insn(Insn::SorterOpen {
         cursor_id: sorter_cursor_id,
-        columns: columns.len(),
+        columns: idx.columns.len(),
         order,
         collations: idx.columns.iter().map(|c| c.collation).collect(),
     });
@@ -123,14 +218,14 @@ pub fn translate_create_index(
     program.emit_insn(Insn::OpenPseudo {
         cursor_id: pseudo_cursor_id,
         content_reg,
-        num_fields: columns.len() + 1,
+        num_fields: num_record_columns,
     });
 
-    // open the table we are creating the index on for reading
+    // open the table the index is on for reading
     program.emit_insn(Insn::OpenRead {
         cursor_id: table_cursor_id,
         root_page: tbl.root_page,
-        db: 0,
+        db: database_id,
     });
 
     let loop_start_label = program.allocate_label();
@@ -144,42 +239,50 @@ pub fn translate_create_index(
     // Loop start:
     // Collect index values into start_reg..rowid_reg
     // emit MakeRecord (index key + rowid) into record_reg.
+    // Tables without rowid store their primary key columns instead of the rowid.
     //
-    // Then insert the record into the sorter
-    let start_reg = program.alloc_registers(columns.len() + 1);
-    for (i, (col, _)) in columns.iter().enumerate() {
-        program.emit_column(table_cursor_id, col.0, start_reg + i);
-    }
-    let rowid_reg = start_reg + columns.len();
-    program.emit_insn(Insn::RowId {
-        cursor_id: table_cursor_id,
-        dest: rowid_reg,
-    });
+    // Then insert the record into the sorter, unless the index is partial and the row has
+    // no entry in it.
+    let not_indexed_label = program.allocate_label();
+    let (start_reg, _) = emit_index_entry(
+        program,
+        resolver,
+        tbl,
+        table_cursor_id,
+        idx,
+        not_indexed_label,
+    )?;
     let record_reg = program.alloc_register();
     program.emit_insn(Insn::MakeRecord {
         start_reg,
-        count: columns.len() + 1,
+        count: num_record_columns,
         dest_reg: record_reg,
-        index_name: Some(idx_name.clone()),
+        index_name: Some(idx.name.clone()),
     });
     program.emit_insn(Insn::SorterInsert {
         cursor_id: sorter_cursor_id,
         record_reg,
     });
 
+    program.preassign_label_to_next_insn(not_indexed_label);
     program.emit_insn(Insn::Next {
         cursor_id: table_cursor_id,
         pc_if_next: loop_start_label,
     });
     program.preassign_label_to_next_insn(loop_end_label);
 
-    // Open the index btree we created for writing to insert the
+    // Open the index btree for writing to insert the
     // newly sorted index records.
     program.emit_insn(Insn::OpenWrite {
         cursor_id: btree_cursor_id,
-        root_page: RegisterOrLiteral::Register(root_page_reg),
-        db: 0,
+        root_page,
+        db: database_id,
     });
+    if clear {
+        program.emit_insn(Insn::Clear {
+            cursor_id: btree_cursor_id,
+        });
+    }
 
     let sorted_loop_start = program.allocate_label();
     let sorted_loop_end = program.allocate_label();
@@ -215,65 +318,163 @@ pub fn translate_create_index(
     });
     program.preassign_label_to_next_insn(sorted_loop_end);
 
-    // End of the outer loop
-    //
-    // Keep schema table open to emit ParseSchema, close the other cursors.
     program.close_cursors(&[sorter_cursor_id, table_cursor_id, btree_cursor_id]);
-
-    program.emit_insn(Insn::SetCookie {
-        db: 0,
-        cookie: Cookie::SchemaVersion,
-        value: schema.schema_version as i32 + 1,
-        p5: 0,
-    });
-    // Parse the schema table to get the index root page and add new index to Schema
-    let parse_schema_where_clause = format!("name = '{idx_name}' AND type = 'index'");
-    program.emit_insn(Insn::ParseSchema {
-        db: sqlite_schema_cursor_id,
-        where_clause: Some(parse_schema_where_clause),
-    });
-    // Close the final sqlite_schema cursor
-    program.emit_insn(Insn::Close {
-        cursor_id: sqlite_schema_cursor_id,
-    });
-
-    // Epilogue:
-    program.epilogue(super::emitter::TransactionMode::Write);
-
-    Ok(program)
+    Ok(())
 }
 
-fn resolve_sorted_columns<'a>(
-    table: &'a BTreeTable,
-    cols: &[SortedColumn],
-) -> crate::Result<Vec<((usize, &'a Column), SortOrder)>> {
-    let mut resolved = Vec::with_capacity(cols.len());
-    for sc in cols {
-        let ident = normalize_ident(match &sc.expr {
-            // SQLite supports indexes on arbitrary expressions, but we don't (yet).
-            // See "How to use indexes on expressions" in https://www.sqlite.org/expridx.html
-            Expr::Id(ast::Name::Ident(col_name))
-            | Expr::Id(ast::Name::Quoted(col_name))
-            | Expr::Name(ast::Name::Ident(col_name))
-            | Expr::Name(ast::Name::Quoted(col_name)) => col_name,
-            _ => crate::bail_parse_error!("Error: cannot use expressions in CREATE INDEX"),
-        });
-        let Some(col) = table.get_column(&ident) else {
-            crate::bail_parse_error!(
-                "Error: column '{ident}' does not exist in table '{}'",
-                table.name
-            );
+/// Emits the entry in `index` of the row whose rowid is held in `rowid_reg` and whose columns
+/// are held in the registers from `columns_start_reg`, into the registers from `start_reg`: the
+/// indexed columns and expressions, followed by the rowid if the table has one. Jumps to
+/// `not_indexed_label` if the index is partial and the row has no entry in it.
+#[allow(clippy::too_many_arguments)]
+pub fn emit_index_entry_from_row(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    table: &Rc<BTreeTable>,
+    index: &Index,
+    rowid_reg: usize,
+    columns_start_reg: usize,
+    start_reg: usize,
+    not_indexed_label: BranchOffset,
+) -> crate::Result<()> {
+    let internal_id = program.table_reference_counter.next();
+    let table_references = row_table_references(table, internal_id);
+    let column_exprs = table
+        .columns
+        .iter()
+        .enumerate()
+        .map(|(column, col)| Expr::Column {
+            database: None,
+            table: internal_id,
+            column,
+            is_rowid_alias: col.is_rowid_alias,
+        })
+        .collect::<Vec<_>>();
+    let mut row_resolver = Resolver::new(resolver.schema, resolver.symbol_table);
+    for expr in &column_exprs {
+        let reg = match expr {
+            Expr::Column {
+                column,
+                is_rowid_alias: false,
+                ..
+            } => columns_start_reg + column,
+            _ => rowid_reg,
         };
-        resolved.push((col, sc.order.unwrap_or(SortOrder::Asc)));
+        row_resolver.expr_to_reg_cache.push((expr, reg));
     }
-    Ok(resolved)
+    row_resolver.enable_expr_to_reg_cache();
+
+    if let Some(where_clause) = &index.where_clause {
+        let expr = bind_column_names(where_clause, table, internal_id);
+        let reg = program.alloc_register();
+        translate_expr_no_constant_opt(
+            program,
+            Some(&table_references),
+            &expr,
+            reg,
+            &row_resolver,
+            NoConstantOptReason::RegisterReuse,
+        )?;
+        program.emit_insn(Insn::IfNot {
+            reg,
+            target_pc: not_indexed_label,
+            jump_if_null: true,
+        });
+    }
+    for (i, column) in index.columns.iter().enumerate() {
+        match &column.expr {
+            Some(expr) => {
+                let expr = bind_column_names(expr, table, internal_id);
+                translate_expr_no_constant_opt(
+                    program,
+                    Some(&table_references),
+                    &expr,
+                    start_reg + i,
+                    &row_resolver,
+                    NoConstantOptReason::RegisterReuse,
+                )?;
+            }
+            None => {
+                program.emit_insn(Insn::Copy {
+                    src_reg: if table.columns[column.pos_in_table].is_rowid_alias {
+                        rowid_reg
+                    } else {
+                        columns_start_reg + column.pos_in_table
+                    },
+                    dst_reg: start_reg + i,
+                    extra_amount: 0,
+                });
+            }
+        }
+    }
+    if index.has_rowid {
+        program.emit_insn(Insn::Copy {
+            src_reg: rowid_reg,
+            dst_reg: start_reg + index.columns.len(),
+            extra_amount: 0,
+        });
+    }
+    Ok(())
+}
+
+/// Fails if `expr`, an expression of an index or its WHERE clause, may not be used in
+/// `context`: like in SQLite, it may only refer to the columns of the table and may only call
+/// deterministic functions.
+fn validate_index_expr(table: &BTreeTable, expr: &Expr, context: &str) -> crate::Result<()> {
+    walk_expr(expr, &mut |expr| {
+        match expr {
+            Expr::Exists(_) | Expr::Subquery(_) | Expr::InSelect { .. } => {
+                crate::bail_parse_error!("subqueries prohibited in {context}");
+            }
+            Expr::Variable(_) => {
+                crate::bail_parse_error!("parameters prohibited in {context}");
+            }
+            Expr::Qualified(..) | Expr::DoublyQualified(..) => {
+                crate::bail_parse_error!("the \".\" operator prohibited in {context}");
+            }
+            Expr::Id(id) => {
+                if table.get_column(id.as_str()).is_none()
+                    && !is_double_quoted_identifier(id.as_str())
+                    && !id.as_str().eq_ignore_ascii_case("true")
+                    && !id.as_str().eq_ignore_ascii_case("false")
+                {
+                    crate::bail_parse_error!("no such column: {}", normalize_ident(id.as_str()));
+                }
+            }
+            Expr::FunctionCall { name, args, .. } => {
+                let arg_count = args.as_ref().map_or(0, |args| args.len());
+                match Func::resolve_function(name.as_str(), arg_count) {
+                    Ok(Func::Agg(_)) => {
+                        crate::bail_parse_error!(
+                            "misuse of aggregate function {}()",
+                            name.as_str()
+                        );
+                    }
+                    Ok(func) if !func.is_deterministic() => {
+                        crate::bail_parse_error!(
+                            "non-deterministic functions prohibited in {context}"
+                        );
+                    }
+                    _ => {}
+                }
+            }
+            Expr::FunctionCallStar { name, .. } => {
+                crate::bail_parse_error!("misuse of aggregate function {}()", name.as_str());
+            }
+            _ => {}
+        }
+        Ok(WalkControl::Continue)
+    })?;
+    Ok(())
 }
 
 fn create_idx_stmt_to_sql(
     tbl_name: &str,
     idx_name: &str,
     unique_if_not_exists: (bool, bool),
-    cols: &[((usize, &Column), SortOrder)],
+    columns: &[SortedColumn],
+    index_columns: &[IndexColumn],
+    where_clause: Option<&Expr>,
 ) -> String {
     let mut sql = String::with_capacity(128);
     sql.push_str("CREATE ");
@@ -288,31 +489,41 @@ fn create_idx_stmt_to_sql(
     sql.push_str(" ON ");
     sql.push_str(tbl_name);
     sql.push_str(" (");
-    for (i, (col, order)) in cols.iter().enumerate() {
+    for (i, (column, index_column)) in columns.iter().zip(index_columns).enumerate() {
         if i > 0 {
             sql.push_str(", ");
         }
-        sql.push_str(col.1.name.as_ref().unwrap());
-        if *order == SortOrder::Desc {
+        // Expressions and collations are kept as they were written
+        match column.expr {
+            Expr::Id(_) | Expr::Name(_) => sql.push_str(&index_column.name),
+            ref expr => sql.push_str(&expr.to_string()),
+        }
+        if index_column.order == SortOrder::Desc {
             sql.push_str(" DESC");
         }
     }
     sql.push(')');
+    if let Some(where_clause) = where_clause {
+        sql.push_str(" WHERE ");
+        sql.push_str(&where_clause.to_string());
+    }
     sql
 }
 
 pub fn translate_drop_index(
-    idx_name: &str,
+    idx_name: &ast::QualifiedName,
     if_exists: bool,
-    schema: &Schema,
     mut program: ProgramBuilder,
+    connection: &Arc<Connection>,
 ) -> crate::Result<ProgramBuilder> {
+    let database_id = connection.resolve_database_id(idx_name)?;
+    let schema = &connection.database_schema(database_id);
     if !schema.indexes_enabled() {
         crate::bail_parse_error!(
             "DROP INDEX is disabled by default. Run with `--experimental-indexes` to enable this feature."
         );
     }
-    let idx_name = normalize_ident(idx_name);
+    let idx_name = normalize_ident(idx_name.name.as_str());
     let opts = crate::vdbe::builder::ProgramBuilderOpts {
         num_cursors: 5,
         approx_num_insns: 40,
@@ -370,7 +581,7 @@ pub fn translate_drop_index(
     program.emit_insn(Insn::OpenWrite {
         cursor_id: sqlite_schema_cursor_id,
         root_page: RegisterOrLiteral::Literal(sqlite_table.root_page),
-        db: 0,
+        db: database_id,
     });
 
     let loop_start_label = program.allocate_label();
@@ -432,7 +643,7 @@ pub fn translate_drop_index(
     program.resolve_label(loop_end_label, program.offset());
 
     program.emit_insn(Insn::SetCookie {
-        db: 0,
+        db: database_id,
         cookie: Cookie::SchemaVersion,
         value: schema.schema_version as i32 + 1,
         p5: 0,
@@ -442,14 +653,14 @@ pub fn translate_drop_index(
     program.emit_insn(Insn::Destroy {
         root: maybe_index.unwrap().root_page,
         former_root_reg: 0,
-        is_temp: 0,
+        db: database_id,
     });
 
     // Remove from the Schema any mention of the index
     if let Some(idx) = maybe_index {
         program.emit_insn(Insn::DropIndex {
             index: idx.clone(),
-            db: 0,
+            db: database_id,
         });
     }
 
@@ -458,3 +669,113 @@ pub fn translate_drop_index(
 
     Ok(program)
 }
+
+/// Translate REINDEX [collation-name | [schema-name.]table-or-index-name]
+///
+/// Like in SQLite, a name that is not qualified with a schema is looked up as a collation
+/// sequence first, which rebuilds every index with a column that uses it, and then as a table,
+/// which rebuilds all its indexes. Without a name, all the indexes of all databases are rebuilt.
+pub fn translate_reindex(
+    obj_name: Option<&ast::QualifiedName>,
+    syms: &SymbolTable,
+    mut program: ProgramBuilder,
+    connection: &Arc<Connection>,
+) -> crate::Result<ProgramBuilder> {
+    let opts = crate::vdbe::builder::ProgramBuilderOpts {
+        num_cursors: 4,
+        approx_num_insns: 30,
+        approx_num_labels: 4,
+    };
+    program.extend(&opts);
+
+    let all_databases = || {
+        connection
+            .list_all_databases()
+            .into_iter()
+            .map(|(database_id, ..)| database_id)
+            .collect::<Vec<_>>()
+    };
+    let (databases, filter) = match obj_name {
+        None => (all_databases(), ReindexFilter::All),
+        Some(name) => {
+            let object_name = normalize_ident(name.name.as_str());
+            match CollationSeq::from_str(&object_name) {
+                Ok(collation) if name.db_name.is_none() => {
+                    (all_databases(), ReindexFilter::Collation(collation))
+                }
+                _ => {
+                    let database_id = connection.resolve_database_id(name)?;
+                    let schema = connection.database_schema(database_id);
+                    let filter = if schema.get_btree_table(&object_name).is_some() {
+                        ReindexFilter::Table(object_name)
+                    } else if schema.get_index_by_name(&object_name).is_some() {
+                        ReindexFilter::Index(object_name)
+                    } else {
+                        crate::bail_parse_error!("unable to identify the object to be reindexed");
+                    };
+                    (vec![database_id], filter)
+                }
+            }
+        }
+    };
+
+    let mut rebuilt_any = false;
+    for database_id in databases {
+        let schema = connection.database_schema(database_id);
+        let mut indexes = schema
+            .indexes
+            .values()
+            .flatten()
+            .filter(|index| filter.matches(index))
+            .cloned()
+            .collect::<Vec<_>>();
+        indexes.sort_by(|a, b| a.name.cmp(&b.name));
+        let resolver = Resolver::new(&schema, syms);
+        for index in indexes {
+            let table = schema.get_btree_table(&index.table_name).unwrap();
+            // The clustered index of a table without rowid is the table itself
+            if index.root_page == table.root_page {
+                continue;
+            }
+            emit_populate_index(
+                &mut program,
+                &resolver,
+                database_id,
+                &table,
+                &index,
+                RegisterOrLiteral::Literal(index.root_page),
+                true,
+            )?;
+            rebuilt_any = true;
+        }
+    }
+
+    program.epilogue(if rebuilt_any {
+        super::emitter::TransactionMode::Write
+    } else {
+        super::emitter::TransactionMode::None
+    });
+    Ok(program)
+}
+
+/// The indexes REINDEX rebuilds.
+enum ReindexFilter {
+    All,
+    Collation(CollationSeq),
+    Table(String),
+    Index(String),
+}
+
+impl ReindexFilter {
+    fn matches(&self, index: &Index) -> bool {
+        match self {
+            ReindexFilter::All => true,
+            ReindexFilter::Collation(collation) => index
+                .columns
+                .iter()
+                .any(|column| column.collation.unwrap_or_default() == *collation),
+            ReindexFilter::Table(table_name) => index.table_name == *table_name,
+            ReindexFilter::Index(index_name) => index.name == *index_name,
+        }
+    }
+}
@@ -2,19 +2,29 @@ use std::rc::Rc;
 use std::sync::Arc;
 
 use turso_sqlite3_parser::ast::{
-    DistinctNames, Expr, InsertBody, OneSelect, QualifiedName, ResolveType, ResultColumn, With,
+    DistinctNames, Expr, InsertBody, OneSelect, QualifiedName, ResolveType, ResultColumn, Select,
+    TriggerEvent, UpsertDo, With,
 };
 
-use crate::error::{SQLITE_CONSTRAINT_NOTNULL, SQLITE_CONSTRAINT_PRIMARYKEY};
-use crate::schema::{self, IndexColumn, Table};
-use crate::translate::emitter::{emit_cdc_insns, emit_cdc_patch_record, OperationMode};
+use crate::error::SQLITE_CONSTRAINT_PRIMARYKEY;
+use crate::schema::{self, Table};
+use crate::translate::check::CheckConstraints;
+use crate::translate::emitter::{
+    emit_cdc_insns, emit_cdc_patch_record, emit_notnull_check, emit_replace_delete,
+    emit_seek_row_of_index_entry, OperationMode,
+};
 use crate::translate::expr::{
     emit_returning_results, process_returning_clause, ReturningValueRegisters,
 };
+use crate::translate::generated::{emit_table_record, GeneratedColumns};
+use crate::translate::index::emit_index_entry_from_row;
 use crate::translate::plan::TableReferences;
 use crate::translate::planner::ROWID;
+use crate::translate::upsert::{
+    emit_upsert_update, resolve_upsert_targets, ConflictingInsert, UniqueConstraint,
+};
 use crate::util::normalize_ident;
-use crate::vdbe::builder::ProgramBuilderOpts;
+use crate::vdbe::builder::{CursorKey, ProgramBuilderOpts};
 use crate::vdbe::insn::{IdxInsertFlags, InsertFlags, RegisterOrLiteral};
 use crate::vdbe::BranchOffset;
 use crate::{
@@ -28,9 +38,19 @@ use crate::{Result, SymbolTable, VirtualTable};
 
 use super::emitter::Resolver;
 use super::expr::{translate_expr, translate_expr_no_constant_opt, NoConstantOptReason};
+use super::fkey::ForeignKeyChecks;
+use super::instead_of::translate_view_insert;
 use super::optimizer::rewrite_expr;
 use super::plan::QueryDestination;
 use super::select::translate_select;
+use super::trigger::TableTriggers;
+
+/// What an INSERT does with a row that violates a PRIMARY KEY or UNIQUE constraint.
+#[derive(Clone, Copy)]
+enum ConflictAction<'a> {
+    Resolve(ResolveType),
+    Upsert(&'a UpsertDo),
+}
 
 struct TempTableCtx {
     cursor_id: usize,
@@ -57,13 +77,11 @@ pub fn translate_insert(
         approx_num_labels: 5,
     };
     program.extend(&opts);
-    if with.is_some() {
-        crate::bail_parse_error!("WITH clause is not supported");
-    }
-    if on_conflict.is_some() {
-        crate::bail_parse_error!("ON CONFLICT clause is not supported");
-    }
 
+    // The SELECT of the body is planned against the main schema like any other statement.
+    let main_schema = schema;
+    let database_id = connection.resolve_database_id(&tbl_name)?;
+    let schema = &connection.database_schema(database_id);
     if schema.table_has_indexes(&tbl_name.name.to_string()) && !schema.indexes_enabled() {
         // Let's disable altering a table with indices altogether instead of checking column by
         // column to be extra safe.
@@ -72,6 +90,20 @@ pub fn translate_insert(
         );
     }
     let table_name = &tbl_name.name;
+    if let Some(view) = schema.get_view(table_name.as_str()) {
+        return translate_view_insert(
+            schema,
+            database_id,
+            &view,
+            with,
+            columns,
+            body,
+            returning,
+            syms,
+            program,
+            connection,
+        );
+    }
     let table = match schema.get_table(table_name.as_str()) {
         Some(table) => table,
         None => crate::bail_parse_error!("no such table: {}", table_name),
@@ -95,12 +127,40 @@ pub fn translate_insert(
     let Some(btree_table) = table.btree() else {
         crate::bail_parse_error!("no such table: {}", table_name);
     };
-    if !btree_table.has_rowid {
-        crate::bail_parse_error!("INSERT into WITHOUT ROWID table is not supported");
-    }
+    let triggers = TableTriggers::compile(
+        &program,
+        schema,
+        database_id,
+        syms,
+        connection,
+        &btree_table,
+        TriggerEvent::Insert,
+        &[],
+    )?;
+    let checks = CheckConstraints::compile(&mut program, connection, &btree_table, None)?;
+    let generated = GeneratedColumns::compile(&mut program, &btree_table)?;
+    // The rows REPLACE deletes may be the parent of rows of other tables
+    let replace_foreign_keys = ForeignKeyChecks::compile(
+        schema,
+        database_id,
+        connection,
+        &btree_table,
+        &TriggerEvent::Delete,
+        &[],
+    )?;
+    // A table without rowid is stored in its clustered index
+    let clustered_index = schema.get_clustered_index(&btree_table).cloned();
 
     let root_page = btree_table.root_page;
 
+    if let (Some(with), InsertBody::Select(select, _)) = (with, &mut body) {
+        add_ctes(select, with);
+    }
+    let upsert = match &mut body {
+        InsertBody::Select(_, upsert) => upsert.take(),
+        InsertBody::DefaultValues => None,
+    };
+
     let mut values: Option<Vec<Expr>> = None;
     let inserting_multiple_rows = match &mut body {
         InsertBody::Select(select, _) => match select.body.select.as_mut() {
@@ -124,8 +184,15 @@ pub fn translate_insert(
     let halt_label = program.allocate_label();
     let loop_start_label = program.allocate_label();
 
-    let cdc_table = program.capture_data_changes_mode().table();
+    // Only the changes of the main database are captured.
+    let cdc_table = program
+        .capture_data_changes_mode()
+        .table()
+        .filter(|_| database_id == 0);
     let cdc_table = if let Some(cdc_table) = cdc_table {
+        if clustered_index.is_some() {
+            crate::bail_parse_error!("capturing changes of WITHOUT ROWID tables is not supported");
+        }
         if table.get_name() != cdc_table {
             let Some(turso_cdc_table) = schema.get_table(cdc_table) else {
                 crate::bail_parse_error!("no such table: {}", cdc_table);
@@ -162,18 +229,23 @@ pub fn translate_insert(
         program.result_columns = result_columns.clone();
     }
 
+    // The table is referenced by the expressions of an upsert.
+    let table_internal_id = program.table_reference_counter.next();
+    let cursor_id = program.alloc_cursor_id_keyed(
+        CursorKey::table(table_internal_id),
+        match &clustered_index {
+            Some(index) => CursorType::BTreeIndex(index.clone()),
+            None => CursorType::BTreeTable(btree_table.clone()),
+        },
+    );
     let mut yield_reg_opt = None;
     let mut temp_table_ctx = None;
-    let (num_values, cursor_id) = match body {
-        // TODO: upsert
+    let num_values = match body {
         InsertBody::Select(select, _) => {
             // Simple Common case of INSERT INTO <table> VALUES (...)
             if matches!(select.body.select.as_ref(),  OneSelect::Values(values) if values.len() <= 1)
             {
-                (
-                    values.as_ref().unwrap().len(),
-                    program.alloc_cursor_id(CursorType::BTreeTable(btree_table.clone())),
-                )
+                values.as_ref().unwrap().len()
             } else {
                 // Multiple rows - use coroutine for value population
                 let yield_reg = program.alloc_register();
@@ -193,7 +265,7 @@ pub fn translate_insert(
                 };
                 program.incr_nesting();
                 let result = translate_select(
-                    schema,
+                    main_schema,
                     *select,
                     syms,
                     program,
@@ -206,9 +278,6 @@ pub fn translate_insert(
                 program.emit_insn(Insn::EndCoroutine { yield_reg });
                 program.preassign_label_to_next_insn(jump_on_definition_label);
 
-                let cursor_id =
-                    program.alloc_cursor_id(CursorType::BTreeTable(btree_table.clone()));
-
                 // From SQLite
                 /* Set useTempTable to TRUE if the result of the SELECT statement
                  ** should be written into a temporary table (template 4).  Set to
@@ -219,7 +288,7 @@ pub fn translate_insert(
                  ** of the tables being read by the SELECT statement.  Also use a
                  ** temp table in the case of row triggers.
                  */
-                if program.is_table_open(&table) {
+                if program.is_table_open(&table) || !triggers.is_empty() {
                     let temp_cursor_id =
                         program.alloc_cursor_id(CursorType::BTreeTable(btree_table.clone()));
                     temp_table_ctx = Some(TempTableCtx {
@@ -278,13 +347,13 @@ pub fn translate_insert(
                     program.emit_insn(Insn::OpenWrite {
                         cursor_id,
                         root_page: RegisterOrLiteral::Literal(root_page),
-                        db: 0,
+                        db: database_id,
                     });
                 } else {
                     program.emit_insn(Insn::OpenWrite {
                         cursor_id,
                         root_page: RegisterOrLiteral::Literal(root_page),
-                        db: 0,
+                        db: database_id,
                     });
 
                     // Main loop
@@ -298,28 +367,26 @@ pub fn translate_insert(
                 }
 
                 yield_reg_opt = Some(yield_reg);
-                (result.num_result_cols, cursor_id)
+                result.num_result_cols
             }
         }
-        InsertBody::DefaultValues => (
-            0,
-            program.alloc_cursor_id(CursorType::BTreeTable(btree_table.clone())),
-        ),
+        InsertBody::DefaultValues => 0,
     };
 
     // allocate cursor id's for each btree index cursor we'll need to populate the indexes
-    // (idx name, root_page, idx cursor id)
-    let idx_cursors = schema
-        .get_indices(table_name.as_str())
+    let indexes = schema.get_indices(table_name.as_str());
+    // The rows of a table without rowid are written through the cursor of the table
+    let index_cursors = indexes
         .iter()
-        .map(|idx| {
-            (
-                &idx.name,
-                idx.root_page,
-                program.alloc_cursor_id(CursorType::BTreeIndex(idx.clone())),
-            )
+        .map(|index| {
+            let idx_cursor_id = if index.root_page == root_page {
+                cursor_id
+            } else {
+                program.alloc_cursor_id(CursorType::BTreeIndex(index.clone()))
+            };
+            (index.clone(), idx_cursor_id)
         })
-        .collect::<Vec<(&String, usize, usize)>>();
+        .collect::<Vec<_>>();
 
     let column_mappings = resolve_columns_for_insert(&table, &columns, num_values)?;
     let has_user_provided_rowid = column_mappings
@@ -356,7 +423,7 @@ pub fn translate_insert(
         program.emit_insn(Insn::OpenWrite {
             cursor_id,
             root_page: RegisterOrLiteral::Literal(root_page),
-            db: 0,
+            db: database_id,
         });
 
         populate_column_registers(
@@ -367,6 +434,33 @@ pub fn translate_insert(
             &resolver,
         )?;
     }
+    // Skipping the row when a BEFORE trigger calls RAISE(IGNORE)
+    let row_done_label = program.allocate_label();
+    let trigger_params = if triggers.is_empty() {
+        None
+    } else {
+        let params = triggers.alloc_params(&mut program);
+        params.emit_null_old(&mut program);
+        if !triggers.before.is_empty() {
+            // The generated columns of the NEW row of BEFORE triggers are computed before the
+            // rowid is known.
+            generated.emit(
+                &mut program,
+                &resolver,
+                rowid_and_columns_start_register,
+                columns_start_register,
+            )?;
+        }
+        params.emit_copy_row(
+            &mut program,
+            &btree_table,
+            rowid_and_columns_start_register,
+            columns_start_register,
+            false,
+        );
+        triggers.emit_before(&mut program, &params, row_done_label);
+        Some(params)
+    };
     // Open turso_cdc table btree for writing if necessary
     if let Some((cdc_cursor_id, cdc_btree)) = &cdc_table {
         program.emit_insn(Insn::OpenWrite {
@@ -377,11 +471,11 @@ pub fn translate_insert(
     }
 
     // Open all the index btrees for writing
-    for idx_cursor in idx_cursors.iter() {
+    for (index, idx_cursor_id) in index_cursors.iter().filter(|(_, id)| *id != cursor_id) {
         program.emit_insn(Insn::OpenWrite {
-            cursor_id: idx_cursor.2,
-            root_page: idx_cursor.1.into(),
-            db: 0,
+            cursor_id: *idx_cursor_id,
+            root_page: index.root_page.into(),
+            db: database_id,
         });
     }
     // Common record insertion logic for both single and multiple rows
@@ -398,11 +492,13 @@ pub fn translate_insert(
     }
 
     // Create new rowid if a) not provided by user or b) provided by user but is NULL
-    program.emit_insn(Insn::NewRowid {
-        cursor: cursor_id,
-        rowid_reg: rowid_and_columns_start_register,
-        prev_largest_reg: 0,
-    });
+    if btree_table.has_rowid {
+        program.emit_insn(Insn::NewRowid {
+            cursor: cursor_id,
+            rowid_reg: rowid_and_columns_start_register,
+            prev_largest_reg: 0,
+        });
+    }
 
     if let Some(must_be_int_label) = check_rowid_is_integer_label {
         program.resolve_label(must_be_int_label, program.offset());
@@ -412,168 +508,296 @@ pub fn translate_insert(
         });
     }
 
-    // Check uniqueness constraint for rowid if it was provided by user.
-    // When the DB allocates it there are no need for separate uniqueness checks.
-    if has_user_provided_rowid {
-        let make_record_label = program.allocate_label();
-        program.emit_insn(Insn::NotExists {
-            cursor: cursor_id,
-            rowid_reg: rowid_and_columns_start_register,
-            target_pc: make_record_label,
-        });
-        let rowid_column = column_mappings
-            .iter()
-            .find(|x| x.value_index.is_some() && x.column.is_rowid_alias)
-            .expect("rowid alias column must be provided");
-        let rowid_column_name = rowid_column.column.name.as_deref().unwrap_or(ROWID);
-        program.emit_insn(Insn::Halt {
-            err_code: SQLITE_CONSTRAINT_PRIMARYKEY,
-            description: format!("{}.{}", table_name.as_str(), rowid_column_name),
-        });
-        program.preassign_label_to_next_insn(make_record_label);
-    }
+    generated.emit(
+        &mut program,
+        &resolver,
+        rowid_and_columns_start_register,
+        columns_start_register,
+    )?;
 
-    match table.btree() {
-        Some(t) if t.is_strict => {
-            program.emit_insn(Insn::TypeCheck {
-                start_reg: columns_start_register,
-                count: num_cols,
-                check_generated: true,
-                table_reference: Rc::clone(&t),
-            });
+    for (i, col) in column_mappings
+        .iter()
+        .enumerate()
+        .filter(|(_, col)| col.column.notnull)
+    {
+        // if this is rowid alias - turso-db will emit NULL as a column value and always use rowid for the row as a column value
+        if col.column.is_rowid_alias {
+            continue;
         }
-        _ => (),
+        emit_notnull_check(
+            &mut program,
+            &resolver,
+            table_name.as_str(),
+            col.column,
+            i + rowid_and_columns_start_register,
+            on_conflict
+                .or_else(|| btree_table.notnull_conflict_clause(col.column))
+                .unwrap_or(ResolveType::Abort),
+            row_done_label,
+        )?;
     }
+    checks.emit(
+        &mut program,
+        &resolver,
+        rowid_and_columns_start_register,
+        columns_start_register,
+        on_conflict.unwrap_or(ResolveType::Abort),
+        row_done_label,
+    )?;
 
-    let index_col_mappings = resolve_indicies_for_insert(schema, table.as_ref(), &column_mappings)?;
-    for index_col_mapping in index_col_mappings {
-        // find which cursor we opened earlier for this index
-        let idx_cursor_id = idx_cursors
-            .iter()
-            .find(|(name, _, _)| *name == &index_col_mapping.idx_name)
-            .map(|(_, _, c_id)| *c_id)
-            .expect("no cursor found for index");
-
-        let num_cols = index_col_mapping.columns.len();
-        // allocate scratch registers for the index columns plus rowid
-        let idx_start_reg = program.alloc_registers(num_cols + 1);
-
-        // copy each index column from the table's column registers into these scratch regs
-        for (i, col) in index_col_mapping.columns.iter().enumerate() {
-            // copy from the table's column register over to the index's scratch register
-
-            program.emit_insn(Insn::Copy {
-                src_reg: rowid_and_columns_start_register + col.0,
-                dst_reg: idx_start_reg + i,
-                extra_amount: 0,
-            });
-        }
-        // last register is the rowid
-        program.emit_insn(Insn::Copy {
-            src_reg: rowid_and_columns_start_register,
-            dst_reg: idx_start_reg + num_cols,
-            extra_amount: 0,
+    if btree_table.is_strict {
+        program.emit_insn(Insn::TypeCheck {
+            start_reg: columns_start_register,
+            count: num_cols,
+            check_generated: true,
+            table_reference: Rc::clone(&btree_table),
         });
+    }
 
-        let index = schema
-            .get_index(table_name.as_str(), &index_col_mapping.idx_name)
-            .expect("index should be present");
-
+    // The keys of the row in the indexes: the indexed columns followed by the rowid, if the
+    // table has one. The record of the key stays NULL if the index is partial and the row has
+    // no entry in it.
+    let mut index_keys = Vec::with_capacity(index_cursors.len());
+    for (index, _) in index_cursors.iter() {
+        let num_regs = index.columns.len() + usize::from(index.has_rowid);
+        let idx_start_reg = program.alloc_registers(num_regs);
         let record_reg = program.alloc_register();
+        let not_indexed_label = program.allocate_label();
+        if index.where_clause.is_some() {
+            program.emit_insn(Insn::Null {
+                dest: record_reg,
+                dest_end: None,
+            });
+        }
+        emit_index_entry_from_row(
+            &mut program,
+            &resolver,
+            &btree_table,
+            index,
+            rowid_and_columns_start_register,
+            columns_start_register,
+            idx_start_reg,
+            not_indexed_label,
+        )?;
         program.emit_insn(Insn::MakeRecord {
             start_reg: idx_start_reg,
-            count: num_cols + 1,
+            count: num_regs,
             dest_reg: record_reg,
-            index_name: Some(index_col_mapping.idx_name),
+            index_name: Some(index.name.clone()),
         });
+        program.preassign_label_to_next_insn(not_indexed_label);
+        index_keys.push((idx_start_reg, record_reg));
+    }
 
-        if index.unique {
-            let label_idx_insert = program.allocate_label();
-            program.emit_insn(Insn::NoConflict {
-                cursor_id: idx_cursor_id,
-                target_pc: label_idx_insert,
-                record_reg: idx_start_reg,
-                num_regs: num_cols,
-            });
-            let column_names = index_col_mapping.columns.iter().enumerate().fold(
-                String::with_capacity(50),
-                |mut accum, (idx, (index, _))| {
-                    if idx > 0 {
-                        accum.push_str(", ");
-                    }
-
-                    accum.push_str(&btree_table.name);
-                    accum.push('.');
-
-                    let name = column_mappings
-                        .get(*index)
-                        .unwrap()
-                        .column
-                        .name
-                        .as_ref()
-                        .expect("column name is None");
-                    accum.push_str(name);
-
-                    accum
-                },
-            );
+    // The rowid needs a uniqueness check only if it was provided by user.
+    // When the DB allocates it there are no need for separate uniqueness checks.
+    let mut constraints = Vec::new();
+    if has_user_provided_rowid {
+        constraints.push(UniqueConstraint::Rowid);
+    }
+    constraints.extend(
+        indexes
+            .iter()
+            .enumerate()
+            .filter(|(_, index)| index.unique)
+            .map(|(i, _)| UniqueConstraint::Index(i)),
+    );
+    let upsert_clauses = match &upsert {
+        Some(upsert) => resolve_upsert_targets(upsert, &btree_table, indexes)?,
+        None => vec![],
+    };
+    let rowid_alias_name = btree_table
+        .get_rowid_alias_column()
+        .and_then(|(_, column)| column.name.as_deref());
+    let mut constraints = constraints
+        .into_iter()
+        .map(|constraint| {
+            let clause = upsert_clauses
+                .iter()
+                .position(|(_, targets)| targets.contains(&constraint));
+            if let Some(clause) = clause {
+                return (
+                    clause,
+                    constraint,
+                    ConflictAction::Upsert(upsert_clauses[clause].0),
+                );
+            }
+            let on_conflict = on_conflict
+                .or_else(|| match constraint {
+                    UniqueConstraint::Rowid => btree_table.conflict_clause(rowid_alias_name),
+                    UniqueConstraint::Index(i) => btree_table.index_conflict_clause(&indexes[i]),
+                })
+                .unwrap_or(ResolveType::Abort);
+            let order = upsert_clauses.len() + usize::from(on_conflict == ResolveType::Replace);
+            (order, constraint, ConflictAction::Resolve(on_conflict))
+        })
+        .collect::<Vec<_>>();
+    // Like SQLite, check the targets of the ON CONFLICT clauses first, and the constraints whose
+    // conflicts are resolved by REPLACE last, so that no row is deleted if another constraint
+    // fails.
+    constraints.sort_by_key(|(order, _, _)| *order);
+
+    let mut replaces_rows = false;
+    for (_, constraint, action) in constraints {
+        let no_conflict_label = program.allocate_label();
+        let description = match constraint {
+            UniqueConstraint::Rowid => {
+                program.emit_insn(Insn::NotExists {
+                    cursor: cursor_id,
+                    rowid_reg: rowid_and_columns_start_register,
+                    target_pc: no_conflict_label,
+                });
+                let rowid_column = column_mappings
+                    .iter()
+                    .find(|x| x.value_index.is_some() && x.column.is_rowid_alias)
+                    .expect("rowid alias column must be provided");
+                let rowid_column_name = rowid_column.column.name.as_deref().unwrap_or(ROWID);
+                format!("{}.{}", table_name.as_str(), rowid_column_name)
+            }
+            UniqueConstraint::Index(i) => {
+                let (index, idx_cursor_id) = &index_cursors[i];
+                let (idx_start_reg, record_reg) = index_keys[i];
+                if index.where_clause.is_some() {
+                    program.emit_insn(Insn::IsNull {
+                        reg: record_reg,
+                        target_pc: no_conflict_label,
+                    });
+                }
+                program.emit_insn(Insn::NoConflict {
+                    cursor_id: *idx_cursor_id,
+                    target_pc: no_conflict_label,
+                    record_reg: idx_start_reg,
+                    num_regs: index.key_columns().len(),
+                });
+                if matches!(
+                    action,
+                    ConflictAction::Resolve(ResolveType::Replace)
+                        | ConflictAction::Upsert(UpsertDo::Set { .. })
+                ) && *idx_cursor_id != cursor_id
+                {
+                    // Position the table cursor on the conflicting row
+                    emit_seek_row_of_index_entry(
+                        &mut program,
+                        &btree_table,
+                        cursor_id,
+                        index,
+                        *idx_cursor_id,
+                        no_conflict_label,
+                    );
+                }
+                index.unique_constraint_description(&btree_table.name)
+            }
+        };
+        match action {
+            ConflictAction::Resolve(ResolveType::Ignore)
+            | ConflictAction::Upsert(UpsertDo::Nothing) => {
+                program.emit_insn(Insn::Goto {
+                    target_pc: row_done_label,
+                });
+            }
+            ConflictAction::Resolve(ResolveType::Replace) => {
+                emit_replace_delete(
+                    &mut program,
+                    &resolver,
+                    &btree_table,
+                    cursor_id,
+                    &index_cursors,
+                    cdc_table.as_ref().map(|(cdc_cursor_id, _)| *cdc_cursor_id),
+                    &replace_foreign_keys,
+                )?;
+                replaces_rows = true;
+            }
+            ConflictAction::Resolve(on_error) => {
+                program.emit_insn(Insn::Halt {
+                    err_code: SQLITE_CONSTRAINT_PRIMARYKEY,
+                    on_error,
+                    description,
+                });
+            }
+            ConflictAction::Upsert(UpsertDo::Set { sets, where_clause }) => {
+                let insert = ConflictingInsert {
+                    table: &btree_table,
+                    database_id,
+                    table_internal_id,
+                    cursor_id,
+                    index_cursors: &index_cursors,
+                    rowid_reg: rowid_and_columns_start_register,
+                    columns_start_reg: columns_start_register,
+                    returning: &result_columns,
+                    cdc_cursor_id: cdc_table.as_ref().map(|(cdc_cursor_id, _)| *cdc_cursor_id),
+                    row_done_label,
+                };
+                emit_upsert_update(
+                    &mut program,
+                    &resolver,
+                    connection,
+                    &insert,
+                    sets,
+                    where_clause.as_ref(),
+                )?;
+            }
+        }
+        program.preassign_label_to_next_insn(no_conflict_label);
+    }
 
-            program.emit_insn(Insn::Halt {
-                err_code: SQLITE_CONSTRAINT_PRIMARYKEY,
-                description: column_names,
+    for ((index, idx_cursor_id), (idx_start_reg, record_reg)) in
+        index_cursors.iter().zip(index_keys)
+    {
+        let num_regs = index.columns.len() + usize::from(index.has_rowid);
+        let not_indexed_label = program.allocate_label();
+        if index.where_clause.is_some() {
+            program.emit_insn(Insn::IsNull {
+                reg: record_reg,
+                target_pc: not_indexed_label,
             });
-
-            program.resolve_label(label_idx_insert, program.offset());
         }
-
         // now do the actual index insertion using the unpacked registers
         program.emit_insn(Insn::IdxInsert {
-            cursor_id: idx_cursor_id,
+            cursor_id: *idx_cursor_id,
             record_reg,
             unpacked_start: Some(idx_start_reg), // TODO: enable optimization
-            unpacked_count: Some((num_cols + 1) as u16),
+            unpacked_count: Some(num_regs as u16),
             // TODO: figure out how to determine whether or not we need to seek prior to insert.
             flags: IdxInsertFlags::new(),
         });
+        program.preassign_label_to_next_insn(not_indexed_label);
     }
 
-    for (i, col) in column_mappings
-        .iter()
-        .enumerate()
-        .filter(|(_, col)| col.column.notnull)
-    {
-        // if this is rowid alias - turso-db will emit NULL as a column value and always use rowid for the row as a column value
-        if col.column.is_rowid_alias {
-            continue;
-        }
-        let target_reg = i + rowid_and_columns_start_register;
-        program.emit_insn(Insn::HaltIfNull {
-            target_reg,
-            err_code: SQLITE_CONSTRAINT_NOTNULL,
-            description: format!(
-                "{}.{}",
-                table_name,
-                col.column
-                    .name
-                    .as_ref()
-                    .expect("Column name must be present")
-            ),
+    // Create and insert the record. The row of a table without rowid is the entry of its
+    // clustered index inserted above.
+    if btree_table.has_rowid {
+        emit_table_record(
+            &mut program,
+            &btree_table,
+            columns_start_register,
+            record_register,
+        );
+        program.emit_insn(Insn::Insert {
+            cursor: cursor_id,
+            key_reg: rowid_and_columns_start_register,
+            record_reg: record_register,
+            // Deleting the replaced rows moved the cursor away from where the row goes.
+            flag: if replaces_rows {
+                InsertFlags::new().require_seek()
+            } else {
+                InsertFlags::new()
+            },
+            table_name: table_name.to_string(),
         });
     }
-    // Create and insert the record
-    program.emit_insn(Insn::MakeRecord {
-        start_reg: columns_start_register,
-        count: num_cols,
-        dest_reg: record_register,
-        index_name: None,
-    });
-    program.emit_insn(Insn::Insert {
-        cursor: cursor_id,
-        key_reg: rowid_and_columns_start_register,
-        record_reg: record_register,
-        flag: InsertFlags::new(),
-        table_name: table_name.to_string(),
-    });
+
+    if let Some(params) = &trigger_params {
+        // NEW.rowid is only known once the row is inserted
+        params.emit_copy_row(
+            &mut program,
+            &btree_table,
+            rowid_and_columns_start_register,
+            columns_start_register,
+            false,
+        );
+        triggers.emit_new_foreign_key_checks(&mut program, params);
+        triggers.emit_after(&mut program, params, row_done_label);
+    }
 
     // Emit update in the CDC table if necessary (after the INSERT updated the table)
     if let Some((cdc_cursor_id, _)) = &cdc_table {
@@ -612,6 +836,7 @@ pub fn translate_insert(
         emit_returning_results(&mut program, &result_columns, &value_registers)?;
     }
 
+    program.preassign_label_to_next_insn(row_done_label);
     if inserting_multiple_rows {
         if let Some(temp_table_ctx) = temp_table_ctx {
             program.emit_insn(Insn::Next {
@@ -661,6 +886,7 @@ pub const ROWID_COLUMN: &Column = &Column {
     unique: false,
     collation: None,
     hidden: false,
+    generated: None,
 };
 
 /// Resolves how each column in a table should be populated during an INSERT.
@@ -703,12 +929,14 @@ fn resolve_columns_for_insert<'a>(
 
     if columns.is_none() {
         // Case 1: No columns specified - map values to columns in order
+        // Generated columns are computed rather than inserted
         let mut value_idx = 0;
         for (i, col) in table_columns.iter().enumerate() {
-            column_mappings[i + 1].value_index = if col.hidden { None } else { Some(value_idx) };
-            if !col.hidden {
-                value_idx += 1;
+            if col.hidden || col.is_generated() {
+                continue;
             }
+            column_mappings[i + 1].value_index = Some(value_idx);
+            value_idx += 1;
         }
 
         if num_values != value_idx {
@@ -742,6 +970,9 @@ fn resolve_columns_for_insert<'a>(
                     column_name
                 );
             };
+            if column_mappings[table_index].column.is_generated() {
+                crate::bail_parse_error!("cannot INSERT into generated column \"{}\"", column_name);
+            }
 
             column_mappings[table_index].value_index = Some(value_index);
         }
@@ -750,69 +981,6 @@ fn resolve_columns_for_insert<'a>(
     Ok(column_mappings)
 }
 
-/// Represents how a column in an index should be populated during an INSERT.
-/// Similar to ColumnMapping above but includes the index name, as well as multiple
-/// possible value indices for each.
-#[derive(Debug, Default)]
-struct IndexColMapping {
-    idx_name: String,
-    columns: Vec<(usize, IndexColumn)>,
-    value_indicies: Vec<Option<usize>>,
-}
-
-impl IndexColMapping {
-    fn new(name: String) -> Self {
-        IndexColMapping {
-            idx_name: name,
-            ..Default::default()
-        }
-    }
-}
-
-/// Example:
-/// Table 'test': (a, b, c);
-/// Index 'idx': test(a, b);
-///________________________________
-/// Insert (a, c): (2, 3)
-/// Record: (2, NULL, 3)
-/// IndexColMapping: (a, b) = (2, NULL)
-fn resolve_indicies_for_insert(
-    schema: &Schema,
-    table: &Table,
-    columns: &[ColumnMapping<'_>],
-) -> Result<Vec<IndexColMapping>> {
-    let mut index_col_mappings = Vec::new();
-    // Iterate over all indices for this table
-    for index in schema.get_indices(table.get_name()) {
-        let mut idx_map = IndexColMapping::new(index.name.clone());
-        // For each column in the index (in the order defined by the index),
-        // try to find the corresponding column in the insert’s column mapping.
-        for idx_col in &index.columns {
-            let target_name = normalize_ident(idx_col.name.as_str());
-            if let Some((i, col_mapping)) = columns.iter().enumerate().find(|(_, mapping)| {
-                mapping
-                    .column
-                    .name
-                    .as_ref()
-                    .is_some_and(|name| name.eq_ignore_ascii_case(&target_name))
-            }) {
-                idx_map.columns.push((i, idx_col.clone()));
-                idx_map.value_indicies.push(col_mapping.value_index);
-            } else {
-                return Err(crate::LimboError::ParseError(format!(
-                    "Column {} not found in index {}",
-                    target_name, index.name
-                )));
-            }
-        }
-        // Add the mapping if at least one column was found.
-        if !idx_map.columns.is_empty() {
-            index_col_mappings.push(idx_map);
-        }
-    }
-    Ok(index_col_mappings)
-}
-
 fn populate_columns_multiple_rows(
     program: &mut ProgramBuilder,
     column_mappings: &[ColumnMapping], // columns in order of table definition
@@ -1018,3 +1186,25 @@ fn translate_virtual_table_insert(
 
     Ok(program)
 }
+
+/// Adds the CTEs of the WITH clause of an INSERT to the SELECT that produces the inserted rows.
+/// The CTEs of the SELECT come after those of the INSERT, so they can reference them, and hide
+/// those with the same name.
+pub(crate) fn add_ctes(select: &mut Select, with: With) {
+    let Some(select_with) = select.with.as_mut() else {
+        select.with = Some(with);
+        return;
+    };
+    let mut ctes: Vec<_> = with
+        .ctes
+        .into_iter()
+        .filter(|cte| {
+            !select_with.ctes.iter().any(|c| {
+                normalize_ident(c.tbl_name.as_str()) == normalize_ident(cte.tbl_name.as_str())
+            })
+        })
+        .collect();
+    ctes.append(&mut select_with.ctes);
+    select_with.ctes = ctes;
+    select_with.recursive |= with.recursive;
+}
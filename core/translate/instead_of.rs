@@ -0,0 +1,490 @@
+//! INSERT, UPDATE and DELETE on views.
+//!
+//! A view has no storage, so a statement that modifies it only fires the INSTEAD OF
+//! triggers of the view. A SELECT computes the OLD and NEW row of each row the statement
+//! changes, and all of its rows are stored in an ephemeral table before the first trigger
+//! fires, so that the triggers cannot change what the SELECT reads. The triggers then fire
+//! once for each stored row, with NULL as OLD.rowid and NEW.rowid.
+
+use std::rc::Rc;
+use std::sync::Arc;
+
+use turso_sqlite3_parser::ast::{
+    DistinctNames, Expr, FromClause, Indexed, InsertBody, JoinOperator, JoinedSelectTable, Limit,
+    Literal, Name, OneSelect, QualifiedName, ResultColumn, Select, SelectBody, SelectInner,
+    SelectTable, SortedColumn, TriggerEvent, Update, With,
+};
+
+use crate::schema::{BTreeTable, Schema, View};
+use crate::translate::emitter::TransactionMode;
+use crate::translate::insert::add_ctes;
+use crate::translate::plan::QueryDestination;
+use crate::translate::planner::view_table;
+use crate::translate::select::translate_select;
+use crate::translate::trigger::TableTriggers;
+use crate::util::normalize_ident;
+use crate::vdbe::builder::{CursorType, ProgramBuilder, ProgramBuilderOpts};
+use crate::vdbe::insn::{InsertFlags, Insn};
+use crate::{bail_parse_error, Connection, Result, SymbolTable};
+
+/// The rows a statement changes in a view.
+struct ViewRows {
+    /// Produces one row for each row the statement changes.
+    select: Select,
+    /// For each column of the view, the column of `select` holding its OLD value, or `None`
+    /// if it is NULL.
+    old: Vec<Option<usize>>,
+    /// For each column of the view, the column of `select` holding its NEW value, or `None`
+    /// if it is NULL.
+    new: Vec<Option<usize>>,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn translate_view_insert(
+    schema: &Schema,
+    database_id: usize,
+    view: &View,
+    with: Option<With>,
+    columns: Option<DistinctNames>,
+    body: InsertBody,
+    returning: Option<Vec<ResultColumn>>,
+    syms: &SymbolTable,
+    mut program: ProgramBuilder,
+    connection: &Arc<Connection>,
+) -> Result<ProgramBuilder> {
+    if returning.is_some() {
+        bail_parse_error!("RETURNING is not supported on views");
+    }
+    let table = view_table(
+        schema,
+        view,
+        database_id,
+        syms,
+        &mut program.table_reference_counter,
+        connection,
+    )?;
+    let triggers = TableTriggers::compile_instead_of(
+        &program,
+        schema,
+        database_id,
+        syms,
+        connection,
+        &table,
+        TriggerEvent::Insert,
+        &[],
+    )?;
+    if triggers.is_empty() {
+        bail_parse_error!("cannot modify {} because it is a view", view.name);
+    }
+
+    let num_columns = table.columns.len();
+    let (select, new, num_values) = match body {
+        InsertBody::Select(_, Some(_)) => bail_parse_error!("cannot UPSERT a view"),
+        InsertBody::Select(mut select, None) => {
+            if let Some(with) = with {
+                add_ctes(&mut select, with);
+            }
+            match &columns {
+                Some(columns) => {
+                    let mut new = vec![None; num_columns];
+                    for (value_index, name) in columns.iter().enumerate() {
+                        let Some((idx, _)) = table.get_column(name.as_str()) else {
+                            bail_parse_error!(
+                                "table {} has no column named {}",
+                                view.name,
+                                normalize_ident(name.as_str())
+                            );
+                        };
+                        new[idx] = Some(value_index);
+                    }
+                    (*select, new, Some(columns.len()))
+                }
+                None => (
+                    *select,
+                    (0..num_columns).map(Some).collect(),
+                    Some(num_columns),
+                ),
+            }
+        }
+        InsertBody::DefaultValues => (
+            values_select(vec![Expr::Literal(Literal::Null)]),
+            vec![None; num_columns],
+            None,
+        ),
+    };
+    let rows = ViewRows {
+        select,
+        old: vec![None; num_columns],
+        new,
+    };
+    emit_view_rows(
+        program,
+        schema,
+        syms,
+        connection,
+        &table,
+        &triggers,
+        rows,
+        |num_result_cols| match (num_values, &columns) {
+            (Some(num_values), _) if num_values == num_result_cols => Ok(()),
+            (Some(num_values), Some(_)) => {
+                bail_parse_error!("{} values for {} columns", num_result_cols, num_values)
+            }
+            (Some(num_values), None) => bail_parse_error!(
+                "table {} has {} columns but {} values were supplied",
+                view.name,
+                num_values,
+                num_result_cols
+            ),
+            (None, _) => Ok(()),
+        },
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn translate_view_delete(
+    schema: &Schema,
+    database_id: usize,
+    view: &View,
+    with: Option<With>,
+    tbl_name: &QualifiedName,
+    indexed: Option<Indexed>,
+    where_clause: Option<Box<Expr>>,
+    limit: Option<Box<Limit>>,
+    returning: Option<Vec<ResultColumn>>,
+    syms: &SymbolTable,
+    mut program: ProgramBuilder,
+    connection: &Arc<Connection>,
+) -> Result<ProgramBuilder> {
+    if returning.is_some() {
+        bail_parse_error!("RETURNING is not supported on views");
+    }
+    let table = view_table(
+        schema,
+        view,
+        database_id,
+        syms,
+        &mut program.table_reference_counter,
+        connection,
+    )?;
+    let triggers = TableTriggers::compile_instead_of(
+        &program,
+        schema,
+        database_id,
+        syms,
+        connection,
+        &table,
+        TriggerEvent::Delete,
+        &[],
+    )?;
+    if triggers.is_empty() {
+        bail_parse_error!("cannot modify {} because it is a view", view.name);
+    }
+
+    let num_columns = table.columns.len();
+    let rows = ViewRows {
+        select: view_select(
+            view,
+            with,
+            tbl_name,
+            indexed,
+            vec![],
+            None,
+            where_clause.map(|expr| *expr),
+            None,
+            limit,
+        ),
+        old: (0..num_columns).map(Some).collect(),
+        new: vec![None; num_columns],
+    };
+    emit_view_rows(
+        program,
+        schema,
+        syms,
+        connection,
+        &table,
+        &triggers,
+        rows,
+        |_| Ok(()),
+    )
+}
+
+pub fn translate_view_update(
+    schema: &Schema,
+    database_id: usize,
+    view: &View,
+    body: Update,
+    syms: &SymbolTable,
+    mut program: ProgramBuilder,
+    connection: &Arc<Connection>,
+) -> Result<ProgramBuilder> {
+    if body.returning.is_some() {
+        bail_parse_error!("RETURNING is not supported on views");
+    }
+    let table = view_table(
+        schema,
+        view,
+        database_id,
+        syms,
+        &mut program.table_reference_counter,
+        connection,
+    )?;
+    let num_columns = table.columns.len();
+
+    // The SELECT returns the OLD row, followed by the value of each assignment.
+    let mut new = (0..num_columns).map(Some).collect::<Vec<_>>();
+    let mut assignments = vec![];
+    for set in body.sets {
+        let exprs = match set.expr {
+            Expr::Parenthesized(exprs) if set.col_names.len() > 1 => exprs,
+            expr => vec![expr],
+        };
+        if exprs.len() != set.col_names.len() {
+            bail_parse_error!(
+                "{} columns assigned {} values",
+                set.col_names.len(),
+                exprs.len()
+            );
+        }
+        for (name, expr) in set.col_names.iter().zip(exprs) {
+            let Some((idx, _)) = table.get_column(name.as_str()) else {
+                bail_parse_error!("no such column: {}", normalize_ident(name.as_str()));
+            };
+            new[idx] = Some(num_columns + assignments.len());
+            assignments.push(ResultColumn::Expr(expr, None));
+        }
+    }
+    let updated_columns = new
+        .iter()
+        .enumerate()
+        .filter(|(idx, new)| **new != Some(*idx))
+        .filter_map(|(idx, _)| table.columns[idx].name.as_deref())
+        .collect::<Vec<_>>();
+    let triggers = TableTriggers::compile_instead_of(
+        &program,
+        schema,
+        database_id,
+        syms,
+        connection,
+        &table,
+        TriggerEvent::Update,
+        &updated_columns,
+    )?;
+    if triggers.is_empty() {
+        bail_parse_error!("cannot modify {} because it is a view", view.name);
+    }
+
+    let rows = ViewRows {
+        select: view_select(
+            view,
+            body.with,
+            &body.tbl_name,
+            body.indexed,
+            assignments,
+            body.from,
+            body.where_clause.map(|expr| *expr),
+            body.order_by,
+            body.limit,
+        ),
+        old: (0..num_columns).map(Some).collect(),
+        new,
+    };
+    emit_view_rows(
+        program,
+        schema,
+        syms,
+        connection,
+        &table,
+        &triggers,
+        rows,
+        |_| Ok(()),
+    )
+}
+
+/// `with` SELECT view.*, `extra_columns` FROM view, `from` WHERE `where_clause`
+/// ORDER BY `order_by` LIMIT `limit`
+#[allow(clippy::too_many_arguments)]
+fn view_select(
+    view: &View,
+    with: Option<With>,
+    tbl_name: &QualifiedName,
+    indexed: Option<Indexed>,
+    extra_columns: Vec<ResultColumn>,
+    from: Option<FromClause>,
+    where_clause: Option<Expr>,
+    order_by: Option<Vec<SortedColumn>>,
+    limit: Option<Box<Limit>>,
+) -> Select {
+    let mut columns = vec![ResultColumn::TableStar(Name::from_str(&view.name))];
+    columns.extend(extra_columns);
+    let joins = from.map(|from| {
+        let mut joins = vec![];
+        if let Some(table) = from.select {
+            joins.push(JoinedSelectTable {
+                operator: JoinOperator::Comma,
+                table: *table,
+                constraint: None,
+            });
+        }
+        joins.extend(from.joins.into_iter().flatten());
+        joins
+    });
+    Select {
+        with,
+        body: SelectBody {
+            select: Box::new(OneSelect::Select(Box::new(SelectInner {
+                distinctness: None,
+                columns,
+                from: Some(FromClause {
+                    select: Some(Box::new(SelectTable::Table(
+                        tbl_name.clone(),
+                        None,
+                        indexed,
+                    ))),
+                    joins,
+                    op: None,
+                }),
+                where_clause,
+                group_by: None,
+                window_clause: None,
+            }))),
+            compounds: None,
+        },
+        order_by,
+        limit,
+    }
+}
+
+/// VALUES (`row`)
+fn values_select(row: Vec<Expr>) -> Select {
+    Select {
+        with: None,
+        body: SelectBody {
+            select: Box::new(OneSelect::Values(vec![row])),
+            compounds: None,
+        },
+        order_by: None,
+        limit: None,
+    }
+}
+
+/// Store the rows of `rows.select` in an ephemeral table, then fire `triggers` for each of
+/// them. `check_num_result_cols` validates the number of columns of the SELECT.
+#[allow(clippy::too_many_arguments)]
+fn emit_view_rows(
+    mut program: ProgramBuilder,
+    schema: &Schema,
+    syms: &SymbolTable,
+    connection: &Arc<Connection>,
+    table: &Rc<BTreeTable>,
+    triggers: &TableTriggers,
+    rows: ViewRows,
+    check_num_result_cols: impl FnOnce(usize) -> Result<()>,
+) -> Result<ProgramBuilder> {
+    let opts = ProgramBuilderOpts {
+        num_cursors: 1,
+        approx_num_insns: 30,
+        approx_num_labels: 5,
+    };
+    program.extend(&opts);
+
+    let ViewRows { select, old, new } = rows;
+    let end_label = program.allocate_label();
+    let yield_reg = program.alloc_register();
+    let jump_on_definition_label = program.allocate_label();
+    let start_offset_label = program.allocate_label();
+    program.emit_insn(Insn::InitCoroutine {
+        yield_reg,
+        jump_on_definition: jump_on_definition_label,
+        start_offset: start_offset_label,
+    });
+    program.preassign_label_to_next_insn(start_offset_label);
+    program.incr_nesting();
+    let result = translate_select(
+        schema,
+        select,
+        syms,
+        program,
+        QueryDestination::CoroutineYield {
+            yield_reg,
+            coroutine_implementation_start: end_label,
+        },
+        connection,
+    )?;
+    program = result.program;
+    program.decr_nesting();
+    check_num_result_cols(result.num_result_cols)?;
+    program.emit_insn(Insn::EndCoroutine { yield_reg });
+    program.preassign_label_to_next_insn(jump_on_definition_label);
+
+    // Store the rows of the SELECT.
+    let temp_cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(table.clone()));
+    program.emit_insn(Insn::OpenEphemeral {
+        cursor_id: temp_cursor_id,
+        is_table: true,
+    });
+    let store_loop_label = program.allocate_label();
+    let store_end_label = program.allocate_label();
+    program.preassign_label_to_next_insn(store_loop_label);
+    program.emit_insn(Insn::Yield {
+        yield_reg,
+        end_offset: store_end_label,
+    });
+    let record_reg = program.alloc_register();
+    program.emit_insn(Insn::MakeRecord {
+        start_reg: yield_reg + 1,
+        count: result.num_result_cols,
+        dest_reg: record_reg,
+        index_name: None,
+    });
+    let rowid_reg = program.alloc_register();
+    program.emit_insn(Insn::NewRowid {
+        cursor: temp_cursor_id,
+        rowid_reg,
+        prev_largest_reg: 0,
+    });
+    program.emit_insn(Insn::Insert {
+        cursor: temp_cursor_id,
+        key_reg: rowid_reg,
+        record_reg,
+        flag: InsertFlags::new().require_seek(),
+        table_name: "".to_string(),
+    });
+    program.emit_insn(Insn::Goto {
+        target_pc: store_loop_label,
+    });
+    program.preassign_label_to_next_insn(store_end_label);
+
+    // Fire the triggers for each stored row.
+    let params = triggers.alloc_params(&mut program);
+    let loop_start_label = program.allocate_label();
+    let next_label = program.allocate_label();
+    program.emit_insn(Insn::Rewind {
+        cursor_id: temp_cursor_id,
+        pc_if_empty: end_label,
+    });
+    program.preassign_label_to_next_insn(loop_start_label);
+    program.emit_null(params.old_rowid_reg(), None);
+    program.emit_null(params.new_rowid_reg(), None);
+    for (columns, start_reg) in [
+        (&old, params.old_columns_start_reg()),
+        (&new, params.new_columns_start_reg()),
+    ] {
+        for (i, column) in columns.iter().enumerate() {
+            match column {
+                Some(column) => program.emit_column(temp_cursor_id, *column, start_reg + i),
+                None => program.emit_null(start_reg + i, None),
+            }
+        }
+    }
+    triggers.emit_instead_of(&mut program, &params, next_label);
+    program.preassign_label_to_next_insn(next_label);
+    program.emit_insn(Insn::Next {
+        cursor_id: temp_cursor_id,
+        pc_if_next: loop_start_label,
+    });
+
+    program.preassign_label_to_next_insn(end_label);
+    program.epilogue(TransactionMode::Write);
+    Ok(program)
+}
@@ -1,9 +1,10 @@
 use turso_sqlite3_parser::ast::{self, SortOrder};
 
+use std::rc::Rc;
 use std::sync::Arc;
 
 use crate::{
-    schema::{Affinity, Index, IndexColumn, Table},
+    schema::{Affinity, BTreeTable, CteRows, Index, IndexColumn, Table},
     translate::{
         plan::{DistinctCtx, Distinctness},
         result_row::emit_select_result,
@@ -19,7 +20,7 @@ use crate::{
 
 use super::{
     aggregation::translate_aggregation_step,
-    emitter::{OperationMode, TranslateCtx},
+    emitter::{emit_index_entry, OperationMode, Resolver, TranslateCtx},
     expr::{
         translate_condition_expr, translate_expr, translate_expr_no_constant_opt,
         ConditionMetadata, NoConstantOptReason,
@@ -28,9 +29,12 @@ use super::{
     optimizer::Optimizable,
     order_by::{order_by_sorter_insert, sorter_insert},
     plan::{
-        convert_where_to_vtab_constraint, Aggregate, GroupBy, IterationDirection, JoinOrderMember,
-        Operation, QueryDestination, Search, SeekDef, SelectPlan, TableReferences, WhereTerm,
+        convert_where_to_vtab_constraint, Aggregate, EvalAt, GroupBy, IterationDirection,
+        JoinOrderMember, JoinedTable, Operation, QueryDestination, Search, SeekDef, SelectPlan,
+        TableReferences, WhereTerm,
     },
+    planner::determine_where_to_eval_term,
+    window::emit_window_source_row,
 };
 
 // Metadata for handling LEFT JOIN operations
@@ -44,6 +48,43 @@ pub struct LeftJoinMetadata {
     pub label_match_flag_check_value: BranchOffset,
 }
 
+// Metadata for handling RIGHT and FULL OUTER JOIN operations
+#[derive(Debug)]
+pub struct RightJoinMetadata {
+    // cursor of an ephemeral index holding the key of each row of the right table that has a match
+    pub matched_cursor_id: CursorID,
+    // register that holds the key of the current row of the right table: its rowid,
+    // or its number if the table is read from a coroutine
+    pub reg_key: usize,
+    // register that holds the constant 1, used to number the rows read from a coroutine
+    pub reg_one: Option<usize>,
+    // positions of the primary key columns of a table without rowid, whose values are the key
+    // in the registers starting at reg_key
+    pub primary_key_columns: Vec<usize>,
+}
+
+impl RightJoinMetadata {
+    fn num_key_regs(&self) -> usize {
+        self.primary_key_columns.len().max(1)
+    }
+
+    fn emit_reset_row_number(&self, program: &mut ProgramBuilder) {
+        if self.reg_one.is_some() {
+            program.emit_int(0, self.reg_key);
+        }
+    }
+
+    fn emit_increment_row_number(&self, program: &mut ProgramBuilder) {
+        if let Some(reg_one) = self.reg_one {
+            program.emit_insn(Insn::Add {
+                lhs: self.reg_key,
+                rhs: reg_one,
+                dest: self.reg_key,
+            });
+        }
+    }
+}
+
 /// Jump labels for each loop in the query's main execution loop
 #[derive(Debug, Clone, Copy)]
 pub struct LoopLabels {
@@ -82,10 +123,13 @@ pub fn init_distinct(program: &mut ProgramBuilder, plan: &SelectPlan) -> Distinc
                 pos_in_table: i,
                 collation: None, // FIXME: this should be determined based on the result column expression!
                 default: None, // FIXME: this should be determined based on the result column expression!
+                expr: None,
             })
             .collect(),
         unique: false,
         has_rowid: false,
+        num_extra_columns: 0,
+        where_clause: None,
     });
     let cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(index.clone()));
     let ctx = DistinctCtx {
@@ -117,8 +161,13 @@ pub fn init_loop(
         "meta_left_joins length does not match tables length"
     );
 
+    // Only the changes of the main database are captured.
     let cdc_table = program.capture_data_changes_mode().table();
     if cdc_table.is_some()
+        && tables
+            .joined_tables()
+            .first()
+            .is_some_and(|table| table.database_id == 0)
         && matches!(
             mode,
             OperationMode::INSERT | OperationMode::UPDATE | OperationMode::DELETE
@@ -127,6 +176,14 @@ pub fn init_loop(
         assert!(tables.joined_tables().len() == 1);
         let cdc_table_name = cdc_table.unwrap();
         if tables.joined_tables()[0].table.get_name() != cdc_table_name {
+            if tables.joined_tables()[0]
+                .btree()
+                .is_some_and(|table| !table.has_rowid)
+            {
+                crate::bail_parse_error!(
+                    "capturing changes of WITHOUT ROWID tables is not supported"
+                );
+            }
             let Some(cdc_table) = t_ctx.resolver.schema.get_table(cdc_table_name) else {
                 crate::bail_parse_error!("no such table: {}", cdc_table_name);
             };
@@ -165,9 +222,12 @@ pub fn init_loop(
                 pos_in_table: 0,
                 collation: None, // FIXME: this should be inferred from the expression
                 default: None,   // FIXME: this should be inferred from the expression
+                expr: None,
             }],
             has_rowid: false,
             unique: false,
+            num_extra_columns: 0,
+            where_clause: None,
         });
         let cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(index.clone()));
         if group_by.is_none() {
@@ -199,6 +259,59 @@ pub fn init_loop(
             }
         }
         let (table_cursor_id, index_cursor_id) = table.open_cursors(program, mode)?;
+        // Initialize bookkeeping for RIGHT JOIN
+        if table
+            .join_info
+            .as_ref()
+            .is_some_and(|join_info| join_info.right)
+        {
+            let primary_key_columns = match table.btree() {
+                Some(btree) if !btree.has_rowid => btree
+                    .primary_key_columns
+                    .iter()
+                    .map(|(name, _)| btree.get_column(name).unwrap().0)
+                    .collect(),
+                _ => vec![],
+            };
+            let index = Arc::new(Index {
+                name: format!("right_join_{table_index}"),
+                table_name: String::new(),
+                ephemeral: true,
+                root_page: 0,
+                columns: (0..primary_key_columns.len().max(1))
+                    .map(|i| IndexColumn {
+                        name: format!("key{i}"),
+                        order: SortOrder::Asc,
+                        pos_in_table: i,
+                        collation: None,
+                        default: None,
+                        expr: None,
+                    })
+                    .collect(),
+                unique: false,
+                has_rowid: false,
+                num_extra_columns: 0,
+                where_clause: None,
+            });
+            let matched_cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(index));
+            program.emit_insn(Insn::OpenEphemeral {
+                cursor_id: matched_cursor_id,
+                is_table: false,
+            });
+            let reg_one = if table_cursor_id.is_none() && index_cursor_id.is_none() {
+                let reg_one = program.alloc_register();
+                program.emit_int(1, reg_one);
+                Some(reg_one)
+            } else {
+                None
+            };
+            t_ctx.meta_right_joins[table_index] = Some(RightJoinMetadata {
+                matched_cursor_id,
+                reg_key: program.alloc_registers(primary_key_columns.len().max(1)),
+                reg_one,
+                primary_key_columns,
+            });
+        }
         match &table.op {
             Operation::Scan { index, .. } => match (mode, &table.table) {
                 (OperationMode::SELECT, Table::BTree(btree)) => {
@@ -220,12 +333,14 @@ pub fn init_loop(
                 }
                 (OperationMode::DELETE, Table::BTree(btree)) => {
                     let root_page = btree.root_page;
-                    program.emit_insn(Insn::OpenWrite {
-                        cursor_id: table_cursor_id
-                            .expect("table cursor is always opened in OperationMode::DELETE"),
-                        root_page: root_page.into(),
-                        db: table.database_id,
-                    });
+                    // A table without rowid is written through its clustered index cursor.
+                    if let Some(cursor_id) = table_cursor_id {
+                        program.emit_insn(Insn::OpenWrite {
+                            cursor_id,
+                            root_page: root_page.into(),
+                            db: table.database_id,
+                        });
+                    }
                     if let Some(index_cursor_id) = index_cursor_id {
                         program.emit_insn(Insn::OpenWrite {
                             cursor_id: index_cursor_id,
@@ -257,12 +372,13 @@ pub fn init_loop(
                 }
                 (OperationMode::UPDATE, Table::BTree(btree)) => {
                     let root_page = btree.root_page;
-                    program.emit_insn(Insn::OpenWrite {
-                        cursor_id: table_cursor_id
-                            .expect("table cursor is always opened in OperationMode::UPDATE"),
-                        root_page: root_page.into(),
-                        db: table.database_id,
-                    });
+                    if let Some(cursor_id) = table_cursor_id {
+                        program.emit_insn(Insn::OpenWrite {
+                            cursor_id,
+                            root_page: root_page.into(),
+                            db: table.database_id,
+                        });
+                    }
                     if let Some(index_cursor_id) = index_cursor_id {
                         program.emit_insn(Insn::OpenWrite {
                             cursor_id: index_cursor_id,
@@ -271,6 +387,16 @@ pub fn init_loop(
                         });
                     }
                 }
+                (OperationMode::SELECT, Table::Cte(cte)) => {
+                    if let (Some(new_cursor_id), Some(CteRows::Ephemeral { cursor_id })) =
+                        (table_cursor_id, cte.rows.get())
+                    {
+                        program.emit_insn(Insn::OpenDup {
+                            new_cursor_id,
+                            original_cursor_id: cursor_id,
+                        });
+                    }
+                }
                 (_, Table::Virtual(tbl)) => {
                     let is_write = matches!(
                         mode,
@@ -297,15 +423,14 @@ pub fn init_loop(
                         }
                     }
                     OperationMode::DELETE | OperationMode::UPDATE => {
-                        let table_cursor_id = table_cursor_id.expect(
-                                        "table cursor is always opened in OperationMode::DELETE or OperationMode::UPDATE",
-                                    );
-
-                        program.emit_insn(Insn::OpenWrite {
-                            cursor_id: table_cursor_id,
-                            root_page: table.table.get_root_page().into(),
-                            db: table.database_id,
-                        });
+                        // A table without rowid is written through its clustered index cursor.
+                        if let Some(table_cursor_id) = table_cursor_id {
+                            program.emit_insn(Insn::OpenWrite {
+                                cursor_id: table_cursor_id,
+                                root_page: table.table.get_root_page().into(),
+                                db: table.database_id,
+                            });
+                        }
 
                         // For DELETE, we need to open all the indexes for writing
                         // UPDATE opens these in emit_program_for_update() separately
@@ -372,9 +497,21 @@ pub fn init_loop(
         }
     }
 
+    // Only whether a term refers to any of the tables matters here, not their order.
+    let join_order = tables
+        .joined_tables()
+        .iter()
+        .enumerate()
+        .map(|(i, t)| JoinOrderMember {
+            table_id: t.internal_id,
+            original_idx: i,
+            is_outer: false,
+            is_right: false,
+        })
+        .collect::<Vec<_>>();
     for cond in where_clause
         .iter()
-        .filter(|c| c.should_eval_before_loop(&[JoinOrderMember::default()]))
+        .filter(|c| c.should_eval_before_loop(&join_order))
     {
         let jump_target = program.allocate_label();
         let meta = ConditionMetadata {
@@ -400,7 +537,29 @@ pub fn open_loop(
     predicates: &[WhereTerm],
     temp_cursor_id: Option<CursorID>,
 ) -> Result<()> {
-    for (join_index, join) in join_order.iter().enumerate() {
+    open_loops_from(
+        program,
+        t_ctx,
+        table_references,
+        join_order,
+        0,
+        predicates,
+        temp_cursor_id,
+    )
+}
+
+/// Opens the loops of the tables in `join_order` from `first_loop` on, the tables before it
+/// being already positioned.
+fn open_loops_from(
+    program: &mut ProgramBuilder,
+    t_ctx: &mut TranslateCtx,
+    table_references: &TableReferences,
+    join_order: &[JoinOrderMember],
+    first_loop: usize,
+    predicates: &[WhereTerm],
+    temp_cursor_id: Option<CursorID>,
+) -> Result<()> {
+    for (join_index, join) in join_order.iter().enumerate().skip(first_loop) {
         let joined_table_index = join.original_idx;
         let table = &table_references.joined_tables()[joined_table_index];
         let LoopLabels {
@@ -430,7 +589,35 @@ pub fn open_loop(
         match &table.op {
             Operation::Scan { iter_dir, .. } => {
                 match &table.table {
-                    Table::BTree(_) => {
+                    Table::Cte(cte) if table_cursor_id.is_none() => {
+                        let Some(CteRows::Coroutine {
+                            yield_reg,
+                            coroutine_implementation_start,
+                            ..
+                        }) = cte.rows.get()
+                        else {
+                            unreachable!("a CTE without a cursor is read from a coroutine");
+                        };
+                        // Like a FROM clause subquery, the coroutine is reinitialized on each
+                        // iteration of the outer loop.
+                        program.emit_insn(Insn::InitCoroutine {
+                            yield_reg,
+                            jump_on_definition: BranchOffset::Offset(0),
+                            start_offset: coroutine_implementation_start,
+                        });
+                        if let Some(rj_meta) = t_ctx.meta_right_joins[joined_table_index].as_ref() {
+                            rj_meta.emit_reset_row_number(program);
+                        }
+                        program.preassign_label_to_next_insn(loop_start);
+                        program.emit_insn(Insn::Yield {
+                            yield_reg,
+                            end_offset: loop_end,
+                        });
+                        if let Some(rj_meta) = t_ctx.meta_right_joins[joined_table_index].as_ref() {
+                            rj_meta.emit_increment_row_number(program);
+                        }
+                    }
+                    Table::BTree(_) | Table::Cte(_) => {
                         let iteration_cursor_id = temp_cursor_id.unwrap_or_else(|| {
                             index_cursor_id.unwrap_or_else(|| {
                                 table_cursor_id.expect(
@@ -568,6 +755,9 @@ pub fn open_loop(
                             jump_on_definition: BranchOffset::Offset(0),
                             start_offset: coroutine_implementation_start,
                         });
+                        if let Some(rj_meta) = t_ctx.meta_right_joins[joined_table_index].as_ref() {
+                            rj_meta.emit_reset_row_number(program);
+                        }
                         program.preassign_label_to_next_insn(loop_start);
                         // A subquery within the main loop of a parent query has no cursor, so instead of advancing the cursor,
                         // it emits a Yield which jumps back to the main loop of the subquery itself to retrieve the next row.
@@ -577,6 +767,9 @@ pub fn open_loop(
                             yield_reg,
                             end_offset: loop_end,
                         });
+                        if let Some(rj_meta) = t_ctx.meta_right_joins[joined_table_index].as_ref() {
+                            rj_meta.emit_increment_row_number(program);
+                        }
                     }
                 }
 
@@ -588,26 +781,6 @@ pub fn open_loop(
                         });
                     }
                 }
-
-                for cond in predicates
-                    .iter()
-                    .filter(|cond| cond.should_eval_at_loop(join_index, join_order))
-                {
-                    let jump_target_when_true = program.allocate_label();
-                    let condition_metadata = ConditionMetadata {
-                        jump_if_condition_is_true: false,
-                        jump_target_when_true,
-                        jump_target_when_false: next,
-                    };
-                    translate_condition_expr(
-                        program,
-                        table_references,
-                        &cond.expr,
-                        condition_metadata,
-                        &t_ctx.resolver,
-                    )?;
-                    program.preassign_label_to_next_insn(jump_target_when_true);
-                }
             }
             Operation::Search(search) => {
                 assert!(
@@ -638,19 +811,18 @@ pub fn open_loop(
                     } = search
                     {
                         if index.ephemeral {
-                            let table_has_rowid = if let Table::BTree(btree) = &table.table {
-                                btree.has_rowid
-                            } else {
-                                false
+                            let Table::BTree(btree) = &table.table else {
+                                unreachable!("an ephemeral index must be built on a btree table");
                             };
                             Some(emit_autoindex(
                                 program,
+                                &t_ctx.resolver,
+                                btree,
                                 index,
                                 table_cursor_id
                                     .expect("an ephemeral index must have a source table cursor"),
                                 index_cursor_id
                                     .expect("an ephemeral index must have an index cursor"),
-                                table_has_rowid,
                             )?)
                         } else {
                             index_cursor_id
@@ -703,29 +875,52 @@ pub fn open_loop(
                         }
                     }
                 }
-
-                for cond in predicates
-                    .iter()
-                    .filter(|cond| cond.should_eval_at_loop(join_index, join_order))
-                {
-                    let jump_target_when_true = program.allocate_label();
-                    let condition_metadata = ConditionMetadata {
-                        jump_if_condition_is_true: false,
-                        jump_target_when_true,
-                        jump_target_when_false: next,
-                    };
-                    translate_condition_expr(
-                        program,
-                        table_references,
-                        &cond.expr,
-                        condition_metadata,
-                        &t_ctx.resolver,
-                    )?;
-                    program.preassign_label_to_next_insn(jump_target_when_true);
-                }
             }
         }
 
+        // The ON clause of an OUTER JOIN decides whether the row of the right table matches, so it
+        // is evaluated first. The other conditions filter the joined rows, including the rows with
+        // NULLs emitted for the right table when there is no match, so they are evaluated after
+        // the match is recorded.
+        let (join_conditions, other_conditions): (Vec<_>, Vec<_>) = predicates
+            .iter()
+            .filter(|cond| cond.should_eval_at_loop(join_index, join_order))
+            .partition(|cond| cond.from_outer_join == Some(table.internal_id));
+        emit_conditions(
+            program,
+            t_ctx,
+            table_references,
+            join_conditions.into_iter(),
+            next,
+        )?;
+
+        // Record that the row of the right table of a RIGHT JOIN has a match.
+        if let Some(rj_meta) = t_ctx.meta_right_joins[joined_table_index].as_ref() {
+            emit_right_join_key(program, rj_meta, table_cursor_id, index_cursor_id);
+            let label_recorded = program.allocate_label();
+            program.emit_insn(Insn::Found {
+                cursor_id: rj_meta.matched_cursor_id,
+                target_pc: label_recorded,
+                record_reg: rj_meta.reg_key,
+                num_regs: rj_meta.num_key_regs(),
+            });
+            let record_reg = program.alloc_register();
+            program.emit_insn(Insn::MakeRecord {
+                start_reg: rj_meta.reg_key,
+                count: rj_meta.num_key_regs(),
+                dest_reg: record_reg,
+                index_name: None,
+            });
+            program.emit_insn(Insn::IdxInsert {
+                cursor_id: rj_meta.matched_cursor_id,
+                record_reg,
+                unpacked_start: Some(rj_meta.reg_key),
+                unpacked_count: Some(rj_meta.num_key_regs() as u16),
+                flags: IdxInsertFlags::new(),
+            });
+            program.preassign_label_to_next_insn(label_recorded);
+        }
+
         // Set the match flag to true if this is a LEFT JOIN.
         // At this point of execution we are going to emit columns for the left table,
         // and either emit columns or NULLs for the right table, depending on whether the null_flag is set
@@ -740,6 +935,14 @@ pub fn open_loop(
                 });
             }
         }
+
+        emit_conditions(
+            program,
+            t_ctx,
+            table_references,
+            other_conditions.into_iter(),
+            next,
+        )?;
     }
 
     Ok(())
@@ -751,11 +954,13 @@ pub fn open_loop(
 /// - a GROUP BY phase with no sorting (when the rows are already in the order required by the GROUP BY keys)
 /// - an ORDER BY sorter (when there is no GROUP BY, but there is an ORDER BY)
 /// - an AggStep (the columns are collected for aggregation, which is finished later)
+/// - a window stage (the rows are collected for computing window functions, which emits the results later)
 /// - a QueryResult (there is none of the above, so the loop either emits a ResultRow, or if it's a subquery, yields to the parent query)
 enum LoopEmitTarget {
     GroupBy,
     OrderBySorter,
     AggStep,
+    Window,
     QueryResult,
 }
 
@@ -766,6 +971,11 @@ pub fn emit_loop(
     t_ctx: &mut TranslateCtx,
     plan: &SelectPlan,
 ) -> Result<()> {
+    // if we have window functions, the rows go into the first window stage.
+    // window functions are planned over a subquery, so there is no group by or aggregation here.
+    if plan.window.is_some() {
+        return emit_loop_source(program, t_ctx, plan, LoopEmitTarget::Window);
+    }
     // if we have a group by, we emit a record into the group by sorter,
     // or if the rows are already sorted, we do the group by aggregation phase directly.
     if plan.group_by.is_some() {
@@ -838,8 +1048,9 @@ fn emit_loop_source(
             for agg in aggregates.iter() {
                 // For a query like: SELECT group_col, SUM(val1), AVG(val2) FROM table GROUP BY group_col
                 // we'll process val1 and val2 here, storing them in the sorter so they're available
-                // when computing the aggregates after sorting by group_col
-                for expr in agg.args.iter() {
+                // when computing the aggregates after sorting by group_col.
+                // A FILTER condition is stored after the arguments.
+                for expr in agg.args.iter().chain(agg.filter.iter()) {
                     let agg_reg = cur_reg;
                     cur_reg += 1;
                     translate_expr(
@@ -957,6 +1168,7 @@ fn emit_loop_source(
 
             Ok(())
         }
+        LoopEmitTarget::Window => emit_window_source_row(program, t_ctx, plan),
         LoopEmitTarget::QueryResult => {
             assert!(
                 plan.aggregates.is_empty(),
@@ -1021,7 +1233,13 @@ pub fn close_loop(
             Operation::Scan { iter_dir, .. } => {
                 program.resolve_label(loop_labels.next, program.offset());
                 match &table.table {
-                    Table::BTree(_) => {
+                    Table::Cte(_) if table_cursor_id.is_none() => {
+                        // A CTE read from a coroutine jumps back to its Yield instruction.
+                        program.emit_insn(Insn::Goto {
+                            target_pc: loop_labels.loop_start,
+                        });
+                    }
+                    Table::BTree(_) | Table::Cte(_) => {
                         let iteration_cursor_id = temp_cursor_id.unwrap_or_else(|| {
                             index_cursor_id.unwrap_or_else(|| {
                                 table_cursor_id.expect(
@@ -1114,16 +1332,7 @@ pub fn close_loop(
                 // but since it's a LEFT JOIN, we still need to emit a row with NULLs for the right table.
                 // In that case, we now enter the routine that does exactly that.
                 // First we set the right table cursor's "pseudo null bit" on, which means any Insn::Column will return NULL.
-                // This needs to be set for both the table and the index cursor, if present,
-                // since even if the iteration cursor is the index cursor, it might fetch values from the table cursor.
-                [table_cursor_id, index_cursor_id]
-                    .iter()
-                    .filter_map(|maybe_cursor_id| maybe_cursor_id.as_ref())
-                    .for_each(|cursor_id| {
-                        program.emit_insn(Insn::NullRow {
-                            cursor_id: *cursor_id,
-                        });
-                    });
+                emit_null_row(program, table, table_cursor_id, index_cursor_id);
                 // Then we jump to setting the left join match flag to 1 again,
                 // but this time the right table cursor will set everything to null.
                 // This leads to emitting a row with cols from the left + nulls from the right,
@@ -1140,6 +1349,272 @@ pub fn close_loop(
     Ok(())
 }
 
+/// Makes the columns of `table` read as NULL until it is positioned on another row.
+fn emit_null_row(
+    program: &mut ProgramBuilder,
+    table: &JoinedTable,
+    table_cursor_id: Option<CursorID>,
+    index_cursor_id: Option<CursorID>,
+) {
+    // A table read from a coroutine has no cursor, and its columns are read from the registers
+    // its rows are yielded in.
+    let coroutine_result_reg = match &table.table {
+        Table::FromClauseSubquery(subquery) => subquery.result_columns_start_reg,
+        Table::Cte(cte) => match cte.rows.get() {
+            Some(CteRows::Coroutine {
+                result_columns_start_reg,
+                ..
+            }) if table_cursor_id.is_none() => Some(result_columns_start_reg),
+            _ => None,
+        },
+        _ => None,
+    };
+    if let Some(start_reg) = coroutine_result_reg {
+        program.emit_insn(Insn::Null {
+            dest: start_reg,
+            dest_end: Some(start_reg + table.columns().len() - 1),
+        });
+        return;
+    }
+    // The null bit needs to be set for both the table and the index cursor, if present,
+    // since even if the iteration cursor is the index cursor, it might fetch values from the table cursor.
+    for cursor_id in [table_cursor_id, index_cursor_id].into_iter().flatten() {
+        program.emit_insn(Insn::NullRow { cursor_id });
+    }
+}
+
+/// Emits the conditions, jumping to `jump_target_when_false` as soon as one of them is false.
+fn emit_conditions<'a>(
+    program: &mut ProgramBuilder,
+    t_ctx: &TranslateCtx,
+    table_references: &TableReferences,
+    conditions: impl Iterator<Item = &'a WhereTerm>,
+    jump_target_when_false: BranchOffset,
+) -> Result<()> {
+    for cond in conditions {
+        let jump_target_when_true = program.allocate_label();
+        let condition_metadata = ConditionMetadata {
+            jump_if_condition_is_true: false,
+            jump_target_when_true,
+            jump_target_when_false,
+        };
+        translate_condition_expr(
+            program,
+            table_references,
+            &cond.expr,
+            condition_metadata,
+            &t_ctx.resolver,
+        )?;
+        program.preassign_label_to_next_insn(jump_target_when_true);
+    }
+    Ok(())
+}
+
+/// Loads the key of the current row of the right table of a RIGHT JOIN. The number of a row read
+/// from a coroutine is already kept up to date by its loop.
+fn emit_right_join_key(
+    program: &mut ProgramBuilder,
+    rj_meta: &RightJoinMetadata,
+    table_cursor_id: Option<CursorID>,
+    index_cursor_id: Option<CursorID>,
+) {
+    match (table_cursor_id, index_cursor_id) {
+        // Every index of a table without rowid stores its primary key.
+        (None, Some(cursor_id)) if !rj_meta.primary_key_columns.is_empty() => {
+            for (i, &pos_in_table) in rj_meta.primary_key_columns.iter().enumerate() {
+                program.emit_table_column(cursor_id, pos_in_table, rj_meta.reg_key + i);
+            }
+        }
+        (Some(cursor_id), _) => program.emit_insn(Insn::RowId {
+            cursor_id,
+            dest: rj_meta.reg_key,
+        }),
+        (None, Some(cursor_id)) => program.emit_insn(Insn::IdxRowId {
+            cursor_id,
+            dest: rj_meta.reg_key,
+        }),
+        (None, None) => {}
+    }
+}
+
+/// Emits the main loop of a query with a RIGHT or FULL OUTER JOIN, whose loops have been opened.
+///
+/// The rows of the right table of such a join that match no row of the tables to its left are
+/// only known once the main loop is done. They are then emitted by scanning the right table
+/// again, skipping the rows whose key was recorded as matched, with NULL rows for the tables to
+/// its left. The loops of the tables after it are run again for each of them. The inner loop is
+/// a subroutine called from each of those places.
+pub fn emit_loop_with_right_joins(
+    program: &mut ProgramBuilder,
+    t_ctx: &mut TranslateCtx,
+    plan: &SelectPlan,
+) -> Result<()> {
+    let tables = &plan.table_references;
+    let join_order = &plan.join_order;
+    let return_reg = program.alloc_register();
+    let label_loop_body = program.allocate_label();
+    program.emit_insn(Insn::Gosub {
+        target_pc: label_loop_body,
+        return_reg,
+    });
+    close_loop(program, t_ctx, tables, join_order, None)?;
+
+    for (right_idx, member) in join_order.iter().enumerate().filter(|(_, m)| m.is_right) {
+        let table = &tables.joined_tables()[member.original_idx];
+        let loop_labels = LoopLabels::new(program);
+        for left_member in &join_order[..right_idx] {
+            let left_table = &tables.joined_tables()[left_member.original_idx];
+            let (table_cursor_id, index_cursor_id) = left_table.resolve_cursors(program)?;
+            emit_null_row(program, left_table, table_cursor_id, index_cursor_id);
+            // Skipping a row of the left side, e.g. for the OFFSET, skips the unmatched row.
+            t_ctx.labels_main_loop[left_member.original_idx] = loop_labels;
+        }
+
+        let (table_cursor_id, index_cursor_id) = table.resolve_cursors(program)?;
+        let rj_meta = t_ctx.meta_right_joins[member.original_idx]
+            .as_ref()
+            .expect("right join metadata must exist");
+        let coroutine = match &table.table {
+            Table::FromClauseSubquery(subquery) => match &subquery.plan.query_destination {
+                QueryDestination::CoroutineYield {
+                    yield_reg,
+                    coroutine_implementation_start,
+                } => Some((*yield_reg, *coroutine_implementation_start)),
+                _ => unreachable!("Subquery table with non-subquery query type"),
+            },
+            Table::Cte(cte) if table_cursor_id.is_none() => match cte.rows.get() {
+                Some(CteRows::Coroutine {
+                    yield_reg,
+                    coroutine_implementation_start,
+                    ..
+                }) => Some((yield_reg, coroutine_implementation_start)),
+                _ => unreachable!("a CTE without a cursor is read from a coroutine"),
+            },
+            _ => None,
+        };
+        let iteration_cursor_id = match coroutine {
+            Some((yield_reg, coroutine_implementation_start)) => {
+                program.emit_insn(Insn::InitCoroutine {
+                    yield_reg,
+                    jump_on_definition: BranchOffset::Offset(0),
+                    start_offset: coroutine_implementation_start,
+                });
+                rj_meta.emit_reset_row_number(program);
+                program.preassign_label_to_next_insn(loop_labels.loop_start);
+                program.emit_insn(Insn::Yield {
+                    yield_reg,
+                    end_offset: loop_labels.loop_end,
+                });
+                rj_meta.emit_increment_row_number(program);
+                None
+            }
+            None => {
+                let cursor_id = index_cursor_id
+                    .or(table_cursor_id)
+                    .expect("Either index or table cursor must be opened");
+                program.emit_insn(Insn::Rewind {
+                    cursor_id,
+                    pc_if_empty: loop_labels.loop_end,
+                });
+                program.preassign_label_to_next_insn(loop_labels.loop_start);
+                if let (Some(index_cursor_id), Some(table_cursor_id)) =
+                    (index_cursor_id, table_cursor_id)
+                {
+                    program.emit_insn(Insn::DeferredSeek {
+                        index_cursor_id,
+                        table_cursor_id,
+                    });
+                }
+                Some(cursor_id)
+            }
+        };
+        emit_right_join_key(program, rj_meta, table_cursor_id, index_cursor_id);
+        program.emit_insn(Insn::Found {
+            cursor_id: rj_meta.matched_cursor_id,
+            target_pc: loop_labels.next,
+            record_reg: rj_meta.reg_key,
+            num_regs: rj_meta.num_key_regs(),
+        });
+
+        // The WHERE clause conditions that would have been evaluated up to this table. The ON
+        // clauses of the joins up to this one are not, since the row has no match.
+        let mut conditions = vec![];
+        for cond in plan
+            .where_clause
+            .iter()
+            .filter(|cond| cond.from_outer_join.is_none())
+        {
+            if let EvalAt::Loop(idx) = determine_where_to_eval_term(cond, join_order)? {
+                if idx <= right_idx {
+                    conditions.push(cond);
+                }
+            }
+        }
+        emit_conditions(
+            program,
+            t_ctx,
+            tables,
+            conditions.into_iter(),
+            loop_labels.next,
+        )?;
+
+        // The loops after this table are opened again, with new labels.
+        for inner_member in &join_order[right_idx + 1..] {
+            let idx = inner_member.original_idx;
+            t_ctx.labels_main_loop[idx] = LoopLabels::new(program);
+            if let Some(lj_meta) = t_ctx.meta_left_joins[idx].as_mut() {
+                lj_meta.label_match_flag_set_true = program.allocate_label();
+                lj_meta.label_match_flag_check_value = program.allocate_label();
+            }
+        }
+        open_loops_from(
+            program,
+            t_ctx,
+            tables,
+            join_order,
+            right_idx + 1,
+            &plan.where_clause,
+            None,
+        )?;
+        program.emit_insn(Insn::Gosub {
+            target_pc: label_loop_body,
+            return_reg,
+        });
+        close_loop(program, t_ctx, tables, &join_order[right_idx + 1..], None)?;
+
+        program.preassign_label_to_next_insn(loop_labels.next);
+        match iteration_cursor_id {
+            Some(cursor_id) => program.emit_insn(Insn::Next {
+                cursor_id,
+                pc_if_next: loop_labels.loop_start,
+            }),
+            None => program.emit_insn(Insn::Goto {
+                target_pc: loop_labels.loop_start,
+            }),
+        }
+        program.preassign_label_to_next_insn(loop_labels.loop_end);
+    }
+
+    let label_loop_body_end = program.allocate_label();
+    program.emit_insn(Insn::Goto {
+        target_pc: label_loop_body_end,
+    });
+    program.preassign_label_to_next_insn(label_loop_body);
+    // Skipping a row, e.g. for the OFFSET, returns from the subroutine.
+    let label_return = program.allocate_label();
+    if let Some(first_loop_labels) = t_ctx.labels_main_loop.first_mut() {
+        first_loop_labels.next = label_return;
+    }
+    emit_loop(program, t_ctx, plan)?;
+    program.preassign_label_to_next_insn(label_return);
+    program.emit_insn(Insn::Return {
+        return_reg,
+        can_fallthrough: false,
+    });
+    program.preassign_label_to_next_insn(label_loop_body_end);
+    Ok(())
+}
+
 /// Emits instructions for an index seek. See e.g. [crate::translate::plan::SeekDef]
 /// for more details about the seek definition.
 ///
@@ -1414,10 +1889,11 @@ fn emit_seek_termination(
 /// Returns the cursor id of the ephemeral index cursor.
 fn emit_autoindex(
     program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    table: &Rc<BTreeTable>,
     index: &Arc<Index>,
     table_cursor_id: CursorID,
     index_cursor_id: CursorID,
-    table_has_rowid: bool,
 ) -> Result<CursorID> {
     assert!(index.ephemeral, "Index {} is not ephemeral", index.name);
     let label_ephemeral_build_end = program.allocate_label();
@@ -1435,20 +1911,17 @@ fn emit_autoindex(
         pc_if_empty: label_ephemeral_build_loop_start,
     });
     program.preassign_label_to_next_insn(label_ephemeral_build_loop_start);
-    // Emit all columns from source table that are needed in the ephemeral index.
-    // Also reserve a register for the rowid if the source table has rowids.
-    let num_regs_to_reserve = index.columns.len() + table_has_rowid as usize;
-    let ephemeral_cols_start_reg = program.alloc_registers(num_regs_to_reserve);
-    for (i, col) in index.columns.iter().enumerate() {
-        let reg = ephemeral_cols_start_reg + i;
-        program.emit_column(table_cursor_id, col.pos_in_table, reg);
-    }
-    if table_has_rowid {
-        program.emit_insn(Insn::RowId {
-            cursor_id: table_cursor_id,
-            dest: ephemeral_cols_start_reg + index.columns.len(),
-        });
-    }
+    // Emit all columns from source table that are needed in the ephemeral index,
+    // followed by the rowid if the source table has rowids.
+    let not_indexed_label = program.allocate_label();
+    let (ephemeral_cols_start_reg, num_regs_to_reserve) = emit_index_entry(
+        program,
+        resolver,
+        table,
+        table_cursor_id,
+        index,
+        not_indexed_label,
+    )?;
     let record_reg = program.alloc_register();
     program.emit_insn(Insn::MakeRecord {
         start_reg: ephemeral_cols_start_reg,
@@ -1463,6 +1936,7 @@ fn emit_autoindex(
         unpacked_count: Some(num_regs_to_reserve as u16),
         flags: IdxInsertFlags::new().use_seek(false),
     });
+    program.preassign_label_to_next_insn(not_indexed_label);
     program.emit_insn(Insn::Next {
         cursor_id: table_cursor_id,
         pc_if_next: label_ephemeral_build_loop_start,
@@ -9,16 +9,22 @@
 
 pub(crate) mod aggregation;
 pub(crate) mod alter;
+pub(crate) mod analyze;
 pub(crate) mod attach;
+pub(crate) mod check;
 pub(crate) mod collate;
-mod compound_select;
+pub(crate) mod compound_select;
+pub(crate) mod cte;
 pub(crate) mod delete;
 pub(crate) mod display;
 pub(crate) mod emitter;
 pub(crate) mod expr;
+pub(crate) mod fkey;
+pub(crate) mod generated;
 pub(crate) mod group_by;
 pub(crate) mod index;
 pub(crate) mod insert;
+pub(crate) mod instead_of;
 pub(crate) mod integrity_check;
 pub(crate) mod main_loop;
 pub(crate) mod optimizer;
@@ -32,8 +38,13 @@ pub(crate) mod schema;
 pub(crate) mod select;
 pub(crate) mod subquery;
 pub(crate) mod transaction;
+pub(crate) mod trigger;
 pub(crate) mod update;
+pub(crate) mod upsert;
+pub(crate) mod vacuum;
 mod values;
+pub(crate) mod view;
+pub(crate) mod window;
 
 use crate::schema::Schema;
 use crate::storage::pager::Pager;
@@ -42,15 +53,20 @@ use crate::vdbe::builder::{ProgramBuilder, ProgramBuilderOpts, QueryMode};
 use crate::vdbe::Program;
 use crate::{bail_parse_error, Connection, Result, SymbolTable};
 use alter::translate_alter_table;
-use index::{translate_create_index, translate_drop_index};
+use index::{translate_create_index, translate_drop_index, translate_reindex};
 use insert::translate_insert;
 use rollback::translate_rollback;
-use schema::{translate_create_table, translate_create_virtual_table, translate_drop_table};
+use schema::{
+    translate_create_table, translate_create_table_as_select, translate_create_virtual_table,
+    translate_drop_table,
+};
 use select::translate_select;
 use std::rc::Rc;
 use std::sync::Arc;
 use tracing::{instrument, Level};
-use transaction::{translate_tx_begin, translate_tx_commit};
+use transaction::{
+    translate_release, translate_savepoint, translate_tx_begin, translate_tx_commit,
+};
 use turso_sqlite3_parser::ast::{self, Delete, Insert};
 use update::translate_update;
 
@@ -63,9 +79,9 @@ pub fn translate(
     connection: Arc<Connection>,
     syms: &SymbolTable,
     query_mode: QueryMode,
-    _input: &str, // TODO: going to be used for CREATE VIEW
+    input: &str,
 ) -> Result<Program> {
-    tracing::trace!("querying {}", _input);
+    tracing::trace!("querying {}", input);
     let change_cnt_on = matches!(
         stmt,
         ast::Stmt::CreateIndex { .. }
@@ -116,10 +132,8 @@ pub fn translate_inner(
     connection: &Arc<Connection>,
 ) -> Result<ProgramBuilder> {
     let program = match stmt {
-        ast::Stmt::AlterTable(alter) => {
-            translate_alter_table(*alter, syms, schema, program, connection)?
-        }
-        ast::Stmt::Analyze(_) => bail_parse_error!("ANALYZE not supported yet"),
+        ast::Stmt::AlterTable(alter) => translate_alter_table(*alter, syms, program, connection)?,
+        ast::Stmt::Analyze(name) => analyze::translate_analyze(name, connection, program)?,
         ast::Stmt::Attach { expr, db_name, key } => {
             attach::translate_attach(&expr, &db_name, &key, schema, syms, program)?
         }
@@ -131,37 +145,78 @@ pub fn translate_inner(
             idx_name,
             tbl_name,
             columns,
-            ..
+            where_clause,
         } => translate_create_index(
             (unique, if_not_exists),
-            idx_name.name.as_str(),
+            &idx_name,
             tbl_name.as_str(),
             &columns,
-            schema,
+            where_clause.as_deref(),
+            syms,
             program,
+            connection,
         )?,
         ast::Stmt::CreateTable {
             temporary,
             if_not_exists,
             tbl_name,
             body,
-        } => translate_create_table(tbl_name, temporary, *body, if_not_exists, schema, program)?,
-        ast::Stmt::CreateTrigger { .. } => bail_parse_error!("CREATE TRIGGER not supported yet"),
-        ast::Stmt::CreateView { .. } => bail_parse_error!("CREATE VIEW not supported yet"),
+        } => match *body {
+            ast::CreateTableBody::AsSelect(select) => translate_create_table_as_select(
+                tbl_name,
+                temporary,
+                *select,
+                if_not_exists,
+                schema,
+                syms,
+                program,
+                connection,
+                input,
+            )?,
+            body => translate_create_table(
+                tbl_name,
+                temporary,
+                body,
+                if_not_exists,
+                connection,
+                program,
+            )?,
+        },
+        ast::Stmt::CreateTrigger(create_trigger) => {
+            trigger::translate_create_trigger(*create_trigger, program, connection)?
+        }
+        ast::Stmt::CreateView {
+            temporary,
+            if_not_exists,
+            view_name,
+            columns,
+            select,
+        } => view::translate_create_view(
+            temporary,
+            if_not_exists,
+            view_name,
+            columns,
+            select,
+            program,
+            connection,
+        )?,
         ast::Stmt::CreateVirtualTable(vtab) => {
             translate_create_virtual_table(*vtab, schema, syms, program)?
         }
         ast::Stmt::Delete(delete) => {
             let Delete {
+                with,
                 tbl_name,
+                indexed,
                 where_clause,
                 limit,
                 returning,
                 ..
             } = *delete;
             translate_delete(
-                schema,
+                with,
                 &tbl_name,
+                indexed,
                 where_clause,
                 limit,
                 returning,
@@ -174,23 +229,31 @@ pub fn translate_inner(
         ast::Stmt::DropIndex {
             if_exists,
             idx_name,
-        } => translate_drop_index(idx_name.name.as_str(), if_exists, schema, program)?,
+        } => translate_drop_index(&idx_name, if_exists, program, connection)?,
         ast::Stmt::DropTable {
             if_exists,
             tbl_name,
-        } => translate_drop_table(tbl_name, if_exists, schema, program)?,
-        ast::Stmt::DropTrigger { .. } => bail_parse_error!("DROP TRIGGER not supported yet"),
-        ast::Stmt::DropView { .. } => bail_parse_error!("DROP VIEW not supported yet"),
+        } => translate_drop_table(tbl_name, if_exists, connection, program)?,
+        ast::Stmt::DropTrigger {
+            if_exists,
+            trigger_name,
+        } => trigger::translate_drop_trigger(&trigger_name, if_exists, program, connection)?,
+        ast::Stmt::DropView {
+            if_exists,
+            view_name,
+        } => view::translate_drop_view(&view_name, if_exists, program, connection)?,
         ast::Stmt::Pragma(..) => {
             bail_parse_error!("PRAGMA statement cannot be evaluated in a nested context")
         }
-        ast::Stmt::Reindex { .. } => bail_parse_error!("REINDEX not supported yet"),
-        ast::Stmt::Release(_) => bail_parse_error!("RELEASE not supported yet"),
+        ast::Stmt::Reindex { obj_name } => {
+            translate_reindex(obj_name.as_ref(), syms, program, connection)?
+        }
+        ast::Stmt::Release(name) => translate_release(name, program)?,
         ast::Stmt::Rollback {
             tx_name,
             savepoint_name,
         } => translate_rollback(schema, syms, program, tx_name, savepoint_name)?,
-        ast::Stmt::Savepoint(_) => bail_parse_error!("SAVEPOINT not supported yet"),
+        ast::Stmt::Savepoint(name) => translate_savepoint(name, program)?,
         ast::Stmt::Select(select) => {
             translate_select(
                 schema,
@@ -202,10 +265,10 @@ pub fn translate_inner(
             )?
             .program
         }
-        ast::Stmt::Update(mut update) => {
-            translate_update(schema, &mut update, syms, program, connection)?
+        ast::Stmt::Update(mut update) => translate_update(&mut update, syms, program, connection)?,
+        ast::Stmt::Vacuum(schema_name, into) => {
+            vacuum::translate_vacuum(schema_name, into, schema, syms, program)?
         }
-        ast::Stmt::Vacuum(_, _) => bail_parse_error!("VACUUM not supported yet"),
         ast::Stmt::Insert(insert) => {
             let Insert {
                 with,
@@ -4,7 +4,7 @@ use turso_sqlite3_parser::ast::SortOrder;
 
 use crate::{
     schema::Index,
-    translate::plan::{IterationDirection, JoinOrderMember, JoinedTable},
+    translate::plan::{IndexHint, IterationDirection, JoinOrderMember, JoinedTable},
     Result,
 };
 
@@ -36,9 +36,20 @@ impl AccessMethod<'_> {
         self.constraint_refs.is_empty()
     }
 
-    pub fn new_table_scan(input_cardinality: f64, iter_dir: IterationDirection) -> Self {
+    pub fn new_table_scan(
+        row_count: f64,
+        input_cardinality: f64,
+        iter_dir: IterationDirection,
+    ) -> Self {
         Self {
-            cost: estimate_cost_for_scan_or_seek(None, &[], &[], input_cardinality),
+            cost: estimate_cost_for_scan_or_seek(
+                None,
+                None,
+                &[],
+                &[],
+                row_count,
+                input_cardinality,
+            ),
             iter_dir,
             index: None,
             constraint_refs: &[],
@@ -55,12 +66,47 @@ pub fn find_best_access_method_for_join_order<'a>(
     input_cardinality: f64,
 ) -> Result<AccessMethod<'a>> {
     let table_no = join_order.last().unwrap().table_id;
-    let mut best_access_method =
-        AccessMethod::new_table_scan(input_cardinality, IterationDirection::Forwards);
+    let mut best_access_method = AccessMethod::new_table_scan(
+        rhs_constraints.row_count(),
+        input_cardinality,
+        IterationDirection::Forwards,
+    );
     let rowid_column_idx = rhs_table.columns().iter().position(|c| c.is_rowid_alias);
+    // A table without rowid is stored in its clustered index, so the rows cannot be looked up
+    // by rowid and an index that does not cover the query is of no use.
+    let without_rowid = rhs_table.btree().is_some_and(|table| !table.has_rowid);
+    let is_clustered_index = |index: &Index| {
+        rhs_table
+            .btree()
+            .is_some_and(|table| index.root_page == table.root_page)
+    };
 
     // Estimate cost for each candidate index (including the rowid index) and replace best_access_method if the cost is lower.
     for candidate in rhs_constraints.candidates.iter() {
+        // INDEXED BY forces the use of its index whatever the cost, and NOT INDEXED leaves only
+        // the rowid, or the clustered index of a table without rowid.
+        let is_allowed = match &rhs_table.index_hint {
+            Some(IndexHint::IndexedBy(forced)) => candidate
+                .index
+                .as_ref()
+                .is_some_and(|index| index.name == forced.name),
+            Some(IndexHint::NotIndexed) => candidate
+                .index
+                .as_ref()
+                .is_none_or(|index| is_clustered_index(index)),
+            None => true,
+        };
+        if !is_allowed {
+            continue;
+        }
+        if without_rowid
+            && !candidate
+                .index
+                .as_ref()
+                .is_some_and(|index| rhs_table.index_is_covering(index))
+        {
+            continue;
+        }
         let index_info = match candidate.index.as_ref() {
             Some(index) => IndexInfo {
                 unique: index.unique,
@@ -80,8 +126,13 @@ pub fn find_best_access_method_for_join_order<'a>(
         );
         let cost = estimate_cost_for_scan_or_seek(
             Some(index_info),
+            candidate
+                .index
+                .as_ref()
+                .and_then(|index| rhs_constraints.index_stats(index)),
             &rhs_constraints.constraints,
             usable_constraint_refs,
+            rhs_constraints.row_count(),
             input_cardinality,
         );
 
@@ -134,7 +185,8 @@ pub fn find_best_access_method_for_join_order<'a>(
         } else {
             (IterationDirection::Forwards, Cost(0.0))
         };
-        if cost < best_access_method.cost + order_satisfiability_bonus {
+        let is_forced = matches!(rhs_table.index_hint, Some(IndexHint::IndexedBy(_)));
+        if is_forced || cost < best_access_method.cost + order_satisfiability_bonus {
             best_access_method = AccessMethod {
                 cost,
                 index: candidate.index.clone(),
@@ -144,5 +196,22 @@ pub fn find_best_access_method_for_join_order<'a>(
         }
     }
 
+    // The index of INDEXED BY cannot be used, e.g. a partial index whose WHERE clause the query
+    // does not imply.
+    if let (Some(IndexHint::IndexedBy(_)), None) =
+        (&rhs_table.index_hint, &best_access_method.index)
+    {
+        crate::bail_parse_error!("no query solution");
+    }
+
+    if without_rowid && best_access_method.index.is_none() {
+        best_access_method.index = rhs_constraints
+            .candidates
+            .iter()
+            .filter_map(|candidate| candidate.index.as_ref())
+            .find(|index| is_clustered_index(index))
+            .cloned();
+    }
+
     Ok(best_access_method)
 }
@@ -1,13 +1,16 @@
 use std::{cmp::Ordering, collections::HashMap, sync::Arc};
 
 use crate::{
-    schema::{Column, Index},
+    analyze::{IndexStats, TableStats},
+    schema::{Column, Index, Schema, EXPR_COLUMN_POS},
     translate::{
-        expr::as_binary_components,
+        expr::{as_binary_components, sanitize_string},
+        generated::bind_column_names,
         plan::{JoinOrderMember, TableReferences, WhereTerm},
-        planner::{table_mask_from_expr, TableMask},
+        planner::{break_predicate_at_and_boundaries, table_mask_from_expr, TableMask},
     },
-    Result,
+    util::{exprs_are_equivalent, parse_signed_number},
+    Result, Value,
 };
 use turso_sqlite3_parser::ast::{self, SortOrder, TableInternalId};
 
@@ -38,8 +41,12 @@ pub struct Constraint {
     pub where_clause_pos: (usize, BinaryExprSide),
     /// The comparison operator (e.g., `=`, `>`, `<`) used in the constraint.
     pub operator: ast::Operator,
-    /// The zero-based index of the constrained column within the table's schema.
+    /// The zero-based index of the constrained column within the table's schema, or
+    /// [EXPR_COLUMN_POS] if the constrained expression is an expression of an index.
     pub table_col_pos: usize,
+    /// The constrained expression, if it is an expression of an index on expressions rather
+    /// than a column, bound to the table.
+    pub index_expr: Option<ast::Expr>,
     /// A bitmask representing the set of tables that appear on the *constraining* side
     /// of the comparison expression. For example, in SELECT * FROM t1,t2,t3 WHERE t1.x = t2.x + t3.x,
     /// the lhs_mask contains t2 and t3. Thus, this constraint can only be used if t2 and t3
@@ -139,6 +146,24 @@ pub struct TableConstraints {
     pub constraints: Vec<Constraint>,
     /// Candidates for indexes that may use the constraints to perform a lookup.
     pub candidates: Vec<ConstraintUseCandidate>,
+    /// The statistics ANALYZE gathered for the table, if it was analyzed.
+    pub table_stats: Option<Arc<TableStats>>,
+}
+
+impl TableConstraints {
+    /// The number of rows of the table, estimated in lieu of statistics.
+    pub fn row_count(&self) -> f64 {
+        self.table_stats
+            .as_ref()
+            .map_or(ESTIMATED_HARDCODED_ROWS_PER_TABLE as f64, |stats| {
+                stats.row_count.max(1) as f64
+            })
+    }
+
+    /// The statistics of an index of the table, if it was analyzed.
+    pub fn index_stats(&self, index: &Index) -> Option<&IndexStats> {
+        self.table_stats.as_ref()?.indexes.get(&index.name)
+    }
 }
 
 /// In lieu of statistics, we estimate that an equality filter will reduce the output set to 1% of its size.
@@ -150,11 +175,12 @@ const SELECTIVITY_OTHER: f64 = 0.9;
 
 const SELECTIVITY_UNIQUE_EQUALITY: f64 = 1.0 / ESTIMATED_HARDCODED_ROWS_PER_TABLE as f64;
 
-/// Estimate the selectivity of a constraint based on the operator and the column type.
-fn estimate_selectivity(column: &Column, op: ast::Operator) -> f64 {
+/// Estimate the selectivity of a constraint based on the operator and the column type. The
+/// column is None for a constraint on an expression of an index.
+fn estimate_selectivity(column: Option<&Column>, op: ast::Operator) -> f64 {
     match op {
         ast::Operator::Equals => {
-            if column.is_rowid_alias || column.primary_key {
+            if column.is_some_and(|column| column.is_rowid_alias || column.primary_key) {
                 SELECTIVITY_UNIQUE_EQUALITY
             } else {
                 SELECTIVITY_EQ
@@ -178,26 +204,75 @@ pub fn constraints_from_where_clause(
     let mut constraints = Vec::new();
 
     // For each table, collect all the Constraints and all potential index candidates that may use them.
-    for table_reference in table_references.joined_tables() {
+    for (table_idx, table_reference) in table_references.joined_tables().iter().enumerate() {
+        // The WHERE clause is evaluated after the matches of a RIGHT JOIN are known, so it cannot
+        // restrict the rows of the tables on the left side of the join.
+        let is_left_of_right_join = table_references.joined_tables()[table_idx + 1..]
+            .iter()
+            .any(|t| t.join_info.as_ref().is_some_and(|j| j.right));
         let rowid_alias_column = table_reference
             .columns()
             .iter()
             .position(|c| c.is_rowid_alias);
 
+        // A partial index lacks the rows its WHERE clause is not true for, so it can only be
+        // used if the terms that restrict the rows of the table imply that clause. The WHERE
+        // clause does not restrict the rows of the right side of an outer join, only its ON
+        // clause does.
+        let is_outer = table_reference
+            .join_info
+            .as_ref()
+            .is_some_and(|join_info| join_info.outer);
+        let restricting_terms = where_clause
+            .iter()
+            .filter(|term| match term.from_outer_join {
+                Some(outer_join_tbl) => outer_join_tbl == table_reference.internal_id,
+                None => !is_outer && !is_left_of_right_join,
+            })
+            .map(|term| &term.expr)
+            .collect::<Vec<_>>();
+        // The expressions of the indexes on expressions, bound to the table
+        let mut indexes = Vec::new();
+        for index in available_indexes
+            .get(table_reference.table.get_name())
+            .into_iter()
+            .flatten()
+        {
+            let Some(btree) = table_reference.btree() else {
+                indexes.push((index.clone(), vec![]));
+                continue;
+            };
+            if let Some(index_where_clause) = &index.where_clause {
+                let index_where_clause =
+                    bind_column_names(index_where_clause, &btree, table_reference.internal_id);
+                if !terms_imply(&restricting_terms, &index_where_clause) {
+                    continue;
+                }
+            }
+            let exprs = index
+                .columns
+                .iter()
+                .map(|column| {
+                    column
+                        .expr
+                        .as_ref()
+                        .map(|expr| bind_column_names(expr, &btree, table_reference.internal_id))
+                })
+                .collect::<Vec<_>>();
+            indexes.push((index.clone(), exprs));
+        }
+
         let mut cs = TableConstraints {
             table_id: table_reference.internal_id,
             constraints: Vec::new(),
-            candidates: available_indexes
-                .get(table_reference.table.get_name())
-                .map_or(Vec::new(), |indexes| {
-                    indexes
-                        .iter()
-                        .map(|index| ConstraintUseCandidate {
-                            index: Some(index.clone()),
-                            refs: Vec::new(),
-                        })
-                        .collect()
-                }),
+            candidates: indexes
+                .iter()
+                .map(|(index, _)| ConstraintUseCandidate {
+                    index: Some(index.clone()),
+                    refs: Vec::new(),
+                })
+                .collect(),
+            table_stats: None,
         };
         // Add a candidate for the rowid index, which is always available when the table has a rowid alias.
         cs.candidates.push(ConstraintUseCandidate {
@@ -216,6 +291,8 @@ pub fn constraints_from_where_clause(
                 if outer_join_tbl != table_reference.internal_id {
                     continue;
                 }
+            } else if is_left_of_right_join {
+                continue;
             }
 
             // If either the LHS or RHS of the constraint is a column from the table, add the constraint.
@@ -227,8 +304,9 @@ pub fn constraints_from_where_clause(
                             where_clause_pos: (i, BinaryExprSide::Rhs),
                             operator,
                             table_col_pos: *column,
+                            index_expr: None,
                             lhs_mask: table_mask_from_expr(rhs, table_references)?,
-                            selectivity: estimate_selectivity(table_column, operator),
+                            selectivity: estimate_selectivity(Some(table_column), operator),
                         });
                     }
                 }
@@ -243,12 +321,24 @@ pub fn constraints_from_where_clause(
                             where_clause_pos: (i, BinaryExprSide::Rhs),
                             operator,
                             table_col_pos: rowid_alias_column.unwrap(),
+                            index_expr: None,
+                            lhs_mask: table_mask_from_expr(rhs, table_references)?,
+                            selectivity: estimate_selectivity(Some(table_column), operator),
+                        });
+                    }
+                }
+                _ => {
+                    if is_index_expr(&indexes, lhs) {
+                        cs.constraints.push(Constraint {
+                            where_clause_pos: (i, BinaryExprSide::Rhs),
+                            operator,
+                            table_col_pos: EXPR_COLUMN_POS,
+                            index_expr: Some(lhs.clone()),
                             lhs_mask: table_mask_from_expr(rhs, table_references)?,
-                            selectivity: estimate_selectivity(table_column, operator),
+                            selectivity: estimate_selectivity(None, operator),
                         });
                     }
                 }
-                _ => {}
             };
             match rhs {
                 ast::Expr::Column { table, column, .. } => {
@@ -258,8 +348,9 @@ pub fn constraints_from_where_clause(
                             where_clause_pos: (i, BinaryExprSide::Lhs),
                             operator: opposite_cmp_op(operator),
                             table_col_pos: *column,
+                            index_expr: None,
                             lhs_mask: table_mask_from_expr(lhs, table_references)?,
-                            selectivity: estimate_selectivity(table_column, operator),
+                            selectivity: estimate_selectivity(Some(table_column), operator),
                         });
                     }
                 }
@@ -271,12 +362,24 @@ pub fn constraints_from_where_clause(
                             where_clause_pos: (i, BinaryExprSide::Lhs),
                             operator: opposite_cmp_op(operator),
                             table_col_pos: rowid_alias_column.unwrap(),
+                            index_expr: None,
                             lhs_mask: table_mask_from_expr(lhs, table_references)?,
-                            selectivity: estimate_selectivity(table_column, operator),
+                            selectivity: estimate_selectivity(Some(table_column), operator),
+                        });
+                    }
+                }
+                _ => {
+                    if is_index_expr(&indexes, rhs) {
+                        cs.constraints.push(Constraint {
+                            where_clause_pos: (i, BinaryExprSide::Lhs),
+                            operator: opposite_cmp_op(operator),
+                            table_col_pos: EXPR_COLUMN_POS,
+                            index_expr: Some(rhs.clone()),
+                            lhs_mask: table_mask_from_expr(lhs, table_references)?,
+                            selectivity: estimate_selectivity(None, operator),
                         });
                     }
                 }
-                _ => {}
             };
         }
         // sort equalities first so that index keys will be properly constructed.
@@ -311,13 +414,26 @@ pub fn constraints_from_where_clause(
                     sort_order: SortOrder::Asc,
                 });
             }
-            for index in available_indexes
-                .get(table_reference.table.get_name())
-                .unwrap_or(&Vec::new())
-            {
-                if let Some(position_in_index) =
-                    index.column_table_pos_to_index_pos(constraint.table_col_pos)
-                {
+            for (index, exprs) in indexes.iter() {
+                let position_in_index = match &constraint.index_expr {
+                    Some(expr) => exprs
+                        .iter()
+                        .position(|index_expr| {
+                            index_expr
+                                .as_ref()
+                                .is_some_and(|index_expr| exprs_are_equivalent(index_expr, expr))
+                        })
+                        .filter(|pos| index.columns[*pos].collation.is_none()),
+                    // An index column with a collation of its own orders the values differently
+                    // than the comparisons of the column do.
+                    None => index
+                        .column_table_pos_to_index_pos(constraint.table_col_pos)
+                        .filter(|pos| {
+                            index.columns[*pos].collation
+                                == table_reference.columns()[constraint.table_col_pos].collation
+                        }),
+                };
+                if let Some(position_in_index) = position_in_index {
                     let index_candidate = cs
                         .candidates
                         .iter_mut()
@@ -370,6 +486,89 @@ pub fn constraints_from_where_clause(
     Ok(constraints)
 }
 
+/// Replace the estimated selectivities of the constraints on the tables that were analyzed with
+/// estimates from the statistics of their indexes: equalities match the average number of rows
+/// that share a value, and comparisons with a literal are estimated from the samples of the
+/// index, if there are any.
+pub fn apply_table_statistics(
+    constraints: &mut [TableConstraints],
+    where_clause: &[WhereTerm],
+    table_references: &TableReferences,
+    schema: &Schema,
+) {
+    for cs in constraints.iter_mut() {
+        let Some(btree) = table_references
+            .find_joined_table_by_internal_id(cs.table_id)
+            .and_then(|table| table.btree())
+        else {
+            continue;
+        };
+        if !schema.has_btree_table(&btree) {
+            continue;
+        }
+        let Some(table_stats) = schema.get_table_stats(&btree.name) else {
+            continue;
+        };
+        cs.table_stats = Some(table_stats.clone());
+        let row_count = cs.row_count();
+        let indexes = schema.get_indices(&btree.name);
+        for constraint in cs.constraints.iter_mut() {
+            let column = btree.columns.get(constraint.table_col_pos);
+            if column.is_some_and(|column| column.is_rowid_alias) {
+                if constraint.operator == ast::Operator::Equals {
+                    constraint.selectivity = 1.0 / row_count;
+                }
+                continue;
+            }
+            // The statistics of an index on the column or expression tell how its values are
+            // distributed
+            let Some((index, index_stats)) = indexes
+                .iter()
+                .filter(
+                    |index| match (&constraint.index_expr, &index.columns[0].expr) {
+                        (Some(expr), Some(index_expr)) => exprs_are_equivalent(
+                            &bind_column_names(index_expr, &btree, cs.table_id),
+                            expr,
+                        ),
+                        (Some(_), None) => false,
+                        (None, _) => index.columns[0].pos_in_table == constraint.table_col_pos,
+                    },
+                )
+                .find_map(|index| Some((index, table_stats.indexes.get(&index.name)?)))
+            else {
+                continue;
+            };
+            let value = literal_value(&constraint.get_constraining_expr(where_clause));
+            let lt_eq = value
+                .and_then(|value| index_stats.estimate_rows_lt_eq(&value, index.columns[0].order));
+            let rows = match (constraint.operator, lt_eq) {
+                (ast::Operator::Equals, Some((_, eq))) => eq,
+                (ast::Operator::Equals, None) => match index_stats.avg_eq.first() {
+                    Some(&avg_eq) => avg_eq as f64,
+                    None => continue,
+                },
+                (ast::Operator::Less, Some((lt, _))) => lt,
+                (ast::Operator::LessEquals, Some((lt, eq))) => lt + eq,
+                (ast::Operator::Greater, Some((lt, eq))) => row_count - lt - eq,
+                (ast::Operator::GreaterEquals, Some((lt, _))) => row_count - lt,
+                _ => continue,
+            };
+            constraint.selectivity = rows.clamp(1.0, row_count) / row_count;
+        }
+    }
+}
+
+/// The value of a literal constraining expression, used to look it up in the samples of an index.
+fn literal_value(expr: &ast::Expr) -> Option<Value> {
+    match expr {
+        ast::Expr::Literal(ast::Literal::String(s)) => Some(Value::build_text(sanitize_string(s))),
+        ast::Expr::Literal(ast::Literal::Numeric(_)) | ast::Expr::Unary(..) => {
+            parse_signed_number(expr).ok()
+        }
+        _ => None,
+    }
+}
+
 /// Find which [Constraint]s are usable for a given join order.
 /// Returns a slice of the references to the constraints that are usable.
 /// A constraint is considered usable for a given table if all of the other tables referenced by the constraint
@@ -402,6 +601,56 @@ pub fn usable_constraints_for_join_order<'a>(
     &refs[..usable_until]
 }
 
+/// Whether `expr` is one of the expressions of the indexes on expressions in `indexes`.
+fn is_index_expr(indexes: &[(Arc<Index>, Vec<Option<ast::Expr>>)], expr: &ast::Expr) -> bool {
+    indexes
+        .iter()
+        .flat_map(|(_, exprs)| exprs.iter().flatten())
+        .any(|index_expr| exprs_are_equivalent(index_expr, expr))
+}
+
+/// Whether `terms`, conditions known to be true, imply `expr`, the WHERE clause of a partial
+/// index. Like in SQLite, each term of `expr` separated by AND must be one of `terms`, or be
+/// `x IS NOT NULL` where one of `terms` compares `x` with a comparison operator, which can only
+/// be true if `x` is not NULL.
+pub fn terms_imply(terms: &[&ast::Expr], expr: &ast::Expr) -> bool {
+    let mut conjuncts = Vec::new();
+    break_predicate_at_and_boundaries(expr.clone(), &mut conjuncts);
+    conjuncts.iter().all(|conjunct| {
+        let not_null_operand = match conjunct {
+            ast::Expr::NotNull(operand) => Some(operand.as_ref()),
+            ast::Expr::Binary(operand, ast::Operator::IsNot, null)
+                if matches!(null.as_ref(), ast::Expr::Literal(ast::Literal::Null)) =>
+            {
+                Some(operand.as_ref())
+            }
+            _ => None,
+        };
+        terms.iter().any(|term| {
+            if exprs_are_equivalent(term, conjunct) {
+                return true;
+            }
+            let Some(operand) = not_null_operand else {
+                return false;
+            };
+            match term {
+                ast::Expr::Binary(lhs, op, rhs) => {
+                    matches!(
+                        op,
+                        ast::Operator::Equals
+                            | ast::Operator::NotEquals
+                            | ast::Operator::Less
+                            | ast::Operator::LessEquals
+                            | ast::Operator::Greater
+                            | ast::Operator::GreaterEquals
+                    ) && (exprs_are_equivalent(lhs, operand) || exprs_are_equivalent(rhs, operand))
+                }
+                _ => false,
+            }
+        })
+    })
+}
+
 fn opposite_cmp_op(op: ast::Operator) -> ast::Operator {
     match op {
         ast::Operator::Equals => ast::Operator::Equals,
@@ -1,3 +1,7 @@
+use turso_sqlite3_parser::ast;
+
+use crate::analyze::IndexStats;
+
 use super::constraints::{Constraint, ConstraintRef};
 
 /// A simple newtype wrapper over a f64 that represents the cost of an operation.
@@ -40,19 +44,34 @@ pub fn estimate_page_io_cost(rowcount: f64) -> Cost {
 ///
 /// This is a very simple model that estimates the number of pages read
 /// based on the number of rows read, ignoring any CPU costs.
+/// `row_count` is the number of rows of the table, and `index_stats` the statistics of the index,
+/// if it was analyzed, which estimate how many rows share the values of several leading columns
+/// better than the product of the selectivities of the columns.
 pub fn estimate_cost_for_scan_or_seek(
     index_info: Option<IndexInfo>,
+    index_stats: Option<&IndexStats>,
     constraints: &[Constraint],
     usable_constraint_refs: &[ConstraintRef],
+    row_count: f64,
     input_cardinality: f64,
 ) -> Cost {
     let Some(index_info) = index_info else {
-        return estimate_page_io_cost(
-            input_cardinality * ESTIMATED_HARDCODED_ROWS_PER_TABLE as f64,
-        );
+        return estimate_page_io_cost(input_cardinality * row_count);
+    };
+
+    let num_equalities = usable_constraint_refs
+        .iter()
+        .take_while(|cref| constraints[cref.constraint_vec_pos].operator == ast::Operator::Equals)
+        .count();
+    let avg_eq = index_stats
+        .filter(|_| num_equalities >= 2)
+        .and_then(|stats| stats.avg_eq.get(num_equalities - 1));
+    let (rows, remaining_refs) = match avg_eq {
+        Some(&avg_eq) => (avg_eq as f64, &usable_constraint_refs[num_equalities..]),
+        None => (row_count, usable_constraint_refs),
     };
 
-    let selectivity_multiplier: f64 = usable_constraint_refs
+    let selectivity_multiplier: f64 = remaining_refs
         .iter()
         .map(|cref| {
             let constraint = &constraints[cref.constraint_vec_pos];
@@ -63,10 +82,5 @@ pub fn estimate_cost_for_scan_or_seek(
     // little cheeky bonus for covering indexes
     let covering_multiplier = if index_info.covering { 0.9 } else { 1.0 };
 
-    estimate_page_io_cost(
-        selectivity_multiplier
-            * ESTIMATED_HARDCODED_ROWS_PER_TABLE as f64
-            * input_cardinality
-            * covering_multiplier,
-    )
+    estimate_page_io_cost(selectivity_multiplier * rows * input_cardinality * covering_multiplier)
 }
@@ -14,7 +14,6 @@ use crate::{
 use super::{
     access_method::{find_best_access_method_for_join_order, AccessMethod},
     constraints::TableConstraints,
-    cost::ESTIMATED_HARDCODED_ROWS_PER_TABLE,
     order::OrderTarget,
 };
 
@@ -94,10 +93,9 @@ pub fn join_lhs_and_rhs<'a>(
     // Produce a number of rows estimated to be returned when this table is filtered by the WHERE clause.
     // If this table is the rightmost table in the join order, we multiply by the input cardinality,
     // which is the output cardinality of the previous tables.
-    let output_cardinality = (input_cardinality as f64
-        * ESTIMATED_HARDCODED_ROWS_PER_TABLE as f64
-        * output_cardinality_multiplier)
-        .ceil() as usize;
+    let output_cardinality =
+        (input_cardinality as f64 * rhs_constraints.row_count() * output_cardinality_multiplier)
+            .ceil() as usize;
 
     Ok(Some(JoinN {
         data: best_access_methods,
@@ -168,6 +166,7 @@ pub fn compute_best_join_order<'a>(
         table_id: TableInternalId::default(),
         original_idx: 0,
         is_outer: false,
+        is_right: false,
     });
 
     // Keep track of the current best cost so we can short-circuit planning for subplans
@@ -193,6 +192,7 @@ pub fn compute_best_join_order<'a>(
             table_id: table_ref.internal_id,
             original_idx: i,
             is_outer: false,
+            is_right: false,
         };
         assert!(join_order.len() == 1);
         let rel = join_lhs_and_rhs(
@@ -214,20 +214,21 @@ pub fn compute_best_join_order<'a>(
     // Example:
     // "a LEFT JOIN b" can NOT be reordered as "b LEFT JOIN a".
     // If there are outer joins in the plan, ensure correct ordering.
+    // The unmatched rows of the right table of a RIGHT JOIN are joined with the tables after it
+    // by running their loops again, so those tables must also stay after it.
     let left_join_illegal_map = {
-        let left_join_count = joined_tables
-            .iter()
-            .filter(|t| t.join_info.as_ref().is_some_and(|j| j.outer))
-            .count();
+        let is_outer = |t: &JoinedTable| t.join_info.as_ref().is_some_and(|j| j.outer || j.right);
+        let is_right = |t: &JoinedTable| t.join_info.as_ref().is_some_and(|j| j.right);
+        let left_join_count = joined_tables.iter().filter(|t| is_outer(t)).count();
         if left_join_count == 0 {
             None
         } else {
             // map from rhs table index to lhs table index
             let mut left_join_illegal_map: HashMap<usize, TableMask> =
                 HashMap::with_capacity(left_join_count);
-            for (i, _) in joined_tables.iter().enumerate() {
+            for (i, table) in joined_tables.iter().enumerate() {
                 for (j, joined_table) in joined_tables.iter().enumerate().skip(i + 1) {
-                    if joined_table.join_info.as_ref().is_some_and(|j| j.outer) {
+                    if is_outer(joined_table) || is_right(table) {
                         // bitwise OR the masks
                         if let Some(illegal_lhs) = left_join_illegal_map.get_mut(&i) {
                             illegal_lhs.add_table(j);
@@ -297,6 +298,10 @@ pub fn compute_best_join_order<'a>(
                             .join_info
                             .as_ref()
                             .is_some_and(|j| j.outer),
+                        is_right: joined_tables[table_no]
+                            .join_info
+                            .as_ref()
+                            .is_some_and(|j| j.right),
                     });
                 }
                 join_order.push(JoinOrderMember {
@@ -306,6 +311,10 @@ pub fn compute_best_join_order<'a>(
                         .join_info
                         .as_ref()
                         .is_some_and(|j| j.outer),
+                    is_right: joined_tables[rhs_idx]
+                        .join_info
+                        .as_ref()
+                        .is_some_and(|j| j.right),
                 });
                 assert!(join_order.len() == subset_size);
 
@@ -407,6 +416,7 @@ pub fn compute_naive_left_deep_plan<'a>(
             table_id: t.internal_id,
             original_idx: i,
             is_outer: t.join_info.as_ref().is_some_and(|j| j.outer),
+            is_right: t.join_info.as_ref().is_some_and(|j| j.right),
         })
         .collect::<Vec<_>>();
 
@@ -654,11 +664,14 @@ mod tests {
                 pos_in_table: 0,
                 collation: None,
                 default: None,
+                expr: None,
             }],
             unique: true,
             ephemeral: false,
             root_page: 1,
             has_rowid: true,
+            num_extra_columns: 0,
+            where_clause: None,
         });
         available_indexes.insert("test_table".to_string(), vec![index]);
 
@@ -702,6 +715,7 @@ mod tests {
                 t2.clone(),
                 Some(JoinInfo {
                     outer: false,
+                    right: false,
                     using: None,
                 }),
                 table_id_counter.next(),
@@ -722,11 +736,14 @@ mod tests {
                 pos_in_table: 0,
                 collation: None,
                 default: None,
+                expr: None,
             }],
             unique: true,
             ephemeral: false,
             root_page: 1,
             has_rowid: true,
+            num_extra_columns: 0,
+            where_clause: None,
         });
         available_indexes.insert("table1".to_string(), vec![index1]);
 
@@ -805,6 +822,7 @@ mod tests {
                 table_customers.clone(),
                 Some(JoinInfo {
                     outer: false,
+                    right: false,
                     using: None,
                 }),
                 table_id_counter.next(),
@@ -813,6 +831,7 @@ mod tests {
                 table_order_items.clone(),
                 Some(JoinInfo {
                     outer: false,
+                    right: false,
                     using: None,
                 }),
                 table_id_counter.next(),
@@ -838,11 +857,14 @@ mod tests {
                         pos_in_table: 0,
                         collation: None,
                         default: None,
+                        expr: None,
                     }],
                     unique: true,
                     ephemeral: false,
                     root_page: 1,
                     has_rowid: true,
+                    num_extra_columns: 0,
+                    where_clause: None,
                 });
                 available_indexes.insert(table_name.to_string(), vec![index]);
             });
@@ -855,11 +877,14 @@ mod tests {
                 pos_in_table: 1,
                 collation: None,
                 default: None,
+                expr: None,
             }],
             unique: false,
             ephemeral: false,
             root_page: 1,
             has_rowid: true,
+            num_extra_columns: 0,
+            where_clause: None,
         });
         let order_id_idx = Arc::new(Index {
             name: "order_items_order_id_idx".to_string(),
@@ -870,11 +895,14 @@ mod tests {
                 pos_in_table: 1,
                 collation: None,
                 default: None,
+                expr: None,
             }],
             unique: false,
             ephemeral: false,
             root_page: 1,
             has_rowid: true,
+            num_extra_columns: 0,
+            where_clause: None,
         });
 
         available_indexes
@@ -989,6 +1017,7 @@ mod tests {
                 t2.clone(),
                 Some(JoinInfo {
                     outer: false,
+                    right: false,
                     using: None,
                 }),
                 table_id_counter.next(),
@@ -997,6 +1026,7 @@ mod tests {
                 t3.clone(),
                 Some(JoinInfo {
                     outer: false,
+                    right: false,
                     using: None,
                 }),
                 table_id_counter.next(),
@@ -1092,6 +1122,7 @@ mod tests {
                     t.clone(),
                     Some(JoinInfo {
                         outer: false,
+                        right: false,
                         using: None,
                     }),
                     table_id_counter.next(),
@@ -1101,6 +1132,7 @@ mod tests {
                 fact_table.clone(),
                 Some(JoinInfo {
                     outer: false,
+                    right: false,
                     using: None,
                 }),
                 table_id_counter.next(),
@@ -1282,6 +1314,7 @@ mod tests {
                     pos_in_table: 0,
                     collation: None,
                     default: None,
+                    expr: None,
                 },
                 IndexColumn {
                     name: "y".to_string(),
@@ -1289,12 +1322,15 @@ mod tests {
                     pos_in_table: 1,
                     collation: None,
                     default: None,
+                    expr: None,
                 },
             ],
             unique: false,
             root_page: 2,
             ephemeral: false,
             has_rowid: true,
+            num_extra_columns: 0,
+            where_clause: None,
         });
 
         let mut available_indexes = HashMap::new();
@@ -1311,6 +1347,7 @@ mod tests {
             join_info: None,
             col_used_mask: ColumnUsedMask::default(),
             database_id: 0,
+            index_hint: None,
         });
 
         // Create where clause that only references second column
@@ -1369,6 +1406,7 @@ mod tests {
                     pos_in_table: 0,
                     collation: None,
                     default: None,
+                    expr: None,
                 },
                 IndexColumn {
                     name: "c2".to_string(),
@@ -1376,6 +1414,7 @@ mod tests {
                     pos_in_table: 1,
                     collation: None,
                     default: None,
+                    expr: None,
                 },
                 IndexColumn {
                     name: "c3".to_string(),
@@ -1383,12 +1422,15 @@ mod tests {
                     pos_in_table: 2,
                     collation: None,
                     default: None,
+                    expr: None,
                 },
             ],
             unique: false,
             root_page: 2,
             ephemeral: false,
             has_rowid: true,
+            num_extra_columns: 0,
+            where_clause: None,
         });
         available_indexes.insert("t1".to_string(), vec![index]);
 
@@ -1403,6 +1445,7 @@ mod tests {
             join_info: None,
             col_used_mask: ColumnUsedMask::default(),
             database_id: 0,
+            index_hint: None,
         });
 
         // Create where clause that references first and third columns
@@ -1486,6 +1529,7 @@ mod tests {
                     pos_in_table: 0,
                     collation: None,
                     default: None,
+                    expr: None,
                 },
                 IndexColumn {
                     name: "c2".to_string(),
@@ -1493,6 +1537,7 @@ mod tests {
                     pos_in_table: 1,
                     collation: None,
                     default: None,
+                    expr: None,
                 },
                 IndexColumn {
                     name: "c3".to_string(),
@@ -1500,12 +1545,15 @@ mod tests {
                     pos_in_table: 2,
                     collation: None,
                     default: None,
+                    expr: None,
                 },
             ],
             root_page: 2,
             ephemeral: false,
             has_rowid: true,
             unique: false,
+            num_extra_columns: 0,
+            where_clause: None,
         });
         available_indexes.insert("t1".to_string(), vec![index]);
 
@@ -1520,6 +1568,7 @@ mod tests {
             join_info: None,
             col_used_mask: ColumnUsedMask::default(),
             database_id: 0,
+            index_hint: None,
         });
 
         // Create where clause: c1 = 5 AND c2 > 10 AND c3 = 7
@@ -1614,6 +1663,7 @@ mod tests {
             unique: false,
             collation: None,
             hidden: false,
+            generated: None,
         }
     }
     fn _create_column_of_type(name: &str, ty: Type) -> Column {
@@ -1649,6 +1699,10 @@ mod tests {
             has_rowid: true,
             is_strict: false,
             unique_sets: None,
+            conflict_clauses: vec![],
+            foreign_keys: vec![],
+            checks: vec![],
+            notnull_conflict_clauses: vec![],
         })
     }
 
@@ -1670,6 +1724,7 @@ mod tests {
             join_info,
             col_used_mask: ColumnUsedMask::default(),
             database_id: 0,
+            index_hint: None,
         }
     }
 
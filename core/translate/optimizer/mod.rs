@@ -1,7 +1,8 @@
 use std::{cell::RefCell, cmp::Ordering, collections::HashMap, sync::Arc};
 
 use constraints::{
-    constraints_from_where_clause, usable_constraints_for_join_order, Constraint, ConstraintRef,
+    apply_table_statistics, constraints_from_where_clause, usable_constraints_for_join_order,
+    Constraint, ConstraintRef,
 };
 use cost::Cost;
 use join::{compute_best_join_order, BestJoinOrderResult};
@@ -11,7 +12,7 @@ use turso_sqlite3_parser::ast::{self, fmt::ToTokens as _, Expr, SortOrder};
 
 use crate::{
     parameters::PARAM_PREFIX,
-    schema::{Index, IndexColumn, Schema, Table},
+    schema::{CteTable, Index, IndexColumn, Schema, Table},
     translate::{expr::is_double_quoted_identifier, expr::walk_expr_mut, plan::TerminationKey},
     types::SeekOp,
     Result,
@@ -20,8 +21,9 @@ use crate::{
 use super::{
     emitter::Resolver,
     plan::{
-        DeletePlan, GroupBy, IterationDirection, JoinOrderMember, JoinedTable, Operation, Plan,
-        Search, SeekDef, SeekKey, SelectPlan, TableReferences, UpdatePlan, WhereTerm,
+        CtePlan, DeletePlan, GroupBy, IterationDirection, JoinOrderMember, JoinedTable,
+        NonFromClauseSubquery, Operation, Plan, Search, SeekDef, SeekKey, SelectPlan,
+        TableReferences, UpdatePlan, WhereTerm,
     },
 };
 
@@ -62,8 +64,12 @@ pub fn optimize_plan(plan: &mut Plan, schema: &Schema) -> Result<()> {
  * but having them separate makes them easier to understand
  */
 pub fn optimize_select_plan(plan: &mut SelectPlan, schema: &Schema) -> Result<()> {
+    scan_tables_without_rowid_through_clustered_index(&mut plan.table_references, schema);
     optimize_subqueries(plan, schema)?;
     rewrite_exprs_select(plan)?;
+    for subquery in plan.non_from_clause_subqueries.iter_mut() {
+        optimize_plan(&mut subquery.plan, schema)?;
+    }
     if let ConstantConditionEliminationResult::ImpossibleCondition =
         eliminate_constant_conditions(&mut plan.where_clause)?
     {
@@ -87,8 +93,12 @@ pub fn optimize_select_plan(plan: &mut SelectPlan, schema: &Schema) -> Result<()
     Ok(())
 }
 
-fn optimize_delete_plan(plan: &mut DeletePlan, _schema: &Schema) -> Result<()> {
+fn optimize_delete_plan(plan: &mut DeletePlan, schema: &Schema) -> Result<()> {
+    scan_tables_without_rowid_through_clustered_index(&mut plan.table_references, schema);
     rewrite_exprs_delete(plan)?;
+    for subquery in plan.non_from_clause_subqueries.iter_mut() {
+        optimize_plan(&mut subquery.plan, schema)?;
+    }
     if let ConstantConditionEliminationResult::ImpossibleCondition =
         eliminate_constant_conditions(&mut plan.where_clause)?
     {
@@ -110,17 +120,37 @@ fn optimize_delete_plan(plan: &mut DeletePlan, _schema: &Schema) -> Result<()> {
 }
 
 fn optimize_update_plan(plan: &mut UpdatePlan, schema: &Schema) -> Result<()> {
+    scan_tables_without_rowid_through_clustered_index(&mut plan.table_references, schema);
     rewrite_exprs_update(plan)?;
+    for subquery in plan.non_from_clause_subqueries.iter_mut() {
+        optimize_plan(&mut subquery.plan, schema)?;
+    }
     if let ConstantConditionEliminationResult::ImpossibleCondition =
         eliminate_constant_conditions(&mut plan.where_clause)?
     {
         plan.contains_constant_false_condition = true;
         return Ok(());
     }
+    // The rows to update are read from the ephemeral table, which the table is then
+    // positioned on by rowid.
+    if plan.ephemeral_plan.is_some() {
+        return Ok(());
+    }
+    // A table without rowid is updated in place through its clustered index.
+    let table = plan.table_references.joined_tables()[0].btree();
+    let clustered_index = table.and_then(|table| schema.get_clustered_index(&table).cloned());
+    let clustered_index_only;
+    let available_indexes = match clustered_index {
+        Some(index) => {
+            clustered_index_only = HashMap::from([(index.table_name.clone(), vec![index])]);
+            &clustered_index_only
+        }
+        None => &schema.indexes,
+    };
     let _ = optimize_table_access(
         schema,
         &mut plan.table_references,
-        &schema.indexes,
+        available_indexes,
         &mut plan.where_clause,
         &mut plan.order_by,
         &mut None,
@@ -128,16 +158,58 @@ fn optimize_update_plan(plan: &mut UpdatePlan, schema: &Schema) -> Result<()> {
     Ok(())
 }
 
+/// Tables without rowid are stored in their clustered index, so a full scan of such a table
+/// iterates that index.
+fn scan_tables_without_rowid_through_clustered_index(
+    table_references: &mut TableReferences,
+    schema: &Schema,
+) {
+    for table in table_references.joined_tables_mut() {
+        let Table::BTree(btree) = &table.table else {
+            continue;
+        };
+        if !schema.has_btree_table(btree) {
+            continue;
+        }
+        if let Operation::Scan {
+            index: index @ None,
+            ..
+        } = &mut table.op
+        {
+            *index = schema.get_clustered_index(btree).cloned();
+        }
+    }
+}
+
 fn optimize_subqueries(plan: &mut SelectPlan, schema: &Schema) -> Result<()> {
     for table in plan.table_references.joined_tables_mut() {
-        if let Table::FromClauseSubquery(from_clause_subquery) = &mut table.table {
-            optimize_select_plan(&mut from_clause_subquery.plan, schema)?;
+        match &mut table.table {
+            Table::FromClauseSubquery(from_clause_subquery) => {
+                optimize_select_plan(&mut from_clause_subquery.plan, schema)?;
+            }
+            // Materialized CTEs are optimized with the query that declares them.
+            Table::Cte(cte) if !cte.materialized => optimize_cte(cte, schema)?,
+            _ => {}
         }
     }
+    for cte in &plan.materialized_ctes {
+        optimize_cte(cte, schema)?;
+    }
 
     Ok(())
 }
 
+fn optimize_cte(cte: &CteTable, schema: &Schema) -> Result<()> {
+    match cte.plan.borrow_mut().as_mut() {
+        Some(CtePlan::Select(plan)) => optimize_plan(plan, schema),
+        Some(CtePlan::Recursive(recursive)) => {
+            optimize_plan(&mut recursive.setup, schema)?;
+            optimize_select_plan(&mut recursive.step, schema)
+        }
+        None => Ok(()),
+    }
+}
+
 /// Optimize the join order and index selection for a query.
 ///
 /// This function does the following:
@@ -158,9 +230,49 @@ fn optimize_table_access(
     group_by: &mut Option<GroupBy>,
 ) -> Result<Option<Vec<JoinOrderMember>>> {
     let access_methods_arena = RefCell::new(Vec::new());
-    let maybe_order_target = compute_order_target(order_by, group_by.as_mut());
-    let constraints_per_table =
+    // The unmatched rows of a RIGHT JOIN are emitted after the main loop, so the order of the
+    // loops never provides the order of the result. The tables on the left side of the join are
+    // then set to NULL rows, which is not possible for an ephemeral index that was never built.
+    let has_right_join = table_references
+        .joined_tables()
+        .iter()
+        .any(|t| t.join_info.as_ref().is_some_and(|j| j.right));
+    let maybe_order_target = if has_right_join {
+        None
+    } else {
+        compute_order_target(order_by, group_by.as_mut())
+    };
+    // The indexes of the schema are not those of the tables of other databases, which are
+    // accessed through their clustered index if they have one, set by the planner.
+    let other_database_tables = table_references
+        .joined_tables()
+        .iter()
+        .filter_map(|table| {
+            let btree = table
+                .btree()
+                .filter(|btree| !schema.has_btree_table(btree))?;
+            Some((btree.name.clone(), table.op.index().cloned()))
+        })
+        .collect::<Vec<_>>();
+    let other_database_indexes;
+    let available_indexes = if other_database_tables.is_empty() {
+        available_indexes
+    } else {
+        let mut indexes = available_indexes.clone();
+        for (table_name, clustered_index) in other_database_tables {
+            indexes.insert(table_name, clustered_index.into_iter().collect());
+        }
+        other_database_indexes = indexes;
+        &other_database_indexes
+    };
+    let mut constraints_per_table =
         constraints_from_where_clause(where_clause, table_references, available_indexes)?;
+    apply_table_statistics(
+        &mut constraints_per_table,
+        where_clause,
+        table_references,
+        schema,
+    );
     let Some(best_join_order_result) = compute_best_join_order(
         table_references.joined_tables_mut(),
         maybe_order_target.as_ref(),
@@ -233,6 +345,10 @@ fn optimize_table_access(
                 .join_info
                 .as_ref()
                 .is_some_and(|join_info| join_info.outer),
+            is_right: joined_tables[table_number]
+                .join_info
+                .as_ref()
+                .is_some_and(|join_info| join_info.right),
         })
         .collect();
 
@@ -241,14 +357,19 @@ fn optimize_table_access(
         let table_idx = join_order_member.original_idx;
         let access_method = &access_methods_arena.borrow()[best_access_methods[i]];
         if access_method.is_scan() {
-            let try_to_build_ephemeral_index = if schema.indexes_enabled() {
+            let try_to_build_ephemeral_index = if schema.indexes_enabled() && !has_right_join {
                 let is_leftmost_table = i == 0;
                 let uses_index = access_method.index.is_some();
                 let source_table_does_not_support_search = matches!(
                     &joined_tables[table_idx].table,
-                    Table::FromClauseSubquery(_) | Table::Virtual(_)
+                    Table::FromClauseSubquery(_) | Table::Virtual(_) | Table::Cte(_)
                 );
-                !is_leftmost_table && !uses_index && !source_table_does_not_support_search
+                // The access to a table with INDEXED BY or NOT INDEXED is not up to the optimizer
+                let has_index_hint = joined_tables[table_idx].index_hint.is_some();
+                !is_leftmost_table
+                    && !uses_index
+                    && !source_table_does_not_support_search
+                    && !has_index_hint
             } else {
                 false
             };
@@ -272,7 +393,9 @@ fn optimize_table_access(
                 };
                 continue;
             };
+            // An ephemeral index only has columns of the table
             let temp_constraint_refs = (0..table_constraints.constraints.len())
+                .filter(|i| table_constraints.constraints[*i].index_expr.is_none())
                 .map(|i| ConstraintRef {
                     constraint_vec_pos: i,
                     index_col_pos: table_constraints.constraints[i].table_col_pos,
@@ -399,24 +522,35 @@ fn eliminate_constant_conditions(
 
 fn rewrite_exprs_select(plan: &mut SelectPlan) -> Result<()> {
     let mut param_count = 1;
+    rewrite_exprs_select_from(plan, &mut param_count)
+}
+
+/// Rewrites the expressions of `plan`, numbering its anonymous parameters from `param_count`.
+fn rewrite_exprs_select_from(plan: &mut SelectPlan, param_count: &mut usize) -> Result<()> {
+    let subqueries = &mut plan.non_from_clause_subqueries;
     for rc in plan.result_columns.iter_mut() {
-        rewrite_expr(&mut rc.expr, &mut param_count)?;
+        rewrite_expr_and_subqueries(&mut rc.expr, param_count, subqueries)?;
     }
     for agg in plan.aggregates.iter_mut() {
-        rewrite_expr(&mut agg.original_expr, &mut param_count)?;
+        rewrite_expr_and_subqueries(&mut agg.original_expr, param_count, subqueries)?;
     }
     lift_common_subexpressions_from_binary_or_terms(&mut plan.where_clause)?;
     for cond in plan.where_clause.iter_mut() {
-        rewrite_expr(&mut cond.expr, &mut param_count)?;
+        rewrite_expr_and_subqueries(&mut cond.expr, param_count, subqueries)?;
     }
     if let Some(group_by) = &mut plan.group_by {
         for expr in group_by.exprs.iter_mut() {
-            rewrite_expr(expr, &mut param_count)?;
+            rewrite_expr_and_subqueries(expr, param_count, subqueries)?;
+        }
+        if let Some(having) = &mut group_by.having {
+            for expr in having.iter_mut() {
+                rewrite_expr_and_subqueries(expr, param_count, subqueries)?;
+            }
         }
     }
     if let Some(order_by) = &mut plan.order_by {
         for (expr, _) in order_by.iter_mut() {
-            rewrite_expr(expr, &mut param_count)?;
+            rewrite_expr_and_subqueries(expr, param_count, subqueries)?;
         }
     }
 
@@ -426,18 +560,23 @@ fn rewrite_exprs_select(plan: &mut SelectPlan) -> Result<()> {
 fn rewrite_exprs_delete(plan: &mut DeletePlan) -> Result<()> {
     let mut param_idx = 1;
     for cond in plan.where_clause.iter_mut() {
-        rewrite_expr(&mut cond.expr, &mut param_idx)?;
+        rewrite_expr_and_subqueries(
+            &mut cond.expr,
+            &mut param_idx,
+            &mut plan.non_from_clause_subqueries,
+        )?;
     }
     Ok(())
 }
 
 fn rewrite_exprs_update(plan: &mut UpdatePlan) -> Result<()> {
     let mut param_idx = 1;
+    let subqueries = &mut plan.non_from_clause_subqueries;
     for (_, expr) in plan.set_clauses.iter_mut() {
-        rewrite_expr(expr, &mut param_idx)?;
+        rewrite_expr_and_subqueries(expr, &mut param_idx, subqueries)?;
     }
     for cond in plan.where_clause.iter_mut() {
-        rewrite_expr(&mut cond.expr, &mut param_idx)?;
+        rewrite_expr_and_subqueries(&mut cond.expr, &mut param_idx, subqueries)?;
     }
     if let Some(order_by) = &mut plan.order_by {
         for (expr, _) in order_by.iter_mut() {
@@ -522,7 +661,9 @@ impl Optimizable for ast::Expr {
                     return true;
                 }
 
-                let table_ref = tables.find_joined_table_by_internal_id(*table).unwrap();
+                let Some(table_ref) = tables.find_table_by_internal_id(*table) else {
+                    return false;
+                };
                 let columns = table_ref.columns();
                 let column = &columns[*column];
                 column.primary_key || column.notnull
@@ -556,6 +697,7 @@ impl Optimizable for ast::Expr {
             }
             Expr::Raise(..) => false,
             Expr::Subquery(..) => false,
+            Expr::SubqueryResult { .. } => false,
             Expr::Unary(_, expr) => expr.is_nonnull(tables),
             Expr::Variable(..) => false,
         }
@@ -638,8 +780,9 @@ impl Optimizable for ast::Expr {
             Expr::Qualified(_, _) => {
                 panic!("Qualified should have been rewritten as Column")
             }
-            Expr::Raise(_, expr) => expr.as_ref().is_none_or(|expr| expr.is_constant(resolver)),
+            Expr::Raise(..) => false,
             Expr::Subquery(_) => false,
+            Expr::SubqueryResult { .. } => false,
             Expr::Unary(_, expr) => expr.is_constant(resolver),
             Expr::Variable(_) => false,
         }
@@ -778,6 +921,7 @@ fn ephemeral_index_build(
             pos_in_table: i,
             collation: c.collation,
             default: c.default.clone(),
+            expr: None,
         })
         // only include columns that are used in the query
         .filter(|c| table_reference.column_is_used(c.pos_in_table))
@@ -814,6 +958,8 @@ fn ephemeral_index_build(
             .table
             .btree()
             .is_some_and(|btree| btree.has_rowid),
+        num_extra_columns: 0,
+        where_clause: None,
     };
 
     ephemeral_index
@@ -1303,8 +1449,44 @@ fn build_seek_def(
 }
 
 pub fn rewrite_expr(top_level_expr: &mut ast::Expr, param_idx: &mut usize) -> Result<()> {
+    rewrite_expr_and_subqueries(top_level_expr, param_idx, &mut [])
+}
+
+/// Like [rewrite_expr], but also rewrites the expressions of the `subqueries` that
+/// `top_level_expr` uses, so that their anonymous parameters are numbered in the order they come
+/// in the statement.
+fn rewrite_expr_and_subqueries(
+    top_level_expr: &mut ast::Expr,
+    param_idx: &mut usize,
+    subqueries: &mut [NonFromClauseSubquery],
+) -> Result<()> {
     walk_expr_mut(top_level_expr, &mut |expr: &mut ast::Expr| -> Result<()> {
         match expr {
+            ast::Expr::SubqueryResult {
+                subquery_id, lhs, ..
+            } => {
+                // The left-hand side of `x IN (SELECT ...)` comes before the subquery.
+                if let Some(lhs) = lhs {
+                    rewrite_expr_and_subqueries(lhs, param_idx, subqueries)?;
+                }
+                if let Some(subquery) = subqueries
+                    .iter_mut()
+                    .find(|subquery| subquery.internal_id == *subquery_id)
+                {
+                    match subquery.plan.as_mut() {
+                        Plan::Select(plan) => rewrite_exprs_select_from(plan, param_idx)?,
+                        Plan::CompoundSelect {
+                            left, right_most, ..
+                        } => {
+                            for (plan, _) in left.iter_mut() {
+                                rewrite_exprs_select_from(plan, param_idx)?;
+                            }
+                            rewrite_exprs_select_from(right_most, param_idx)?;
+                        }
+                        Plan::Delete(_) | Plan::Update(_) => unreachable!(),
+                    }
+                }
+            }
             ast::Expr::Id(id) => {
                 // Convert "true" and "false" to 1 and 0
                 if id.as_str().eq_ignore_ascii_case("true") {
@@ -1,10 +1,16 @@
-use std::{cell::Cell, cmp::Ordering, rc::Rc, sync::Arc};
+use std::{
+    cell::{Cell, OnceCell},
+    cmp::Ordering,
+    rc::Rc,
+    sync::Arc,
+};
 use turso_ext::{ConstraintInfo, ConstraintOp};
 use turso_sqlite3_parser::ast::{self, SortOrder};
 
+use crate::translate::collate::CollationSeq;
 use crate::{
-    function::AggFunc,
-    schema::{BTreeTable, Column, FromClauseSubquery, Index, Table},
+    function::{AggFunc, WindowFunc},
+    schema::{BTreeTable, Column, CteRows, CteTable, FromClauseSubquery, Index, Table},
     vdbe::{
         builder::{CursorKey, CursorType, ProgramBuilder},
         insn::{IdxInsertFlags, Insn},
@@ -16,7 +22,10 @@ use crate::{schema::Type, types::SeekOp, util::can_pushdown_predicate};
 
 use turso_sqlite3_parser::ast::TableInternalId;
 
-use super::{emitter::OperationMode, planner::determine_where_to_eval_term};
+use super::{
+    check::CheckConstraints, emitter::OperationMode, fkey::ForeignKeyChecks,
+    planner::determine_where_to_eval_term, trigger::TableTriggers,
+};
 
 #[derive(Debug, Clone)]
 pub struct ResultSetColumn {
@@ -298,7 +307,71 @@ pub enum Plan {
         order_by: Option<Vec<(ast::Expr, SortOrder)>>,
     },
     Delete(DeletePlan),
-    Update(UpdatePlan),
+    Update(Box<UpdatePlan>),
+}
+
+/// How the rows of a [CteTable] are computed.
+#[derive(Debug)]
+pub enum CtePlan {
+    /// The rows of a SELECT or compound SELECT.
+    Select(Plan),
+    /// The rows of a recursive CTE.
+    Recursive(RecursiveCtePlan),
+}
+
+/// A recursive CTE of the form `setup UNION [ALL] step`.
+///
+/// The rows of `setup` are added to a queue. Then, until the queue is empty, a row is taken from
+/// the queue and added to the CTE, and `step` is run with `current` holding only that row.
+/// The rows of `step` are added to the queue.
+#[derive(Debug)]
+pub struct RecursiveCtePlan {
+    pub setup: Plan,
+    pub step: Box<SelectPlan>,
+    /// The table read by the reference to the CTE in `step`.
+    pub current: Rc<CteTable>,
+    /// Whether the CTE uses UNION rather than UNION ALL, i.e. rows that were added to the queue
+    /// before are discarded.
+    pub distinct: bool,
+    pub limit: Option<isize>,
+    pub offset: Option<isize>,
+}
+
+/// A common table expression of a WITH clause, visible to the query that declares it and to the
+/// queries nested in that query.
+#[derive(Debug)]
+pub struct Cte {
+    /// The normalized name of the CTE.
+    pub name: String,
+    pub definition: CteDefinition,
+}
+
+#[derive(Debug)]
+pub enum CteDefinition {
+    /// The CTE is planned wherever it is referenced, as a FROM clause subquery or, if its body is a
+    /// compound SELECT, as a [CteTable] whose rows are yielded by a coroutine.
+    Inline(CteBody),
+    /// The CTE is computed once, and every reference reads the result. It is planned when it is
+    /// first referenced, so that a CTE that is never referenced is neither planned nor computed.
+    Materialized {
+        body: CteBody,
+        cte: OnceCell<Rc<CteTable>>,
+    },
+    /// The row that the recursive part of a recursive CTE is run for. The recursive part may only
+    /// reference it once.
+    RecursiveRow {
+        cte: Rc<CteTable>,
+        referenced: Cell<bool>,
+    },
+}
+
+#[derive(Debug)]
+pub struct CteBody {
+    pub select: ast::Select,
+    /// The names given to the columns of the CTE, if any.
+    pub columns: Option<Vec<String>>,
+    /// The tables of the outer queries of the query that declares the CTE.
+    pub outer_query_refs: Vec<OuterQueryReference>,
 }
 
 /// The destination of the results of a query.
@@ -346,6 +419,8 @@ pub struct JoinOrderMember {
     pub original_idx: usize,
     /// Whether this member is the right side of an OUTER JOIN
     pub is_outer: bool,
+    /// Whether this member is the right side of a RIGHT or FULL OUTER JOIN
+    pub is_right: bool,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -442,6 +517,12 @@ pub struct SelectPlan {
     pub distinctness: Distinctness,
     /// values: https://sqlite.org/syntax/select-core.html
     pub values: Vec<Vec<Expr>>,
+    /// window functions, computed over the rows of the only table of the plan
+    pub window: Option<Window>,
+    /// the materialized CTEs declared by the WITH clause of this query, computed before the query runs
+    pub materialized_ctes: Vec<Rc<CteTable>>,
+    /// the subqueries used as expressions, e.g. in the WHERE clause or the result columns
+    pub non_from_clause_subqueries: Vec<NonFromClauseSubquery>,
 }
 
 impl SelectPlan {
@@ -449,8 +530,42 @@ impl SelectPlan {
         self.table_references.joined_tables()
     }
 
+    /// Number of values stored per row for the aggregates: their arguments, plus the FILTER
+    /// condition of the aggregates that have one.
     pub fn agg_args_count(&self) -> usize {
-        self.aggregates.iter().map(|agg| agg.args.len()).sum()
+        self.aggregates
+            .iter()
+            .map(|agg| agg.args.len() + usize::from(agg.filter.is_some()))
+            .sum()
+    }
+
+    /// The columns of the table formed by the rows of this query, e.g. when it is a FROM clause
+    /// subquery.
+    pub fn result_table_columns(&self) -> Vec<Column> {
+        self.result_columns
+            .iter()
+            .map(|rc| Column {
+                name: rc.name(&self.table_references).map(String::from),
+                ty: Type::Blob, // FIXME: infer proper type
+                ty_str: "BLOB".to_string(),
+                is_rowid_alias: false,
+                primary_key: false,
+                notnull: false,
+                default: None,
+                unique: false,
+                collation: match &rc.expr {
+                    ast::Expr::Collate(_, collation_name) => CollationSeq::new(collation_name).ok(),
+                    ast::Expr::Column { table, column, .. } => self
+                        .table_references
+                        .find_table_by_internal_id(*table)
+                        .and_then(|table| table.get_column_at(*column))
+                        .and_then(|column| column.collation),
+                    _ => None,
+                },
+                hidden: false,
+                generated: None,
+            })
+            .collect()
     }
 
     /// Reference: https://github.com/sqlite/sqlite/blob/5db695197b74580c777b37ab1b787531f15f7f9f/src/select.c#L8613
@@ -518,6 +633,10 @@ pub struct DeletePlan {
     pub contains_constant_false_condition: bool,
     /// Indexes that must be updated by the delete operation.
     pub indexes: Vec<Arc<Index>>,
+    /// Triggers fired for each deleted row, if any.
+    pub triggers: Option<TableTriggers>,
+    /// The subqueries used as expressions in the WHERE clause.
+    pub non_from_clause_subqueries: Vec<NonFromClauseSubquery>,
 }
 
 #[derive(Debug, Clone)]
@@ -536,6 +655,28 @@ pub struct UpdatePlan {
     pub indexes_to_update: Vec<Arc<Index>>,
     // If the table's rowid alias is used, gather all the target rowids into an ephemeral table, and then use that table as the single JoinedTable for the actual UPDATE loop.
     pub ephemeral_plan: Option<SelectPlan>,
+    // Triggers fired for each updated row, if any
+    pub triggers: Option<TableTriggers>,
+    // The conflict resolution of UPDATE OR ..., which overrides those of the table constraints
+    pub or_conflict: Option<ast::ResolveType>,
+    // Foreign key checks of the rows deleted by REPLACE
+    pub replace_foreign_keys: ForeignKeyChecks,
+    // CHECK constraints of the updated rows
+    pub checks: CheckConstraints,
+    // The subqueries used as expressions in the SET and WHERE clauses
+    pub non_from_clause_subqueries: Vec<NonFromClauseSubquery>,
+}
+
+/// A subquery used as an expression, e.g. `EXISTS (SELECT ...)`, `x IN (SELECT ...)` or a scalar
+/// `(SELECT ...)`. The expression is replaced by an [ast::Expr::SubqueryResult] that refers to the
+/// subquery by its internal ID.
+///
+/// The subquery is evaluated where its result is used. A subquery that does not refer to any
+/// column of the enclosing queries is only evaluated once per statement.
+#[derive(Debug, Clone)]
+pub struct NonFromClauseSubquery {
+    pub internal_id: TableInternalId,
+    pub plan: Box<Plan>,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -545,7 +686,7 @@ pub enum IterationDirection {
 }
 
 pub fn select_star(tables: &[JoinedTable], out_columns: &mut Vec<ResultSetColumn>) {
-    for table in tables.iter() {
+    for (table_idx, table) in tables.iter().enumerate() {
         let maybe_using_cols = table
             .join_info
             .as_ref()
@@ -569,25 +710,98 @@ pub fn select_star(tables: &[JoinedTable], out_columns: &mut Vec<ResultSetColumn
                         true
                     }
                 })
-                .map(|(i, col)| ResultSetColumn {
-                    alias: None,
-                    expr: ast::Expr::Column {
+                .map(|(i, col)| {
+                    let expr = ast::Expr::Column {
                         database: None,
                         table: table.internal_id,
                         column: i,
                         is_rowid_alias: col.is_rowid_alias,
-                    },
-                    contains_aggregates: false,
+                    };
+                    // The column of a RIGHT or FULL OUTER JOIN ... USING is the value of
+                    // whichever side has a row.
+                    let right_join_cols = col.name.as_deref().map_or(vec![], |name| {
+                        using_columns_of_right_joins(tables, table_idx, name)
+                    });
+                    if right_join_cols.is_empty() {
+                        return ResultSetColumn {
+                            alias: None,
+                            expr,
+                            contains_aggregates: false,
+                        };
+                    }
+                    ResultSetColumn {
+                        alias: col.name.clone(),
+                        expr: ast::Expr::FunctionCall {
+                            name: ast::Name::Ident("coalesce".to_string()),
+                            distinctness: None,
+                            args: Some(std::iter::once(expr).chain(right_join_cols).collect()),
+                            order_by: None,
+                            filter_over: None,
+                        },
+                        contains_aggregates: false,
+                    }
                 }),
         );
     }
 }
 
+/// Returns the columns named `name` of the tables after `table_idx` that are joined to it by a
+/// RIGHT or FULL OUTER JOIN ... USING.
+pub fn using_columns_of_right_joins(
+    tables: &[JoinedTable],
+    table_idx: usize,
+    name: &str,
+) -> Vec<ast::Expr> {
+    let has_column = |table: &JoinedTable| {
+        table.columns().iter().any(|col| {
+            col.name
+                .as_ref()
+                .is_some_and(|col_name| col_name.eq_ignore_ascii_case(name))
+        })
+    };
+    let mut columns = vec![];
+    for (right_idx, right_table) in tables.iter().enumerate().skip(table_idx + 1) {
+        let Some(join_info) = right_table.join_info.as_ref() else {
+            continue;
+        };
+        let is_using_column = join_info.using.as_ref().is_some_and(|using_cols| {
+            using_cols
+                .iter()
+                .any(|using_col| using_col.as_str().eq_ignore_ascii_case(name))
+        });
+        // USING refers to the column of the leftmost table that has it.
+        if !join_info.right
+            || !is_using_column
+            || tables[..table_idx].iter().any(has_column)
+            || !tables[..right_idx].iter().any(has_column)
+        {
+            continue;
+        }
+        if let Some((column, col)) = right_table.columns().iter().enumerate().find(|(_, col)| {
+            col.name
+                .as_ref()
+                .is_some_and(|col_name| col_name.eq_ignore_ascii_case(name))
+        }) {
+            columns.push(ast::Expr::Column {
+                database: None,
+                table: right_table.internal_id,
+                column,
+                is_rowid_alias: col.is_rowid_alias,
+            });
+        }
+    }
+    columns
+}
+
 /// Join information for a table reference.
 #[derive(Debug, Clone)]
 pub struct JoinInfo {
-    /// Whether this is an OUTER JOIN.
+    /// Whether this is a LEFT or FULL OUTER JOIN, i.e. the rows of the tables to the left that
+    /// have no match in this table are emitted with NULLs for this table.
     pub outer: bool,
+    /// Whether this is a RIGHT or FULL OUTER JOIN, i.e. the rows of this table that have no match
+    /// in the tables to the left are emitted with NULLs for those tables.
+    pub right: bool,
     /// The USING clause for the join, if any. NATURAL JOIN is transformed into USING (col1, col2, ...).
     pub using: Option<ast::DistinctNames>,
 }
@@ -601,7 +815,7 @@ pub struct JoinInfo {
 /// - all have [Operation::Scan]
 /// - identifiers are `t`, `p`, `sub`
 /// - `t` and `p` are [Table::BTree] while `sub` is [Table::FromClauseSubquery]
-/// - join_info is None for the first table reference, and Some(JoinInfo { outer: false, using: None }) for the second and third table references
+/// - join_info is None for the first table reference, and Some(JoinInfo { outer: false, right: false, using: None }) for the second and third table references
 #[derive(Debug, Clone)]
 pub struct JoinedTable {
     /// The operation that this table reference performs.
@@ -619,6 +833,18 @@ pub struct JoinedTable {
     pub col_used_mask: ColumnUsedMask,
     /// The index of the database. "main" is always zero.
     pub database_id: usize,
+    /// The INDEXED BY or NOT INDEXED clause of the table reference, if any.
+    pub index_hint: Option<IndexHint>,
+}
+
+/// A clause of a table reference that overrides the choice of the optimizer of how to access
+/// the table.
+#[derive(Debug, Clone)]
+pub enum IndexHint {
+    /// `INDEXED BY`: the table must be accessed through this index, or the query fails.
+    IndexedBy(Arc<Index>),
+    /// `NOT INDEXED`: the table must be scanned or looked up by rowid.
+    NotIndexed,
 }
 
 #[derive(Debug, Clone)]
@@ -907,22 +1133,7 @@ impl JoinedTable {
         join_info: Option<JoinInfo>,
         internal_id: TableInternalId,
     ) -> Self {
-        let columns = plan
-            .result_columns
-            .iter()
-            .map(|rc| Column {
-                name: rc.name(&plan.table_references).map(String::from),
-                ty: Type::Blob, // FIXME: infer proper type
-                ty_str: "BLOB".to_string(),
-                is_rowid_alias: false,
-                primary_key: false,
-                notnull: false,
-                default: None,
-                unique: false,
-                collation: None, // FIXME: infer collation from subquery
-                hidden: false,
-            })
-            .collect();
+        let columns = plan.result_table_columns();
 
         let table = Table::FromClauseSubquery(FromClauseSubquery {
             name: identifier.clone(),
@@ -941,6 +1152,7 @@ impl JoinedTable {
             join_info,
             col_used_mask: ColumnUsedMask::default(),
             database_id: 0,
+            index_hint: None,
         }
     }
 
@@ -967,8 +1179,10 @@ impl JoinedTable {
             Table::BTree(btree) => {
                 let use_covering_index = self.utilizes_covering_index();
                 let index_is_ephemeral = index.is_some_and(|index| index.ephemeral);
-                let table_not_required =
-                    OperationMode::SELECT == mode && use_covering_index && !index_is_ephemeral;
+                // A table without rowid is stored in its clustered index, which is read and
+                // written through the index cursor.
+                let table_not_required = !btree.has_rowid
+                    || OperationMode::SELECT == mode && use_covering_index && !index_is_ephemeral;
                 let table_cursor_id = if table_not_required {
                     None
                 } else {
@@ -994,6 +1208,16 @@ impl JoinedTable {
                 Ok((table_cursor_id, index_cursor_id))
             }
             Table::FromClauseSubquery(..) => Ok((None, None)),
+            Table::Cte(cte) => match cte.rows.get() {
+                Some(CteRows::Ephemeral { .. }) => {
+                    let table_cursor_id = Some(program.alloc_cursor_id_keyed(
+                        CursorKey::table(self.internal_id),
+                        CursorType::BTreeTable(cte.table.clone()),
+                    ));
+                    Ok((table_cursor_id, None))
+                }
+                _ => Ok((None, None)),
+            },
         }
     }
 
@@ -1016,10 +1240,11 @@ impl JoinedTable {
             return false;
         };
         if self.col_used_mask.is_empty() {
-            return false;
+            // The indexes of a table without rowid are the only way to read its rows.
+            return !btree.has_rowid;
         }
         let mut index_cols_mask = ColumnUsedMask::default();
-        for col in index.columns.iter() {
+        for col in index.columns.iter().filter(|col| !col.is_expression()) {
             index_cols_mask.set(col.pos_in_table);
         }
 
@@ -1039,7 +1264,32 @@ impl JoinedTable {
             }
         }
 
-        index_cols_mask.contains_all_set_bits_of(&self.col_used_mask)
+        // A VIRTUAL generated column the index does not store is computed from the columns it
+        // refers to, which must be read from the index instead.
+        let mut needed_mask = ColumnUsedMask::default();
+        let mut visited_mask = ColumnUsedMask::default();
+        let mut pending = (0..btree.columns.len())
+            .filter(|pos| self.col_used_mask.get(*pos))
+            .collect::<Vec<_>>();
+        while let Some(pos) = pending.pop() {
+            if visited_mask.get(pos) {
+                continue;
+            }
+            visited_mask.set(pos);
+            match &btree.columns[pos].generated {
+                Some(generated) if !generated.stored && !index_cols_mask.get(pos) => {
+                    pending.extend(
+                        generated
+                            .referenced_columns()
+                            .iter()
+                            .filter_map(|name| btree.get_column(name).map(|(dep, _)| dep)),
+                    );
+                }
+                _ => needed_mask.set(pos),
+            }
+        }
+
+        index_cols_mask.contains_all_set_bits_of(&needed_mask)
     }
 
     /// Returns true if the index selected for use with this [TableReference] is a covering index,
@@ -1130,6 +1380,9 @@ pub struct Aggregate {
     pub args: Vec<ast::Expr>,
     pub original_expr: ast::Expr,
     pub distinctness: Distinctness,
+    /// The condition of a `FILTER (WHERE ...)` clause: rows for which it is not true are not
+    /// aggregated.
+    pub filter: Option<ast::Expr>,
 }
 
 impl Aggregate {
@@ -1137,3 +1390,82 @@ impl Aggregate {
         self.distinctness.is_distinct()
     }
 }
+
+/// The window functions of a SELECT.
+///
+/// Everything else in the SELECT (FROM, WHERE, GROUP BY, aggregates) is planned as a FROM clause
+/// subquery, which is the only table of the plan. The window stage reads the rows of that subquery,
+/// and all arguments, PARTITION BY and ORDER BY terms of the window functions are references to its
+/// result columns.
+#[derive(Debug, Clone)]
+pub struct Window {
+    /// The result columns of the subquery, as [ast::Expr::Column] references.
+    pub source_columns: Vec<ast::Expr>,
+    /// One entry per distinct window, computed one after the other.
+    pub definitions: Vec<WindowDefinition>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WindowDefinition {
+    pub partition_by: Vec<ast::Expr>,
+    pub order_by: Vec<(ast::Expr, SortOrder)>,
+    pub frame: WindowFrame,
+    pub functions: Vec<WindowFunction>,
+}
+
+impl WindowDefinition {
+    /// Number of sort keys of the window: PARTITION BY terms followed by ORDER BY terms.
+    pub fn key_count(&self) -> usize {
+        self.partition_by.len() + self.order_by.len()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowFrame {
+    pub mode: ast::FrameMode,
+    pub start: ast::FrameBound,
+    pub end: ast::FrameBound,
+    pub exclude: ast::FrameExclude,
+}
+
+impl Default for WindowFrame {
+    /// The frame of a window without a frame specification.
+    fn default() -> Self {
+        Self {
+            mode: ast::FrameMode::Range,
+            start: ast::FrameBound::UnboundedPreceding,
+            end: ast::FrameBound::CurrentRow,
+            exclude: ast::FrameExclude::NoOthers,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum WindowFunction {
+    /// An aggregate computed over the rows of the frame.
+    Aggregate(Aggregate),
+    /// A built-in window function.
+    Builtin {
+        func: WindowFunc,
+        args: Vec<ast::Expr>,
+        original_expr: ast::Expr,
+    },
+}
+
+impl WindowFunction {
+    /// The function call as it appears in the result columns and ORDER BY of the plan.
+    pub fn original_expr(&self) -> &ast::Expr {
+        match self {
+            WindowFunction::Aggregate(agg) => &agg.original_expr,
+            WindowFunction::Builtin { original_expr, .. } => original_expr,
+        }
+    }
+
+    /// Whether the result depends on the window frame.
+    pub fn uses_frame(&self) -> bool {
+        match self {
+            WindowFunction::Aggregate(_) => true,
+            WindowFunction::Builtin { func, .. } => func.uses_frame(),
+        }
+    }
+}
@@ -1,12 +1,14 @@
 use std::cell::Cell;
+use std::rc::Rc;
 use std::sync::Arc;
 
 use super::{
+    cte::{parse_cte_reference, plan_compound_view},
     expr::walk_expr,
     plan::{
-        Aggregate, ColumnUsedMask, Distinctness, EvalAt, IterationDirection, JoinInfo,
-        JoinOrderMember, JoinedTable, Operation, OuterQueryReference, Plan, QueryDestination,
-        ResultSetColumn, TableReferences, WhereTerm,
+        using_columns_of_right_joins, Aggregate, ColumnUsedMask, Cte, Distinctness, EvalAt,
+        IndexHint, IterationDirection, JoinInfo, JoinOrderMember, JoinedTable, Operation, Plan,
+        QueryDestination, ResultSetColumn, SelectPlan, TableReferences, WhereTerm,
     },
     select::prepare_select_plan,
     SymbolTable,
@@ -14,16 +16,16 @@ use super::{
 use crate::translate::expr::WalkControl;
 use crate::{
     function::Func,
-    schema::{Schema, Table},
+    schema::{BTreeTable, Index, Schema, Table, View},
     translate::expr::walk_expr_mut,
-    util::{exprs_are_equivalent, normalize_ident},
+    util::{exprs_are_equivalent, normalize_ident, quote_ident},
     vdbe::{builder::TableRefIdCounter, BranchOffset},
     Result,
 };
 use turso_sqlite3_parser::ast::Literal::Null;
 use turso_sqlite3_parser::ast::{
-    self, As, Expr, FromClause, JoinType, Limit, Literal, Materialized, QualifiedName,
-    TableInternalId, UnaryOperator, With,
+    self, As, Expr, FromClause, JoinType, Limit, Literal, QualifiedName, TableInternalId,
+    UnaryOperator,
 };
 
 pub const ROWID: &str = "rowid";
@@ -47,6 +49,7 @@ pub fn resolve_aggregates(
                 name,
                 args,
                 distinctness,
+                filter_over,
                 ..
             } => {
                 let args_count = if let Some(args) = &args {
@@ -73,6 +76,7 @@ pub fn resolve_aggregates(
                             args: args.clone().unwrap_or_default(),
                             original_expr: expr.clone(),
                             distinctness,
+                            filter: resolve_filter_clause(filter_over.as_ref())?,
                         });
                         contains_aggregates = true;
                     }
@@ -85,13 +89,14 @@ pub fn resolve_aggregates(
                     }
                 }
             }
-            Expr::FunctionCallStar { name, .. } => {
+            Expr::FunctionCallStar { name, filter_over } => {
                 if let Ok(Func::Agg(f)) = Func::resolve_function(name.as_str(), 0) {
                     aggs.push(Aggregate {
                         func: f,
                         args: vec![],
                         original_expr: expr.clone(),
                         distinctness: Distinctness::NonDistinct,
+                        filter: resolve_filter_clause(filter_over.as_ref())?,
                     });
                     contains_aggregates = true;
                 }
@@ -105,6 +110,25 @@ pub fn resolve_aggregates(
     Ok(contains_aggregates)
 }
 
+/// Returns the condition of an aggregate's `FILTER (WHERE ...)` clause, if it has one.
+pub fn resolve_filter_clause(filter_over: Option<&ast::FunctionTail>) -> Result<Option<Expr>> {
+    let Some(filter) = filter_over.and_then(|f| f.filter_clause.as_deref()) else {
+        return Ok(None);
+    };
+    walk_expr(filter, &mut |expr: &Expr| -> Result<WalkControl> {
+        let (name, args_count) = match expr {
+            Expr::FunctionCall { name, args, .. } => (name, args.as_ref().map_or(0, |a| a.len())),
+            Expr::FunctionCallStar { name, .. } => (name, 0),
+            _ => return Ok(WalkControl::Continue),
+        };
+        if let Ok(Func::Agg(_)) = Func::resolve_function(name.as_str(), args_count) {
+            crate::bail_parse_error!("misuse of aggregate function {}()", name.as_str());
+        }
+        Ok(WalkControl::Continue)
+    })?;
+    Ok(Some(filter.clone()))
+}
+
 pub fn bind_column_references(
     top_level_expr: &mut Expr,
     referenced_tables: &mut TableReferences,
@@ -123,7 +147,12 @@ pub fn bind_column_references(
                 }
                 let normalized_id = normalize_ident(id.as_str());
 
-                if !referenced_tables.joined_tables().is_empty() {
+                // Tables without rowid have no rowid to refer to.
+                if referenced_tables
+                    .joined_tables()
+                    .first()
+                    .is_some_and(|table| table.btree().is_none_or(|table| table.has_rowid))
+                {
                     if let Some(row_id_expr) = parse_row_id(
                         &normalized_id,
                         referenced_tables.joined_tables()[0].internal_id,
@@ -225,10 +254,12 @@ pub fn bind_column_references(
                 let (tbl_id, tbl) = matching_tbl.unwrap();
                 let normalized_id = normalize_ident(id.as_str());
 
-                if let Some(row_id_expr) = parse_row_id(&normalized_id, tbl_id, || false)? {
-                    *expr = row_id_expr;
+                if tbl.btree().is_none_or(|table| table.has_rowid) {
+                    if let Some(row_id_expr) = parse_row_id(&normalized_id, tbl_id, || false)? {
+                        *expr = row_id_expr;
 
-                    return Ok(());
+                        return Ok(());
+                    }
                 }
                 let col_idx = tbl.columns().iter().position(|c| {
                     c.name
@@ -328,19 +359,22 @@ fn parse_from_clause_table(
     table: ast::SelectTable,
     table_references: &mut TableReferences,
     out_where_clause: &mut Vec<WhereTerm>,
-    ctes: &mut Vec<JoinedTable>,
+    ctes: &[Rc<Cte>],
     syms: &SymbolTable,
     table_ref_counter: &mut TableRefIdCounter,
     connection: &Arc<crate::Connection>,
 ) -> Result<()> {
     match table {
-        ast::SelectTable::Table(qualified_name, maybe_alias, _) => parse_table(
+        ast::SelectTable::Table(qualified_name, maybe_alias, indexed) => parse_table(
+            schema,
+            syms,
             table_references,
             ctes,
             table_ref_counter,
             out_where_clause,
             qualified_name,
             maybe_alias,
+            indexed,
             None,
             connection,
         ),
@@ -350,6 +384,7 @@ fn parse_from_clause_table(
                 *subselect,
                 syms,
                 table_references.outer_query_refs(),
+                ctes,
                 table_ref_counter,
                 QueryDestination::CoroutineYield {
                     yield_reg: usize::MAX, // will be set later in bytecode emission
@@ -376,12 +411,15 @@ fn parse_from_clause_table(
             Ok(())
         }
         ast::SelectTable::TableCall(qualified_name, maybe_args, maybe_alias) => parse_table(
+            schema,
+            syms,
             table_references,
             ctes,
             table_ref_counter,
             out_where_clause,
             qualified_name,
             maybe_alias,
+            None,
             maybe_args,
             connection,
         ),
@@ -391,12 +429,15 @@ fn parse_from_clause_table(
 
 #[allow(clippy::too_many_arguments)]
 fn parse_table(
+    schema: &Schema,
+    syms: &SymbolTable,
     table_references: &mut TableReferences,
-    ctes: &mut Vec<JoinedTable>,
+    ctes: &[Rc<Cte>],
     table_ref_counter: &mut TableRefIdCounter,
     out_where_clause: &mut Vec<WhereTerm>,
     qualified_name: QualifiedName,
     maybe_alias: Option<As>,
+    indexed: Option<ast::Indexed>,
     maybe_args: Option<Vec<Expr>>,
     connection: &Arc<crate::Connection>,
 ) -> Result<()> {
@@ -404,27 +445,39 @@ fn parse_table(
     let database_id = connection.resolve_database_id(&qualified_name)?;
     let table_name = qualified_name.name;
 
+    let alias = maybe_alias
+        .map(|a| match a {
+            ast::As::As(id) => id,
+            ast::As::Elided(id) => id,
+        })
+        .map(|a| a.as_str().to_string());
+
     // Check if the FROM clause table is referring to a CTE in the current scope.
-    if let Some(cte_idx) = ctes
-        .iter()
-        .position(|cte| cte.identifier == normalized_qualified_name)
-    {
-        // TODO: what if the CTE is referenced multiple times?
-        let cte_table = ctes.remove(cte_idx);
-        table_references.add_joined_table(cte_table);
-        return Ok(());
-    };
+    // Later CTEs shadow earlier ones, and CTEs shadow schema tables.
+    if qualified_name.db_name.is_none() {
+        if let Some(cte_idx) = ctes
+            .iter()
+            .rposition(|cte| cte.name == normalized_qualified_name)
+        {
+            // A CTE has no indexes
+            resolve_index_hint(indexed, &[])?;
+            return parse_cte_reference(
+                schema,
+                syms,
+                ctes,
+                cte_idx,
+                alias.unwrap_or(normalized_qualified_name),
+                table_references,
+                table_ref_counter,
+                connection,
+            );
+        }
+    }
 
     // Resolve table using connection's with_schema method
     let table = connection.with_schema(database_id, |schema| schema.get_table(table_name.as_str()));
 
     if let Some(table) = table {
-        let alias = maybe_alias
-            .map(|a| match a {
-                ast::As::As(id) => id,
-                ast::As::Elided(id) => id,
-            })
-            .map(|a| a.as_str().to_string());
         let internal_id = table_ref_counter.next();
         let tbl_ref = if let Table::Virtual(tbl) = table.as_ref() {
             if let Some(args) = maybe_args {
@@ -443,10 +496,20 @@ fn parse_table(
                 "Table type not supported".to_string(),
             ));
         };
+        // A table without rowid is scanned through its clustered index, which is looked up in
+        // the database of the table.
+        let clustered_index = tbl_ref.btree().and_then(|table| {
+            connection.with_schema(database_id, |schema| {
+                schema.get_clustered_index(&table).cloned()
+            })
+        });
+        let index_hint = connection.with_schema(database_id, |schema| {
+            resolve_index_hint(indexed, schema.get_indices(tbl_ref.get_name()))
+        })?;
         table_references.add_joined_table(JoinedTable {
             op: Operation::Scan {
                 iter_dir: IterationDirection::Forwards,
-                index: None,
+                index: clustered_index,
             },
             table: tbl_ref,
             identifier: alias.unwrap_or(normalized_qualified_name),
@@ -454,40 +517,319 @@ fn parse_table(
             join_info: None,
             col_used_mask: ColumnUsedMask::default(),
             database_id,
+            index_hint,
         });
         return Ok(());
     };
 
-    // CTEs are transformed into FROM clause subqueries.
-    // If we find a CTE with this name in our outer query references,
-    // we can use it as a joined table, but we must clone it since it's not MATERIALIZED.
-    //
-    // For other types of tables in the outer query references, we do not add them as joined tables,
-    // because the query can simply _reference_ them in e.g. the SELECT columns or the WHERE clause,
-    // but it's not part of the join order.
-    if let Some(outer_ref) =
-        table_references.find_outer_query_ref_by_identifier(&normalized_qualified_name)
-    {
-        if matches!(outer_ref.table, Table::FromClauseSubquery(_)) {
+    // Views are transformed into FROM clause subqueries.
+    let view = connection.with_schema(database_id, |schema| schema.get_view(table_name.as_str()));
+    if let Some(view) = view {
+        // A view has no indexes
+        resolve_index_hint(indexed, &[])?;
+        // Like a CTE, a compound view is read from a coroutine.
+        if view.select.body.compounds.is_some() {
+            let cte_table = plan_compound_view(
+                schema,
+                syms,
+                &view,
+                view_select(&view, database_id, connection)?,
+                table_ref_counter,
+                connection,
+            )?;
             table_references.add_joined_table(JoinedTable {
                 op: Operation::Scan {
                     iter_dir: IterationDirection::Forwards,
                     index: None,
                 },
-                table: outer_ref.table.clone(),
-                identifier: outer_ref.identifier.clone(),
+                table: Table::Cte(cte_table),
+                identifier: alias.unwrap_or(normalized_qualified_name),
                 internal_id: table_ref_counter.next(),
                 join_info: None,
                 col_used_mask: ColumnUsedMask::default(),
-                database_id,
+                database_id: 0,
+                index_hint: None,
             });
             return Ok(());
         }
+        let subplan = parse_view(
+            schema,
+            &view,
+            database_id,
+            syms,
+            table_ref_counter,
+            connection,
+        )?;
+        table_references.add_joined_table(JoinedTable::new_subquery(
+            alias.unwrap_or(normalized_qualified_name),
+            subplan,
+            None,
+            table_ref_counter.next(),
+        ));
+        return Ok(());
     }
 
     crate::bail_parse_error!("no such table: {}", normalized_qualified_name);
 }
 
+/// Resolves the INDEXED BY or NOT INDEXED clause of a reference to a table with the given
+/// indexes.
+pub fn resolve_index_hint(
+    indexed: Option<ast::Indexed>,
+    indexes: &[Arc<Index>],
+) -> Result<Option<IndexHint>> {
+    match indexed {
+        None => Ok(None),
+        Some(ast::Indexed::NotIndexed) => Ok(Some(IndexHint::NotIndexed)),
+        Some(ast::Indexed::IndexedBy(name)) => {
+            let index_name = normalize_ident(name.as_str());
+            match indexes.iter().find(|index| index.name == index_name) {
+                Some(index) => Ok(Some(IndexHint::IndexedBy(index.clone()))),
+                None => crate::bail_parse_error!("no such index: {}", name.as_str()),
+            }
+        }
+    }
+}
+
+/// The SELECT of `view`, whose table names are qualified with the database of the view.
+fn view_select(
+    view: &View,
+    database_id: usize,
+    connection: &Arc<crate::Connection>,
+) -> Result<ast::Select> {
+    check_view_is_not_circular(&connection.database_schema(database_id), view, &mut vec![])?;
+    let mut select = *view.select.clone();
+    // Like in SQLite, the tables a view of an attached database reads are in that database.
+    if database_id > 1 {
+        let database_name = quote_ident(&connection.get_database_name(database_id));
+        qualify_select_tables(&mut select, &database_name, &mut vec![])?;
+    }
+    Ok(select)
+}
+
+/// Plan the SELECT of a view so that it can be used as a FROM clause subquery.
+/// The view's column list, if any, renames the result columns. Views with a compound
+/// SELECT are planned by [plan_compound_view] instead.
+fn parse_view(
+    schema: &Schema,
+    view: &View,
+    database_id: usize,
+    syms: &SymbolTable,
+    table_ref_counter: &mut TableRefIdCounter,
+    connection: &Arc<crate::Connection>,
+) -> Result<SelectPlan> {
+    let select = view_select(view, database_id, connection)?;
+    // A view cannot reference the tables of the query it is used in.
+    let Plan::Select(mut plan) = prepare_select_plan(
+        schema,
+        select,
+        syms,
+        &[],
+        &[],
+        table_ref_counter,
+        QueryDestination::CoroutineYield {
+            yield_reg: usize::MAX, // will be set later in bytecode emission
+            coroutine_implementation_start: BranchOffset::Placeholder, // will be set later in bytecode emission
+        },
+        connection,
+    )?
+    else {
+        unreachable!("a SELECT without compounds is planned as a single SELECT");
+    };
+    if let Some(columns) = &view.columns {
+        if columns.len() != plan.result_columns.len() {
+            crate::bail_parse_error!(
+                "expected {} columns for '{}' but got {}",
+                columns.len(),
+                view.name,
+                plan.result_columns.len()
+            );
+        }
+        for (result_column, name) in plan.result_columns.iter_mut().zip(columns) {
+            result_column.alias = Some(name.clone());
+        }
+    }
+    Ok(plan)
+}
+
+/// The table the INSTEAD OF triggers of `view` are defined on, whose columns are the result
+/// columns of the view. Like a CTE, it has no storage of its own.
+pub(crate) fn view_table(
+    schema: &Schema,
+    view: &View,
+    database_id: usize,
+    syms: &SymbolTable,
+    table_ref_counter: &mut TableRefIdCounter,
+    connection: &Arc<crate::Connection>,
+) -> Result<Rc<BTreeTable>> {
+    if view.select.body.compounds.is_some() {
+        let select = view_select(view, database_id, connection)?;
+        let cte_table =
+            plan_compound_view(schema, syms, view, select, table_ref_counter, connection)?;
+        return Ok(cte_table.table.clone());
+    }
+    let plan = parse_view(
+        schema,
+        view,
+        database_id,
+        syms,
+        table_ref_counter,
+        connection,
+    )?;
+    Ok(Rc::new(BTreeTable {
+        root_page: 0,
+        name: view.name.clone(),
+        primary_key_columns: vec![],
+        columns: plan.result_table_columns(),
+        has_rowid: true,
+        is_strict: false,
+        unique_sets: None,
+        conflict_clauses: vec![],
+        foreign_keys: vec![],
+        checks: vec![],
+        notnull_conflict_clauses: vec![],
+    }))
+}
+
+/// Qualifies the unqualified table names `select` reads with `database_name`, leaving the
+/// references to the CTEs in scope, listed in `ctes`, untouched.
+pub(crate) fn qualify_select_tables(
+    select: &mut ast::Select,
+    database_name: &str,
+    ctes: &mut Vec<String>,
+) -> Result<()> {
+    let scope = ctes.len();
+    if let Some(with) = &mut select.with {
+        for cte in &mut with.ctes {
+            ctes.push(normalize_ident(cte.tbl_name.as_str()));
+            qualify_select_tables(&mut cte.select, database_name, ctes)?;
+        }
+    }
+    let compounds = select.body.compounds.iter_mut().flatten();
+    for one in std::iter::once(&mut select.body.select).chain(compounds.map(|c| &mut c.select)) {
+        match one.as_mut() {
+            ast::OneSelect::Select(one) => {
+                if let Some(from) = &mut one.from {
+                    qualify_from_tables(from, database_name, ctes)?;
+                }
+                let columns = one.columns.iter_mut().filter_map(|column| match column {
+                    ast::ResultColumn::Expr(expr, _) => Some(expr),
+                    _ => None,
+                });
+                for expr in columns.chain(one.where_clause.as_mut()) {
+                    qualify_expr_tables(expr, database_name, ctes)?;
+                }
+            }
+            ast::OneSelect::Values(rows) => {
+                for expr in rows.iter_mut().flatten() {
+                    qualify_expr_tables(expr, database_name, ctes)?;
+                }
+            }
+        }
+    }
+    ctes.truncate(scope);
+    Ok(())
+}
+
+pub(crate) fn qualify_from_tables(
+    from: &mut FromClause,
+    database_name: &str,
+    ctes: &mut Vec<String>,
+) -> Result<()> {
+    let joins = from.joins.iter_mut().flatten();
+    for table in from
+        .select
+        .as_deref_mut()
+        .into_iter()
+        .chain(joins.map(|j| &mut j.table))
+    {
+        match table {
+            ast::SelectTable::Table(name, ..) | ast::SelectTable::TableCall(name, ..) => {
+                if name.db_name.is_none() && !ctes.contains(&normalize_ident(name.name.as_str())) {
+                    name.db_name = Some(ast::Name::from_str(database_name));
+                }
+            }
+            ast::SelectTable::Select(select, _) => {
+                qualify_select_tables(select, database_name, ctes)?
+            }
+            ast::SelectTable::Sub(from, _) => qualify_from_tables(from, database_name, ctes)?,
+        }
+    }
+    for join in from.joins.iter_mut().flatten() {
+        if let Some(ast::JoinConstraint::On(expr)) = &mut join.constraint {
+            qualify_expr_tables(expr, database_name, ctes)?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn qualify_expr_tables(
+    expr: &mut Expr,
+    database_name: &str,
+    ctes: &mut Vec<String>,
+) -> Result<()> {
+    walk_expr_mut(expr, &mut |expr: &mut Expr| -> Result<()> {
+        match expr {
+            Expr::Exists(select) | Expr::Subquery(select) => {
+                qualify_select_tables(select, database_name, ctes)
+            }
+            Expr::InSelect { rhs, .. } => qualify_select_tables(rhs, database_name, ctes),
+            _ => Ok(()),
+        }
+    })
+}
+
+/// Returns an error if expanding `view` would end up expanding `view` again.
+fn check_view_is_not_circular(
+    schema: &Schema,
+    view: &View,
+    expanding: &mut Vec<String>,
+) -> Result<()> {
+    fn visit_select(
+        schema: &Schema,
+        select: &ast::Select,
+        expanding: &mut Vec<String>,
+    ) -> Result<()> {
+        if let Some(with) = &select.with {
+            for cte in &with.ctes {
+                visit_select(schema, &cte.select, expanding)?;
+            }
+        }
+        let compounds = select.body.compounds.iter().flatten();
+        for one in std::iter::once(&select.body.select).chain(compounds.map(|c| &c.select)) {
+            if let ast::OneSelect::Select(one) = one.as_ref() {
+                if let Some(from) = &one.from {
+                    visit_from(schema, from, expanding)?;
+                }
+            }
+        }
+        Ok(())
+    }
+    fn visit_from(schema: &Schema, from: &FromClause, expanding: &mut Vec<String>) -> Result<()> {
+        let joins = from.joins.iter().flatten().map(|j| &j.table);
+        for table in from.select.as_deref().into_iter().chain(joins) {
+            match table {
+                ast::SelectTable::Table(name, ..) | ast::SelectTable::TableCall(name, ..) => {
+                    if let Some(view) = schema.get_view(name.name.as_str()) {
+                        check_view_is_not_circular(schema, &view, expanding)?;
+                    }
+                }
+                ast::SelectTable::Select(select, _) => visit_select(schema, select, expanding)?,
+                ast::SelectTable::Sub(from, _) => visit_from(schema, from, expanding)?,
+            }
+        }
+        Ok(())
+    }
+
+    if expanding.contains(&view.name) {
+        crate::bail_parse_error!("view {} is circularly defined", view.name);
+    }
+    expanding.push(view.name.clone());
+    visit_select(schema, &view.select, expanding)?;
+    expanding.pop();
+    Ok(())
+}
+
 fn transform_args_into_where_terms(
     args: Vec<Expr>,
     internal_id: TableInternalId,
@@ -559,7 +901,7 @@ pub fn parse_from(
     schema: &Schema,
     mut from: Option<FromClause>,
     syms: &SymbolTable,
-    with: Option<With>,
+    ctes: &[Rc<Cte>],
     out_where_clause: &mut Vec<WhereTerm>,
     table_references: &mut TableReferences,
     table_ref_counter: &mut TableRefIdCounter,
@@ -569,77 +911,6 @@ pub fn parse_from(
         return Ok(());
     }
 
-    let mut ctes_as_subqueries = vec![];
-
-    if let Some(with) = with {
-        if with.recursive {
-            crate::bail_parse_error!("Recursive CTEs are not yet supported");
-        }
-        for cte in with.ctes {
-            if cte.materialized == Materialized::Yes {
-                crate::bail_parse_error!("Materialized CTEs are not yet supported");
-            }
-            if cte.columns.is_some() {
-                crate::bail_parse_error!("CTE columns are not yet supported");
-            }
-
-            // Check if normalized name conflicts with catalog tables or other CTEs
-            // TODO: sqlite actually allows overriding a catalog table with a CTE.
-            // We should carry over the 'Scope' struct to all of our identifier resolution.
-            let cte_name_normalized = normalize_ident(cte.tbl_name.as_str());
-            if schema.get_table(&cte_name_normalized).is_some() {
-                crate::bail_parse_error!(
-                    "CTE name {} conflicts with catalog table name",
-                    cte.tbl_name.as_str()
-                );
-            }
-            if table_references
-                .outer_query_refs()
-                .iter()
-                .any(|t| t.identifier == cte_name_normalized)
-            {
-                crate::bail_parse_error!(
-                    "CTE name {} conflicts with WITH table name {}",
-                    cte.tbl_name.as_str(),
-                    cte_name_normalized
-                );
-            }
-
-            let mut outer_query_refs_for_cte = table_references.outer_query_refs().to_vec();
-            outer_query_refs_for_cte.extend(ctes_as_subqueries.iter().map(|t: &JoinedTable| {
-                OuterQueryReference {
-                    identifier: t.identifier.clone(),
-                    internal_id: t.internal_id,
-                    table: t.table.clone(),
-                    col_used_mask: ColumnUsedMask::default(),
-                }
-            }));
-
-            // CTE can refer to other CTEs that came before it, plus any schema tables or tables in the outer scope.
-            let cte_plan = prepare_select_plan(
-                schema,
-                *cte.select,
-                syms,
-                &outer_query_refs_for_cte,
-                table_ref_counter,
-                QueryDestination::CoroutineYield {
-                    yield_reg: usize::MAX, // will be set later in bytecode emission
-                    coroutine_implementation_start: BranchOffset::Placeholder, // will be set later in bytecode emission
-                },
-                connection,
-            )?;
-            let Plan::Select(cte_plan) = cte_plan else {
-                crate::bail_parse_error!("Only SELECT queries are currently supported in CTEs");
-            };
-            ctes_as_subqueries.push(JoinedTable::new_subquery(
-                cte_name_normalized,
-                cte_plan,
-                None,
-                table_ref_counter.next(),
-            ));
-        }
-    }
-
     let mut from_owned = std::mem::take(&mut from).unwrap();
     let select_owned = *std::mem::take(&mut from_owned.select).unwrap();
     let joins_owned = std::mem::take(&mut from_owned.joins).unwrap_or_default();
@@ -648,7 +919,7 @@ pub fn parse_from(
         select_owned,
         table_references,
         out_where_clause,
-        &mut ctes_as_subqueries,
+        ctes,
         syms,
         table_ref_counter,
         connection,
@@ -659,7 +930,7 @@ pub fn parse_from(
             schema,
             join,
             syms,
-            &mut ctes_as_subqueries,
+            ctes,
             out_where_clause,
             table_references,
             table_ref_counter,
@@ -716,7 +987,14 @@ pub fn determine_where_to_eval_term(
         ));
     }
 
-    determine_where_to_eval_expr(&term.expr, join_order)
+    let eval_at = determine_where_to_eval_expr(&term.expr, join_order)?;
+    // A WHERE term that refers to the left side of a RIGHT JOIN is evaluated after it is known
+    // whether the row of the right table matched, because the term filters the joined rows
+    // and not the rows that are matched.
+    match (eval_at, join_order.iter().rposition(|t| t.is_right)) {
+        (EvalAt::Loop(idx), Some(right_idx)) if idx < right_idx => Ok(EvalAt::Loop(right_idx)),
+        _ => Ok(eval_at),
+    }
 }
 
 /// A bitmask representing a set of tables in a query plan.
@@ -856,11 +1134,11 @@ pub fn determine_where_to_eval_expr(
     walk_expr(top_level_expr, &mut |expr: &Expr| -> Result<WalkControl> {
         match expr {
             Expr::Column { table, .. } | Expr::RowId { table, .. } => {
-                let join_idx = join_order
-                    .iter()
-                    .position(|t| t.table_id == *table)
-                    .unwrap_or(usize::MAX);
-                eval_at = eval_at.max(EvalAt::Loop(join_idx));
+                // Tables from outer query scopes are guaranteed to be 'in scope' for this query,
+                // so they don't delay the evaluation.
+                if let Some(join_idx) = join_order.iter().position(|t| t.table_id == *table) {
+                    eval_at = eval_at.max(EvalAt::Loop(join_idx));
+                }
             }
             _ => {}
         }
@@ -875,7 +1153,7 @@ fn parse_join(
     schema: &Schema,
     join: ast::JoinedSelectTable,
     syms: &SymbolTable,
-    ctes: &mut Vec<JoinedTable>,
+    ctes: &[Rc<Cte>],
     out_where_clause: &mut Vec<WhereTerm>,
     table_references: &mut TableReferences,
     table_ref_counter: &mut TableRefIdCounter,
@@ -898,15 +1176,57 @@ fn parse_join(
         connection,
     )?;
 
-    let (outer, natural) = match join_operator {
+    let (outer, right, natural) = match join_operator {
         ast::JoinOperator::TypedJoin(Some(join_type)) => {
-            let is_outer = join_type.contains(JoinType::OUTER);
+            let is_outer = join_type.contains(JoinType::LEFT);
+            let is_right = join_type.contains(JoinType::RIGHT);
             let is_natural = join_type.contains(JoinType::NATURAL);
-            (is_outer, is_natural)
+            (is_outer, is_right, is_natural)
         }
-        _ => (false, false),
+        _ => (false, false, false),
     };
 
+    if right {
+        if let Table::Virtual(_) = table_references.joined_tables().last().unwrap().table {
+            crate::bail_parse_error!(
+                "RIGHT and FULL OUTER JOIN of a virtual table are not supported"
+            );
+        }
+        // The rows of this table that match no row of the tables to its left are emitted after
+        // the main loop, with NULLs for those tables and without the ON clauses that made up
+        // the left side of the join. Those ON clauses are tagged with their join, so they are
+        // not confused with the WHERE clause, which applies to all rows.
+        let joined_tables = table_references.joined_tables();
+        let left_tables = &joined_tables[..joined_tables.len() - 1];
+        if left_tables
+            .iter()
+            .any(|table| matches!(table.table, Table::Virtual(_)))
+        {
+            crate::bail_parse_error!(
+                "RIGHT and FULL OUTER JOIN of a virtual table are not supported"
+            );
+        }
+        for term in out_where_clause
+            .iter_mut()
+            .filter(|term| term.from_outer_join.is_none())
+        {
+            let mut rightmost_table = left_tables.last().unwrap().internal_id;
+            let mut rightmost_idx = 0;
+            walk_expr(&term.expr, &mut |expr| {
+                if let Expr::Column { table, .. } | Expr::RowId { table, .. } = expr {
+                    if let Some(idx) = left_tables.iter().position(|t| t.internal_id == *table) {
+                        if idx >= rightmost_idx {
+                            rightmost_idx = idx;
+                            rightmost_table = *table;
+                        }
+                    }
+                }
+                Ok(WalkControl::Continue)
+            })?;
+            term.from_outer_join = Some(rightmost_table);
+        }
+    }
+
     let mut using = None;
 
     if natural && constraint.is_some() {
@@ -969,7 +1289,7 @@ fn parse_join(
                 for pred in preds {
                     out_where_clause.push(WhereTerm {
                         expr: pred,
-                        from_outer_join: if outer {
+                        from_outer_join: if outer || right {
                             Some(table_references.joined_tables().last().unwrap().internal_id)
                         } else {
                             None
@@ -1022,13 +1342,33 @@ fn parse_join(
                     }
                     let (left_table_idx, left_table_id, left_col_idx, left_col) = left_col.unwrap();
                     let (right_col_idx, right_col) = right_col.unwrap();
+                    let left_expr = Expr::Column {
+                        database: None,
+                        table: left_table_id,
+                        column: left_col_idx,
+                        is_rowid_alias: left_col.is_rowid_alias,
+                    };
+                    // After a RIGHT or FULL OUTER JOIN ... USING, the column is the value of
+                    // whichever side of that join has a row.
+                    let right_join_cols =
+                        using_columns_of_right_joins(left_tables, left_table_idx, &name_normalized);
+                    let left_expr = if right_join_cols.is_empty() {
+                        left_expr
+                    } else {
+                        Expr::FunctionCall {
+                            name: ast::Name::Ident("coalesce".to_string()),
+                            distinctness: None,
+                            args: Some(
+                                std::iter::once(left_expr)
+                                    .chain(right_join_cols.iter().cloned())
+                                    .collect(),
+                            ),
+                            order_by: None,
+                            filter_over: None,
+                        }
+                    };
                     let expr = Expr::Binary(
-                        Box::new(Expr::Column {
-                            database: None,
-                            table: left_table_id,
-                            column: left_col_idx,
-                            is_rowid_alias: left_col.is_rowid_alias,
-                        }),
+                        Box::new(left_expr),
                         ast::Operator::Equals,
                         Box::new(Expr::Column {
                             database: None,
@@ -1043,6 +1383,11 @@ fn parse_join(
                         .get_mut(left_table_idx)
                         .unwrap();
                     left_table.mark_column_used(left_col_idx);
+                    for col in right_join_cols {
+                        if let Expr::Column { table, column, .. } = col {
+                            table_references.mark_column_used(table, column);
+                        }
+                    }
                     let right_table: &mut JoinedTable = table_references
                         .joined_tables_mut()
                         .get_mut(cur_table_idx)
@@ -1050,7 +1395,7 @@ fn parse_join(
                     right_table.mark_column_used(right_col_idx);
                     out_where_clause.push(WhereTerm {
                         expr,
-                        from_outer_join: if outer {
+                        from_outer_join: if outer || right {
                             Some(right_table.internal_id)
                         } else {
                             None
@@ -1069,7 +1414,11 @@ fn parse_join(
         .joined_tables_mut()
         .get_mut(last_idx)
         .unwrap();
-    rightmost_table.join_info = Some(JoinInfo { outer, using });
+    rightmost_table.join_info = Some(JoinInfo {
+        outer,
+        right,
+        using,
+    });
 
     Ok(())
 }
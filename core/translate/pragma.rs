@@ -4,22 +4,29 @@
 use chrono::Datelike;
 use std::rc::Rc;
 use std::sync::Arc;
-use turso_sqlite3_parser::ast::{self, ColumnDefinition, Expr};
+use turso_sqlite3_parser::ast::{self, fmt::ToTokens, ColumnDefinition, Expr};
 use turso_sqlite3_parser::ast::{PragmaName, QualifiedName};
 
 use crate::pragma::pragma_for;
-use crate::schema::Schema;
+use crate::schema::{BTreeTable, Index, Schema};
 use crate::storage::pager::AutoVacuumMode;
 use crate::storage::sqlite3_ondisk::{DatabaseEncoding, MIN_PAGE_CACHE_SIZE};
 use crate::storage::wal::CheckpointMode;
+use crate::translate::fkey::ForeignKeyRef;
 use crate::translate::schema::translate_create_table;
-use crate::util::{normalize_ident, parse_signed_number, parse_string};
-use crate::vdbe::builder::{ProgramBuilder, ProgramBuilderOpts};
+use crate::util::{
+    normalize_ident, parse_pragma_bool, parse_signed_number, parse_string,
+    PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX,
+};
+use crate::vdbe::builder::{CursorType, ProgramBuilder, ProgramBuilderOpts};
 use crate::vdbe::insn::{Cookie, Insn};
-use crate::{bail_parse_error, storage, CaptureDataChangesMode, LimboError, Value};
+use crate::{
+    bail_parse_error, storage, CaptureDataChangesMode, IsolationLevel, LimboError, TempStore, Value,
+};
 use std::str::FromStr;
 use strum::IntoEnumIterator;
 
+use super::analyze::translate_optimize;
 use super::integrity_check::translate_integrity_check;
 use crate::storage::header_accessor;
 use crate::storage::pager::Pager;
@@ -63,7 +70,13 @@ pub fn translate_pragma(
     let (mut program, mode) = match body {
         None => query_pragma(pragma, schema, None, pager, connection, program)?,
         Some(ast::PragmaBody::Equals(value) | ast::PragmaBody::Call(value)) => match pragma {
-            PragmaName::TableInfo => {
+            PragmaName::TableInfo
+            | PragmaName::TableXinfo
+            | PragmaName::IndexInfo
+            | PragmaName::IndexList
+            | PragmaName::IndexXinfo
+            | PragmaName::ForeignKeyList
+            | PragmaName::ForeignKeyCheck => {
                 query_pragma(pragma, schema, Some(value), pager, connection, program)?
             }
             _ => update_pragma(pragma, schema, value, pager, connection, program)?,
@@ -83,6 +96,22 @@ fn update_pragma(
     mut program: ProgramBuilder,
 ) -> crate::Result<(ProgramBuilder, TransactionMode)> {
     match pragma {
+        PragmaName::AnalysisLimit => {
+            // Like in SQLite, a negative limit leaves the limit unchanged
+            if let Value::Integer(limit) = parse_signed_number(&value)? {
+                if limit >= 0 {
+                    connection.set_analysis_limit((limit & 0x7fffffff) as u64);
+                }
+            }
+            query_pragma(
+                PragmaName::AnalysisLimit,
+                schema,
+                None,
+                pager,
+                connection,
+                program,
+            )
+        }
         PragmaName::ApplicationId => {
             let data = parse_signed_number(&value)?;
             let app_id_value = match data {
@@ -108,10 +137,51 @@ fn update_pragma(
             update_cache_size(cache_size, pager, connection)?;
             Ok((program, TransactionMode::None))
         }
+        PragmaName::DeferForeignKeys => {
+            connection.set_defer_foreign_keys(parse_pragma_bool(&value)?);
+            Ok((program, TransactionMode::None))
+        }
         PragmaName::Encoding => {
             let year = chrono::Local::now().year();
             bail_parse_error!("It's {year}. UTF-8 won.");
         }
+        PragmaName::ForeignKeys => {
+            // Like in SQLite, this is a no-op inside a transaction
+            if connection.get_auto_commit() {
+                connection.set_foreign_keys(parse_pragma_bool(&value)?);
+            }
+            Ok((program, TransactionMode::None))
+        }
+        PragmaName::ForeignKeyCheck | PragmaName::ForeignKeyList => {
+            // Queried with a table name argument, see translate_pragma
+            unreachable!();
+        }
+        PragmaName::IgnoreCheckConstraints => {
+            connection.set_ignore_check_constraints(parse_pragma_bool(&value)?);
+            Ok((program, TransactionMode::None))
+        }
+        PragmaName::IndexInfo | PragmaName::IndexList | PragmaName::IndexXinfo => {
+            // Queried with an index or table name argument, see translate_pragma
+            unreachable!();
+        }
+        PragmaName::IsolationLevel => {
+            let isolation_level = match &value {
+                Expr::Name(name) | Expr::Id(name) => {
+                    IsolationLevel::parse(normalize_ident(name.as_str()).trim_matches('\''))
+                }
+                Expr::Literal(ast::Literal::String(s)) => {
+                    IsolationLevel::parse(s.trim_matches('\''))
+                }
+                _ => None,
+            };
+            let Some(isolation_level) = isolation_level else {
+                return Err(LimboError::InvalidArgument(
+                    "invalid isolation level, expected snapshot or serializable".to_string(),
+                ));
+            };
+            connection.set_isolation_level(isolation_level);
+            Ok((program, TransactionMode::None))
+        }
         PragmaName::JournalMode => query_pragma(
             PragmaName::JournalMode,
             schema,
@@ -121,6 +191,14 @@ fn update_pragma(
             program,
         ),
         PragmaName::LegacyFileFormat => Ok((program, TransactionMode::None)),
+        PragmaName::Optimize => {
+            let mask = match parse_signed_number(&value)? {
+                Value::Integer(mask) => mask as u64,
+                _ => bail_parse_error!("Invalid value for optimize pragma"),
+            };
+            let mode = translate_optimize(mask, &connection, &mut program)?;
+            Ok((program, mode))
+        }
         PragmaName::WalCheckpoint => query_pragma(
             PragmaName::WalCheckpoint,
             schema,
@@ -137,6 +215,20 @@ fn update_pragma(
             connection,
             program,
         ),
+        PragmaName::TempStore => {
+            let temp_store = match &value {
+                Expr::Name(name) | Expr::Id(name) => {
+                    TempStore::parse(normalize_ident(name.as_str()).trim_matches('\''))
+                }
+                Expr::Literal(ast::Literal::String(s)) => TempStore::parse(s.trim_matches('\'')),
+                value => match parse_signed_number(value)? {
+                    Value::Integer(i) => TempStore::parse(&i.to_string()),
+                    _ => TempStore::Default,
+                },
+            };
+            connection.set_temp_store(temp_store)?;
+            Ok((program, TransactionMode::None))
+        }
         PragmaName::UserVersion => {
             let data = parse_signed_number(&value)?;
             let version_value = match data {
@@ -159,7 +251,7 @@ fn update_pragma(
             program.emit_insn(Insn::Noop {});
             Ok((program, TransactionMode::None))
         }
-        PragmaName::TableInfo => {
+        PragmaName::TableInfo | PragmaName::TableXinfo => {
             // because we need control over the write parameter for the transaction,
             // this should be unreachable. We have to force-call query_pragma before
             // getting here
@@ -219,6 +311,7 @@ fn update_pragma(
             });
             program.emit_insn(Insn::Halt {
                 err_code: 0,
+                on_error: ast::ResolveType::Abort,
                 description: "Early halt because auto vacuum mode is not enabled".to_string(),
             });
             program.resolve_label(set_cookie_label, program.offset());
@@ -248,7 +341,7 @@ fn update_pragma(
                     )
                     .unwrap(),
                     true,
-                    schema,
+                    &connection,
                     program,
                 )?;
             }
@@ -269,6 +362,12 @@ fn query_pragma(
 ) -> crate::Result<(ProgramBuilder, TransactionMode)> {
     let register = program.alloc_register();
     match pragma {
+        PragmaName::AnalysisLimit => {
+            program.emit_int(connection.get_analysis_limit() as i64, register);
+            program.emit_result_row(register, 1);
+            program.add_pragma_result_column(pragma.to_string());
+            Ok((program, TransactionMode::None))
+        }
         PragmaName::ApplicationId => {
             program.emit_insn(Insn::ReadCookie {
                 db: 0,
@@ -310,6 +409,12 @@ fn query_pragma(
             }
             Ok((program, TransactionMode::None))
         }
+        PragmaName::DeferForeignKeys => {
+            program.emit_bool(connection.get_defer_foreign_keys(), register);
+            program.emit_result_row(register, 1);
+            program.add_pragma_result_column(pragma.to_string());
+            Ok((program, TransactionMode::None))
+        }
         PragmaName::Encoding => {
             let encoding: &str = if !pager.db_state.is_initialized() {
                 DatabaseEncoding::Utf8
@@ -324,6 +429,183 @@ fn query_pragma(
             program.add_pragma_result_column(pragma.to_string());
             Ok((program, TransactionMode::None))
         }
+        PragmaName::ForeignKeyCheck => {
+            let tables = match value {
+                Some(ast::Expr::Name(name)) => {
+                    let tbl = normalize_ident(name.as_str());
+                    match schema.get_btree_table(&tbl) {
+                        Some(table) => vec![table],
+                        None => bail_parse_error!("no such table: {}", tbl),
+                    }
+                }
+                _ => {
+                    let mut tables = schema
+                        .tables
+                        .values()
+                        .filter_map(|table| table.btree())
+                        .collect::<Vec<_>>();
+                    tables.sort_by(|a, b| a.name.cmp(&b.name));
+                    tables
+                }
+            };
+            let base_reg = register;
+            program.alloc_registers(3);
+            for table in tables {
+                emit_foreign_key_check(&mut program, schema, &table, base_reg)?;
+            }
+            let pragma = pragma_for(&pragma);
+            for col_name in pragma.columns.iter() {
+                program.add_pragma_result_column(col_name.to_string());
+            }
+            Ok((program, TransactionMode::Read))
+        }
+        PragmaName::ForeignKeyList => {
+            let table = match value {
+                Some(ast::Expr::Name(name)) => {
+                    let tbl = normalize_ident(name.as_str());
+                    schema.get_btree_table(&tbl)
+                }
+                _ => None,
+            };
+            let base_reg = register;
+            program.alloc_registers(7);
+            if let Some(table) = table {
+                // Foreign keys are numbered from the last one declared, like in SQLite
+                for (id, foreign_key) in table.foreign_keys.iter().rev().enumerate() {
+                    let on_update = foreign_key
+                        .on_update
+                        .format()
+                        .map_err(|e| LimboError::ParseError(e.to_string()))?;
+                    let on_delete = foreign_key
+                        .on_delete
+                        .format()
+                        .map_err(|e| LimboError::ParseError(e.to_string()))?;
+                    for (seq, from) in foreign_key.child_columns.iter().enumerate() {
+                        program.emit_int(id as i64, base_reg);
+                        program.emit_int(seq as i64, base_reg + 1);
+                        program.emit_string8(foreign_key.parent_table.clone(), base_reg + 2);
+                        program.emit_string8(from.clone(), base_reg + 3);
+                        // NULL when the parent primary key is referenced implicitly
+                        match foreign_key.parent_columns.get(seq) {
+                            Some(to) => program.emit_string8(to.clone(), base_reg + 4),
+                            None => program.emit_null(base_reg + 4, None),
+                        }
+                        program.emit_string8(on_update.clone(), base_reg + 5);
+                        program.emit_string8(on_delete.clone(), base_reg + 6);
+                        program.emit_string8("NONE".to_string(), base_reg + 7);
+                        program.emit_result_row(base_reg, 8);
+                    }
+                }
+            }
+            let pragma = pragma_for(&pragma);
+            for col_name in pragma.columns.iter() {
+                program.add_pragma_result_column(col_name.to_string());
+            }
+            Ok((program, TransactionMode::None))
+        }
+        PragmaName::ForeignKeys => {
+            program.emit_bool(connection.foreign_keys_enabled(), register);
+            program.emit_result_row(register, 1);
+            program.add_pragma_result_column(pragma.to_string());
+            Ok((program, TransactionMode::None))
+        }
+        PragmaName::IgnoreCheckConstraints => {
+            program.emit_bool(connection.get_ignore_check_constraints(), register);
+            program.emit_result_row(register, 1);
+            program.add_pragma_result_column(pragma.to_string());
+            Ok((program, TransactionMode::None))
+        }
+        PragmaName::IsolationLevel => {
+            program.emit_string8(connection.get_isolation_level().to_string(), register);
+            program.emit_result_row(register, 1);
+            program.add_pragma_result_column(pragma.to_string());
+            Ok((program, TransactionMode::None))
+        }
+        PragmaName::IndexInfo | PragmaName::IndexXinfo => {
+            let xinfo = pragma == PragmaName::IndexXinfo;
+            let index = match value {
+                Some(ast::Expr::Name(name)) => {
+                    let name = normalize_ident(name.as_str());
+                    // Like in SQLite, the name of a WITHOUT ROWID table stands for its
+                    // primary key index
+                    schema.get_index_by_name(&name).cloned().or_else(|| {
+                        schema
+                            .get_btree_table(&name)
+                            .and_then(|table| schema.get_clustered_index(&table).cloned())
+                    })
+                }
+                _ => None,
+            };
+            let base_reg = register;
+            program.alloc_registers(5);
+            if let Some(index) = index {
+                let columns = if xinfo {
+                    &index.columns[..]
+                } else {
+                    index.key_columns()
+                };
+                let num_key_columns = index.key_columns().len();
+                for (seqno, column) in columns.iter().enumerate() {
+                    program.emit_int(seqno as i64, base_reg);
+                    // Like in SQLite, an expression is column -2 and has no name
+                    if column.is_expression() {
+                        program.emit_int(-2, base_reg + 1);
+                        program.emit_null(base_reg + 2, None);
+                    } else {
+                        program.emit_int(column.pos_in_table as i64, base_reg + 1);
+                        program.emit_string8(column.name.clone(), base_reg + 2);
+                    }
+                    if !xinfo {
+                        program.emit_result_row(base_reg, 3);
+                        continue;
+                    }
+                    program.emit_bool(column.order == ast::SortOrder::Desc, base_reg + 3);
+                    let collation = column.collation.unwrap_or_default();
+                    program.emit_string8(collation.to_string().to_uppercase(), base_reg + 4);
+                    program.emit_bool(seqno < num_key_columns, base_reg + 5);
+                    program.emit_result_row(base_reg, 6);
+                }
+                // The rowid every entry of an index on a rowid table ends with
+                if xinfo && index.has_rowid {
+                    program.emit_int(columns.len() as i64, base_reg);
+                    program.emit_int(-1, base_reg + 1);
+                    program.emit_null(base_reg + 2, None);
+                    program.emit_bool(false, base_reg + 3);
+                    program.emit_string8("BINARY".to_string(), base_reg + 4);
+                    program.emit_bool(false, base_reg + 5);
+                    program.emit_result_row(base_reg, 6);
+                }
+            }
+            for name in pragma_for(&pragma).columns {
+                program.add_pragma_result_column(name.to_string());
+            }
+            Ok((program, TransactionMode::None))
+        }
+        PragmaName::IndexList => {
+            let table = match value {
+                Some(ast::Expr::Name(name)) => {
+                    let tbl = normalize_ident(name.as_str());
+                    schema.get_btree_table(&tbl)
+                }
+                _ => None,
+            };
+            let base_reg = register;
+            program.alloc_registers(4);
+            if let Some(table) = table {
+                for (seq, index) in indexes_in_list_order(schema, &table).iter().enumerate() {
+                    program.emit_int(seq as i64, base_reg);
+                    program.emit_string8(index.name.clone(), base_reg + 1);
+                    program.emit_bool(index.unique, base_reg + 2);
+                    program.emit_string8(index_origin(&table, index).to_string(), base_reg + 3);
+                    program.emit_bool(index.where_clause.is_some(), base_reg + 4);
+                    program.emit_result_row(base_reg, 5);
+                }
+            }
+            for name in pragma_for(&pragma).columns {
+                program.add_pragma_result_column(name.to_string());
+            }
+            Ok((program, TransactionMode::None))
+        }
         PragmaName::JournalMode => {
             program.emit_string8("wal".into(), register);
             program.emit_result_row(register, 1);
@@ -331,6 +613,11 @@ fn query_pragma(
             Ok((program, TransactionMode::None))
         }
         PragmaName::LegacyFileFormat => Ok((program, TransactionMode::None)),
+        PragmaName::Optimize => {
+            // The default mask of SQLite
+            let mode = translate_optimize(0xfffe, &connection, &mut program)?;
+            Ok((program, mode))
+        }
         PragmaName::WalCheckpoint => {
             // Checkpoint uses 3 registers: P1, P2, P3. Ref Insn::Checkpoint for more info.
             // Allocate two more here as one was allocated at the top.
@@ -368,7 +655,8 @@ fn query_pragma(
             program.add_pragma_result_column(pragma.to_string());
             Ok((program, TransactionMode::Read))
         }
-        PragmaName::TableInfo => {
+        PragmaName::TableInfo | PragmaName::TableXinfo => {
+            let xinfo = pragma == PragmaName::TableXinfo;
             let table = match value {
                 Some(ast::Expr::Name(name)) => {
                     let tbl = normalize_ident(name.as_str());
@@ -378,12 +666,18 @@ fn query_pragma(
             };
 
             let base_reg = register;
-            program.alloc_registers(5);
+            program.alloc_registers(6);
             if let Some(table) = table {
                 // According to the SQLite documentation: "The 'cid' column should not be taken to
                 // mean more than 'rank within the current result set'."
-                // Therefore, we enumerate only after filtering out hidden columns.
-                for (i, column) in table.columns().iter().filter(|col| !col.hidden).enumerate() {
+                // Therefore, we enumerate only after filtering out hidden columns. Generated
+                // columns are hidden from table_info too, but not from table_xinfo.
+                for (i, column) in table
+                    .columns()
+                    .iter()
+                    .filter(|col| xinfo || !(col.hidden || col.is_generated()))
+                    .enumerate()
+                {
                     // cid
                     program.emit_int(i as i64, base_reg);
                     // name
@@ -408,15 +702,39 @@ fn query_pragma(
                     // pk
                     program.emit_bool(column.primary_key, base_reg + 5);
 
-                    program.emit_result_row(base_reg, 6);
+                    if !xinfo {
+                        program.emit_result_row(base_reg, 6);
+                        continue;
+                    }
+
+                    // hidden: 1 for hidden columns of virtual tables, 2 for VIRTUAL and 3 for
+                    // STORED generated columns
+                    let hidden = match &column.generated {
+                        Some(generated) if generated.stored => 3,
+                        Some(_) => 2,
+                        None => i64::from(column.hidden),
+                    };
+                    program.emit_int(hidden, base_reg + 6);
+
+                    program.emit_result_row(base_reg, 7);
                 }
             }
-            let col_names = ["cid", "name", "type", "notnull", "dflt_value", "pk"];
-            for name in col_names {
-                program.add_pragma_result_column(name.into());
+            for name in pragma_for(&pragma).columns {
+                program.add_pragma_result_column(name.to_string());
             }
             Ok((program, TransactionMode::None))
         }
+        PragmaName::TempStore => {
+            let value = match connection.get_temp_store() {
+                TempStore::Default => 0,
+                TempStore::File => 1,
+                TempStore::Memory => 2,
+            };
+            program.emit_int(value, register);
+            program.emit_result_row(register, 1);
+            program.add_pragma_result_column(pragma.to_string());
+            Ok((program, TransactionMode::None))
+        }
         PragmaName::UserVersion => {
             program.emit_insn(Insn::ReadCookie {
                 db: 0,
@@ -485,6 +803,108 @@ fn query_pragma(
     }
 }
 
+/// Emit a row for each foreign key constraint a row of `table` violates, like
+/// `PRAGMA foreign_key_check` does.
+fn emit_foreign_key_check(
+    program: &mut ProgramBuilder,
+    schema: &Schema,
+    table: &Rc<BTreeTable>,
+    base_reg: usize,
+) -> crate::Result<()> {
+    // Foreign keys are numbered from the last one declared, like in SQLite
+    let foreign_keys = table
+        .foreign_keys
+        .iter()
+        .rev()
+        .map(|foreign_key| {
+            ForeignKeyRef::resolve(schema, 0, table.clone(), foreign_key)
+                .map(|foreign_key_ref| (&foreign_key.parent_table, foreign_key_ref))
+        })
+        .collect::<crate::Result<Vec<_>>>()?;
+    if foreign_keys.is_empty() {
+        return Ok(());
+    }
+    let columns_start_reg = program.alloc_registers(table.columns.len());
+    let cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(table.clone()));
+    program.emit_insn(Insn::OpenRead {
+        cursor_id,
+        root_page: table.root_page,
+        db: 0,
+    });
+    program.cursor_loop(cursor_id, |program, rowid| {
+        for (fkid, (parent_table, foreign_key)) in foreign_keys.iter().enumerate() {
+            for column in &foreign_key.child_columns {
+                if table.columns[*column].is_rowid_alias {
+                    program.emit_insn(Insn::Copy {
+                        src_reg: rowid,
+                        dst_reg: columns_start_reg + column,
+                        extra_amount: 0,
+                    });
+                } else {
+                    program.emit_table_column(cursor_id, *column, columns_start_reg + column);
+                }
+            }
+            let found_label = program.allocate_label();
+            foreign_key.emit_parent_lookup(program, columns_start_reg, found_label);
+            program.emit_string8(table.name.clone(), base_reg);
+            program.emit_insn(Insn::Copy {
+                src_reg: rowid,
+                dst_reg: base_reg + 1,
+                extra_amount: 0,
+            });
+            program.emit_string8(parent_table.to_string(), base_reg + 2);
+            program.emit_int(fkid as i64, base_reg + 3);
+            program.emit_result_row(base_reg, 4);
+            program.preassign_label_to_next_insn(found_label);
+        }
+    });
+    Ok(())
+}
+
+/// Returns the indexes of `table` in the order `PRAGMA index_list` reports them, which like in
+/// SQLite is the reverse of the order they were created in: the indexes created with CREATE
+/// INDEX, then the automatic indexes of the constraints of the table.
+fn indexes_in_list_order(schema: &Schema, table: &BTreeTable) -> Vec<Arc<Index>> {
+    let automatic_index_number = |index: &Index| {
+        index
+            .name
+            .strip_prefix(PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX)
+            .and_then(|name| name.rsplit('_').next())
+            .and_then(|number| number.parse::<usize>().ok())
+    };
+    let (mut automatic, mut created): (Vec<_>, Vec<_>) = schema
+        .get_indices(&table.name)
+        .iter()
+        .cloned()
+        .partition(|index| automatic_index_number(index).is_some());
+    created.reverse();
+    automatic.sort_by_key(|index| std::cmp::Reverse(automatic_index_number(index)));
+    created.extend(automatic);
+    created
+}
+
+/// Returns how an index was created, as reported by `PRAGMA index_list`: "c" for CREATE INDEX,
+/// "pk" for a PRIMARY KEY constraint and "u" for a UNIQUE constraint.
+fn index_origin(table: &BTreeTable, index: &Index) -> &'static str {
+    if !index
+        .name
+        .starts_with(PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX)
+    {
+        return "c";
+    }
+    let key_columns = index.key_columns();
+    let is_primary_key = key_columns.len() == table.primary_key_columns.len()
+        && key_columns
+            .iter()
+            .zip(table.primary_key_columns.iter())
+            .all(|(column, (name, _))| column.name == normalize_ident(name));
+    if is_primary_key {
+        "pk"
+    } else {
+        "u"
+    }
+}
+
 fn update_auto_vacuum_mode(
     auto_vacuum_mode: AutoVacuumMode,
     largest_root_page_number: u32,
@@ -9,7 +9,7 @@ use crate::{
 
 use super::{
     emitter::{LimitCtx, Resolver},
-    expr::translate_expr,
+    expr::{translate_expr, translate_expr_no_constant_opt, NoConstantOptReason},
     plan::{Distinctness, QueryDestination, SelectPlan},
 };
 
@@ -45,13 +45,30 @@ pub fn emit_select_result(
             || reg_nonagg_emit_once_flag.is_none()
     }) {
         let reg = start_reg + i;
-        translate_expr(
-            program,
-            Some(&plan.table_references),
-            &rc.expr,
-            reg,
-            resolver,
-        )?;
+        // The subselects of a compound SELECT that yields to a coroutine all yield the same
+        // registers, also when they are first deduplicated in an ephemeral index, so a constant
+        // result column must not be hoisted out of the coroutine.
+        if matches!(
+            plan.query_destination,
+            QueryDestination::CoroutineYield { .. } | QueryDestination::EphemeralIndex { .. }
+        ) {
+            translate_expr_no_constant_opt(
+                program,
+                Some(&plan.table_references),
+                &rc.expr,
+                reg,
+                resolver,
+                NoConstantOptReason::RegisterReuse,
+            )?;
+        } else {
+            translate_expr(
+                program,
+                Some(&plan.table_references),
+                &rc.expr,
+                reg,
+                resolver,
+            )?;
+        }
     }
 
     // Handle SELECT DISTINCT deduplication
@@ -111,6 +128,26 @@ pub fn emit_result_row_and_limit(
                 });
             }
         }
+        QueryDestination::EphemeralTable {
+            cursor_id: table_cursor_id,
+            table,
+        } if !table.has_rowid => {
+            // Without a rowid, the result row is the key of an ephemeral index.
+            let record_reg = program.alloc_register();
+            program.emit_insn(Insn::MakeRecord {
+                start_reg: result_columns_start_reg,
+                count: plan.result_columns.len(),
+                dest_reg: record_reg,
+                index_name: Some(table.name.clone()),
+            });
+            program.emit_insn(Insn::IdxInsert {
+                cursor_id: *table_cursor_id,
+                record_reg,
+                unpacked_start: None,
+                unpacked_count: None,
+                flags: IdxInsertFlags::new(),
+            });
+        }
         QueryDestination::EphemeralTable {
             cursor_id: table_cursor_id,
             table,
@@ -2,22 +2,25 @@ use turso_sqlite3_parser::ast::Name;
 
 use crate::{
     schema::Schema,
-    translate::emitter::TransactionMode,
-    vdbe::{builder::ProgramBuilder, insn::Insn},
+    translate::{emitter::TransactionMode, transaction::translate_savepoint_op},
+    vdbe::{
+        builder::ProgramBuilder,
+        insn::{Insn, SavepointOp},
+    },
     Result, SymbolTable,
 };
 
 pub fn translate_rollback(
     _schema: &Schema,
     _syms: &SymbolTable,
-    mut program: ProgramBuilder,
-    txn_name: Option<Name>,
+    program: ProgramBuilder,
+    _txn_name: Option<Name>,
     savepoint_name: Option<Name>,
 ) -> Result<ProgramBuilder> {
-    assert!(
-        txn_name.is_none() && savepoint_name.is_none(),
-        "txn_name and savepoint not supported yet"
-    );
+    if let Some(savepoint_name) = savepoint_name {
+        return translate_savepoint_op(SavepointOp::RollbackTo, savepoint_name, program);
+    }
+    let mut program = program;
     program.emit_insn(Insn::AutoCommit {
         auto_commit: true,
         rollback: true,
@@ -1,40 +1,53 @@
 use std::collections::HashSet;
 use std::ops::Range;
 use std::rc::Rc;
+use std::sync::Arc;
 
 use crate::ast;
 use crate::ext::VTabImpl;
+use crate::schema::Affinity;
 use crate::schema::BTreeTable;
 use crate::schema::Column;
 use crate::schema::Schema;
 use crate::schema::Table;
 use crate::schema::Type;
 use crate::storage::pager::CreateBTreeFlags;
+use crate::translate::check::validate_check_expr;
+use crate::translate::emitter::emit_program;
+use crate::translate::expr::get_expr_affinity;
+use crate::translate::generated::validate_generated_columns;
+use crate::translate::optimizer::optimize_plan;
+use crate::translate::plan::{Plan, QueryDestination};
+use crate::translate::select::prepare_select_plan;
 use crate::translate::ProgramBuilder;
 use crate::translate::ProgramBuilderOpts;
 use crate::util::normalize_ident;
+use crate::util::quote_ident;
 use crate::util::PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX;
 use crate::vdbe::builder::CursorType;
 use crate::vdbe::insn::Cookie;
-use crate::vdbe::insn::{CmpInsFlags, InsertFlags, Insn};
+use crate::vdbe::insn::{CmpInsFlags, InsertFlags, Insn, RegisterOrLiteral};
+use crate::Connection;
 use crate::LimboError;
 use crate::SymbolTable;
 use crate::{bail_parse_error, Result};
 
 use turso_ext::VTabKind;
 use turso_sqlite3_parser::ast::{fmt::ToTokens, CreateVirtualTable};
+use turso_sqlite3_parser::dialect::{keyword_token, TokenType};
+use turso_sqlite3_parser::lexer::sql::Tokenizer;
+use turso_sqlite3_parser::lexer::Scanner;
 
 pub fn translate_create_table(
     tbl_name: ast::QualifiedName,
     temporary: bool,
     body: ast::CreateTableBody,
     if_not_exists: bool,
-    schema: &Schema,
+    connection: &Arc<Connection>,
     mut program: ProgramBuilder,
 ) -> Result<ProgramBuilder> {
-    if temporary {
-        bail_parse_error!("TEMPORARY table not supported yet");
-    }
+    let database_id = connection.resolve_create_database_id(&tbl_name, temporary)?;
+    let schema = &connection.database_schema(database_id);
     let opts = ProgramBuilderOpts {
         num_cursors: 1,
         approx_num_insns: 30,
@@ -42,6 +55,9 @@ pub fn translate_create_table(
     };
     program.extend(&opts);
     let normalized_tbl_name = normalize_ident(tbl_name.name.as_str());
+    if schema.get_view(&normalized_tbl_name).is_some() {
+        bail_parse_error!("view {} already exists", normalized_tbl_name);
+    }
     if schema.get_table(&normalized_tbl_name).is_some() {
         if if_not_exists {
             program.epilogue(crate::translate::emitter::TransactionMode::Write);
@@ -51,7 +67,35 @@ pub fn translate_create_table(
         bail_parse_error!("Table {} already exists", normalized_tbl_name);
     }
 
+    if let ast::CreateTableBody::ColumnsAndConstraints {
+        columns,
+        constraints,
+        ..
+    } = &body
+    {
+        let column_checks = columns
+            .values()
+            .flat_map(|column| &column.constraints)
+            .filter_map(|constraint| match &constraint.constraint {
+                ast::ColumnConstraint::Check(expr) => Some(expr),
+                _ => None,
+            });
+        let table_checks = constraints
+            .iter()
+            .flatten()
+            .filter_map(|constraint| match &constraint.constraint {
+                ast::TableConstraint::Check(expr) => Some(expr),
+                _ => None,
+            });
+        for expr in column_checks.chain(table_checks) {
+            validate_check_expr(expr)?;
+        }
+    }
+
     let sql = create_table_body_to_str(&tbl_name, &body);
+    if matches!(body, ast::CreateTableBody::ColumnsAndConstraints { .. }) {
+        validate_generated_columns(&BTreeTable::from_sql(&sql, 0)?)?;
+    }
 
     let parse_schema_label = program.allocate_label();
     // TODO: ReadCookie
@@ -59,12 +103,21 @@ pub fn translate_create_table(
     // TODO: SetCookie
     // TODO: SetCookie
 
-    // Create the table B-tree
+    // Create the table B-tree. A table without rowid is an index B-tree keyed by its primary key.
+    let without_rowid = matches!(
+        &body,
+        ast::CreateTableBody::ColumnsAndConstraints { options, .. }
+            if options.contains(ast::TableOptions::WITHOUT_ROWID)
+    );
     let table_root_reg = program.alloc_register();
     program.emit_insn(Insn::CreateBtree {
-        db: 0,
+        db: database_id,
         root: table_root_reg,
-        flags: CreateBTreeFlags::new_table(),
+        flags: if without_rowid {
+            CreateBTreeFlags::new_index()
+        } else {
+            CreateBTreeFlags::new_table()
+        },
     });
 
     // Create an automatic index B-tree if needed
@@ -99,7 +152,7 @@ pub fn translate_create_table(
         }
         for index_reg in index_regs.clone() {
             program.emit_insn(Insn::CreateBtree {
-                db: 0,
+                db: database_id,
                 root: index_reg,
                 flags: CreateBTreeFlags::new_index(),
             });
@@ -111,7 +164,7 @@ pub fn translate_create_table(
     program.emit_insn(Insn::OpenWrite {
         cursor_id: sqlite_schema_cursor_id,
         root_page: 1usize.into(),
-        db: 0,
+        db: database_id,
     });
 
     // Add the table entry to sqlite_schema
@@ -149,7 +202,7 @@ pub fn translate_create_table(
     program.resolve_label(parse_schema_label, program.offset());
     // TODO: SetCookie
     program.emit_insn(Insn::SetCookie {
-        db: 0,
+        db: database_id,
         cookie: Cookie::SchemaVersion,
         value: schema.schema_version as i32 + 1,
         p5: 0,
@@ -158,7 +211,7 @@ pub fn translate_create_table(
     let parse_schema_where_clause =
         format!("tbl_name = '{normalized_tbl_name}' AND type != 'trigger'");
     program.emit_insn(Insn::ParseSchema {
-        db: sqlite_schema_cursor_id,
+        db: database_id,
         where_clause: Some(parse_schema_where_clause),
     });
 
@@ -168,10 +221,282 @@ pub fn translate_create_table(
     Ok(program)
 }
 
+/// Returns the source text of each result column of the first SELECT in the last statement of
+/// `input`, or nothing if `input` cannot be tokenized.
+fn result_column_spans(input: &str) -> Vec<&str> {
+    let mut scanner = Scanner::new(Tokenizer::new());
+    let mut tokens = vec![];
+    let mut statement_ended = false;
+    loop {
+        match scanner.scan(input.as_bytes()) {
+            Ok((_, None, _)) => break,
+            Ok((_, Some((_, TokenType::TK_SEMI)), _)) => statement_ended = true,
+            Ok((start, Some((_, token_type)), end)) => {
+                if statement_ended {
+                    tokens.clear();
+                    statement_ended = false;
+                }
+                tokens.push((start, token_type, end));
+            }
+            Err(_) => return vec![],
+        }
+    }
+
+    let mut depth = 0usize;
+    let tokens = tokens
+        .into_iter()
+        .skip_while(|(_, token_type, _)| {
+            match token_type {
+                TokenType::TK_LP => depth += 1,
+                TokenType::TK_RP => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+            depth > 0 || *token_type != TokenType::TK_SELECT
+        })
+        .skip(1)
+        .skip_while(|(_, token_type, _)| {
+            matches!(token_type, TokenType::TK_DISTINCT | TokenType::TK_ALL)
+        });
+
+    let mut spans = vec![];
+    let mut span: Option<(usize, usize)> = None;
+    let mut depth = 0usize;
+    for (start, token_type, end) in tokens {
+        if depth == 0 {
+            match token_type {
+                TokenType::TK_COMMA => {
+                    spans.extend(span.take());
+                    continue;
+                }
+                TokenType::TK_FROM
+                | TokenType::TK_WHERE
+                | TokenType::TK_GROUP
+                | TokenType::TK_HAVING
+                | TokenType::TK_WINDOW
+                | TokenType::TK_ORDER
+                | TokenType::TK_LIMIT
+                | TokenType::TK_UNION
+                | TokenType::TK_INTERSECT
+                | TokenType::TK_EXCEPT => break,
+                _ => {}
+            }
+        }
+        match token_type {
+            TokenType::TK_LP => depth += 1,
+            TokenType::TK_RP => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        span = Some((span.map_or(start, |(start, _)| start), end));
+    }
+    spans.extend(span);
+    spans
+        .into_iter()
+        .map(|(start, end)| &input[start..end])
+        .collect()
+}
+
+/// Translates `CREATE TABLE ... AS SELECT`. Like SQLite, the columns of the table are named after
+/// the result columns of the SELECT and declared with the type of their affinity, and the table
+/// is populated with the rows of the SELECT by the same statement.
+#[allow(clippy::too_many_arguments)]
+pub fn translate_create_table_as_select(
+    tbl_name: ast::QualifiedName,
+    temporary: bool,
+    mut select: ast::Select,
+    if_not_exists: bool,
+    schema: &Schema,
+    syms: &SymbolTable,
+    mut program: ProgramBuilder,
+    connection: &Arc<Connection>,
+    input: &str,
+) -> Result<ProgramBuilder> {
+    let database_id = connection.resolve_create_database_id(&tbl_name, temporary)?;
+    let target_schema = &connection.database_schema(database_id);
+    let normalized_tbl_name = normalize_ident(tbl_name.name.as_str());
+    if target_schema.get_view(&normalized_tbl_name).is_some() {
+        bail_parse_error!("view {} already exists", normalized_tbl_name);
+    }
+    if target_schema.get_table(&normalized_tbl_name).is_some() {
+        if if_not_exists {
+            program.epilogue(crate::translate::emitter::TransactionMode::Write);
+            return Ok(program);
+        }
+        bail_parse_error!("Table {} already exists", normalized_tbl_name);
+    }
+
+    // The columns that are neither named nor plain column references are named after the source
+    // text of their expression, which is lost once they are bound.
+    if let ast::OneSelect::Select(inner) = select.body.select.as_mut() {
+        let spans = result_column_spans(input);
+        let spans = if spans.len() == inner.columns.len() {
+            spans
+        } else {
+            vec![]
+        };
+        for (i, column) in inner.columns.iter_mut().enumerate() {
+            if let ast::ResultColumn::Expr(expr, alias @ None) = column {
+                if !matches!(
+                    expr,
+                    ast::Expr::Id(_) | ast::Expr::Qualified(..) | ast::Expr::DoublyQualified(..)
+                ) {
+                    let name = match spans.get(i) {
+                        Some(span) => span.to_string(),
+                        None => expr.to_string(),
+                    };
+                    *alias = Some(ast::As::As(ast::Name::Ident(name)));
+                }
+            }
+        }
+    }
+
+    let table_root_reg = program.alloc_register();
+    // The SELECT yields its rows in the registers following `yield_reg`.
+    let yield_reg = program.alloc_register();
+    let start_offset_label = program.allocate_label();
+    let mut plan = prepare_select_plan(
+        schema,
+        select,
+        syms,
+        &[],
+        &[],
+        &mut program.table_reference_counter,
+        QueryDestination::CoroutineYield {
+            yield_reg,
+            coroutine_implementation_start: start_offset_label,
+        },
+        connection,
+    )?;
+    optimize_plan(&mut plan, schema)?;
+
+    // The columns of a compound SELECT are those of its first SELECT.
+    let first_select = match &plan {
+        Plan::Select(select) => select,
+        Plan::CompoundSelect { left, .. } => &left[0].0,
+        _ => unreachable!("a SELECT must be planned as a SELECT"),
+    };
+    let mut columns: Vec<(String, Affinity)> = vec![];
+    for (i, result_column) in first_select.result_columns.iter().enumerate() {
+        let name = match result_column.name(&first_select.table_references) {
+            Some(name) => normalize_ident(name),
+            None => format!("column{}", i + 1),
+        };
+        // Like SQLite, a name that is already taken gets a numbered suffix.
+        let mut unique_name = name.clone();
+        let mut suffix = 0;
+        while columns.iter().any(|(taken, _)| *taken == unique_name) {
+            suffix += 1;
+            unique_name = format!("{name}:{suffix}");
+        }
+        let affinity = match &result_column.expr {
+            ast::Expr::RowId { .. } => Affinity::Integer,
+            expr => get_expr_affinity(expr, Some(&first_select.table_references)),
+        };
+        columns.push((unique_name, affinity));
+    }
+    let sql = create_table_as_select_to_str(&normalized_tbl_name, &columns);
+    let table = Rc::new(BTreeTable::from_sql(&sql, 0)?);
+
+    program.emit_insn(Insn::CreateBtree {
+        db: database_id,
+        root: table_root_reg,
+        flags: CreateBTreeFlags::new_table(),
+    });
+
+    // Populate the table with the rows the SELECT yields.
+    let jump_on_definition_label = program.allocate_label();
+    program.emit_insn(Insn::InitCoroutine {
+        yield_reg,
+        jump_on_definition: jump_on_definition_label,
+        start_offset: start_offset_label,
+    });
+    program.preassign_label_to_next_insn(start_offset_label);
+    program.incr_nesting();
+    emit_program(&mut program, plan, schema, syms, |_| {})?;
+    program.decr_nesting();
+    program.emit_insn(Insn::EndCoroutine { yield_reg });
+    program.preassign_label_to_next_insn(jump_on_definition_label);
+
+    let cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(table.clone()));
+    program.emit_insn(Insn::OpenWrite {
+        cursor_id,
+        root_page: RegisterOrLiteral::Register(table_root_reg),
+        db: database_id,
+    });
+    let loop_start_label = program.allocate_label();
+    let loop_end_label = program.allocate_label();
+    program.preassign_label_to_next_insn(loop_start_label);
+    program.emit_insn(Insn::Yield {
+        yield_reg,
+        end_offset: loop_end_label,
+    });
+    let record_reg = program.alloc_register();
+    program.emit_insn(Insn::MakeRecord {
+        start_reg: yield_reg + 1,
+        count: table.columns.len(),
+        dest_reg: record_reg,
+        index_name: None,
+    });
+    let rowid_reg = program.alloc_register();
+    program.emit_insn(Insn::NewRowid {
+        cursor: cursor_id,
+        rowid_reg,
+        prev_largest_reg: 0,
+    });
+    program.emit_insn(Insn::Insert {
+        cursor: cursor_id,
+        key_reg: rowid_reg,
+        record_reg,
+        flag: InsertFlags::new(),
+        table_name: normalized_tbl_name.clone(),
+    });
+    program.emit_insn(Insn::Goto {
+        target_pc: loop_start_label,
+    });
+    program.preassign_label_to_next_insn(loop_end_label);
+    program.close_cursors(&[cursor_id]);
+
+    let sqlite_schema = target_schema.get_btree_table(SQLITE_TABLEID).unwrap();
+    let sqlite_schema_cursor_id =
+        program.alloc_cursor_id(CursorType::BTreeTable(sqlite_schema.clone()));
+    program.emit_insn(Insn::OpenWrite {
+        cursor_id: sqlite_schema_cursor_id,
+        root_page: 1usize.into(),
+        db: database_id,
+    });
+    emit_schema_entry(
+        &mut program,
+        sqlite_schema_cursor_id,
+        SchemaEntryType::Table,
+        &normalized_tbl_name,
+        &normalized_tbl_name,
+        table_root_reg,
+        Some(sql),
+    );
+
+    program.emit_insn(Insn::SetCookie {
+        db: database_id,
+        cookie: Cookie::SchemaVersion,
+        value: target_schema.schema_version as i32 + 1,
+        p5: 0,
+    });
+    let parse_schema_where_clause =
+        format!("tbl_name = '{normalized_tbl_name}' AND type != 'trigger'");
+    program.emit_insn(Insn::ParseSchema {
+        db: database_id,
+        where_clause: Some(parse_schema_where_clause),
+    });
+
+    program.epilogue(super::emitter::TransactionMode::Write);
+
+    Ok(program)
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum SchemaEntryType {
     Table,
     Index,
+    Trigger,
+    View,
 }
 
 impl SchemaEntryType {
@@ -179,6 +504,8 @@ impl SchemaEntryType {
         match self {
             SchemaEntryType::Table => "table",
             SchemaEntryType::Index => "index",
+            SchemaEntryType::Trigger => "trigger",
+            SchemaEntryType::View => "view",
         }
     }
 }
@@ -208,7 +535,7 @@ pub fn emit_schema_entry(
     if root_page_reg == 0 {
         program.emit_insn(Insn::Integer {
             dest: rootpage_reg,
-            value: 0, // virtual tables in sqlite always have rootpage=0
+            value: 0, // virtual tables, triggers and views in sqlite always have rootpage=0
         });
     } else {
         program.emit_insn(Insn::Copy {
@@ -338,13 +665,9 @@ fn check_automatic_pk_index_required(
                         }
                     } else if let ast::TableConstraint::Unique {
                         columns: unique_columns,
-                        conflict_clause,
+                        ..
                     } = &constraint.constraint
                     {
-                        if conflict_clause.is_some() {
-                            unimplemented!("ON CONFLICT not implemented");
-                        }
-
                         let col_names = unique_columns
                             .iter()
                             .map(|column| match &column.expr {
@@ -355,7 +678,9 @@ fn check_automatic_pk_index_required(
                                     Ok(crate::util::normalize_ident(id.as_str()))
                                 }
                                 _ => {
-                                    todo!("Unsupported unique expression");
+                                    bail_parse_error!(
+                                        "expressions prohibited in PRIMARY KEY and UNIQUE constraints"
+                                    );
                                 }
                             })
                             .collect::<Result<HashSet<String>>>()?;
@@ -388,9 +713,10 @@ fn check_automatic_pk_index_required(
                 }
             }
 
-            // Check if table has rowid
-            if options.contains(ast::TableOptions::WITHOUT_ROWID) {
-                bail_parse_error!("WITHOUT ROWID tables are not supported yet");
+            // A table without rowid is stored in its primary key index
+            let without_rowid = options.contains(ast::TableOptions::WITHOUT_ROWID);
+            if without_rowid && primary_key_definition.is_none() {
+                bail_parse_error!("PRIMARY KEY missing on table {}", tbl_name);
             }
 
             unique_sets.dedup();
@@ -421,7 +747,12 @@ fn check_automatic_pk_index_required(
             };
             let mut total_indices = unique_sets.len();
             // if pk needs and index, but we already found out we primary key is unique, we only need a single index since constraint pk == unique
-            if auto_index_pk && !pk_is_unique {
+            if without_rowid {
+                // the table itself is the primary key index
+                if pk_is_unique {
+                    total_indices -= 1;
+                }
+            } else if auto_index_pk && !pk_is_unique {
                 total_indices += 1;
             }
 
@@ -466,11 +797,69 @@ fn create_table_body_to_str(tbl_name: &ast::QualifiedName, body: &ast::CreateTab
             constraints: _,
             options: _,
         } => {}
-        ast::CreateTableBody::AsSelect(_select) => todo!("as select not yet supported"),
+        ast::CreateTableBody::AsSelect(_) => {
+            unreachable!(
+                "CREATE TABLE ... AS SELECT is translated by translate_create_table_as_select"
+            )
+        }
     }
     sql
 }
 
+/// Formats the `CREATE TABLE` statement of a table created by `CREATE TABLE ... AS SELECT` the
+/// way SQLite does, declaring each column with the type of its affinity.
+fn create_table_as_select_to_str(tbl_name: &str, columns: &[(String, Affinity)]) -> String {
+    // SQLite only breaks the statement into lines when it is long.
+    let len = columns
+        .iter()
+        .map(|(name, _)| quoted_ident_len(name) + 5)
+        .sum::<usize>()
+        + quoted_ident_len(tbl_name);
+    let (start, separator, end) = if len < 50 {
+        ("", ",", ")")
+    } else {
+        ("\n  ", ",\n  ", "\n)")
+    };
+    let columns = columns
+        .iter()
+        .map(|(name, affinity)| {
+            let ty = match affinity {
+                Affinity::Text => " TEXT",
+                Affinity::Numeric => " NUM",
+                Affinity::Integer => " INT",
+                Affinity::Real => " REAL",
+                Affinity::Blob => "",
+            };
+            format!("{}{ty}", quote_ident_if_needed(name))
+        })
+        .collect::<Vec<_>>()
+        .join(separator);
+    format!(
+        "CREATE TABLE {}({start}{columns}{end}",
+        quote_ident_if_needed(tbl_name)
+    )
+}
+
+/// Quotes an identifier unless it is made of alphanumeric characters and underscores only and is
+/// not a keyword.
+fn quote_ident_if_needed(identifier: &str) -> String {
+    let plain = !identifier.is_empty()
+        && !identifier.starts_with(|c: char| c.is_ascii_digit())
+        && identifier
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && keyword_token(identifier.as_bytes()).is_none();
+    if plain {
+        identifier.to_string()
+    } else {
+        quote_ident(identifier)
+    }
+}
+
+fn quoted_ident_len(identifier: &str) -> usize {
+    identifier.len() + identifier.matches('"').count() + 2
+}
+
 fn create_vtable_body_to_str(vtab: &CreateVirtualTable, module: Rc<VTabImpl>) -> String {
     let args = if let Some(args) = &vtab.args {
         args.iter()
@@ -539,6 +928,9 @@ pub fn translate_create_virtual_table(
     if !vtab_module.module_kind.eq(&VTabKind::VirtualTable) {
         bail_parse_error!("module {} is not a virtual table", module_name_str);
     };
+    if schema.get_view(&table_name).is_some() {
+        bail_parse_error!("view {} already exists", tbl_name);
+    }
     if schema.get_table(&table_name).is_some() {
         if *if_not_exists {
             program.epilogue(crate::translate::emitter::TransactionMode::Write);
@@ -608,7 +1000,7 @@ pub fn translate_create_virtual_table(
     });
     let parse_schema_where_clause = format!("tbl_name = '{table_name}' AND type != 'trigger'");
     program.emit_insn(Insn::ParseSchema {
-        db: sqlite_schema_cursor_id,
+        db: 0,
         where_clause: Some(parse_schema_where_clause),
     });
 
@@ -620,9 +1012,11 @@ pub fn translate_create_virtual_table(
 pub fn translate_drop_table(
     tbl_name: ast::QualifiedName,
     if_exists: bool,
-    schema: &Schema,
+    connection: &Arc<Connection>,
     mut program: ProgramBuilder,
 ) -> Result<ProgramBuilder> {
+    let database_id = connection.resolve_database_id(&tbl_name)?;
+    let schema = &connection.database_schema(database_id);
     if !schema.indexes_enabled() && schema.table_has_indexes(&tbl_name.name.to_string()) {
         bail_parse_error!(
             "DROP TABLE with indexes on the table is disabled by default. Run with `--experimental-indexes` to enable this feature."
@@ -636,6 +1030,9 @@ pub fn translate_drop_table(
     program.extend(&opts);
     let table = schema.get_table(tbl_name.name.as_str());
     if table.is_none() {
+        if schema.get_view(tbl_name.name.as_str()).is_some() {
+            bail_parse_error!("use DROP VIEW to delete view {}", tbl_name.name.as_str());
+        }
         if if_exists {
             program.epilogue(crate::translate::emitter::TransactionMode::Write);
 
@@ -651,9 +1048,7 @@ pub fn translate_drop_table(
     let table_name_and_root_page_register = program.alloc_register(); //  r2, this register is special because it's first used to track table name and then moved root page
     let table_reg = program.emit_string8_new_reg(tbl_name.name.as_str().to_string()); //  r3
     program.mark_last_insn_constant();
-    let table_type = program.emit_string8_new_reg("trigger".to_string()); //  r4
-    program.mark_last_insn_constant();
-    let row_id_reg = program.alloc_register(); //  r5
+    let row_id_reg = program.alloc_register(); //  r4
 
     let schema_table = schema.get_btree_table(SQLITE_TABLEID).unwrap();
     let sqlite_schema_cursor_id_0 = program.alloc_cursor_id(
@@ -663,10 +1058,10 @@ pub fn translate_drop_table(
     program.emit_insn(Insn::OpenWrite {
         cursor_id: sqlite_schema_cursor_id_0,
         root_page: 1usize.into(),
-        db: 0,
+        db: database_id,
     });
 
-    //  1. Remove all entries from the schema table related to the table we are dropping, except for triggers
+    //  1. Remove all entries from the schema table related to the table we are dropping, including its triggers
     //  loop to beginning of schema table
     let end_metadata_label = program.allocate_label();
     let metadata_loop = program.allocate_label();
@@ -690,18 +1085,6 @@ pub fn translate_drop_table(
         flags: CmpInsFlags::default(),
         collation: program.curr_collation(),
     });
-    program.emit_column(
-        sqlite_schema_cursor_id_0,
-        0,
-        table_name_and_root_page_register,
-    );
-    program.emit_insn(Insn::Eq {
-        lhs: table_name_and_root_page_register,
-        rhs: table_type,
-        target_pc: next_label,
-        flags: CmpInsFlags::default(),
-        collation: program.curr_collation(),
-    });
     program.emit_insn(Insn::RowId {
         cursor_id: sqlite_schema_cursor_id_0,
         dest: row_id_reg,
@@ -719,19 +1102,18 @@ pub fn translate_drop_table(
     //  end of loop on schema table
 
     //  2. Destroy the indices within a loop
+    //  The clustered index of a table without rowid is destroyed with the table.
     let indices = schema.get_indices(tbl_name.name.as_str());
-    for index in indices {
+    for index in indices.iter().filter(|index| {
+        table
+            .btree()
+            .is_none_or(|table| index.root_page != table.root_page)
+    }) {
         program.emit_insn(Insn::Destroy {
             root: index.root_page,
             former_root_reg: 0, //  no autovacuum (https://www.sqlite.org/opcode.html#Destroy)
-            is_temp: 0,
+            db: database_id,
         });
-
-        //  3. TODO: Open an ephemeral table, and read over triggers from schema table into ephemeral table
-        //  Requires support via https://github.com/tursodatabase/turso/pull/768
-
-        //  4. TODO: Open a write cursor to the schema table and re-insert all triggers into the sqlite schema table from the ephemeral table and delete old trigger
-        //  Requires support via https://github.com/tursodatabase/turso/pull/768
     }
 
     //  3. Destroy the table structure
@@ -740,7 +1122,7 @@ pub fn translate_drop_table(
             program.emit_insn(Insn::Destroy {
                 root: table.root_page,
                 former_root_reg: table_name_and_root_page_register,
-                is_temp: 0,
+                db: database_id,
             });
         }
         Table::Virtual(vtab) => {
@@ -759,6 +1141,7 @@ pub fn translate_drop_table(
             });
         }
         Table::FromClauseSubquery(..) => panic!("FromClauseSubquery can't be dropped"),
+        Table::Cte(..) => panic!("CTE can't be dropped"),
     };
 
     let schema_data_register = program.alloc_register();
@@ -788,9 +1171,14 @@ pub fn translate_drop_table(
                 unique: false,
                 collation: None,
                 hidden: false,
+                generated: None,
             }],
             is_strict: false,
             unique_sets: None,
+            conflict_clauses: vec![],
+            foreign_keys: vec![],
+            checks: vec![],
+            notnull_conflict_clauses: vec![],
         });
         //  cursor id 2
         let ephemeral_cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(simple_table_rc));
@@ -807,7 +1195,7 @@ pub fn translate_drop_table(
         program.emit_insn(Insn::OpenRead {
             cursor_id: sqlite_schema_cursor_id_1,
             root_page: 1usize,
-            db: 0,
+            db: database_id,
         });
 
         let schema_column_0_register = program.alloc_register();
@@ -864,7 +1252,7 @@ pub fn translate_drop_table(
         program.emit_insn(Insn::OpenWrite {
             cursor_id: sqlite_schema_cursor_id_1,
             root_page: 1usize.into(),
-            db: 0,
+            db: database_id,
         });
 
         //  Loop to copy over row id's from the ephemeral table and then re-insert into the schema table with the correct root page
@@ -927,14 +1315,14 @@ pub fn translate_drop_table(
 
     //  Drop the in-memory structures for the table
     program.emit_insn(Insn::DropTable {
-        db: 0,
+        db: database_id,
         _p2: 0,
         _p3: 0,
         table_name: tbl_name.name.as_str().to_string(),
     });
 
     program.emit_insn(Insn::SetCookie {
-        db: 0,
+        db: database_id,
         cookie: Cookie::SchemaVersion,
         value: schema.schema_version as i32 + 1,
         p5: 0,
@@ -1,21 +1,25 @@
 use super::emitter::{emit_program, TranslateCtx};
 use super::plan::{
-    select_star, Distinctness, JoinOrderMember, Operation, OuterQueryReference, QueryDestination,
-    Search, TableReferences,
+    select_star, Cte, CteBody, CteDefinition, Distinctness, JoinOrderMember, Operation,
+    OuterQueryReference, QueryDestination, Search, TableReferences,
 };
 use crate::function::{AggFunc, ExtFunc, Func};
 use crate::schema::Table;
+use crate::translate::cte::{parse_with, referenced_materialized_ctes};
 use crate::translate::optimizer::optimize_plan;
 use crate::translate::plan::{Aggregate, GroupBy, Plan, ResultSetColumn, SelectPlan};
 use crate::translate::planner::{
     bind_column_references, break_predicate_at_and_boundaries, parse_from, parse_limit,
-    parse_where, resolve_aggregates,
+    parse_where, resolve_aggregates, resolve_filter_clause,
 };
-use crate::util::normalize_ident;
+use crate::translate::subquery::plan_subqueries_from_expr;
+use crate::translate::window;
+use crate::util::{exprs_are_equivalent, normalize_ident};
 use crate::vdbe::builder::{ProgramBuilderOpts, TableRefIdCounter};
 use crate::vdbe::insn::Insn;
 use crate::SymbolTable;
 use crate::{schema::Schema, vdbe::builder::ProgramBuilder, Result};
+use std::rc::Rc;
 use std::sync::Arc;
 use turso_sqlite3_parser::ast::{self, CompoundSelect, SortOrder};
 use turso_sqlite3_parser::ast::{ResultColumn, SelectInner};
@@ -38,6 +42,7 @@ pub fn translate_select(
         select,
         syms,
         &[],
+        &[],
         &mut program.table_reference_counter,
         query_destination,
         connection,
@@ -88,11 +93,13 @@ pub fn translate_select(
     })
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn prepare_select_plan(
     schema: &Schema,
     mut select: ast::Select,
     syms: &SymbolTable,
     outer_query_refs: &[OuterQueryReference],
+    ctes: &[Rc<Cte>],
     table_ref_counter: &mut TableRefIdCounter,
     query_destination: QueryDestination,
     connection: &Arc<crate::Connection>,
@@ -109,11 +116,27 @@ pub fn prepare_select_plan(
                 select.with.take(),
                 syms,
                 outer_query_refs,
+                ctes,
                 table_ref_counter,
                 query_destination,
                 connection,
             )?))
         }
+        Some(compounds)
+            if select.order_by.is_some() || select.with.is_some() || has_offset(&select)? =>
+        {
+            select.body.compounds = Some(compounds);
+            prepare_compound_select_as_cte_plan(
+                schema,
+                select,
+                syms,
+                outer_query_refs,
+                ctes,
+                table_ref_counter,
+                query_destination,
+                connection,
+            )
+        }
         Some(compounds) => {
             let mut last = prepare_one_select_plan(
                 schema,
@@ -123,6 +146,7 @@ pub fn prepare_select_plan(
                 None,
                 syms,
                 outer_query_refs,
+                ctes,
                 table_ref_counter,
                 query_destination.clone(),
                 connection,
@@ -139,6 +163,7 @@ pub fn prepare_select_plan(
                     None,
                     syms,
                     outer_query_refs,
+                    ctes,
                     table_ref_counter,
                     query_destination.clone(),
                     connection,
@@ -153,19 +178,6 @@ pub fn prepare_select_plan(
                 }
             }
             let (limit, offset) = select.limit.map_or(Ok((None, None)), |l| parse_limit(&l))?;
-
-            // FIXME: handle OFFSET for compound selects
-            if offset.is_some_and(|o| o > 0) {
-                crate::bail_parse_error!("OFFSET is not supported for compound SELECTs yet");
-            }
-            // FIXME: handle ORDER BY for compound selects
-            if select.order_by.is_some() {
-                crate::bail_parse_error!("ORDER BY is not supported for compound SELECTs yet");
-            }
-            // FIXME: handle WITH for compound selects
-            if select.with.is_some() {
-                crate::bail_parse_error!("WITH is not supported for compound SELECTs yet");
-            }
             Ok(Plan::CompoundSelect {
                 left,
                 right_most: last,
@@ -177,6 +189,182 @@ pub fn prepare_select_plan(
     }
 }
 
+fn has_offset(select: &ast::Select) -> Result<bool> {
+    let (_, offset) = select
+        .limit
+        .as_deref()
+        .map_or(Ok((None, None)), parse_limit)?;
+    Ok(offset.is_some_and(|o| o > 0))
+}
+
+/// The name of the CTE that a compound SELECT with an ORDER BY, OFFSET or WITH clause is planned as.
+const COMPOUND_SELECT_CTE: &str = "sqlite_compound_select";
+
+/// Plans a compound SELECT with an ORDER BY, OFFSET or WITH clause as a SELECT that reads the rows of
+/// the compound SELECT from a CTE, declared after the CTEs of the WITH clause, and sorts and pages
+/// them with its own ORDER BY and LIMIT clauses.
+#[allow(clippy::too_many_arguments)]
+fn prepare_compound_select_as_cte_plan(
+    schema: &Schema,
+    select: ast::Select,
+    syms: &SymbolTable,
+    outer_query_refs: &[OuterQueryReference],
+    ctes: &[Rc<Cte>],
+    table_ref_counter: &mut TableRefIdCounter,
+    query_destination: QueryDestination,
+    connection: &Arc<crate::Connection>,
+) -> Result<Plan> {
+    let ast::Select {
+        with,
+        body,
+        order_by,
+        limit,
+    } = select;
+    let components = std::iter::once(body.select.as_ref())
+        .chain(body.compounds.iter().flatten().map(|c| c.select.as_ref()))
+        .filter_map(|select| match select {
+            ast::OneSelect::Select(inner) => inner
+                .columns
+                .iter()
+                .map(|column| match column {
+                    ResultColumn::Expr(expr, alias) => Some((alias.clone(), expr.clone())),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>(),
+            ast::OneSelect::Values(_) => None,
+        })
+        .collect::<Vec<_>>();
+    let declared_ctes = ctes.len();
+    let mut scope = parse_with(with, ctes, outer_query_refs)?;
+    scope.push(Rc::new(Cte {
+        name: COMPOUND_SELECT_CTE.to_string(),
+        definition: CteDefinition::Inline(CteBody {
+            select: ast::Select {
+                with: None,
+                body,
+                order_by: None,
+                limit: None,
+            },
+            columns: None,
+            outer_query_refs: outer_query_refs.to_vec(),
+        }),
+    }));
+
+    let select_all = ast::OneSelect::Select(Box::new(SelectInner {
+        distinctness: None,
+        columns: vec![ResultColumn::Star],
+        from: Some(ast::FromClause {
+            select: Some(Box::new(ast::SelectTable::Table(
+                ast::QualifiedName::single(ast::Name::from_str(COMPOUND_SELECT_CTE)),
+                None,
+                None,
+            ))),
+            joins: None,
+            op: None,
+        }),
+        where_clause: None,
+        group_by: None,
+        window_clause: None,
+    }));
+    let mut plan = prepare_one_select_plan(
+        schema,
+        select_all,
+        limit.as_deref(),
+        None,
+        None,
+        syms,
+        outer_query_refs,
+        &scope,
+        table_ref_counter,
+        query_destination,
+        connection,
+    )?;
+
+    // The ORDER BY terms of a compound SELECT must be result columns, named by their position or
+    // their name in the leftmost SELECT.
+    if let Some(order_by) = order_by {
+        let mut key = Vec::with_capacity(order_by.len());
+        for (i, term) in order_by.into_iter().enumerate() {
+            let (expr, collation) = match term.expr {
+                ast::Expr::Collate(expr, collation) => (*expr, Some(collation)),
+                expr => (expr, None),
+            };
+            let column = compound_order_by_column(&plan, &components, &expr, i + 1)?;
+            let mut expr = plan.result_columns[column].expr.clone();
+            if let Some(collation) = collation {
+                expr = ast::Expr::Collate(Box::new(expr), collation);
+            }
+            key.push((expr, term.order.unwrap_or(SortOrder::Asc)));
+        }
+        plan.order_by = Some(key);
+    }
+    plan.materialized_ctes = referenced_materialized_ctes(&scope[declared_ctes..]);
+    Ok(Plan::Select(plan))
+}
+
+/// Returns the index of the result column that the `n`th ORDER BY term of a compound SELECT refers to.
+/// Like SQLite, a term that is not a result column of the leftmost SELECT can match the alias or the
+/// expression of a result column of the SELECTs to its right, given as `components`.
+fn compound_order_by_column(
+    plan: &SelectPlan,
+    components: &[Vec<(Option<ast::As>, ast::Expr)>],
+    expr: &ast::Expr,
+    n: usize,
+) -> Result<usize> {
+    let num_columns = plan.result_columns.len();
+    match expr {
+        ast::Expr::Literal(ast::Literal::Numeric(number)) => match number.parse::<usize>() {
+            Ok(position) if (1..=num_columns).contains(&position) => return Ok(position - 1),
+            _ => crate::bail_parse_error!(
+                "{} ORDER BY term out of range - should be between 1 and {}",
+                ordinal(n),
+                num_columns
+            ),
+        },
+        ast::Expr::Id(name) | ast::Expr::Name(name) => {
+            let name = normalize_ident(name.as_str());
+            let position = plan.result_columns.iter().position(|column| {
+                column
+                    .name(&plan.table_references)
+                    .is_some_and(|column_name| normalize_ident(column_name) == name)
+            });
+            if let Some(position) = position {
+                return Ok(position);
+            }
+        }
+        _ => {}
+    }
+    for columns in components {
+        let position = columns
+            .iter()
+            .position(|(alias, column)| match (expr, alias) {
+                (ast::Expr::Id(name), Some(ast::As::As(alias) | ast::As::Elided(alias))) => {
+                    normalize_ident(name.as_str()) == normalize_ident(alias.as_str())
+                }
+                _ => exprs_are_equivalent(expr, column),
+            });
+        if let Some(position) = position {
+            return Ok(position);
+        }
+    }
+    crate::bail_parse_error!(
+        "{} ORDER BY term does not match any column in the result set",
+        ordinal(n)
+    )
+}
+
+/// Formats `n` as an English ordinal number, as in "1st" or "12th".
+fn ordinal(n: usize) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{n}{suffix}")
+}
+
 #[allow(clippy::too_many_arguments)]
 fn prepare_one_select_plan(
     schema: &Schema,
@@ -186,12 +374,28 @@ fn prepare_one_select_plan(
     with: Option<ast::With>,
     syms: &SymbolTable,
     outer_query_refs: &[OuterQueryReference],
+    ctes: &[Rc<Cte>],
     table_ref_counter: &mut TableRefIdCounter,
     query_destination: QueryDestination,
     connection: &Arc<crate::Connection>,
 ) -> Result<SelectPlan> {
     match select {
         ast::OneSelect::Select(select_inner) => {
+            if window::select_has_window_functions(&select_inner.columns, order_by.as_deref())? {
+                return window::prepare_window_select_plan(
+                    schema,
+                    *select_inner,
+                    limit,
+                    order_by,
+                    with,
+                    syms,
+                    outer_query_refs,
+                    ctes,
+                    table_ref_counter,
+                    query_destination,
+                    connection,
+                );
+            }
             let SelectInner {
                 mut columns,
                 from,
@@ -222,12 +426,15 @@ fn prepare_one_select_plan(
                 }
             }
 
+            let declared_ctes = ctes.len();
+            let ctes = parse_with(with, ctes, outer_query_refs)?;
+
             // Parse the FROM clause into a vec of TableReferences. Fold all the join conditions expressions into the WHERE clause.
             parse_from(
                 schema,
                 from,
                 syms,
-                with,
+                &ctes,
                 &mut where_predicates,
                 &mut table_references,
                 table_ref_counter,
@@ -267,6 +474,7 @@ fn prepare_one_select_plan(
                         table_id: t.internal_id,
                         original_idx: i,
                         is_outer: t.join_info.as_ref().is_some_and(|j| j.outer),
+                        is_right: t.join_info.as_ref().is_some_and(|j| j.right),
                     })
                     .collect(),
                 table_references,
@@ -281,6 +489,9 @@ fn prepare_one_select_plan(
                 query_destination,
                 distinctness: Distinctness::from_ast(distinctness.as_ref()),
                 values: vec![],
+                window: None,
+                materialized_ctes: vec![],
+                non_from_clause_subqueries: vec![],
             };
 
             let mut aggregate_expressions = Vec::new();
@@ -339,12 +550,22 @@ fn prepare_one_select_plan(
                             Some(&plan.result_columns),
                             connection,
                         )?;
+                        plan_subqueries_from_expr(
+                            schema,
+                            syms,
+                            expr,
+                            &mut plan.table_references,
+                            &mut plan.non_from_clause_subqueries,
+                            &ctes,
+                            table_ref_counter,
+                            connection,
+                        )?;
                         match expr {
                             ast::Expr::FunctionCall {
                                 name,
                                 distinctness,
                                 args,
-                                filter_over: _,
+                                filter_over,
                                 order_by: _,
                             } => {
                                 let args_count = if let Some(args) = &args {
@@ -362,6 +583,7 @@ fn prepare_one_select_plan(
                                 if distinctness.is_distinct() && args_count != 1 {
                                     crate::bail_parse_error!("DISTINCT aggregate functions must have exactly one argument");
                                 }
+                                let filter = resolve_filter_clause(filter_over.as_ref())?;
                                 match Func::resolve_function(name.as_str(), args_count) {
                                     Ok(Func::Agg(f)) => {
                                         let agg_args = match (args, &f) {
@@ -383,6 +605,7 @@ fn prepare_one_select_plan(
                                             args: agg_args.clone(),
                                             original_expr: expr.clone(),
                                             distinctness,
+                                            filter,
                                         };
                                         aggregate_expressions.push(agg.clone());
                                         plan.result_columns.push(ResultSetColumn {
@@ -443,6 +666,7 @@ fn prepare_one_select_plan(
                                                     args: args.as_ref().unwrap().clone(),
                                                     original_expr: expr.clone(),
                                                     distinctness,
+                                                    filter,
                                                 };
                                                 aggregate_expressions.push(agg.clone());
                                                 plan.result_columns.push(ResultSetColumn {
@@ -467,47 +691,50 @@ fn prepare_one_select_plan(
                                     }
                                 }
                             }
-                            ast::Expr::FunctionCallStar {
-                                name,
-                                filter_over: _,
-                            } => match Func::resolve_function(name.as_str(), 0) {
-                                Ok(Func::Agg(f)) => {
-                                    let agg = Aggregate {
-                                        func: f,
-                                        args: vec![ast::Expr::Literal(ast::Literal::Numeric(
-                                            "1".to_string(),
-                                        ))],
-                                        original_expr: expr.clone(),
-                                        distinctness: Distinctness::NonDistinct,
-                                    };
-                                    aggregate_expressions.push(agg.clone());
-                                    plan.result_columns.push(ResultSetColumn {
-                                        alias: maybe_alias.as_ref().map(|alias| match alias {
-                                            ast::As::Elided(alias) => alias.as_str().to_string(),
-                                            ast::As::As(alias) => alias.as_str().to_string(),
-                                        }),
-                                        expr: expr.clone(),
-                                        contains_aggregates: true,
-                                    });
-                                }
-                                Ok(_) => {
-                                    crate::bail_parse_error!(
-                                        "Invalid aggregate function: {}",
-                                        name.as_str()
-                                    );
-                                }
-                                Err(e) => match e {
-                                    crate::LimboError::ParseError(e) => {
-                                        crate::bail_parse_error!("{}", e);
+                            ast::Expr::FunctionCallStar { name, filter_over } => {
+                                match Func::resolve_function(name.as_str(), 0) {
+                                    Ok(Func::Agg(f)) => {
+                                        let filter = resolve_filter_clause(filter_over.as_ref())?;
+                                        let agg = Aggregate {
+                                            func: f,
+                                            args: vec![ast::Expr::Literal(ast::Literal::Numeric(
+                                                "1".to_string(),
+                                            ))],
+                                            original_expr: expr.clone(),
+                                            distinctness: Distinctness::NonDistinct,
+                                            filter,
+                                        };
+                                        aggregate_expressions.push(agg.clone());
+                                        plan.result_columns.push(ResultSetColumn {
+                                            alias: maybe_alias.as_ref().map(|alias| match alias {
+                                                ast::As::Elided(alias) => {
+                                                    alias.as_str().to_string()
+                                                }
+                                                ast::As::As(alias) => alias.as_str().to_string(),
+                                            }),
+                                            expr: expr.clone(),
+                                            contains_aggregates: true,
+                                        });
                                     }
-                                    _ => {
+                                    Ok(_) => {
                                         crate::bail_parse_error!(
                                             "Invalid aggregate function: {}",
                                             name.as_str()
                                         );
                                     }
-                                },
-                            },
+                                    Err(e) => match e {
+                                        crate::LimboError::ParseError(e) => {
+                                            crate::bail_parse_error!("{}", e);
+                                        }
+                                        _ => {
+                                            crate::bail_parse_error!(
+                                                "Invalid aggregate function: {}",
+                                                name.as_str()
+                                            );
+                                        }
+                                    },
+                                }
+                            }
                             expr => {
                                 let contains_aggregates =
                                     resolve_aggregates(schema, expr, &mut aggregate_expressions)?;
@@ -533,6 +760,18 @@ fn prepare_one_select_plan(
                 &mut plan.where_clause,
                 connection,
             )?;
+            for term in plan.where_clause.iter_mut() {
+                plan_subqueries_from_expr(
+                    schema,
+                    syms,
+                    &mut term.expr,
+                    &mut plan.table_references,
+                    &mut plan.non_from_clause_subqueries,
+                    &ctes,
+                    table_ref_counter,
+                    connection,
+                )?;
+            }
 
             if let Some(mut group_by) = group_by {
                 for expr in group_by.exprs.iter_mut() {
@@ -543,6 +782,16 @@ fn prepare_one_select_plan(
                         Some(&plan.result_columns),
                         connection,
                     )?;
+                    plan_subqueries_from_expr(
+                        schema,
+                        syms,
+                        expr,
+                        &mut plan.table_references,
+                        &mut plan.non_from_clause_subqueries,
+                        &ctes,
+                        table_ref_counter,
+                        connection,
+                    )?;
                 }
 
                 plan.group_by = Some(GroupBy {
@@ -558,6 +807,16 @@ fn prepare_one_select_plan(
                                 Some(&plan.result_columns),
                                 connection,
                             )?;
+                            plan_subqueries_from_expr(
+                                schema,
+                                syms,
+                                expr,
+                                &mut plan.table_references,
+                                &mut plan.non_from_clause_subqueries,
+                                &ctes,
+                                table_ref_counter,
+                                connection,
+                            )?;
                             let contains_aggregates =
                                 resolve_aggregates(schema, expr, &mut aggregate_expressions)?;
                             if !contains_aggregates {
@@ -595,6 +854,16 @@ fn prepare_one_select_plan(
                         Some(&plan.result_columns),
                         connection,
                     )?;
+                    plan_subqueries_from_expr(
+                        schema,
+                        syms,
+                        &mut o.expr,
+                        &mut plan.table_references,
+                        &mut plan.non_from_clause_subqueries,
+                        &ctes,
+                        table_ref_counter,
+                        connection,
+                    )?;
                     resolve_aggregates(schema, &o.expr, &mut plan.aggregates)?;
 
                     key.push((o.expr, o.order.unwrap_or(ast::SortOrder::Asc)));
@@ -605,6 +874,8 @@ fn prepare_one_select_plan(
             // Parse the LIMIT/OFFSET clause
             (plan.limit, plan.offset) = limit.map_or(Ok((None, None)), parse_limit)?;
 
+            plan.materialized_ctes = referenced_materialized_ctes(&ctes[declared_ctes..]);
+
             // Return the unoptimized query plan
             Ok(plan)
         }
@@ -633,6 +904,9 @@ fn prepare_one_select_plan(
                 query_destination,
                 distinctness: Distinctness::NonDistinct,
                 values,
+                window: None,
+                materialized_ctes: vec![],
+                non_from_clause_subqueries: vec![],
             };
 
             Ok(plan)
@@ -1,16 +1,36 @@
+use std::num::NonZeroUsize;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use turso_sqlite3_parser::ast::{self, SortOrder, SubqueryType, TableInternalId};
+
 use crate::{
-    schema::Table,
-    vdbe::{builder::ProgramBuilder, insn::Insn},
-    Result,
+    schema::{Affinity, Index, IndexColumn, Schema, Table},
+    vdbe::{
+        builder::{CursorType, ProgramBuilder, TableRefIdCounter},
+        insn::{CmpInsFlags, Insn},
+        BranchOffset,
+    },
+    Connection, Result, SymbolTable,
 };
 
 use super::{
+    collate::CollationSeq,
+    compound_select::emit_compound_subquery,
+    cte::emit_cte_coroutine,
     emitter::{emit_query, Resolver, TranslateCtx},
+    expr::{
+        compare_affinity, get_expr_affinity, translate_expr, walk_expr, walk_expr_mut, WalkControl,
+    },
     main_loop::LoopLabels,
-    plan::{QueryDestination, SelectPlan, TableReferences},
+    plan::{
+        Cte, NonFromClauseSubquery, OuterQueryReference, Plan, QueryDestination, SelectPlan,
+        TableReferences,
+    },
+    select::prepare_select_plan,
 };
 
-/// Emit the subqueries contained in the FROM clause.
+/// Emit the subqueries contained in the FROM clause, including the CTEs read from a coroutine.
 /// This is done first so the results can be read in the main query loop.
 pub fn emit_subqueries(
     program: &mut ProgramBuilder,
@@ -26,6 +46,11 @@ pub fn emit_subqueries(
             // This is done so that translate_expr() can read the result columns of the subquery,
             // as if it were reading from a regular table.
             from_clause_subquery.result_columns_start_reg = Some(result_columns_start);
+        } else if let Table::Cte(cte) = &table_reference.table {
+            // Materialized CTEs are computed by the query that declares them.
+            if !cte.materialized {
+                emit_cte_coroutine(program, t_ctx, cte)?;
+            }
         }
     }
     Ok(())
@@ -48,6 +73,17 @@ pub fn emit_subquery(
     program: &mut ProgramBuilder,
     plan: &mut SelectPlan,
     t_ctx: &mut TranslateCtx,
+) -> Result<usize> {
+    let resolver = Resolver::new(t_ctx.resolver.schema, t_ctx.resolver.symbol_table);
+    emit_subquery_with_resolver(program, plan, resolver)
+}
+
+/// Like [emit_subquery], but the subquery is translated with `resolver`, whose expression cache
+/// may hold the registers of the columns of enclosing queries.
+fn emit_subquery_with_resolver<'a>(
+    program: &mut ProgramBuilder,
+    plan: &'a mut SelectPlan,
+    resolver: Resolver<'a>,
 ) -> Result<usize> {
     let yield_reg = program.alloc_register();
     let coroutine_implementation_start_offset = program.allocate_label();
@@ -71,7 +107,9 @@ pub fn emit_subquery(
         label_main_loop_end: None,
         meta_group_by: None,
         meta_left_joins: (0..plan.joined_tables().len()).map(|_| None).collect(),
+        meta_right_joins: (0..plan.joined_tables().len()).map(|_| None).collect(),
         meta_sort: None,
+        meta_window: None,
         reg_agg_start: None,
         reg_nonagg_emit_once_flag: None,
         reg_result_cols_start: None,
@@ -80,7 +118,7 @@ pub fn emit_subquery(
         limit_ctx: None,
         reg_offset: None,
         reg_limit_offset_sum: None,
-        resolver: Resolver::new(t_ctx.resolver.schema, t_ctx.resolver.symbol_table),
+        resolver,
         non_aggregate_expressions: Vec::new(),
         cdc_cursor_id: None,
     };
@@ -97,3 +135,604 @@ pub fn emit_subquery(
     program.preassign_label_to_next_insn(subquery_body_end_label);
     Ok(result_column_start_reg)
 }
+
+/// Plans the subqueries used as expressions in `expr`, i.e. `EXISTS (SELECT ...)`,
+/// `x IN (SELECT ...)`, `x IN table` and scalar `(SELECT ...)`. Each of them is replaced by an
+/// [ast::Expr::SubqueryResult] that refers to the plan pushed to `subqueries`.
+///
+/// The subqueries may refer to the tables of `table_references`, and to the tables of the queries
+/// enclosing it. The columns they refer to are marked as used in `table_references`.
+#[allow(clippy::too_many_arguments)]
+pub fn plan_subqueries_from_expr(
+    schema: &Schema,
+    syms: &SymbolTable,
+    expr: &mut ast::Expr,
+    table_references: &mut TableReferences,
+    subqueries: &mut Vec<NonFromClauseSubquery>,
+    ctes: &[Rc<Cte>],
+    table_ref_counter: &mut TableRefIdCounter,
+    connection: &Arc<Connection>,
+) -> Result<()> {
+    walk_expr_mut(expr, &mut |expr: &mut ast::Expr| -> Result<()> {
+        let (query_type, lhs, not_in, select) = match expr {
+            ast::Expr::Exists(_)
+            | ast::Expr::Subquery(_)
+            | ast::Expr::InSelect { .. }
+            | ast::Expr::InTable { .. } => {
+                match std::mem::replace(expr, ast::Expr::Literal(ast::Literal::Null)) {
+                    ast::Expr::Exists(select) => (SubqueryType::Exists, None, false, select),
+                    ast::Expr::Subquery(select) => (SubqueryType::Scalar, None, false, select),
+                    ast::Expr::InSelect { lhs, not, rhs } => {
+                        (SubqueryType::In, Some(lhs), not, rhs)
+                    }
+                    // `x IN t` is the same as `x IN (SELECT * FROM t)`
+                    ast::Expr::InTable {
+                        lhs,
+                        not,
+                        rhs,
+                        args,
+                    } => {
+                        let table = match args {
+                            Some(args) => ast::SelectTable::TableCall(rhs, Some(args), None),
+                            None => ast::SelectTable::Table(rhs, None, None),
+                        };
+                        let select = ast::Select {
+                            with: None,
+                            body: ast::SelectBody {
+                                select: Box::new(ast::OneSelect::Select(Box::new(
+                                    ast::SelectInner {
+                                        distinctness: None,
+                                        columns: vec![ast::ResultColumn::Star],
+                                        from: Some(ast::FromClause {
+                                            select: Some(Box::new(table)),
+                                            joins: None,
+                                            op: None,
+                                        }),
+                                        where_clause: None,
+                                        group_by: None,
+                                        window_clause: None,
+                                    },
+                                ))),
+                                compounds: None,
+                            },
+                            order_by: None,
+                            limit: None,
+                        };
+                        (SubqueryType::In, Some(lhs), not, Box::new(select))
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            _ => return Ok(()),
+        };
+
+        // The tables of this query are outer tables of the subquery, like the ones of the
+        // queries enclosing this one.
+        let outer_query_refs = table_references
+            .joined_tables()
+            .iter()
+            .map(|table| OuterQueryReference {
+                identifier: table.identifier.clone(),
+                internal_id: table.internal_id,
+                table: table.table.clone(),
+                col_used_mask: Default::default(),
+            })
+            .chain(
+                table_references
+                    .outer_query_refs()
+                    .iter()
+                    .map(|outer_query_ref| OuterQueryReference {
+                        col_used_mask: Default::default(),
+                        ..outer_query_ref.clone()
+                    }),
+            )
+            .collect::<Vec<_>>();
+        let plan = prepare_select_plan(
+            schema,
+            *select,
+            syms,
+            &outer_query_refs,
+            ctes,
+            table_ref_counter,
+            QueryDestination::CoroutineYield {
+                yield_reg: usize::MAX, // will be set later in emit_subquery
+                coroutine_implementation_start: BranchOffset::Placeholder,
+            },
+            connection,
+        )?;
+
+        let num_columns = match &plan {
+            Plan::Select(plan) => plan.result_columns.len(),
+            Plan::CompoundSelect { right_most, .. } => right_most.result_columns.len(),
+            Plan::Delete(_) | Plan::Update(_) => unreachable!(),
+        };
+        let expected_columns = match lhs.as_deref() {
+            Some(ast::Expr::Parenthesized(exprs)) => exprs.len(),
+            Some(_) => 1,
+            None if query_type == SubqueryType::Scalar => 1,
+            None => num_columns,
+        };
+        if num_columns != expected_columns {
+            crate::bail_parse_error!(
+                "sub-select returns {} columns - expected {}",
+                num_columns,
+                expected_columns
+            );
+        }
+
+        let outer_ids = outer_query_refs
+            .iter()
+            .map(|outer_query_ref| outer_query_ref.internal_id)
+            .collect::<Vec<_>>();
+        let mut outer_columns = vec![];
+        collect_outer_columns(&plan, &outer_ids, &mut outer_columns)?;
+        for column in outer_columns.iter() {
+            if let ast::Expr::Column { table, column, .. } = column {
+                table_references.mark_column_used(*table, *column);
+            }
+        }
+
+        let internal_id = table_ref_counter.next();
+        subqueries.push(NonFromClauseSubquery {
+            internal_id,
+            plan: Box::new(plan),
+        });
+        *expr = ast::Expr::SubqueryResult {
+            subquery_id: internal_id,
+            lhs,
+            not_in,
+            query_type,
+            outer_columns,
+        };
+        Ok(())
+    })
+}
+
+/// Collects the columns and rowids of the tables in `outer_ids` that `plan` reads, without
+/// duplicates.
+fn collect_outer_columns(
+    plan: &Plan,
+    outer_ids: &[TableInternalId],
+    outer_columns: &mut Vec<ast::Expr>,
+) -> Result<()> {
+    match plan {
+        Plan::Select(plan) => collect_outer_columns_from_select(plan, outer_ids, outer_columns),
+        Plan::CompoundSelect {
+            left, right_most, ..
+        } => {
+            for (plan, _) in left {
+                collect_outer_columns_from_select(plan, outer_ids, outer_columns)?;
+            }
+            collect_outer_columns_from_select(right_most, outer_ids, outer_columns)
+        }
+        Plan::Delete(_) | Plan::Update(_) => unreachable!(),
+    }
+}
+
+fn collect_outer_columns_from_select(
+    plan: &SelectPlan,
+    outer_ids: &[TableInternalId],
+    outer_columns: &mut Vec<ast::Expr>,
+) -> Result<()> {
+    let mut exprs = plan
+        .result_columns
+        .iter()
+        .map(|rc| &rc.expr)
+        .chain(plan.where_clause.iter().map(|term| &term.expr))
+        .chain(
+            plan.aggregates
+                .iter()
+                .flat_map(|agg| agg.args.iter().chain(&agg.filter)),
+        )
+        .chain(plan.values.iter().flatten())
+        .collect::<Vec<_>>();
+    if let Some(group_by) = &plan.group_by {
+        exprs.extend(group_by.exprs.iter());
+        exprs.extend(group_by.having.iter().flatten());
+    }
+    if let Some(order_by) = &plan.order_by {
+        exprs.extend(order_by.iter().map(|(expr, _)| expr));
+    }
+    for expr in exprs {
+        walk_expr(expr, &mut |expr: &ast::Expr| -> Result<WalkControl> {
+            if let ast::Expr::Column { table, .. } | ast::Expr::RowId { table, .. } = expr {
+                if outer_ids.contains(table) && !outer_columns.contains(expr) {
+                    outer_columns.push(expr.clone());
+                }
+            }
+            Ok(WalkControl::Continue)
+        })?;
+    }
+    for table in plan.joined_tables() {
+        if let Table::FromClauseSubquery(from_clause_subquery) = &table.table {
+            collect_outer_columns_from_select(
+                &from_clause_subquery.plan,
+                outer_ids,
+                outer_columns,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Emits the evaluation of an [ast::Expr::SubqueryResult] into `target_register`.
+///
+/// The subquery is emitted as a coroutine at the point of use, so it can read the columns of the
+/// enclosing queries wherever they are read from, e.g. a table cursor or a GROUP BY sorter. Their
+/// values are copied to registers first, from which the subquery reads them.
+///
+/// A subquery that does not refer to any enclosing query is only run once per statement. For
+/// `IN`, its rows are then kept in an ephemeral index, which each evaluation looks up.
+pub fn translate_subquery_result(
+    program: &mut ProgramBuilder,
+    referenced_tables: Option<&TableReferences>,
+    expr: &ast::Expr,
+    target_register: usize,
+    resolver: &Resolver,
+) -> Result<usize> {
+    let ast::Expr::SubqueryResult {
+        subquery_id,
+        lhs,
+        not_in,
+        query_type,
+        outer_columns,
+    } = expr
+    else {
+        unreachable!("translate_subquery_result called on {expr:?}");
+    };
+    let Some(subquery) = resolver
+        .subqueries
+        .iter()
+        .find(|subquery| subquery.internal_id == *subquery_id)
+    else {
+        crate::bail_parse_error!("subqueries are not supported in this context");
+    };
+    let mut plan = (*subquery.plan).clone();
+    if matches!(plan, Plan::CompoundSelect { .. }) && !outer_columns.is_empty() {
+        crate::bail_parse_error!("correlated compound subqueries are not supported");
+    }
+
+    let outer_columns_start_reg = program.alloc_registers(outer_columns.len());
+    for (i, column) in outer_columns.iter().enumerate() {
+        translate_expr(
+            program,
+            referenced_tables,
+            column,
+            outer_columns_start_reg + i,
+            resolver,
+        )?;
+    }
+    let mut subquery_resolver = Resolver::new(resolver.schema, resolver.symbol_table);
+    for (i, column) in outer_columns.iter().enumerate() {
+        subquery_resolver
+            .expr_to_reg_cache
+            .push((column, outer_columns_start_reg + i));
+    }
+    subquery_resolver.enable_expr_to_reg_cache();
+
+    let num_columns = match &plan {
+        Plan::Select(plan) => plan.result_columns.len(),
+        Plan::CompoundSelect { right_most, .. } => right_most.result_columns.len(),
+        Plan::Delete(_) | Plan::Update(_) => unreachable!(),
+    };
+    // The rows are compared to x with the affinity and the collation of each `x = row`.
+    let comparisons = match (query_type, lhs.as_deref()) {
+        (SubqueryType::In, Some(lhs)) => {
+            let rhs_plan = match &plan {
+                Plan::Select(plan) => plan,
+                Plan::CompoundSelect { right_most, .. } => right_most,
+                Plan::Delete(_) | Plan::Update(_) => unreachable!(),
+            };
+            let lhs_exprs = match lhs {
+                ast::Expr::Parenthesized(exprs) if exprs.len() > 1 => exprs.iter().collect(),
+                lhs => vec![lhs],
+            };
+            lhs_exprs
+                .into_iter()
+                .zip(&rhs_plan.result_columns)
+                .map(|(lhs, rhs)| {
+                    in_comparison(
+                        lhs,
+                        referenced_tables,
+                        &rhs.expr,
+                        &rhs_plan.table_references,
+                    )
+                })
+                .collect::<Result<Vec<_>>>()?
+        }
+        _ => vec![],
+    };
+    let result_reg = program.alloc_register();
+    let in_cursor = if *query_type == SubqueryType::In {
+        let index = Arc::new(Index {
+            columns: comparisons
+                .iter()
+                .map(|(_, collation)| IndexColumn {
+                    name: String::new(),
+                    order: SortOrder::Asc,
+                    pos_in_table: 0,
+                    default: None,
+                    expr: None,
+                    collation: Some(*collation),
+                })
+                .collect(),
+            name: "subquery_in".to_string(),
+            root_page: 0,
+            ephemeral: true,
+            table_name: String::new(),
+            unique: false,
+            has_rowid: false,
+            num_extra_columns: 0,
+            where_clause: None,
+        });
+        let cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(index));
+        Some((cursor_id, program.alloc_register()))
+    } else {
+        None
+    };
+
+    // The subquery runs once if its result cannot change between evaluations.
+    let label_evaluated = program.allocate_label();
+    if outer_columns.is_empty() {
+        program.emit_insn(Insn::Once {
+            target_pc_when_reentered: label_evaluated,
+        });
+    }
+    // The subquery may be evaluated at several places, e.g. when the expression is copied by an
+    // alias, each of which opens its own cursors.
+    let first_cursor_id = program.next_cursor_id();
+    let (yield_reg, result_columns_start_reg) = match &mut plan {
+        Plan::Select(plan) => {
+            let result_columns_start_reg =
+                emit_subquery_with_resolver(program, plan, subquery_resolver)?;
+            let QueryDestination::CoroutineYield { yield_reg, .. } = plan.query_destination else {
+                unreachable!();
+            };
+            (yield_reg, result_columns_start_reg)
+        }
+        Plan::CompoundSelect { .. } => {
+            let (yield_reg, _, result_columns_start_reg) = emit_compound_subquery(
+                program,
+                plan.clone(),
+                resolver.schema,
+                resolver.symbol_table,
+            )?;
+            (yield_reg, result_columns_start_reg)
+        }
+        Plan::Delete(_) | Plan::Update(_) => unreachable!(),
+    };
+    program.forget_cursor_keys(first_cursor_id);
+    let label_done = program.allocate_label();
+    match query_type {
+        SubqueryType::Exists => {
+            program.emit_int(0, result_reg);
+            program.emit_insn(Insn::Yield {
+                yield_reg,
+                end_offset: label_done,
+            });
+            program.emit_int(1, result_reg);
+        }
+        SubqueryType::Scalar => {
+            // The result is the first row, or NULL if there is none.
+            program.emit_insn(Insn::Null {
+                dest: result_reg,
+                dest_end: None,
+            });
+            program.emit_insn(Insn::Yield {
+                yield_reg,
+                end_offset: label_done,
+            });
+            program.emit_insn(Insn::Copy {
+                src_reg: result_columns_start_reg,
+                dst_reg: result_reg,
+                extra_amount: 0,
+            });
+        }
+        SubqueryType::In => {
+            let (cursor_id, has_null_reg) = in_cursor.unwrap();
+            program.emit_insn(Insn::OpenEphemeral {
+                cursor_id,
+                is_table: false,
+            });
+            program.emit_int(0, has_null_reg);
+            let label_next_row = program.allocate_label();
+            program.preassign_label_to_next_insn(label_next_row);
+            program.emit_insn(Insn::Yield {
+                yield_reg,
+                end_offset: label_done,
+            });
+            // Rows with a NULL never match, but make a failed lookup NULL rather than false.
+            for i in 0..num_columns {
+                let label_not_null = program.allocate_label();
+                program.emit_insn(Insn::NotNull {
+                    reg: result_columns_start_reg + i,
+                    target_pc: label_not_null,
+                });
+                program.emit_int(1, has_null_reg);
+                program.preassign_label_to_next_insn(label_not_null);
+            }
+            emit_in_affinity(program, &comparisons, result_columns_start_reg);
+            let record_reg = program.alloc_register();
+            program.emit_insn(Insn::MakeRecord {
+                start_reg: result_columns_start_reg,
+                count: num_columns,
+                dest_reg: record_reg,
+                index_name: None,
+            });
+            program.emit_insn(Insn::IdxInsert {
+                cursor_id,
+                record_reg,
+                unpacked_start: Some(result_columns_start_reg),
+                unpacked_count: Some(num_columns as u16),
+                flags: Default::default(),
+            });
+            program.emit_insn(Insn::Goto {
+                target_pc: label_next_row,
+            });
+        }
+    }
+    program.preassign_label_to_next_insn(label_done);
+    program.preassign_label_to_next_insn(label_evaluated);
+
+    let Some((cursor_id, has_null_reg)) = in_cursor else {
+        program.emit_insn(Insn::Copy {
+            src_reg: result_reg,
+            dst_reg: target_register,
+            extra_amount: 0,
+        });
+        return Ok(target_register);
+    };
+
+    // `x IN (SELECT ...)` is true if x is one of the rows, NULL if x is NULL or if the rows have a
+    // NULL, and false otherwise. NOT IN negates true and false.
+    let lhs = lhs
+        .as_deref()
+        .expect("IN subquery must have a left-hand side");
+    let lhs_reg = program.alloc_registers(num_columns);
+    match lhs {
+        ast::Expr::Parenthesized(exprs) if exprs.len() > 1 => {
+            for (i, expr) in exprs.iter().enumerate() {
+                translate_expr(program, referenced_tables, expr, lhs_reg + i, resolver)?;
+            }
+        }
+        lhs => {
+            translate_expr(program, referenced_tables, lhs, lhs_reg, resolver)?;
+        }
+    }
+    emit_in_affinity(program, &comparisons, lhs_reg);
+    let label_true = program.allocate_label();
+    let label_false = program.allocate_label();
+    let label_null = program.allocate_label();
+    let label_end = program.allocate_label();
+    let label_scan = program.allocate_label();
+    for i in 0..num_columns {
+        program.emit_insn(Insn::IsNull {
+            reg: lhs_reg + i,
+            target_pc: label_scan,
+        });
+    }
+    program.emit_insn(Insn::Found {
+        cursor_id,
+        target_pc: label_true,
+        record_reg: lhs_reg,
+        num_regs: num_columns,
+    });
+    program.emit_insn(Insn::IfNot {
+        reg: has_null_reg,
+        target_pc: label_false,
+        jump_if_null: true,
+    });
+
+    // With a NULL on either side, the result is NULL if a row could match x, and false
+    // otherwise. Each row is compared column by column, skipping the NULL comparisons.
+    program.preassign_label_to_next_insn(label_scan);
+    program.emit_insn(Insn::Rewind {
+        cursor_id,
+        pc_if_empty: label_false,
+    });
+    let label_loop = program.allocate_label();
+    let label_next = program.allocate_label();
+    program.preassign_label_to_next_insn(label_loop);
+    let column_reg = program.alloc_register();
+    for (i, (_, collation)) in comparisons.iter().enumerate() {
+        program.emit_column(cursor_id, i, column_reg);
+        program.emit_insn(Insn::Ne {
+            lhs: lhs_reg + i,
+            rhs: column_reg,
+            target_pc: label_next,
+            flags: CmpInsFlags::default(),
+            collation: Some(*collation),
+        });
+    }
+    program.emit_insn(Insn::Goto {
+        target_pc: label_null,
+    });
+    program.preassign_label_to_next_insn(label_next);
+    program.emit_insn(Insn::Next {
+        cursor_id,
+        pc_if_next: label_loop,
+    });
+    program.emit_insn(Insn::Goto {
+        target_pc: label_false,
+    });
+
+    program.preassign_label_to_next_insn(label_true);
+    program.emit_int(!*not_in as i64, target_register);
+    program.emit_insn(Insn::Goto {
+        target_pc: label_end,
+    });
+    program.preassign_label_to_next_insn(label_false);
+    program.emit_int(*not_in as i64, target_register);
+    program.emit_insn(Insn::Goto {
+        target_pc: label_end,
+    });
+    program.preassign_label_to_next_insn(label_null);
+    program.emit_insn(Insn::Null {
+        dest: target_register,
+        dest_end: None,
+    });
+    program.preassign_label_to_next_insn(label_end);
+    Ok(target_register)
+}
+
+/// The affinity and the collation `lhs IN (SELECT rhs ...)` compares with, which are those of
+/// the comparison `lhs = rhs`.
+fn in_comparison(
+    lhs: &ast::Expr,
+    lhs_tables: Option<&TableReferences>,
+    rhs: &ast::Expr,
+    rhs_tables: &TableReferences,
+) -> Result<(Affinity, CollationSeq)> {
+    let affinity = compare_affinity(rhs, get_expr_affinity(lhs, lhs_tables), Some(rhs_tables));
+    // An explicit COLLATE takes precedence over the collation of a column, and the left-hand
+    // side over the right-hand side.
+    let collation = match (
+        expr_collation(lhs, lhs_tables)?,
+        expr_collation(rhs, Some(rhs_tables))?,
+    ) {
+        (Some((collation, true)), _) | (_, Some((collation, true))) => collation,
+        (Some((collation, false)), _) | (None, Some((collation, false))) => collation,
+        (None, None) => CollationSeq::default(),
+    };
+    Ok((affinity, collation))
+}
+
+/// The collation of `expr` if it is a column or has a COLLATE clause, along with whether it
+/// comes from a COLLATE clause.
+fn expr_collation(
+    expr: &ast::Expr,
+    tables: Option<&TableReferences>,
+) -> Result<Option<(CollationSeq, bool)>> {
+    match expr {
+        ast::Expr::Collate(_, collation_name) => {
+            Ok(Some((CollationSeq::new(collation_name)?, true)))
+        }
+        ast::Expr::Column { table, column, .. } => Ok(tables
+            .and_then(|tables| tables.find_table_by_internal_id(*table))
+            .and_then(|table| table.get_column_at(*column))
+            .map(|column| (column.collation.unwrap_or_default(), false))),
+        _ => Ok(None),
+    }
+}
+
+/// Applies the affinities of the IN comparisons to the row in the registers from `start_reg`.
+fn emit_in_affinity(
+    program: &mut ProgramBuilder,
+    comparisons: &[(Affinity, CollationSeq)],
+    start_reg: usize,
+) {
+    if comparisons
+        .iter()
+        .all(|(affinity, _)| *affinity == Affinity::Blob)
+    {
+        return;
+    }
+    program.emit_insn(Insn::Affinity {
+        start_reg,
+        count: NonZeroUsize::new(comparisons.len()).unwrap(),
+        affinities: comparisons
+            .iter()
+            .map(|(affinity, _)| affinity.aff_mask())
+            .collect(),
+    });
+}
@@ -1,5 +1,6 @@
 use crate::translate::{ProgramBuilder, ProgramBuilderOpts};
-use crate::vdbe::insn::Insn;
+use crate::util::normalize_ident;
+use crate::vdbe::insn::{Insn, SavepointOp};
 use crate::Result;
 use turso_sqlite3_parser::ast::{Name, TransactionType};
 
@@ -50,3 +51,29 @@ pub fn translate_tx_commit(
     program.epilogue(super::emitter::TransactionMode::None);
     Ok(program)
 }
+
+pub fn translate_savepoint(name: Name, program: ProgramBuilder) -> Result<ProgramBuilder> {
+    translate_savepoint_op(SavepointOp::Begin, name, program)
+}
+
+pub fn translate_release(name: Name, program: ProgramBuilder) -> Result<ProgramBuilder> {
+    translate_savepoint_op(SavepointOp::Release, name, program)
+}
+
+pub fn translate_savepoint_op(
+    op: SavepointOp,
+    name: Name,
+    mut program: ProgramBuilder,
+) -> Result<ProgramBuilder> {
+    program.extend(&ProgramBuilderOpts {
+        num_cursors: 0,
+        approx_num_insns: 0,
+        approx_num_labels: 0,
+    });
+    program.emit_insn(Insn::Savepoint {
+        op,
+        name: normalize_ident(name.as_str()),
+    });
+    program.epilogue(super::emitter::TransactionMode::None);
+    Ok(program)
+}
@@ -0,0 +1,959 @@
+//! CREATE TRIGGER / DROP TRIGGER and the code generation of trigger programs.
+//!
+//! A trigger is compiled into a sub-program every time a statement that fires it is
+//! translated. The firing statement runs it with [Insn::Program], passing the OLD and NEW
+//! row as parameters: for a table with `n` columns, parameter 1 is OLD.rowid, parameters
+//! 2..=n+1 the OLD columns, parameter n+2 NEW.rowid and parameters n+3..=2n+2 the NEW
+//! columns. References to NEW/OLD in the trigger body are rewritten into those parameters
+//! before the body statements are translated. The ON DELETE and ON UPDATE actions of
+//! foreign keys are compiled and run the same way, see [super::fkey], and so are the
+//! INSTEAD OF triggers of views, see [super::instead_of].
+
+use std::rc::Rc;
+use std::sync::Arc;
+
+use turso_sqlite3_parser::ast::{
+    self, fmt::ToTokens, Expr, FromClause, OneSelect, QualifiedName, ResultColumn, Select,
+    SelectTable, TriggerCmd, TriggerEvent, TriggerTime, Upsert, UpsertDo,
+};
+
+use crate::parameters::PARAM_PREFIX;
+use crate::schema::{BTreeTable, Schema, Trigger};
+use crate::translate::emitter::{Resolver, TransactionMode};
+use crate::translate::expr::{translate_expr, walk_expr_mut};
+use crate::translate::fkey::{self, ForeignKeyChecks};
+use crate::translate::generated::GeneratedColumns;
+use crate::translate::planner::{
+    qualify_expr_tables, qualify_from_tables, qualify_select_tables, ROWID,
+};
+use crate::translate::schema::{emit_schema_entry, SchemaEntryType, SQLITE_TABLEID};
+use crate::translate::translate_inner;
+use crate::util::{normalize_ident, quote_ident};
+use crate::vdbe::builder::{CursorType, ProgramBuilder, ProgramBuilderOpts};
+use crate::vdbe::insn::{CmpInsFlags, Cookie, Insn, RegisterOrLiteral, SubProgram};
+use crate::vdbe::{BranchOffset, Program};
+use crate::{bail_parse_error, Connection, Result, SymbolTable};
+
+pub fn translate_create_trigger(
+    create_trigger: ast::CreateTrigger,
+    mut program: ProgramBuilder,
+    connection: &Arc<Connection>,
+) -> Result<ProgramBuilder> {
+    let opts = ProgramBuilderOpts {
+        num_cursors: 1,
+        approx_num_insns: 20,
+        approx_num_labels: 1,
+    };
+    program.extend(&opts);
+
+    if create_trigger.temporary {
+        bail_parse_error!("TEMP triggers are not supported yet");
+    }
+    // Like in SQLite, the trigger is created in the database of its table unless its name
+    // is qualified.
+    let database_id = match create_trigger.trigger_name.db_name {
+        Some(_) => connection.resolve_database_id(&create_trigger.trigger_name)?,
+        None => connection.resolve_database_id(&create_trigger.tbl_name)?,
+    };
+    let schema = &connection.database_schema(database_id);
+    let trigger_name = normalize_ident(create_trigger.trigger_name.name.as_str());
+    let tbl_name = normalize_ident(create_trigger.tbl_name.name.as_str());
+
+    if schema.get_trigger(&trigger_name).is_some() {
+        if create_trigger.if_not_exists {
+            program.epilogue(TransactionMode::Write);
+            return Ok(program);
+        }
+        bail_parse_error!("trigger {} already exists", trigger_name);
+    }
+    let table_name = if let Some(view) = schema.get_view(&tbl_name) {
+        match create_trigger.time {
+            Some(TriggerTime::InsteadOf) => {}
+            Some(TriggerTime::After) => {
+                bail_parse_error!("cannot create AFTER trigger on view: {}", tbl_name)
+            }
+            _ => bail_parse_error!("cannot create BEFORE trigger on view: {}", tbl_name),
+        }
+        view.name.clone()
+    } else {
+        let Some(table) = schema.get_table(&tbl_name) else {
+            bail_parse_error!("no such table: {}", tbl_name);
+        };
+        let Some(btree_table) = table.btree() else {
+            bail_parse_error!("cannot create trigger on virtual table: {}", tbl_name);
+        };
+        if tbl_name.starts_with("sqlite_") {
+            bail_parse_error!("cannot create trigger on system table");
+        }
+        if create_trigger.time == Some(TriggerTime::InsteadOf) {
+            bail_parse_error!("cannot create INSTEAD OF trigger on table: {}", tbl_name);
+        }
+        btree_table.name.clone()
+    };
+    // Only look for variables here: references to missing NEW/OLD columns are reported
+    // when the trigger fires, like SQLite does.
+    let refs = TriggerRefs {
+        table: None,
+        event: &create_trigger.event,
+    };
+    if let Some(when_clause) = &create_trigger.when_clause {
+        refs.rewrite_expr(&mut when_clause.clone())?;
+    }
+    for command in &create_trigger.commands {
+        refs.rewrite_cmd(&mut command.clone())?;
+    }
+
+    let sql = ast::Stmt::CreateTrigger(Box::new(create_trigger))
+        .format()
+        .map_err(|e| crate::LimboError::ParseError(e.to_string()))?;
+
+    let sqlite_table = schema.get_btree_table(SQLITE_TABLEID).unwrap();
+    let sqlite_schema_cursor_id =
+        program.alloc_cursor_id(CursorType::BTreeTable(sqlite_table.clone()));
+    program.emit_insn(Insn::OpenWrite {
+        cursor_id: sqlite_schema_cursor_id,
+        root_page: RegisterOrLiteral::Literal(sqlite_table.root_page),
+        db: database_id,
+    });
+    emit_schema_entry(
+        &mut program,
+        sqlite_schema_cursor_id,
+        SchemaEntryType::Trigger,
+        &trigger_name,
+        &table_name,
+        0,
+        Some(sql),
+    );
+    program.emit_insn(Insn::SetCookie {
+        db: database_id,
+        cookie: Cookie::SchemaVersion,
+        value: schema.schema_version as i32 + 1,
+        p5: 0,
+    });
+    program.emit_insn(Insn::ParseSchema {
+        db: database_id,
+        where_clause: Some(format!("name = '{trigger_name}' AND type = 'trigger'")),
+    });
+    program.emit_insn(Insn::Close {
+        cursor_id: sqlite_schema_cursor_id,
+    });
+
+    program.epilogue(TransactionMode::Write);
+
+    Ok(program)
+}
+
+pub fn translate_drop_trigger(
+    trigger_name: &QualifiedName,
+    if_exists: bool,
+    mut program: ProgramBuilder,
+    connection: &Arc<Connection>,
+) -> Result<ProgramBuilder> {
+    let opts = ProgramBuilderOpts {
+        num_cursors: 1,
+        approx_num_insns: 20,
+        approx_num_labels: 3,
+    };
+    program.extend(&opts);
+
+    let database_id = match trigger_name.db_name {
+        Some(_) => connection.resolve_database_id(trigger_name)?,
+        None if connection.with_schema(1, |schema| {
+            schema.get_trigger(trigger_name.name.as_str()).is_some()
+        }) =>
+        {
+            1
+        }
+        None => 0,
+    };
+    let schema = &connection.database_schema(database_id);
+    let trigger_name = normalize_ident(trigger_name.name.as_str());
+    if schema.get_trigger(&trigger_name).is_none() {
+        if if_exists {
+            program.epilogue(TransactionMode::Write);
+            return Ok(program);
+        }
+        bail_parse_error!("no such trigger: {}", trigger_name);
+    }
+
+    let trigger_name_reg = program.emit_string8_new_reg(trigger_name.clone());
+    let trigger_type_reg = program.emit_string8_new_reg("trigger".to_string());
+    let row_id_reg = program.alloc_register();
+
+    let sqlite_table = schema.get_btree_table(SQLITE_TABLEID).unwrap();
+    let sqlite_schema_cursor_id =
+        program.alloc_cursor_id(CursorType::BTreeTable(sqlite_table.clone()));
+    program.emit_insn(Insn::OpenWrite {
+        cursor_id: sqlite_schema_cursor_id,
+        root_page: RegisterOrLiteral::Literal(sqlite_table.root_page),
+        db: database_id,
+    });
+
+    let loop_start_label = program.allocate_label();
+    let loop_end_label = program.allocate_label();
+    program.emit_insn(Insn::Rewind {
+        cursor_id: sqlite_schema_cursor_id,
+        pc_if_empty: loop_end_label,
+    });
+    program.preassign_label_to_next_insn(loop_start_label);
+
+    // skip if sqlite_schema.name != trigger_name or sqlite_schema.type != 'trigger'
+    let dest_reg = program.alloc_register();
+    let next_label = program.allocate_label();
+    program.emit_column(sqlite_schema_cursor_id, 1, dest_reg);
+    program.emit_insn(Insn::Ne {
+        lhs: trigger_name_reg,
+        rhs: dest_reg,
+        target_pc: next_label,
+        flags: CmpInsFlags::default(),
+        collation: program.curr_collation(),
+    });
+    program.emit_column(sqlite_schema_cursor_id, 0, dest_reg);
+    program.emit_insn(Insn::Ne {
+        lhs: trigger_type_reg,
+        rhs: dest_reg,
+        target_pc: next_label,
+        flags: CmpInsFlags::default(),
+        collation: program.curr_collation(),
+    });
+    program.emit_insn(Insn::RowId {
+        cursor_id: sqlite_schema_cursor_id,
+        dest: row_id_reg,
+    });
+    program.emit_insn(Insn::Delete {
+        cursor_id: sqlite_schema_cursor_id,
+    });
+
+    program.preassign_label_to_next_insn(next_label);
+    program.emit_insn(Insn::Next {
+        cursor_id: sqlite_schema_cursor_id,
+        pc_if_next: loop_start_label,
+    });
+    program.preassign_label_to_next_insn(loop_end_label);
+
+    program.emit_insn(Insn::SetCookie {
+        db: database_id,
+        cookie: Cookie::SchemaVersion,
+        value: schema.schema_version as i32 + 1,
+        p5: 0,
+    });
+    program.emit_insn(Insn::DropTrigger {
+        db: database_id,
+        trigger_name,
+    });
+
+    program.epilogue(TransactionMode::Write);
+
+    Ok(program)
+}
+
+/// The trigger programs a statement fires for each row it changes in a table, along with
+/// the foreign key checks and actions of the row.
+#[derive(Debug, Clone)]
+pub struct TableTriggers {
+    pub before: Vec<SubProgram>,
+    /// The foreign key actions, then the AFTER triggers.
+    pub after: Vec<SubProgram>,
+    foreign_keys: ForeignKeyChecks,
+    /// Number of columns of the table the triggers are defined on.
+    num_columns: usize,
+}
+
+impl TableTriggers {
+    /// Compile the triggers on `table` that fire for `event`. `updated_columns` are the
+    /// columns assigned by an UPDATE statement and are matched against `UPDATE OF` triggers.
+    #[allow(clippy::too_many_arguments)]
+    pub fn compile(
+        program: &ProgramBuilder,
+        schema: &Schema,
+        database_id: usize,
+        syms: &SymbolTable,
+        connection: &Arc<Connection>,
+        table: &BTreeTable,
+        event: TriggerEvent,
+        updated_columns: &[&str],
+    ) -> Result<Self> {
+        let mut before = Vec::new();
+        let mut after = fkey::compile_actions(
+            program,
+            schema,
+            database_id,
+            syms,
+            connection,
+            table,
+            &event,
+            updated_columns,
+        )?;
+        for trigger in schema.get_triggers(&table.name) {
+            if program.is_trigger_active(&trigger.name) {
+                continue;
+            }
+            let programs = if trigger.fires_on(TriggerTime::Before, &event, updated_columns) {
+                &mut before
+            } else if trigger.fires_on(TriggerTime::After, &event, updated_columns) {
+                &mut after
+            } else {
+                continue;
+            };
+            programs.push(SubProgram::Program(compile_trigger(
+                trigger,
+                table,
+                program,
+                schema,
+                database_id,
+                syms,
+                connection,
+            )?));
+        }
+        Ok(Self {
+            before,
+            after,
+            foreign_keys: ForeignKeyChecks::compile(
+                schema,
+                database_id,
+                connection,
+                table,
+                &event,
+                updated_columns,
+            )?,
+            num_columns: table.columns.len(),
+        })
+    }
+
+    /// Compile the INSTEAD OF triggers on the view `view` that fire for `event`, where
+    /// `view` has the result columns of the view as columns. They are kept with the BEFORE
+    /// triggers, as nothing else runs for a row of a view.
+    #[allow(clippy::too_many_arguments)]
+    pub fn compile_instead_of(
+        program: &ProgramBuilder,
+        schema: &Schema,
+        database_id: usize,
+        syms: &SymbolTable,
+        connection: &Arc<Connection>,
+        view: &BTreeTable,
+        event: TriggerEvent,
+        updated_columns: &[&str],
+    ) -> Result<Self> {
+        let mut before = Vec::new();
+        for trigger in schema.get_triggers(&view.name) {
+            if program.is_trigger_active(&trigger.name)
+                || !trigger.fires_on(TriggerTime::InsteadOf, &event, updated_columns)
+            {
+                continue;
+            }
+            before.push(SubProgram::Program(compile_trigger(
+                trigger,
+                view,
+                program,
+                schema,
+                database_id,
+                syms,
+                connection,
+            )?));
+        }
+        Ok(Self {
+            before,
+            after: vec![],
+            foreign_keys: ForeignKeyChecks::default(),
+            num_columns: view.columns.len(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.before.is_empty() && self.after.is_empty() && self.foreign_keys.is_empty()
+    }
+
+    /// Allocate the registers holding the OLD and NEW row passed to the trigger programs.
+    pub fn alloc_params(&self, program: &mut ProgramBuilder) -> TriggerParams {
+        TriggerParams::alloc(program, self.num_columns)
+    }
+
+    pub fn emit_before(
+        &self,
+        program: &mut ProgramBuilder,
+        params: &TriggerParams,
+        ignore_label: BranchOffset,
+    ) {
+        emit_fire_triggers(program, &self.before, params, ignore_label);
+    }
+
+    pub fn emit_instead_of(
+        &self,
+        program: &mut ProgramBuilder,
+        params: &TriggerParams,
+        ignore_label: BranchOffset,
+    ) {
+        emit_fire_triggers(program, &self.before, params, ignore_label);
+    }
+
+    pub fn emit_after(
+        &self,
+        program: &mut ProgramBuilder,
+        params: &TriggerParams,
+        ignore_label: BranchOffset,
+    ) {
+        emit_fire_triggers(program, &self.after, params, ignore_label);
+    }
+
+    /// Check the foreign keys of the OLD row, before it is deleted or overwritten.
+    pub fn emit_old_foreign_key_checks(
+        &self,
+        program: &mut ProgramBuilder,
+        params: &TriggerParams,
+    ) {
+        self.foreign_keys.emit_old(program, params);
+    }
+
+    /// Check the foreign keys of the NEW row, once it is written.
+    pub fn emit_new_foreign_key_checks(
+        &self,
+        program: &mut ProgramBuilder,
+        params: &TriggerParams,
+    ) {
+        self.foreign_keys.emit_new(program, params);
+    }
+}
+
+/// Registers holding the OLD row followed by the NEW row, each as rowid then columns.
+pub struct TriggerParams {
+    start_reg: usize,
+    num_columns: usize,
+}
+
+impl TriggerParams {
+    pub fn alloc(program: &mut ProgramBuilder, num_columns: usize) -> Self {
+        Self {
+            start_reg: program.alloc_registers(2 * (num_columns + 1)),
+            num_columns,
+        }
+    }
+
+    pub fn old_rowid_reg(&self) -> usize {
+        self.start_reg
+    }
+
+    pub fn old_columns_start_reg(&self) -> usize {
+        self.start_reg + 1
+    }
+
+    pub fn new_rowid_reg(&self) -> usize {
+        self.start_reg + self.num_columns + 1
+    }
+
+    pub fn new_columns_start_reg(&self) -> usize {
+        self.start_reg + self.num_columns + 2
+    }
+
+    /// Set the OLD row to NULL, for INSERT triggers.
+    pub fn emit_null_old(&self, program: &mut ProgramBuilder) {
+        program.emit_null(
+            self.old_rowid_reg(),
+            Some(self.old_rowid_reg() + self.num_columns),
+        );
+    }
+
+    /// Set the NEW row to NULL, for DELETE triggers.
+    pub fn emit_null_new(&self, program: &mut ProgramBuilder) {
+        program.emit_null(
+            self.new_rowid_reg(),
+            Some(self.new_rowid_reg() + self.num_columns),
+        );
+    }
+
+    /// Read the OLD row from the row `cursor_id` points to. The OLD.rowid of a table without
+    /// rowid is NULL.
+    pub fn emit_old_from_cursor(
+        &self,
+        program: &mut ProgramBuilder,
+        resolver: &Resolver,
+        table: &Rc<BTreeTable>,
+        cursor_id: usize,
+    ) -> Result<()> {
+        if table.has_rowid {
+            program.emit_insn(Insn::RowId {
+                cursor_id,
+                dest: self.old_rowid_reg(),
+            });
+        } else {
+            program.emit_null(self.old_rowid_reg(), None);
+        }
+        for (i, column) in table.columns.iter().enumerate() {
+            if column.is_rowid_alias {
+                program.emit_insn(Insn::Copy {
+                    src_reg: self.old_rowid_reg(),
+                    dst_reg: self.old_columns_start_reg() + i,
+                    extra_amount: 0,
+                });
+            } else {
+                program.emit_table_column(cursor_id, i, self.old_columns_start_reg() + i);
+            }
+        }
+        GeneratedColumns::compile(program, table)?.emit_virtual(
+            program,
+            resolver,
+            self.old_rowid_reg(),
+            self.old_columns_start_reg(),
+        )
+    }
+
+    /// Copy a row laid out as a rowid register followed by the column registers into the
+    /// OLD or NEW row. The rowid alias column, whose register holds NULL, gets the rowid.
+    /// A NULL rowid, as seen by BEFORE INSERT triggers of rows without an explicit rowid,
+    /// is passed as -1, unless the table has no rowid.
+    pub fn emit_copy_row(
+        &self,
+        program: &mut ProgramBuilder,
+        table: &BTreeTable,
+        rowid_reg: usize,
+        columns_start_reg: usize,
+        old: bool,
+    ) {
+        let (dst_rowid_reg, dst_columns_start_reg) = if old {
+            (self.old_rowid_reg(), self.old_columns_start_reg())
+        } else {
+            (self.new_rowid_reg(), self.new_columns_start_reg())
+        };
+        program.emit_insn(Insn::Copy {
+            src_reg: rowid_reg,
+            dst_reg: dst_rowid_reg,
+            extra_amount: 0,
+        });
+        if table.has_rowid {
+            let rowid_not_null_label = program.allocate_label();
+            program.emit_insn(Insn::NotNull {
+                reg: dst_rowid_reg,
+                target_pc: rowid_not_null_label,
+            });
+            program.emit_int(-1, dst_rowid_reg);
+            program.preassign_label_to_next_insn(rowid_not_null_label);
+        }
+        program.emit_insn(Insn::Copy {
+            src_reg: columns_start_reg,
+            dst_reg: dst_columns_start_reg,
+            extra_amount: self.num_columns - 1,
+        });
+        if let Some((idx, _)) = table.get_rowid_alias_column() {
+            program.emit_insn(Insn::Copy {
+                src_reg: dst_rowid_reg,
+                dst_reg: dst_columns_start_reg + idx,
+                extra_amount: 0,
+            });
+        }
+    }
+}
+
+fn emit_fire_triggers(
+    program: &mut ProgramBuilder,
+    triggers: &[SubProgram],
+    params: &TriggerParams,
+    ignore_label: BranchOffset,
+) {
+    for trigger in triggers {
+        program.emit_insn(Insn::Program {
+            param_start_reg: params.start_reg,
+            num_params: 2 * (params.num_columns + 1),
+            program: trigger.clone(),
+            ignore_jump_target: ignore_label,
+        });
+    }
+}
+
+fn compile_trigger(
+    trigger: &Trigger,
+    table: &BTreeTable,
+    parent: &ProgramBuilder,
+    schema: &Schema,
+    database_id: usize,
+    syms: &SymbolTable,
+    connection: &Arc<Connection>,
+) -> Result<Rc<Program>> {
+    let refs = TriggerRefs {
+        table: Some(table),
+        event: &trigger.event,
+    };
+    let when_clause = match &trigger.when_clause {
+        Some(when_clause) => {
+            let mut when_clause = when_clause.clone();
+            refs.rewrite_expr(&mut when_clause)?;
+            Some(when_clause)
+        }
+        None => None,
+    };
+    // Like in SQLite, the tables the body of a trigger of an attached database uses are in
+    // that database.
+    let database_name = match database_id {
+        0 | 1 => None,
+        _ => Some(quote_ident(&connection.get_database_name(database_id))),
+    };
+    let mut stmts = Vec::with_capacity(trigger.commands.len());
+    for command in &trigger.commands {
+        let mut command = command.clone();
+        refs.rewrite_cmd(&mut command)?;
+        let mut stmt = trigger_cmd_to_stmt(command);
+        if let Some(database_name) = &database_name {
+            qualify_stmt_tables(&mut stmt, database_name)?;
+        }
+        stmts.push(stmt);
+    }
+    compile_sub_program(
+        parent,
+        &trigger.name,
+        when_clause.as_ref(),
+        stmts,
+        schema,
+        syms,
+        connection,
+    )
+}
+
+/// Compile the sub-program `name`, which runs `stmts` when `when_clause` is true. The
+/// statements refer to the row that fired it through parameters, see the module docs.
+pub(crate) fn compile_sub_program(
+    parent: &ProgramBuilder,
+    name: &str,
+    when_clause: Option<&Expr>,
+    stmts: Vec<ast::Stmt>,
+    schema: &Schema,
+    syms: &SymbolTable,
+    connection: &Arc<Connection>,
+) -> Result<Rc<Program>> {
+    let mut program = parent.new_trigger_program(name);
+    program.prologue();
+
+    let end_label = program.allocate_label();
+    if let Some(when_clause) = when_clause {
+        let reg = program.alloc_register();
+        translate_expr(
+            &mut program,
+            None,
+            when_clause,
+            reg,
+            &Resolver::new(schema, syms),
+        )?;
+        program.emit_insn(Insn::IfNot {
+            reg,
+            target_pc: end_label,
+            jump_if_null: true,
+        });
+    }
+
+    for stmt in stmts {
+        program.incr_nesting();
+        program = translate_inner(schema, stmt, syms, program, connection)?;
+        program.decr_nesting();
+    }
+
+    program.preassign_label_to_next_insn(end_label);
+    program.epilogue(TransactionMode::None);
+
+    Ok(program.build_sub_program(connection.clone()))
+}
+
+fn trigger_cmd_to_stmt(command: TriggerCmd) -> ast::Stmt {
+    match command {
+        TriggerCmd::Update(update) => {
+            let ast::TriggerCmdUpdate {
+                or_conflict,
+                tbl_name,
+                sets,
+                from,
+                where_clause,
+            } = *update;
+            ast::Stmt::Update(Box::new(ast::Update {
+                with: None,
+                or_conflict,
+                tbl_name: QualifiedName::single(tbl_name),
+                indexed: None,
+                sets,
+                from,
+                where_clause: where_clause.map(Box::new),
+                returning: None,
+                order_by: None,
+                limit: None,
+            }))
+        }
+        TriggerCmd::Insert(insert) => {
+            let ast::TriggerCmdInsert {
+                or_conflict,
+                tbl_name,
+                col_names,
+                select,
+                upsert,
+                returning,
+            } = *insert;
+            ast::Stmt::Insert(Box::new(ast::Insert {
+                with: None,
+                or_conflict,
+                tbl_name: QualifiedName::single(tbl_name),
+                columns: col_names,
+                body: ast::InsertBody::Select(select, upsert),
+                returning,
+            }))
+        }
+        TriggerCmd::Delete(delete) => {
+            let ast::TriggerCmdDelete {
+                tbl_name,
+                where_clause,
+            } = *delete;
+            ast::Stmt::Delete(Box::new(ast::Delete {
+                with: None,
+                tbl_name: QualifiedName::single(tbl_name),
+                indexed: None,
+                where_clause: where_clause.map(Box::new),
+                returning: None,
+                order_by: None,
+                limit: None,
+            }))
+        }
+        TriggerCmd::Select(select) => ast::Stmt::Select(select),
+    }
+}
+
+/// Qualifies the unqualified table names the trigger body statement `stmt` uses with
+/// `database_name`.
+fn qualify_stmt_tables(stmt: &mut ast::Stmt, database_name: &str) -> Result<()> {
+    let qualify_name = |name: &mut QualifiedName| {
+        if name.db_name.is_none() {
+            name.db_name = Some(ast::Name::from_str(database_name));
+        }
+    };
+    let ctes = &mut vec![];
+    match stmt {
+        ast::Stmt::Update(update) => {
+            qualify_name(&mut update.tbl_name);
+            if let Some(from) = &mut update.from {
+                qualify_from_tables(from, database_name, ctes)?;
+            }
+            for set in &mut update.sets {
+                qualify_expr_tables(&mut set.expr, database_name, ctes)?;
+            }
+            if let Some(where_clause) = &mut update.where_clause {
+                qualify_expr_tables(where_clause, database_name, ctes)?;
+            }
+        }
+        ast::Stmt::Insert(insert) => {
+            qualify_name(&mut insert.tbl_name);
+            if let ast::InsertBody::Select(select, _) = &mut insert.body {
+                qualify_select_tables(select, database_name, ctes)?;
+            }
+        }
+        ast::Stmt::Delete(delete) => {
+            qualify_name(&mut delete.tbl_name);
+            if let Some(where_clause) = &mut delete.where_clause {
+                qualify_expr_tables(where_clause, database_name, ctes)?;
+            }
+        }
+        ast::Stmt::Select(select) => qualify_select_tables(select, database_name, ctes)?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Rewrites the NEW.x and OLD.x references of a trigger body into the parameters
+/// holding the row that fired the trigger.
+struct TriggerRefs<'a> {
+    /// The table the trigger is defined on, `None` to only validate the body.
+    table: Option<&'a BTreeTable>,
+    event: &'a TriggerEvent,
+}
+
+impl TriggerRefs<'_> {
+    fn rewrite_cmd(&self, command: &mut TriggerCmd) -> Result<()> {
+        match command {
+            TriggerCmd::Update(update) => {
+                for set in update.sets.iter_mut() {
+                    self.rewrite_expr(&mut set.expr)?;
+                }
+                if let Some(from) = &mut update.from {
+                    self.rewrite_from(from)?;
+                }
+                if let Some(where_clause) = &mut update.where_clause {
+                    self.rewrite_expr(where_clause)?;
+                }
+            }
+            TriggerCmd::Insert(insert) => {
+                self.rewrite_select(&mut insert.select)?;
+                let mut upsert = insert.upsert.as_mut();
+                while let Some(Upsert {
+                    index,
+                    do_clause,
+                    next,
+                }) = upsert
+                {
+                    if let Some(index) = index {
+                        for target in index.targets.iter_mut() {
+                            self.rewrite_expr(&mut target.expr)?;
+                        }
+                        if let Some(where_clause) = &mut index.where_clause {
+                            self.rewrite_expr(where_clause)?;
+                        }
+                    }
+                    if let UpsertDo::Set { sets, where_clause } = do_clause.as_mut() {
+                        for set in sets.iter_mut() {
+                            self.rewrite_expr(&mut set.expr)?;
+                        }
+                        if let Some(where_clause) = where_clause {
+                            self.rewrite_expr(where_clause)?;
+                        }
+                    }
+                    upsert = next.as_deref_mut();
+                }
+                if let Some(returning) = &mut insert.returning {
+                    self.rewrite_result_columns(returning)?;
+                }
+            }
+            TriggerCmd::Delete(delete) => {
+                if let Some(where_clause) = &mut delete.where_clause {
+                    self.rewrite_expr(where_clause)?;
+                }
+            }
+            TriggerCmd::Select(select) => self.rewrite_select(select)?,
+        }
+        Ok(())
+    }
+
+    fn rewrite_select(&self, select: &mut Select) -> Result<()> {
+        if let Some(with) = &mut select.with {
+            for cte in with.ctes.iter_mut() {
+                self.rewrite_select(&mut cte.select)?;
+            }
+        }
+        self.rewrite_one_select(&mut select.body.select)?;
+        if let Some(compounds) = &mut select.body.compounds {
+            for compound in compounds.iter_mut() {
+                self.rewrite_one_select(&mut compound.select)?;
+            }
+        }
+        if let Some(order_by) = &mut select.order_by {
+            for column in order_by.iter_mut() {
+                self.rewrite_expr(&mut column.expr)?;
+            }
+        }
+        if let Some(limit) = &mut select.limit {
+            self.rewrite_expr(&mut limit.expr)?;
+            if let Some(offset) = &mut limit.offset {
+                self.rewrite_expr(offset)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn rewrite_one_select(&self, select: &mut OneSelect) -> Result<()> {
+        match select {
+            OneSelect::Select(select) => {
+                self.rewrite_result_columns(&mut select.columns)?;
+                if let Some(from) = &mut select.from {
+                    self.rewrite_from(from)?;
+                }
+                if let Some(where_clause) = &mut select.where_clause {
+                    self.rewrite_expr(where_clause)?;
+                }
+                if let Some(group_by) = &mut select.group_by {
+                    for expr in group_by.exprs.iter_mut() {
+                        self.rewrite_expr(expr)?;
+                    }
+                    if let Some(having) = &mut group_by.having {
+                        self.rewrite_expr(having)?;
+                    }
+                }
+            }
+            OneSelect::Values(values) => {
+                for row in values.iter_mut() {
+                    for expr in row.iter_mut() {
+                        self.rewrite_expr(expr)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn rewrite_result_columns(&self, columns: &mut [ResultColumn]) -> Result<()> {
+        for column in columns.iter_mut() {
+            if let ResultColumn::Expr(expr, _) = column {
+                self.rewrite_expr(expr)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn rewrite_from(&self, from: &mut FromClause) -> Result<()> {
+        if let Some(select) = &mut from.select {
+            self.rewrite_select_table(select)?;
+        }
+        if let Some(joins) = &mut from.joins {
+            for join in joins.iter_mut() {
+                self.rewrite_select_table(&mut join.table)?;
+                if let Some(ast::JoinConstraint::On(expr)) = &mut join.constraint {
+                    self.rewrite_expr(expr)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn rewrite_select_table(&self, table: &mut SelectTable) -> Result<()> {
+        match table {
+            SelectTable::Table(..) => {}
+            SelectTable::TableCall(_, args, _) => {
+                for arg in args.iter_mut().flatten() {
+                    self.rewrite_expr(arg)?;
+                }
+            }
+            SelectTable::Select(select, _) => self.rewrite_select(select)?,
+            SelectTable::Sub(from, _) => self.rewrite_from(from)?,
+        }
+        Ok(())
+    }
+
+    fn rewrite_expr(&self, expr: &mut Expr) -> Result<()> {
+        walk_expr_mut(expr, &mut |expr: &mut Expr| -> Result<()> {
+            match expr {
+                Expr::Variable(_) => bail_parse_error!("trigger cannot use variables"),
+                Expr::Qualified(tbl, col) => {
+                    let tbl = normalize_ident(tbl.as_str());
+                    let old = match tbl.as_str() {
+                        "old" => true,
+                        "new" => false,
+                        _ => return Ok(()),
+                    };
+                    if let Some(index) = self.param_index(old, col.as_str())? {
+                        *expr = Expr::Variable(format!("{PARAM_PREFIX}{index}"));
+                    }
+                }
+                Expr::Exists(select) | Expr::Subquery(select) => self.rewrite_select(select)?,
+                Expr::InSelect { rhs, .. } => self.rewrite_select(rhs)?,
+                _ => {}
+            }
+            Ok(())
+        })
+    }
+
+    /// The parameter index of OLD.`column` or NEW.`column`.
+    fn param_index(&self, old: bool, column: &str) -> Result<Option<usize>> {
+        let Some(table) = self.table else {
+            return Ok(None);
+        };
+        let qualifier = if old { "OLD" } else { "NEW" };
+        match (old, self.event) {
+            (true, TriggerEvent::Insert) | (false, TriggerEvent::Delete) => {
+                bail_parse_error!("no such column: {}.{}", qualifier, column)
+            }
+            _ => {}
+        }
+        let rowid_param = if old { 1 } else { table.columns.len() + 2 };
+        let index = match table.get_column(column) {
+            Some((_, col)) if col.is_rowid_alias => rowid_param,
+            Some((idx, _)) => rowid_param + 1 + idx,
+            None => {
+                let column = normalize_ident(column);
+                if column == ROWID || column == "oid" || column == "_rowid_" {
+                    rowid_param
+                } else {
+                    bail_parse_error!("no such column: {}.{}", qualifier, column)
+                }
+            }
+        };
+        Ok(Some(index))
+    }
+}
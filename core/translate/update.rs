@@ -2,7 +2,7 @@ use std::collections::HashMap;
 use std::rc::Rc;
 use std::sync::Arc;
 
-use crate::schema::{BTreeTable, Column, Type};
+use crate::schema::{BTreeTable, Column, Index, IndexColumn, Type};
 use crate::translate::optimizer::optimize_select_plan;
 use crate::translate::plan::{Operation, QueryDestination, Search, SelectPlan};
 use crate::vdbe::builder::CursorType;
@@ -13,17 +13,24 @@ use crate::{
     vdbe::builder::{ProgramBuilder, ProgramBuilderOpts},
     SymbolTable,
 };
-use turso_sqlite3_parser::ast::{Expr, SortOrder, Update};
+use turso_sqlite3_parser::ast::{Expr, ResolveType, SortOrder, TriggerEvent, Update};
 
+use super::check::CheckConstraints;
+use super::cte::parse_dml_with;
 use super::emitter::emit_program;
 use super::expr::process_returning_clause;
+use super::fkey::ForeignKeyChecks;
+use super::generated::dependent_generated_columns;
+use super::instead_of::translate_view_update;
 use super::optimizer::optimize_plan;
 use super::plan::{
     ColumnUsedMask, IterationDirection, JoinedTable, Plan, ResultSetColumn, TableReferences,
     UpdatePlan,
 };
 use super::planner::bind_column_references;
-use super::planner::{parse_limit, parse_where};
+use super::planner::{parse_limit, parse_where, resolve_index_hint};
+use super::subquery::plan_subqueries_from_expr;
+use super::trigger::TableTriggers;
 /*
 * Update is simple. By default we scan the table, and for each row, we check the WHERE
 * clause. If it evaluates to true, we build the new record with the updated value and insert.
@@ -54,13 +61,25 @@ addr  opcode         p1    p2    p3    p4             p5  comment
 18    Goto           0     1     0                    0
 */
 pub fn translate_update(
-    schema: &Schema,
     body: &mut Update,
     syms: &SymbolTable,
     mut program: ProgramBuilder,
     connection: &Arc<crate::Connection>,
 ) -> crate::Result<ProgramBuilder> {
-    let mut plan = prepare_update_plan(&mut program, schema, body, connection)?;
+    let database_id = connection.resolve_database_id(&body.tbl_name)?;
+    let schema = &connection.database_schema(database_id);
+    if let Some(view) = schema.get_view(body.tbl_name.name.as_str()) {
+        return translate_view_update(
+            schema,
+            database_id,
+            &view,
+            body.clone(),
+            syms,
+            program,
+            connection,
+        );
+    }
+    let mut plan = prepare_update_plan(&mut program, schema, database_id, syms, body, connection)?;
     optimize_plan(&mut plan, schema)?;
     // TODO: freestyling these numbers
     let opts = ProgramBuilderOpts {
@@ -74,14 +93,15 @@ pub fn translate_update(
 }
 
 pub fn translate_update_with_after(
-    schema: &Schema,
     body: &mut Update,
     syms: &SymbolTable,
     mut program: ProgramBuilder,
     connection: &Arc<crate::Connection>,
     after: impl FnOnce(&mut ProgramBuilder),
 ) -> crate::Result<ProgramBuilder> {
-    let mut plan = prepare_update_plan(&mut program, schema, body, connection)?;
+    let database_id = connection.resolve_database_id(&body.tbl_name)?;
+    let schema = &connection.database_schema(database_id);
+    let mut plan = prepare_update_plan(&mut program, schema, database_id, syms, body, connection)?;
     optimize_plan(&mut plan, schema)?;
     // TODO: freestyling these numbers
     let opts = ProgramBuilderOpts {
@@ -97,15 +117,11 @@ pub fn translate_update_with_after(
 pub fn prepare_update_plan(
     program: &mut ProgramBuilder,
     schema: &Schema,
+    database_id: usize,
+    syms: &SymbolTable,
     body: &mut Update,
     connection: &Arc<crate::Connection>,
 ) -> crate::Result<Plan> {
-    if body.with.is_some() {
-        bail_parse_error!("WITH clause is not supported");
-    }
-    if body.or_conflict.is_some() {
-        bail_parse_error!("ON CONFLICT clause is not supported");
-    }
     let table_name = &body.tbl_name.name;
     if schema.table_has_indexes(&table_name.to_string()) && !schema.indexes_enabled() {
         // Let's disable altering a table with indices altogether instead of checking column by
@@ -114,6 +130,9 @@ pub fn prepare_update_plan(
             "UPDATE table disabled for table with indexes is disabled by default. Run with `--experimental-indexes` to enable this feature."
         );
     }
+    if schema.get_view(table_name.as_str()).is_some() {
+        bail_parse_error!("cannot modify {} because it is a view", table_name);
+    }
     let table = match schema.get_table(table_name.as_str()) {
         Some(table) => table,
         None => bail_parse_error!("Parse error: no such table: {}", table_name),
@@ -130,6 +149,10 @@ pub fn prepare_update_plan(
             })
         })
         .unwrap_or(IterationDirection::Forwards);
+    let index_hint = resolve_index_hint(
+        body.indexed.clone(),
+        schema.get_indices(table_name.as_str()),
+    )?;
 
     let joined_tables = vec![JoinedTable {
         table: match table.as_ref() {
@@ -145,9 +168,11 @@ pub fn prepare_update_plan(
         },
         join_info: None,
         col_used_mask: ColumnUsedMask::default(),
-        database_id: 0,
+        database_id,
+        index_hint: index_hint.clone(),
     }];
     let mut table_references = TableReferences::new(joined_tables, vec![]);
+    let ctes = parse_dml_with(body.with.clone())?;
 
     let column_lookup: HashMap<String, usize> = table
         .columns()
@@ -157,13 +182,27 @@ pub fn prepare_update_plan(
         .collect();
 
     let mut set_clauses = Vec::with_capacity(body.sets.len());
+    let mut non_from_clause_subqueries = vec![];
     for set in &mut body.sets {
         let ident = normalize_ident(set.col_names[0].as_str());
         let Some(col_index) = column_lookup.get(&ident) else {
             bail_parse_error!("no such column: {}", ident);
         };
+        if table.columns()[*col_index].is_generated() {
+            bail_parse_error!("cannot UPDATE generated column \"{}\"", ident);
+        }
 
         bind_column_references(&mut set.expr, &mut table_references, None, connection)?;
+        plan_subqueries_from_expr(
+            schema,
+            syms,
+            &mut set.expr,
+            &mut table_references,
+            &mut non_from_clause_subqueries,
+            &ctes,
+            &mut program.table_reference_counter,
+            connection,
+        )?;
 
         if let Some(idx) = set_clauses.iter().position(|(idx, _)| *idx == *col_index) {
             set_clauses[idx].1 = set.expr.clone();
@@ -203,102 +242,235 @@ pub fn prepare_update_plan(
     let rowid_alias_used = set_clauses.iter().fold(false, |accum, (idx, _)| {
         accum || columns[*idx].is_rowid_alias
     });
+    let clustered_index = table
+        .btree()
+        .and_then(|btree_table| schema.get_clustered_index(&btree_table).cloned());
+    let without_rowid = table
+        .btree()
+        .is_some_and(|btree_table| !btree_table.has_rowid);
+    // Rows of tables without rowid are ordered by their primary key, so updating it moves them.
+    let primary_key_used =
+        without_rowid && set_clauses.iter().any(|(idx, _)| columns[*idx].primary_key);
 
-    let (ephemeral_plan, mut where_clause) = if rowid_alias_used {
-        let mut where_clause = vec![];
-        let internal_id = program.table_reference_counter.next();
+    let triggers = match table.btree() {
+        Some(btree_table) => {
+            let updated_columns = set_clauses
+                .iter()
+                .filter_map(|(idx, _)| columns[*idx].name.as_deref())
+                .collect::<Vec<_>>();
+            let triggers = TableTriggers::compile(
+                program,
+                schema,
+                database_id,
+                syms,
+                connection,
+                &btree_table,
+                TriggerEvent::Update,
+                &updated_columns,
+            )?;
+            (!triggers.is_empty()).then_some(triggers)
+        }
+        None => None,
+    };
 
-        let joined_tables = vec![JoinedTable {
-            table: match table.as_ref() {
-                Table::Virtual(vtab) => Table::Virtual(vtab.clone()),
-                Table::BTree(btree_table) => Table::BTree(btree_table.clone()),
-                _ => unreachable!(),
-            },
-            identifier: table_name.as_str().to_string(),
-            internal_id,
-            op: Operation::Scan {
-                iter_dir,
-                index: None,
-            },
-            join_info: None,
-            col_used_mask: ColumnUsedMask::default(),
-            database_id: 0,
-        }];
-        let mut table_references = TableReferences::new(joined_tables, vec![]);
+    // The generated columns that depend on the updated columns are recomputed.
+    let mut updated_columns = set_clauses.iter().map(|(idx, _)| *idx).collect::<Vec<_>>();
+    if let Some(btree_table) = table.btree() {
+        updated_columns.extend(dependent_generated_columns(&btree_table, &updated_columns));
+    }
 
-        // Parse the WHERE clause
-        parse_where(
-            body.where_clause.as_ref().map(|w| *w.clone()),
-            &mut table_references,
-            Some(&result_columns),
-            &mut where_clause,
+    let checks = match table.btree() {
+        Some(btree_table) => {
+            CheckConstraints::compile(program, connection, &btree_table, Some(&updated_columns))?
+        }
+        None => CheckConstraints::default(),
+    };
+
+    // REPLACE deletes the rows the updated rows conflict with.
+    let may_replace = match body.or_conflict {
+        Some(or_conflict) => or_conflict == ResolveType::Replace,
+        None => table.btree().is_some_and(|btree_table| {
+            btree_table
+                .conflict_clauses
+                .iter()
+                .any(|(_, conflict_clause)| *conflict_clause == ResolveType::Replace)
+        }),
+    };
+    let replace_foreign_keys = match table.btree() {
+        Some(btree_table) if may_replace => ForeignKeyChecks::compile(
+            schema,
+            database_id,
             connection,
-        )?;
+            &btree_table,
+            &TriggerEvent::Delete,
+            &[],
+        )?,
+        _ => ForeignKeyChecks::default(),
+    };
 
-        let table = Rc::new(BTreeTable {
-            root_page: 0, // Not relevant for ephemeral table definition
-            name: "ephemeral_scratch".to_string(),
-            has_rowid: true,
-            primary_key_columns: vec![],
-            columns: vec![Column {
-                name: Some("rowid".to_string()),
-                ty: Type::Integer,
-                ty_str: "INTEGER".to_string(),
-                primary_key: true,
-                is_rowid_alias: false,
-                notnull: true,
-                default: None,
-                unique: false,
-                collation: None,
-                hidden: false,
-            }],
-            is_strict: false,
-            unique_sets: None,
-        });
+    // Triggers and REPLACE may change the table while it is being updated, so the rowids to
+    // update are collected upfront as well. Tables without rowid collect primary keys instead.
+    let (ephemeral_plan, mut where_clause) =
+        if rowid_alias_used || primary_key_used || triggers.is_some() || may_replace {
+            let mut where_clause = vec![];
+            let internal_id = program.table_reference_counter.next();
 
-        let temp_cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(table.clone()));
+            let joined_tables = vec![JoinedTable {
+                table: match table.as_ref() {
+                    Table::Virtual(vtab) => Table::Virtual(vtab.clone()),
+                    Table::BTree(btree_table) => Table::BTree(btree_table.clone()),
+                    _ => unreachable!(),
+                },
+                identifier: table_name.as_str().to_string(),
+                internal_id,
+                op: Operation::Scan {
+                    iter_dir,
+                    index: None,
+                },
+                join_info: None,
+                col_used_mask: ColumnUsedMask::default(),
+                database_id,
+                index_hint,
+            }];
+            let mut table_references = TableReferences::new(joined_tables, vec![]);
+
+            // Parse the WHERE clause
+            parse_where(
+                body.where_clause.as_ref().map(|w| *w.clone()),
+                &mut table_references,
+                Some(&result_columns),
+                &mut where_clause,
+                connection,
+            )?;
+            let mut where_subqueries = vec![];
+            for term in where_clause.iter_mut() {
+                plan_subqueries_from_expr(
+                    schema,
+                    syms,
+                    &mut term.expr,
+                    &mut table_references,
+                    &mut where_subqueries,
+                    &ctes,
+                    &mut program.table_reference_counter,
+                    connection,
+                )?;
+            }
 
-        let mut ephemeral_plan = SelectPlan {
-            table_references,
-            result_columns: vec![ResultSetColumn {
-                expr: Expr::RowId {
-                    database: None,
-                    table: internal_id,
+            let table = Rc::new(BTreeTable {
+                root_page: 0, // Not relevant for ephemeral table definition
+                name: "ephemeral_scratch".to_string(),
+                has_rowid: !without_rowid,
+                primary_key_columns: vec![],
+                columns: vec![Column {
+                    name: Some("rowid".to_string()),
+                    ty: Type::Integer,
+                    ty_str: "INTEGER".to_string(),
+                    primary_key: true,
+                    is_rowid_alias: false,
+                    notnull: true,
+                    default: None,
+                    unique: false,
+                    collation: None,
+                    hidden: false,
+                    generated: None,
+                }],
+                is_strict: false,
+                unique_sets: None,
+                conflict_clauses: vec![],
+                foreign_keys: vec![],
+                checks: vec![],
+                notnull_conflict_clauses: vec![],
+            });
+
+            let (temp_cursor_id, result_columns) = match &clustered_index {
+                Some(clustered_index) => {
+                    let primary_key_columns = clustered_index.key_columns();
+                    let index = Arc::new(Index {
+                        name: "ephemeral_scratch".to_string(),
+                        table_name: String::new(),
+                        root_page: 0,
+                        columns: primary_key_columns
+                            .iter()
+                            .enumerate()
+                            .map(|(i, column)| IndexColumn {
+                                pos_in_table: i,
+                                ..column.clone()
+                            })
+                            .collect(),
+                        unique: false,
+                        ephemeral: true,
+                        has_rowid: false,
+                        num_extra_columns: 0,
+                        where_clause: None,
+                    });
+                    let result_columns = primary_key_columns
+                        .iter()
+                        .map(|column| ResultSetColumn {
+                            expr: Expr::Column {
+                                database: None,
+                                table: internal_id,
+                                column: column.pos_in_table,
+                                is_rowid_alias: false,
+                            },
+                            alias: None,
+                            contains_aggregates: false,
+                        })
+                        .collect();
+                    (
+                        program.alloc_cursor_id(CursorType::BTreeIndex(index)),
+                        result_columns,
+                    )
+                }
+                None => (
+                    program.alloc_cursor_id(CursorType::BTreeTable(table.clone())),
+                    vec![ResultSetColumn {
+                        expr: Expr::RowId {
+                            database: None,
+                            table: internal_id,
+                        },
+                        alias: None,
+                        contains_aggregates: false,
+                    }],
+                ),
+            };
+
+            let mut ephemeral_plan = SelectPlan {
+                table_references,
+                result_columns,
+                where_clause,       // original WHERE terms from the UPDATE clause
+                group_by: None,     // N/A
+                order_by: None,     // N/A
+                aggregates: vec![], // N/A
+                limit: None,        // N/A
+                query_destination: QueryDestination::EphemeralTable {
+                    cursor_id: temp_cursor_id,
+                    table,
                 },
-                alias: None,
-                contains_aggregates: false,
-            }],
-            where_clause,       // original WHERE terms from the UPDATE clause
-            group_by: None,     // N/A
-            order_by: None,     // N/A
-            aggregates: vec![], // N/A
-            limit: None,        // N/A
-            query_destination: QueryDestination::EphemeralTable {
-                cursor_id: temp_cursor_id,
-                table,
-            },
-            join_order: vec![],
-            offset: None,
-            contains_constant_false_condition: false,
-            distinctness: super::plan::Distinctness::NonDistinct,
-            values: vec![],
-        };
+                join_order: vec![],
+                offset: None,
+                contains_constant_false_condition: false,
+                distinctness: super::plan::Distinctness::NonDistinct,
+                values: vec![],
+                window: None,
+                materialized_ctes: vec![],
+                non_from_clause_subqueries: where_subqueries,
+            };
 
-        optimize_select_plan(&mut ephemeral_plan, schema)?;
-        let table = ephemeral_plan
-            .table_references
-            .joined_tables()
-            .first()
-            .unwrap();
-        // We do not need to emit an ephemeral plan if we are not going to loop over the table values
-        if matches!(table.op, Operation::Search(Search::RowidEq { .. })) {
-            (None, vec![])
+            optimize_select_plan(&mut ephemeral_plan, schema)?;
+            let table = ephemeral_plan
+                .table_references
+                .joined_tables()
+                .first()
+                .unwrap();
+            // We do not need to emit an ephemeral plan if we are not going to loop over the table values
+            if matches!(table.op, Operation::Search(Search::RowidEq { .. })) {
+                (None, vec![])
+            } else {
+                (Some(ephemeral_plan), vec![])
+            }
         } else {
-            (Some(ephemeral_plan), vec![])
-        }
-    } else {
-        (None, vec![])
-    };
+            (None, vec![])
+        };
 
     if ephemeral_plan.is_none() {
         // Parse the WHERE clause
@@ -309,6 +481,18 @@ pub fn prepare_update_plan(
             &mut where_clause,
             connection,
         )?;
+        for term in where_clause.iter_mut() {
+            plan_subqueries_from_expr(
+                schema,
+                syms,
+                &mut term.expr,
+                &mut table_references,
+                &mut non_from_clause_subqueries,
+                &ctes,
+                &mut program.table_reference_counter,
+                connection,
+            )?;
+        }
     };
 
     // Parse the LIMIT/OFFSET clause
@@ -319,21 +503,30 @@ pub fn prepare_update_plan(
         .unwrap_or(Ok((None, None)))?;
 
     // Check what indexes will need to be updated by checking set_clauses and see
-    // if a column is contained in an index.
+    // if a column is contained in an index, or referred to by its expressions or its WHERE
+    // clause. Deleting a replaced row needs all of them.
+    // The clustered index of a table without rowid is the table itself.
     let indexes = schema.get_indices(table_name.as_str());
     let indexes_to_update = indexes
         .iter()
         .filter(|index| {
-            index.columns.iter().any(|index_column| {
-                set_clauses
-                    .iter()
-                    .any(|(set_index_column, _)| index_column.pos_in_table == *set_index_column)
-            })
+            clustered_index
+                .as_ref()
+                .is_none_or(|clustered_index| index.root_page != clustered_index.root_page)
+        })
+        .filter(|index| {
+            may_replace
+                || table.btree().is_some_and(|btree_table| {
+                    index
+                        .referenced_columns(&btree_table)
+                        .iter()
+                        .any(|column| updated_columns.contains(column))
+                })
         })
         .cloned()
         .collect();
 
-    Ok(Plan::Update(UpdatePlan {
+    Ok(Plan::Update(Box::new(UpdatePlan {
         table_references,
         set_clauses,
         where_clause,
@@ -348,5 +541,10 @@ pub fn prepare_update_plan(
         contains_constant_false_condition: false,
         indexes_to_update,
         ephemeral_plan,
-    }))
+        triggers,
+        or_conflict: body.or_conflict,
+        replace_foreign_keys,
+        non_from_clause_subqueries,
+        checks,
+    })))
 }
@@ -0,0 +1,684 @@
+//! The ON CONFLICT clauses of INSERT (upserts).
+//!
+//! Each clause handles the violations of the PRIMARY KEY or UNIQUE constraint named by its
+//! conflict target or, for a last clause without a target, of every constraint not handled by
+//! a previous clause. DO NOTHING skips the row being inserted. DO UPDATE updates the row it
+//! conflicts with instead, and reads the row that was not inserted through the `excluded` table,
+//! an ephemeral table the row is written to.
+
+use std::rc::Rc;
+use std::sync::Arc;
+
+use turso_sqlite3_parser::ast::{self, Expr, ResolveType, Set, TriggerEvent, Upsert, UpsertDo};
+
+use crate::error::{SQLITE_CONSTRAINT_NOTNULL, SQLITE_CONSTRAINT_PRIMARYKEY};
+use crate::schema::{BTreeTable, Index, Table};
+use crate::translate::check::CheckConstraints;
+use crate::translate::emitter::{
+    emit_cdc_full_record, emit_cdc_insns, emit_cdc_patch_record, emit_index_entry, OperationMode,
+    Resolver,
+};
+use crate::translate::expr::{emit_returning_results, translate_expr, ReturningValueRegisters};
+use crate::translate::generated::{
+    dependent_generated_columns, emit_table_record, GeneratedColumns,
+};
+use crate::translate::index::emit_index_entry_from_row;
+use crate::translate::optimizer::constraints::terms_imply;
+use crate::translate::plan::{
+    ColumnUsedMask, JoinedTable, Operation, OuterQueryReference, ResultSetColumn, TableReferences,
+};
+use crate::translate::planner::{bind_column_references, break_predicate_at_and_boundaries, ROWID};
+use crate::translate::trigger::TableTriggers;
+use crate::util::{exprs_are_equivalent, normalize_ident};
+use crate::vdbe::builder::{CursorKey, CursorType, ProgramBuilder};
+use crate::vdbe::insn::{CmpInsFlags, IdxInsertFlags, InsertFlags, Insn};
+use crate::vdbe::{BranchOffset, CursorID};
+use crate::{bail_parse_error, Connection, Result};
+
+/// A PRIMARY KEY or UNIQUE constraint that the rows inserted into a table are checked against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UniqueConstraint {
+    /// The INTEGER PRIMARY KEY, whose values are the rowids.
+    Rowid,
+    /// A unique index, by position in the indexes of the table.
+    Index(usize),
+}
+
+/// Returns the DO clause of each ON CONFLICT clause of `upsert` with the constraints it
+/// handles, in clause order.
+pub fn resolve_upsert_targets<'a>(
+    upsert: &'a Upsert,
+    table: &BTreeTable,
+    indexes: &[Arc<Index>],
+) -> Result<Vec<(&'a UpsertDo, Vec<UniqueConstraint>)>> {
+    let constraints = std::iter::once(UniqueConstraint::Rowid)
+        .chain(
+            indexes
+                .iter()
+                .enumerate()
+                .filter(|(_, index)| index.unique)
+                .map(|(i, _)| UniqueConstraint::Index(i)),
+        )
+        .collect::<Vec<_>>();
+    let mut handled = vec![];
+    let mut clauses = vec![];
+    let mut upsert = Some(upsert);
+    while let Some(clause) = upsert {
+        let targets = match &clause.index {
+            Some(target) => vec![resolve_conflict_target(target, table, indexes)?],
+            None => constraints
+                .iter()
+                .filter(|constraint| !handled.contains(*constraint))
+                .copied()
+                .collect(),
+        };
+        handled.extend(targets.iter().copied());
+        clauses.push((clause.do_clause.as_ref(), targets));
+        upsert = clause.next.as_deref();
+    }
+    Ok(clauses)
+}
+
+/// Finds the constraint on exactly the columns or expressions of a conflict target, in any
+/// order. A partial index only matches if the WHERE clause of the target implies its own.
+fn resolve_conflict_target(
+    target: &ast::UpsertIndex,
+    table: &BTreeTable,
+    indexes: &[Arc<Index>],
+) -> Result<UniqueConstraint> {
+    let exprs = target
+        .targets
+        .iter()
+        .map(|target| match &target.expr {
+            Expr::Collate(expr, _) => expr.as_ref(),
+            expr => expr,
+        })
+        .collect::<Vec<_>>();
+    if let [Expr::Id(name) | Expr::Name(name)] = exprs.as_slice() {
+        let column = normalize_ident(name.as_str());
+        let is_rowid = match table.get_column(&column) {
+            Some((_, column)) => column.is_rowid_alias,
+            None => column == ROWID,
+        };
+        if is_rowid {
+            return Ok(UniqueConstraint::Rowid);
+        }
+    }
+    let mut terms = Vec::new();
+    if let Some(where_clause) = &target.where_clause {
+        break_predicate_at_and_boundaries(where_clause.clone(), &mut terms);
+    }
+    let terms = terms.iter().collect::<Vec<_>>();
+    let position = indexes.iter().position(|index| {
+        index.unique
+            && index.key_columns().len() == exprs.len()
+            && index.key_columns().iter().all(|column| {
+                exprs.iter().any(|expr| match (&column.expr, expr) {
+                    (Some(column_expr), expr) => exprs_are_equivalent(column_expr, expr),
+                    (None, Expr::Id(name) | Expr::Name(name)) => {
+                        normalize_ident(name.as_str()) == column.name
+                    }
+                    (None, _) => false,
+                })
+            })
+            && index
+                .where_clause
+                .as_ref()
+                .is_none_or(|where_clause| terms_imply(&terms, where_clause))
+    });
+    match position {
+        Some(position) => Ok(UniqueConstraint::Index(position)),
+        None => {
+            bail_parse_error!(
+                "ON CONFLICT clause does not match any PRIMARY KEY or UNIQUE constraint"
+            )
+        }
+    }
+}
+
+/// The row an INSERT could not insert because of a conflict handled by a DO UPDATE clause.
+pub struct ConflictingInsert<'a> {
+    pub table: &'a Rc<BTreeTable>,
+    /// The database the table is in.
+    pub database_id: usize,
+    /// The internal id the table is referenced by in the SET and WHERE expressions.
+    pub table_internal_id: ast::TableInternalId,
+    /// The table cursor, positioned on the row the inserted row conflicts with.
+    pub cursor_id: CursorID,
+    /// All the indexes of the table with their cursors.
+    pub index_cursors: &'a [(Arc<Index>, CursorID)],
+    /// The rowid of the row that was not inserted, followed by its columns.
+    pub rowid_reg: usize,
+    pub columns_start_reg: usize,
+    pub returning: &'a [ResultSetColumn],
+    pub cdc_cursor_id: Option<CursorID>,
+    /// Where the INSERT continues with its next row.
+    pub row_done_label: BranchOffset,
+}
+
+/// Emits the DO UPDATE of an ON CONFLICT clause, which updates the row the table cursor is
+/// positioned on like an UPDATE statement would. The inserted row is skipped afterwards.
+pub fn emit_upsert_update(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    connection: &Arc<Connection>,
+    insert: &ConflictingInsert,
+    sets: &[Set],
+    where_clause: Option<&Expr>,
+) -> Result<()> {
+    let table = insert.table;
+    let cursor_id = insert.cursor_id;
+    let excluded_internal_id = program.table_reference_counter.next();
+    let mut table_references = TableReferences::new(
+        vec![JoinedTable {
+            op: Operation::Scan {
+                iter_dir: crate::translate::plan::IterationDirection::Forwards,
+                index: None,
+            },
+            table: Table::BTree(table.clone()),
+            identifier: table.name.clone(),
+            internal_id: insert.table_internal_id,
+            join_info: None,
+            col_used_mask: ColumnUsedMask::default(),
+            database_id: insert.database_id,
+            index_hint: None,
+        }],
+        vec![OuterQueryReference {
+            identifier: "excluded".to_string(),
+            internal_id: excluded_internal_id,
+            table: Table::BTree(table.clone()),
+            col_used_mask: ColumnUsedMask::default(),
+        }],
+    );
+
+    let mut set_clauses: Vec<(usize, Expr)> = Vec::with_capacity(sets.len());
+    for set in sets {
+        let name = normalize_ident(set.col_names[0].as_str());
+        let Some((col_index, column)) = table.get_column(&name) else {
+            bail_parse_error!("no such column: {}", name);
+        };
+        if column.is_generated() {
+            bail_parse_error!("cannot UPDATE generated column \"{}\"", name);
+        }
+        let mut expr = set.expr.clone();
+        bind_column_references(&mut expr, &mut table_references, None, connection)?;
+        match set_clauses.iter_mut().find(|(idx, _)| *idx == col_index) {
+            Some(set_clause) => set_clause.1 = expr,
+            None => set_clauses.push((col_index, expr)),
+        }
+    }
+    let where_clause = where_clause
+        .map(|expr| {
+            let mut expr = expr.clone();
+            bind_column_references(&mut expr, &mut table_references, None, connection)?;
+            Ok::<_, crate::LimboError>(expr)
+        })
+        .transpose()?;
+
+    // Write the row that was not inserted to the `excluded` table.
+    let excluded_cursor_id = program.alloc_cursor_id_keyed(
+        CursorKey::table(excluded_internal_id),
+        CursorType::BTreeTable(table.clone()),
+    );
+    let excluded_opened_label = program.allocate_label();
+    program.emit_insn(Insn::Once {
+        target_pc_when_reentered: excluded_opened_label,
+    });
+    program.emit_insn(Insn::OpenEphemeral {
+        cursor_id: excluded_cursor_id,
+        is_table: true,
+    });
+    program.preassign_label_to_next_insn(excluded_opened_label);
+    let num_cols = table.columns.len();
+    let excluded_record_reg = program.alloc_register();
+    emit_table_record(
+        program,
+        table,
+        insert.columns_start_reg,
+        excluded_record_reg,
+    );
+    // A row without rowid is written to the `excluded` table under a new rowid.
+    let excluded_rowid_reg = if table.has_rowid {
+        insert.rowid_reg
+    } else {
+        let rowid_reg = program.alloc_register();
+        program.emit_insn(Insn::NewRowid {
+            cursor: excluded_cursor_id,
+            rowid_reg,
+            prev_largest_reg: 0,
+        });
+        rowid_reg
+    };
+    program.emit_insn(Insn::Insert {
+        cursor: excluded_cursor_id,
+        key_reg: excluded_rowid_reg,
+        record_reg: excluded_record_reg,
+        flag: InsertFlags::new().require_seek(),
+        table_name: "".to_string(),
+    });
+
+    // The row being updated is found by its rowid, or by its primary key if the table has no
+    // rowid, in which case its rowid is NULL.
+    let old_rowid_reg = program.alloc_register();
+    let primary_key_columns = if table.has_rowid {
+        vec![]
+    } else {
+        table
+            .primary_key_columns
+            .iter()
+            .map(|(name, _)| table.get_column(name).unwrap())
+            .collect::<Vec<_>>()
+    };
+    let old_primary_key_reg = program.alloc_registers(primary_key_columns.len());
+    if table.has_rowid {
+        program.emit_insn(Insn::RowId {
+            cursor_id,
+            dest: old_rowid_reg,
+        });
+    } else {
+        program.emit_null(old_rowid_reg, None);
+        for (i, (pos_in_table, _)) in primary_key_columns.iter().enumerate() {
+            program.emit_table_column(cursor_id, *pos_in_table, old_primary_key_reg + i);
+        }
+    }
+    let emit_seek_old_row = |program: &mut ProgramBuilder| {
+        if table.has_rowid {
+            program.emit_insn(Insn::NotExists {
+                cursor: cursor_id,
+                rowid_reg: old_rowid_reg,
+                target_pc: insert.row_done_label,
+            });
+        } else {
+            program.emit_insn(Insn::NotFound {
+                cursor_id,
+                target_pc: insert.row_done_label,
+                record_reg: old_primary_key_reg,
+                num_regs: primary_key_columns.len(),
+            });
+        }
+    };
+    if let Some(where_clause) = &where_clause {
+        let reg = program.alloc_register();
+        translate_expr(
+            program,
+            Some(&table_references),
+            where_clause,
+            reg,
+            resolver,
+        )?;
+        program.emit_insn(Insn::IfNot {
+            reg,
+            target_pc: insert.row_done_label,
+            jump_if_null: true,
+        });
+    }
+
+    // The updated row, laid out like the row of the INSERT.
+    let new_rowid_reg = program.alloc_registers(num_cols + 1);
+    let new_columns_start_reg = new_rowid_reg + 1;
+    let rowid_alias = table.get_rowid_alias_column().map(|(idx, _)| idx);
+    let rowid_changes = rowid_alias
+        .is_some_and(|alias| set_clauses.iter().any(|(col_index, _)| *col_index == alias));
+    if !rowid_changes {
+        program.emit_insn(Insn::Copy {
+            src_reg: old_rowid_reg,
+            dst_reg: new_rowid_reg,
+            extra_amount: 0,
+        });
+    }
+    for (idx, column) in table.columns.iter().enumerate() {
+        let target_reg = new_columns_start_reg + idx;
+        match set_clauses.iter().find(|(col_index, _)| *col_index == idx) {
+            Some((_, expr)) if column.is_rowid_alias => {
+                translate_expr(
+                    program,
+                    Some(&table_references),
+                    expr,
+                    new_rowid_reg,
+                    resolver,
+                )?;
+                program.emit_insn(Insn::MustBeInt { reg: new_rowid_reg });
+                program.emit_null(target_reg, None);
+            }
+            Some((_, expr)) => {
+                translate_expr(program, Some(&table_references), expr, target_reg, resolver)?;
+            }
+            None if column.is_rowid_alias => program.emit_null(target_reg, None),
+            None => program.emit_table_column(cursor_id, idx, target_reg),
+        }
+    }
+    GeneratedColumns::compile(program, table)?.emit(
+        program,
+        resolver,
+        new_rowid_reg,
+        new_columns_start_reg,
+    )?;
+
+    let updated_columns = set_clauses
+        .iter()
+        .filter_map(|(idx, _)| table.columns[*idx].name.as_deref())
+        .collect::<Vec<_>>();
+    let triggers = TableTriggers::compile(
+        program,
+        resolver.schema,
+        insert.database_id,
+        resolver.symbol_table,
+        connection,
+        table,
+        TriggerEvent::Update,
+        &updated_columns,
+    )?;
+    let trigger_params = if triggers.is_empty() {
+        None
+    } else {
+        let params = triggers.alloc_params(program);
+        params.emit_old_from_cursor(program, resolver, table, cursor_id)?;
+        params.emit_copy_row(program, table, new_rowid_reg, new_columns_start_reg, false);
+        if !triggers.before.is_empty() {
+            triggers.emit_before(program, &params, insert.row_done_label);
+            // The BEFORE triggers may have moved the cursor or deleted the row
+            emit_seek_old_row(program);
+        }
+        triggers.emit_old_foreign_key_checks(program, &params);
+        Some(params)
+    };
+
+    for (idx, column) in table.columns.iter().enumerate() {
+        if !column.notnull || column.is_rowid_alias {
+            continue;
+        }
+        program.emit_insn(Insn::HaltIfNull {
+            target_reg: new_columns_start_reg + idx,
+            err_code: SQLITE_CONSTRAINT_NOTNULL,
+            on_error: ResolveType::Abort,
+            description: format!(
+                "{}.{}",
+                table.name,
+                column.name.as_ref().expect("Column name must be present")
+            ),
+        });
+    }
+    let mut set_columns = set_clauses.iter().map(|(idx, _)| *idx).collect::<Vec<_>>();
+    set_columns.extend(dependent_generated_columns(table, &set_columns));
+    CheckConstraints::compile(program, connection, table, Some(&set_columns))?.emit(
+        program,
+        resolver,
+        new_rowid_reg,
+        new_columns_start_reg,
+        ResolveType::Abort,
+        insert.row_done_label,
+    )?;
+    if table.is_strict {
+        program.emit_insn(Insn::TypeCheck {
+            start_reg: new_columns_start_reg,
+            count: num_cols,
+            check_generated: true,
+            table_reference: Rc::clone(table),
+        });
+    }
+
+    if rowid_changes {
+        let rowid_checked_label = program.allocate_label();
+        program.emit_insn(Insn::Eq {
+            lhs: new_rowid_reg,
+            rhs: old_rowid_reg,
+            target_pc: rowid_checked_label,
+            flags: CmpInsFlags::default(),
+            collation: program.curr_collation(),
+        });
+        let rowid_free_label = program.allocate_label();
+        program.emit_insn(Insn::NotExists {
+            cursor: cursor_id,
+            rowid_reg: new_rowid_reg,
+            target_pc: rowid_free_label,
+        });
+        let (_, alias) = table.get_rowid_alias_column().unwrap();
+        program.emit_insn(Insn::Halt {
+            err_code: SQLITE_CONSTRAINT_PRIMARYKEY,
+            on_error: ResolveType::Abort,
+            description: format!("{}.{}", table.name, alias.name.as_deref().unwrap_or("")),
+        });
+        program.preassign_label_to_next_insn(rowid_free_label);
+        // NotExists moved the cursor away from the row being updated.
+        emit_seek_old_row(program);
+        program.preassign_label_to_next_insn(rowid_checked_label);
+    }
+
+    // The new index entries: the indexed columns of the updated row followed by its rowid, if
+    // the table has one. The record of the entry stays NULL if the index is partial and the row
+    // has no entry in it. The entry of the clustered index of a table without rowid is the row.
+    let mut new_keys = Vec::with_capacity(insert.index_cursors.len());
+    for (index, idx_cursor_id) in insert.index_cursors {
+        let num_regs = index.columns.len() + usize::from(index.has_rowid);
+        let key_start_reg = program.alloc_registers(num_regs);
+        let record_reg = program.alloc_register();
+        let not_indexed_label = program.allocate_label();
+        if index.where_clause.is_some() {
+            program.emit_insn(Insn::Null {
+                dest: record_reg,
+                dest_end: None,
+            });
+        }
+        emit_index_entry_from_row(
+            program,
+            resolver,
+            table,
+            index,
+            new_rowid_reg,
+            new_columns_start_reg,
+            key_start_reg,
+            not_indexed_label,
+        )?;
+        if index.unique {
+            let unique_label = program.allocate_label();
+            program.emit_insn(Insn::NoConflict {
+                cursor_id: *idx_cursor_id,
+                target_pc: unique_label,
+                record_reg: key_start_reg,
+                num_regs: index.key_columns().len(),
+            });
+            // The row may keep its own key.
+            if table.has_rowid {
+                let conflict_rowid_reg = program.alloc_register();
+                program.emit_insn(Insn::IdxRowId {
+                    cursor_id: *idx_cursor_id,
+                    dest: conflict_rowid_reg,
+                });
+                program.emit_insn(Insn::Eq {
+                    lhs: conflict_rowid_reg,
+                    rhs: old_rowid_reg,
+                    target_pc: unique_label,
+                    flags: CmpInsFlags::default(),
+                    collation: program.curr_collation(),
+                });
+            } else {
+                let conflict_label = program.allocate_label();
+                let conflict_key_reg = program.alloc_register();
+                for (i, (pos_in_table, column)) in primary_key_columns.iter().enumerate() {
+                    let pos_in_index = index
+                        .column_table_pos_to_index_pos(*pos_in_table)
+                        .expect("indexes of tables without rowid store the primary key");
+                    program.emit_column(*idx_cursor_id, pos_in_index, conflict_key_reg);
+                    program.emit_insn(Insn::Ne {
+                        lhs: conflict_key_reg,
+                        rhs: old_primary_key_reg + i,
+                        target_pc: conflict_label,
+                        flags: CmpInsFlags::default(),
+                        collation: column.collation,
+                    });
+                }
+                program.emit_insn(Insn::Goto {
+                    target_pc: unique_label,
+                });
+                program.preassign_label_to_next_insn(conflict_label);
+            }
+            program.emit_insn(Insn::Halt {
+                err_code: SQLITE_CONSTRAINT_PRIMARYKEY,
+                on_error: ResolveType::Abort,
+                description: index.unique_constraint_description(&table.name),
+            });
+            program.preassign_label_to_next_insn(unique_label);
+        }
+        program.emit_insn(Insn::MakeRecord {
+            start_reg: key_start_reg,
+            count: num_regs,
+            dest_reg: record_reg,
+            index_name: Some(index.name.clone()),
+        });
+        program.preassign_label_to_next_insn(not_indexed_label);
+        new_keys.push((key_start_reg, num_regs, record_reg));
+    }
+    if !table.has_rowid {
+        // Looking up the clustered index moved the cursor away from the row being updated.
+        emit_seek_old_row(program);
+    }
+
+    for ((index, idx_cursor_id), (key_start_reg, num_regs, record_reg)) in insert
+        .index_cursors
+        .iter()
+        .zip(new_keys.iter().copied())
+        .filter(|((_, idx_cursor_id), _)| *idx_cursor_id != cursor_id)
+    {
+        let old_not_indexed_label = program.allocate_label();
+        let (old_key_start_reg, old_key_num_regs) = emit_index_entry(
+            program,
+            resolver,
+            table,
+            cursor_id,
+            index,
+            old_not_indexed_label,
+        )?;
+        program.emit_insn(Insn::IdxDelete {
+            start_reg: old_key_start_reg,
+            num_regs: old_key_num_regs,
+            cursor_id: *idx_cursor_id,
+            raise_error_if_no_matching_entry: true,
+        });
+        program.preassign_label_to_next_insn(old_not_indexed_label);
+        let new_not_indexed_label = program.allocate_label();
+        if index.where_clause.is_some() {
+            program.emit_insn(Insn::IsNull {
+                reg: record_reg,
+                target_pc: new_not_indexed_label,
+            });
+        }
+        program.emit_insn(Insn::IdxInsert {
+            cursor_id: *idx_cursor_id,
+            record_reg,
+            unpacked_start: Some(key_start_reg),
+            unpacked_count: Some(num_regs as u16),
+            flags: IdxInsertFlags::new(),
+        });
+        program.preassign_label_to_next_insn(new_not_indexed_label);
+    }
+
+    let cdc_before_reg = match insert.cdc_cursor_id {
+        Some(_) if program.capture_data_changes_mode().has_before() => Some(emit_cdc_full_record(
+            program,
+            &Table::BTree(table.clone()),
+            cursor_id,
+            old_rowid_reg,
+        )),
+        _ => None,
+    };
+
+    let record_reg = program.alloc_register();
+    if table.has_rowid {
+        emit_table_record(program, table, new_columns_start_reg, record_reg);
+        if rowid_changes {
+            program.emit_insn(Insn::Delete { cursor_id });
+        }
+        program.emit_insn(Insn::Insert {
+            cursor: cursor_id,
+            key_reg: new_rowid_reg,
+            record_reg,
+            flag: if rowid_changes {
+                InsertFlags::new().require_seek()
+            } else {
+                InsertFlags::new()
+            },
+            table_name: table.name.clone(),
+        });
+    } else {
+        // The row of a table without rowid is replaced by the new entry of its clustered index
+        let clustered = insert
+            .index_cursors
+            .iter()
+            .position(|(_, idx_cursor_id)| *idx_cursor_id == cursor_id)
+            .expect("table without rowid is written through its clustered index");
+        let (key_start_reg, num_regs, clustered_record_reg) = new_keys[clustered];
+        program.emit_insn(Insn::Delete { cursor_id });
+        program.emit_insn(Insn::IdxInsert {
+            cursor_id,
+            record_reg: clustered_record_reg,
+            unpacked_start: Some(key_start_reg),
+            unpacked_count: Some(num_regs as u16),
+            flags: IdxInsertFlags::new(),
+        });
+    }
+
+    if !insert.returning.is_empty() {
+        let value_registers = ReturningValueRegisters {
+            rowid_register: new_rowid_reg,
+            columns_start_register: new_columns_start_reg,
+            num_columns: num_cols,
+        };
+        emit_returning_results(program, insert.returning, &value_registers)?;
+    }
+
+    if let Some(cdc_cursor_id) = insert.cdc_cursor_id {
+        let cdc_after_reg = if program.capture_data_changes_mode().has_after() {
+            Some(emit_cdc_patch_record(
+                program,
+                &Table::BTree(table.clone()),
+                new_columns_start_reg,
+                record_reg,
+                new_rowid_reg,
+            ))
+        } else {
+            None
+        };
+        if rowid_changes {
+            emit_cdc_insns(
+                program,
+                resolver,
+                OperationMode::DELETE,
+                cdc_cursor_id,
+                old_rowid_reg,
+                cdc_before_reg,
+                None,
+                &table.name,
+            )?;
+            emit_cdc_insns(
+                program,
+                resolver,
+                OperationMode::INSERT,
+                cdc_cursor_id,
+                new_rowid_reg,
+                cdc_after_reg,
+                None,
+                &table.name,
+            )?;
+        } else {
+            emit_cdc_insns(
+                program,
+                resolver,
+                OperationMode::UPDATE,
+                cdc_cursor_id,
+                old_rowid_reg,
+                cdc_before_reg,
+                cdc_after_reg,
+                &table.name,
+            )?;
+        }
+    }
+
+    if let Some(params) = &trigger_params {
+        triggers.emit_new_foreign_key_checks(program, params);
+        triggers.emit_after(program, params, insert.row_done_label);
+    }
+    program.emit_insn(Insn::Goto {
+        target_pc: insert.row_done_label,
+    });
+    Ok(())
+}
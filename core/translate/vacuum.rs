@@ -0,0 +1,51 @@
+use crate::schema::Schema;
+use crate::translate::emitter::{Resolver, TransactionMode};
+use crate::translate::expr::translate_expr;
+use crate::translate::{ProgramBuilder, ProgramBuilderOpts};
+use crate::util::normalize_ident;
+use crate::vdbe::insn::Insn;
+use crate::{bail_parse_error, Result, SymbolTable};
+use turso_sqlite3_parser::ast::{Expr, Name};
+
+/// Translate VACUUM [schema-name] [INTO filename]
+///
+/// A plain VACUUM rebuilds the database within a write transaction, while VACUUM INTO only
+/// needs a read transaction to write a consistent copy of the database to another file.
+pub fn translate_vacuum(
+    schema_name: Option<Name>,
+    into: Option<Box<Expr>>,
+    schema: &Schema,
+    syms: &SymbolTable,
+    mut program: ProgramBuilder,
+) -> Result<ProgramBuilder> {
+    if let Some(schema_name) = schema_name {
+        let schema_name = normalize_ident(schema_name.as_str());
+        if schema_name != "main" {
+            bail_parse_error!("VACUUM of database {} is not supported", schema_name);
+        }
+    }
+
+    program.extend(&ProgramBuilderOpts {
+        num_cursors: 0,
+        approx_num_insns: 5,
+        approx_num_labels: 0,
+    });
+
+    let into = match into {
+        Some(expr) => {
+            let reg = program.alloc_register();
+            let resolver = Resolver::new(schema, syms);
+            translate_expr(&mut program, None, &expr, reg, &resolver)?;
+            Some(reg)
+        }
+        None => None,
+    };
+    program.emit_insn(Insn::Vacuum { db: 0, into });
+
+    program.epilogue(if into.is_some() {
+        TransactionMode::Read
+    } else {
+        TransactionMode::Write
+    });
+    Ok(program)
+}
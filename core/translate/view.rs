@@ -0,0 +1,218 @@
+//! CREATE VIEW / DROP VIEW.
+//!
+//! A view only exists as its `CREATE VIEW` statement in `sqlite_schema`. Queries that
+//! reference a view have the view's SELECT planned as a FROM clause subquery, see
+//! [crate::translate::planner].
+
+use std::sync::Arc;
+
+use turso_sqlite3_parser::ast::{
+    self,
+    fmt::{ToTokens, TokenStream},
+    IndexedColumn, QualifiedName, Select,
+};
+use turso_sqlite3_parser::dialect::TokenType;
+
+use crate::translate::emitter::TransactionMode;
+use crate::translate::schema::{emit_schema_entry, SchemaEntryType, SQLITE_TABLEID};
+use crate::util::normalize_ident;
+use crate::vdbe::builder::{CursorType, ProgramBuilder, ProgramBuilderOpts};
+use crate::vdbe::insn::{CmpInsFlags, Cookie, Insn, RegisterOrLiteral};
+use crate::{bail_parse_error, Connection, Result};
+
+pub fn translate_create_view(
+    temporary: bool,
+    if_not_exists: bool,
+    view_name: QualifiedName,
+    columns: Option<Vec<IndexedColumn>>,
+    select: Box<Select>,
+    mut program: ProgramBuilder,
+    connection: &Arc<Connection>,
+) -> Result<ProgramBuilder> {
+    let opts = ProgramBuilderOpts {
+        num_cursors: 1,
+        approx_num_insns: 20,
+        approx_num_labels: 1,
+    };
+    program.extend(&opts);
+
+    let database_id = connection.resolve_create_database_id(&view_name, temporary)?;
+    let schema = &connection.database_schema(database_id);
+    let name = normalize_ident(view_name.name.as_str());
+    if schema.get_view(&name).is_some() {
+        if if_not_exists {
+            program.epilogue(TransactionMode::Write);
+            return Ok(program);
+        }
+        bail_parse_error!("view {} already exists", name);
+    }
+    if schema.get_table(&name).is_some() {
+        bail_parse_error!("table {} already exists", name);
+    }
+    if !schema.is_unique_idx_name(&name) {
+        bail_parse_error!("there is already an index named {}", name);
+    }
+    if name.starts_with("sqlite_") {
+        bail_parse_error!("object name reserved for internal use: {}", name);
+    }
+    if has_variables(&select) {
+        bail_parse_error!("parameters are not allowed in views");
+    }
+
+    // Like in SQLite, the schema table of the temp database already says the view is temporary.
+    let sql = ast::Stmt::CreateView {
+        temporary: false,
+        if_not_exists,
+        view_name,
+        columns,
+        select,
+    }
+    .format()
+    .map_err(|e| crate::LimboError::ParseError(e.to_string()))?;
+
+    let sqlite_table = schema.get_btree_table(SQLITE_TABLEID).unwrap();
+    let sqlite_schema_cursor_id =
+        program.alloc_cursor_id(CursorType::BTreeTable(sqlite_table.clone()));
+    program.emit_insn(Insn::OpenWrite {
+        cursor_id: sqlite_schema_cursor_id,
+        root_page: RegisterOrLiteral::Literal(sqlite_table.root_page),
+        db: database_id,
+    });
+    emit_schema_entry(
+        &mut program,
+        sqlite_schema_cursor_id,
+        SchemaEntryType::View,
+        &name,
+        &name,
+        0,
+        Some(sql),
+    );
+    program.emit_insn(Insn::SetCookie {
+        db: database_id,
+        cookie: Cookie::SchemaVersion,
+        value: schema.schema_version as i32 + 1,
+        p5: 0,
+    });
+    program.emit_insn(Insn::ParseSchema {
+        db: database_id,
+        where_clause: Some(format!("name = '{name}' AND type = 'view'")),
+    });
+    program.emit_insn(Insn::Close {
+        cursor_id: sqlite_schema_cursor_id,
+    });
+
+    program.epilogue(TransactionMode::Write);
+
+    Ok(program)
+}
+
+pub fn translate_drop_view(
+    view_name: &QualifiedName,
+    if_exists: bool,
+    mut program: ProgramBuilder,
+    connection: &Arc<Connection>,
+) -> Result<ProgramBuilder> {
+    let opts = ProgramBuilderOpts {
+        num_cursors: 1,
+        approx_num_insns: 20,
+        approx_num_labels: 3,
+    };
+    program.extend(&opts);
+
+    let database_id = connection.resolve_database_id(view_name)?;
+    let schema = &connection.database_schema(database_id);
+
+    let name = normalize_ident(view_name.name.as_str());
+    if schema.get_view(&name).is_none() {
+        if schema.get_table(&name).is_some() {
+            bail_parse_error!("use DROP TABLE to delete table {}", name);
+        }
+        if if_exists {
+            program.epilogue(TransactionMode::Write);
+            return Ok(program);
+        }
+        bail_parse_error!("no such view: {}", name);
+    }
+
+    let view_name_reg = program.emit_string8_new_reg(name.clone());
+    let row_id_reg = program.alloc_register();
+
+    let sqlite_table = schema.get_btree_table(SQLITE_TABLEID).unwrap();
+    let sqlite_schema_cursor_id =
+        program.alloc_cursor_id(CursorType::BTreeTable(sqlite_table.clone()));
+    program.emit_insn(Insn::OpenWrite {
+        cursor_id: sqlite_schema_cursor_id,
+        root_page: RegisterOrLiteral::Literal(sqlite_table.root_page),
+        db: database_id,
+    });
+
+    let loop_start_label = program.allocate_label();
+    let loop_end_label = program.allocate_label();
+    program.emit_insn(Insn::Rewind {
+        cursor_id: sqlite_schema_cursor_id,
+        pc_if_empty: loop_end_label,
+    });
+    program.preassign_label_to_next_insn(loop_start_label);
+
+    // skip if sqlite_schema.tbl_name != view_name, which deletes the view and its triggers
+    let dest_reg = program.alloc_register();
+    let next_label = program.allocate_label();
+    program.emit_column(sqlite_schema_cursor_id, 2, dest_reg);
+    program.emit_insn(Insn::Ne {
+        lhs: view_name_reg,
+        rhs: dest_reg,
+        target_pc: next_label,
+        flags: CmpInsFlags::default(),
+        collation: program.curr_collation(),
+    });
+    program.emit_insn(Insn::RowId {
+        cursor_id: sqlite_schema_cursor_id,
+        dest: row_id_reg,
+    });
+    program.emit_insn(Insn::Delete {
+        cursor_id: sqlite_schema_cursor_id,
+    });
+
+    program.preassign_label_to_next_insn(next_label);
+    program.emit_insn(Insn::Next {
+        cursor_id: sqlite_schema_cursor_id,
+        pc_if_next: loop_start_label,
+    });
+    program.preassign_label_to_next_insn(loop_end_label);
+
+    program.emit_insn(Insn::SetCookie {
+        db: database_id,
+        cookie: Cookie::SchemaVersion,
+        value: schema.schema_version as i32 + 1,
+        p5: 0,
+    });
+    // Like SQLite, DropTable removes the view and its triggers from the in-memory schema.
+    program.emit_insn(Insn::DropTable {
+        db: database_id,
+        _p2: 0,
+        _p3: 0,
+        table_name: name,
+    });
+
+    program.epilogue(TransactionMode::Write);
+
+    Ok(program)
+}
+
+/// Returns true if the SELECT contains a bound parameter anywhere, including subqueries.
+fn has_variables(select: &Select) -> bool {
+    struct VariableFinder(bool);
+    impl TokenStream for VariableFinder {
+        type Error = std::convert::Infallible;
+
+        fn append(&mut self, ty: TokenType, _value: Option<&str>) -> Result<(), Self::Error> {
+            if ty == TokenType::TK_VARIABLE {
+                self.0 = true;
+            }
+            Ok(())
+        }
+    }
+    let mut finder = VariableFinder(false);
+    let Ok(()) = select.to_tokens(&mut finder);
+    finder.0
+}
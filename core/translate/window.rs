@@ -0,0 +1,2577 @@
+//! Window functions.
+//!
+//! A SELECT with window functions is planned in two parts. Everything except the window
+//! functions (FROM, WHERE, GROUP BY, HAVING and aggregates) is planned as a FROM clause subquery
+//! that returns the arguments, PARTITION BY and ORDER BY terms of the window functions, plus
+//! whatever else the result columns need. The outer plan reads the rows of that subquery and
+//! computes the window functions, see [Window].
+//!
+//! Each distinct window is computed by a stage. The rows are sorted by the PARTITION BY and ORDER
+//! BY terms of the window and copied into an ephemeral table, which is then walked one partition
+//! at a time. The rows are passed on to the next stage together with the results of the window
+//! functions of the stage, and the last stage emits the result rows of the query.
+
+use std::num::NonZeroUsize;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use turso_sqlite3_parser::ast::{
+    self, Expr, FrameBound, FrameExclude, FrameMode, ResultColumn, SelectInner, SortOrder,
+    SortedColumn,
+};
+
+use crate::error::SQLITE_ERROR;
+use crate::function::{AggFunc, ExtFunc, Func, FuncCtx, ScalarFunc, WindowFunc};
+use crate::schema::{Affinity, BTreeTable, Column, PseudoCursorType, Type};
+use crate::translate::aggregation::translate_aggregation_step;
+use crate::translate::collate::CollationSeq;
+use crate::translate::emitter::{Resolver, TranslateCtx};
+use crate::translate::expr::{translate_expr, walk_expr, walk_expr_mut, WalkControl};
+use crate::translate::order_by::{order_by_sorter_insert, sorter_insert};
+use crate::translate::plan::{
+    Aggregate, Cte, Distinctness, JoinOrderMember, JoinedTable, OuterQueryReference, Plan,
+    QueryDestination, ResultSetColumn, SelectPlan, TableReferences, Window, WindowDefinition,
+    WindowFrame, WindowFunction,
+};
+use crate::translate::planner::parse_limit;
+use crate::translate::result_row::emit_select_result;
+use crate::translate::select::prepare_select_plan;
+use crate::util::normalize_ident;
+use crate::vdbe::builder::{CursorType, ProgramBuilder, TableRefIdCounter};
+use crate::vdbe::insn::{CmpInsFlags, InsertFlags, Insn};
+use crate::vdbe::{BranchOffset, CursorID};
+use crate::{bail_parse_error, Result, SymbolTable};
+
+/// Returns true if the result columns or the ORDER BY clause of a SELECT call window functions.
+pub fn select_has_window_functions(
+    columns: &[ResultColumn],
+    order_by: Option<&[SortedColumn]>,
+) -> Result<bool> {
+    for column in columns {
+        if let ResultColumn::Expr(expr, _) = column {
+            if find_window_function(expr)?.is_some() {
+                return Ok(true);
+            }
+        }
+    }
+    for sorted_column in order_by.unwrap_or_default() {
+        if find_window_function(&sorted_column.expr)?.is_some() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Returns the name of the function if `expr` is a window function call.
+fn window_function_name(expr: &Expr) -> Option<&str> {
+    match expr {
+        Expr::FunctionCall {
+            name,
+            filter_over:
+                Some(ast::FunctionTail {
+                    over_clause: Some(_),
+                    ..
+                }),
+            ..
+        }
+        | Expr::FunctionCallStar {
+            name,
+            filter_over:
+                Some(ast::FunctionTail {
+                    over_clause: Some(_),
+                    ..
+                }),
+        } => Some(name.as_str()),
+        _ => None,
+    }
+}
+
+/// Returns the name of the first window function called in `expr`, not counting subqueries.
+fn find_window_function(expr: &Expr) -> Result<Option<&str>> {
+    let mut found = None;
+    walk_expr(expr, &mut |e: &Expr| -> Result<WalkControl> {
+        if found.is_some() {
+            return Ok(WalkControl::SkipChildren);
+        }
+        if let Some(name) = window_function_name(e) {
+            found = Some(name);
+            return Ok(WalkControl::SkipChildren);
+        }
+        Ok(WalkControl::Continue)
+    })?;
+    Ok(found)
+}
+
+fn bail_if_window_function(expr: &Expr) -> Result<()> {
+    if let Some(name) = find_window_function(expr)? {
+        bail_parse_error!("misuse of window function {}()", name);
+    }
+    Ok(())
+}
+
+enum ResolvedFunction {
+    Builtin(WindowFunc),
+    Aggregate(AggFunc),
+}
+
+/// Rewrites the expressions of a SELECT with window functions so that they only reference the
+/// result columns of the subquery that computes everything else, see [prepare_window_select_plan].
+struct WindowRewriter<'a> {
+    subquery_id: ast::TableInternalId,
+    named_windows: &'a [ast::WindowDef],
+    syms: &'a SymbolTable,
+    /// Expressions computed by the subquery, one per result column of the subquery.
+    leaves: Vec<Expr>,
+    definitions: Vec<WindowDefinition>,
+    function_count: usize,
+}
+
+impl WindowRewriter<'_> {
+    fn rewrite(&mut self, expr: &mut Expr) -> Result<()> {
+        walk_expr_mut(expr, &mut |e: &mut Expr| -> Result<()> {
+            if matches!(e, Expr::Column { .. }) {
+                // Already rewritten.
+                return Ok(());
+            }
+            if window_function_name(e).is_some() {
+                return self.rewrite_window_function(e);
+            }
+            if find_window_function(e)?.is_none() {
+                let leaf = std::mem::replace(e, Expr::Literal(ast::Literal::Null));
+                *e = self.leaf(leaf);
+            }
+            Ok(())
+        })
+    }
+
+    /// Returns a reference to the subquery result column that computes `expr`.
+    fn leaf(&mut self, expr: Expr) -> Expr {
+        let column = match self.leaves.iter().position(|leaf| *leaf == expr) {
+            Some(column) => column,
+            None => {
+                self.leaves.push(expr);
+                self.leaves.len() - 1
+            }
+        };
+        Expr::Column {
+            database: None,
+            table: self.subquery_id,
+            column,
+            is_rowid_alias: false,
+        }
+    }
+
+    /// Like [Self::leaf], but keeps a COLLATE clause around the reference so that the
+    /// collation is used when sorting and comparing.
+    fn sort_key_leaf(&mut self, expr: Expr) -> Result<Expr> {
+        bail_if_window_function(&expr)?;
+        Ok(match expr {
+            Expr::Collate(expr, collation) => Expr::Collate(Box::new(self.leaf(*expr)), collation),
+            expr => self.leaf(expr),
+        })
+    }
+
+    fn rewrite_window_function(&mut self, expr: &mut Expr) -> Result<()> {
+        let (name, distinctness, args, filter_over) = match expr {
+            Expr::FunctionCall {
+                name,
+                distinctness,
+                args,
+                filter_over,
+                ..
+            } => (
+                name.clone(),
+                distinctness.take(),
+                args.take().unwrap_or_default(),
+                filter_over.take(),
+            ),
+            Expr::FunctionCallStar { name, filter_over } => {
+                (name.clone(), None, vec![], filter_over.take())
+            }
+            _ => unreachable!(),
+        };
+        let is_star = matches!(expr, Expr::FunctionCallStar { .. });
+        let ast::FunctionTail {
+            filter_clause,
+            over_clause,
+        } = filter_over.expect("window function without OVER clause");
+        let over = over_clause.expect("window function without OVER clause");
+
+        if distinctness.is_some() {
+            bail_parse_error!("DISTINCT is not supported for window functions");
+        }
+        let func = match Func::resolve_function(name.as_str(), args.len()) {
+            Ok(Func::Window(func)) => ResolvedFunction::Builtin(func),
+            Ok(Func::Agg(func)) => ResolvedFunction::Aggregate(func),
+            Ok(_) => bail_parse_error!("{}() may not be used as a window function", name.as_str()),
+            Err(e) => match self.syms.resolve_function(name.as_str(), args.len()) {
+                Some(f) if matches!(f.func, ExtFunc::Aggregate { .. }) => {
+                    ResolvedFunction::Aggregate(AggFunc::External(f.func.clone().into()))
+                }
+                Some(_) => {
+                    bail_parse_error!("{}() may not be used as a window function", name.as_str())
+                }
+                None => return Err(e),
+            },
+        };
+        if filter_clause.is_some() && matches!(func, ResolvedFunction::Builtin(_)) {
+            bail_parse_error!("FILTER clause may only be used with aggregate window functions");
+        }
+
+        let mut rewritten_args = Vec::with_capacity(args.len());
+        for arg in args {
+            bail_if_window_function(&arg)?;
+            rewritten_args.push(self.leaf(arg));
+        }
+        let filter = match filter_clause {
+            Some(filter) => {
+                bail_if_window_function(&filter)?;
+                Some(self.leaf(*filter))
+            }
+            None => None,
+        };
+
+        let window = self.resolve_window(*over)?;
+        let (partition_by, order_by, frame) = self.resolve_definition(window)?;
+
+        // The OVER clause has been resolved, replace it with a name that is unique to this call so
+        // that the call can be matched to its result register.
+        let over_clause = Some(Box::new(ast::Over::Name(ast::Name::from_str(&format!(
+            "#{}",
+            self.function_count
+        )))));
+        self.function_count += 1;
+        let filter_over = Some(ast::FunctionTail {
+            filter_clause: filter.clone().map(Box::new),
+            over_clause,
+        });
+        *expr = if is_star {
+            Expr::FunctionCallStar { name, filter_over }
+        } else {
+            Expr::FunctionCall {
+                name,
+                distinctness: None,
+                args: Some(rewritten_args.clone()),
+                order_by: None,
+                filter_over,
+            }
+        };
+
+        let function = match func {
+            ResolvedFunction::Builtin(func) => WindowFunction::Builtin {
+                func,
+                args: rewritten_args,
+                original_expr: expr.clone(),
+            },
+            ResolvedFunction::Aggregate(func) => WindowFunction::Aggregate(Aggregate {
+                args: if rewritten_args.is_empty() && matches!(func, AggFunc::Count0) {
+                    vec![Expr::Literal(ast::Literal::Numeric("1".to_string()))]
+                } else {
+                    rewritten_args
+                },
+                func,
+                original_expr: expr.clone(),
+                distinctness: Distinctness::NonDistinct,
+                filter,
+            }),
+        };
+        match self
+            .definitions
+            .iter_mut()
+            .find(|d| d.partition_by == partition_by && d.order_by == order_by && d.frame == frame)
+        {
+            Some(definition) => definition.functions.push(function),
+            None => self.definitions.push(WindowDefinition {
+                partition_by,
+                order_by,
+                frame,
+                functions: vec![function],
+            }),
+        }
+        Ok(())
+    }
+
+    /// Resolves references to named windows in an OVER clause.
+    fn resolve_window(&self, over: ast::Over) -> Result<ast::Window> {
+        match over {
+            ast::Over::Name(name) => self.named_window(name.as_str(), self.named_windows.len()),
+            ast::Over::Window(mut window) => match window.base.take() {
+                Some(base) => {
+                    let base_window = self.named_window(base.as_str(), self.named_windows.len())?;
+                    merge_windows(base.as_str(), base_window, window)
+                }
+                None => Ok(window),
+            },
+        }
+    }
+
+    /// Returns the named window `name`, looking only at the first `count` windows of the
+    /// WINDOW clause. Like SQLite, the last window of a given name wins.
+    fn named_window(&self, name: &str, count: usize) -> Result<ast::Window> {
+        let normalized_name = normalize_ident(name);
+        let Some(index) = self.named_windows[..count]
+            .iter()
+            .rposition(|w| normalize_ident(w.name.as_str()) == normalized_name)
+        else {
+            bail_parse_error!("no such window: {}", name);
+        };
+        let mut window = self.named_windows[index].window.clone();
+        match window.base.take() {
+            Some(base) => {
+                let base_window = self.named_window(base.as_str(), index)?;
+                merge_windows(base.as_str(), base_window, window)
+            }
+            None => Ok(window),
+        }
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn resolve_definition(
+        &mut self,
+        window: ast::Window,
+    ) -> Result<(Vec<Expr>, Vec<(Expr, SortOrder)>, WindowFrame)> {
+        let mut partition_by = vec![];
+        for expr in window.partition_by.unwrap_or_default() {
+            partition_by.push(self.sort_key_leaf(expr)?);
+        }
+        let mut order_by = vec![];
+        for sorted_column in window.order_by.unwrap_or_default() {
+            order_by.push((
+                self.sort_key_leaf(sorted_column.expr)?,
+                sorted_column.order.unwrap_or(SortOrder::Asc),
+            ));
+        }
+        let frame = match window.frame_clause {
+            None => WindowFrame::default(),
+            Some(frame_clause) => {
+                let start = frame_clause.start;
+                let end = frame_clause.end.unwrap_or(FrameBound::CurrentRow);
+                let unsupported = matches!(start, FrameBound::UnboundedFollowing)
+                    || matches!(end, FrameBound::UnboundedPreceding)
+                    || matches!(
+                        (&start, &end),
+                        (FrameBound::CurrentRow, FrameBound::Preceding(_))
+                            | (
+                                FrameBound::Following(_),
+                                FrameBound::Preceding(_) | FrameBound::CurrentRow
+                            )
+                    );
+                if unsupported {
+                    bail_parse_error!("unsupported frame specification");
+                }
+                if frame_clause.mode == FrameMode::Range
+                    && (frame_bound_offset(&start).is_some() || frame_bound_offset(&end).is_some())
+                    && order_by.len() != 1
+                {
+                    bail_parse_error!(
+                        "RANGE with offset PRECEDING/FOLLOWING requires one ORDER BY expression"
+                    );
+                }
+                if frame_bound_offset(&start).is_some_and(|offset| !is_constant_offset(offset)) {
+                    bail_parse_error!("frame starting offset must be a non-negative integer");
+                }
+                if frame_bound_offset(&end).is_some_and(|offset| !is_constant_offset(offset)) {
+                    bail_parse_error!("frame ending offset must be a non-negative integer");
+                }
+                WindowFrame {
+                    mode: frame_clause.mode,
+                    start,
+                    end,
+                    exclude: frame_clause.exclude.unwrap_or(FrameExclude::NoOthers),
+                }
+            }
+        };
+        Ok((partition_by, order_by, frame))
+    }
+}
+
+/// Applies a window definition on top of the named window `base_name`.
+fn merge_windows(base_name: &str, base: ast::Window, window: ast::Window) -> Result<ast::Window> {
+    if window.partition_by.is_some() {
+        bail_parse_error!("cannot override PARTITION clause of window: {}", base_name);
+    }
+    if base.order_by.is_some() && window.order_by.is_some() {
+        bail_parse_error!("cannot override ORDER BY clause of window: {}", base_name);
+    }
+    if base.frame_clause.is_some() {
+        bail_parse_error!(
+            "cannot override frame specification of window: {}",
+            base_name
+        );
+    }
+    Ok(ast::Window {
+        base: None,
+        partition_by: base.partition_by,
+        order_by: window.order_by.or(base.order_by),
+        frame_clause: window.frame_clause,
+    })
+}
+
+fn frame_bound_offset(bound: &FrameBound) -> Option<&Expr> {
+    match bound {
+        FrameBound::Preceding(offset) | FrameBound::Following(offset) => Some(offset),
+        _ => None,
+    }
+}
+
+/// Frame offsets are evaluated once per query, so they cannot reference columns.
+fn is_constant_offset(expr: &Expr) -> bool {
+    let mut constant = true;
+    let _ = walk_expr(expr, &mut |e: &Expr| -> Result<WalkControl> {
+        if matches!(
+            e,
+            Expr::Id(_)
+                | Expr::Qualified(..)
+                | Expr::DoublyQualified(..)
+                | Expr::Column { .. }
+                | Expr::Subquery(_)
+                | Expr::Exists(_)
+                | Expr::InSelect { .. }
+                | Expr::InTable { .. }
+        ) || window_function_name(e).is_some()
+        {
+            constant = false;
+        }
+        Ok(WalkControl::Continue)
+    });
+    constant
+}
+
+/// A term of the ORDER BY clause of a SELECT with window functions.
+enum OrderByTerm {
+    /// A reference to a result column by its number, e.g. ORDER BY 2.
+    ResultColumn(usize),
+    Expr(Expr),
+}
+
+/// A result column of a SELECT with window functions.
+enum OuterResultColumn {
+    Expr {
+        expr: Expr,
+        alias: Option<String>,
+        user_alias: bool,
+    },
+    /// `*` or `table.*`, expanded once the subquery has been planned.
+    Star(ResultColumn),
+}
+
+/// Plans a SELECT whose result columns or ORDER BY clause call window functions.
+///
+/// The SELECT is split in two: a FROM clause subquery that computes every expression that is
+/// not a window function call, and an outer plan that reads the subquery and computes the
+/// window functions. For example
+///
+/// ```sql
+/// SELECT a, sum(b) OVER (PARTITION BY c ORDER BY d) FROM t WHERE e > 0
+/// ```
+///
+/// becomes a plan with window functions over the subquery `SELECT a, b, c, d FROM t WHERE e > 0`.
+#[allow(clippy::too_many_arguments)]
+pub fn prepare_window_select_plan(
+    schema: &crate::schema::Schema,
+    select: SelectInner,
+    limit: Option<&ast::Limit>,
+    order_by: Option<Vec<SortedColumn>>,
+    with: Option<ast::With>,
+    syms: &SymbolTable,
+    outer_query_refs: &[OuterQueryReference],
+    ctes: &[Rc<Cte>],
+    table_ref_counter: &mut TableRefIdCounter,
+    query_destination: QueryDestination,
+    connection: &Arc<crate::Connection>,
+) -> Result<SelectPlan> {
+    let SelectInner {
+        distinctness,
+        columns,
+        from,
+        where_clause,
+        group_by,
+        window_clause,
+    } = select;
+    if let Some(where_clause) = &where_clause {
+        bail_if_window_function(where_clause)?;
+    }
+    if let Some(group_by) = &group_by {
+        for expr in group_by.exprs.iter() {
+            bail_if_window_function(expr)?;
+        }
+        if let Some(having) = &group_by.having {
+            bail_if_window_function(having)?;
+        }
+    }
+
+    let subquery_id = table_ref_counter.next();
+    let mut rewriter = WindowRewriter {
+        subquery_id,
+        named_windows: window_clause.as_deref().unwrap_or_default(),
+        syms,
+        leaves: vec![],
+        definitions: vec![],
+        function_count: 0,
+    };
+
+    let mut outer_columns = Vec::with_capacity(columns.len());
+    for column in columns {
+        match column {
+            ResultColumn::Expr(mut expr, maybe_alias) => {
+                let (alias, user_alias) = match maybe_alias {
+                    Some(ast::As::As(alias) | ast::As::Elided(alias)) => {
+                        (Some(alias.as_str().to_string()), true)
+                    }
+                    // Bare column references are named after the subquery column they read.
+                    None if matches!(
+                        expr,
+                        Expr::Id(_) | Expr::Qualified(..) | Expr::DoublyQualified(..)
+                    ) =>
+                    {
+                        (None, false)
+                    }
+                    None => (Some(expr.to_string()), false),
+                };
+                rewriter.rewrite(&mut expr)?;
+                outer_columns.push(OuterResultColumn::Expr {
+                    expr,
+                    alias,
+                    user_alias,
+                });
+            }
+            star => outer_columns.push(OuterResultColumn::Star(star)),
+        }
+    }
+
+    let mut order_by_terms = vec![];
+    for sorted_column in order_by.unwrap_or_default() {
+        let order = sorted_column.order.unwrap_or(SortOrder::Asc);
+        let term = match sorted_column.expr {
+            Expr::Literal(ast::Literal::Numeric(num)) => {
+                let column_number = num.parse::<usize>()?;
+                if column_number == 0 {
+                    bail_parse_error!("invalid column index: {}", column_number);
+                }
+                OrderByTerm::ResultColumn(column_number - 1)
+            }
+            mut expr => {
+                let aliased = match &expr {
+                    Expr::Id(id) => outer_columns.iter().find_map(|c| match c {
+                        OuterResultColumn::Expr {
+                            expr,
+                            alias: Some(alias),
+                            user_alias: true,
+                        } if alias.eq_ignore_ascii_case(id.as_str()) => Some(expr.clone()),
+                        _ => None,
+                    }),
+                    _ => None,
+                };
+                match aliased {
+                    Some(aliased) => OrderByTerm::Expr(aliased),
+                    None => {
+                        rewriter.rewrite(&mut expr)?;
+                        OrderByTerm::Expr(expr)
+                    }
+                }
+            }
+        };
+        order_by_terms.push((term, order));
+    }
+
+    // Plan the subquery: the leaves first, then the expansion of any `*` result columns.
+    let WindowRewriter {
+        leaves,
+        definitions,
+        ..
+    } = rewriter;
+    let leaf_count = leaves.len();
+    let mut subquery_columns: Vec<ResultColumn> = leaves
+        .into_iter()
+        .map(|leaf| ResultColumn::Expr(leaf, None))
+        .collect();
+    let mut star_columns = vec![];
+    for column in outer_columns.iter() {
+        if let OuterResultColumn::Star(star) = column {
+            subquery_columns.push(star.clone());
+            star_columns.push(star.clone());
+        }
+    }
+    if subquery_columns.is_empty() {
+        subquery_columns.push(ResultColumn::Expr(Expr::Literal(ast::Literal::Null), None));
+    }
+    let subquery_select = ast::Select {
+        with,
+        body: ast::SelectBody {
+            select: Box::new(ast::OneSelect::Select(Box::new(SelectInner {
+                distinctness: None,
+                columns: subquery_columns,
+                from,
+                where_clause,
+                group_by,
+                window_clause: None,
+            }))),
+            compounds: None,
+        },
+        order_by: None,
+        limit: None,
+    };
+    let Plan::Select(subquery_plan) = prepare_select_plan(
+        schema,
+        subquery_select,
+        syms,
+        outer_query_refs,
+        ctes,
+        table_ref_counter,
+        QueryDestination::CoroutineYield {
+            yield_reg: usize::MAX, // will be set later in bytecode emission
+            coroutine_implementation_start: BranchOffset::Placeholder, // will be set later in bytecode emission
+        },
+        connection,
+    )?
+    else {
+        unreachable!("a SELECT without compounds is planned as a single SELECT");
+    };
+
+    // Number of subquery columns produced by each `*` result column.
+    let star_widths = star_columns
+        .iter()
+        .map(|star| {
+            let tables = subquery_plan.table_references.joined_tables().iter();
+            let visible_columns =
+                |t: &JoinedTable| t.columns().iter().filter(|c| !c.hidden).count();
+            match star {
+                ResultColumn::TableStar(name) => {
+                    let name = normalize_ident(name.as_str());
+                    tables
+                        .filter(|t| t.identifier == name)
+                        .map(visible_columns)
+                        .sum()
+                }
+                _ => tables.map(visible_columns).sum::<usize>(),
+            }
+        })
+        .collect::<Vec<_>>();
+    let source_column_count = subquery_plan.result_columns.len();
+
+    let column_ref = |column: usize| Expr::Column {
+        database: None,
+        table: subquery_id,
+        column,
+        is_rowid_alias: false,
+    };
+    let mut result_columns = vec![];
+    let mut next_star_column = leaf_count;
+    let mut star_widths = star_widths.into_iter();
+    for column in outer_columns {
+        match column {
+            OuterResultColumn::Expr { expr, alias, .. } => {
+                result_columns.push(ResultSetColumn {
+                    expr,
+                    alias,
+                    contains_aggregates: false,
+                });
+            }
+            OuterResultColumn::Star(_) => {
+                let width = star_widths.next().unwrap();
+                for column in next_star_column..next_star_column + width {
+                    result_columns.push(ResultSetColumn {
+                        expr: column_ref(column),
+                        alias: None,
+                        contains_aggregates: false,
+                    });
+                }
+                next_star_column += width;
+            }
+        }
+    }
+
+    let mut order_by = None;
+    if !order_by_terms.is_empty() {
+        let mut key = vec![];
+        for (term, order) in order_by_terms {
+            let expr = match term {
+                OrderByTerm::ResultColumn(index) => match result_columns.get(index) {
+                    Some(result_column) => result_column.expr.clone(),
+                    None => bail_parse_error!("invalid column index: {}", index + 1),
+                },
+                OrderByTerm::Expr(expr) => expr,
+            };
+            key.push((expr, order));
+        }
+        order_by = Some(key);
+    }
+
+    let (limit, offset) = limit.map_or(Ok((None, None)), parse_limit)?;
+    let subquery_name = format!("window_subquery_{}", usize::from(subquery_id));
+    let table_references = TableReferences::new(
+        vec![JoinedTable::new_subquery(
+            subquery_name,
+            subquery_plan,
+            None,
+            subquery_id,
+        )],
+        vec![],
+    );
+
+    Ok(SelectPlan {
+        join_order: vec![JoinOrderMember {
+            table_id: subquery_id,
+            original_idx: 0,
+            is_outer: false,
+            is_right: false,
+        }],
+        table_references,
+        result_columns,
+        where_clause: vec![],
+        group_by: None,
+        order_by,
+        aggregates: vec![],
+        limit,
+        offset,
+        contains_constant_false_condition: false,
+        query_destination,
+        distinctness: Distinctness::from_ast(distinctness.as_ref()),
+        values: vec![],
+        window: Some(Window {
+            source_columns: (0..source_column_count).map(column_ref).collect(),
+            definitions,
+        }),
+        materialized_ctes: vec![],
+        non_from_clause_subqueries: vec![],
+    })
+}
+
+/// Cursors and registers of a window stage, see [init_window].
+#[derive(Debug)]
+pub struct WindowStage {
+    /// Sorter ordering the rows by the PARTITION BY and ORDER BY terms, if the window has any.
+    sort_cursor: Option<CursorID>,
+    reg_sorter_data: usize,
+    /// Collations of the PARTITION BY and ORDER BY terms.
+    collations: Vec<Option<CollationSeq>>,
+    /// Ephemeral table holding the rows of the stage in order, with rowids 1..N.
+    table: Rc<BTreeTable>,
+    table_cursor: CursorID,
+    /// Rowid of the last row inserted into the table, which is also the number of rows.
+    reg_row_count: usize,
+    /// Registers holding a row of the stage: the sort keys followed by the carried columns,
+    /// i.e. the result columns of the subquery and the results of the previous stages.
+    reg_row_start: usize,
+    key_count: usize,
+    carry_count: usize,
+}
+
+impl WindowStage {
+    fn column_count(&self) -> usize {
+        self.key_count + self.carry_count
+    }
+}
+
+/// Metadata for computing window functions, one stage per window definition.
+#[derive(Debug)]
+pub struct WindowMetadata {
+    stages: Vec<WindowStage>,
+}
+
+/// Opens the sorters and ephemeral tables of all window stages.
+pub fn init_window(
+    program: &mut ProgramBuilder,
+    t_ctx: &mut TranslateCtx,
+    window: &Window,
+    tables: &TableReferences,
+) -> Result<()> {
+    let mut stages = Vec::with_capacity(window.definitions.len());
+    let mut carry_count = window.source_columns.len();
+    for definition in window.definitions.iter() {
+        let key_count = definition.key_count();
+        let keys = definition
+            .partition_by
+            .iter()
+            .map(|expr| (expr, SortOrder::Asc))
+            .chain(
+                definition
+                    .order_by
+                    .iter()
+                    .map(|(expr, order)| (expr, *order)),
+            );
+        let collations = keys
+            .clone()
+            .map(|(expr, _)| sort_key_collation(expr, tables))
+            .collect::<Result<Vec<_>>>()?;
+        let sort_cursor = if key_count > 0 {
+            let sort_cursor = program.alloc_cursor_id(CursorType::Sorter);
+            program.emit_insn(Insn::SorterOpen {
+                cursor_id: sort_cursor,
+                columns: key_count,
+                order: keys.map(|(_, order)| order).collect(),
+                collations: collations.clone(),
+            });
+            Some(sort_cursor)
+        } else {
+            None
+        };
+
+        let column_count = key_count + carry_count;
+        let table = Rc::new(BTreeTable {
+            root_page: 0, // Not relevant for ephemeral table definition
+            name: "window_scratch".to_string(),
+            has_rowid: true,
+            primary_key_columns: vec![],
+            columns: (0..column_count)
+                .map(|_| Column {
+                    name: None,
+                    ty: Type::Blob,
+                    ty_str: "BLOB".to_string(),
+                    primary_key: false,
+                    is_rowid_alias: false,
+                    notnull: false,
+                    default: None,
+                    unique: false,
+                    collation: None,
+                    hidden: false,
+                    generated: None,
+                })
+                .collect(),
+            is_strict: false,
+            unique_sets: None,
+            conflict_clauses: vec![],
+            foreign_keys: vec![],
+            checks: vec![],
+            notnull_conflict_clauses: vec![],
+        });
+        let table_cursor = program.alloc_cursor_id(CursorType::BTreeTable(table.clone()));
+        program.emit_insn(Insn::OpenEphemeral {
+            cursor_id: table_cursor,
+            is_table: true,
+        });
+        let reg_row_count = program.alloc_register();
+        program.emit_int(0, reg_row_count);
+
+        stages.push(WindowStage {
+            sort_cursor,
+            reg_sorter_data: program.alloc_register(),
+            collations,
+            table,
+            table_cursor,
+            reg_row_count,
+            reg_row_start: program.alloc_registers(column_count),
+            key_count,
+            carry_count,
+        });
+        carry_count += definition.functions.len();
+    }
+    t_ctx.meta_window = Some(WindowMetadata { stages });
+    Ok(())
+}
+
+/// The collation of a PARTITION BY or ORDER BY term, following the rules of [init_order_by].
+///
+/// [init_order_by]: crate::translate::order_by::init_order_by
+fn sort_key_collation(expr: &Expr, tables: &TableReferences) -> Result<Option<CollationSeq>> {
+    match expr {
+        Expr::Collate(_, collation_name) => CollationSeq::new(collation_name).map(Some),
+        Expr::Column { table, column, .. } => Ok(tables
+            .find_table_by_internal_id(*table)
+            .and_then(|table| table.get_column_at(*column))
+            .and_then(|column| column.collation)),
+        _ => Ok(Some(CollationSeq::default())),
+    }
+}
+
+/// Returns the subquery result column read by a window function argument or sort key.
+fn source_column_index(expr: &Expr) -> usize {
+    match expr {
+        Expr::Column { column, .. } => *column,
+        Expr::Collate(expr, _) => source_column_index(expr),
+        _ => unreachable!("window function terms are subquery result columns"),
+    }
+}
+
+/// Writes the current row of the main loop into the first window stage.
+pub fn emit_window_source_row(
+    program: &mut ProgramBuilder,
+    t_ctx: &TranslateCtx,
+    plan: &SelectPlan,
+) -> Result<()> {
+    let window = plan.window.as_ref().expect("window must exist");
+    let definition = &window.definitions[0];
+    let stage = &t_ctx
+        .meta_window
+        .as_ref()
+        .expect("window metadata must exist")
+        .stages[0];
+    let terms = definition
+        .partition_by
+        .iter()
+        .chain(definition.order_by.iter().map(|(expr, _)| expr))
+        .chain(window.source_columns.iter());
+    for (i, expr) in terms.enumerate() {
+        translate_expr(
+            program,
+            Some(&plan.table_references),
+            expr,
+            stage.reg_row_start + i,
+            &t_ctx.resolver,
+        )?;
+    }
+    emit_stage_insert(program, stage);
+    Ok(())
+}
+
+/// Adds the row in the registers of `stage` to the stage.
+fn emit_stage_insert(program: &mut ProgramBuilder, stage: &WindowStage) {
+    match stage.sort_cursor {
+        Some(sort_cursor) => sorter_insert(
+            program,
+            stage.reg_row_start,
+            stage.column_count(),
+            sort_cursor,
+            stage.reg_sorter_data,
+        ),
+        None => {
+            program.emit_insn(Insn::MakeRecord {
+                start_reg: stage.reg_row_start,
+                count: stage.column_count(),
+                dest_reg: stage.reg_sorter_data,
+                index_name: None,
+            });
+            program.emit_insn(Insn::NewRowid {
+                cursor: stage.table_cursor,
+                rowid_reg: stage.reg_row_count,
+                prev_largest_reg: 0,
+            });
+            program.emit_insn(Insn::Insert {
+                cursor: stage.table_cursor,
+                key_reg: stage.reg_row_count,
+                record_reg: stage.reg_sorter_data,
+                flag: InsertFlags::new(),
+                table_name: stage.table.name.clone(),
+            });
+        }
+    }
+}
+
+/// Emits the bytecode computing the window functions once the main loop has written all rows
+/// into the first stage. The last stage emits the result rows, or inserts them into the ORDER BY
+/// sorter.
+pub fn emit_window(
+    program: &mut ProgramBuilder,
+    t_ctx: &mut TranslateCtx,
+    plan: &SelectPlan,
+) -> Result<()> {
+    let window = plan.window.as_ref().expect("window must exist");
+    let metadata = t_ctx
+        .meta_window
+        .take()
+        .expect("window metadata must exist");
+    let label_done = program.allocate_label();
+
+    // Registers holding the subquery columns of the row a window function is looking at.
+    let reg_probe_start = program.alloc_registers(window.source_columns.len());
+    let mut probe_resolver = Resolver::new(t_ctx.resolver.schema, t_ctx.resolver.symbol_table);
+    probe_resolver.enable_expr_to_reg_cache();
+    for (i, expr) in window.source_columns.iter().enumerate() {
+        probe_resolver
+            .expr_to_reg_cache
+            .push((expr, reg_probe_start + i));
+    }
+
+    for (index, (definition, stage)) in window
+        .definitions
+        .iter()
+        .zip(metadata.stages.iter())
+        .enumerate()
+    {
+        let emitter = StageEmitter::new(
+            program,
+            stage,
+            definition,
+            &plan.table_references,
+            &probe_resolver,
+            reg_probe_start,
+        );
+        let output = match metadata.stages.get(index + 1) {
+            Some(next_stage) => StageOutput::NextStage(next_stage, &window.definitions[index + 1]),
+            None => StageOutput::Result { label_done },
+        };
+        emitter.emit(program, t_ctx, plan, index, output)?;
+    }
+
+    program.preassign_label_to_next_insn(label_done);
+    Ok(())
+}
+
+/// Where the rows of a stage go once its window functions have been computed.
+enum StageOutput<'a> {
+    NextStage(&'a WindowStage, &'a WindowDefinition),
+    Result { label_done: BranchOffset },
+}
+
+/// Registers of an aggregate window function that is computed incrementally, i.e. whose
+/// accumulator is kept between the rows of a partition.
+#[derive(Clone, Copy)]
+struct IncrementalAggregate {
+    reg_acc: usize,
+    /// The last row stepped into the accumulator.
+    reg_last_row: usize,
+}
+
+/// Emits the bytecode walking the rows of one window stage.
+///
+/// Rows are numbered by their rowid in the ephemeral table of the stage. The registers hold the
+/// current row, the bounds of its partition and peer group, and the bounds of its frame.
+struct StageEmitter<'a> {
+    stage: &'a WindowStage,
+    definition: &'a WindowDefinition,
+    tables: &'a TableReferences,
+    probe_resolver: &'a Resolver<'a>,
+    reg_probe_start: usize,
+    reg_one: usize,
+    reg_row: usize,
+    reg_partition_start: usize,
+    reg_partition_end: usize,
+    reg_group_start: usize,
+    reg_group_end: usize,
+    /// Number of the peer group of the current row in its partition, starting at 1.
+    reg_group_number: usize,
+    reg_frame_start: usize,
+    reg_frame_end: usize,
+    reg_scan: usize,
+    reg_partition_keys: usize,
+    reg_group_keys: usize,
+    reg_scan_keys: usize,
+    reg_pointer_keys: usize,
+    reg_carry: usize,
+    reg_results: usize,
+    /// Values of the frame offsets, e.g. the 2 of `ROWS 2 PRECEDING`.
+    reg_start_offset: Option<usize>,
+    reg_end_offset: Option<usize>,
+    /// GROUPS and RANGE frames with offsets: the first row of the frame, and the first row after it.
+    reg_start_pointer: usize,
+    reg_end_pointer: usize,
+    /// GROUPS frames with offsets: the peer group number from which the pointers advance.
+    reg_start_threshold: usize,
+    reg_end_threshold: usize,
+    reg_range_bound: usize,
+    incremental: Vec<Option<IncrementalAggregate>>,
+}
+
+impl<'a> StageEmitter<'a> {
+    fn new(
+        program: &mut ProgramBuilder,
+        stage: &'a WindowStage,
+        definition: &'a WindowDefinition,
+        tables: &'a TableReferences,
+        probe_resolver: &'a Resolver<'a>,
+        reg_probe_start: usize,
+    ) -> Self {
+        let frame = &definition.frame;
+        let key_count = stage.key_count.max(1);
+        let incremental = definition
+            .functions
+            .iter()
+            .map(|function| match function {
+                WindowFunction::Aggregate(agg)
+                    if frame.start == FrameBound::UnboundedPreceding
+                        && frame.exclude == FrameExclude::NoOthers
+                        && !matches!(agg.func, AggFunc::External(_)) =>
+                {
+                    Some(IncrementalAggregate {
+                        reg_acc: program.alloc_register(),
+                        reg_last_row: program.alloc_register(),
+                    })
+                }
+                _ => None,
+            })
+            .collect();
+        Self {
+            stage,
+            definition,
+            tables,
+            probe_resolver,
+            reg_probe_start,
+            reg_one: program.alloc_register(),
+            reg_row: program.alloc_register(),
+            reg_partition_start: program.alloc_register(),
+            reg_partition_end: program.alloc_register(),
+            reg_group_start: program.alloc_register(),
+            reg_group_end: program.alloc_register(),
+            reg_group_number: program.alloc_register(),
+            reg_frame_start: program.alloc_register(),
+            reg_frame_end: program.alloc_register(),
+            reg_scan: program.alloc_register(),
+            reg_partition_keys: program.alloc_registers(key_count),
+            reg_group_keys: program.alloc_registers(key_count),
+            reg_scan_keys: program.alloc_registers(key_count),
+            reg_pointer_keys: program.alloc_registers(key_count),
+            reg_carry: program.alloc_registers(stage.carry_count),
+            reg_results: program.alloc_registers(definition.functions.len()),
+            reg_start_offset: frame_bound_offset(&frame.start).map(|_| program.alloc_register()),
+            reg_end_offset: frame_bound_offset(&frame.end).map(|_| program.alloc_register()),
+            reg_start_pointer: program.alloc_register(),
+            reg_end_pointer: program.alloc_register(),
+            reg_start_threshold: program.alloc_register(),
+            reg_end_threshold: program.alloc_register(),
+            reg_range_bound: program.alloc_register(),
+            incremental,
+        }
+    }
+
+    fn partition_key_count(&self) -> usize {
+        self.definition.partition_by.len()
+    }
+
+    fn order_key_count(&self) -> usize {
+        self.definition.order_by.len()
+    }
+
+    fn uses_frame(&self) -> bool {
+        self.definition.functions.iter().any(|f| f.uses_frame())
+    }
+
+    fn emit(
+        &self,
+        program: &mut ProgramBuilder,
+        t_ctx: &mut TranslateCtx,
+        plan: &SelectPlan,
+        stage_index: usize,
+        output: StageOutput,
+    ) -> Result<()> {
+        let stage = self.stage;
+        let label_stage_end = program.allocate_label();
+
+        // Copy the sorted rows into the ephemeral table, numbering them from 1.
+        if let Some(sort_cursor) = stage.sort_cursor {
+            let pseudo_cursor = program.alloc_cursor_id(CursorType::Pseudo(PseudoCursorType {
+                column_count: stage.column_count(),
+            }));
+            program.emit_insn(Insn::OpenPseudo {
+                cursor_id: pseudo_cursor,
+                content_reg: stage.reg_sorter_data,
+                num_fields: stage.column_count(),
+            });
+            let label_sorted = program.allocate_label();
+            let label_sort_loop = program.allocate_label();
+            program.emit_insn(Insn::SorterSort {
+                cursor_id: sort_cursor,
+                pc_if_empty: label_sorted,
+            });
+            program.preassign_label_to_next_insn(label_sort_loop);
+            program.emit_insn(Insn::SorterData {
+                cursor_id: sort_cursor,
+                dest_reg: stage.reg_sorter_data,
+                pseudo_cursor,
+            });
+            program.emit_insn(Insn::NewRowid {
+                cursor: stage.table_cursor,
+                rowid_reg: stage.reg_row_count,
+                prev_largest_reg: 0,
+            });
+            program.emit_insn(Insn::Insert {
+                cursor: stage.table_cursor,
+                key_reg: stage.reg_row_count,
+                record_reg: stage.reg_sorter_data,
+                flag: InsertFlags::new(),
+                table_name: stage.table.name.clone(),
+            });
+            program.emit_insn(Insn::SorterNext {
+                cursor_id: sort_cursor,
+                pc_if_next: label_sort_loop,
+            });
+            program.preassign_label_to_next_insn(label_sorted);
+        }
+        program.emit_insn(Insn::IfNot {
+            reg: stage.reg_row_count,
+            target_pc: label_stage_end,
+            jump_if_null: true,
+        });
+
+        program.emit_int(1, self.reg_one);
+        self.emit_frame_offsets(program, t_ctx)?;
+
+        // Partition loop
+        program.emit_int(1, self.reg_row);
+        let label_partition = program.allocate_label();
+        program.preassign_label_to_next_insn(label_partition);
+        self.emit_copy(program, self.reg_row, self.reg_partition_start);
+        if self.partition_key_count() > 0 {
+            self.emit_read_columns(
+                program,
+                self.reg_partition_start,
+                0,
+                self.partition_key_count(),
+                self.reg_partition_keys,
+            );
+            self.emit_find_run_end(
+                program,
+                self.reg_partition_start,
+                stage.reg_row_count,
+                0,
+                self.partition_key_count(),
+                self.reg_partition_keys,
+                self.reg_scan,
+            );
+            program.emit_insn(Insn::Subtract {
+                lhs: self.reg_scan,
+                rhs: self.reg_one,
+                dest: self.reg_partition_end,
+            });
+        } else {
+            self.emit_copy(program, stage.reg_row_count, self.reg_partition_end);
+        }
+        program.emit_int(0, self.reg_group_number);
+        program.emit_insn(Insn::Subtract {
+            lhs: self.reg_partition_start,
+            rhs: self.reg_one,
+            dest: self.reg_group_end,
+        });
+        self.emit_partition_init(program)?;
+
+        // Row loop
+        let label_row = program.allocate_label();
+        let label_same_group = program.allocate_label();
+        let label_next_row = program.allocate_label();
+        program.preassign_label_to_next_insn(label_row);
+        program.emit_insn(Insn::Le {
+            lhs: self.reg_row,
+            rhs: self.reg_group_end,
+            target_pc: label_same_group,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        });
+        self.emit_new_group(program)?;
+        program.preassign_label_to_next_insn(label_same_group);
+        if self.definition.frame.mode == FrameMode::Rows && self.uses_frame() {
+            self.emit_rows_frame(program);
+        }
+
+        self.emit_read_columns(
+            program,
+            self.reg_row,
+            stage.key_count,
+            stage.carry_count,
+            self.reg_carry,
+        );
+        for (i, function) in self.definition.functions.iter().enumerate() {
+            self.emit_function(program, function, i)?;
+        }
+
+        match output {
+            StageOutput::NextStage(next_stage, next_definition) => {
+                let keys = next_definition
+                    .partition_by
+                    .iter()
+                    .chain(next_definition.order_by.iter().map(|(expr, _)| expr));
+                for (i, expr) in keys.enumerate() {
+                    self.emit_copy(
+                        program,
+                        self.reg_carry + source_column_index(expr),
+                        next_stage.reg_row_start + i,
+                    );
+                }
+                let carry_start = next_stage.reg_row_start + next_stage.key_count;
+                program.emit_insn(Insn::Copy {
+                    src_reg: self.reg_carry,
+                    dst_reg: carry_start,
+                    extra_amount: stage.carry_count - 1,
+                });
+                program.emit_insn(Insn::Copy {
+                    src_reg: self.reg_results,
+                    dst_reg: carry_start + stage.carry_count,
+                    extra_amount: self.definition.functions.len() - 1,
+                });
+                emit_stage_insert(program, next_stage);
+            }
+            StageOutput::Result { label_done } => {
+                // Result columns read the subquery columns and the results of the earlier stages
+                // from the carried columns, and the results of this stage from their registers.
+                let window = plan.window.as_ref().expect("window must exist");
+                let mut resolver =
+                    Resolver::new(t_ctx.resolver.schema, t_ctx.resolver.symbol_table);
+                resolver.enable_expr_to_reg_cache();
+                for (i, expr) in window.source_columns.iter().enumerate() {
+                    resolver.expr_to_reg_cache.push((expr, self.reg_carry + i));
+                }
+                let mut carry_index = window.source_columns.len();
+                for definition in window.definitions[..stage_index].iter() {
+                    for function in definition.functions.iter() {
+                        resolver
+                            .expr_to_reg_cache
+                            .push((function.original_expr(), self.reg_carry + carry_index));
+                        carry_index += 1;
+                    }
+                }
+                for (i, function) in window.definitions[stage_index].functions.iter().enumerate() {
+                    resolver
+                        .expr_to_reg_cache
+                        .push((function.original_expr(), self.reg_results + i));
+                }
+
+                if plan.order_by.is_some() {
+                    order_by_sorter_insert(
+                        program,
+                        &resolver,
+                        t_ctx
+                            .meta_sort
+                            .as_ref()
+                            .expect("sort metadata must exist for ORDER BY"),
+                        &mut t_ctx.result_column_indexes_in_orderby_sorter,
+                        plan,
+                    )?;
+                } else {
+                    emit_select_result(
+                        program,
+                        &resolver,
+                        plan,
+                        Some(label_done),
+                        Some(label_next_row),
+                        t_ctx.reg_nonagg_emit_once_flag,
+                        t_ctx.reg_offset,
+                        t_ctx.reg_result_cols_start.unwrap(),
+                        t_ctx.limit_ctx,
+                    )?;
+                }
+                if let Distinctness::Distinct { ctx } = &plan.distinctness {
+                    let distinct_ctx = ctx.as_ref().expect("distinct context must exist");
+                    program.preassign_label_to_next_insn(distinct_ctx.label_on_conflict);
+                }
+            }
+        }
+
+        program.preassign_label_to_next_insn(label_next_row);
+        program.emit_insn(Insn::Add {
+            lhs: self.reg_row,
+            rhs: self.reg_one,
+            dest: self.reg_row,
+        });
+        program.emit_insn(Insn::Le {
+            lhs: self.reg_row,
+            rhs: self.reg_partition_end,
+            target_pc: label_row,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        });
+        program.emit_insn(Insn::Le {
+            lhs: self.reg_row,
+            rhs: stage.reg_row_count,
+            target_pc: label_partition,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        });
+        program.preassign_label_to_next_insn(label_stage_end);
+        Ok(())
+    }
+
+    /// Evaluates the frame offsets and checks that they are valid.
+    fn emit_frame_offsets(&self, program: &mut ProgramBuilder, t_ctx: &TranslateCtx) -> Result<()> {
+        let frame = &self.definition.frame;
+        let bounds = [
+            (&frame.start, self.reg_start_offset, "starting"),
+            (&frame.end, self.reg_end_offset, "ending"),
+        ];
+        for (bound, reg, description) in bounds {
+            let (Some(offset), Some(reg)) = (frame_bound_offset(bound), reg) else {
+                continue;
+            };
+            translate_expr(program, Some(self.tables), offset, reg, &t_ctx.resolver)?;
+            let label_invalid = program.allocate_label();
+            let label_valid = program.allocate_label();
+            program.emit_insn(Insn::IsNull {
+                reg,
+                target_pc: label_invalid,
+            });
+            if frame.mode == FrameMode::Range {
+                // Text and blobs sort after numbers, so anything from '' up is not a number.
+                self.emit_numeric_affinity(program, reg);
+                let reg_empty_text = program.alloc_register();
+                program.emit_string8(String::new(), reg_empty_text);
+                program.emit_insn(Insn::Ge {
+                    lhs: reg,
+                    rhs: reg_empty_text,
+                    target_pc: label_invalid,
+                    flags: CmpInsFlags::default(),
+                    collation: None,
+                });
+            } else {
+                self.emit_integer_check(program, reg, label_invalid);
+            }
+            let reg_zero = program.alloc_register();
+            program.emit_int(0, reg_zero);
+            program.emit_insn(Insn::Ge {
+                lhs: reg,
+                rhs: reg_zero,
+                target_pc: label_valid,
+                flags: CmpInsFlags::default(),
+                collation: None,
+            });
+            program.preassign_label_to_next_insn(label_invalid);
+            program.emit_insn(Insn::Halt {
+                err_code: SQLITE_ERROR,
+                on_error: ast::ResolveType::Abort,
+                description: format!(
+                    "frame {} offset must be a non-negative {}",
+                    description,
+                    if frame.mode == FrameMode::Range {
+                        "number"
+                    } else {
+                        "integer"
+                    }
+                ),
+            });
+            program.preassign_label_to_next_insn(label_valid);
+        }
+
+        if frame.mode == FrameMode::Groups {
+            // The pointer of an offset bound moves forward by one group whenever the current
+            // group number reaches the threshold, see [Self::emit_new_group].
+            let thresholds = [
+                (
+                    &frame.start,
+                    self.reg_start_offset,
+                    self.reg_start_threshold,
+                    2,
+                ),
+                (&frame.end, self.reg_end_offset, self.reg_end_threshold, 1),
+            ];
+            for (bound, reg_offset, reg_threshold, preceding_extra) in thresholds {
+                match bound {
+                    FrameBound::Preceding(_) => {
+                        program.emit_int(preceding_extra, reg_threshold);
+                        program.emit_insn(Insn::Add {
+                            lhs: reg_offset.unwrap(),
+                            rhs: reg_threshold,
+                            dest: reg_threshold,
+                        });
+                    }
+                    FrameBound::Following(_) => program.emit_int(2, reg_threshold),
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Resets the per-partition state of the frame pointers and incremental aggregates.
+    fn emit_partition_init(&self, program: &mut ProgramBuilder) -> Result<()> {
+        let frame = &self.definition.frame;
+        if self.uses_frame() && frame.mode != FrameMode::Rows {
+            self.emit_copy(program, self.reg_partition_start, self.reg_start_pointer);
+            self.emit_copy(program, self.reg_partition_start, self.reg_end_pointer);
+            if frame.mode == FrameMode::Groups {
+                if let FrameBound::Following(_) = frame.start {
+                    self.emit_advance_groups(
+                        program,
+                        self.reg_start_pointer,
+                        self.reg_start_offset.unwrap(),
+                    );
+                }
+                if let FrameBound::Following(_) = frame.end {
+                    // The end pointer is the first row after the frame.
+                    let reg_count = program.alloc_register();
+                    program.emit_insn(Insn::Add {
+                        lhs: self.reg_end_offset.unwrap(),
+                        rhs: self.reg_one,
+                        dest: reg_count,
+                    });
+                    self.emit_advance_groups(program, self.reg_end_pointer, reg_count);
+                }
+            }
+        }
+        for incremental in self.incremental.iter().flatten() {
+            program.emit_null(incremental.reg_acc, None);
+            program.emit_insn(Insn::Subtract {
+                lhs: self.reg_partition_start,
+                rhs: self.reg_one,
+                dest: incremental.reg_last_row,
+            });
+        }
+        Ok(())
+    }
+
+    /// Called on the first row of a peer group: finds the end of the group and, for GROUPS and
+    /// RANGE frames, computes the frame, which is the same for all the rows of the group.
+    fn emit_new_group(&self, program: &mut ProgramBuilder) -> Result<()> {
+        self.emit_copy(program, self.reg_row, self.reg_group_start);
+        program.emit_insn(Insn::Add {
+            lhs: self.reg_group_number,
+            rhs: self.reg_one,
+            dest: self.reg_group_number,
+        });
+        let order_key_count = self.order_key_count();
+        if order_key_count > 0 {
+            self.emit_read_columns(
+                program,
+                self.reg_group_start,
+                self.partition_key_count(),
+                order_key_count,
+                self.reg_group_keys,
+            );
+            self.emit_find_run_end(
+                program,
+                self.reg_group_start,
+                self.reg_partition_end,
+                self.partition_key_count(),
+                order_key_count,
+                self.reg_group_keys,
+                self.reg_scan,
+            );
+            program.emit_insn(Insn::Subtract {
+                lhs: self.reg_scan,
+                rhs: self.reg_one,
+                dest: self.reg_group_end,
+            });
+        } else {
+            self.emit_copy(program, self.reg_partition_end, self.reg_group_end);
+        }
+
+        if !self.uses_frame() {
+            return Ok(());
+        }
+        let frame = &self.definition.frame;
+        match frame.mode {
+            FrameMode::Rows => {}
+            FrameMode::Groups => {
+                let pointers = [
+                    (
+                        &frame.start,
+                        self.reg_start_pointer,
+                        self.reg_start_threshold,
+                    ),
+                    (&frame.end, self.reg_end_pointer, self.reg_end_threshold),
+                ];
+                for (bound, reg_pointer, reg_threshold) in pointers {
+                    if frame_bound_offset(bound).is_none() {
+                        continue;
+                    }
+                    let label_skip = program.allocate_label();
+                    program.emit_insn(Insn::Lt {
+                        lhs: self.reg_group_number,
+                        rhs: reg_threshold,
+                        target_pc: label_skip,
+                        flags: CmpInsFlags::default(),
+                        collation: None,
+                    });
+                    self.emit_advance_group(program, reg_pointer);
+                    program.preassign_label_to_next_insn(label_skip);
+                }
+                self.emit_group_frame(program, true);
+            }
+            FrameMode::Range => {
+                let has_offset = frame_bound_offset(&frame.start).is_some()
+                    || frame_bound_offset(&frame.end).is_some();
+                if !has_offset {
+                    self.emit_group_frame(program, true);
+                    return Ok(());
+                }
+                // Rows with a NULL sort key are only in range of each other, so an offset bound
+                // behaves like CURRENT ROW for them.
+                let label_null = program.allocate_label();
+                let label_done = program.allocate_label();
+                program.emit_insn(Insn::IsNull {
+                    reg: self.reg_group_keys,
+                    target_pc: label_null,
+                });
+                let order = self.definition.order_by[0].1;
+                let bounds = [
+                    (&frame.start, self.reg_start_pointer, true),
+                    (&frame.end, self.reg_end_pointer, false),
+                ];
+                for (bound, reg_pointer, is_start) in bounds {
+                    let reg_offset = match bound {
+                        FrameBound::Preceding(_) | FrameBound::Following(_) if is_start => {
+                            self.reg_start_offset.unwrap()
+                        }
+                        FrameBound::Preceding(_) | FrameBound::Following(_) => {
+                            self.reg_end_offset.unwrap()
+                        }
+                        _ => continue,
+                    };
+                    let add = matches!(
+                        (bound, order),
+                        (FrameBound::Preceding(_), SortOrder::Desc)
+                            | (FrameBound::Following(_), SortOrder::Asc)
+                    );
+                    program.emit_insn(if add {
+                        Insn::Add {
+                            lhs: self.reg_group_keys,
+                            rhs: reg_offset,
+                            dest: self.reg_range_bound,
+                        }
+                    } else {
+                        Insn::Subtract {
+                            lhs: self.reg_group_keys,
+                            rhs: reg_offset,
+                            dest: self.reg_range_bound,
+                        }
+                    });
+                    self.emit_advance_range(program, reg_pointer, is_start, order);
+                }
+                self.emit_group_frame(program, true);
+                program.emit_insn(Insn::Goto {
+                    target_pc: label_done,
+                });
+                program.preassign_label_to_next_insn(label_null);
+                self.emit_group_frame(program, false);
+                program.preassign_label_to_next_insn(label_done);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the frame of a GROUPS or RANGE window from the peer group and the frame pointers.
+    /// If `use_pointers` is false, offset bounds use the peer group instead of the pointers.
+    fn emit_group_frame(&self, program: &mut ProgramBuilder, use_pointers: bool) {
+        let frame = &self.definition.frame;
+        match &frame.start {
+            FrameBound::UnboundedPreceding => {
+                self.emit_copy(program, self.reg_partition_start, self.reg_frame_start)
+            }
+            FrameBound::Preceding(_) | FrameBound::Following(_) if use_pointers => {
+                self.emit_copy(program, self.reg_start_pointer, self.reg_frame_start)
+            }
+            _ => self.emit_copy(program, self.reg_group_start, self.reg_frame_start),
+        }
+        match &frame.end {
+            FrameBound::UnboundedFollowing => {
+                self.emit_copy(program, self.reg_partition_end, self.reg_frame_end)
+            }
+            FrameBound::Preceding(_) | FrameBound::Following(_) if use_pointers => program
+                .emit_insn(Insn::Subtract {
+                    lhs: self.reg_end_pointer,
+                    rhs: self.reg_one,
+                    dest: self.reg_frame_end,
+                }),
+            _ => self.emit_copy(program, self.reg_group_end, self.reg_frame_end),
+        }
+    }
+
+    /// Sets the frame of a ROWS window for the current row.
+    fn emit_rows_frame(&self, program: &mut ProgramBuilder) {
+        let frame = &self.definition.frame;
+        let bounds = [
+            (
+                &frame.start,
+                self.reg_start_offset,
+                self.reg_frame_start,
+                true,
+            ),
+            (&frame.end, self.reg_end_offset, self.reg_frame_end, false),
+        ];
+        // Only a frame start before the partition or a frame end after it needs clamping: a start
+        // after the partition or an end before it makes the frame empty.
+        for (bound, reg_offset, dest, is_start) in bounds {
+            match bound {
+                FrameBound::UnboundedPreceding => {
+                    self.emit_copy(program, self.reg_partition_start, dest)
+                }
+                FrameBound::UnboundedFollowing => {
+                    self.emit_copy(program, self.reg_partition_end, dest)
+                }
+                FrameBound::CurrentRow => self.emit_copy(program, self.reg_row, dest),
+                FrameBound::Preceding(_) => {
+                    program.emit_insn(Insn::Subtract {
+                        lhs: self.reg_row,
+                        rhs: reg_offset.unwrap(),
+                        dest,
+                    });
+                    if !is_start {
+                        continue;
+                    }
+                    let label_in_partition = program.allocate_label();
+                    program.emit_insn(Insn::Ge {
+                        lhs: dest,
+                        rhs: self.reg_partition_start,
+                        target_pc: label_in_partition,
+                        flags: CmpInsFlags::default(),
+                        collation: None,
+                    });
+                    self.emit_copy(program, self.reg_partition_start, dest);
+                    program.preassign_label_to_next_insn(label_in_partition);
+                }
+                FrameBound::Following(_) => {
+                    program.emit_insn(Insn::Add {
+                        lhs: self.reg_row,
+                        rhs: reg_offset.unwrap(),
+                        dest,
+                    });
+                    if is_start {
+                        continue;
+                    }
+                    let label_in_partition = program.allocate_label();
+                    program.emit_insn(Insn::Le {
+                        lhs: dest,
+                        rhs: self.reg_partition_end,
+                        target_pc: label_in_partition,
+                        flags: CmpInsFlags::default(),
+                        collation: None,
+                    });
+                    self.emit_copy(program, self.reg_partition_end, dest);
+                    program.preassign_label_to_next_insn(label_in_partition);
+                }
+            }
+        }
+    }
+
+    fn emit_copy(&self, program: &mut ProgramBuilder, src_reg: usize, dst_reg: usize) {
+        program.emit_insn(Insn::Copy {
+            src_reg,
+            dst_reg,
+            extra_amount: 0,
+        });
+    }
+
+    /// Positions the table cursor of the stage on the row numbered `reg_row`.
+    fn emit_seek(&self, program: &mut ProgramBuilder, reg_row: usize) {
+        // Rows are numbered without gaps, so the seek always finds the row.
+        let label_found = program.allocate_label();
+        program.emit_insn(Insn::SeekRowid {
+            cursor_id: self.stage.table_cursor,
+            src_reg: reg_row,
+            target_pc: label_found,
+        });
+        program.preassign_label_to_next_insn(label_found);
+    }
+
+    /// Reads `count` columns of the row numbered `reg_row`, starting at column `first_column`.
+    fn emit_read_columns(
+        &self,
+        program: &mut ProgramBuilder,
+        reg_row: usize,
+        first_column: usize,
+        count: usize,
+        dest: usize,
+    ) {
+        self.emit_seek(program, reg_row);
+        for i in 0..count {
+            program.emit_column(self.stage.table_cursor, first_column + i, dest + i);
+        }
+    }
+
+    /// Reads the given subquery columns of the current row of the table cursor into the probe
+    /// registers, where [Self::probe_resolver] finds them.
+    fn emit_read_probe(&self, program: &mut ProgramBuilder, source_columns: &[usize]) {
+        for &column in source_columns {
+            program.emit_column(
+                self.stage.table_cursor,
+                self.stage.key_count + column,
+                self.reg_probe_start + column,
+            );
+        }
+    }
+
+    /// Sets `reg_out` to the first row after `reg_from` whose keys `first_key..first_key + count`
+    /// differ from the registers starting at `reg_compare`, or to `reg_last` + 1.
+    #[allow(clippy::too_many_arguments)]
+    fn emit_find_run_end(
+        &self,
+        program: &mut ProgramBuilder,
+        reg_from: usize,
+        reg_last: usize,
+        first_key: usize,
+        count: usize,
+        reg_compare: usize,
+        reg_out: usize,
+    ) {
+        let label_loop = program.allocate_label();
+        let label_done = program.allocate_label();
+        if reg_from != reg_out {
+            self.emit_copy(program, reg_from, reg_out);
+        }
+        program.preassign_label_to_next_insn(label_loop);
+        program.emit_insn(Insn::Add {
+            lhs: reg_out,
+            rhs: self.reg_one,
+            dest: reg_out,
+        });
+        program.emit_insn(Insn::Gt {
+            lhs: reg_out,
+            rhs: reg_last,
+            target_pc: label_done,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        });
+        self.emit_read_columns(program, reg_out, first_key, count, self.reg_scan_keys);
+        for i in 0..count {
+            program.emit_insn(Insn::Ne {
+                lhs: self.reg_scan_keys + i,
+                rhs: reg_compare + i,
+                target_pc: label_done,
+                flags: CmpInsFlags::default().null_eq(),
+                collation: self.stage.collations[first_key + i],
+            });
+        }
+        program.emit_insn(Insn::Goto {
+            target_pc: label_loop,
+        });
+        program.preassign_label_to_next_insn(label_done);
+    }
+
+    /// Moves `reg_pointer` to the first row of the next peer group, unless it is already past
+    /// the end of the partition.
+    fn emit_advance_group(&self, program: &mut ProgramBuilder, reg_pointer: usize) {
+        let label_done = program.allocate_label();
+        program.emit_insn(Insn::Gt {
+            lhs: reg_pointer,
+            rhs: self.reg_partition_end,
+            target_pc: label_done,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        });
+        self.emit_read_columns(
+            program,
+            reg_pointer,
+            self.partition_key_count(),
+            self.order_key_count(),
+            self.reg_pointer_keys,
+        );
+        self.emit_find_run_end(
+            program,
+            reg_pointer,
+            self.reg_partition_end,
+            self.partition_key_count(),
+            self.order_key_count(),
+            self.reg_pointer_keys,
+            reg_pointer,
+        );
+        program.preassign_label_to_next_insn(label_done);
+    }
+
+    /// Moves `reg_pointer` forward by the number of peer groups in `reg_count`.
+    fn emit_advance_groups(
+        &self,
+        program: &mut ProgramBuilder,
+        reg_pointer: usize,
+        reg_count: usize,
+    ) {
+        let reg_remaining = program.alloc_register();
+        self.emit_copy(program, reg_count, reg_remaining);
+        let label_loop = program.allocate_label();
+        let label_advance = program.allocate_label();
+        let label_done = program.allocate_label();
+        program.preassign_label_to_next_insn(label_loop);
+        program.emit_insn(Insn::IfPos {
+            reg: reg_remaining,
+            target_pc: label_advance,
+            decrement_by: 1,
+        });
+        program.emit_insn(Insn::Goto {
+            target_pc: label_done,
+        });
+        program.preassign_label_to_next_insn(label_advance);
+        self.emit_advance_group(program, reg_pointer);
+        program.emit_insn(Insn::Goto {
+            target_pc: label_loop,
+        });
+        program.preassign_label_to_next_insn(label_done);
+    }
+
+    /// Moves the pointer of a RANGE bound past the rows whose sort key is before the bound in
+    /// [Self::reg_range_bound]. The start pointer stops at the first row in range, the end pointer
+    /// at the first row after the range.
+    fn emit_advance_range(
+        &self,
+        program: &mut ProgramBuilder,
+        reg_pointer: usize,
+        is_start: bool,
+        order: SortOrder,
+    ) {
+        let label_loop = program.allocate_label();
+        let label_advance = program.allocate_label();
+        let label_done = program.allocate_label();
+        let key = self.partition_key_count();
+        let collation = self.stage.collations[key];
+        program.preassign_label_to_next_insn(label_loop);
+        program.emit_insn(Insn::Gt {
+            lhs: reg_pointer,
+            rhs: self.reg_partition_end,
+            target_pc: label_done,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        });
+        self.emit_read_columns(program, reg_pointer, key, 1, self.reg_scan_keys);
+        let (lhs, rhs, target_pc) = (self.reg_scan_keys, self.reg_range_bound, label_advance);
+        // NULLs sort first, so in ascending order they are always before the bound.
+        let ascending_flags = CmpInsFlags::default().jump_if_null();
+        let flags = CmpInsFlags::default();
+        program.emit_insn(match (order, is_start) {
+            (SortOrder::Asc, true) => Insn::Lt {
+                lhs,
+                rhs,
+                target_pc,
+                flags: ascending_flags,
+                collation,
+            },
+            (SortOrder::Asc, false) => Insn::Le {
+                lhs,
+                rhs,
+                target_pc,
+                flags: ascending_flags,
+                collation,
+            },
+            (SortOrder::Desc, true) => Insn::Gt {
+                lhs,
+                rhs,
+                target_pc,
+                flags,
+                collation,
+            },
+            (SortOrder::Desc, false) => Insn::Ge {
+                lhs,
+                rhs,
+                target_pc,
+                flags,
+                collation,
+            },
+        });
+        program.emit_insn(Insn::Goto {
+            target_pc: label_done,
+        });
+        program.preassign_label_to_next_insn(label_advance);
+        program.emit_insn(Insn::Add {
+            lhs: reg_pointer,
+            rhs: self.reg_one,
+            dest: reg_pointer,
+        });
+        program.emit_insn(Insn::Goto {
+            target_pc: label_loop,
+        });
+        program.preassign_label_to_next_insn(label_done);
+    }
+
+    /// Emits a loop over the rows of the frame of the current row, skipping the rows excluded
+    /// by the EXCLUDE clause. `body` is emitted with the table cursor positioned on the row, and
+    /// gets the labels of the next iteration and of the end of the loop.
+    fn emit_frame_loop(
+        &self,
+        program: &mut ProgramBuilder,
+        reverse: bool,
+        body: impl FnOnce(&mut ProgramBuilder, BranchOffset, BranchOffset) -> Result<()>,
+    ) -> Result<()> {
+        let reg_scan = program.alloc_register();
+        let label_loop = program.allocate_label();
+        let label_next = program.allocate_label();
+        let label_end = program.allocate_label();
+        let (first, last) = if reverse {
+            (self.reg_frame_end, self.reg_frame_start)
+        } else {
+            (self.reg_frame_start, self.reg_frame_end)
+        };
+        self.emit_copy(program, first, reg_scan);
+        program.preassign_label_to_next_insn(label_loop);
+        program.emit_insn(if reverse {
+            Insn::Lt {
+                lhs: reg_scan,
+                rhs: last,
+                target_pc: label_end,
+                flags: CmpInsFlags::default(),
+                collation: None,
+            }
+        } else {
+            Insn::Gt {
+                lhs: reg_scan,
+                rhs: last,
+                target_pc: label_end,
+                flags: CmpInsFlags::default(),
+                collation: None,
+            }
+        });
+        self.emit_exclusion_check(program, reg_scan, label_next);
+        self.emit_seek(program, reg_scan);
+        body(program, label_next, label_end)?;
+        program.preassign_label_to_next_insn(label_next);
+        program.emit_insn(if reverse {
+            Insn::Subtract {
+                lhs: reg_scan,
+                rhs: self.reg_one,
+                dest: reg_scan,
+            }
+        } else {
+            Insn::Add {
+                lhs: reg_scan,
+                rhs: self.reg_one,
+                dest: reg_scan,
+            }
+        });
+        program.emit_insn(Insn::Goto {
+            target_pc: label_loop,
+        });
+        program.preassign_label_to_next_insn(label_end);
+        Ok(())
+    }
+
+    /// Jumps to `label_skip` if the row `reg_scan` is excluded from the frame of the current row.
+    fn emit_exclusion_check(
+        &self,
+        program: &mut ProgramBuilder,
+        reg_scan: usize,
+        label_skip: BranchOffset,
+    ) {
+        let exclude = &self.definition.frame.exclude;
+        if *exclude == FrameExclude::NoOthers {
+            return;
+        }
+        if *exclude == FrameExclude::CurrentRow {
+            program.emit_insn(Insn::Eq {
+                lhs: reg_scan,
+                rhs: self.reg_row,
+                target_pc: label_skip,
+                flags: CmpInsFlags::default(),
+                collation: None,
+            });
+            return;
+        }
+        let label_keep = program.allocate_label();
+        if *exclude == FrameExclude::Ties {
+            program.emit_insn(Insn::Eq {
+                lhs: reg_scan,
+                rhs: self.reg_row,
+                target_pc: label_keep,
+                flags: CmpInsFlags::default(),
+                collation: None,
+            });
+        }
+        program.emit_insn(Insn::Lt {
+            lhs: reg_scan,
+            rhs: self.reg_group_start,
+            target_pc: label_keep,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        });
+        program.emit_insn(Insn::Gt {
+            lhs: reg_scan,
+            rhs: self.reg_group_end,
+            target_pc: label_keep,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        });
+        program.emit_insn(Insn::Goto {
+            target_pc: label_skip,
+        });
+        program.preassign_label_to_next_insn(label_keep);
+    }
+
+    /// Stores `CAST(src AS INTEGER)` in `dest`.
+    fn emit_cast_integer(&self, program: &mut ProgramBuilder, src: usize, dest: usize) {
+        let reg_args = program.alloc_registers(2);
+        self.emit_copy(program, src, reg_args);
+        program.emit_string8("INTEGER".to_string(), reg_args + 1);
+        program.emit_insn(Insn::Function {
+            constant_mask: 0,
+            start_reg: reg_args,
+            dest,
+            func: FuncCtx {
+                func: Func::Scalar(ScalarFunc::Cast),
+                arg_count: 2,
+            },
+        });
+    }
+
+    fn emit_numeric_affinity(&self, program: &mut ProgramBuilder, reg: usize) {
+        program.emit_insn(Insn::Affinity {
+            start_reg: reg,
+            count: NonZeroUsize::new(1).unwrap(),
+            affinities: Affinity::Numeric.aff_mask().to_string(),
+        });
+    }
+
+    /// Converts the value in `reg` to an integer, jumping to `label_invalid` if it is not
+    /// one, e.g. 2.0 and '2' become 2 but 2.5 and 'x' jump.
+    fn emit_integer_check(
+        &self,
+        program: &mut ProgramBuilder,
+        reg: usize,
+        label_invalid: BranchOffset,
+    ) {
+        let reg_integer = program.alloc_register();
+        self.emit_numeric_affinity(program, reg);
+        self.emit_cast_integer(program, reg, reg_integer);
+        program.emit_insn(Insn::Ne {
+            lhs: reg,
+            rhs: reg_integer,
+            target_pc: label_invalid,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        });
+        self.emit_copy(program, reg_integer, reg);
+    }
+
+    /// Halts with `description` unless the value in `reg` is a positive integer.
+    fn emit_positive_integer_check(
+        &self,
+        program: &mut ProgramBuilder,
+        reg: usize,
+        description: &str,
+    ) {
+        let label_invalid = program.allocate_label();
+        let label_valid = program.allocate_label();
+        program.emit_insn(Insn::IsNull {
+            reg,
+            target_pc: label_invalid,
+        });
+        self.emit_integer_check(program, reg, label_invalid);
+        program.emit_insn(Insn::Gt {
+            lhs: reg,
+            rhs: self.reg_one,
+            target_pc: label_valid,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        });
+        program.emit_insn(Insn::Eq {
+            lhs: reg,
+            rhs: self.reg_one,
+            target_pc: label_valid,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        });
+        program.preassign_label_to_next_insn(label_invalid);
+        program.emit_insn(Insn::Halt {
+            err_code: SQLITE_ERROR,
+            on_error: ast::ResolveType::Abort,
+            description: description.to_string(),
+        });
+        program.preassign_label_to_next_insn(label_valid);
+    }
+
+    /// Computes the window function `function` for the current row into its result register.
+    fn emit_function(
+        &self,
+        program: &mut ProgramBuilder,
+        function: &WindowFunction,
+        index: usize,
+    ) -> Result<()> {
+        let reg_result = self.reg_results + index;
+        match function {
+            WindowFunction::Aggregate(agg) => {
+                let mut source_columns = vec![];
+                for expr in agg.args.iter().chain(agg.filter.iter()) {
+                    walk_expr(expr, &mut |e: &Expr| -> Result<WalkControl> {
+                        if let Expr::Column { column, .. } = e {
+                            source_columns.push(*column);
+                        }
+                        Ok(WalkControl::Continue)
+                    })?;
+                }
+                let step = |program: &mut ProgramBuilder, reg_acc: usize| -> Result<()> {
+                    self.emit_read_probe(program, &source_columns);
+                    translate_aggregation_step(
+                        program,
+                        self.tables,
+                        agg,
+                        reg_acc,
+                        self.probe_resolver,
+                    )?;
+                    Ok(())
+                };
+                match self.incremental[index] {
+                    Some(IncrementalAggregate {
+                        reg_acc,
+                        reg_last_row,
+                    }) => {
+                        // The frame starts at the start of the partition and its end never moves
+                        // backwards: step the rows that entered the frame since the last row.
+                        let label_loop = program.allocate_label();
+                        let label_done = program.allocate_label();
+                        program.preassign_label_to_next_insn(label_loop);
+                        program.emit_insn(Insn::Ge {
+                            lhs: reg_last_row,
+                            rhs: self.reg_frame_end,
+                            target_pc: label_done,
+                            flags: CmpInsFlags::default(),
+                            collation: None,
+                        });
+                        program.emit_insn(Insn::Add {
+                            lhs: reg_last_row,
+                            rhs: self.reg_one,
+                            dest: reg_last_row,
+                        });
+                        self.emit_seek(program, reg_last_row);
+                        step(program, reg_acc)?;
+                        program.emit_insn(Insn::Goto {
+                            target_pc: label_loop,
+                        });
+                        program.preassign_label_to_next_insn(label_done);
+                        program.emit_insn(Insn::AggValue {
+                            acc_reg: reg_acc,
+                            dest_reg: reg_result,
+                            func: agg.func.clone(),
+                        });
+                    }
+                    None => {
+                        let reg_acc = program.alloc_register();
+                        program.emit_null(reg_acc, None);
+                        self.emit_frame_loop(program, false, |program, _, _| {
+                            step(program, reg_acc)
+                        })?;
+                        program.emit_insn(Insn::AggFinal {
+                            register: reg_acc,
+                            func: agg.func.clone(),
+                        });
+                        self.emit_copy(program, reg_acc, reg_result);
+                    }
+                }
+            }
+            WindowFunction::Builtin { func, args, .. } => {
+                self.emit_builtin(program, *func, args, reg_result)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn emit_builtin(
+        &self,
+        program: &mut ProgramBuilder,
+        func: WindowFunc,
+        args: &[Expr],
+        reg_result: usize,
+    ) -> Result<()> {
+        // Arguments evaluated for the current row are in the carried columns.
+        let carried = |i: usize| self.reg_carry + source_column_index(&args[i]);
+        match func {
+            WindowFunc::RowNumber => {
+                program.emit_insn(Insn::Subtract {
+                    lhs: self.reg_row,
+                    rhs: self.reg_partition_start,
+                    dest: reg_result,
+                });
+                program.emit_insn(Insn::Add {
+                    lhs: reg_result,
+                    rhs: self.reg_one,
+                    dest: reg_result,
+                });
+            }
+            WindowFunc::Rank => {
+                program.emit_insn(Insn::Subtract {
+                    lhs: self.reg_group_start,
+                    rhs: self.reg_partition_start,
+                    dest: reg_result,
+                });
+                program.emit_insn(Insn::Add {
+                    lhs: reg_result,
+                    rhs: self.reg_one,
+                    dest: reg_result,
+                });
+            }
+            WindowFunc::DenseRank => self.emit_copy(program, self.reg_group_number, reg_result),
+            WindowFunc::PercentRank => {
+                // (rank - 1) / (partition rows - 1), or 0.0 for a partition of one row
+                let reg_denominator = program.alloc_register();
+                let label_done = program.allocate_label();
+                program.emit_insn(Insn::Real {
+                    value: 0.0,
+                    dest: reg_result,
+                });
+                program.emit_insn(Insn::Subtract {
+                    lhs: self.reg_partition_end,
+                    rhs: self.reg_partition_start,
+                    dest: reg_denominator,
+                });
+                program.emit_insn(Insn::IfNot {
+                    reg: reg_denominator,
+                    target_pc: label_done,
+                    jump_if_null: true,
+                });
+                program.emit_insn(Insn::Subtract {
+                    lhs: self.reg_group_start,
+                    rhs: self.reg_partition_start,
+                    dest: reg_result,
+                });
+                program.emit_insn(Insn::RealAffinity {
+                    register: reg_result,
+                });
+                program.emit_insn(Insn::Divide {
+                    lhs: reg_result,
+                    rhs: reg_denominator,
+                    dest: reg_result,
+                });
+                program.preassign_label_to_next_insn(label_done);
+            }
+            WindowFunc::CumeDist => {
+                // (rows up to the end of the peer group) / (partition rows)
+                let reg_denominator = program.alloc_register();
+                program.emit_insn(Insn::Subtract {
+                    lhs: self.reg_group_end,
+                    rhs: self.reg_partition_start,
+                    dest: reg_result,
+                });
+                program.emit_insn(Insn::Add {
+                    lhs: reg_result,
+                    rhs: self.reg_one,
+                    dest: reg_result,
+                });
+                program.emit_insn(Insn::RealAffinity {
+                    register: reg_result,
+                });
+                program.emit_insn(Insn::Subtract {
+                    lhs: self.reg_partition_end,
+                    rhs: self.reg_partition_start,
+                    dest: reg_denominator,
+                });
+                program.emit_insn(Insn::Add {
+                    lhs: reg_denominator,
+                    rhs: self.reg_one,
+                    dest: reg_denominator,
+                });
+                program.emit_insn(Insn::Divide {
+                    lhs: reg_result,
+                    rhs: reg_denominator,
+                    dest: reg_result,
+                });
+            }
+            WindowFunc::Ntile => self.emit_ntile(program, carried(0), reg_result),
+            WindowFunc::Lag | WindowFunc::Lead => {
+                self.emit_lag_lead(program, func == WindowFunc::Lag, args, reg_result)
+            }
+            WindowFunc::FirstValue | WindowFunc::LastValue => {
+                let column = source_column_index(&args[0]);
+                program.emit_null(reg_result, None);
+                self.emit_frame_loop(
+                    program,
+                    func == WindowFunc::LastValue,
+                    |program, _, label_end| {
+                        program.emit_column(
+                            self.stage.table_cursor,
+                            self.stage.key_count + column,
+                            reg_result,
+                        );
+                        program.emit_insn(Insn::Goto {
+                            target_pc: label_end,
+                        });
+                        Ok(())
+                    },
+                )?;
+            }
+            WindowFunc::NthValue => {
+                let column = source_column_index(&args[0]);
+                let reg_remaining = program.alloc_register();
+                self.emit_copy(program, carried(1), reg_remaining);
+                self.emit_positive_integer_check(
+                    program,
+                    reg_remaining,
+                    "second argument to nth_value must be a positive integer",
+                );
+                program.emit_insn(Insn::Subtract {
+                    lhs: reg_remaining,
+                    rhs: self.reg_one,
+                    dest: reg_remaining,
+                });
+                program.emit_null(reg_result, None);
+                self.emit_frame_loop(program, false, |program, label_next, label_end| {
+                    program.emit_insn(Insn::IfPos {
+                        reg: reg_remaining,
+                        target_pc: label_next,
+                        decrement_by: 1,
+                    });
+                    program.emit_column(
+                        self.stage.table_cursor,
+                        self.stage.key_count + column,
+                        reg_result,
+                    );
+                    program.emit_insn(Insn::Goto {
+                        target_pc: label_end,
+                    });
+                    Ok(())
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// ntile(N): splits the partition into N buckets whose sizes differ by at most one, the
+    /// larger buckets first.
+    fn emit_ntile(&self, program: &mut ProgramBuilder, reg_arg: usize, reg_result: usize) {
+        let reg_buckets = program.alloc_register();
+        let reg_rows = program.alloc_register();
+        let reg_size = program.alloc_register();
+        let reg_large = program.alloc_register();
+        let reg_small_start = program.alloc_register();
+        let reg_index = program.alloc_register();
+        let reg_tmp = program.alloc_register();
+        let label_done = program.allocate_label();
+        let label_small = program.allocate_label();
+
+        // Like SQLite, the argument is converted to an integer, e.g. ntile(2.5) is ntile(2).
+        self.emit_cast_integer(program, reg_arg, reg_buckets);
+        self.emit_positive_integer_check(
+            program,
+            reg_buckets,
+            "argument of ntile must be a positive integer",
+        );
+        // rows = partition rows, index = 0-based position of the current row
+        program.emit_insn(Insn::Subtract {
+            lhs: self.reg_partition_end,
+            rhs: self.reg_partition_start,
+            dest: reg_rows,
+        });
+        program.emit_insn(Insn::Add {
+            lhs: reg_rows,
+            rhs: self.reg_one,
+            dest: reg_rows,
+        });
+        program.emit_insn(Insn::Subtract {
+            lhs: self.reg_row,
+            rhs: self.reg_partition_start,
+            dest: reg_index,
+        });
+        // size = rows / buckets; with fewer rows than buckets every row is its own bucket
+        program.emit_insn(Insn::Divide {
+            lhs: reg_rows,
+            rhs: reg_buckets,
+            dest: reg_size,
+        });
+        program.emit_insn(Insn::Add {
+            lhs: reg_index,
+            rhs: self.reg_one,
+            dest: reg_result,
+        });
+        program.emit_insn(Insn::IfNot {
+            reg: reg_size,
+            target_pc: label_done,
+            jump_if_null: true,
+        });
+        // large = rows - buckets * size buckets have size + 1 rows, the others have size rows
+        program.emit_insn(Insn::Multiply {
+            lhs: reg_buckets,
+            rhs: reg_size,
+            dest: reg_tmp,
+        });
+        program.emit_insn(Insn::Subtract {
+            lhs: reg_rows,
+            rhs: reg_tmp,
+            dest: reg_large,
+        });
+        program.emit_insn(Insn::Add {
+            lhs: reg_size,
+            rhs: self.reg_one,
+            dest: reg_tmp,
+        });
+        program.emit_insn(Insn::Multiply {
+            lhs: reg_large,
+            rhs: reg_tmp,
+            dest: reg_small_start,
+        });
+        program.emit_insn(Insn::Ge {
+            lhs: reg_index,
+            rhs: reg_small_start,
+            target_pc: label_small,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        });
+        // 1 + index / (size + 1)
+        program.emit_insn(Insn::Divide {
+            lhs: reg_index,
+            rhs: reg_tmp,
+            dest: reg_result,
+        });
+        program.emit_insn(Insn::Add {
+            lhs: reg_result,
+            rhs: self.reg_one,
+            dest: reg_result,
+        });
+        program.emit_insn(Insn::Goto {
+            target_pc: label_done,
+        });
+        // 1 + large + (index - small_start) / size
+        program.preassign_label_to_next_insn(label_small);
+        program.emit_insn(Insn::Subtract {
+            lhs: reg_index,
+            rhs: reg_small_start,
+            dest: reg_tmp,
+        });
+        program.emit_insn(Insn::Divide {
+            lhs: reg_tmp,
+            rhs: reg_size,
+            dest: reg_result,
+        });
+        program.emit_insn(Insn::Add {
+            lhs: reg_result,
+            rhs: reg_large,
+            dest: reg_result,
+        });
+        program.emit_insn(Insn::Add {
+            lhs: reg_result,
+            rhs: self.reg_one,
+            dest: reg_result,
+        });
+        program.preassign_label_to_next_insn(label_done);
+    }
+
+    /// lag(expr, offset, default) and lead(expr, offset, default): `expr` evaluated on the row
+    /// `offset` rows before or after the current row in the partition, or `default`.
+    fn emit_lag_lead(
+        &self,
+        program: &mut ProgramBuilder,
+        is_lag: bool,
+        args: &[Expr],
+        reg_result: usize,
+    ) {
+        let column = source_column_index(&args[0]);
+        let reg_target = program.alloc_register();
+        let label_default = program.allocate_label();
+        let label_done = program.allocate_label();
+        match args.get(1) {
+            Some(offset) => {
+                let reg_offset = self.reg_carry + source_column_index(offset);
+                program.emit_null(reg_result, None);
+                program.emit_insn(Insn::IsNull {
+                    reg: reg_offset,
+                    target_pc: label_done,
+                });
+                self.emit_copy(program, reg_offset, reg_target);
+            }
+            None => program.emit_int(1, reg_target),
+        }
+        program.emit_insn(if is_lag {
+            Insn::Subtract {
+                lhs: self.reg_row,
+                rhs: reg_target,
+                dest: reg_target,
+            }
+        } else {
+            Insn::Add {
+                lhs: self.reg_row,
+                rhs: reg_target,
+                dest: reg_target,
+            }
+        });
+        // A fractional offset does not name a row.
+        let reg_target_int = program.alloc_register();
+        self.emit_cast_integer(program, reg_target, reg_target_int);
+        program.emit_insn(Insn::Ne {
+            lhs: reg_target,
+            rhs: reg_target_int,
+            target_pc: label_default,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        });
+        program.emit_insn(Insn::Lt {
+            lhs: reg_target,
+            rhs: self.reg_partition_start,
+            target_pc: label_default,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        });
+        program.emit_insn(Insn::Gt {
+            lhs: reg_target,
+            rhs: self.reg_partition_end,
+            target_pc: label_default,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        });
+        self.emit_read_columns(
+            program,
+            reg_target,
+            self.stage.key_count + column,
+            1,
+            reg_result,
+        );
+        program.emit_insn(Insn::Goto {
+            target_pc: label_done,
+        });
+        program.preassign_label_to_next_insn(label_default);
+        match args.get(2) {
+            Some(default) => self.emit_copy(
+                program,
+                self.reg_carry + source_column_index(default),
+                reg_result,
+            ),
+            None => program.emit_null(reg_result, None),
+        }
+        program.preassign_label_to_next_insn(label_done);
+    }
+}
@@ -57,6 +57,11 @@ pub fn normalize_ident(identifier: &str) -> String {
     .to_lowercase()
 }
 
+/// Quotes an identifier so it can be embedded in SQL text.
+pub fn quote_ident(identifier: &str) -> String {
+    format!("\"{}\"", identifier.replace('"', "\"\""))
+}
+
 pub const PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX: &str = "sqlite_autoindex_";
 
 /// Unparsed index that comes from a sql query, i.e not an automatic index
@@ -80,12 +85,14 @@ pub fn parse_schema_rows(
     // IO runs
     let mut from_sql_indexes = Vec::with_capacity(10);
     let mut automatic_indices = std::collections::HashMap::with_capacity(10);
+    let mut trigger_sqls = Vec::new();
+    let mut view_sqls = Vec::new();
     loop {
         match rows.step()? {
             StepResult::Row => {
                 let row = rows.row().unwrap();
                 let ty = row.get::<&str>(0)?;
-                if !["table", "index"].contains(&ty) {
+                if !["table", "index", "trigger", "view"].contains(&ty) {
                     continue;
                 }
                 match ty {
@@ -142,6 +149,12 @@ pub fn parse_schema_rows(
                             }
                         }
                     }
+                    "trigger" => {
+                        trigger_sqls.push(row.get::<&str>(4)?.to_string());
+                    }
+                    "view" => {
+                        view_sqls.push(row.get::<&str>(4)?.to_string());
+                    }
                     _ => continue,
                 }
             }
@@ -170,19 +183,12 @@ pub fn parse_schema_rows(
             schema.add_index(Arc::new(index));
         }
     }
-    for automatic_index in automatic_indices {
-        if !schema.indexes_enabled() {
-            schema.table_set_has_index(&automatic_index.0);
-        } else {
-            let table = schema.get_btree_table(&automatic_index.0).unwrap();
-            let ret_index = schema::Index::automatic_from_primary_key_and_unique(
-                table.as_ref(),
-                automatic_index.1,
-            )?;
-            for index in ret_index {
-                schema.add_index(Arc::new(index));
-            }
-        }
+    schema.add_automatic_indices(automatic_indices)?;
+    for sql in trigger_sqls {
+        schema.add_trigger(Arc::new(schema::Trigger::from_sql(&sql)?));
+    }
+    for sql in view_sqls {
+        schema.add_view(Arc::new(schema::View::from_sql(&sql)?));
     }
     Ok(())
 }
@@ -428,13 +434,14 @@ pub fn exprs_are_equivalent(expr1: &Expr, expr2: &Expr) -> bool {
                             filter_clause: fc2,
                             over_clause: oc2,
                         }),
-                    ) => match ((fc1, fc2), (oc1, oc2)) {
-                        ((Some(fc1), Some(fc2)), (Some(oc1), Some(oc2))) => {
-                            exprs_are_equivalent(fc1, fc2) && oc1 == oc2
-                        }
-                        ((Some(fc1), Some(fc2)), _) => exprs_are_equivalent(fc1, fc2),
-                        _ => false,
-                    },
+                    ) => {
+                        oc1 == oc2
+                            && match (fc1, fc2) {
+                                (Some(fc1), Some(fc2)) => exprs_are_equivalent(fc1, fc2),
+                                (None, None) => true,
+                                _ => false,
+                            }
+                    }
                     _ => false,
                 }
         }
@@ -592,6 +599,7 @@ pub fn columns_from_create_table_body(body: &ast::CreateTableBody) -> crate::Res
                     .as_ref()
                     .map(|data_type| data_type.name.as_str().contains("HIDDEN"))
                     .unwrap_or(false),
+                generated: None,
             }
         })
         .collect::<Vec<_>>())
@@ -1072,7 +1080,6 @@ pub fn parse_string(expr: &Expr) -> Result<String> {
     }
 }
 
-#[allow(unused)]
 pub fn parse_pragma_bool(expr: &Expr) -> Result<bool> {
     const TRUE_VALUES: &[&str] = &["yes", "true", "on"];
     const FALSE_VALUES: &[&str] = &["no", "false", "off"];
@@ -1088,6 +1095,11 @@ pub fn parse_pragma_bool(expr: &Expr) -> Result<bool> {
         if FALSE_VALUES.contains(&ident.as_str()) {
             return Ok(false);
         }
+    } else if let Expr::Literal(Literal::Keyword(keyword)) = expr {
+        // ON is a keyword, not a name
+        if keyword.eq_ignore_ascii_case("on") {
+            return Ok(true);
+        }
     }
     Err(LimboError::InvalidArgument(
         "boolean pragma value must be either 0|1 integer or yes|true|on|no|false|off token"
@@ -2060,6 +2072,7 @@ pub mod tests {
         assert!(parse_pragma_bool(&Expr::Name(Name::Ident("true".into()))).unwrap(),);
         assert!(parse_pragma_bool(&Expr::Name(Name::Ident("on".into()))).unwrap(),);
         assert!(parse_pragma_bool(&Expr::Name(Name::Ident("yes".into()))).unwrap(),);
+        assert!(parse_pragma_bool(&Expr::Literal(Literal::Keyword("ON".into()))).unwrap(),);
 
         assert!(!parse_pragma_bool(&Expr::Literal(Literal::Numeric("0".into()))).unwrap(),);
         assert!(!parse_pragma_bool(&Expr::Name(Name::Ident("false".into()))).unwrap(),);
@@ -0,0 +1,269 @@
+//! VACUUM rebuilds the database by copying its schema and contents into a fresh database, which
+//! leaves every b-tree packed and the freelist empty. A plain VACUUM builds the copy in memory and
+//! writes its pages back over the original; VACUUM INTO writes the copy to a new file instead.
+
+use crate::schema::Schema;
+use crate::storage::database::FileMemoryStorage;
+use crate::storage::header_accessor;
+use crate::storage::pager::AutoVacuumMode;
+use crate::storage::sqlite3_ondisk::DATABASE_HEADER_SIZE;
+use crate::util::{parse_schema_rows, quote_ident, MEMORY_PATH};
+use crate::vdbe::StepResult;
+use crate::{
+    Connection, Database, LimboError, MemoryIO, OpenFlags, Pager, Result, Statement,
+    TransactionState, Value, IO,
+};
+use std::num::NonZero;
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// A row of `sqlite_schema`.
+struct SchemaEntry {
+    ty: String,
+    name: String,
+    tbl_name: String,
+    root_page: i64,
+    sql: Option<String>,
+}
+
+impl Connection {
+    /// Rebuilds the database in place. Must run in the write transaction of the VACUUM statement.
+    pub(crate) fn vacuum(self: &Arc<Connection>, pager: &Rc<Pager>) -> Result<()> {
+        if !matches!(pager.get_auto_vacuum_mode(), AutoVacuumMode::None) {
+            return Err(LimboError::InternalError(
+                "VACUUM is not supported with auto_vacuum".to_string(),
+            ));
+        }
+        let indexes_enabled = self.schema.borrow().indexes_enabled();
+        let io: Arc<dyn IO> = Arc::new(MemoryIO::new());
+        let file = io.open_file(MEMORY_PATH, OpenFlags::Create, false)?;
+        let db = Database::open(
+            io,
+            MEMORY_PATH,
+            Arc::new(FileMemoryStorage::new(file)),
+            false,
+            indexes_enabled,
+        )?;
+        let vacuum_conn = db.connect()?;
+        vacuum_conn.reset_page_size(header_accessor::get_page_size(pager)?)?;
+        self.copy_database(&vacuum_conn)?;
+
+        let vacuum_pager = vacuum_conn.pager.borrow().clone();
+        let old_db_size = header_accessor::get_database_size(pager)?;
+        let db_size = header_accessor::get_database_size(&vacuum_pager)?;
+        for page_idx in 1..=db_size as usize {
+            let src = read_page_blocking(&vacuum_pager, page_idx)?;
+            let dest = if page_idx <= old_db_size as usize {
+                let page = read_page_blocking(pager, page_idx)?;
+                pager.add_dirty(&page);
+                page
+            } else {
+                pager.allocate_page()?
+            };
+            // The header of page 1 is kept, except for the fields updated below.
+            let start = if page_idx == 1 {
+                DATABASE_HEADER_SIZE
+            } else {
+                0
+            };
+            let src_contents = src.get_contents();
+            let dest_contents = dest.get_contents();
+            dest_contents.as_ptr()[start..].copy_from_slice(&src_contents.as_ptr()[start..]);
+            dest_contents.overflow_cells.clear();
+        }
+        header_accessor::set_database_size(pager, db_size)?;
+        header_accessor::set_freelist_trunk_page(
+            pager,
+            header_accessor::get_freelist_trunk_page(&vacuum_pager)?,
+        )?;
+        header_accessor::set_freelist_pages(
+            pager,
+            header_accessor::get_freelist_pages(&vacuum_pager)?,
+        )?;
+        let cookie = header_accessor::get_schema_cookie(pager)? + 1;
+        header_accessor::set_schema_cookie(pager, cookie)?;
+        for page_idx in db_size + 1..=old_db_size {
+            pager.cache_delete(page_idx as usize)?;
+        }
+
+        // Every root page may have moved, so the schema is parsed again from the copied pages.
+        let previous_auto_commit = self.auto_commit.replace(false);
+        let result = self
+            .prepare("SELECT * FROM main.sqlite_schema")
+            .and_then(|stmt| {
+                let mut schema = Schema::new(indexes_enabled);
+                parse_schema_rows(stmt, &mut schema, &self.syms.borrow(), None)?;
+                schema.schema_version = cookie;
+                self.schema.replace(Arc::new(schema));
+                Ok(())
+            });
+        self.auto_commit.set(previous_auto_commit);
+        result?;
+        self.transaction_state.set(TransactionState::Write {
+            schema_did_change: true,
+        });
+        Ok(())
+    }
+
+    /// Writes a rebuilt copy of the database to a new file at `path`. Runs in the read
+    /// transaction of the VACUUM INTO statement, so the copy is a consistent snapshot.
+    #[cfg(feature = "fs")]
+    pub(crate) fn vacuum_into(self: &Arc<Connection>, path: &str) -> Result<()> {
+        if std::fs::metadata(path).is_ok_and(|metadata| metadata.len() > 0) {
+            return Err(LimboError::InvalidArgument(
+                "output file already exists".to_string(),
+            ));
+        }
+        let pager = self.pager.borrow().clone();
+        let indexes_enabled = self.schema.borrow().indexes_enabled();
+        let (_io, db) =
+            Database::open_new::<&str>(path, None, OpenFlags::default(), indexes_enabled, false)?;
+        let dest = db.connect()?;
+        dest.reset_page_size(header_accessor::get_page_size(&pager)?)?;
+        self.copy_database(&dest)?;
+        // Move every frame into the database file, so that the copy does not need its WAL.
+        let checkpoint = dest.checkpoint()?;
+        dest.close()?;
+        if checkpoint.num_checkpointed_frames == checkpoint.num_wal_frames {
+            let _ = std::fs::remove_file(format!("{path}-wal"));
+        }
+        Ok(())
+    }
+
+    #[cfg(not(feature = "fs"))]
+    pub(crate) fn vacuum_into(self: &Arc<Connection>, _path: &str) -> Result<()> {
+        Err(LimboError::InvalidArgument(
+            "VACUUM INTO not available in this build (no-fs)".to_string(),
+        ))
+    }
+
+    /// Copies the schema and rows of this database into the empty database of `dest`.
+    fn copy_database(self: &Arc<Connection>, dest: &Arc<Connection>) -> Result<()> {
+        // The rows are read with nested statements on this connection, which must not end the
+        // transaction of the VACUUM statement.
+        let previous_auto_commit = self.auto_commit.replace(false);
+        let result = self.copy_database_inner(dest);
+        self.auto_commit.set(previous_auto_commit);
+        result
+    }
+
+    /// Every statement names `main` explicitly, as the temp tables of the connection would
+    /// otherwise shadow the tables of the same name.
+    fn copy_database_inner(self: &Arc<Connection>, dest: &Arc<Connection>) -> Result<()> {
+        let mut entries = Vec::new();
+        let mut stmt =
+            self.prepare("SELECT type, name, tbl_name, rootpage, sql FROM main.sqlite_schema")?;
+        while let Some(row) = step_row(&mut stmt)? {
+            entries.push(SchemaEntry {
+                ty: row[0].to_string(),
+                name: row[1].to_string(),
+                tbl_name: row[2].to_string(),
+                root_page: match row[3] {
+                    Value::Integer(root_page) => root_page,
+                    _ => 0,
+                },
+                sql: match &row[4] {
+                    Value::Text(sql) => Some(sql.as_str().to_string()),
+                    _ => None,
+                },
+            });
+        }
+
+        dest.execute("BEGIN")?;
+        for entry in entries
+            .iter()
+            .filter(|entry| entry.ty == "table" && entry.root_page != 0)
+        {
+            let Some(sql) = &entry.sql else {
+                continue;
+            };
+            dest.execute(sql)?;
+            let Some(table) = self.schema.borrow().get_btree_table(&entry.name) else {
+                continue;
+            };
+            let mut columns = Vec::new();
+            if table.has_rowid && table.get_rowid_alias_column().is_none() {
+                columns.push("rowid".to_string());
+            }
+            columns.extend(
+                table
+                    .columns
+                    .iter()
+                    // Generated columns cannot be inserted into; STORED ones are recomputed.
+                    .filter(|column| !column.is_generated())
+                    .filter_map(|column| column.name.as_deref())
+                    .map(quote_ident),
+            );
+            let placeholders = (1..=columns.len())
+                .map(|i| format!("?{i}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let columns = columns.join(", ");
+            let table_name = quote_ident(&entry.name);
+            let mut select = self.prepare(format!("SELECT {columns} FROM main.{table_name}"))?;
+            let mut insert = dest.prepare(format!(
+                "INSERT INTO main.{table_name} ({columns}) VALUES ({placeholders})"
+            ))?;
+            while let Some(row) = step_row(&mut select)? {
+                for (i, value) in row.into_iter().enumerate() {
+                    insert.bind_at(NonZero::new(i + 1).unwrap(), value);
+                }
+                while step_row(&mut insert)?.is_some() {}
+                insert.reset();
+            }
+        }
+        for entry in entries.iter().filter(|entry| entry.ty == "index") {
+            if let Some(sql) = &entry.sql {
+                dest.execute(sql)?;
+            }
+        }
+        // Views, triggers and virtual tables have no b-tree, so their rows are copied as is.
+        let mut insert =
+            dest.prepare("INSERT INTO main.sqlite_schema VALUES (?1, ?2, ?3, 0, ?4)")?;
+        for entry in entries.iter().filter(|entry| entry.root_page == 0) {
+            insert.bind_at(NonZero::new(1).unwrap(), Value::build_text(&entry.ty));
+            insert.bind_at(NonZero::new(2).unwrap(), Value::build_text(&entry.name));
+            insert.bind_at(NonZero::new(3).unwrap(), Value::build_text(&entry.tbl_name));
+            let sql = entry.sql.as_deref().map_or(Value::Null, Value::build_text);
+            insert.bind_at(NonZero::new(4).unwrap(), sql);
+            while step_row(&mut insert)?.is_some() {}
+            insert.reset();
+        }
+        // Setting the header fields creates page 1 if there was nothing else to copy.
+        let pager = self.pager.borrow().clone();
+        dest.pragma_update("user_version", header_accessor::get_user_version(&pager)?)?;
+        dest.pragma_update(
+            "application_id",
+            header_accessor::get_application_id(&pager)?,
+        )?;
+        dest.execute("COMMIT")
+    }
+}
+
+/// Steps `stmt` to its next row, running its I/O to completion.
+fn step_row(stmt: &mut Statement) -> Result<Option<Vec<Value>>> {
+    loop {
+        match stmt.step()? {
+            StepResult::Row => {
+                let row = stmt.row().unwrap();
+                return Ok(Some(row.get_values().cloned().collect()));
+            }
+            StepResult::IO => stmt.run_once()?,
+            StepResult::Done => return Ok(None),
+            StepResult::Busy => return Err(LimboError::Busy),
+            StepResult::Interrupt => {
+                return Err(LimboError::InternalError(
+                    "VACUUM was interrupted".to_string(),
+                ))
+            }
+        }
+    }
+}
+
+fn read_page_blocking(pager: &Pager, page_idx: usize) -> Result<crate::PageRef> {
+    let page = pager.read_page(page_idx)?;
+    while page.is_locked() {
+        pager.io.run_once()?;
+    }
+    Ok(page)
+}
@@ -1,4 +1,10 @@
-use std::{cell::Cell, cmp::Ordering, rc::Rc, sync::Arc};
+use std::{
+    cell::{Cell, OnceCell},
+    cmp::Ordering,
+    collections::BTreeSet,
+    rc::{Rc, Weak},
+    sync::Arc,
+};
 
 use tracing::{instrument, Level};
 use turso_sqlite3_parser::ast::{self, TableInternalId};
@@ -34,7 +40,10 @@ impl TableRefIdCounter {
     }
 }
 
-use super::{BranchOffset, CursorID, Insn, InsnFunction, InsnReference, JumpTarget, Program};
+use super::{
+    insn::SubProgram, BranchOffset, CursorID, Insn, InsnFunction, InsnReference, JumpTarget,
+    Program,
+};
 
 /// A key that uniquely identifies a cursor.
 /// The key is a pair of table reference id and index.
@@ -111,6 +120,10 @@ pub struct ProgramBuilder {
     init_label: BranchOffset,
     start_offset: BranchOffset,
     capture_data_changes_mode: CaptureDataChangesMode,
+    /// Names of the triggers whose programs are being built, outermost first, with the
+    /// cell their program is stored in once built.
+    /// Empty unless this builder emits a trigger sub-program.
+    trigger_stack: Vec<(String, Rc<OnceCell<Weak<Program>>>)>,
 }
 
 #[derive(Debug, Clone)]
@@ -178,9 +191,52 @@ impl ProgramBuilder {
             init_label: BranchOffset::Placeholder,
             start_offset: BranchOffset::Placeholder,
             capture_data_changes_mode,
+            trigger_stack: Vec::new(),
         }
     }
 
+    /// Create a builder for the sub-program of trigger `trigger_name`, fired by the
+    /// statement this builder is emitting.
+    pub fn new_trigger_program(&self, trigger_name: &str) -> Self {
+        let mut program = Self::new(
+            QueryMode::Normal,
+            self.capture_data_changes_mode.clone(),
+            ProgramBuilderOpts {
+                num_cursors: 1,
+                approx_num_insns: 20,
+                approx_num_labels: 4,
+            },
+        );
+        program.trigger_stack = self.trigger_stack.clone();
+        program
+            .trigger_stack
+            .push((trigger_name.to_string(), Rc::new(OnceCell::new())));
+        program
+    }
+
+    /// Whether this builder emits a trigger sub-program.
+    pub fn is_trigger_program(&self) -> bool {
+        !self.trigger_stack.is_empty()
+    }
+
+    /// Whether the program of trigger `trigger_name` is already being built, either by this
+    /// builder or by one of the builders of the statements that fired it.
+    /// Triggers never fire recursively (`PRAGMA recursive_triggers` is always off).
+    pub fn is_trigger_active(&self, trigger_name: &str) -> bool {
+        self.trigger_stack
+            .iter()
+            .any(|(name, _)| name == trigger_name)
+    }
+
+    /// A reference to the program of trigger `trigger_name` if it is already being built,
+    /// for sub-programs that may fire themselves recursively.
+    pub fn active_sub_program(&self, trigger_name: &str) -> Option<SubProgram> {
+        self.trigger_stack
+            .iter()
+            .find(|(name, _)| name == trigger_name)
+            .map(|(_, program)| SubProgram::Recursive(program.clone()))
+    }
+
     pub fn capture_data_changes_mode(&self) -> &CaptureDataChangesMode {
         &self.capture_data_changes_mode
     }
@@ -282,6 +338,19 @@ impl ProgramBuilder {
         self._alloc_cursor_id(None, cursor_type)
     }
 
+    /// Returns the ID the next allocated cursor gets.
+    pub fn next_cursor_id(&self) -> CursorID {
+        self.next_free_cursor_id
+    }
+
+    /// Forgets the keys of the cursors allocated from `first_cursor_id` on, so that the same
+    /// table references can be opened again, e.g. by another evaluation of the same subquery.
+    pub fn forget_cursor_keys(&mut self, first_cursor_id: CursorID) {
+        for (key, _) in self.cursor_ref.iter_mut().skip(first_cursor_id) {
+            *key = None;
+        }
+    }
+
     fn _alloc_cursor_id(&mut self, key: Option<CursorKey>, cursor_type: CursorType) -> usize {
         let cursor = self.next_free_cursor_id;
         self.next_free_cursor_id += 1;
@@ -347,6 +416,7 @@ impl ProgramBuilder {
     fn emit_halt(&mut self, rollback: bool) {
         self.emit_insn(Insn::Halt {
             err_code: 0,
+            on_error: ast::ResolveType::Abort,
             description: if rollback {
                 "rollback".to_string()
             } else {
@@ -362,6 +432,7 @@ impl ProgramBuilder {
     pub fn emit_halt_err(&mut self, err_code: usize, description: String) {
         self.emit_insn(Insn::Halt {
             err_code,
+            on_error: ast::ResolveType::Abort,
             description,
         });
     }
@@ -643,6 +714,14 @@ impl ProgramBuilder {
                 Insn::Gosub { target_pc, .. } => {
                     resolve(target_pc, "Gosub");
                 }
+                Insn::Program {
+                    ignore_jump_target, ..
+                } => {
+                    resolve(ignore_jump_target, "Program");
+                }
+                Insn::FkIfZero { target_pc, .. } => {
+                    resolve(target_pc, "FkIfZero");
+                }
                 Insn::Jump {
                     target_pc_eq,
                     target_pc_lt,
@@ -694,6 +773,9 @@ impl ProgramBuilder {
                 Insn::NotFound { target_pc, .. } => {
                     resolve(target_pc, "NotFound");
                 }
+                Insn::StatNextSample { pc_if_done, .. } => {
+                    resolve(pc_if_done, "StatNextSample");
+                }
                 _ => {}
             }
         }
@@ -767,13 +849,16 @@ impl ProgramBuilder {
             self.emit_halt(rollback);
             self.preassign_label_to_next_insn(self.init_label);
 
-            match txn_mode {
-                TransactionMode::Read => self.emit_insn(Insn::Transaction {
-                    db: 0,
-                    write: false,
-                }),
-                TransactionMode::Write => self.emit_insn(Insn::Transaction { db: 0, write: true }),
-                TransactionMode::None => {}
+            let write = match txn_mode {
+                TransactionMode::Read => Some(false),
+                TransactionMode::Write => Some(true),
+                TransactionMode::None => None,
+            };
+            if let Some(write) = write {
+                // Like in SQLite, every database the program uses gets a transaction
+                for db in self.databases_used() {
+                    self.emit_insn(Insn::Transaction { db, write });
+                }
             }
 
             self.emit_constant_insns();
@@ -783,6 +868,28 @@ impl ProgramBuilder {
         }
     }
 
+    /// Returns the databases the instructions emitted so far use, main first.
+    fn databases_used(&self) -> BTreeSet<usize> {
+        let mut databases = BTreeSet::from([0]);
+        for (insn, ..) in &self.insns {
+            match insn {
+                Insn::OpenRead { db, .. }
+                | Insn::OpenWrite { db, .. }
+                | Insn::CreateBtree { db, .. }
+                | Insn::Destroy { db, .. }
+                | Insn::PageCount { db, .. }
+                | Insn::ReadCookie { db, .. }
+                | Insn::SetCookie { db, .. }
+                | Insn::ParseSchema { db, .. }
+                | Insn::LoadAnalysis { db } => {
+                    databases.insert(*db);
+                }
+                _ => {}
+            }
+        }
+        databases
+    }
+
     /// Checks whether `table` or any of its indices has been opened in the program
     pub fn is_table_open(&self, table: &Table) -> bool {
         self.table_references.contains_table(table)
@@ -820,6 +927,27 @@ impl ProgramBuilder {
         self.preassign_label_to_next_insn(loop_end);
     }
 
+    /// Reads the column at `pos_in_table` of the row a table cursor is positioned on. The cursor
+    /// of a table without rowid is a cursor on its clustered index, which stores the primary key
+    /// columns first. VIRTUAL generated columns are not stored in the record of the row and read
+    /// as NULL: the callers that need their value compute them, see [crate::translate::generated].
+    pub fn emit_table_column(&mut self, cursor_id: CursorID, pos_in_table: usize, out: usize) {
+        let column = match &self.cursor_ref[cursor_id].1 {
+            CursorType::BTreeIndex(index) => index
+                .column_table_pos_to_index_pos(pos_in_table)
+                .expect("clustered index must store all the columns of the table"),
+            CursorType::BTreeTable(table) => match table.column_storage_position(pos_in_table) {
+                Some(column) => column,
+                None => {
+                    self.emit_null(out, None);
+                    return;
+                }
+            },
+            _ => pos_in_table,
+        };
+        self.emit_column(cursor_id, column, out);
+    }
+
     pub fn emit_column(&mut self, cursor_id: CursorID, column: usize, out: usize) {
         let (_, cursor_type) = self.cursor_ref.get(cursor_id).unwrap();
 
@@ -827,7 +955,18 @@ impl ProgramBuilder {
 
         let default = 'value: {
             let default = match cursor_type {
-                CursorType::BTreeTable(btree) => &btree.columns[column].default,
+                // The record of a row does not store the VIRTUAL columns of the table
+                CursorType::BTreeTable(btree) => {
+                    match btree
+                        .columns
+                        .iter()
+                        .filter(|col| !col.is_virtual())
+                        .nth(column)
+                    {
+                        Some(col) => &col.default,
+                        None => break 'value None,
+                    }
+                }
                 CursorType::BTreeIndex(index) => &index.columns[column].default,
                 _ => break 'value None,
             };
@@ -868,6 +1007,19 @@ impl ProgramBuilder {
         });
     }
 
+    /// Build a trigger sub-program, making it available to the [SubProgram::Recursive]
+    /// references emitted while it was built.
+    pub fn build_sub_program(self, connection: Arc<Connection>) -> Rc<Program> {
+        let cell = self
+            .trigger_stack
+            .last()
+            .map(|(_, program)| program.clone())
+            .expect("not a trigger program");
+        let program = Rc::new(self.build(connection, false));
+        let _ = cell.set(Rc::downgrade(&program));
+        program
+    }
+
     pub fn build(mut self, connection: Arc<Connection>, change_cnt_on: bool) -> Program {
         self.resolve_labels();
 
@@ -8,16 +8,20 @@ use crate::storage::pager::{AtomicDbState, CreateBTreeFlags, DbState};
 use crate::storage::sqlite3_ondisk::read_varint;
 use crate::storage::wal::DummyWAL;
 use crate::storage::{self, header_accessor};
+use crate::translate::alter::{rename_select_table, rename_trigger_column, rename_trigger_table};
 use crate::translate::collate::CollationSeq;
+use crate::translate::expr::walk_expr_mut;
 use crate::types::{
-    compare_immutable, compare_records_generic, ImmutableRecord, SeekResult, Text, TextSubtype,
+    compare_immutable, compare_records_generic, ImmutableRecord, IndexInfo, SeekResult, Text,
+    TextSubtype,
 };
 use crate::util::normalize_ident;
 use crate::vdbe::insn::InsertFlags;
 use crate::vdbe::registers_to_ref_values;
 use crate::{
     error::{
-        LimboError, SQLITE_CONSTRAINT, SQLITE_CONSTRAINT_NOTNULL, SQLITE_CONSTRAINT_PRIMARYKEY,
+        LimboError, SQLITE_CONSTRAINT, SQLITE_CONSTRAINT_CHECK, SQLITE_CONSTRAINT_NOTNULL,
+        SQLITE_CONSTRAINT_PRIMARYKEY, SQLITE_CONSTRAINT_TRIGGER, SQLITE_ERROR, SQLITE_IGNORE,
     },
     ext::ExtValue,
     function::{AggFunc, ExtFunc, MathFunc, MathFuncArity, ScalarFunc, VectorFunc},
@@ -32,11 +36,12 @@ use crate::{
 use std::ops::DerefMut;
 use std::{
     borrow::BorrowMut,
+    num::NonZero,
     rc::Rc,
     sync::{Arc, Mutex},
 };
 
-use crate::{pseudo::PseudoCursor, result::LimboResult};
+use crate::{analyze::StatAccum, pseudo::PseudoCursor, result::LimboResult};
 
 use crate::{
     schema::{affinity, Affinity},
@@ -60,17 +65,20 @@ use crate::{
     vector::{vector32, vector64, vector_distance_cos, vector_distance_l2, vector_extract},
 };
 
-use crate::{info, BufferPool, MvCursor, OpenFlags, RefValue, Row, StepResult, TransactionState};
+use crate::{
+    info, BufferPool, MvCursor, MvIndexCursor, OpenFlags, RefValue, Row, StepResult,
+    TransactionState,
+};
 
 use super::{
-    insn::{Cookie, RegisterOrLiteral},
+    insn::{Cookie, RegisterOrLiteral, SavepointOp},
     CommitState,
 };
 use fallible_iterator::FallibleIterator;
 use parking_lot::RwLock;
 use rand::{thread_rng, Rng};
-use turso_sqlite3_parser::ast;
 use turso_sqlite3_parser::ast::fmt::ToTokens;
+use turso_sqlite3_parser::ast::{self, ResolveType};
 use turso_sqlite3_parser::lexer::sql::Parser;
 
 use super::{
@@ -220,12 +228,29 @@ pub fn op_drop_index(
     pager: &Rc<Pager>,
     mv_store: Option<&Rc<MvStore>>,
 ) -> Result<InsnFunctionStepResult> {
-    let Insn::DropIndex { index, db: _ } = insn else {
+    let Insn::DropIndex { index, db } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    program
+        .connection
+        .with_database_schema_mut(*db, |schema| schema.remove_index(index));
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_drop_trigger(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::DropTrigger { trigger_name, db } = insn else {
         unreachable!("unexpected Insn {:?}", insn)
     };
     program
         .connection
-        .with_schema_mut(|schema| schema.remove_index(index));
+        .with_database_schema_mut(*db, |schema| schema.remove_trigger(trigger_name));
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
 }
@@ -891,17 +916,6 @@ pub fn op_open_read(
     let pager = program.get_pager_from_database_index(db);
 
     let (_, cursor_type) = program.cursor_ref.get(*cursor_id).unwrap();
-    let mv_cursor = match state.mv_tx_id {
-        Some(tx_id) => {
-            let table_id = *root_page as u64;
-            let mv_store = mv_store.unwrap().clone();
-            let mv_cursor = Rc::new(RefCell::new(
-                MvCursor::new(mv_store.clone(), tx_id, table_id).unwrap(),
-            ));
-            Some(mv_cursor)
-        }
-        None => None,
-    };
     let mut cursors = state.cursors.borrow_mut();
     let num_columns = match cursor_type {
         CursorType::BTreeTable(table_rc) => table_rc.columns.len(),
@@ -911,6 +925,17 @@ pub fn op_open_read(
 
     match cursor_type {
         CursorType::BTreeTable(_) => {
+            let mv_cursor = match state.mv_tx_id {
+                Some(tx_id) => {
+                    let table_id = *root_page as u64;
+                    let mv_store = mv_store.unwrap().clone();
+                    let mv_cursor = Rc::new(RefCell::new(
+                        MvCursor::new(mv_store.clone(), tx_id, table_id).unwrap(),
+                    ));
+                    Some(mv_cursor)
+                }
+                None => None,
+            };
             let cursor = BTreeCursor::new_table(mv_cursor, pager.clone(), *root_page, num_columns);
             cursors
                 .get_mut(*cursor_id)
@@ -918,6 +943,20 @@ pub fn op_open_read(
                 .replace(Cursor::new_btree(cursor));
         }
         CursorType::BTreeIndex(index) => {
+            let mv_cursor = match state.mv_tx_id {
+                Some(tx_id) => {
+                    let mv_store = mv_store.unwrap().clone();
+                    let mv_cursor = Rc::new(RefCell::new(MvIndexCursor::new(
+                        mv_store,
+                        tx_id,
+                        *root_page as u64,
+                        Arc::new(IndexInfo::new_from_index(index)),
+                        None,
+                    )?));
+                    Some(mv_cursor)
+                }
+                None => None,
+            };
             let cursor = BTreeCursor::new_index(
                 mv_cursor,
                 pager.clone(),
@@ -1811,11 +1850,11 @@ pub fn halt(
     pager: &Rc<Pager>,
     mv_store: Option<&Rc<MvStore>>,
     err_code: usize,
+    on_error: ResolveType,
     description: &str,
 ) -> Result<InsnFunctionStepResult> {
     if err_code > 0 {
-        // invalidate page cache in case of error
-        pager.clear_page_cache();
+        state.on_error = on_error;
     }
     match err_code {
         0 => {}
@@ -1829,6 +1868,11 @@ pub fn halt(
                 "NOT NULL constraint failed: {description} (19)"
             )));
         }
+        SQLITE_CONSTRAINT_CHECK => {
+            return Err(LimboError::Constraint(format!(
+                "CHECK constraint failed: {description} (19)"
+            )));
+        }
         _ => {
             return Err(LimboError::Constraint(format!(
                 "undocumented halt error code {description}"
@@ -1853,14 +1897,19 @@ pub fn op_halt(
 ) -> Result<InsnFunctionStepResult> {
     let Insn::Halt {
         err_code,
+        on_error,
         description,
     } = insn
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
+    if *err_code == SQLITE_IGNORE {
+        // RAISE(IGNORE): stop the trigger program, the statement that fired it skips the row.
+        state.halted_by_ignore = true;
+        return Ok(InsnFunctionStepResult::Done);
+    }
     if *err_code > 0 {
-        // invalidate page cache in case of error
-        pager.clear_page_cache();
+        state.on_error = *on_error;
     }
     match *err_code {
         0 => {}
@@ -1874,13 +1923,42 @@ pub fn op_halt(
                 "NOTNULL constraint failed: {description} (19)"
             )));
         }
+        SQLITE_CONSTRAINT_CHECK => {
+            return Err(LimboError::Constraint(format!(
+                "CHECK constraint failed: {description} (19)"
+            )));
+        }
+        SQLITE_CONSTRAINT_TRIGGER => {
+            return Err(LimboError::Constraint(format!("{description} (19)")));
+        }
+        SQLITE_ERROR => {
+            return Err(LimboError::Constraint(description.to_string()));
+        }
         _ => {
             return Err(LimboError::Constraint(format!(
                 "undocumented halt error code {description}"
             )));
         }
     }
+    if state.is_subprogram {
+        // A trigger program returns control to the statement that fired it, which
+        // owns the transaction.
+        return Ok(InsnFunctionStepResult::Done);
+    }
     let auto_commit = program.connection.auto_commit.get();
+    if state.fk_immediate_violations > 0
+        || (auto_commit && program.connection.fk_deferred_violations.get() > 0)
+    {
+        state.on_error = ResolveType::Abort;
+        return Err(LimboError::Constraint(
+            "FOREIGN KEY constraint failed (19)".to_string(),
+        ));
+    }
+    if let Some(depth) = state.statement_savepoint.take() {
+        for pager in program.connection.savepoint_pagers() {
+            pager.release_savepoint(depth);
+        }
+    }
     tracing::trace!("op_halt(auto_commit={})", auto_commit);
     if auto_commit {
         match program.commit_txn(pager.clone(), state, mv_store, false)? {
@@ -1905,13 +1983,22 @@ pub fn op_halt_if_null(
     let Insn::HaltIfNull {
         target_reg,
         err_code,
+        on_error,
         description,
     } = insn
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
     if state.registers[*target_reg].get_owned_value() == &Value::Null {
-        halt(program, state, pager, mv_store, *err_code, description)
+        halt(
+            program,
+            state,
+            pager,
+            mv_store,
+            *err_code,
+            *on_error,
+            description,
+        )
     } else {
         state.pc += 1;
         Ok(InsnFunctionStepResult::Step)
@@ -1929,6 +2016,18 @@ pub fn op_transaction(
         unreachable!("unexpected Insn {:?}", insn)
     };
     let conn = program.connection.clone();
+    if *db != 0 {
+        match conn.begin_database_transaction(*db, *write)? {
+            IOResult::Done(LimboResult::Busy) => return Ok(InsnFunctionStepResult::Busy),
+            IOResult::IO => return Ok(InsnFunctionStepResult::IO),
+            IOResult::Done(LimboResult::Ok) => {}
+        }
+        if *write && !conn.auto_commit.get() && !state.is_subprogram {
+            open_statement_savepoint(&conn, state);
+        }
+        state.pc += 1;
+        return Ok(InsnFunctionStepResult::Step);
+    }
     if *write && conn._db.open_flags.contains(OpenFlags::ReadOnly) {
         return Err(LimboError::ReadOnly);
     }
@@ -1939,7 +2038,7 @@ pub fn op_transaction(
         if state.mv_tx_id.is_none() {
             // We allocate the first page lazily in the first transaction.
             return_if_io!(pager.maybe_allocate_page1());
-            let tx_id = mv_store.begin_tx();
+            let tx_id = mv_store.begin_tx_with_isolation(conn.get_isolation_level());
             conn.mv_transactions.borrow_mut().push(tx_id);
             state.mv_tx_id = Some(tx_id);
         }
@@ -1991,11 +2090,30 @@ pub fn op_transaction(
         if updated {
             conn.transaction_state.replace(new_transaction_state);
         }
+        if *write && !conn.auto_commit.get() && !state.is_subprogram {
+            open_statement_savepoint(&conn, state);
+        }
     }
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
 }
 
+/// Opens the savepoint of a statement of an explicit transaction, so that a failing statement
+/// only undoes its own changes. It is opened on every database the transaction writes, as the
+/// statement may write any of them.
+fn open_statement_savepoint(conn: &Connection, state: &mut ProgramState) {
+    let depth = conn.savepoints.borrow().len();
+    for pager in conn.savepoint_pagers() {
+        // Drop the savepoint of a statement that was reset before it halted first.
+        pager.release_savepoint(depth);
+        pager.open_savepoint();
+    }
+    if state.statement_savepoint.is_none() {
+        state.statement_savepoint = Some(depth);
+        state.fk_deferred_violations_start = conn.fk_deferred_violations.get();
+    }
+}
+
 pub fn op_auto_commit(
     program: &Program,
     state: &mut ProgramState,
@@ -2028,6 +2146,16 @@ pub fn op_auto_commit(
         };
 
     if *auto_commit != conn.auto_commit.get() {
+        if *auto_commit && !*rollback && conn.fk_deferred_violations.get() > 0 {
+            // The transaction stays open, so that the violations can be fixed.
+            return Err(LimboError::Constraint(
+                "FOREIGN KEY constraint failed (19)".to_string(),
+            ));
+        }
+        // Ending the transaction closes its savepoints.
+        conn.end_fk_transaction();
+        conn.savepoints.borrow_mut().clear();
+        pager.release_savepoint(0);
         if *rollback {
             // TODO(pere): add rollback I/O logic once we implement rollback journal
             pager.rollback(schema_did_change, &conn)?;
@@ -2057,6 +2185,74 @@ pub fn op_auto_commit(
     }
 }
 
+pub fn op_savepoint(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::Savepoint { op, name } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    if mv_store.is_some() {
+        return Err(LimboError::TxError(
+            "savepoints are not supported in MVCC mode".to_string(),
+        ));
+    }
+    let conn = program.connection.clone();
+    let mut savepoints = conn.savepoints.borrow_mut();
+    if *op == SavepointOp::Begin {
+        let starts_transaction = conn.auto_commit.get();
+        conn.auto_commit.replace(false);
+        for pager in conn.savepoint_pagers() {
+            // Drop the savepoint of a statement that was reset before it halted.
+            pager.release_savepoint(savepoints.len());
+            pager.open_savepoint();
+        }
+        savepoints.push(crate::Savepoint {
+            name: name.clone(),
+            starts_transaction,
+            schema: conn.schema.borrow().clone(),
+            fk_deferred_violations: conn.fk_deferred_violations.get(),
+        });
+        state.pc += 1;
+        return Ok(InsnFunctionStepResult::Step);
+    }
+    // The most recently opened savepoint wins when several share a name.
+    let Some(depth) = savepoints
+        .iter()
+        .rposition(|savepoint| savepoint.name.eq_ignore_ascii_case(name))
+    else {
+        return Err(LimboError::TxError(format!("no such savepoint: {name}")));
+    };
+    match op {
+        SavepointOp::Release => {
+            if savepoints[depth].starts_transaction {
+                // Releasing the outermost savepoint of a transaction it started commits it
+                // when the statement halts.
+                conn.auto_commit.replace(true);
+            }
+            savepoints.truncate(depth);
+            for pager in conn.savepoint_pagers() {
+                pager.release_savepoint(depth);
+            }
+        }
+        SavepointOp::RollbackTo => {
+            savepoints.truncate(depth + 1);
+            for pager in conn.savepoint_pagers() {
+                pager.rollback_to_savepoint(depth)?;
+            }
+            conn.schema.replace(savepoints[depth].schema.clone());
+            conn.fk_deferred_violations
+                .set(savepoints[depth].fk_deferred_violations);
+        }
+        SavepointOp::Begin => unreachable!(),
+    }
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
 pub fn op_goto(
     program: &Program,
     state: &mut ProgramState,
@@ -2122,6 +2318,128 @@ pub fn op_return(
     Ok(InsnFunctionStepResult::Step)
 }
 
+pub fn op_program(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::Program {
+        param_start_reg,
+        num_params,
+        program: sub_program,
+        ignore_jump_target,
+    } = insn
+    else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let sub_program = sub_program
+        .get()
+        .expect("recursive sub-program must be built before it runs");
+    // The state of the sub-program survives IO, so only set it up on first entry.
+    if state.op_program_state.is_none() {
+        if state.program_depth >= MAX_PROGRAM_DEPTH {
+            return Err(LimboError::Constraint(
+                "too many levels of trigger recursion".to_string(),
+            ));
+        }
+        let mut sub_state =
+            ProgramState::new(sub_program.max_registers, sub_program.cursor_ref.len());
+        sub_state.is_subprogram = true;
+        sub_state.program_depth = state.program_depth + 1;
+        sub_state.mv_tx_id = state.mv_tx_id;
+        sub_state.fk_immediate_violations = state.fk_immediate_violations;
+        for i in 0..*num_params {
+            let value = state.registers[*param_start_reg + i]
+                .get_owned_value()
+                .clone();
+            sub_state.bind_at(NonZero::new(i + 1).unwrap(), value);
+        }
+        state.op_program_state = Some(Box::new(sub_state));
+    }
+    let sub_state = state.op_program_state.as_mut().unwrap();
+    loop {
+        let (sub_insn, sub_insn_function) = &sub_program.insns[sub_state.pc as usize];
+        let result = sub_insn_function(&sub_program, sub_state, sub_insn, pager, mv_store);
+        let result = result.inspect_err(|_| state.on_error = sub_state.on_error)?;
+        match result {
+            InsnFunctionStepResult::Step => {}
+            // Rows produced by SELECT statements of a trigger are discarded.
+            InsnFunctionStepResult::Row => {}
+            InsnFunctionStepResult::Done => break,
+            InsnFunctionStepResult::IO => return Ok(InsnFunctionStepResult::IO),
+            InsnFunctionStepResult::Interrupt => return Ok(InsnFunctionStepResult::Interrupt),
+            InsnFunctionStepResult::Busy => return Ok(InsnFunctionStepResult::Busy),
+        }
+    }
+    let ignored = sub_state.halted_by_ignore;
+    state.fk_immediate_violations = sub_state.fk_immediate_violations;
+    state.op_program_state = None;
+    if ignored {
+        state.pc = ignore_jump_target.as_offset_int();
+    } else {
+        state.pc += 1;
+    }
+    Ok(InsnFunctionStepResult::Step)
+}
+
+/// SQLite's SQLITE_MAX_TRIGGER_DEPTH.
+const MAX_PROGRAM_DEPTH: usize = 1000;
+
+pub fn op_fk_counter(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::FkCounter {
+        increment_value,
+        deferred,
+    } = insn
+    else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let conn = &program.connection;
+    if *deferred || conn.defer_foreign_keys.get() {
+        conn.fk_deferred_violations
+            .set(conn.fk_deferred_violations.get() + increment_value);
+    } else {
+        state.fk_immediate_violations += increment_value;
+    }
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_fk_if_zero(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::FkIfZero {
+        deferred,
+        target_pc,
+    } = insn
+    else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let conn = &program.connection;
+    let is_zero = if *deferred || conn.defer_foreign_keys.get() {
+        conn.fk_deferred_violations.get() == 0
+    } else {
+        state.fk_immediate_violations == 0
+    };
+    if is_zero {
+        state.pc = target_pc.as_offset_int();
+    } else {
+        state.pc += 1;
+    }
+    Ok(InsnFunctionStepResult::Step)
+}
+
 pub fn op_integer(
     program: &Program,
     state: &mut ProgramState,
@@ -3390,126 +3708,13 @@ pub fn op_agg_final(
         unreachable!("unexpected Insn {:?}", insn)
     };
     match state.registers[*register].borrow_mut() {
-        Register::Aggregate(agg) => match func {
-            AggFunc::Avg => {
-                let AggContext::Avg(acc, count) = agg.borrow_mut() else {
-                    unreachable!();
-                };
-                *acc /= count.clone();
-                state.registers[*register] = Register::Value(acc.clone());
-            }
-            AggFunc::Sum => {
-                let AggContext::Sum(acc, sum_state) = agg.borrow_mut() else {
-                    unreachable!();
-                };
-                let value = match acc {
-                    Value::Null => match sum_state.approx {
-                        true => Value::Float(0.0),
-                        false => Value::Null,
-                    },
-                    Value::Integer(i) if !sum_state.approx && !sum_state.ovrfl => {
-                        Value::Integer(*i)
-                    }
-                    _ => Value::Float(acc.as_float() + sum_state.r_err),
-                };
-                state.registers[*register] = Register::Value(value);
-            }
-            AggFunc::Total => {
-                let AggContext::Sum(acc, _) = agg.borrow_mut() else {
-                    unreachable!();
-                };
-                let value = match acc {
-                    Value::Null => Value::Float(0.0),
-                    Value::Integer(i) => Value::Float(*i as f64),
-                    Value::Float(f) => Value::Float(*f),
-                    _ => unreachable!(),
-                };
-                state.registers[*register] = Register::Value(value);
-            }
-            AggFunc::Count | AggFunc::Count0 => {
-                let AggContext::Count(count) = agg.borrow_mut() else {
-                    unreachable!();
-                };
-                state.registers[*register] = Register::Value(count.clone());
-            }
-            AggFunc::Max => {
-                let AggContext::Max(acc) = agg.borrow_mut() else {
-                    unreachable!();
-                };
-                match acc {
-                    Some(value) => state.registers[*register] = Register::Value(value.clone()),
-                    None => state.registers[*register] = Register::Value(Value::Null),
-                }
-            }
-            AggFunc::Min => {
-                let AggContext::Min(acc) = agg.borrow_mut() else {
-                    unreachable!();
-                };
-                match acc {
-                    Some(value) => state.registers[*register] = Register::Value(value.clone()),
-                    None => state.registers[*register] = Register::Value(Value::Null),
-                }
-            }
-            AggFunc::GroupConcat | AggFunc::StringAgg => {
-                let AggContext::GroupConcat(acc) = agg.borrow_mut() else {
-                    unreachable!();
-                };
-                state.registers[*register] = Register::Value(acc.clone());
-            }
-            #[cfg(feature = "json")]
-            AggFunc::JsonGroupObject => {
-                let AggContext::GroupConcat(acc) = agg.borrow_mut() else {
-                    unreachable!();
-                };
-                let data = acc.to_blob().expect("Should be blob");
-                state.registers[*register] = Register::Value(json_from_raw_bytes_agg(data, false)?);
-            }
-            #[cfg(feature = "json")]
-            AggFunc::JsonbGroupObject => {
-                let AggContext::GroupConcat(acc) = agg.borrow_mut() else {
-                    unreachable!();
-                };
-                let data = acc.to_blob().expect("Should be blob");
-                state.registers[*register] = Register::Value(json_from_raw_bytes_agg(data, true)?);
-            }
-            #[cfg(feature = "json")]
-            AggFunc::JsonGroupArray => {
-                let AggContext::GroupConcat(acc) = agg.borrow_mut() else {
-                    unreachable!();
-                };
-                let data = acc.to_blob().expect("Should be blob");
-                state.registers[*register] = Register::Value(json_from_raw_bytes_agg(data, false)?);
-            }
-            #[cfg(feature = "json")]
-            AggFunc::JsonbGroupArray => {
-                let AggContext::GroupConcat(acc) = agg.borrow_mut() else {
-                    unreachable!();
-                };
-                let data = acc.to_blob().expect("Should be blob");
-                state.registers[*register] = Register::Value(json_from_raw_bytes_agg(data, true)?);
-            }
-            AggFunc::External(_) => {
-                agg.compute_external()?;
-                let AggContext::External(agg_state) = agg else {
-                    unreachable!();
-                };
-                match &agg_state.finalized_value {
-                    Some(value) => state.registers[*register] = Register::Value(value.clone()),
-                    None => state.registers[*register] = Register::Value(Value::Null),
-                }
-            }
-        },
+        Register::Aggregate(agg) => {
+            let value = finalize_aggregate(agg, func)?;
+            state.registers[*register] = Register::Value(value);
+        }
         Register::Value(Value::Null) => {
             // when the set is empty
-            match func {
-                AggFunc::Total => {
-                    state.registers[*register] = Register::Value(Value::Float(0.0));
-                }
-                AggFunc::Count | AggFunc::Count0 => {
-                    state.registers[*register] = Register::Value(Value::Integer(0));
-                }
-                _ => {}
-            }
+            state.registers[*register] = Register::Value(empty_aggregate_value(func));
         }
         other => {
             panic!("Unexpected value {other:?} in AggFinal");
@@ -3519,55 +3724,184 @@ pub fn op_agg_final(
     Ok(InsnFunctionStepResult::Step)
 }
 
-pub fn op_sorter_open(
+pub fn op_agg_value(
     program: &Program,
     state: &mut ProgramState,
     insn: &Insn,
     pager: &Rc<Pager>,
     mv_store: Option<&Rc<MvStore>>,
 ) -> Result<InsnFunctionStepResult> {
-    let Insn::SorterOpen {
-        cursor_id,
-        columns: _,
-        order,
-        collations,
+    let Insn::AggValue {
+        acc_reg,
+        dest_reg,
+        func,
     } = insn
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    let cache_size = program.connection.get_cache_size();
-    // Set the buffer size threshold to be roughly the same as the limit configured for the page-cache.
-    let page_size = header_accessor::get_page_size(pager)
-        .unwrap_or(storage::sqlite3_ondisk::DEFAULT_PAGE_SIZE) as usize;
-    let max_buffer_size_bytes = if cache_size < 0 {
-        (cache_size.abs() * 1024) as usize
-    } else {
-        (cache_size as usize) * page_size
+    let value = match &state.registers[*acc_reg] {
+        Register::Aggregate(agg) => {
+            assert!(
+                !matches!(func, AggFunc::External(_)),
+                "AggValue cannot be used with extension aggregates"
+            );
+            // Finalizing may modify the accumulator, so work on a copy.
+            let mut agg = agg.clone();
+            finalize_aggregate(&mut agg, func)?
+        }
+        Register::Value(Value::Null) => empty_aggregate_value(func),
+        other => {
+            panic!("Unexpected value {other:?} in AggValue");
+        }
     };
-    let cursor = Sorter::new(
-        order,
-        collations
-            .iter()
-            .map(|collation| collation.unwrap_or_default())
-            .collect(),
-        max_buffer_size_bytes,
-        page_size,
-        pager.io.clone(),
-    );
-    let mut cursors = state.cursors.borrow_mut();
-    cursors
-        .get_mut(*cursor_id)
-        .unwrap()
-        .replace(Cursor::new_sorter(cursor));
+    state.registers[*dest_reg] = Register::Value(value);
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
 }
 
-pub fn op_sorter_data(
-    program: &Program,
-    state: &mut ProgramState,
-    insn: &Insn,
-    pager: &Rc<Pager>,
+/// Computes the result of an aggregate from its accumulator.
+fn finalize_aggregate(agg: &mut AggContext, func: &AggFunc) -> Result<Value> {
+    let value = match func {
+        AggFunc::Avg => {
+            let AggContext::Avg(acc, count) = agg else {
+                unreachable!();
+            };
+            *acc /= count.clone();
+            acc.clone()
+        }
+        AggFunc::Sum => {
+            let AggContext::Sum(acc, sum_state) = agg else {
+                unreachable!();
+            };
+            match acc {
+                Value::Null => match sum_state.approx {
+                    true => Value::Float(0.0),
+                    false => Value::Null,
+                },
+                Value::Integer(i) if !sum_state.approx && !sum_state.ovrfl => Value::Integer(*i),
+                _ => Value::Float(acc.as_float() + sum_state.r_err),
+            }
+        }
+        AggFunc::Total => {
+            let AggContext::Sum(acc, _) = agg else {
+                unreachable!();
+            };
+            match acc {
+                Value::Null => Value::Float(0.0),
+                Value::Integer(i) => Value::Float(*i as f64),
+                Value::Float(f) => Value::Float(*f),
+                _ => unreachable!(),
+            }
+        }
+        AggFunc::Count | AggFunc::Count0 => {
+            let AggContext::Count(count) = agg else {
+                unreachable!();
+            };
+            count.clone()
+        }
+        AggFunc::Max => {
+            let AggContext::Max(acc) = agg else {
+                unreachable!();
+            };
+            acc.clone().unwrap_or(Value::Null)
+        }
+        AggFunc::Min => {
+            let AggContext::Min(acc) = agg else {
+                unreachable!();
+            };
+            acc.clone().unwrap_or(Value::Null)
+        }
+        AggFunc::GroupConcat | AggFunc::StringAgg => {
+            let AggContext::GroupConcat(acc) = agg else {
+                unreachable!();
+            };
+            acc.clone()
+        }
+        #[cfg(feature = "json")]
+        AggFunc::JsonGroupObject | AggFunc::JsonbGroupObject => {
+            let AggContext::GroupConcat(acc) = agg else {
+                unreachable!();
+            };
+            let data = acc.to_blob().expect("Should be blob");
+            json_from_raw_bytes_agg(data, matches!(func, AggFunc::JsonbGroupObject))?
+        }
+        #[cfg(feature = "json")]
+        AggFunc::JsonGroupArray | AggFunc::JsonbGroupArray => {
+            let AggContext::GroupConcat(acc) = agg else {
+                unreachable!();
+            };
+            let data = acc.to_blob().expect("Should be blob");
+            json_from_raw_bytes_agg(data, matches!(func, AggFunc::JsonbGroupArray))?
+        }
+        AggFunc::External(_) => {
+            agg.compute_external()?;
+            let AggContext::External(agg_state) = agg else {
+                unreachable!();
+            };
+            agg_state.finalized_value.clone().unwrap_or(Value::Null)
+        }
+    };
+    Ok(value)
+}
+
+/// The result of an aggregate over an empty set of rows.
+fn empty_aggregate_value(func: &AggFunc) -> Value {
+    match func {
+        AggFunc::Total => Value::Float(0.0),
+        AggFunc::Count | AggFunc::Count0 => Value::Integer(0),
+        _ => Value::Null,
+    }
+}
+
+pub fn op_sorter_open(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::SorterOpen {
+        cursor_id,
+        columns: _,
+        order,
+        collations,
+    } = insn
+    else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let cache_size = program.connection.get_cache_size();
+    // Set the buffer size threshold to be roughly the same as the limit configured for the page-cache.
+    let page_size = header_accessor::get_page_size(pager)
+        .unwrap_or(storage::sqlite3_ondisk::DEFAULT_PAGE_SIZE) as usize;
+    let max_buffer_size_bytes = if cache_size < 0 {
+        (cache_size.abs() * 1024) as usize
+    } else {
+        (cache_size as usize) * page_size
+    };
+    let cursor = Sorter::new(
+        order,
+        collations
+            .iter()
+            .map(|collation| collation.unwrap_or_default())
+            .collect(),
+        max_buffer_size_bytes,
+        page_size,
+        pager.io.clone(),
+    );
+    let mut cursors = state.cursors.borrow_mut();
+    cursors
+        .get_mut(*cursor_id)
+        .unwrap()
+        .replace(Cursor::new_sorter(cursor));
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_sorter_data(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
     mv_store: Option<&Rc<MvStore>>,
 ) -> Result<InsnFunctionStepResult> {
     let Insn::SorterData {
@@ -4677,8 +5011,8 @@ pub fn op_function(
                         };
 
                         let mut parser = Parser::new(sql.as_str().as_bytes());
-                        let ast::Cmd::Stmt(stmt) = parser.next().unwrap().unwrap() else {
-                            todo!()
+                        let Ok(Some(ast::Cmd::Stmt(stmt))) = parser.next() else {
+                            break 'sql None;
                         };
 
                         match stmt {
@@ -4736,7 +5070,46 @@ pub fn op_function(
                                     .unwrap(),
                                 )
                             }
-                            _ => todo!(),
+                            ast::Stmt::CreateView {
+                                temporary,
+                                if_not_exists,
+                                view_name,
+                                columns,
+                                mut select,
+                            } => {
+                                let original = select.clone();
+                                rename_select_table(
+                                    &mut select,
+                                    &rename_from,
+                                    &rename_to,
+                                    &mut vec![],
+                                )?;
+                                if select == original {
+                                    break 'sql None;
+                                }
+
+                                Some(
+                                    ast::Stmt::CreateView {
+                                        temporary,
+                                        if_not_exists,
+                                        view_name,
+                                        columns,
+                                        select,
+                                    }
+                                    .format()
+                                    .unwrap(),
+                                )
+                            }
+                            ast::Stmt::CreateTrigger(mut trigger) => {
+                                let original = trigger.clone();
+                                rename_trigger_table(&mut trigger, &rename_from, &rename_to)?;
+                                if trigger == original {
+                                    break 'sql None;
+                                }
+
+                                Some(ast::Stmt::CreateTrigger(trigger).format().unwrap())
+                            }
+                            _ => None,
                         }
                     };
 
@@ -4774,8 +5147,8 @@ pub fn op_function(
                         };
 
                         let mut parser = Parser::new(sql.as_str().as_bytes());
-                        let ast::Cmd::Stmt(stmt) = parser.next().unwrap().unwrap() else {
-                            todo!()
+                        let Ok(Some(ast::Cmd::Stmt(stmt))) = parser.next() else {
+                            break 'sql None;
                         };
 
                         match stmt {
@@ -4785,21 +5158,27 @@ pub fn op_function(
                                 idx_name,
                                 tbl_name,
                                 mut columns,
-                                where_clause,
+                                mut where_clause,
                             } => {
                                 if table != normalize_ident(tbl_name.as_str()) {
                                     break 'sql None;
                                 }
 
-                                for column in &mut columns {
-                                    match &mut column.expr {
-                                        ast::Expr::Id(ast::Name::Ident(id))
-                                            if normalize_ident(id) == rename_from =>
-                                        {
-                                            *id = rename_to.clone();
+                                // Index expressions and the WHERE clause of a partial index
+                                // refer to the column too.
+                                let exprs = columns
+                                    .iter_mut()
+                                    .map(|column| &mut column.expr)
+                                    .chain(where_clause.as_deref_mut());
+                                for expr in exprs {
+                                    walk_expr_mut(expr, &mut |expr| {
+                                        if let ast::Expr::Id(ast::Name::Ident(id)) = expr {
+                                            if normalize_ident(id) == rename_from {
+                                                *id = rename_to.clone();
+                                            }
                                         }
-                                        _ => {}
-                                    }
+                                        Ok(())
+                                    })?;
                                 }
 
                                 Some(
@@ -4827,11 +5206,11 @@ pub fn op_function(
 
                                 let ast::CreateTableBody::ColumnsAndConstraints {
                                     mut columns,
-                                    constraints,
+                                    mut constraints,
                                     options,
                                 } = *body
                                 else {
-                                    todo!()
+                                    break 'sql None;
                                 };
 
                                 let column_index = columns
@@ -4854,6 +5233,73 @@ pub fn op_function(
                                 // replacing the entry.
                                 columns.swap_remove_index(column_index).unwrap();
 
+                                let rename_in_expr = |expr: &mut ast::Expr| {
+                                    walk_expr_mut(expr, &mut |expr| {
+                                        if let ast::Expr::Id(ast::Name::Ident(id)) = expr {
+                                            if normalize_ident(id) == rename_from {
+                                                *id = rename_to.clone();
+                                            }
+                                        }
+                                        Ok(())
+                                    })
+                                };
+
+                                // Foreign keys referencing this same table name the column in
+                                // their parent column list.
+                                let rename_in_clause = |clause: &mut ast::ForeignKeyClause| {
+                                    if normalize_ident(clause.tbl_name.as_str()) != table {
+                                        return;
+                                    }
+                                    for column in clause.columns.iter_mut().flatten() {
+                                        if normalize_ident(column.col_name.as_str()) == rename_from
+                                        {
+                                            column.col_name = ast::Name::from_str(&rename_to);
+                                        }
+                                    }
+                                };
+
+                                // CHECK constraints and generated column expressions may refer
+                                // to the column, and so may foreign key column lists.
+                                for column in columns.values_mut() {
+                                    for constraint in &mut column.constraints {
+                                        match &mut constraint.constraint {
+                                            ast::ColumnConstraint::Check(expr)
+                                            | ast::ColumnConstraint::Generated { expr, .. } => {
+                                                rename_in_expr(expr)?;
+                                            }
+                                            ast::ColumnConstraint::ForeignKey {
+                                                clause, ..
+                                            } => {
+                                                rename_in_clause(clause);
+                                            }
+                                            _ => {}
+                                        }
+                                    }
+                                }
+                                for constraint in constraints.iter_mut().flatten() {
+                                    match &mut constraint.constraint {
+                                        ast::TableConstraint::Check(expr) => {
+                                            rename_in_expr(expr)?;
+                                        }
+                                        ast::TableConstraint::ForeignKey {
+                                            columns,
+                                            clause,
+                                            ..
+                                        } => {
+                                            for column in columns.iter_mut() {
+                                                if normalize_ident(column.col_name.as_str())
+                                                    == rename_from
+                                                {
+                                                    column.col_name =
+                                                        ast::Name::from_str(&rename_to);
+                                                }
+                                            }
+                                            rename_in_clause(clause);
+                                        }
+                                        _ => {}
+                                    }
+                                }
+
                                 Some(
                                     ast::Stmt::CreateTable {
                                         temporary,
@@ -4871,7 +5317,21 @@ pub fn op_function(
                                     .unwrap(),
                                 )
                             }
-                            _ => todo!(),
+                            ast::Stmt::CreateTrigger(mut trigger) => {
+                                let original = trigger.clone();
+                                rename_trigger_column(
+                                    &mut trigger,
+                                    &table,
+                                    &rename_from,
+                                    &rename_to,
+                                )?;
+                                if trigger == original {
+                                    break 'sql None;
+                                }
+
+                                Some(ast::Stmt::CreateTrigger(trigger).format().unwrap())
+                            }
+                            _ => None,
                         }
                     };
 
@@ -4893,6 +5353,9 @@ pub fn op_function(
         crate::function::Func::Agg(_) => {
             unreachable!("Aggregate functions should not be handled here")
         }
+        crate::function::Func::Window(_) => {
+            unreachable!("Window functions should not be handled here")
+        }
     }
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
@@ -5044,7 +5507,9 @@ pub fn op_insert(
                 let record = ImmutableRecord::from_registers(new_regs, new_regs.len());
                 std::borrow::Cow::Owned(record)
             }
-            Register::Aggregate(..) => unreachable!("Cannot insert an aggregate value."),
+            Register::Aggregate(..) | Register::StatAccum(..) => {
+                unreachable!("Cannot insert an aggregate value.")
+            }
         };
 
         // In a table insert, if the caller does not pass InsertFlags::REQUIRE_SEEK, they must ensure that a seek has already happened to the correct location.
@@ -5183,13 +5648,14 @@ pub fn op_idx_delete(
                 state.op_idx_delete_state = Some(OpIdxDeleteState::Verifying);
             }
             Some(OpIdxDeleteState::Verifying) => {
-                let rowid = {
+                let (rowid, has_rowid) = {
                     let mut cursor = state.get_cursor(*cursor_id);
                     let cursor = cursor.as_btree_mut();
-                    return_if_io!(cursor.rowid())
+                    (return_if_io!(cursor.rowid()), cursor.has_rowid())
                 };
 
-                if rowid.is_none() && *raise_error_if_no_matching_entry {
+                // The entries of the indexes of a table without rowid have no rowid to verify.
+                if rowid.is_none() && has_rowid && *raise_error_if_no_matching_entry {
                     return Err(LimboError::Corrupt(format!(
                         "IdxDelete: no matching index entry found for record {:?}",
                         make_record(&state.registers, start_reg, num_regs)
@@ -5752,18 +6218,24 @@ pub fn op_open_write(
         CursorType::BTreeIndex(index) => Some(index),
         _ => None,
     };
-    let mv_cursor = match state.mv_tx_id {
-        Some(tx_id) => {
-            let table_id = root_page;
-            let mv_store = mv_store.unwrap().clone();
-            let mv_cursor = Rc::new(RefCell::new(
-                MvCursor::new(mv_store.clone(), tx_id, table_id).unwrap(),
-            ));
-            Some(mv_cursor)
-        }
-        None => None,
-    };
     if let Some(index) = maybe_index {
+        let mv_cursor = match state.mv_tx_id {
+            Some(tx_id) => {
+                let mv_store = mv_store.unwrap().clone();
+                // Concurrent transactions cannot see each other's entries, so inserting into a
+                // UNIQUE index also checks the entries of the others.
+                let unique_columns = index.unique.then(|| index.key_columns().len());
+                let mv_cursor = Rc::new(RefCell::new(MvIndexCursor::new(
+                    mv_store,
+                    tx_id,
+                    root_page,
+                    Arc::new(IndexInfo::new_from_index(index)),
+                    unique_columns,
+                )?));
+                Some(mv_cursor)
+            }
+            None => None,
+        };
         let conn = program.connection.clone();
         let schema = conn.schema.borrow();
         let table = schema
@@ -5788,6 +6260,17 @@ pub fn op_open_write(
             _ => unreachable!("Expected BTreeTable. This should not have happened."),
         };
 
+        let mv_cursor = match state.mv_tx_id {
+            Some(tx_id) => {
+                let table_id = root_page;
+                let mv_store = mv_store.unwrap().clone();
+                let mv_cursor = Rc::new(RefCell::new(
+                    MvCursor::new(mv_store.clone(), tx_id, table_id).unwrap(),
+                ));
+                Some(mv_cursor)
+            }
+            None => None,
+        };
         let cursor =
             BTreeCursor::new_table(mv_cursor, pager.clone(), root_page as usize, num_columns);
         cursors
@@ -5832,17 +6315,16 @@ pub fn op_create_btree(
         unreachable!("unexpected Insn {:?}", insn)
     };
 
-    assert_eq!(*db, 0);
-
     if program.connection.is_readonly(*db) {
         return Err(LimboError::ReadOnly);
     }
-    if *db > 0 {
-        // TODO: implement temp databases
-        todo!("temp databases not implemented yet");
-    }
-    // FIXME: handle page cache is full
-    let root_page = return_if_io!(pager.btree_create(flags));
+    let pager = program.get_pager_from_database_index(db);
+    let root_page = if mv_store.is_some() {
+        crate::mvcc::checkpoint::create_btree(&pager, &program.connection, flags)?
+    } else {
+        // FIXME: handle page cache is full
+        return_if_io!(pager.btree_create(flags))
+    };
     state.registers[*root] = Register::Value(Value::Integer(root_page as i64));
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
@@ -5858,16 +6340,14 @@ pub fn op_destroy(
     let Insn::Destroy {
         root,
         former_root_reg,
-        is_temp,
+        db,
     } = insn
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    if *is_temp == 1 {
-        todo!("temp databases not implemented yet.");
-    }
+    let pager = program.get_pager_from_database_index(db);
     // TODO not sure if should be BTreeCursor::new_table or BTreeCursor::new_index here or neither and just pass an emtpy vec
-    let mut cursor = BTreeCursor::new(None, pager.clone(), *root, 0);
+    let mut cursor = BTreeCursor::new(None, pager, *root, 0);
     let former_root_page_result = cursor.btree_destroy()?;
     if let IOResult::Done(former_root_page) = former_root_page_result {
         state.registers[*former_root_reg] =
@@ -5877,6 +6357,25 @@ pub fn op_destroy(
     Ok(InsnFunctionStepResult::Step)
 }
 
+pub fn op_clear(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::Clear { cursor_id } = *insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    {
+        let mut cursor = state.get_cursor(cursor_id);
+        let cursor = cursor.as_btree_mut();
+        return_if_io!(cursor.clear_btree());
+    }
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
 pub fn op_drop_table(
     program: &Program,
     state: &mut ProgramState,
@@ -5887,14 +6386,13 @@ pub fn op_drop_table(
     let Insn::DropTable { db, table_name, .. } = insn else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    if *db > 0 {
-        todo!("temp databases not implemented yet");
-    }
     let conn = program.connection.clone();
     {
-        conn.with_schema_mut(|schema| {
+        conn.with_database_schema_mut(*db, |schema| {
             schema.remove_indices_for_table(table_name);
+            schema.remove_triggers_for_table(table_name);
             schema.remove_table(table_name);
+            schema.remove_view(table_name);
         });
     }
     state.pc += 1;
@@ -5945,10 +6443,7 @@ pub fn op_page_count(
     let Insn::PageCount { db, dest } = insn else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    if *db > 0 {
-        // TODO: implement temp databases
-        todo!("temp databases not implemented yet");
-    }
+    let pager = &program.get_pager_from_database_index(db);
     let count = header_accessor::get_database_size(pager).unwrap_or(0);
     state.registers[*dest] = Register::Value(Value::Integer(count as i64));
     state.pc += 1;
@@ -5962,11 +6457,7 @@ pub fn op_parse_schema(
     pager: &Rc<Pager>,
     mv_store: Option<&Rc<MvStore>>,
 ) -> Result<InsnFunctionStepResult> {
-    let Insn::ParseSchema {
-        db: _,
-        where_clause,
-    } = insn
-    else {
+    let Insn::ParseSchema { db, where_clause } = insn else {
         unreachable!("unexpected Insn {:?}", insn)
     };
     let conn = program.connection.clone();
@@ -5975,22 +6466,193 @@ pub fn op_parse_schema(
     let previous_auto_commit = conn.auto_commit.get();
     conn.auto_commit.set(false);
 
-    if let Some(where_clause) = where_clause {
-        let stmt = conn.prepare(format!("SELECT * FROM sqlite_schema WHERE {where_clause}"))?;
-
-        conn.with_schema_mut(|schema| {
-            // TODO: This function below is synchronous, make it async
-            parse_schema_rows(stmt, schema, &conn.syms.borrow(), state.mv_tx_id)
-        })?;
-    } else {
-        let stmt = conn.prepare("SELECT * FROM sqlite_schema")?;
+    let schema_table = match *db {
+        0 => "sqlite_schema".to_string(),
+        db => format!("\"{}\".sqlite_schema", conn.get_database_name(db)),
+    };
+    let stmt = match where_clause {
+        Some(where_clause) => {
+            conn.prepare(format!("SELECT * FROM {schema_table} WHERE {where_clause}"))?
+        }
+        None => conn.prepare(format!("SELECT * FROM {schema_table}"))?,
+    };
+    conn.with_database_schema_mut(*db, |schema| {
+        // TODO: This function below is synchronous, make it async
+        parse_schema_rows(stmt, schema, &conn.syms.borrow(), state.mv_tx_id)
+    })?;
+    conn.auto_commit.set(previous_auto_commit);
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
 
-        conn.with_schema_mut(|schema| {
-            // TODO: This function below is synchronous, make it async
-            parse_schema_rows(stmt, schema, &conn.syms.borrow(), state.mv_tx_id)
-        })?;
+pub fn op_vacuum(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::Vacuum { db, into } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    if *db > 0 {
+        return Err(LimboError::ParseError(
+            "VACUUM is only supported for the main database".to_string(),
+        ));
     }
+    if mv_store.is_some() {
+        return Err(LimboError::TxError(
+            "VACUUM is not supported in MVCC mode".to_string(),
+        ));
+    }
+    let conn = program.connection.clone();
+    match into {
+        Some(reg) => {
+            let Value::Text(path) = state.registers[*reg].get_owned_value() else {
+                return Err(LimboError::InvalidArgument("non-text filename".to_string()));
+            };
+            conn.vacuum_into(path.as_str())?;
+        }
+        None => {
+            if !conn.auto_commit.get() {
+                return Err(LimboError::TxError(
+                    "cannot VACUUM from within a transaction".to_string(),
+                ));
+            }
+            conn.vacuum(pager)?;
+        }
+    }
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_stat_init(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::StatInit {
+        accum_reg,
+        num_columns,
+        num_key_columns,
+        entries_reg,
+        limit,
+    } = insn
+    else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let num_entries = match state.registers[*entries_reg].get_owned_value() {
+        Value::Integer(n) => (*n).max(0) as u64,
+        _ => 0,
+    };
+    state.registers[*accum_reg] = Register::StatAccum(Box::new(StatAccum::new(
+        *num_columns,
+        *num_key_columns,
+        num_entries,
+        *limit,
+    )));
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_stat_push(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::StatPush {
+        accum_reg,
+        change_reg,
+        sample_reg,
+    } = insn
+    else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let Value::Integer(change) = *state.registers[*change_reg].get_owned_value() else {
+        unreachable!("StatPush change register must hold an integer");
+    };
+    let record = sample_reg.map(|reg| match &state.registers[reg] {
+        Register::Record(record) => record.clone(),
+        _ => unreachable!("StatPush sample register must hold a record"),
+    });
+    let Register::StatAccum(accum) = &mut state.registers[*accum_reg] else {
+        unreachable!("StatPush accumulator register must hold a StatAccum");
+    };
+    accum.push(change as usize, record);
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_stat_get(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::StatGet { accum_reg, dest } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let Register::StatAccum(accum) = &state.registers[*accum_reg] else {
+        unreachable!("StatGet accumulator register must hold a StatAccum");
+    };
+    let stat = accum.stat1();
+    state.registers[*dest] = Register::Value(Value::build_text(stat));
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_stat_next_sample(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::StatNextSample {
+        accum_reg,
+        dest,
+        pc_if_done,
+    } = insn
+    else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let Register::StatAccum(accum) = &mut state.registers[*accum_reg] else {
+        unreachable!("StatNextSample accumulator register must hold a StatAccum");
+    };
+    let Some((neq, nlt, ndlt, sample)) = accum.next_sample() else {
+        state.pc = pc_if_done.as_offset_int();
+        return Ok(InsnFunctionStepResult::Step);
+    };
+    state.registers[*dest] = Register::Value(Value::build_text(neq));
+    state.registers[*dest + 1] = Register::Value(Value::build_text(nlt));
+    state.registers[*dest + 2] = Register::Value(Value::build_text(ndlt));
+    state.registers[*dest + 3] = Register::Value(Value::Blob(sample.get_payload().to_vec()));
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_load_analysis(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::LoadAnalysis { db } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let conn = program.connection.clone();
+    // Same as ParseSchema: the nested statements must not commit the running transaction.
+    let previous_auto_commit = conn.auto_commit.get();
+    conn.auto_commit.set(false);
+    let result = conn.load_analysis(*db);
     conn.auto_commit.set(previous_auto_commit);
+    result?;
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
 }
@@ -6005,10 +6667,7 @@ pub fn op_read_cookie(
     let Insn::ReadCookie { db, dest, cookie } = insn else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    if *db > 0 {
-        // TODO: implement temp databases
-        todo!("temp databases not implemented yet");
-    }
+    let pager = &program.get_pager_from_database_index(db);
     let cookie_value = match cookie {
         Cookie::ApplicationId => header_accessor::get_application_id(pager).unwrap_or(0) as i64,
         Cookie::UserVersion => header_accessor::get_user_version(pager).unwrap_or(0) as i64,
@@ -6039,9 +6698,7 @@ pub fn op_set_cookie(
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    if *db > 0 {
-        todo!("temp databases not implemented yet");
-    }
+    let pager = &program.get_pager_from_database_index(db);
     match cookie {
         Cookie::ApplicationId => {
             header_accessor::set_application_id(pager, *value)?;
@@ -6055,6 +6712,13 @@ pub fn op_set_cookie(
         Cookie::IncrementalVacuum => {
             header_accessor::set_incremental_vacuum_enabled(pager, *value as u32)?;
         }
+        Cookie::SchemaVersion if *db != 0 => {
+            program.connection.set_database_schema_did_change(*db);
+            program
+                .connection
+                .with_database_schema_mut(*db, |schema| schema.schema_version = *value as u32);
+            header_accessor::set_schema_cookie(pager, *value as u32)?;
+        }
         Cookie::SchemaVersion => {
             if mv_store.is_none() {
                 // we update transaction state to indicate that the schema has changed
@@ -6304,34 +6968,18 @@ pub fn op_open_ephemeral(
             let root_page = return_if_io!(pager.btree_create(flag));
 
             let (_, cursor_type) = program.cursor_ref.get(cursor_id).unwrap();
-            let mv_cursor = match state.mv_tx_id {
-                Some(tx_id) => {
-                    let table_id = root_page as u64;
-                    let mv_store = mv_store.unwrap().clone();
-                    let mv_cursor = Rc::new(RefCell::new(
-                        MvCursor::new(mv_store.clone(), tx_id, table_id).unwrap(),
-                    ));
-                    Some(mv_cursor)
-                }
-                None => None,
-            };
-
             let num_columns = match cursor_type {
                 CursorType::BTreeTable(table_rc) => table_rc.columns.len(),
                 CursorType::BTreeIndex(index_arc) => index_arc.columns.len(),
                 _ => unreachable!("This should not have happened"),
             };
 
+            // Ephemeral b-trees are private to the statement, so they bypass the MVCC store even
+            // in MVCC mode.
             let mut cursor = if let CursorType::BTreeIndex(index) = cursor_type {
-                BTreeCursor::new_index(
-                    mv_cursor,
-                    pager.clone(),
-                    root_page as usize,
-                    index,
-                    num_columns,
-                )
+                BTreeCursor::new_index(None, pager.clone(), root_page as usize, index, num_columns)
             } else {
-                BTreeCursor::new_table(mv_cursor, pager.clone(), root_page as usize, num_columns)
+                BTreeCursor::new_table(None, pager.clone(), root_page as usize, num_columns)
             };
             cursor.rewind()?; // Will never return io
 
@@ -6371,6 +7019,52 @@ pub fn op_open_ephemeral(
     Ok(InsnFunctionStepResult::Step)
 }
 
+pub fn op_open_dup(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    _pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::OpenDup {
+        new_cursor_id,
+        original_cursor_id,
+    } = insn
+    else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+
+    let (pager, root_page) = {
+        let mut cursor = state.get_cursor(*original_cursor_id);
+        let cursor = cursor.as_btree_mut();
+        (cursor.pager(), cursor.root_page())
+    };
+    let mv_cursor = match state.mv_tx_id {
+        Some(tx_id) => {
+            let table_id = root_page as u64;
+            let mv_store = mv_store.unwrap().clone();
+            let mv_cursor = Rc::new(RefCell::new(
+                MvCursor::new(mv_store.clone(), tx_id, table_id).unwrap(),
+            ));
+            Some(mv_cursor)
+        }
+        None => None,
+    };
+    let (_, cursor_type) = program.cursor_ref.get(*new_cursor_id).unwrap();
+    let CursorType::BTreeTable(table) = cursor_type else {
+        unreachable!("OpenDup on non-table cursor");
+    };
+    let cursor = BTreeCursor::new_table(mv_cursor, pager, root_page, table.columns.len());
+    state
+        .cursors
+        .borrow_mut()
+        .get_mut(*new_cursor_id)
+        .unwrap()
+        .replace(Cursor::new_btree(cursor));
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
 /// Execute the [Insn::Once] instruction.
 ///
 /// This instruction is used to execute a block of code only once.
@@ -624,11 +624,12 @@ pub fn insn_to_str(
             ),
             Insn::Halt {
                 err_code,
+                on_error,
                 description,
             } => (
                 "Halt",
                 *err_code as i32,
-                0,
+                on_error.bit_value() as i32,
                 0,
                 Value::build_text(description),
                 0,
@@ -636,12 +637,13 @@ pub fn insn_to_str(
             ),
             Insn::HaltIfNull {
                 err_code,
+                on_error,
                 target_reg,
                 description,
             } => (
                 "HaltIfNull",
                 *err_code as i32,
-                0,
+                on_error.bit_value() as i32,
                 *target_reg as i32,
                 Value::build_text(description),
                 0,
@@ -947,6 +949,19 @@ pub fn insn_to_str(
                 0,
                 format!("accum=r[{}]", *register),
             ),
+            Insn::AggValue {
+                acc_reg,
+                dest_reg,
+                func,
+            } => (
+                "AggValue",
+                0,
+                *acc_reg as i32,
+                *dest_reg as i32,
+                Value::build_text(func.to_string()),
+                0,
+                format!("r[{}]=value(accum=r[{}])", *dest_reg, *acc_reg),
+            ),
             Insn::SorterOpen {
                 cursor_id,
                 columns,
@@ -1254,17 +1269,24 @@ pub fn insn_to_str(
             Insn::Destroy {
                 root,
                 former_root_reg,
-                is_temp,
+                db,
             } => (
                 "Destroy",
                 *root as i32,
                 *former_root_reg as i32,
-                *is_temp as i32,
+                *db as i32,
                 Value::build_text(""),
                 0,
-                format!(
-                    "root iDb={root} former_root={former_root_reg} is_temp={is_temp}"
-                ),
+                format!("root iDb={db} former_root={former_root_reg}"),
+            ),
+            Insn::Clear { cursor_id } => (
+                "Clear",
+                *cursor_id as i32,
+                0,
+                0,
+                Value::build_text(""),
+                0,
+                format!("clear cursor {cursor_id}"),
             ),
             Insn::DropTable {
                 db,
@@ -1289,6 +1311,15 @@ pub fn insn_to_str(
                 0,
                 format!("DROP INDEX {}", index.name),
             ),
+            Insn::DropTrigger { db, trigger_name } => (
+                "DropTrigger",
+                *db as i32,
+                0,
+                0,
+                Value::build_text(trigger_name.clone()),
+                0,
+                format!("DROP TRIGGER {trigger_name}"),
+            ),
             Insn::Close { cursor_id } => (
                 "Close",
                 *cursor_id as i32,
@@ -1319,6 +1350,88 @@ pub fn insn_to_str(
                 0,
                 format!("if (r[{}]==NULL) goto {}", reg, target_pc.as_debug_int()),
             ),
+            Insn::Vacuum { db, into } => (
+                "Vacuum",
+                *db as i32,
+                into.map_or(0, |reg| reg as i32),
+                0,
+                Value::build_text(""),
+                0,
+                match into {
+                    Some(reg) => format!("vacuum into r[{reg}]"),
+                    None => "vacuum".to_string(),
+                },
+            ),
+            Insn::StatInit {
+                accum_reg,
+                num_columns,
+                num_key_columns,
+                entries_reg,
+                limit,
+            } => (
+                "StatInit",
+                *num_columns as i32,
+                *accum_reg as i32,
+                *entries_reg as i32,
+                Value::build_text(""),
+                0,
+                format!(
+                    "r[{accum_reg}]=stat_init(n={num_columns}, k={num_key_columns}, entries=r[{entries_reg}], limit={limit})"
+                ),
+            ),
+            Insn::StatPush {
+                accum_reg,
+                change_reg,
+                sample_reg,
+            } => (
+                "StatPush",
+                *accum_reg as i32,
+                *change_reg as i32,
+                sample_reg.map_or(0, |reg| reg as i32),
+                Value::build_text(""),
+                0,
+                match sample_reg {
+                    Some(reg) => format!("stat_push(r[{accum_reg}], r[{change_reg}], r[{reg}])"),
+                    None => format!("stat_push(r[{accum_reg}], r[{change_reg}])"),
+                },
+            ),
+            Insn::StatGet { accum_reg, dest } => (
+                "StatGet",
+                *accum_reg as i32,
+                *dest as i32,
+                0,
+                Value::build_text(""),
+                0,
+                format!("r[{dest}]=stat_get(r[{accum_reg}])"),
+            ),
+            Insn::StatNextSample {
+                accum_reg,
+                dest,
+                pc_if_done,
+            } => (
+                "StatNextSample",
+                *accum_reg as i32,
+                *dest as i32,
+                pc_if_done.as_debug_int(),
+                Value::build_text(""),
+                0,
+                format!(
+                    "r[{}..{}]=stat_sample(r[{}]) else goto {}",
+                    dest,
+                    dest + 3,
+                    accum_reg,
+                    pc_if_done.as_debug_int()
+                ),
+            ),
+            Insn::LoadAnalysis { db } => (
+                "LoadAnalysis",
+                *db as i32,
+                0,
+                0,
+                Value::build_text(""),
+                0,
+                "".to_string(),
+            ),
             Insn::ParseSchema { db, where_clause } => (
                 "ParseSchema",
                 *db as i32,
@@ -1459,6 +1572,15 @@ pub fn insn_to_str(
                 0,
                 format!("auto_commit={auto_commit}, rollback={rollback}"),
             ),
+            Insn::Savepoint { op, name } => (
+                "Savepoint",
+                *op as i32,
+                0,
+                0,
+                Value::build_text(name),
+                0,
+                format!("{op:?} {name}"),
+            ),
             Insn::OpenEphemeral {
                 cursor_id,
                 is_table,
@@ -1475,6 +1597,18 @@ pub fn insn_to_str(
                     if *is_table { "true" } else { "false" }
                 ),
             ),
+            Insn::OpenDup {
+                new_cursor_id,
+                original_cursor_id,
+            } => (
+                "OpenDup",
+                *new_cursor_id as i32,
+                *original_cursor_id as i32,
+                0,
+                Value::build_text(""),
+                0,
+                format!("cursor={new_cursor_id} dup_of={original_cursor_id}"),
+            ),
             Insn::OpenAutoindex { cursor_id } => (
                 "OpenAutoindex",
                 *cursor_id as i32,
@@ -1495,6 +1629,56 @@ pub fn insn_to_str(
                 0,
                 format!("goto {}", target_pc_when_reentered.as_debug_int()),
             ),
+            Insn::Program {
+                param_start_reg,
+                num_params,
+                program: _,
+                ignore_jump_target,
+            } => (
+                "Program",
+                *param_start_reg as i32,
+                ignore_jump_target.as_debug_int(),
+                *num_params as i32,
+                Value::build_text("program"),
+                0,
+                format!(
+                    "call trigger program with r[{}..{}]",
+                    param_start_reg,
+                    param_start_reg + num_params
+                ),
+            ),
+            Insn::FkCounter {
+                increment_value,
+                deferred,
+            } => (
+                "FkCounter",
+                *deferred as i32,
+                *increment_value as i32,
+                0,
+                Value::build_text(""),
+                0,
+                format!(
+                    "{}fk_violations += {}",
+                    if *deferred { "deferred " } else { "" },
+                    increment_value
+                ),
+            ),
+            Insn::FkIfZero {
+                deferred,
+                target_pc,
+            } => (
+                "FkIfZero",
+                *deferred as i32,
+                target_pc.as_debug_int(),
+                0,
+                Value::build_text(""),
+                0,
+                format!(
+                    "if {}fk_violations == 0 goto {}",
+                    if *deferred { "deferred " } else { "" },
+                    target_pc.as_debug_int()
+                ),
+            ),
             Insn::BeginSubrtn { dest, dest_end } => (
                 "BeginSubrtn",
                 *dest as i32,
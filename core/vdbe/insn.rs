@@ -1,10 +1,11 @@
 use std::{
+    cell::OnceCell,
     num::{NonZero, NonZeroUsize},
-    rc::Rc,
+    rc::{Rc, Weak},
     sync::Arc,
 };
 
-use super::{execute, AggFunc, BranchOffset, CursorID, FuncCtx, InsnFunction, PageIdx};
+use super::{execute, AggFunc, BranchOffset, CursorID, FuncCtx, InsnFunction, PageIdx, Program};
 use crate::{
     schema::{Affinity, BTreeTable, Index},
     storage::{pager::CreateBTreeFlags, wal::CheckpointMode},
@@ -12,7 +13,25 @@ use crate::{
     Value,
 };
 use turso_macros::Description;
-use turso_sqlite3_parser::ast::SortOrder;
+use turso_sqlite3_parser::ast::{ResolveType, SortOrder};
+
+/// The sub-program run by [Insn::Program].
+#[derive(Debug, Clone)]
+pub enum SubProgram {
+    Program(Rc<Program>),
+    /// A program that runs itself again while it is being built, like the ON DELETE CASCADE
+    /// action of a table that references itself. Set once the program is built.
+    Recursive(Rc<OnceCell<Weak<Program>>>),
+}
+
+impl SubProgram {
+    pub fn get(&self) -> Option<Rc<Program>> {
+        match self {
+            Self::Program(program) => Some(program.clone()),
+            Self::Recursive(program) => program.get().and_then(Weak::upgrade),
+        }
+    }
+}
 
 /// Flags provided to comparison instructions (e.g. Eq, Ne) which determine behavior related to NULL values.
 #[derive(Clone, Copy, Debug, Default)]
@@ -448,14 +467,18 @@ pub enum Insn {
     /// Halt the program.
     Halt {
         err_code: usize,
+        /// How much of the transaction an error undoes: the statement (ABORT), nothing (FAIL)
+        /// or the whole transaction (ROLLBACK).
+        on_error: ResolveType,
         description: String,
     },
 
     /// Halt the program if P3 is null.
     HaltIfNull {
-        target_reg: usize,   // P3
-        description: String, // p4
-        err_code: usize,     // p1
+        target_reg: usize,     // P3
+        description: String,   // p4
+        err_code: usize,       // p1
+        on_error: ResolveType, // p2
     },
 
     /// Start a transaction.
@@ -470,6 +493,12 @@ pub enum Insn {
         rollback: bool,
     },
 
+    /// Open a savepoint, release it or roll back to it.
+    Savepoint {
+        op: SavepointOp,
+        name: String,
+    },
+
     /// Branch to the given PC.
     Goto {
         target_pc: BranchOffset,
@@ -489,6 +518,34 @@ pub enum Insn {
         can_fallthrough: bool,
     },
 
+    /// Execute the trigger sub-program `program`. The `num_params` registers starting at
+    /// `param_start_reg` are bound as the parameters of the sub-program, which reads the
+    /// OLD and NEW values of the row through them. If the sub-program halts because of
+    /// RAISE(IGNORE), jump to `ignore_jump_target` instead of continuing with the next
+    /// instruction.
+    Program {
+        param_start_reg: usize,
+        num_params: usize,
+        program: SubProgram,
+        ignore_jump_target: BranchOffset,
+    },
+
+    /// Add `increment_value` to the number of foreign key violations: the violations of the
+    /// transaction, checked when it commits, if `deferred` or `PRAGMA defer_foreign_keys` is on,
+    /// and the violations of the statement, checked when it halts, otherwise.
+    FkCounter {
+        increment_value: i64,
+        deferred: bool,
+    },
+
+    /// Jump to `target_pc` if there are no foreign key violations of the transaction, if
+    /// `deferred`, or of the statement otherwise. Used to skip the search for violations a
+    /// change could fix.
+    FkIfZero {
+        deferred: bool,
+        target_pc: BranchOffset,
+    },
+
     /// Write an integer value into a register.
     Integer {
         value: i64,
@@ -666,6 +723,14 @@ pub enum Insn {
         func: AggFunc,
     },
 
+    /// Store the current value of the aggregate in `acc_reg` into `dest_reg`, leaving
+    /// the accumulator in place so that more rows can be stepped into it.
+    AggValue {
+        acc_reg: usize,
+        dest_reg: usize,
+        func: AggFunc,
+    },
+
     /// Open a sorter.
     SorterOpen {
         cursor_id: CursorID,                   // P1
@@ -804,7 +869,7 @@ pub enum Insn {
 
     /// Allocate a new b-tree.
     CreateBtree {
-        /// Allocate b-tree in main database if zero or in temp database if non-zero (P1).
+        /// The database to allocate the b-tree in (P1).
         db: usize,
         /// The root page of the new b-tree (P2).
         root: usize,
@@ -818,8 +883,15 @@ pub enum Insn {
         root: usize,
         /// Register to store the former value of any moved root page (for AUTOVACUUM)
         former_root_reg: usize,
-        /// Whether this is a temporary table (1) or main database table (0)
-        is_temp: usize,
+        /// The database the b-tree is in (P3).
+        db: usize,
+    },
+
+    /// Deletes all the entries of the b-tree the write cursor P1 is open on, keeping its root page.
+    /// Unlike SQLite's OP_Clear, the b-tree is given by a cursor, which keeps the progress of
+    /// the operation when it has to wait for IO.
+    Clear {
+        cursor_id: CursorID,
     },
 
     ///  Drop a table
@@ -839,6 +911,13 @@ pub enum Insn {
         //  The name of the index being dropped
         index: Arc<Index>,
     },
+    ///  Drop a trigger
+    DropTrigger {
+        ///  The database within which this trigger needs to be dropped (P1).
+        db: usize,
+        //  The name of the trigger being dropped
+        trigger_name: String,
+    },
 
     /// Close a cursor.
     Close {
@@ -858,6 +937,51 @@ pub enum Insn {
         where_clause: Option<String>,
     },
 
+    /// Rebuild the database, or write a compacted copy of it to the file named by the `into`
+    /// register (VACUUM INTO).
+    Vacuum {
+        db: usize,
+        into: Option<usize>,
+    },
+
+    /// Initialize the statistics accumulator of ANALYZE for an index with `num_columns` columns
+    /// (`num_key_columns` of them excluding the rowid) and the row count in `entries_reg`.
+    /// A non-zero `limit` disables the collection of sqlite_stat4 samples.
+    StatInit {
+        accum_reg: usize,
+        num_columns: usize,
+        num_key_columns: usize,
+        entries_reg: usize,
+        limit: u64,
+    },
+
+    /// Add an index entry to the accumulator. `change_reg` holds the index of the leftmost
+    /// column that differs from the previous entry and `sample_reg` the entry's record.
+    StatPush {
+        accum_reg: usize,
+        change_reg: usize,
+        sample_reg: Option<usize>,
+    },
+
+    /// Write the sqlite_stat1 statistics string of the accumulator into `dest`.
+    StatGet {
+        accum_reg: usize,
+        dest: usize,
+    },
+
+    /// Write the next sqlite_stat4 sample of the accumulator into `dest` to `dest + 3`
+    /// (neq, nlt, ndlt, sample), or jump to `pc_if_done` when there are no samples left.
+    StatNextSample {
+        accum_reg: usize,
+        dest: usize,
+        pc_if_done: BranchOffset,
+    },
+
+    /// Reload the statistics of sqlite_stat1 and sqlite_stat4 into the schema.
+    LoadAnalysis {
+        db: usize,
+    },
+
     /// Place the result of lhs >> rhs in dest register.
     ShiftRight {
         lhs: usize,
@@ -933,6 +1057,12 @@ pub enum Insn {
         cursor_id: usize,
         is_table: bool,
     },
+    /// Open a new cursor on the same transient table as another, already open cursor.
+    /// The new cursor has its own position, so both cursors can be used independently.
+    OpenDup {
+        new_cursor_id: CursorID,
+        original_cursor_id: CursorID,
+    },
     /// Works the same as OpenEphemeral, name just distinguishes its use; used for transient indexes in joins.
     OpenAutoindex {
         cursor_id: usize,
@@ -1005,6 +1135,7 @@ impl Insn {
             Insn::Multiply { .. } => execute::op_multiply,
             Insn::Divide { .. } => execute::op_divide,
             Insn::DropIndex { .. } => execute::op_drop_index,
+            Insn::DropTrigger { .. } => execute::op_drop_trigger,
             Insn::Compare { .. } => execute::op_compare,
             Insn::BitAnd { .. } => execute::op_bit_and,
             Insn::BitOr { .. } => execute::op_bit_or,
@@ -1045,9 +1176,13 @@ impl Insn {
             Insn::HaltIfNull { .. } => execute::op_halt_if_null,
             Insn::Transaction { .. } => execute::op_transaction,
             Insn::AutoCommit { .. } => execute::op_auto_commit,
+            Insn::Savepoint { .. } => execute::op_savepoint,
             Insn::Goto { .. } => execute::op_goto,
             Insn::Gosub { .. } => execute::op_gosub,
             Insn::Return { .. } => execute::op_return,
+            Insn::Program { .. } => execute::op_program,
+            Insn::FkCounter { .. } => execute::op_fk_counter,
+            Insn::FkIfZero { .. } => execute::op_fk_if_zero,
             Insn::Integer { .. } => execute::op_integer,
             Insn::Real { .. } => execute::op_real,
             Insn::RealAffinity { .. } => execute::op_real_affinity,
@@ -1070,6 +1205,7 @@ impl Insn {
             Insn::DecrJumpZero { .. } => execute::op_decr_jump_zero,
             Insn::AggStep { .. } => execute::op_agg_step,
             Insn::AggFinal { .. } => execute::op_agg_final,
+            Insn::AggValue { .. } => execute::op_agg_value,
             Insn::SorterOpen { .. } => execute::op_sorter_open,
             Insn::SorterInsert { .. } => execute::op_sorter_insert,
             Insn::SorterSort { .. } => execute::op_sorter_sort,
@@ -1093,11 +1229,18 @@ impl Insn {
             Insn::Copy { .. } => execute::op_copy,
             Insn::CreateBtree { .. } => execute::op_create_btree,
             Insn::Destroy { .. } => execute::op_destroy,
+            Insn::Clear { .. } => execute::op_clear,
 
             Insn::DropTable { .. } => execute::op_drop_table,
             Insn::Close { .. } => execute::op_close,
             Insn::IsNull { .. } => execute::op_is_null,
             Insn::ParseSchema { .. } => execute::op_parse_schema,
+            Insn::Vacuum { .. } => execute::op_vacuum,
+            Insn::StatInit { .. } => execute::op_stat_init,
+            Insn::StatPush { .. } => execute::op_stat_push,
+            Insn::StatGet { .. } => execute::op_stat_get,
+            Insn::StatNextSample { .. } => execute::op_stat_next_sample,
+            Insn::LoadAnalysis { .. } => execute::op_load_analysis,
             Insn::ShiftRight { .. } => execute::op_shift_right,
             Insn::ShiftLeft { .. } => execute::op_shift_left,
             Insn::Variable { .. } => execute::op_variable,
@@ -1111,6 +1254,7 @@ impl Insn {
             Insn::ReadCookie { .. } => execute::op_read_cookie,
             Insn::SetCookie { .. } => execute::op_set_cookie,
             Insn::OpenEphemeral { .. } | Insn::OpenAutoindex { .. } => execute::op_open_ephemeral,
+            Insn::OpenDup { .. } => execute::op_open_dup,
             Insn::Once { .. } => execute::op_once,
             Insn::Found { .. } | Insn::NotFound { .. } => execute::op_found,
             Insn::Affinity { .. } => execute::op_affinity,
@@ -1121,6 +1265,16 @@ impl Insn {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SavepointOp {
+    /// SAVEPOINT: open a savepoint.
+    Begin = 0,
+    /// RELEASE: close a savepoint and the savepoints opened after it.
+    Release = 1,
+    /// ROLLBACK TO: undo the changes made since a savepoint was opened.
+    RollbackTo = 2,
+}
+
 // TODO: Add remaining cookies.
 #[derive(Description, Debug, Clone, Copy)]
 pub enum Cookie {
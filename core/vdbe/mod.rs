@@ -35,6 +35,7 @@ use crate::{
 };
 
 use crate::{
+    analyze::StatAccum,
     storage::pager::Pager,
     translate::plan::ResultSetColumn,
     types::{AggContext, Cursor, ImmutableRecord, Value},
@@ -43,6 +44,7 @@ use crate::{
 
 #[cfg(feature = "json")]
 use crate::json::JsonCacheCell;
+use crate::util::IOExt;
 use crate::{Connection, MvStore, Result, TransactionState};
 use builder::CursorKey;
 use execute::{
@@ -59,6 +61,7 @@ use std::{
     sync::Arc,
 };
 use tracing::{instrument, Level};
+use turso_sqlite3_parser::ast::ResolveType;
 
 /// We use labels to indicate that we want to jump to whatever the instruction offset
 /// will be at runtime, because the offset cannot always be determined when the jump
@@ -221,6 +224,8 @@ pub enum Register {
     Value(Value),
     Aggregate(AggContext),
     Record(ImmutableRecord),
+    /// The statistics ANALYZE accumulates for an index.
+    StatAccum(Box<StatAccum>),
 }
 
 /// A row is a the list of registers that hold the values for a filtered row. This row is a pointer, therefore
@@ -256,6 +261,25 @@ pub struct ProgramState {
     op_idx_insert_state: OpIdxInsertState,
     op_insert_state: OpInsertState,
     seek_state: OpSeekState,
+    /// State of the trigger sub-program run by the current [Insn::Program].
+    op_program_state: Option<Box<ProgramState>>,
+    /// Whether this state belongs to a trigger sub-program.
+    is_subprogram: bool,
+    /// Set when a trigger sub-program was halted by RAISE(IGNORE).
+    halted_by_ignore: bool,
+    /// How much of the transaction an error undoes, set by the constraint that failed.
+    pub(crate) on_error: ResolveType,
+    /// The depth of the pager savepoint that undoes the statement when it fails inside an
+    /// explicit transaction.
+    pub(crate) statement_savepoint: Option<usize>,
+    /// The number of violations of immediate foreign key constraints by the statement, which
+    /// fails when it halts with any left.
+    pub(crate) fk_immediate_violations: i64,
+    /// The number of deferred foreign key violations of the transaction when the statement
+    /// started, restored when the statement is undone.
+    pub(crate) fk_deferred_violations_start: i64,
+    /// The number of sub-programs running the one this state belongs to.
+    pub(crate) program_depth: usize,
 }
 
 impl ProgramState {
@@ -286,6 +310,14 @@ impl ProgramState {
             op_idx_insert_state: OpIdxInsertState::SeekIfUnique,
             op_insert_state: OpInsertState::Insert,
             seek_state: OpSeekState::Start,
+            op_program_state: None,
+            is_subprogram: false,
+            halted_by_ignore: false,
+            on_error: ResolveType::Abort,
+            statement_savepoint: None,
+            fk_immediate_violations: 0,
+            fk_deferred_violations_start: 0,
+            program_depth: 0,
         }
     }
 
@@ -322,9 +354,16 @@ impl ProgramState {
         self.last_compare = None;
         self.deferred_seeks.iter_mut().for_each(|s| *s = None);
         self.ended_coroutine.0 = [0; 4];
+        self.once = SmallVec::new();
         self.regex_cache.like.clear();
         self.interrupted = false;
         self.parameters.clear();
+        self.op_program_state = None;
+        self.halted_by_ignore = false;
+        self.on_error = ResolveType::Abort;
+        self.statement_savepoint = None;
+        self.fk_immediate_violations = 0;
+        self.fk_deferred_violations_start = 0;
         #[cfg(feature = "json")]
         self.json_cache.clear()
     }
@@ -381,6 +420,15 @@ pub struct Program {
     pub table_references: TableReferences,
 }
 
+impl std::fmt::Debug for Program {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Program")
+            .field("max_registers", &self.max_registers)
+            .field("insns", &self.insns.len())
+            .finish()
+    }
+}
+
 impl Program {
     fn get_pager_from_database_index(&self, idx: &usize) -> Rc<Pager> {
         self.connection.get_pager_from_database_index(idx)
@@ -417,7 +465,14 @@ impl Program {
                 Ok(InsnFunctionStepResult::Interrupt) => return Ok(StepResult::Interrupt),
                 Ok(InsnFunctionStepResult::Busy) => return Ok(StepResult::Busy),
                 Err(err) => {
-                    handle_program_error(&pager, &self.connection, &err)?;
+                    handle_program_error(
+                        &pager,
+                        &self.connection,
+                        &err,
+                        state.on_error,
+                        state.statement_savepoint.take(),
+                        state.fk_deferred_violations_start,
+                    )?;
                     return Err(err);
                 }
             }
@@ -437,10 +492,23 @@ impl Program {
             let auto_commit = conn.auto_commit.get();
             if auto_commit {
                 let mut mv_transactions = conn.mv_transactions.borrow_mut();
-                for tx_id in mv_transactions.iter() {
-                    mv_store.commit_tx(*tx_id).unwrap();
+                let mut result = Ok(());
+                for tx_id in mv_transactions.drain(..) {
+                    if rollback || result.is_err() {
+                        mv_store.rollback_tx(tx_id);
+                    } else if let Err(e) = mv_store.commit_tx(tx_id) {
+                        // The failed transaction is rolled back already, the rest of the
+                        // connection's transactions are rolled back with it.
+                        result = Err(e);
+                    }
+                }
+                drop(mv_transactions);
+                let rollback = rollback || result.is_err();
+                conn.end_database_transactions(rollback)?;
+                result?;
+                if !rollback && mv_store.needs_checkpoint() {
+                    conn.checkpoint_mv_store(mv_store)?;
                 }
-                mv_transactions.clear();
             }
             Ok(StepResult::Done)
         } else {
@@ -478,6 +546,7 @@ impl Program {
                     TransactionState::Read => {
                         connection.transaction_state.replace(TransactionState::None);
                         pager.end_read_tx()?;
+                        connection.end_database_transactions(rollback)?;
                         Ok(StepResult::Done)
                     }
                     TransactionState::None => Ok(StepResult::Done),
@@ -511,10 +580,13 @@ impl Program {
                 if self.change_cnt_on {
                     self.connection.set_changes(self.n_change.get());
                 }
-                if matches!(status, pager::PagerCommitResult::Rollback) {
+                let rollback = matches!(status, pager::PagerCommitResult::Rollback);
+                if rollback {
                     pager.rollback(schema_did_change, connection)?;
                 }
+                connection.end_database_transactions(rollback)?;
                 connection.transaction_state.replace(TransactionState::None);
+                connection.end_fk_transaction();
                 *commit_state = CommitState::Ready;
             }
             IOResult::IO => {
@@ -747,28 +819,97 @@ impl Row {
     }
 }
 
-/// Handle a program error by rolling back the transaction
+/// Handle a program error by undoing what `on_error` asks for: the failed statement, if it has a
+/// savepoint (ABORT), nothing (FAIL) or the whole transaction (ROLLBACK).
 pub fn handle_program_error(
     pager: &Rc<Pager>,
     connection: &Connection,
     err: &LimboError,
+    on_error: ResolveType,
+    statement_savepoint: Option<usize>,
+    fk_deferred_violations_start: i64,
 ) -> Result<()> {
     match err {
         LimboError::TxError(_) => {}
-        _ => {
-            let state = connection.transaction_state.get();
-            if let TransactionState::Write { schema_did_change } = state {
+        _ => match connection.transaction_state.get() {
+            TransactionState::Write { schema_did_change } => {
+                let auto_commit = connection.auto_commit.get();
+                match (on_error, statement_savepoint) {
+                    (ResolveType::Rollback, _) => {}
+                    (ResolveType::Fail, Some(depth)) => {
+                        for pager in connection.savepoint_pagers() {
+                            pager.release_savepoint(depth);
+                        }
+                        return Ok(());
+                    }
+                    (ResolveType::Fail, None) if auto_commit => {
+                        // The changes made before the failure are committed as if the statement
+                        // had succeeded.
+                        connection.end_fk_transaction();
+                        if let Err(e) = pager.io.block(|| {
+                            pager.end_tx(
+                                false,
+                                schema_did_change,
+                                connection,
+                                connection.wal_checkpoint_disabled.get(),
+                            )
+                        }) {
+                            tracing::error!("end_tx failed: {e}");
+                        }
+                        if let Err(e) = connection.end_database_transactions(false) {
+                            tracing::error!("end_tx failed: {e}");
+                        }
+                        connection.transaction_state.replace(TransactionState::None);
+                        return Ok(());
+                    }
+                    (_, Some(depth)) => {
+                        let pagers = connection.savepoint_pagers();
+                        match pagers
+                            .iter()
+                            .try_for_each(|pager| pager.rollback_to_savepoint(depth))
+                        {
+                            Ok(()) => {
+                                for pager in pagers {
+                                    pager.release_savepoint(depth);
+                                }
+                                connection
+                                    .fk_deferred_violations
+                                    .set(fk_deferred_violations_start);
+                                return Ok(());
+                            }
+                            Err(e) => {
+                                tracing::error!("rollback to statement savepoint failed: {e}")
+                            }
+                        }
+                    }
+                    // A statement of an explicit transaction that has no savepoint did not write.
+                    (_, None) if !auto_commit => return Ok(()),
+                    (_, None) => {}
+                }
                 if let Err(e) = pager.rollback(schema_did_change, connection) {
                     tracing::error!("rollback failed: {e}");
                 }
                 if let Err(e) = pager.end_tx(false, schema_did_change, connection, false) {
                     tracing::error!("end_tx failed: {e}");
                 }
-            } else if let Err(e) = pager.end_read_tx() {
-                tracing::error!("end_read_tx failed: {e}");
+                if let Err(e) = connection.end_database_transactions(true) {
+                    tracing::error!("rollback failed: {e}");
+                }
+                connection.transaction_state.replace(TransactionState::None);
+                connection.auto_commit.replace(true);
+                connection.end_fk_transaction();
             }
-            connection.transaction_state.replace(TransactionState::None);
-        }
+            TransactionState::Read => {
+                if let Err(e) = pager.end_read_tx() {
+                    tracing::error!("end_read_tx failed: {e}");
+                }
+                if let Err(e) = connection.end_database_transactions(true) {
+                    tracing::error!("end_read_tx failed: {e}");
+                }
+                connection.transaction_state.replace(TransactionState::None);
+            }
+            TransactionState::None => {}
+        },
     }
     Ok(())
 }
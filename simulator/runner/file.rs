@@ -229,6 +229,14 @@ impl File for SimulatorFile {
     fn size(&self) -> Result<u64> {
         self.inner.size()
     }
+
+    fn truncate(
+        &self,
+        len: usize,
+        c: Arc<turso_core::Completion>,
+    ) -> Result<Arc<turso_core::Completion>> {
+        self.inner.truncate(len, c)
+    }
 }
 
 impl Drop for SimulatorFile {
@@ -147,6 +147,16 @@ def test_aggregates():
         validate_median_odd,
         "median agg function works with odd number of elements",
     )
+    limbo.run_test_fn(
+        "select id % 2, median(value) from numbers group by id % 2;",
+        lambda res: res == "0|5.0\n1|4.0",
+        "median agg function works with GROUP BY",
+    )
+    limbo.run_test_fn(
+        "select id % 2, median(value) filter (where value > 2) from numbers group by id % 2;",
+        lambda res: res == "0|6.0\n1|5.0",
+        "median agg function works with FILTER and GROUP BY",
+    )
     limbo.run_test_fn(
         "SELECT percentile(value, percent) from test;",
         validate_percentile1,
@@ -1,3 +1,4 @@
+mod test_analyze;
 mod test_btree;
 mod test_read_path;
 mod test_write_path;
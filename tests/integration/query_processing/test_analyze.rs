@@ -0,0 +1,73 @@
+use crate::common::{limbo_exec_rows, sqlite_exec_rows, TempDatabase};
+
+const SETUP: &[&str] = &[
+    "CREATE TABLE t(a INTEGER PRIMARY KEY, b, c TEXT)",
+    "CREATE INDEX tb ON t(b)",
+    "CREATE INDEX tbc ON t(b, c)",
+    "CREATE UNIQUE INDEX tc ON t(c)",
+    "CREATE TABLE u(x)",
+    "CREATE TABLE empty(y)",
+    "CREATE TABLE w(p, q, r, PRIMARY KEY(p, q)) WITHOUT ROWID",
+    "CREATE INDEX wr ON w(r)",
+    "WITH RECURSIVE s(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM s WHERE i < 1000) \
+     INSERT INTO t SELECT i, i % 7 * (i % 13), 'v' || (i % 500) || '-' || i FROM s",
+    "INSERT INTO t(a, b, c) VALUES (2000, NULL, NULL), (2001, NULL, NULL)",
+    "INSERT INTO u VALUES (1), (2), (3)",
+    "WITH RECURSIVE s(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM s WHERE i < 300) \
+     INSERT INTO w SELECT i % 10, i, i % 3 FROM s",
+];
+
+const STAT_QUERIES: &[&str] = &[
+    "SELECT tbl, idx, stat FROM sqlite_stat1 ORDER BY tbl, idx",
+    "SELECT tbl, idx, neq, nlt, ndlt, sample FROM sqlite_stat4 ORDER BY tbl, idx, sample",
+];
+
+#[test]
+fn test_analyze_statistics_match_sqlite() {
+    let tmp_db = TempDatabase::new_empty(true);
+    let conn = tmp_db.connect_limbo();
+    for sql in SETUP {
+        limbo_exec_rows(&tmp_db, &conn, sql);
+    }
+    limbo_exec_rows(&tmp_db, &conn, "ANALYZE");
+
+    let sqlite_conn = rusqlite::Connection::open_in_memory().unwrap();
+    for sql in SETUP {
+        sqlite_conn.execute(sql, ()).unwrap();
+    }
+    sqlite_conn.execute("ANALYZE", ()).unwrap();
+
+    for query in STAT_QUERIES {
+        let limbo_rows = limbo_exec_rows(&tmp_db, &conn, query);
+        let sqlite_rows = sqlite_exec_rows(&sqlite_conn, query);
+        assert_eq!(limbo_rows, sqlite_rows, "query={query}");
+    }
+}
+
+#[test]
+fn test_analyze_table_replaces_its_statistics() {
+    let tmp_db = TempDatabase::new_empty(true);
+    let conn = tmp_db.connect_limbo();
+    for sql in SETUP {
+        limbo_exec_rows(&tmp_db, &conn, sql);
+    }
+    limbo_exec_rows(&tmp_db, &conn, "ANALYZE");
+    limbo_exec_rows(&tmp_db, &conn, "INSERT INTO u VALUES (4)");
+    limbo_exec_rows(&tmp_db, &conn, "ANALYZE u");
+    limbo_exec_rows(&tmp_db, &conn, "ANALYZE main.tb");
+
+    let sqlite_conn = rusqlite::Connection::open_in_memory().unwrap();
+    for sql in SETUP {
+        sqlite_conn.execute(sql, ()).unwrap();
+    }
+    sqlite_conn.execute("ANALYZE", ()).unwrap();
+    sqlite_conn.execute("INSERT INTO u VALUES (4)", ()).unwrap();
+    sqlite_conn.execute("ANALYZE u", ()).unwrap();
+    sqlite_conn.execute("ANALYZE main.tb", ()).unwrap();
+
+    for query in STAT_QUERIES {
+        let limbo_rows = limbo_exec_rows(&tmp_db, &conn, query);
+        let sqlite_rows = sqlite_exec_rows(&sqlite_conn, query);
+        assert_eq!(limbo_rows, sqlite_rows, "query={query}");
+    }
+}
@@ -703,3 +703,35 @@ fn test_bind_parameters_delete_rowid_alias_seek_out_of_order() -> anyhow::Result
     assert_eq!(ins.parameters().count(), 4);
     Ok(())
 }
+
+#[test]
+fn test_bind_parameters_subquery_reset() -> anyhow::Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite("CREATE TABLE test (i integer);", false);
+    let conn = tmp_db.connect_limbo();
+    conn.execute("insert into test values (1), (2), (3);")?;
+
+    // The parameters are numbered in the order they come in the statement, and the subquery is
+    // evaluated again after a reset.
+    let mut sel = conn.prepare("select ? in (select i from test where i > ?);")?;
+    for (lhs, min, expected) in [(2, 1, 1), (2, 2, 0), (3, 2, 1)] {
+        sel.reset();
+        sel.bind_at(1.try_into()?, Value::Integer(lhs));
+        sel.bind_at(2.try_into()?, Value::Integer(min));
+        let mut rows = 0;
+        loop {
+            match sel.step()? {
+                StepResult::Row => {
+                    let row = sel.row().unwrap();
+                    assert_eq!(row.get::<&Value>(0).unwrap(), &Value::Integer(expected));
+                    rows += 1;
+                }
+                StepResult::IO => sel.run_once()?,
+                StepResult::Done | StepResult::Interrupt => break,
+                StepResult::Busy => panic!("database busy"),
+            }
+        }
+        assert_eq!(rows, 1);
+    }
+    assert_eq!(sel.parameters().count(), 2);
+    Ok(())
+}
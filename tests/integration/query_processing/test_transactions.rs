@@ -1,6 +1,7 @@
-use turso_core::{LimboError, Result, StepResult, Value};
+use std::sync::Arc;
+use turso_core::{Connection, Database, LimboError, OpenFlags, Result, StepResult, Value};
 
-use crate::common::TempDatabase;
+use crate::common::{limbo_exec_rows, limbo_exec_rows_error, TempDatabase};
 
 #[test]
 fn test_txn_error_doesnt_rollback_txn() -> Result<()> {
@@ -24,3 +25,353 @@ fn test_txn_error_doesnt_rollback_txn() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_conflict_resolution_undoes_statement_or_transaction() {
+    let tmp_db =
+        TempDatabase::new_with_rusqlite("create table t (x integer primary key, y unique);", true);
+    let conn = tmp_db.connect_limbo();
+    let rows = |conn| {
+        limbo_exec_rows(&tmp_db, conn, "select x from t order by x")
+            .into_iter()
+            .map(|row| match row[0] {
+                rusqlite::types::Value::Integer(x) => x,
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>()
+    };
+
+    conn.execute("begin").unwrap();
+    conn.execute("insert into t values (1, 1)").unwrap();
+    // FAIL keeps the rows inserted before the conflict.
+    assert!(limbo_exec_rows_error(
+        &tmp_db,
+        &conn,
+        "insert or fail into t values (2, 2), (3, 1), (4, 4)"
+    )
+    .is_err());
+    assert_eq!(rows(&conn), vec![1, 2]);
+    // ABORT undoes the statement only.
+    assert!(limbo_exec_rows_error(
+        &tmp_db,
+        &conn,
+        "insert or abort into t values (5, 5), (6, 1)"
+    )
+    .is_err());
+    assert_eq!(rows(&conn), vec![1, 2]);
+    assert!(!conn.get_auto_commit());
+    // ROLLBACK undoes the whole transaction.
+    assert!(limbo_exec_rows_error(
+        &tmp_db,
+        &conn,
+        "insert or rollback into t values (7, 7), (8, 1)"
+    )
+    .is_err());
+    assert!(conn.get_auto_commit());
+    assert_eq!(rows(&conn), Vec::<i64>::new());
+}
+
+#[test]
+fn test_statement_rollback_in_attached_database() {
+    let tmp_db = TempDatabase::new_with_rusqlite("create table t (x);", false);
+    let aux = tmp_db.path.with_file_name("aux.db");
+    let conn = tmp_db.connect_limbo();
+    conn.execute(format!("attach '{}' as aux", aux.display()))
+        .unwrap();
+    conn.execute("create table aux.t (x integer primary key)")
+        .unwrap();
+    let rows = |conn| {
+        limbo_exec_rows(&tmp_db, conn, "select x from aux.t order by x")
+            .into_iter()
+            .map(|row| match row[0] {
+                rusqlite::types::Value::Integer(x) => x,
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>()
+    };
+
+    conn.execute("begin").unwrap();
+    conn.execute("insert into t values (1)").unwrap();
+    conn.execute("insert into aux.t values (1)").unwrap();
+    // The failing statement only undoes its own rows, in the attached database too.
+    assert!(
+        limbo_exec_rows_error(&tmp_db, &conn, "insert into aux.t values (2), (3), (1)").is_err()
+    );
+    assert_eq!(rows(&conn), vec![1]);
+    assert!(!conn.get_auto_commit());
+    conn.execute("savepoint s").unwrap();
+    conn.execute("insert into aux.t values (4)").unwrap();
+    conn.execute("rollback to s").unwrap();
+    conn.execute("insert into aux.t values (5)").unwrap();
+    conn.execute("commit").unwrap();
+    assert_eq!(rows(&conn), vec![1, 5]);
+}
+
+#[test]
+fn test_transaction_across_attached_databases() {
+    let tmp_db = TempDatabase::new_with_rusqlite("create table t (x);", false);
+    let dir = tmp_db.path.parent().unwrap();
+    let shards = ["shard1.db", "shard2.db"].map(|name| dir.join(name));
+    for shard in &shards {
+        let conn = rusqlite::Connection::open(shard).unwrap();
+        conn.pragma_update(None, "journal_mode", "wal").unwrap();
+        conn.execute("create table tenant (id integer primary key, name)", ())
+            .unwrap();
+    }
+    let conn = tmp_db.connect_limbo();
+    for (i, shard) in shards.iter().enumerate() {
+        conn.execute(format!("attach '{}' as s{}", shard.display(), i + 1))
+            .unwrap();
+    }
+    let rows = |shard: &std::path::Path| {
+        let conn = rusqlite::Connection::open(shard).unwrap();
+        let mut stmt = conn.prepare("select id from tenant order by id").unwrap();
+        stmt.query_map((), |row| row.get::<_, i64>(0))
+            .unwrap()
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .unwrap()
+    };
+
+    conn.execute("insert into s1.tenant values (1, 'a'), (2, 'b')")
+        .unwrap();
+    conn.execute("begin").unwrap();
+    conn.execute("insert into s2.tenant select * from s1.tenant where id = 2")
+        .unwrap();
+    conn.execute("delete from s1.tenant where id = 2").unwrap();
+    conn.execute("insert into t values (1)").unwrap();
+    conn.execute("commit").unwrap();
+    assert_eq!(rows(&shards[0]), vec![1]);
+    assert_eq!(rows(&shards[1]), vec![2]);
+
+    conn.execute("begin").unwrap();
+    conn.execute("delete from s1.tenant").unwrap();
+    conn.execute("insert into s2.tenant values (3, 'c')")
+        .unwrap();
+    conn.execute("rollback").unwrap();
+    assert_eq!(rows(&shards[0]), vec![1]);
+    assert_eq!(rows(&shards[1]), vec![2]);
+    assert_eq!(
+        limbo_exec_rows(&tmp_db, &conn, "select count(*) from s1.tenant"),
+        vec![vec![rusqlite::types::Value::Integer(1)]]
+    );
+}
+
+#[test]
+fn test_mvcc_commits_survive_reopen_and_checkpoint() {
+    let tmp_db = TempDatabase::new_empty(false);
+    let open = || {
+        Database::open_file_with_flags(
+            tmp_db.io.clone(),
+            tmp_db.path.to_str().unwrap(),
+            OpenFlags::default(),
+            true,
+            false,
+        )
+        .unwrap()
+    };
+    let rows = |db: &Arc<Database>| {
+        let conn = db.connect().unwrap();
+        limbo_exec_rows(&tmp_db, &conn, "select x, y from t")
+    };
+    let expected = |n: i64| {
+        (1..=n)
+            .map(|i| {
+                vec![
+                    rusqlite::types::Value::Integer(i),
+                    rusqlite::types::Value::Text(format!("row {i}")),
+                ]
+            })
+            .collect::<Vec<_>>()
+    };
+
+    {
+        let db = open();
+        let conn = db.connect().unwrap();
+        conn.execute("create table t (x, y)").unwrap();
+        conn.execute("insert into t values (1, 'row 1'), (2, 'row 2')")
+            .unwrap();
+        conn.execute("begin").unwrap();
+        conn.execute("insert into t values (3, 'row 3')").unwrap();
+        conn.execute("commit").unwrap();
+        conn.execute("begin").unwrap();
+        conn.execute("insert into t values (4, 'row 4')").unwrap();
+        conn.execute("rollback").unwrap();
+    }
+    {
+        let db = open();
+        assert_eq!(rows(&db), expected(3));
+        let conn = db.connect().unwrap();
+        conn.execute("pragma wal_checkpoint").unwrap();
+        conn.execute("insert into t values (4, 'row 4')").unwrap();
+    }
+    let db = open();
+    assert_eq!(rows(&db), expected(4));
+    drop(db);
+
+    // The checkpoint wrote the first rows to the b-tree file, the last one is only in the log.
+    let conn = rusqlite::Connection::open(&tmp_db.path).unwrap();
+    let mut stmt = conn.prepare("select x from t order by x").unwrap();
+    let checkpointed = stmt
+        .query_map((), |row| row.get::<_, i64>(0))
+        .unwrap()
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(checkpointed, vec![1, 2, 3]);
+}
+
+#[test]
+fn test_mvcc_serializable_isolation_prevents_write_skew() {
+    let tmp_db = TempDatabase::new_empty(false);
+    let open = || {
+        Database::open_file_with_flags(
+            tmp_db.io.clone(),
+            tmp_db.path.to_str().unwrap(),
+            OpenFlags::default(),
+            true,
+            false,
+        )
+        .unwrap()
+    };
+    {
+        let db = open();
+        let conn = db.connect().unwrap();
+        conn.execute("create table reservations (item, guest)")
+            .unwrap();
+    }
+    let db = open();
+    let guests = |conn: &Arc<Connection>, item: i64| {
+        limbo_exec_rows(
+            &tmp_db,
+            conn,
+            &format!("select guest from reservations where item = {item}"),
+        )
+    };
+    // Each item can be reserved by one guest only.
+    let reserve = |conn: &Arc<Connection>, item: i64, guest: &str| {
+        conn.execute("begin").unwrap();
+        assert!(guests(conn, item).is_empty());
+        conn.execute(format!(
+            "insert into reservations values ({item}, '{guest}')"
+        ))
+        .unwrap();
+    };
+
+    // Under snapshot isolation, both reservations commit.
+    let conn1 = db.connect().unwrap();
+    let conn2 = db.connect().unwrap();
+    reserve(&conn1, 1, "alice");
+    reserve(&conn2, 1, "bob");
+    conn1.execute("commit").unwrap();
+    conn2.execute("commit").unwrap();
+    assert_eq!(guests(&conn1, 1).len(), 2);
+
+    conn1
+        .execute("pragma isolation_level = serializable")
+        .unwrap();
+    conn2
+        .execute("pragma isolation_level = 'serializable'")
+        .unwrap();
+    assert_eq!(
+        limbo_exec_rows(&tmp_db, &conn2, "pragma isolation_level"),
+        vec![vec![rusqlite::types::Value::Text(
+            "serializable".to_string()
+        )]]
+    );
+    reserve(&conn1, 2, "alice");
+    reserve(&conn2, 2, "bob");
+    conn1.execute("commit").unwrap();
+    assert!(matches!(conn2.execute("commit"), Err(LimboError::Busy)));
+    // The transaction was rolled back, and a retry sees the reservation.
+    assert_eq!(
+        guests(&conn2, 2),
+        vec![vec![rusqlite::types::Value::Text("alice".to_string())]]
+    );
+    conn2
+        .execute("insert into reservations values (3, 'bob')")
+        .unwrap();
+    assert_eq!(guests(&conn1, 3).len(), 1);
+}
+
+#[test]
+fn test_mvcc_indexes() {
+    let tmp_db = TempDatabase::new_empty(true);
+    let open = || {
+        Database::open_file_with_flags(
+            tmp_db.io.clone(),
+            tmp_db.path.to_str().unwrap(),
+            OpenFlags::default(),
+            true,
+            true,
+        )
+        .unwrap()
+    };
+    let emails = |db: &Arc<Database>| {
+        let conn = db.connect().unwrap();
+        limbo_exec_rows(
+            &tmp_db,
+            &conn,
+            "select email from users where age >= 30 order by age",
+        )
+    };
+    let text = |s: &str| vec![rusqlite::types::Value::Text(s.to_string())];
+
+    {
+        let db = open();
+        let conn = db.connect().unwrap();
+        conn.execute("create table users (id integer primary key, email text unique, age)")
+            .unwrap();
+        conn.execute("create index users_age on users (age)")
+            .unwrap();
+        conn.execute("insert into users values (1, 'a@x', 50), (2, 'b@x', 40), (3, 'c@x', 30)")
+            .unwrap();
+        assert_eq!(emails(&db), vec![text("c@x"), text("b@x"), text("a@x")]);
+        assert_eq!(
+            limbo_exec_rows(&tmp_db, &conn, "select id from users where email = 'b@x'"),
+            vec![vec![rusqlite::types::Value::Integer(2)]]
+        );
+        assert!(conn
+            .execute("insert into users values (4, 'a@x', 60)")
+            .is_err());
+
+        // Neither transaction sees the other's email, but only the first one gets it.
+        let conn1 = db.connect().unwrap();
+        let conn2 = db.connect().unwrap();
+        conn1.execute("begin").unwrap();
+        conn2.execute("begin").unwrap();
+        conn1
+            .execute("insert into users values (4, 'd@x', 60)")
+            .unwrap();
+        assert!(matches!(
+            conn2.execute("insert into users values (5, 'd@x', 70)"),
+            Err(LimboError::TxError(_))
+        ));
+        conn2.execute("rollback").unwrap();
+        conn1.execute("commit").unwrap();
+        conn2
+            .execute("insert into users values (5, 'e@x', 70)")
+            .unwrap();
+    }
+    let expected = vec![
+        text("c@x"),
+        text("b@x"),
+        text("a@x"),
+        text("d@x"),
+        text("e@x"),
+    ];
+    {
+        let db = open();
+        assert_eq!(emails(&db), expected);
+        let conn = db.connect().unwrap();
+        conn.execute("pragma wal_checkpoint").unwrap();
+    }
+    let db = open();
+    assert_eq!(emails(&db), expected);
+    drop(db);
+
+    // The checkpoint wrote the index entries along with the rows.
+    let conn = rusqlite::Connection::open(&tmp_db.path).unwrap();
+    let integrity: String = conn
+        .query_row("pragma integrity_check", (), |row| row.get(0))
+        .unwrap();
+    assert_eq!(integrity, "ok");
+}
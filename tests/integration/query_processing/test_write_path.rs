@@ -766,6 +766,155 @@ fn test_read_wal_dumb_no_frames() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_vacuum_shrinks_database_file() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db =
+        TempDatabase::new_with_rusqlite("CREATE TABLE t (x INTEGER PRIMARY KEY, y TEXT);", true);
+    let conn = tmp_db.connect_limbo();
+    conn.execute("CREATE INDEX t_y ON t (y)")?;
+    conn.execute("INSERT INTO t SELECT value, randomblob(500) FROM generate_series(1, 1000)")?;
+    conn.execute("DELETE FROM t WHERE x > 10")?;
+    conn.checkpoint()?;
+    let size_before = std::fs::metadata(&tmp_db.path)?.len();
+
+    conn.execute("VACUUM")?;
+    conn.checkpoint()?;
+    let size_after = std::fs::metadata(&tmp_db.path)?.len();
+    assert!(
+        size_after < size_before / 10,
+        "{size_after} >= {size_before} / 10"
+    );
+
+    // The connection keeps working with the moved root pages.
+    conn.execute("INSERT INTO t VALUES (11, 'a')")?;
+    let mut count = 0;
+    run_query_on_row(&tmp_db, &conn, "SELECT count(*) FROM t", |row| {
+        count = row.get::<i64>(0).unwrap();
+    })?;
+    assert_eq!(count, 11);
+    conn.close()?;
+
+    let rusqlite_conn = rusqlite::Connection::open(&tmp_db.path)?;
+    let integrity: String =
+        rusqlite_conn.query_row("PRAGMA integrity_check", (), |row| row.get(0))?;
+    assert_eq!(integrity, "ok");
+    let freelist_count: i64 =
+        rusqlite_conn.query_row("PRAGMA freelist_count", (), |row| row.get(0))?;
+    assert_eq!(freelist_count, 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_vacuum_into() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite("CREATE TABLE t (x, y);", true);
+    let conn = tmp_db.connect_limbo();
+    conn.execute("INSERT INTO t SELECT value, value * 2 FROM generate_series(1, 100)")?;
+    conn.execute("CREATE VIEW v AS SELECT x FROM t WHERE y > 100")?;
+    conn.execute("PRAGMA user_version = 42")?;
+    let copy_path = tmp_db.path.with_file_name("copy.db");
+
+    conn.execute(format!("VACUUM INTO '{}'", copy_path.display()))?;
+    assert!(!copy_path.with_file_name("copy.db-wal").exists());
+    // The output file must not exist yet.
+    assert!(matches!(
+        conn.execute(format!("VACUUM INTO '{}'", copy_path.display())),
+        Err(LimboError::InvalidArgument(_))
+    ));
+
+    let rusqlite_conn = rusqlite::Connection::open(&copy_path)?;
+    let integrity: String =
+        rusqlite_conn.query_row("PRAGMA integrity_check", (), |row| row.get(0))?;
+    assert_eq!(integrity, "ok");
+    let (count, sum): (i64, i64) =
+        rusqlite_conn.query_row("SELECT count(*), sum(y) FROM t", (), |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+    assert_eq!((count, sum), (100, 10100));
+    let view_count: i64 =
+        rusqlite_conn.query_row("SELECT count(*) FROM v", (), |row| row.get(0))?;
+    assert_eq!(view_count, 50);
+    let user_version: i64 = rusqlite_conn.query_row("PRAGMA user_version", (), |row| row.get(0))?;
+    assert_eq!(user_version, 42);
+
+    Ok(())
+}
+
+#[test]
+fn test_vacuum_generated_columns() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_empty(true);
+    let conn = tmp_db.connect_limbo();
+    conn.execute("CREATE TABLE g (a, b AS (a * 2) VIRTUAL, c AS (a + 1) STORED, d)")?;
+    conn.execute("INSERT INTO g (a, d) SELECT value, 'x' FROM generate_series(1, 10)")?;
+    let copy_path = tmp_db.path.with_file_name("copy.db");
+
+    conn.execute("VACUUM")?;
+    conn.execute(format!("VACUUM INTO '{}'", copy_path.display()))?;
+    let mut sums = (0, 0, 0);
+    run_query_on_row(
+        &tmp_db,
+        &conn,
+        "SELECT sum(a), sum(b), sum(c) FROM g WHERE d = 'x'",
+        |row| {
+            sums = (
+                row.get::<i64>(0).unwrap(),
+                row.get::<i64>(1).unwrap(),
+                row.get::<i64>(2).unwrap(),
+            );
+        },
+    )?;
+    assert_eq!(sums, (55, 110, 65));
+
+    let rusqlite_conn = rusqlite::Connection::open(&copy_path)?;
+    let integrity: String =
+        rusqlite_conn.query_row("PRAGMA integrity_check", (), |row| row.get(0))?;
+    assert_eq!(integrity, "ok");
+    let copied: (i64, i64, i64) = rusqlite_conn.query_row(
+        "SELECT sum(a), sum(b), sum(c) FROM g WHERE d = 'x'",
+        (),
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )?;
+    assert_eq!(copied, (55, 110, 65));
+
+    Ok(())
+}
+
+#[test]
+fn test_vacuum_with_temp_table_of_same_name() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite("CREATE TABLE t (x, y);", true);
+    let conn = tmp_db.connect_limbo();
+    conn.execute("INSERT INTO t SELECT value, 'main' FROM generate_series(1, 10)")?;
+    conn.execute("CREATE TEMP TABLE t (z)")?;
+    conn.execute("INSERT INTO t VALUES ('temp')")?;
+    let copy_path = tmp_db.path.with_file_name("copy.db");
+
+    conn.execute("VACUUM")?;
+    conn.execute(format!("VACUUM INTO '{}'", copy_path.display()))?;
+    let mut count = 0;
+    run_query_on_row(
+        &tmp_db,
+        &conn,
+        "SELECT count(*) FROM main.t WHERE y = 'main'",
+        |row| {
+            count = row.get::<i64>(0).unwrap();
+        },
+    )?;
+    assert_eq!(count, 10);
+
+    let rusqlite_conn = rusqlite::Connection::open(&copy_path)?;
+    let copied: i64 =
+        rusqlite_conn.query_row("SELECT count(*) FROM t WHERE y = 'main'", (), |row| {
+            row.get(0)
+        })?;
+    assert_eq!(copied, 10);
+
+    Ok(())
+}
+
 pub fn run_query(tmp_db: &TempDatabase, conn: &Arc<Connection>, query: &str) -> anyhow::Result<()> {
     run_query_core(tmp_db, conn, query, None::<fn(&Row)>)
 }
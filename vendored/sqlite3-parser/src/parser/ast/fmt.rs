@@ -856,6 +856,30 @@ impl ToTokens for Expr {
                 query.to_tokens_with_context(s, context)?;
                 s.append(TK_RP, None)
             }
+            Self::SubqueryResult {
+                subquery_id,
+                lhs,
+                not_in,
+                query_type,
+                ..
+            } => {
+                match query_type {
+                    SubqueryType::Exists => s.append(TK_EXISTS, None)?,
+                    SubqueryType::In => {
+                        if let Some(lhs) = lhs {
+                            lhs.to_tokens_with_context(s, context)?;
+                        }
+                        if *not_in {
+                            s.append(TK_NOT, None)?;
+                        }
+                        s.append(TK_IN, None)?;
+                    }
+                    SubqueryType::Scalar => {}
+                }
+                s.append(TK_LP, None)?;
+                s.append(TK_ID, Some(&format!("subquery_{subquery_id}")))?;
+                s.append(TK_RP, None)
+            }
             Self::Unary(op, sub_expr) => {
                 op.to_tokens_with_context(s, context)?;
                 sub_expr.to_tokens_with_context(s, context)
@@ -336,6 +336,18 @@ impl std::fmt::Display for TableInternalId {
     }
 }
 
+/// Kind of a subquery used as an expression
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SubqueryType {
+    /// `EXISTS (SELECT ...)`
+    Exists,
+    /// `x IN (SELECT ...)`
+    In,
+    /// `(SELECT ...)`
+    Scalar,
+}
+
 /// SQL expression
 // https://sqlite.org/syntax/expr.html
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -474,6 +486,19 @@ pub enum Expr {
     Raise(ResolveType, Option<Box<Expr>>),
     /// Subquery expression
     Subquery(Box<Select>),
+    /// `EXISTS`, `IN` or scalar subquery whose plan is held by the enclosing query
+    SubqueryResult {
+        /// the internal id of the subquery plan
+        subquery_id: TableInternalId,
+        /// the left-hand side of `IN`
+        lhs: Option<Box<Expr>>,
+        /// `NOT IN`
+        not_in: bool,
+        /// the kind of subquery
+        query_type: SubqueryType,
+        /// the columns of the enclosing queries that the subquery refers to
+        outer_columns: Vec<Expr>,
+    },
     /// Unary expression
     Unary(UnaryOperator, Box<Expr>),
     /// Parameters
@@ -1760,6 +1785,8 @@ pub type PragmaValue = Expr; // TODO
 #[strum(serialize_all = "snake_case")]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PragmaName {
+    /// the number of rows of each index ANALYZE scans
+    AnalysisLimit,
     /// Returns the application ID of the database file.
     ApplicationId,
     /// set the autovacuum mode
@@ -1768,14 +1795,34 @@ pub enum PragmaName {
     CacheSize,
     /// List databases
     DatabaseList,
+    /// defer all foreign key constraints until the transaction commits
+    DeferForeignKeys,
     /// Encoding - only support utf8
     Encoding,
+    /// returns the rows that violate foreign key constraints
+    ForeignKeyCheck,
+    /// returns information about the foreign key constraints of a table
+    ForeignKeyList,
+    /// enable foreign key constraint enforcement for the connection
+    ForeignKeys,
+    /// disable CHECK constraint enforcement for the connection
+    IgnoreCheckConstraints,
+    /// returns information about the key columns of an index
+    IndexInfo,
+    /// returns information about the indexes of a table
+    IndexList,
+    /// returns information about all the columns of an index
+    IndexXinfo,
     /// Run integrity check on the database file
     IntegrityCheck,
+    /// isolation level of the MVCC transactions of the connection
+    IsolationLevel,
     /// `journal_mode` pragma
     JournalMode,
     /// Noop as per SQLite docs
     LegacyFileFormat,
+    /// run ANALYZE on the tables whose statistics are missing or out of date
+    Optimize,
     /// Return the total number of pages in the database file.
     PageCount,
     /// Return the page size of the database in bytes.
@@ -1784,6 +1831,10 @@ pub enum PragmaName {
     SchemaVersion,
     /// returns information about the columns of a table
     TableInfo,
+    /// returns information about the columns of a table, including hidden and generated columns
+    TableXinfo,
+    /// where the temp database is stored
+    TempStore,
     /// enable capture-changes logic for the connection
     UnstableCaptureDataChangesConn,
     /// Returns the user version of the database file.